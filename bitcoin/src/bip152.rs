@@ -13,14 +13,15 @@
 use arbitrary::{Arbitrary, Unstructured};
 use hashes::{sha256, siphash24};
 use internals::array::ArrayExt as _;
-use internals::ToU64 as _;
+use internals::{write_err, ToU64 as _};
 use io::{BufRead, Write};
 
+use crate::block::{BlockUncheckedExt as _, InvalidBlockError};
 use crate::consensus::encode::{self, Decodable, Encodable, ReadExt, WriteExt};
 use crate::internal_macros::{self, impl_array_newtype, impl_array_newtype_stringify};
-use crate::prelude::Vec;
+use crate::prelude::{btree_map, BTreeMap, BTreeSet, Vec};
 use crate::transaction::TxIdentifier;
-use crate::{block, consensus, Block, BlockChecked, BlockHash, Transaction};
+use crate::{block, consensus, Block, BlockChecked, BlockHash, Transaction, Wtxid};
 
 /// A BIP-0152 error
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -404,6 +405,263 @@ pub fn from_request(
     }
 }
 
+/// A block being reconstructed from a [`HeaderAndShortIds`].
+///
+/// The short IDs of the compact block are matched against candidate transactions, usually the
+/// mempool. Transactions which could not be matched are requested from the peer with the
+/// [`BlockTransactionsRequest`] returned by [`PartialBlock::missing_transactions`] and the block
+/// is completed with [`PartialBlock::fill`] once the [`BlockTransactions`] reply arrives.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct PartialBlock {
+    header: block::Header,
+    /// The compact block version, version 1 blocks are relayed without witnesses.
+    version: u32,
+    /// The block's transactions, `None` if not yet known.
+    transactions: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    /// Constructs a new [`PartialBlock`] by matching the short IDs of `compact` against
+    /// `candidates`.
+    ///
+    /// `candidates` yields transactions along with their wtxid. `version` is the compact block
+    /// version negotiated with the peer, version 1 short IDs are calculated from the txid and
+    /// version 2 short IDs from the wtxid.
+    ///
+    /// If more than one candidate matches a short ID, none of them is used and the transaction
+    /// is requested from the peer instead.
+    ///
+    /// # Errors
+    ///
+    /// If the version is unknown or the compact block is malformed, including if it contains the
+    /// same short ID twice. In the latter case the full block should be requested.
+    pub fn new<'a, I>(
+        compact: &HeaderAndShortIds,
+        version: u32,
+        candidates: I,
+    ) -> Result<PartialBlock, ReconstructError>
+    where
+        I: IntoIterator<Item = (Wtxid, &'a Transaction)>,
+    {
+        if version != 1 && version != 2 {
+            return Err(ReconstructError::UnknownVersion(version));
+        }
+
+        let len = compact.short_ids.len() + compact.prefilled_txs.len();
+        if len == 0 {
+            return Err(ReconstructError::NoTransactions);
+        }
+
+        let mut transactions = vec![None; len];
+        let mut next = 0;
+        for prefilled in &compact.prefilled_txs {
+            let idx = next + usize::from(prefilled.idx);
+            if idx >= len {
+                return Err(ReconstructError::PrefilledIndexOutOfRange(idx));
+            }
+            transactions[idx] = Some(prefilled.tx.clone());
+            next = idx + 1;
+        }
+
+        // Prefilled indexes are strictly increasing so the empty slots match the short IDs 1:1.
+        let mut short_ids = compact.short_ids.iter();
+        let mut slots = BTreeMap::new();
+        for (idx, tx) in transactions.iter().enumerate() {
+            if tx.is_none() {
+                let short_id = short_ids.next().expect("one short ID per empty slot");
+                if slots.insert(*short_id, idx).is_some() {
+                    return Err(ReconstructError::ShortIdCollision);
+                }
+            }
+        }
+
+        let siphash_keys = ShortId::calculate_siphash_keys(&compact.header, compact.nonce);
+        let mut matched = BTreeMap::new();
+        let mut collided = BTreeSet::new();
+        for (wtxid, tx) in candidates {
+            let short_id = if version == 1 {
+                ShortId::with_siphash_keys(&tx.compute_txid(), siphash_keys)
+            } else {
+                ShortId::with_siphash_keys(&wtxid, siphash_keys)
+            };
+            let idx = match slots.get(&short_id) {
+                Some(idx) => *idx,
+                None => continue,
+            };
+            if collided.contains(&idx) {
+                continue;
+            }
+            match matched.entry(idx) {
+                btree_map::Entry::Vacant(e) => {
+                    e.insert((wtxid, tx));
+                }
+                btree_map::Entry::Occupied(e) =>
+                    if e.get().0 != wtxid {
+                        e.remove();
+                        collided.insert(idx);
+                    },
+            }
+        }
+
+        for (idx, (_, tx)) in matched {
+            transactions[idx] = Some(tx.clone());
+        }
+
+        Ok(PartialBlock { header: compact.header, version, transactions })
+    }
+
+    /// Returns the header of the block.
+    pub fn header(&self) -> &block::Header { &self.header }
+
+    /// Returns the hash of the block.
+    pub fn block_hash(&self) -> BlockHash { self.header.block_hash() }
+
+    /// Returns true if no transactions are missing.
+    pub fn is_complete(&self) -> bool { self.transactions.iter().all(Option::is_some) }
+
+    /// Returns a request for the missing transactions, or `None` if the block is complete.
+    pub fn missing_transactions(&self) -> Option<BlockTransactionsRequest> {
+        let indexes = self
+            .transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.is_none())
+            .map(|(idx, _)| idx.to_u64())
+            .collect::<Vec<_>>();
+
+        if indexes.is_empty() {
+            None
+        } else {
+            Some(BlockTransactionsRequest { block_hash: self.block_hash(), indexes })
+        }
+    }
+
+    /// Completes the block with the transactions of a reply to
+    /// [`PartialBlock::missing_transactions`].
+    ///
+    /// # Errors
+    ///
+    /// If the reply is for a different block, does not contain exactly the missing transactions,
+    /// or if the reconstructed block is invalid.
+    pub fn fill(
+        mut self,
+        reply: BlockTransactions,
+    ) -> Result<Block<BlockChecked>, ReconstructError> {
+        if reply.block_hash != self.block_hash() {
+            return Err(ReconstructError::BlockHashMismatch);
+        }
+
+        let missing = self.transactions.iter().filter(|tx| tx.is_none()).count();
+        if reply.transactions.len() != missing {
+            return Err(ReconstructError::TransactionCount {
+                expected: missing,
+                got: reply.transactions.len(),
+            });
+        }
+
+        let mut txs = reply.transactions.into_iter();
+        for slot in self.transactions.iter_mut().filter(|tx| tx.is_none()) {
+            *slot = txs.next();
+        }
+        self.into_block()
+    }
+
+    /// Returns the reconstructed block.
+    ///
+    /// Version 1 compact blocks are relayed without witnesses so the witness commitment can't be
+    /// checked. For version 1 the witnesses are stripped from all transactions and the returned
+    /// block is the non-witness serialization of the block, only its Merkle root is validated.
+    ///
+    /// # Errors
+    ///
+    /// If transactions are missing or if the reconstructed block is invalid. The latter most
+    /// likely means a short ID matched the wrong transaction, the full block should then be
+    /// requested from the peer.
+    pub fn into_block(self) -> Result<Block<BlockChecked>, ReconstructError> {
+        let missing = self.transactions.iter().filter(|tx| tx.is_none()).count();
+        if missing != 0 {
+            return Err(ReconstructError::MissingTransactions(missing));
+        }
+
+        let mut transactions = self.transactions.into_iter().flatten().collect::<Vec<_>>();
+        if self.version == 1 {
+            // Without witnesses the witness commitment is not required.
+            transactions.iter_mut().flat_map(|tx| &mut tx.inputs).for_each(|i| i.witness.clear());
+        }
+        Block::new_unchecked(self.header, transactions)
+            .validate()
+            .map_err(ReconstructError::InvalidBlock)
+    }
+}
+
+/// An error reconstructing a block from a compact block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ReconstructError {
+    /// An unknown compact block version was used.
+    UnknownVersion(u32),
+    /// The compact block has no transactions.
+    NoTransactions,
+    /// A prefilled transaction index is beyond the end of the block.
+    PrefilledIndexOutOfRange(usize),
+    /// The compact block contains the same short ID more than once.
+    ShortIdCollision,
+    /// The block is missing this many transactions.
+    MissingTransactions(usize),
+    /// The reply is for a different block.
+    BlockHashMismatch,
+    /// The reply does not contain the number of missing transactions.
+    TransactionCount {
+        /// The number of missing transactions.
+        expected: usize,
+        /// The number of transactions in the reply.
+        got: usize,
+    },
+    /// The reconstructed block is invalid.
+    InvalidBlock(InvalidBlockError),
+}
+
+impl From<Infallible> for ReconstructError {
+    fn from(never: Infallible) -> Self { match never {} }
+}
+
+impl fmt::Display for ReconstructError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ReconstructError::*;
+
+        match *self {
+            UnknownVersion(v) => write!(f, "unknown compact block version: {}", v),
+            NoTransactions => write!(f, "the compact block has no transactions"),
+            PrefilledIndexOutOfRange(idx) =>
+                write!(f, "prefilled transaction index {} is out of range", idx),
+            ShortIdCollision => write!(f, "the compact block contains duplicate short IDs"),
+            MissingTransactions(n) => write!(f, "the block is missing {} transactions", n),
+            BlockHashMismatch => write!(f, "the block transactions are for a different block"),
+            TransactionCount { expected, got } =>
+                write!(f, "expected {} block transactions but got {}", expected, got),
+            InvalidBlock(ref e) => write_err!(f, "the reconstructed block is invalid"; e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ReconstructError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ReconstructError::*;
+
+        match *self {
+            InvalidBlock(ref e) => Some(e),
+            UnknownVersion(_)
+            | NoTransactions
+            | PrefilledIndexOutOfRange(_)
+            | ShortIdCollision
+            | MissingTransactions(_)
+            | BlockHashMismatch
+            | TransactionCount { .. } => None,
+        }
+    }
+}
+
 #[cfg(feature = "arbitrary")]
 impl<'a> Arbitrary<'a> for ShortId {
     fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
@@ -527,6 +785,98 @@ fn compact_block_vector() {
         assert_eq!(compact, compact_expected);
     }
 
+    /// The block from `compact_block_vector`, which has a valid Merkle root and witness commitment.
+    fn valid_block() -> Block<BlockChecked> {
+        let raw_block = Vec::<u8>::from_hex("000000206c750a364035aefd5f81508a08769975116d9195312ee4520dceac39e1fdc62c4dc67473b8e354358c1e610afeaff7410858bd45df43e2940f8a62bd3d5e3ac943c2975cffff7f200000000002020000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff04016b0101ffffffff020006062a0100000001510000000000000000266a24aa21a9ed4a3d9f3343dafcc0d6f6d4310f2ee5ce273ed34edca6c75db3a73e7f368734200120000000000000000000000000000000000000000000000000000000000000000000000000020000000001021fc20ba2bd745507b8e00679e3b362558f9457db374ca28ffa5243f4c23a4d5f00000000171600147c9dea14ffbcaec4b575e03f05ceb7a81cd3fcbffdffffff915d689be87b43337f42e26033df59807b768223368f189a023d0242d837768900000000171600147c9dea14ffbcaec4b575e03f05ceb7a81cd3fcbffdffffff0200cdf5050000000017a9146803c72d9154a6a20f404bed6d3dcee07986235a8700e1f5050000000017a9144e6a4c7cb5b5562904843bdf816342f4db9f5797870247304402205e9bf6e70eb0e4b495bf483fd8e6e02da64900f290ef8aaa64bb32600d973c450220670896f5d0e5f33473e5f399ab680cc1d25c2d2afd15abd722f04978f28be887012103e4e4d9312b2261af508b367d8ba9be4f01b61d6d6e78bec499845b4f410bcf2702473044022045ac80596a6ac9c8c572f94708709adaf106677221122e08daf8b9741a04f66a022003ccd52a3b78f8fd08058fc04fc0cffa5f4c196c84eae9e37e2a85babe731b57012103e4e4d9312b2261af508b367d8ba9be4f01b61d6d6e78bec499845b4f410bcf276a000000").unwrap();
+        let block: Block = deserialize(&raw_block).unwrap();
+        block.validate().unwrap()
+    }
+
+    #[test]
+    fn reconstruct_from_candidates() {
+        let block = valid_block();
+        let tx = &block.transactions()[1];
+        let unrelated = dummy_tx(&[5]);
+        let candidates = [(unrelated.compute_wtxid(), &unrelated), (tx.compute_wtxid(), tx)];
+
+        // Version 1 reconstructs the block without witnesses.
+        let mut stripped = block.transactions().to_vec();
+        stripped.iter_mut().flat_map(|tx| &mut tx.inputs).for_each(|i| i.witness.clear());
+        let stripped = Block::new_unchecked(*block.header(), stripped).validate().unwrap();
+
+        for (version, expected) in [(1, &stripped), (2, &block)] {
+            let compact = HeaderAndShortIds::from_block(&block, 42, version, &[]).unwrap();
+            let partial = PartialBlock::new(&compact, version, candidates.iter().copied()).unwrap();
+            assert!(partial.is_complete());
+            assert_eq!(partial.missing_transactions(), None);
+            assert_eq!(&partial.into_block().unwrap(), expected);
+        }
+
+        let compact = HeaderAndShortIds::from_block(&block, 42, 2, &[]).unwrap();
+        assert_eq!(
+            PartialBlock::new(&compact, 3, candidates.iter().copied()),
+            Err(ReconstructError::UnknownVersion(3))
+        );
+    }
+
+    #[test]
+    fn reconstruct_with_missing_transactions() {
+        let block = valid_block();
+        let compact = HeaderAndShortIds::from_block(&block, 42, 2, &[]).unwrap();
+        let partial = PartialBlock::new(&compact, 2, core::iter::empty()).unwrap();
+        assert!(!partial.is_complete());
+        assert_eq!(partial.clone().into_block(), Err(ReconstructError::MissingTransactions(1)));
+
+        let request = partial.missing_transactions().unwrap();
+        assert_eq!(request.block_hash, block.block_hash());
+        assert_eq!(request.indexes, [1]);
+
+        let reply = BlockTransactions::from_request(&request, &block).unwrap();
+        assert_eq!(partial.clone().fill(reply.clone()).unwrap(), block);
+
+        let mut wrong_hash = reply.clone();
+        wrong_hash.block_hash = BlockHash::from_byte_array([0; 32]);
+        assert_eq!(partial.clone().fill(wrong_hash), Err(ReconstructError::BlockHashMismatch));
+
+        let mut wrong_count = reply.clone();
+        wrong_count.transactions.push(dummy_tx(&[5]));
+        assert_eq!(
+            partial.clone().fill(wrong_count),
+            Err(ReconstructError::TransactionCount { expected: 1, got: 2 })
+        );
+
+        let mut wrong_tx = reply;
+        wrong_tx.transactions[0] = dummy_tx(&[5]);
+        assert!(matches!(partial.fill(wrong_tx), Err(ReconstructError::InvalidBlock(_))));
+    }
+
+    #[test]
+    fn reconstruct_invalid_compact_block() {
+        let block = valid_block();
+        let tx = &block.transactions()[1];
+        let compact = HeaderAndShortIds::from_block(&block, 42, 2, &[]).unwrap();
+
+        let mut duplicate = compact.clone();
+        duplicate.short_ids.push(duplicate.short_ids[0]);
+        assert_eq!(
+            PartialBlock::new(&duplicate, 2, core::iter::empty()),
+            Err(ReconstructError::ShortIdCollision)
+        );
+
+        let mut out_of_range = compact.clone();
+        out_of_range.prefilled_txs[0].idx = 2;
+        assert_eq!(
+            PartialBlock::new(&out_of_range, 2, core::iter::empty()),
+            Err(ReconstructError::PrefilledIndexOutOfRange(2))
+        );
+
+        let empty = HeaderAndShortIds { short_ids: vec![], prefilled_txs: vec![], ..compact };
+        assert_eq!(
+            PartialBlock::new(&empty, 2, [(tx.compute_wtxid(), tx)]),
+            Err(ReconstructError::NoTransactions)
+        );
+    }
+
     #[test]
     fn getblocktx_differential_encoding_de_and_serialization() {
         let testcases = vec![
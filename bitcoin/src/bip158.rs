@@ -49,11 +49,11 @@
 use io::{BufRead, Write};
 
 use crate::block::{Block, BlockHash, Checked};
-use crate::consensus::{ReadExt, WriteExt};
-use crate::internal_macros;
+use crate::consensus::{self, ReadExt, WriteExt};
 use crate::prelude::{BTreeSet, Borrow, Vec};
-use crate::script::{ScriptPubKey, ScriptPubKeyExt as _};
+use crate::script::{ScriptPubKey, ScriptPubKeyBuf, ScriptPubKeyExt as _};
 use crate::transaction::OutPoint;
+use crate::{internal_macros, BlockHeight};
 
 /// Golomb encoding parameter as in BIP-0158, see also https://gist.github.com/sipa/576d5f09c3b86c3b1b75598d799fc845
 const P: u8 = 19;
@@ -81,6 +81,8 @@ pub enum Error {
     UtxoMissing(OutPoint),
     /// I/O error reading or writing binary serialization of the filter.
     Io(io::Error),
+    /// The number of elements in the filter is too large to map hashes to.
+    TooManyElements(u64),
 }
 
 impl From<Infallible> for Error {
@@ -94,6 +96,7 @@ fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match *self {
             UtxoMissing(ref coin) => write!(f, "unresolved UTXO {}", coin),
             Io(ref e) => write_err!(f, "I/O error"; e),
+            TooManyElements(n) => write!(f, "too many elements in the filter: {}", n),
         }
     }
 }
@@ -104,7 +107,7 @@ fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         use Error::*;
 
         match *self {
-            UtxoMissing(_) => None,
+            UtxoMissing(_) | TooManyElements(_) => None,
             Io(ref e) => Some(e),
         }
     }
@@ -279,6 +282,120 @@ pub fn match_all<I, R>(&self, reader: &mut R, query: I) -> Result<bool, Error>
     }
 }
 
+/// Scans a stream of block filters for the blocks relevant to a set of watched scripts.
+///
+/// Filters are fed in block order starting at the height the scanner was constructed with, and
+/// the heights of the blocks whose filter matches any of the watched scripts are returned. Each
+/// filter is decoded once for the whole watch set, no matter how many scripts are watched.
+///
+/// Scripts may be added at any point, e.g. as a wallet reveals addresses to keep its gap limit,
+/// they are matched against the filters scanned from then on.
+///
+/// # Examples
+///
+/// ```
+/// # use bitcoin::bip158::{BlockFilter, FilterScanner};
+/// # use bitcoin::{BlockHash, BlockHeight, ScriptPubKeyBuf};
+/// # let filters: Vec<(BlockHash, BlockFilter)> = Vec::new();
+/// # let revealed_scripts: Vec<ScriptPubKeyBuf> = Vec::new();
+/// let mut scanner = FilterScanner::new(BlockHeight::ZERO);
+/// scanner.watch(revealed_scripts);
+///
+/// let mut to_fetch = Vec::new();
+/// for (block_hash, filter) in filters {
+///     if let Some(height) = scanner.scan_filter(block_hash, &filter).unwrap() {
+///         // Fetch the block, reveal more scripts if it pays to the wallet.
+///         to_fetch.push(height);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FilterScanner {
+    scripts: BTreeSet<ScriptPubKeyBuf>,
+    next_height: BlockHeight,
+    /// The watched scripts hashed for the current filter, kept to reuse the allocation.
+    mapped: Vec<u64>,
+}
+
+impl FilterScanner {
+    /// Constructs a new scanner, without watched scripts, whose first filter is at `start_height`.
+    pub fn new(start_height: BlockHeight) -> FilterScanner {
+        FilterScanner { scripts: BTreeSet::new(), next_height: start_height, mapped: Vec::new() }
+    }
+
+    /// Adds scripts to the watch set, they are matched from the next filter on.
+    pub fn watch<I>(&mut self, scripts: I)
+    where
+        I: IntoIterator<Item = ScriptPubKeyBuf>,
+    {
+        self.scripts.extend(scripts);
+    }
+
+    /// Returns true if `script` is in the watch set.
+    pub fn is_watched(&self, script: &ScriptPubKey) -> bool { self.scripts.contains(script) }
+
+    /// Returns the number of watched scripts.
+    pub fn len(&self) -> usize { self.scripts.len() }
+
+    /// Returns true if no scripts are watched.
+    pub fn is_empty(&self) -> bool { self.scripts.is_empty() }
+
+    /// Returns the height of the block whose filter is expected next.
+    pub fn next_height(&self) -> BlockHeight { self.next_height }
+
+    /// Scans the filter of the block at [`Self::next_height`].
+    ///
+    /// Returns the height of the block if it must be fetched. The height is not advanced if the
+    /// filter can not be decoded.
+    pub fn scan_filter(
+        &mut self,
+        block_hash: BlockHash,
+        filter: &BlockFilter,
+    ) -> Result<Option<BlockHeight>, Error> {
+        let height = self.next_height;
+        let matched = self.match_any(block_hash, &filter.content)?;
+        self.next_height = BlockHeight::from_u32(height.to_u32() + 1);
+        Ok(if matched { Some(height) } else { None })
+    }
+
+    /// Scans consecutive filters, returns the heights of the blocks that must be fetched.
+    pub fn scan<I>(&mut self, filters: I) -> Result<Vec<BlockHeight>, Error>
+    where
+        I: IntoIterator<Item = (BlockHash, BlockFilter)>,
+    {
+        let mut heights = Vec::new();
+        for (block_hash, filter) in filters {
+            if let Some(height) = self.scan_filter(block_hash, &filter)? {
+                heights.push(height);
+            }
+        }
+        Ok(heights)
+    }
+
+    fn match_any(&mut self, block_hash: BlockHash, mut content: &[u8]) -> Result<bool, Error> {
+        // Unlike `BlockFilter::match_any` an empty query matches nothing.
+        if self.scripts.is_empty() {
+            return Ok(false);
+        }
+        let n_elements = content.read_compact_size().map_err(|e| match e {
+            consensus::Error::Io(e) => Error::Io(e),
+            consensus::Error::Parse(_) =>
+                Error::Io(io::Error::new(io::ErrorKind::InvalidData, "invalid element count")),
+        })?;
+        if n_elements == 0 {
+            return Ok(false);
+        }
+
+        let filter = BlockFilterReader::new(block_hash).reader.filter;
+        let nm = n_elements.checked_mul(M).ok_or(Error::TooManyElements(n_elements))?;
+        self.mapped.clear();
+        self.mapped
+            .extend(self.scripts.iter().map(|s| map_to_range(filter.hash(s.as_bytes()), nm)));
+        self.mapped.sort_unstable();
+        filter.match_any_sorted(&mut content, n_elements, &self.mapped)
+    }
+}
+
 /// Golomb-Rice encoded filter reader.
 pub struct GcsFilterReader {
     filter: GcsFilter,
@@ -312,26 +429,7 @@ pub fn match_any<I, R>(&self, reader: &mut R, query: I) -> Result<bool, Error>
             return Ok(false);
         }
 
-        // find first match in two sorted arrays in one read pass
-        let mut reader = BitStreamReader::new(reader);
-        let mut data = self.filter.golomb_rice_decode(&mut reader)?;
-        let mut remaining = n_elements - 1;
-        for p in mapped {
-            loop {
-                match data.cmp(&p) {
-                    Ordering::Equal => return Ok(true),
-                    Ordering::Less =>
-                        if remaining > 0 {
-                            data += self.filter.golomb_rice_decode(&mut reader)?;
-                            remaining -= 1;
-                        } else {
-                            return Ok(false);
-                        },
-                    Ordering::Greater => break,
-                }
-            }
-        }
-        Ok(false)
+        self.filter.match_any_sorted(reader, n_elements, &mapped)
     }
 
     /// Returns true if all queries match against this [`GcsFilterReader`].
@@ -475,6 +573,38 @@ fn golomb_rice_decode<R>(&self, reader: &mut BitStreamReader<R>) -> Result<u64,
         Ok((q << self.p) + r)
     }
 
+    /// Returns true if any of the sorted `mapped` queries is one of the `n_elements` in `reader`.
+    fn match_any_sorted<R>(
+        &self,
+        reader: &mut R,
+        n_elements: u64,
+        mapped: &[u64],
+    ) -> Result<bool, Error>
+    where
+        R: BufRead + ?Sized,
+    {
+        // find first match in two sorted arrays in one read pass
+        let mut reader = BitStreamReader::new(reader);
+        let mut data = self.golomb_rice_decode(&mut reader)?;
+        let mut remaining = n_elements - 1;
+        for &p in mapped {
+            loop {
+                match data.cmp(&p) {
+                    Ordering::Equal => return Ok(true),
+                    Ordering::Less =>
+                        if remaining > 0 {
+                            data += self.golomb_rice_decode(&mut reader)?;
+                            remaining -= 1;
+                        } else {
+                            return Ok(false);
+                        },
+                    Ordering::Greater => break,
+                }
+            }
+        }
+        Ok(false)
+    }
+
     /// Hashes an arbitrary slice with siphash using parameters of this filter.
     fn hash(&self, element: &[u8]) -> u64 {
         siphash24::Hash::hash_to_u64_with_keys(self.k0, self.k1, element)
@@ -599,7 +729,6 @@ mod test {
 
     use super::*;
     use crate::consensus::encode::deserialize;
-    use crate::ScriptPubKeyBuf;
 
     #[test]
     fn blockfilters() {
@@ -739,6 +868,99 @@ fn filter() {
         }
     }
 
+    /// Builds the filter of the block with `block_hash` paying to `scripts`.
+    fn block_filter(block_hash: BlockHash, scripts: &[&ScriptPubKeyBuf]) -> BlockFilter {
+        let filter = BlockFilterReader::new(block_hash).reader.filter;
+        let mut content = Vec::new();
+        let mut writer = GcsFilterWriter::new(&mut content, filter.k0, filter.k1, M, P);
+        for script in scripts {
+            writer.add_element(script.as_bytes());
+        }
+        writer.finish().unwrap();
+        BlockFilter { content }
+    }
+
+    fn script(n: u8) -> ScriptPubKeyBuf {
+        let mut bytes = vec![0x00, 0x14];
+        bytes.extend_from_slice(&[n; 20]);
+        ScriptPubKeyBuf::from(bytes)
+    }
+
+    fn block_hash(height: u32) -> BlockHash {
+        BlockHash::from_byte_array(sha256d::Hash::hash(&height.to_le_bytes()).to_byte_array())
+    }
+
+    #[test]
+    fn filter_scanner() {
+        let scripts = (0..40).map(script).collect::<Vec<_>>();
+        // Blocks 100 to 109, each paying an unrelated script and some paying the wallet.
+        let filters = (100..110)
+            .map(|height| {
+                let unrelated = script(200 + (height - 100) as u8);
+                let paid = match height {
+                    102 => vec![&scripts[1], &unrelated],
+                    105 | 107 => vec![&unrelated, &scripts[25]],
+                    108 => vec![],
+                    _ => vec![&unrelated],
+                };
+                (block_hash(height), block_filter(block_hash(height), &paid))
+            })
+            .collect::<Vec<_>>();
+
+        let mut scanner = FilterScanner::new(BlockHeight::from_u32(100));
+        scanner.watch(scripts[..20].iter().cloned());
+        assert_eq!(scanner.len(), 20);
+        assert_eq!(
+            scanner.scan(filters[..5].iter().cloned()).unwrap(),
+            [BlockHeight::from_u32(102)]
+        );
+
+        // Reveal more scripts mid-scan, they are matched from the next filter on.
+        scanner.watch(scripts[20..].iter().cloned());
+        assert!(scanner.is_watched(&scripts[25]));
+        assert_eq!(
+            scanner.scan(filters[5..].iter().cloned()).unwrap(),
+            [BlockHeight::from_u32(105), BlockHeight::from_u32(107)]
+        );
+        assert_eq!(scanner.next_height(), BlockHeight::from_u32(110));
+    }
+
+    #[test]
+    fn filter_scanner_edge_cases() {
+        let hash = block_hash(0);
+        let filter = block_filter(hash, &[&script(1)]);
+
+        // Nothing is watched, unlike an empty query to `match_any` this matches nothing.
+        let mut scanner = FilterScanner::new(BlockHeight::ZERO);
+        assert!(scanner.is_empty());
+        assert_eq!(scanner.scan_filter(hash, &filter).unwrap(), None);
+
+        scanner.watch([script(1)]);
+        assert_eq!(scanner.scan_filter(hash, &filter).unwrap(), Some(BlockHeight::from_u32(1)));
+
+        // A truncated filter is an error and the height is not advanced.
+        let truncated = BlockFilter::new(&filter.content[..1]);
+        assert!(matches!(scanner.scan_filter(hash, &truncated), Err(Error::Io(_))));
+        let empty = BlockFilter::new(&[]);
+        assert!(matches!(scanner.scan_filter(hash, &empty), Err(Error::Io(_))));
+        let truncated_count = BlockFilter::new(&[0xfd, 0x01]);
+        assert!(matches!(scanner.scan_filter(hash, &truncated_count), Err(Error::Io(_))));
+        let non_minimal_count = BlockFilter::new(&[0xfd, 0x01, 0x00]);
+        assert!(matches!(scanner.scan_filter(hash, &non_minimal_count), Err(Error::Io(_))));
+        assert_eq!(scanner.next_height(), BlockHeight::from_u32(2));
+
+        // So is an element count too large to map hashes to.
+        let mut content = vec![0xff];
+        content.extend_from_slice(&u64::MAX.to_le_bytes());
+        content.extend_from_slice(&filter.content[1..]);
+        let hostile = BlockFilter::new(&content);
+        assert!(matches!(
+            scanner.scan_filter(hash, &hostile),
+            Err(Error::TooManyElements(u64::MAX))
+        ));
+        assert_eq!(scanner.next_height(), BlockHeight::from_u32(2));
+    }
+
     #[test]
     fn bit_stream() {
         let mut out = Vec::new();
@@ -0,0 +1,397 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! BIP-0021 payment URIs.
+//!
+//! Implementation of the `bitcoin:` URI scheme defined in BIP-0021, for example
+//! `bitcoin:bc1qvzvkjn4q3nszqxrv3nraga2r822xjty3ykvkuw?amount=0.001&label=Luke-Jr`.
+//!
+//! Besides the parameters defined in BIP-0021 the `lightning` parameter (a BOLT11 invoice) and
+//! the `pj` parameter (a BIP-0078 payjoin endpoint) are supported. Other parameters are kept as
+//! extras, unknown parameters prefixed with `req-` make the URI invalid.
+
+use core::fmt;
+use core::str::FromStr;
+
+use internals::write_err;
+
+use crate::address::{self, Address, NetworkUnchecked};
+use crate::amount::{Denomination, ParseAmountError};
+use crate::prelude::{String, ToString, Vec};
+use crate::Amount;
+
+/// The URI scheme, compared case-insensitively when parsing.
+const SCHEME: &str = "bitcoin:";
+
+/// The prefix of parameters the URI is invalid without understanding.
+const REQUIRED_PREFIX: &str = "req-";
+
+/// A BIP-0021 payment URI.
+///
+/// The `Display` implementation serializes the URI, percent-encoding parameter values. Alternate
+/// formatting `{:#}` uppercases the scheme and bech32 addresses, which allows QR codes to use the
+/// more compact alphanumeric mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uri {
+    /// The address to pay to.
+    pub address: Address<NetworkUnchecked>,
+    /// The amount to pay.
+    pub amount: Option<Amount>,
+    /// A label for the address, e.g. the name of the receiver.
+    pub label: Option<String>,
+    /// A message describing the payment.
+    pub message: Option<String>,
+    /// A BOLT11 invoice that may be paid instead.
+    pub lightning: Option<String>,
+    /// A BIP-0078 payjoin endpoint.
+    pub pj: Option<String>,
+    /// Any other parameters as decoded `(key, value)` pairs, in the order they appear.
+    pub extras: Vec<(String, String)>,
+}
+
+impl Uri {
+    /// Constructs a new URI paying to `address` without any parameters.
+    pub fn new(address: Address<NetworkUnchecked>) -> Self {
+        Uri {
+            address,
+            amount: None,
+            label: None,
+            message: None,
+            lightning: None,
+            pj: None,
+            extras: Vec::new(),
+        }
+    }
+
+    /// Parses a URI, accepting the `req-` parameters in `supported`.
+    ///
+    /// `supported` are full parameter keys including the `req-` prefix, e.g. `"req-foo"`, compared
+    /// case-insensitively like all keys. These parameters are returned in [`Uri::extras`]. Parsing with [`FromStr`] is equivalent to
+    /// passing an empty slice.
+    ///
+    /// # Errors
+    ///
+    /// If the URI is invalid or contains a `req-` parameter not in `supported`.
+    pub fn parse_with_required(s: &str, supported: &[&str]) -> Result<Uri, ParseError> {
+        let rest = match s.get(..SCHEME.len()) {
+            Some(scheme) if scheme.eq_ignore_ascii_case(SCHEME) => &s[SCHEME.len()..],
+            _ => return Err(ParseError::MissingScheme),
+        };
+        let (address, query) = match rest.split_once('?') {
+            Some((address, query)) => (address, query),
+            None => (rest, ""),
+        };
+        let mut uri = Uri::new(address.parse().map_err(ParseError::Address)?);
+
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let key = percent_decode(key)?;
+            let value = percent_decode(value)?;
+
+            let slot = match key.to_ascii_lowercase().as_str() {
+                "amount" => {
+                    let amount = Amount::from_str_in(&value, Denomination::Bitcoin)
+                        .map_err(ParseError::Amount)?;
+                    if uri.amount.replace(amount).is_some() {
+                        return Err(ParseError::DuplicateParameter(key));
+                    }
+                    continue;
+                }
+                "label" => &mut uri.label,
+                "message" => &mut uri.message,
+                "lightning" => &mut uri.lightning,
+                "pj" => &mut uri.pj,
+                lower => {
+                    if lower.starts_with(REQUIRED_PREFIX)
+                        && !supported.iter().any(|s| s.eq_ignore_ascii_case(lower))
+                    {
+                        return Err(ParseError::UnknownRequiredParameter(key));
+                    }
+                    uri.extras.push((key, value));
+                    continue;
+                }
+            };
+            if slot.replace(value).is_some() {
+                return Err(ParseError::DuplicateParameter(key));
+            }
+        }
+        Ok(uri)
+    }
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let address = self.address.assume_checked_ref();
+        if f.alternate() {
+            write!(f, "{}{:#}", SCHEME.to_ascii_uppercase(), address)?;
+        } else {
+            write!(f, "{}{}", SCHEME, address)?;
+        }
+
+        let mut separator = '?';
+        if let Some(amount) = self.amount {
+            write!(f, "{}amount={}", separator, amount.display_in(Denomination::Bitcoin))?;
+            separator = '&';
+        }
+        let known = [
+            ("label", &self.label),
+            ("message", &self.message),
+            ("lightning", &self.lightning),
+            ("pj", &self.pj),
+        ];
+        let known = known.iter().filter_map(|(key, value)| value.as_ref().map(|v| (*key, v)));
+        let extras = self.extras.iter().map(|(key, value)| (key.as_str(), value));
+        for (key, value) in known.chain(extras) {
+            write!(f, "{}", separator)?;
+            write_percent_encoded(f, key)?;
+            f.write_str("=")?;
+            write_percent_encoded(f, value)?;
+            separator = '&';
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Uri {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> { Uri::parse_with_required(s, &[]) }
+}
+
+/// Returns true for the characters that are written without percent-encoding.
+///
+/// These are the unreserved characters of RFC 3986.
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+}
+
+fn write_percent_encoded(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    for byte in s.bytes() {
+        if is_unreserved(byte) {
+            write!(f, "{}", char::from(byte))?;
+        } else {
+            write!(f, "%{:02X}", byte)?;
+        }
+    }
+    Ok(())
+}
+
+fn percent_decode(s: &str) -> Result<String, ParseError> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(byte) = iter.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hi = iter.next().and_then(|c| char::from(c).to_digit(16));
+        let lo = iter.next().and_then(|c| char::from(c).to_digit(16));
+        match (hi, lo) {
+            (Some(hi), Some(lo)) => bytes.push(((hi << 4) | lo) as u8),
+            _ => return Err(ParseError::PercentEncoding(s.to_string())),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| ParseError::PercentEncoding(s.to_string()))
+}
+
+/// Error parsing a BIP-0021 URI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// The URI does not start with `bitcoin:`.
+    MissingScheme,
+    /// Invalid address.
+    Address(address::ParseError),
+    /// Invalid amount.
+    Amount(ParseAmountError),
+    /// A parameter appears more than once.
+    DuplicateParameter(String),
+    /// A `req-` parameter that is not understood.
+    UnknownRequiredParameter(String),
+    /// Invalid percent-encoding, or the decoded string is not valid UTF-8.
+    PercentEncoding(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ParseError::*;
+
+        match *self {
+            MissingScheme => write!(f, "URI does not start with {}", SCHEME),
+            Address(ref e) => write_err!(f, "invalid address"; e),
+            Amount(ref e) => write_err!(f, "invalid amount"; e),
+            DuplicateParameter(ref key) => write!(f, "duplicate parameter {}", key),
+            UnknownRequiredParameter(ref key) => write!(f, "unknown required parameter {}", key),
+            PercentEncoding(ref s) => write!(f, "invalid percent-encoding: {}", s),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ParseError::*;
+
+        match *self {
+            Address(ref e) => Some(e),
+            Amount(ref e) => Some(e),
+            MissingScheme
+            | DuplicateParameter(_)
+            | UnknownRequiredParameter(_)
+            | PercentEncoding(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BECH32: &str = "bc1qvzvkjn4q3nszqxrv3nraga2r822xjty3ykvkuw";
+    const BASE58: &str = "1J4LVanjHMu3JkXbVrahNuQCTGCRRgfWWx";
+
+    fn address(s: &str) -> Address<NetworkUnchecked> { s.parse().unwrap() }
+
+    #[test]
+    fn parse_address_only() {
+        let uri: Uri = format!("bitcoin:{}", BASE58).parse().unwrap();
+        assert_eq!(uri, Uri::new(address(BASE58)));
+        assert_eq!(uri.to_string(), format!("bitcoin:{}", BASE58));
+    }
+
+    // Examples from BIP-0021.
+    #[test]
+    fn parse_bip21_examples() {
+        let uri: Uri = format!("bitcoin:{}?label=Luke-Jr", BASE58).parse().unwrap();
+        assert_eq!(uri.label.as_deref(), Some("Luke-Jr"));
+
+        let uri: Uri = format!("bitcoin:{}?amount=20.3&label=Luke-Jr", BASE58).parse().unwrap();
+        assert_eq!(uri.amount, Some(Amount::from_sat(2_030_000_000).unwrap()));
+        assert_eq!(uri.label.as_deref(), Some("Luke-Jr"));
+
+        let s = format!(
+            "bitcoin:{}?amount=50&label=Luke-Jr&message=Donation%20for%20project%20xyz",
+            BASE58
+        );
+        let uri: Uri = s.parse().unwrap();
+        assert_eq!(uri.amount, Some(Amount::from_int_btc(50u16)));
+        assert_eq!(uri.message.as_deref(), Some("Donation for project xyz"));
+        assert_eq!(uri.to_string(), s);
+
+        let s = format!(
+            "bitcoin:{}?req-somethingyoudontunderstand=50&req-somethingelseyoudontget=999",
+            BASE58
+        );
+        assert_eq!(
+            s.parse::<Uri>(),
+            Err(ParseError::UnknownRequiredParameter("req-somethingyoudontunderstand".into()))
+        );
+
+        let s =
+            format!("bitcoin:{}?somethingyoudontunderstand=50&somethingelseyoudontget=999", BASE58);
+        let uri: Uri = s.parse().unwrap();
+        assert_eq!(
+            uri.extras,
+            [
+                ("somethingyoudontunderstand".to_string(), "50".to_string()),
+                ("somethingelseyoudontget".to_string(), "999".to_string()),
+            ]
+        );
+        assert_eq!(uri.to_string(), s);
+    }
+
+    #[test]
+    fn supported_required_parameter() {
+        let s = format!("bitcoin:{}?req-foo=bar", BECH32);
+        let uri = Uri::parse_with_required(&s, &["req-foo"]).unwrap();
+        assert_eq!(uri.extras, [("req-foo".to_string(), "bar".to_string())]);
+        assert_eq!(uri.to_string(), s);
+
+        // Keys are case-insensitive.
+        let s = format!("bitcoin:{}?REQ-Foo=bar", BECH32);
+        assert!(Uri::parse_with_required(&s, &["req-foo"]).is_ok());
+        assert!(Uri::parse_with_required(&s, &["req-FOO"]).is_ok());
+        assert_eq!(
+            Uri::parse_with_required(&s, &["req-bar"]),
+            Err(ParseError::UnknownRequiredParameter("REQ-Foo".into()))
+        );
+    }
+
+    #[test]
+    fn amount_is_exact() {
+        let uri: Uri = format!("bitcoin:{}?amount=0.00000001", BECH32).parse().unwrap();
+        assert_eq!(uri.amount, Some(Amount::ONE_SAT));
+        let uri: Uri = format!("bitcoin:{}?amount=.1", BECH32).parse().unwrap();
+        assert_eq!(uri.amount, Some(Amount::from_sat(10_000_000).unwrap()));
+
+        for amount in ["0.000000001", "1e3", "1,5", "-1", "", "21000001"] {
+            let s = format!("bitcoin:{}?amount={}", BECH32, amount);
+            assert!(matches!(s.parse::<Uri>(), Err(ParseError::Amount(_))), "{}", amount);
+        }
+    }
+
+    #[test]
+    fn lightning_and_payjoin() {
+        let s = format!(
+            "bitcoin:{}?amount=0.001&lightning=lnbc100u1pjexample&pj=https://example.com/pj",
+            BECH32
+        );
+        let uri: Uri = s.parse().unwrap();
+        assert_eq!(uri.lightning.as_deref(), Some("lnbc100u1pjexample"));
+        assert_eq!(uri.pj.as_deref(), Some("https://example.com/pj"));
+        assert_eq!(
+            uri.to_string(),
+            format!(
+                "bitcoin:{}?amount=0.001&lightning=lnbc100u1pjexample&pj=https%3A%2F%2Fexample.com%2Fpj",
+                BECH32
+            )
+        );
+        assert_eq!(uri.to_string().parse::<Uri>().unwrap(), uri);
+    }
+
+    #[test]
+    fn uppercase_qr_form() {
+        let mut uri = Uri::new(address(BECH32));
+        uri.amount = Some(Amount::from_sat(100_000).unwrap());
+        let qr = format!("{:#}", uri);
+        assert_eq!(qr, format!("BITCOIN:{}?amount=0.001", BECH32.to_uppercase()));
+        assert_eq!(qr.parse::<Uri>().unwrap(), uri);
+
+        let s = format!("BITCOIN:{}?AMOUNT=0.001&LABEL=Shop", BECH32.to_uppercase());
+        let parsed: Uri = s.parse().unwrap();
+        assert_eq!(parsed.amount, uri.amount);
+        assert_eq!(parsed.label.as_deref(), Some("Shop"));
+    }
+
+    #[test]
+    fn percent_encoding() {
+        let mut uri = Uri::new(address(BECH32));
+        uri.label = Some("Café & Co".to_string());
+        uri.message = Some("50% off?".to_string());
+        let s = uri.to_string();
+        assert_eq!(
+            s,
+            format!("bitcoin:{}?label=Caf%C3%A9%20%26%20Co&message=50%25%20off%3F", BECH32)
+        );
+        assert_eq!(s.parse::<Uri>().unwrap(), uri);
+
+        for value in ["%", "%2", "%zz", "%ff"] {
+            let s = format!("bitcoin:{}?label={}", BECH32, value);
+            assert!(matches!(s.parse::<Uri>(), Err(ParseError::PercentEncoding(_))), "{}", value);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(BECH32.parse::<Uri>(), Err(ParseError::MissingScheme));
+        assert_eq!(format!("litecoin:{}", BECH32).parse::<Uri>(), Err(ParseError::MissingScheme));
+        assert!(matches!("bitcoin:notanaddress".parse::<Uri>(), Err(ParseError::Address(_))));
+        assert_eq!(
+            format!("bitcoin:{}?label=a&label=b", BECH32).parse::<Uri>(),
+            Err(ParseError::DuplicateParameter("label".into()))
+        );
+        assert_eq!(
+            format!("bitcoin:{}?amount=1&amount=1", BECH32).parse::<Uri>(),
+            Err(ParseError::DuplicateParameter("amount".into()))
+        );
+    }
+}
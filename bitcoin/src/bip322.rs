@@ -0,0 +1,747 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! BIP-0322 generic signed message format.
+//!
+//! Implementation of the generic message signing scheme described in BIP-0322. A message is signed
+//! by proving that a virtual `to_sign` transaction can spend a virtual `to_spend` transaction
+//! whose output is the script being signed for. Unlike the legacy scheme in
+//! [`sign_message`](crate::sign_message) this works for any script, including Taproot.
+//!
+//! Three formats are supported:
+//!
+//! - simple: only the witness of the `to_sign` input is encoded, used for segwit addresses.
+//! - full: the whole `to_sign` transaction is encoded, required for legacy and P2SH addresses.
+//! - proof of funds: a full signature whose `to_sign` transaction spends additional UTXOs.
+//!
+//! Signing goes through [`Psbt::sign`] and the PSBT finalizer, verification runs every input of
+//! the `to_sign` transaction through the script [`interpreter`].
+
+use core::fmt;
+
+use hashes::{hash_newtype, sha256t, sha256t_tag, HashEngine};
+use internals::write_err;
+use secp256k1::{Secp256k1, Signing, Verification};
+
+use crate::address::Address;
+use crate::bip32::{DerivationPath, Fingerprint};
+use crate::consensus::encode::{self, DeserializeError};
+use crate::crypto::key::{PrivateKey, XOnlyPublicKey};
+use crate::locktime::absolute;
+use crate::opcodes::all::OP_RETURN;
+use crate::opcodes::OP_0;
+use crate::prelude::{BTreeMap, ToOwned, Vec};
+use crate::psbt::{self, FinalizeError, Input, Psbt};
+use crate::script::interpreter::{self, VerifyFlags};
+use crate::script::{
+    Builder, RedeemScriptBuf, ScriptBufExt as _, ScriptExt as _, ScriptPubKey, ScriptPubKeyBuf,
+    ScriptPubKeyBufExt as _, ScriptPubKeyExt as _,
+};
+use crate::transaction::{self, OutPoint, Transaction, TxIn, TxOut};
+use crate::{Amount, Sequence, Witness};
+
+sha256t_tag! {
+    pub struct MessageTag = hash_str("BIP0322-signed-message");
+}
+
+hash_newtype! {
+    /// Tagged hash of a message signed using BIP-0322.
+    pub struct MessageHash(sha256t::Hash<MessageTag>);
+}
+
+hashes::impl_hex_for_newtype!(MessageHash);
+
+impl MessageHash {
+    /// Computes the tagged hash of `message`.
+    pub fn from_message(message: &[u8]) -> MessageHash {
+        let mut eng = sha256t::Hash::<MessageTag>::engine();
+        eng.input(message);
+        let inner = sha256t::Hash::<MessageTag>::from_engine(eng);
+        MessageHash::from_byte_array(inner.to_byte_array())
+    }
+}
+
+/// Constructs the virtual `to_spend` transaction committing to `message` and `script_pubkey`.
+pub fn to_spend(script_pubkey: &ScriptPubKey, message: &[u8]) -> Transaction {
+    let message_hash = MessageHash::from_message(message);
+    let script_sig =
+        Builder::new().push_opcode(OP_0).push_slice(message_hash.to_byte_array()).into_script();
+
+    Transaction {
+        version: transaction::Version::maybe_non_standard(0),
+        lock_time: absolute::LockTime::ZERO,
+        inputs: vec![TxIn {
+            previous_output: OutPoint::COINBASE_PREVOUT,
+            script_sig,
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        outputs: vec![TxOut { value: Amount::ZERO, script_pubkey: script_pubkey.to_owned() }],
+    }
+}
+
+/// Constructs the unsigned virtual `to_sign` transaction spending `to_spend`.
+///
+/// The returned transaction has a single input, add further inputs for a proof of funds.
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: transaction::Version::maybe_non_standard(0),
+        lock_time: absolute::LockTime::ZERO,
+        inputs: vec![TxIn {
+            previous_output: OutPoint { txid: to_spend.compute_txid(), vout: 0 },
+            script_sig: Default::default(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        outputs: vec![to_sign_output()],
+    }
+}
+
+/// Constructs a PSBT for the `to_sign` transaction, ready to be passed to signers.
+///
+/// The first input spends the `to_spend` transaction for `script_pubkey` and `message`, one
+/// further input is added for each of `additional_inputs` (for a proof of funds). The UTXO
+/// information is filled in for all inputs.
+pub fn to_sign_psbt(
+    script_pubkey: &ScriptPubKey,
+    message: &[u8],
+    additional_inputs: &[(OutPoint, TxOut)],
+) -> Psbt {
+    let to_spend = to_spend(script_pubkey, message);
+    let mut tx = to_sign(&to_spend);
+    tx.inputs.extend(additional_inputs.iter().map(|(outpoint, _)| TxIn {
+        previous_output: *outpoint,
+        script_sig: Default::default(),
+        sequence: Sequence::ZERO,
+        witness: Witness::new(),
+    }));
+
+    let mut psbt = Psbt::from_unsigned_tx(tx).expect("to_sign inputs are unsigned");
+    psbt.inputs[0].witness_utxo = Some(to_spend.outputs[0].clone());
+    psbt.inputs[0].non_witness_utxo = Some(to_spend);
+    for (input, (_, txout)) in psbt.inputs[1..].iter_mut().zip(additional_inputs) {
+        input.witness_utxo = Some(txout.clone());
+    }
+    psbt
+}
+
+/// The single output of every `to_sign` transaction.
+fn to_sign_output() -> TxOut {
+    TxOut {
+        value: Amount::ZERO,
+        script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+    }
+}
+
+/// The encoding used for a BIP-0322 signature.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SignatureFormat {
+    /// Only the witness of the `to_sign` input, usable for segwit addresses.
+    Simple,
+    /// The whole `to_sign` transaction.
+    Full,
+}
+
+/// A BIP-0322 signature.
+///
+/// In order to use the `to_base64` and `from_base64` methods, as well as the `fmt::Display` and
+/// `str::FromStr` implementations, the `base64` feature must be enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signature {
+    /// The witness of the single `to_sign` input.
+    Simple(Witness),
+    /// The signed `to_sign` transaction, possibly spending additional inputs.
+    Full(Transaction),
+}
+
+impl Signature {
+    /// Finalizes a signed `to_sign` PSBT and extracts the signature in `format`.
+    ///
+    /// # Errors
+    ///
+    /// If finalizing fails, or `format` is [`SignatureFormat::Simple`] and the transaction has a
+    /// script sig or more than one input.
+    pub fn from_psbt<C: Verification>(
+        secp: &Secp256k1<C>,
+        mut psbt: Psbt,
+        format: SignatureFormat,
+    ) -> Result<Signature, SignError> {
+        if let Err(errors) = psbt.finalize(secp) {
+            let (_, error) = errors.into_iter().next().expect("errors is non-empty");
+            return Err(SignError::Finalize(error));
+        }
+
+        let mut tx = psbt.extract_tx_unchecked_fee_rate();
+        match format {
+            SignatureFormat::Full => Ok(Signature::Full(tx)),
+            SignatureFormat::Simple => {
+                if tx.inputs.len() != 1 || !tx.inputs[0].script_sig.is_empty() {
+                    return Err(SignError::NotSimple);
+                }
+                let input = tx.inputs.pop().expect("checked above");
+                Ok(Signature::Simple(input.witness))
+            }
+        }
+    }
+
+    /// Returns the format of this signature.
+    pub fn format(&self) -> SignatureFormat {
+        match *self {
+            Signature::Simple(_) => SignatureFormat::Simple,
+            Signature::Full(_) => SignatureFormat::Full,
+        }
+    }
+
+    /// Serializes the signature, this is the consensus encoding of the witness or transaction.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            Signature::Simple(ref witness) => encode::serialize(witness),
+            Signature::Full(ref tx) => encode::serialize(tx),
+        }
+    }
+
+    /// Deserializes a signature.
+    ///
+    /// As described in BIP-0322, `bytes` are decoded as a simple signature if they are a valid
+    /// witness encoding with no trailing data and as a full signature otherwise.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Signature, DecodeError> {
+        if let Ok(witness) = encode::deserialize(bytes) {
+            return Ok(Signature::Simple(witness));
+        }
+        encode::deserialize(bytes).map(Signature::Full).map_err(DecodeError::Transaction)
+    }
+
+    /// Verifies that this signature signs `message` for `address`.
+    ///
+    /// # Errors
+    ///
+    /// If the signature is not valid, or is a proof of funds (use [`Self::verify_proof_of_funds`]).
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        address: &Address,
+        message: &[u8],
+    ) -> Result<(), VerifyError> {
+        self.verify_proof_of_funds(secp, address, message, &[])
+    }
+
+    /// Verifies that this signature signs `message` for `address` and spends the additional
+    /// outputs `additional_spent_outputs`.
+    ///
+    /// `additional_spent_outputs` are the outputs spent by the `to_sign` transaction after the
+    /// first input, in order. The caller is responsible for checking that they are unspent.
+    ///
+    /// # Errors
+    ///
+    /// If the signature is not valid or does not spend exactly `additional_spent_outputs`.
+    pub fn verify_proof_of_funds<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        address: &Address,
+        message: &[u8],
+        additional_spent_outputs: &[TxOut],
+    ) -> Result<(), VerifyError> {
+        let to_spend = to_spend(&address.script_pubkey(), message);
+
+        let to_sign = match *self {
+            Signature::Simple(ref witness) => {
+                let mut to_sign = to_sign(&to_spend);
+                to_sign.inputs[0].witness = witness.clone();
+                to_sign
+            }
+            Signature::Full(ref tx) => {
+                let expected_prevout = OutPoint { txid: to_spend.compute_txid(), vout: 0 };
+                match tx.inputs.first() {
+                    Some(input) if input.previous_output == expected_prevout => {}
+                    _ => return Err(VerifyError::InvalidToSign),
+                }
+                if tx.outputs.len() != 1 || tx.outputs[0] != to_sign_output() {
+                    return Err(VerifyError::InvalidToSign);
+                }
+                tx.clone()
+            }
+        };
+
+        if to_sign.inputs.len() != additional_spent_outputs.len() + 1 {
+            return Err(VerifyError::SpentOutputs);
+        }
+
+        let mut spent_outputs = Vec::with_capacity(to_sign.inputs.len());
+        spent_outputs.push(to_spend.outputs[0].clone());
+        spent_outputs.extend_from_slice(additional_spent_outputs);
+
+        for input_index in 0..to_sign.inputs.len() {
+            interpreter::verify_input(
+                secp,
+                &to_sign,
+                input_index,
+                &spent_outputs,
+                VerifyFlags::STANDARD,
+            )
+            .map_err(|error| VerifyError::Script { input_index, error })?;
+        }
+        Ok(())
+    }
+}
+
+/// Signs `message` for `address` with `private_key`.
+///
+/// Supports P2PKH, P2WPKH, P2SH-P2WPKH and P2TR key path addresses. Only P2WPKH and P2TR can be
+/// signed using [`SignatureFormat::Simple`].
+///
+/// # Errors
+///
+/// If `address` is not supported or does not belong to `private_key`, or signing fails.
+pub fn sign<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    private_key: &PrivateKey,
+    address: &Address,
+    message: &[u8],
+    format: SignatureFormat,
+) -> Result<Signature, SignError> {
+    sign_inner(secp, private_key, address, message, &[], format)
+}
+
+/// Signs a proof of funds over `message` for `address`, spending `utxos`.
+///
+/// All of `utxos` must be spendable by `private_key` using one of the address types supported by
+/// [`sign`]. The resulting signature always uses [`SignatureFormat::Full`].
+///
+/// # Errors
+///
+/// If `address` or any of `utxos` is not supported or does not belong to `private_key`, or
+/// signing fails.
+pub fn sign_proof_of_funds<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    private_key: &PrivateKey,
+    address: &Address,
+    message: &[u8],
+    utxos: &[(OutPoint, TxOut)],
+) -> Result<Signature, SignError> {
+    sign_inner(secp, private_key, address, message, utxos, SignatureFormat::Full)
+}
+
+fn sign_inner<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    private_key: &PrivateKey,
+    address: &Address,
+    message: &[u8],
+    additional_inputs: &[(OutPoint, TxOut)],
+    format: SignatureFormat,
+) -> Result<Signature, SignError> {
+    let script_pubkey = address.script_pubkey();
+    let mut psbt = to_sign_psbt(&script_pubkey, message, additional_inputs);
+
+    add_key(secp, private_key, &script_pubkey, &mut psbt.inputs[0])?;
+    for (input, (_, txout)) in psbt.inputs[1..].iter_mut().zip(additional_inputs) {
+        add_key(secp, private_key, &txout.script_pubkey, input)?;
+    }
+
+    let mut keys = BTreeMap::new();
+    keys.insert(private_key.public_key(secp), *private_key);
+    if let Err((_, errors)) = psbt.sign(&keys, secp) {
+        let (_, error) = errors.into_iter().next().expect("errors is non-empty");
+        return Err(SignError::Signing(error));
+    }
+
+    Signature::from_psbt(secp, psbt, format)
+}
+
+/// Adds the key information required to sign and finalize `input` spending `script_pubkey`.
+fn add_key<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    private_key: &PrivateKey,
+    script_pubkey: &ScriptPubKey,
+    input: &mut Input,
+) -> Result<(), SignError> {
+    let pubkey = private_key.public_key(secp);
+    let origin = (Fingerprint::default(), DerivationPath::master());
+
+    if script_pubkey.is_p2pkh() {
+        if ScriptPubKeyBuf::new_p2pkh(pubkey.pubkey_hash()) != *script_pubkey {
+            return Err(SignError::KeyMismatch);
+        }
+        input.bip32_derivation.insert(pubkey.inner, origin);
+    } else if script_pubkey.is_p2wpkh() {
+        let wpubkey_hash = pubkey.wpubkey_hash().map_err(|_| SignError::KeyMismatch)?;
+        if ScriptPubKeyBuf::new_p2wpkh(wpubkey_hash) != *script_pubkey {
+            return Err(SignError::KeyMismatch);
+        }
+        input.bip32_derivation.insert(pubkey.inner, origin);
+    } else if script_pubkey.is_p2sh() {
+        let wpubkey_hash = pubkey.wpubkey_hash().map_err(|_| SignError::KeyMismatch)?;
+        let redeem_script = RedeemScriptBuf::new_p2wpkh(wpubkey_hash);
+        let p2sh = redeem_script.to_p2sh().expect("P2WPKH redeem script is small");
+        if p2sh != *script_pubkey {
+            return Err(SignError::KeyMismatch);
+        }
+        input.redeem_script = Some(redeem_script);
+        input.bip32_derivation.insert(pubkey.inner, origin);
+    } else if script_pubkey.is_p2tr() {
+        let internal_key = XOnlyPublicKey::from(pubkey);
+        if ScriptPubKeyBuf::new_p2tr(secp, internal_key, None) != *script_pubkey {
+            return Err(SignError::KeyMismatch);
+        }
+        input.tap_internal_key = Some(internal_key);
+        input.tap_key_origins.insert(internal_key, (Vec::new(), origin));
+    } else {
+        return Err(SignError::UnsupportedAddress);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "base64")]
+mod base64_impls {
+    use base64::prelude::{Engine as _, BASE64_STANDARD};
+
+    use super::*;
+    use crate::prelude::String;
+
+    impl Signature {
+        /// Decodes a signature from base64.
+        pub fn from_base64(s: &str) -> Result<Signature, DecodeError> {
+            let bytes = BASE64_STANDARD.decode(s).map_err(|_| DecodeError::InvalidBase64)?;
+            Signature::from_bytes(&bytes)
+        }
+
+        /// Encodes the signature as base64.
+        pub fn to_base64(&self) -> String { BASE64_STANDARD.encode(self.to_bytes()) }
+    }
+
+    impl fmt::Display for Signature {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let bytes = self.to_bytes();
+            write!(f, "{}", base64::display::Base64Display::new(&bytes, &BASE64_STANDARD))
+        }
+    }
+
+    impl core::str::FromStr for Signature {
+        type Err = DecodeError;
+        fn from_str(s: &str) -> Result<Signature, DecodeError> { Signature::from_base64(s) }
+    }
+}
+
+/// Error signing a BIP-0322 message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SignError {
+    /// Signing for the address type is not supported.
+    UnsupportedAddress,
+    /// The private key does not control the address.
+    KeyMismatch,
+    /// Error signing the `to_sign` PSBT.
+    Signing(psbt::SignError),
+    /// Error finalizing the `to_sign` PSBT.
+    Finalize(FinalizeError),
+    /// The signature cannot be encoded using the simple format.
+    NotSimple,
+}
+
+impl fmt::Display for SignError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use SignError::*;
+
+        match *self {
+            UnsupportedAddress => f.write_str("signing for the address type is not supported"),
+            KeyMismatch => f.write_str("private key does not control the address"),
+            Signing(ref e) => write_err!(f, "failed to sign the to_sign transaction"; e),
+            Finalize(ref e) => write_err!(f, "failed to finalize the to_sign transaction"; e),
+            NotSimple => f.write_str(
+                "signature needs a script sig or additional inputs, use the full format",
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SignError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use SignError::*;
+
+        match *self {
+            Signing(ref e) => Some(e),
+            Finalize(ref e) => Some(e),
+            UnsupportedAddress | KeyMismatch | NotSimple => None,
+        }
+    }
+}
+
+impl From<psbt::SignError> for SignError {
+    fn from(e: psbt::SignError) -> Self { SignError::Signing(e) }
+}
+
+impl From<FinalizeError> for SignError {
+    fn from(e: FinalizeError) -> Self { SignError::Finalize(e) }
+}
+
+/// Error verifying a BIP-0322 signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VerifyError {
+    /// The transaction does not spend `to_spend` or does not have the single `OP_RETURN` output.
+    InvalidToSign,
+    /// The number of additional spent outputs does not match the inputs of `to_sign`.
+    SpentOutputs,
+    /// Script verification of an input failed.
+    Script {
+        /// The index of the input in `to_sign`.
+        input_index: usize,
+        /// The script verification error.
+        error: interpreter::Error,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use VerifyError::*;
+
+        match *self {
+            InvalidToSign =>
+                f.write_str("transaction is not a to_sign transaction for the message"),
+            SpentOutputs =>
+                f.write_str("number of spent outputs does not match the to_sign transaction"),
+            Script { input_index, ref error } =>
+                write_err!(f, "script verification failed for input {}", input_index; error),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VerifyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use VerifyError::*;
+
+        match *self {
+            Script { ref error, .. } => Some(error),
+            InvalidToSign | SpentOutputs => None,
+        }
+    }
+}
+
+/// Error decoding a BIP-0322 signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DecodeError {
+    /// Invalid base64 encoding.
+    InvalidBase64,
+    /// The signature is neither a witness nor a transaction.
+    Transaction(DeserializeError),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use DecodeError::*;
+
+        match *self {
+            InvalidBase64 => f.write_str("invalid base64"),
+            Transaction(ref e) => write_err!(f, "signature is not a witness or transaction"; e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use DecodeError::*;
+
+        match *self {
+            Transaction(ref e) => Some(e),
+            InvalidBase64 => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+    use crate::address::NetworkUnchecked;
+    use crate::script::WScriptHash;
+    use crate::{CompressedPublicKey, KnownHrp, NetworkKind, Txid};
+
+    const WIF: &str = "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k";
+
+    fn address(s: &str) -> Address {
+        Address::<NetworkUnchecked>::from_str(s).unwrap().assume_checked()
+    }
+
+    fn private_key() -> PrivateKey { PrivateKey::from_wif(WIF).unwrap() }
+
+    // Test vectors from BIP-0322.
+    #[test]
+    fn message_hash() {
+        assert_eq!(
+            MessageHash::from_message(b"").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            MessageHash::from_message(b"Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+    }
+
+    #[test]
+    fn virtual_transactions() {
+        let script_pubkey = address("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l").script_pubkey();
+        let vectors = [
+            (
+                &b""[..],
+                "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7",
+                "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6",
+            ),
+            (
+                &b"Hello World"[..],
+                "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b",
+                "88737ae86f2077145f93cc4b153ae9a1cb8d56afa511988c149c5c8c9d93bddf",
+            ),
+        ];
+        for (message, to_spend_txid, to_sign_txid) in vectors {
+            let to_spend = to_spend(&script_pubkey, message);
+            assert_eq!(to_spend.compute_txid().to_string(), to_spend_txid);
+            assert_eq!(to_sign(&to_spend).compute_txid().to_string(), to_sign_txid);
+        }
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let secp = Secp256k1::new();
+        let private_key = private_key();
+        let pubkey = private_key.public_key(&secp);
+        let compressed: CompressedPublicKey = pubkey.try_into().unwrap();
+
+        let p2pkh = Address::p2pkh(pubkey, NetworkKind::Main);
+        let p2wpkh = Address::p2wpkh(compressed, KnownHrp::Mainnet);
+        let p2shwpkh = Address::p2shwpkh(compressed, NetworkKind::Main);
+        let p2tr = Address::p2tr(&secp, XOnlyPublicKey::from(pubkey), None, KnownHrp::Mainnet);
+        assert_eq!(p2tr, address("bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3"));
+
+        for address in [&p2pkh, &p2wpkh, &p2shwpkh, &p2tr] {
+            let sig =
+                sign(&secp, &private_key, address, b"Hello World", SignatureFormat::Full).unwrap();
+            assert_eq!(sig.format(), SignatureFormat::Full);
+            sig.verify(&secp, address, b"Hello World").unwrap();
+            assert!(sig.verify(&secp, address, b"Hello world").is_err());
+            assert_eq!(Signature::from_bytes(&sig.to_bytes()).unwrap(), sig);
+        }
+
+        for address in [&p2wpkh, &p2tr] {
+            let sig = sign(&secp, &private_key, address, b"", SignatureFormat::Simple).unwrap();
+            assert_eq!(sig.format(), SignatureFormat::Simple);
+            sig.verify(&secp, address, b"").unwrap();
+            assert!(matches!(
+                sig.verify(&secp, address, b"Hello World"),
+                Err(VerifyError::Script { input_index: 0, .. })
+            ));
+            assert_eq!(Signature::from_bytes(&sig.to_bytes()).unwrap(), sig);
+        }
+
+        for address in [&p2pkh, &p2shwpkh] {
+            assert_eq!(
+                sign(&secp, &private_key, address, b"", SignatureFormat::Simple),
+                Err(SignError::NotSimple)
+            );
+        }
+    }
+
+    #[test]
+    fn sign_errors() {
+        let secp = Secp256k1::new();
+        let other = address("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l");
+        let other_key = PrivateKey::from_byte_array([1; 32], NetworkKind::Main).unwrap();
+        assert_eq!(
+            sign(&secp, &other_key, &other, b"", SignatureFormat::Simple),
+            Err(SignError::KeyMismatch)
+        );
+
+        let p2wsh =
+            Address::p2wsh_from_hash(WScriptHash::from_byte_array([0; 32]), KnownHrp::Mainnet);
+        assert_eq!(
+            sign(&secp, &other_key, &p2wsh, b"", SignatureFormat::Full),
+            Err(SignError::UnsupportedAddress)
+        );
+    }
+
+    #[test]
+    fn proof_of_funds() {
+        let secp = Secp256k1::new();
+        let private_key = private_key();
+        let address = address("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l");
+        let utxo = TxOut {
+            value: Amount::from_sat(100_000).unwrap(),
+            script_pubkey: address.script_pubkey(),
+        };
+        let outpoint = OutPoint { txid: Txid::from_byte_array([1; 32]), vout: 3 };
+
+        let sig = sign_proof_of_funds(
+            &secp,
+            &private_key,
+            &address,
+            b"Hello World",
+            &[(outpoint, utxo.clone())],
+        )
+        .unwrap();
+        sig.verify_proof_of_funds(&secp, &address, b"Hello World", core::slice::from_ref(&utxo))
+            .unwrap();
+        assert_eq!(sig.verify(&secp, &address, b"Hello World"), Err(VerifyError::SpentOutputs));
+
+        let other = TxOut { value: Amount::from_sat(200_000).unwrap(), ..utxo };
+        assert!(matches!(
+            sig.verify_proof_of_funds(&secp, &address, b"Hello World", &[other]),
+            Err(VerifyError::Script { input_index: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn full_signature_must_spend_to_spend() {
+        let secp = Secp256k1::new();
+        let address = address("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l");
+        let sig = sign(&secp, &private_key(), &address, b"", SignatureFormat::Full).unwrap();
+        let mut tx = match sig {
+            Signature::Full(tx) => tx,
+            Signature::Simple(_) => unreachable!(),
+        };
+
+        tx.outputs[0].value = Amount::ONE_SAT;
+        assert_eq!(
+            Signature::Full(tx.clone()).verify(&secp, &address, b""),
+            Err(VerifyError::InvalidToSign)
+        );
+
+        tx.outputs[0].value = Amount::ZERO;
+        tx.inputs[0].previous_output.vout = 1;
+        assert_eq!(
+            Signature::Full(tx).verify(&secp, &address, b""),
+            Err(VerifyError::InvalidToSign)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "base64")]
+    fn bip322_signatures() {
+        let secp = Secp256k1::new();
+
+        let p2wpkh = address("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l");
+        let vectors = [
+            (&b""[..], "AkcwRAIgM2gBAQqvZX15ZiysmKmQpDrG83avLIT492QBzLnQIxYCIBaTpOaD20qRlEylyxFSeEA2ba9YOixpX8z46TSDtS40ASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="),
+            (&b"Hello World"[..], "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="),
+            (&b"Hello World"[..], "AkgwRQIhAOzyynlqt93lOKJr+wmmxIens//zPzl9tqIOua93wO6MAiBi5n5EyAcPScOjf1lAqIUIQtr3zKNeavYabHyR8eGhowEhAsfxIAMZZEKUPYWI4BruhAQjzFT8FSFSajuFwrDL1Yhy"),
+        ];
+        for (message, s) in vectors {
+            let sig = Signature::from_str(s).unwrap();
+            assert_eq!(sig.format(), SignatureFormat::Simple);
+            sig.verify(&secp, &p2wpkh, message).unwrap();
+        }
+        let sig = Signature::from_str(vectors[0].1).unwrap();
+        assert!(sig.verify(&secp, &p2wpkh, b"Hello World").is_err());
+
+        let p2tr = address("bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3");
+        let sig = Signature::from_str(
+            "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ==",
+        )
+        .unwrap();
+        sig.verify(&secp, &p2tr, b"Hello World").unwrap();
+        assert_eq!(sig.to_string().parse::<Signature>().unwrap(), sig);
+
+        assert_eq!(Signature::from_base64("not base64!"), Err(DecodeError::InvalidBase64));
+    }
+}
@@ -0,0 +1,281 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Silent payment addresses.
+//!
+//! A silent payment address is a bech32m string encoding a version and the receiver's scan and
+//! spend public keys, e.g. `sp1qq...` on mainnet and `tsp1qq...` on test networks.
+
+use core::fmt;
+use core::str::FromStr;
+
+use bech32::primitives::decode::{CheckedHrpstring, CheckedHrpstringError};
+use bech32::primitives::gf32::Fe32;
+use bech32::primitives::iter::{ByteIterExt, Fe32IterExt};
+use bech32::{Bech32m, Hrp};
+use internals::write_err;
+
+use crate::network::NetworkKind;
+use crate::prelude::{String, ToString, Vec};
+
+/// Human-readable part of mainnet silent payment addresses.
+const HRP_MAIN: &str = "sp";
+/// Human-readable part of silent payment addresses on test networks.
+const HRP_TEST: &str = "tsp";
+
+/// Number of bytes of key material encoded in an address (two compressed public keys).
+const KEYS_LEN: usize = 66;
+
+/// A silent payment address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SilentPaymentAddress {
+    version: u8,
+    network: NetworkKind,
+    scan_key: secp256k1::PublicKey,
+    spend_key: secp256k1::PublicKey,
+}
+
+impl SilentPaymentAddress {
+    /// Creates a version 0 silent payment address from the receiver's scan and spend keys.
+    ///
+    /// For a labeled address `spend_key` is the labeled spend key `B_m`.
+    pub fn new(
+        network: impl Into<NetworkKind>,
+        scan_key: secp256k1::PublicKey,
+        spend_key: secp256k1::PublicKey,
+    ) -> Self {
+        Self { version: 0, network: network.into(), scan_key, spend_key }
+    }
+
+    /// Returns the address version.
+    ///
+    /// Addresses with versions newer than 0 are parsed in a forward compatible way, only their
+    /// keys are used.
+    pub fn version(&self) -> u8 { self.version }
+
+    /// Returns the kind of network this address is valid on.
+    pub fn network(&self) -> NetworkKind { self.network }
+
+    /// Returns the receiver's scan public key.
+    pub fn scan_key(&self) -> secp256k1::PublicKey { self.scan_key }
+
+    /// Returns the receiver's (possibly labeled) spend public key.
+    pub fn spend_key(&self) -> secp256k1::PublicKey { self.spend_key }
+
+    /// Returns the human-readable part used to encode this address.
+    fn hrp(&self) -> Hrp {
+        let hrp = match self.network {
+            NetworkKind::Main => HRP_MAIN,
+            NetworkKind::Test => HRP_TEST,
+        };
+        Hrp::parse_unchecked(hrp)
+    }
+}
+
+/// Formats the address as upper case if alternate formatting is chosen (`{:#}`).
+impl fmt::Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut keys = [0u8; KEYS_LEN];
+        keys[..33].copy_from_slice(&self.scan_key.serialize());
+        keys[33..].copy_from_slice(&self.spend_key.serialize());
+
+        let version = Fe32::try_from(self.version).expect("versions 0-30 are valid fe32 values");
+        let hrp = self.hrp();
+        let chars = keys
+            .iter()
+            .copied()
+            .bytes_to_fes()
+            .with_checksum::<Bech32m>(&hrp)
+            .with_witness_version(version)
+            .chars();
+
+        let upper = f.alternate();
+        chars
+            .map(|c| if upper { c.to_ascii_uppercase() } else { c })
+            .try_for_each(|c| fmt::Write::write_char(f, c))
+    }
+}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let checked =
+            CheckedHrpstring::new::<Bech32m>(s).map_err(|e| ParseError::Bech32(Bech32Error(e)))?;
+
+        let hrp = checked.hrp();
+        let network = if hrp.as_str().eq_ignore_ascii_case(HRP_MAIN) {
+            NetworkKind::Main
+        } else if hrp.as_str().eq_ignore_ascii_case(HRP_TEST) {
+            NetworkKind::Test
+        } else {
+            return Err(ParseError::UnknownHrp(hrp.to_string()));
+        };
+
+        let mut data = checked
+            .data_part_ascii_no_checksum()
+            .iter()
+            .map(|&c| Fe32::from_char(char::from(c)).expect("checked by CheckedHrpstring"));
+
+        let version = data.next().ok_or(ParseError::InvalidLength(0))?.to_u8();
+        if version == 31 {
+            return Err(ParseError::InvalidVersion(version));
+        }
+
+        let bytes = data.fes_to_bytes().collect::<Vec<u8>>();
+        // Version 0 encodes exactly the two keys, later versions may append data which we ignore.
+        if bytes.len() < KEYS_LEN || (version == 0 && bytes.len() != KEYS_LEN) {
+            return Err(ParseError::InvalidLength(bytes.len()));
+        }
+
+        let scan_key = secp256k1::PublicKey::from_slice(&bytes[..33])?;
+        let spend_key = secp256k1::PublicKey::from_slice(&bytes[33..KEYS_LEN])?;
+
+        Ok(Self { version, network, scan_key, spend_key })
+    }
+}
+
+/// Error parsing a [`SilentPaymentAddress`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// Invalid bech32m encoding.
+    Bech32(Bech32Error),
+    /// The human-readable part is not `sp` or `tsp`.
+    UnknownHrp(String),
+    /// Version 31 is reserved as invalid.
+    InvalidVersion(u8),
+    /// The data part does not hold the expected number of bytes.
+    InvalidLength(usize),
+    /// The scan or spend key is not a valid compressed public key.
+    PublicKey(secp256k1::Error),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ParseError::*;
+
+        match *self {
+            Bech32(ref e) => write_err!(f, "invalid silent payment address encoding"; e),
+            UnknownHrp(ref hrp) => write!(f, "unknown silent payment hrp: {}", hrp),
+            InvalidVersion(v) => write!(f, "invalid silent payment address version: {}", v),
+            InvalidLength(len) =>
+                write!(f, "invalid silent payment address data length: {} bytes", len),
+            PublicKey(ref e) => write_err!(f, "invalid silent payment address key"; e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ParseError::*;
+
+        match *self {
+            Bech32(ref e) => Some(e),
+            PublicKey(ref e) => Some(e),
+            UnknownHrp(_) | InvalidVersion(_) | InvalidLength(_) => None,
+        }
+    }
+}
+
+impl From<secp256k1::Error> for ParseError {
+    fn from(e: secp256k1::Error) -> Self { Self::PublicKey(e) }
+}
+
+/// Bech32m decoding error.
+// This wrapper exists because we do not want to expose the `bech32` crate in our public API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bech32Error(CheckedHrpstringError);
+
+impl fmt::Display for Bech32Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_err!(f, "bech32m decoding error"; self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Bech32Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { Some(&self.0) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> (secp256k1::PublicKey, secp256k1::PublicKey) {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let scan = secp256k1::SecretKey::from_byte_array(&[0x11; 32]).unwrap();
+        let spend = secp256k1::SecretKey::from_byte_array(&[0x22; 32]).unwrap();
+        (scan.public_key(&secp), spend.public_key(&secp))
+    }
+
+    #[test]
+    fn roundtrip() {
+        let (scan, spend) = keys();
+        for network in [NetworkKind::Main, NetworkKind::Test] {
+            let addr = SilentPaymentAddress::new(network, scan, spend);
+            let s = addr.to_string();
+            assert!(s.starts_with(if network.is_mainnet() { "sp1q" } else { "tsp1q" }));
+            assert_eq!(s.parse::<SilentPaymentAddress>().unwrap(), addr);
+
+            let upper = format!("{:#}", addr);
+            assert_eq!(upper, s.to_ascii_uppercase());
+            assert_eq!(upper.parse::<SilentPaymentAddress>().unwrap(), addr);
+        }
+    }
+
+    #[test]
+    fn forward_compatible_version() {
+        let (scan, spend) = keys();
+        let mut keys = scan.serialize().to_vec();
+        keys.extend_from_slice(&spend.serialize());
+        keys.extend_from_slice(&[0xab; 10]);
+
+        let hrp = Hrp::parse_unchecked(HRP_MAIN);
+        let encode = |version| {
+            keys.iter()
+                .copied()
+                .bytes_to_fes()
+                .with_checksum::<Bech32m>(&hrp)
+                .with_witness_version(Fe32::try_from(version).unwrap())
+                .chars()
+                .collect::<String>()
+        };
+
+        let addr = encode(1).parse::<SilentPaymentAddress>().unwrap();
+        assert_eq!(addr.version(), 1);
+        assert_eq!(addr.scan_key(), scan);
+        assert_eq!(addr.spend_key(), spend);
+
+        // Version 0 must not carry extra data.
+        assert_eq!(
+            encode(0).parse::<SilentPaymentAddress>(),
+            Err(ParseError::InvalidLength(KEYS_LEN + 10))
+        );
+        assert_eq!(encode(31).parse::<SilentPaymentAddress>(), Err(ParseError::InvalidVersion(31)));
+    }
+
+    #[test]
+    fn invalid_hrp() {
+        let (scan, spend) = keys();
+        let s = SilentPaymentAddress::new(NetworkKind::Main, scan, spend).to_string();
+        let bc = s.replacen("sp1", "bc1", 1);
+        assert!(matches!(bc.parse::<SilentPaymentAddress>(), Err(ParseError::Bech32(_))));
+
+        let mut keys = scan.serialize().to_vec();
+        keys.extend_from_slice(&spend.serialize());
+        let hrp = Hrp::parse_unchecked("bc");
+        let bc = keys
+            .iter()
+            .copied()
+            .bytes_to_fes()
+            .with_checksum::<Bech32m>(&hrp)
+            .with_witness_version(Fe32::Q)
+            .chars()
+            .collect::<String>();
+        assert_eq!(
+            bc.parse::<SilentPaymentAddress>(),
+            Err(ParseError::UnknownHrp("bc".to_string()))
+        );
+    }
+}
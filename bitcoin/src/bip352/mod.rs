@@ -0,0 +1,776 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! BIP-0352 silent payments.
+//!
+//! Implementation of the silent payment protocol described in BIP-0352. A receiver publishes a
+//! static [`SilentPaymentAddress`] and a sender derives a fresh Taproot output key for it from the
+//! public keys of the inputs it spends, via an ECDH shared secret. The receiver finds payments by
+//! scanning transactions with their scan key, nothing is shared on chain.
+//!
+//! Only inputs spending P2PKH, P2WPKH, P2SH-P2WPKH and P2TR outputs contribute to the shared
+//! secret, see [`input_public_key`].
+
+pub mod address;
+
+use core::fmt;
+
+use hashes::{hash160, sha256t, sha256t_tag, HashEngine};
+use internals::write_err;
+use secp256k1::{Parity, Scalar, Secp256k1, SecretKey, Signing, Verification};
+
+use crate::consensus::encode;
+use crate::crypto::key::XOnlyPublicKey;
+use crate::network::NetworkKind;
+use crate::prelude::{BTreeMap, Vec};
+use crate::script::{ScriptExt as _, ScriptPubKey, ScriptPubKeyExt as _};
+use crate::taproot::TAPROOT_ANNEX_PREFIX;
+use crate::transaction::{OutPoint, Transaction, TxIn, TxOut};
+
+#[rustfmt::skip]                // Keep public re-exports separate.
+#[doc(inline)]
+pub use self::address::{ParseError, SilentPaymentAddress};
+
+sha256t_tag! {
+    struct InputsTag = hash_str("BIP0352/Inputs");
+}
+
+sha256t_tag! {
+    struct SharedSecretTag = hash_str("BIP0352/SharedSecret");
+}
+
+sha256t_tag! {
+    struct LabelTag = hash_str("BIP0352/Label");
+}
+
+/// The x coordinate of the NUMS point `H` used as internal key by script path only Taproot
+/// outputs, see BIP-0341.
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// Returns the public key an input contributes to the silent payment shared secret.
+///
+/// `script_pubkey` is the script of the output spent by `txin`. Returns `None` if the input is not
+/// eligible, in which case it is ignored by both sender and receiver:
+///
+/// - P2PKH: the last compressed public key in the script sig hashing to the output's key hash.
+/// - P2SH-P2WPKH and P2WPKH: the compressed public key at the end of the witness.
+/// - P2TR: the output key with even y, unless the input is a script path spend with the NUMS
+///   point `H` as internal key.
+///
+/// The signatures in `txin` are not inspected, so a sender may call this before signing with
+/// placeholder signatures in place.
+pub fn input_public_key(txin: &TxIn, script_pubkey: &ScriptPubKey) -> Option<secp256k1::PublicKey> {
+    let spk = script_pubkey.as_bytes();
+
+    if script_pubkey.is_p2pkh() {
+        // The script sig may be malleated so search from the end for the key matching the hash.
+        let script_sig = txin.script_sig.as_bytes();
+        let key_hash = &spk[3..23];
+        return (33..=script_sig.len()).rev().map(|end| &script_sig[end - 33..end]).find_map(
+            |key| {
+                if is_compressed_key(key) && hash160::Hash::hash(key).as_byte_array() == key_hash {
+                    secp256k1::PublicKey::from_slice(key).ok()
+                } else {
+                    None
+                }
+            },
+        );
+    }
+
+    if script_pubkey.is_p2sh() {
+        // Only P2SH-P2WPKH is eligible, the script sig is a single push of the redeem script.
+        let script_sig = txin.script_sig.as_bytes();
+        if script_sig.len() != 23 || script_sig[0] != 22 || script_sig[1..3] != [0x00, 0x14] {
+            return None;
+        }
+        if hash160::Hash::hash(&script_sig[1..]).as_byte_array() != &spk[2..22] {
+            return None;
+        }
+        return witness_public_key(txin);
+    }
+
+    if script_pubkey.is_p2wpkh() {
+        return witness_public_key(txin);
+    }
+
+    if script_pubkey.is_p2tr() {
+        let witness = &txin.witness;
+        let mut len = witness.len();
+        if len >= 2
+            && witness.last().map_or(false, |item| item.first() == Some(&TAPROOT_ANNEX_PREFIX))
+        {
+            len -= 1;
+        }
+        if len > 1 && witness[len - 1].get(1..33) == Some(&NUMS_H[..]) {
+            return None;
+        }
+        let output_key = secp256k1::XOnlyPublicKey::from_byte_array(
+            spk[2..34].try_into().expect("P2TR program is 32 bytes"),
+        )
+        .ok()?;
+        return Some(output_key.public_key(Parity::Even));
+    }
+
+    None
+}
+
+/// Returns the compressed public key at the end of the witness of a P2WPKH spend.
+fn witness_public_key(txin: &TxIn) -> Option<secp256k1::PublicKey> {
+    txin.witness
+        .last()
+        .filter(|key| is_compressed_key(key))
+        .and_then(|key| secp256k1::PublicKey::from_slice(key).ok())
+}
+
+fn is_compressed_key(key: &[u8]) -> bool { key.len() == 33 && (key[0] == 0x02 || key[0] == 0x03) }
+
+/// Returns true if any of the spent outputs is a segwit output with version greater than 1.
+///
+/// Such transactions are skipped to leave room for future upgrades.
+fn spends_future_segwit<'a>(mut script_pubkeys: impl Iterator<Item = &'a ScriptPubKey>) -> bool {
+    script_pubkeys.any(|spk| spk.witness_version().map_or(false, |v| v.to_num() > 1))
+}
+
+/// Computes `input_hash`, the tagged hash of the smallest outpoint and the summed input key.
+fn input_hash<'a>(
+    outpoints: impl Iterator<Item = &'a OutPoint>,
+    input_key: &secp256k1::PublicKey,
+) -> Result<Scalar, Error> {
+    let smallest = outpoints.map(encode::serialize).min().ok_or(Error::NoEligibleInputs)?;
+
+    let mut eng = sha256t::Hash::<InputsTag>::engine();
+    eng.input(&smallest);
+    eng.input(&input_key.serialize());
+    let hash = sha256t::Hash::<InputsTag>::from_engine(eng);
+    Ok(Scalar::from(SecretKey::from_byte_array(&hash.to_byte_array())?))
+}
+
+/// Computes the output tweak `t_k` from the ECDH shared secret.
+fn shared_secret_tweak(shared_secret: &secp256k1::PublicKey, k: u32) -> Result<SecretKey, Error> {
+    let mut eng = sha256t::Hash::<SharedSecretTag>::engine();
+    eng.input(&shared_secret.serialize());
+    eng.input(&k.to_be_bytes());
+    let hash = sha256t::Hash::<SharedSecretTag>::from_engine(eng);
+    Ok(SecretKey::from_byte_array(&hash.to_byte_array())?)
+}
+
+/// Computes the tweak for label `m`.
+fn label_tweak(scan_key: &SecretKey, m: u32) -> Result<SecretKey, Error> {
+    let mut eng = sha256t::Hash::<LabelTag>::engine();
+    eng.input(&scan_key.secret_bytes());
+    eng.input(&m.to_be_bytes());
+    let hash = sha256t::Hash::<LabelTag>::from_engine(eng);
+    Ok(SecretKey::from_byte_array(&hash.to_byte_array())?)
+}
+
+/// An input of a transaction paying to silent payment addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SenderInput<'a> {
+    /// The input as it will appear in the transaction, signatures may be placeholders.
+    pub txin: &'a TxIn,
+    /// The output spent by this input.
+    pub prevout: &'a TxOut,
+    /// The secret key signing this input, required if the input is eligible.
+    ///
+    /// For P2TR key path spends this is the tweaked output key.
+    pub secret_key: Option<SecretKey>,
+}
+
+/// Derives the Taproot output keys paying `recipients` from a transaction spending `inputs`.
+///
+/// The returned keys are in the same order as `recipients`; paying the same address more than
+/// once yields a distinct key each time.
+///
+/// # Errors
+///
+/// Silent payments can not be used if the transaction spends a segwit output with version greater
+/// than 1, if none of the inputs is eligible or if the input keys sum to zero.
+pub fn create_outputs<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    inputs: &[SenderInput],
+    recipients: &[SilentPaymentAddress],
+) -> Result<Vec<XOnlyPublicKey>, Error> {
+    if spends_future_segwit(inputs.iter().map(|input| input.prevout.script_pubkey.as_script())) {
+        return Err(Error::FutureSegwitSpend);
+    }
+
+    let mut sum: Option<SecretKey> = None;
+    for (index, input) in inputs.iter().enumerate() {
+        let public_key = match input_public_key(input.txin, &input.prevout.script_pubkey) {
+            Some(key) => key,
+            None => continue,
+        };
+        let mut secret_key = input.secret_key.ok_or(Error::MissingSecretKey(index))?;
+
+        let (x_only, parity) = secret_key.x_only_public_key(secp);
+        if input.prevout.script_pubkey.is_p2tr() {
+            if x_only != public_key.x_only_public_key().0 {
+                return Err(Error::SecretKeyMismatch(index));
+            }
+            if parity == Parity::Odd {
+                secret_key = secret_key.negate();
+            }
+        } else if secret_key.public_key(secp) != public_key {
+            return Err(Error::SecretKeyMismatch(index));
+        }
+
+        sum = Some(match sum {
+            None => secret_key,
+            Some(sum) => sum.add_tweak(&Scalar::from(secret_key)).map_err(|_| Error::ZeroSum)?,
+        });
+    }
+    let sum = sum.ok_or(Error::NoEligibleInputs)?;
+
+    let input_hash =
+        input_hash(inputs.iter().map(|input| &input.txin.previous_output), &sum.public_key(secp))?;
+    let sum = sum.mul_tweak(&input_hash)?;
+
+    // Recipients sharing a scan key share the ECDH secret, `k` counts their outputs.
+    let mut shared_secrets: Vec<(secp256k1::PublicKey, secp256k1::PublicKey, u32)> = Vec::new();
+    let mut outputs = Vec::with_capacity(recipients.len());
+    for recipient in recipients {
+        let scan_key = recipient.scan_key();
+        let index = match shared_secrets.iter().position(|(key, _, _)| *key == scan_key) {
+            Some(index) => index,
+            None => {
+                let shared_secret = scan_key.mul_tweak(secp, &Scalar::from(sum))?;
+                shared_secrets.push((scan_key, shared_secret, 0));
+                shared_secrets.len() - 1
+            }
+        };
+        let (_, shared_secret, k) = &mut shared_secrets[index];
+        let tweak = shared_secret_tweak(shared_secret, *k)?;
+        *k += 1;
+
+        let output_key = recipient.spend_key().add_exp_tweak(secp, &Scalar::from(tweak))?;
+        outputs.push(output_key.x_only_public_key().0.into());
+    }
+    Ok(outputs)
+}
+
+/// A silent payment receiver, scans transactions for outputs paying to its addresses.
+#[derive(Debug, Clone)]
+pub struct Receiver {
+    network: NetworkKind,
+    scan_secret: SecretKey,
+    scan_key: secp256k1::PublicKey,
+    spend_key: secp256k1::PublicKey,
+    /// Maps the label public key `label·G` to the label and its tweak.
+    labels: BTreeMap<secp256k1::PublicKey, (u32, SecretKey)>,
+}
+
+impl Receiver {
+    /// Creates a receiver from its secret scan key and public spend key.
+    ///
+    /// Only the spend public key is needed for scanning, the spend secret key is required to spend
+    /// found outputs, see [`FoundOutput::spending_key`].
+    pub fn new<C: Signing>(
+        secp: &Secp256k1<C>,
+        network: impl Into<NetworkKind>,
+        scan_secret: SecretKey,
+        spend_key: secp256k1::PublicKey,
+    ) -> Self {
+        Self {
+            network: network.into(),
+            scan_secret,
+            scan_key: scan_secret.public_key(secp),
+            spend_key,
+            labels: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the receiver's unlabeled address.
+    pub fn address(&self) -> SilentPaymentAddress {
+        SilentPaymentAddress::new(self.network, self.scan_key, self.spend_key)
+    }
+
+    /// Registers label `m` and returns the corresponding labeled address.
+    ///
+    /// Outputs paying to labeled addresses are only found once their label is registered. Label
+    /// 0 is reserved by BIP-0352 for change.
+    pub fn add_label<C: Signing>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        m: u32,
+    ) -> Result<SilentPaymentAddress, Error> {
+        let tweak = label_tweak(&self.scan_secret, m)?;
+        let label = tweak.public_key(secp);
+        let spend_key = self.spend_key.combine(&label)?;
+        self.labels.insert(label, (m, tweak));
+        Ok(SilentPaymentAddress::new(self.network, self.scan_key, spend_key))
+    }
+
+    /// Returns the outputs of `tx` paying to this receiver.
+    ///
+    /// `prevouts` are the outputs spent by `tx`, in input order. Transactions that can not carry
+    /// silent payments yield no outputs.
+    pub fn scan<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        tx: &Transaction,
+        prevouts: &[TxOut],
+    ) -> Result<Vec<FoundOutput>, Error> {
+        if tx.inputs.len() != prevouts.len() {
+            return Err(Error::PrevoutsLength {
+                inputs: tx.inputs.len(),
+                prevouts: prevouts.len(),
+            });
+        }
+        if spends_future_segwit(prevouts.iter().map(|prevout| prevout.script_pubkey.as_script())) {
+            return Ok(Vec::new());
+        }
+
+        let keys = tx
+            .inputs
+            .iter()
+            .zip(prevouts)
+            .filter_map(|(txin, prevout)| input_public_key(txin, &prevout.script_pubkey))
+            .collect::<Vec<_>>();
+        let input_key = match secp256k1::PublicKey::combine_keys(&keys.iter().collect::<Vec<_>>()) {
+            Ok(key) => key,
+            // No eligible inputs or the keys sum to the point at infinity.
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let input_hash =
+            input_hash(tx.inputs.iter().map(|txin| &txin.previous_output), &input_key)?;
+        let shared_secret = input_key
+            .mul_tweak(secp, &input_hash)?
+            .mul_tweak(secp, &Scalar::from(self.scan_secret))?;
+
+        let mut outputs = tx
+            .outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| output.script_pubkey.is_p2tr())
+            .filter_map(|(vout, output)| {
+                let program = output.script_pubkey.as_bytes()[2..34].try_into().expect("32 bytes");
+                let key = secp256k1::XOnlyPublicKey::from_byte_array(program).ok()?;
+                Some((vout, key))
+            })
+            .collect::<Vec<_>>();
+
+        let mut found = Vec::new();
+        for k in 0.. {
+            let tweak = shared_secret_tweak(&shared_secret, k)?;
+            let candidate = self.spend_key.add_exp_tweak(secp, &Scalar::from(tweak))?;
+
+            let hit = outputs.iter().enumerate().find_map(|(i, (_, output_key))| {
+                if candidate.x_only_public_key().0 == *output_key {
+                    return Some((i, tweak, None));
+                }
+                self.match_label(secp, &candidate, output_key)
+                    .map(|(m, label_tweak)| (i, tweak, Some((m, label_tweak))))
+            });
+
+            let (i, tweak, label) = match hit {
+                Some(hit) => hit,
+                None => break,
+            };
+            let (vout, output_key) = outputs.remove(i);
+            let (label, tweak) = match label {
+                None => (None, tweak),
+                Some((m, label_tweak)) => (Some(m), tweak.add_tweak(&Scalar::from(label_tweak))?),
+            };
+            found.push(FoundOutput {
+                vout: vout as u32,
+                output_key: output_key.into(),
+                tweak,
+                label,
+            });
+        }
+        Ok(found)
+    }
+
+    /// Returns the label whose key is the difference between `output_key` (with either parity)
+    /// and `candidate`, if any.
+    fn match_label<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        candidate: &secp256k1::PublicKey,
+        output_key: &secp256k1::XOnlyPublicKey,
+    ) -> Option<(u32, SecretKey)> {
+        if self.labels.is_empty() {
+            return None;
+        }
+        let negated = candidate.negate(secp);
+        [Parity::Even, Parity::Odd].iter().find_map(|parity| {
+            // Combining fails if the keys cancel out, that is no label.
+            let label = output_key.public_key(*parity).combine(&negated).ok()?;
+            self.labels.get(&label).copied()
+        })
+    }
+}
+
+/// An output paying to a silent payment [`Receiver`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FoundOutput {
+    /// Index of the output in the transaction.
+    pub vout: u32,
+    /// The Taproot output key.
+    pub output_key: XOnlyPublicKey,
+    /// The tweak to add to the spend secret key to spend the output.
+    pub tweak: SecretKey,
+    /// The label of the address that was paid to, `None` for the unlabeled address.
+    pub label: Option<u32>,
+}
+
+impl FoundOutput {
+    /// Returns the secret key for the output key, given the receiver's spend secret key.
+    ///
+    /// The key may correspond to the output key with odd y, signers negate it as needed.
+    pub fn spending_key(&self, spend_secret: &SecretKey) -> Result<SecretKey, Error> {
+        Ok(spend_secret.add_tweak(&Scalar::from(self.tweak))?)
+    }
+}
+
+/// Error creating silent payment outputs or scanning for them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The transaction spends a segwit output with version greater than 1.
+    FutureSegwitSpend,
+    /// None of the inputs is eligible for silent payments.
+    NoEligibleInputs,
+    /// The secret keys of the eligible inputs sum to zero.
+    ZeroSum,
+    /// No secret key was given for the eligible input at this index.
+    MissingSecretKey(usize),
+    /// The secret key given for the input at this index does not match its public key.
+    SecretKeyMismatch(usize),
+    /// The number of spent outputs does not match the number of inputs.
+    PrevoutsLength {
+        /// Number of transaction inputs.
+        inputs: usize,
+        /// Number of spent outputs given.
+        prevouts: usize,
+    },
+    /// A hash or key is out of range, this happens with negligible probability.
+    Secp256k1(secp256k1::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Error::*;
+
+        match *self {
+            FutureSegwitSpend =>
+                f.write_str("transaction spends a segwit output with version greater than 1"),
+            NoEligibleInputs => f.write_str("no input is eligible for silent payments"),
+            ZeroSum => f.write_str("input secret keys sum to zero"),
+            MissingSecretKey(index) => write!(f, "missing secret key for input {}", index),
+            SecretKeyMismatch(index) =>
+                write!(f, "secret key does not match the public key of input {}", index),
+            PrevoutsLength { inputs, prevouts } =>
+                write!(f, "got {} spent outputs for {} inputs", prevouts, inputs),
+            Secp256k1(ref e) => write_err!(f, "secp256k1 error"; e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use Error::*;
+
+        match *self {
+            Secp256k1(ref e) => Some(e),
+            FutureSegwitSpend
+            | NoEligibleInputs
+            | ZeroSum
+            | MissingSecretKey(_)
+            | SecretKeyMismatch(_)
+            | PrevoutsLength { .. } => None,
+        }
+    }
+}
+
+impl From<secp256k1::Error> for Error {
+    fn from(e: secp256k1::Error) -> Self { Self::Secp256k1(e) }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::sha256;
+    use hex::FromHex;
+    use secp256k1::Keypair;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::consensus::encode::deserialize;
+    use crate::locktime::absolute;
+    use crate::script::{ScriptPubKeyBuf, ScriptPubKeyBufExt as _, ScriptSigBuf};
+    use crate::transaction::{self, Txid};
+    use crate::{Amount, Sequence, Witness};
+
+    fn read_vectors() -> Vec<Value> {
+        let json_str = include_str!("../../tests/data/send_and_receive_test_vectors.json");
+        serde_json::from_str(json_str).expect("JSON was not well-formatted")
+    }
+
+    fn hex(value: &Value) -> Vec<u8> { Vec::from_hex(value.as_str().unwrap()).unwrap() }
+
+    fn secret_key(value: &Value) -> SecretKey {
+        SecretKey::from_byte_array(&hex(value).try_into().unwrap()).unwrap()
+    }
+
+    /// Returns the inputs, spent outputs and input secret keys of a test vector.
+    fn inputs(given: &Value) -> (Vec<TxIn>, Vec<TxOut>, Vec<SecretKey>) {
+        let mut txins = Vec::new();
+        let mut prevouts = Vec::new();
+        let mut keys = Vec::new();
+        for vin in given["vin"].as_array().unwrap() {
+            let witness = hex(&vin["txinwitness"]);
+            txins.push(TxIn {
+                previous_output: OutPoint {
+                    txid: vin["txid"].as_str().unwrap().parse::<Txid>().unwrap(),
+                    vout: vin["vout"].as_u64().unwrap() as u32,
+                },
+                script_sig: ScriptSigBuf::from_bytes(hex(&vin["scriptSig"])),
+                sequence: Sequence::MAX,
+                witness: if witness.is_empty() {
+                    Witness::new()
+                } else {
+                    deserialize(&witness).unwrap()
+                },
+            });
+            prevouts.push(TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptPubKeyBuf::from_bytes(hex(
+                    &vin["prevout"]["scriptPubKey"]["hex"]
+                )),
+            });
+            keys.push(secret_key(&vin["private_key"]));
+        }
+        (txins, prevouts, keys)
+    }
+
+    fn x_only(value: &Value) -> [u8; 32] { hex(value).try_into().unwrap() }
+
+    #[test]
+    fn bip352_sending() {
+        let secp = Secp256k1::new();
+        for vector in read_vectors() {
+            for case in vector["sending"].as_array().unwrap() {
+                let (txins, prevouts, keys) = inputs(&case["given"]);
+                let inputs = txins
+                    .iter()
+                    .zip(&prevouts)
+                    .zip(keys)
+                    .map(|((txin, prevout), key)| SenderInput {
+                        txin,
+                        prevout,
+                        secret_key: Some(key),
+                    })
+                    .collect::<Vec<_>>();
+                let recipients = case["given"]["recipients"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|r| r.as_str().unwrap().parse::<SilentPaymentAddress>().unwrap())
+                    .collect::<Vec<_>>();
+
+                let mut outputs = match create_outputs(&secp, &inputs, &recipients) {
+                    Ok(outputs) => outputs.iter().map(|key| key.serialize()).collect::<Vec<_>>(),
+                    Err(Error::FutureSegwitSpend | Error::NoEligibleInputs | Error::ZeroSum) =>
+                        Vec::new(),
+                    Err(e) => panic!("{}: {}", vector["comment"], e),
+                };
+                outputs.sort();
+
+                // Any of the expected output sets is valid, their order is not significant.
+                let matches = case["expected"]["outputs"].as_array().unwrap().iter().any(|set| {
+                    let mut expected =
+                        set.as_array().unwrap().iter().map(x_only).collect::<Vec<_>>();
+                    expected.sort();
+                    expected == outputs
+                });
+                assert!(matches, "{}", vector["comment"]);
+            }
+        }
+    }
+
+    #[test]
+    fn bip352_receiving() {
+        let secp = Secp256k1::new();
+        for vector in read_vectors() {
+            for case in vector["receiving"].as_array().unwrap() {
+                let given = &case["given"];
+                let expected = &case["expected"];
+                let scan_secret = secret_key(&given["key_material"]["scan_priv_key"]);
+                let spend_secret = secret_key(&given["key_material"]["spend_priv_key"]);
+                let mut receiver = Receiver::new(
+                    &secp,
+                    NetworkKind::Main,
+                    scan_secret,
+                    spend_secret.public_key(&secp),
+                );
+
+                let mut addresses = vec![receiver.address()];
+                for label in given["labels"].as_array().unwrap() {
+                    let m = label.as_u64().unwrap() as u32;
+                    addresses.push(receiver.add_label(&secp, m).unwrap());
+                }
+                let addresses = addresses.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+                let expected_addresses = expected["addresses"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|a| a.as_str().unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(addresses, expected_addresses, "{}", vector["comment"]);
+
+                let (inputs, prevouts, _) = inputs(given);
+                let outputs = given["outputs"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|key| {
+                        let mut spk = vec![0x51, 0x20];
+                        spk.extend_from_slice(&x_only(key));
+                        TxOut {
+                            value: Amount::ZERO,
+                            script_pubkey: ScriptPubKeyBuf::from_bytes(spk),
+                        }
+                    })
+                    .collect();
+                let tx = Transaction {
+                    version: transaction::Version::TWO,
+                    lock_time: absolute::LockTime::ZERO,
+                    inputs,
+                    outputs,
+                };
+
+                let found = receiver.scan(&secp, &tx, &prevouts).unwrap();
+                for output in &found {
+                    let (key, _) =
+                        output.spending_key(&spend_secret).unwrap().x_only_public_key(&secp);
+                    assert_eq!(XOnlyPublicKey::from(key), output.output_key);
+                }
+
+                // The BIP signs `sha256("message")` with the spending key of each output.
+                let msg = sha256::Hash::hash(b"message");
+                let aux = sha256::Hash::hash(b"random auxiliary data");
+                for expected_output in expected["outputs"].as_array().unwrap() {
+                    let signature = match expected_output.get("signature") {
+                        Some(signature) => hex(signature),
+                        None => continue,
+                    };
+                    let output = found
+                        .iter()
+                        .find(|o| o.output_key.serialize() == x_only(&expected_output["pub_key"]))
+                        .expect("expected output is found");
+                    let keypair = Keypair::from_secret_key(
+                        &secp,
+                        &output.spending_key(&spend_secret).unwrap(),
+                    );
+                    let sig = secp.sign_schnorr_with_aux_rand(
+                        msg.as_byte_array(),
+                        &keypair,
+                        aux.as_byte_array(),
+                    );
+                    assert_eq!(sig.to_byte_array().to_vec(), signature, "{}", vector["comment"]);
+                }
+
+                let mut found = found
+                    .iter()
+                    .map(|output| (output.output_key.serialize(), output.tweak.secret_bytes()))
+                    .collect::<Vec<_>>();
+                found.sort();
+                let mut expected_found = expected["outputs"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|output| (x_only(&output["pub_key"]), x_only(&output["priv_key_tweak"])))
+                    .collect::<Vec<_>>();
+                expected_found.sort();
+                assert_eq!(found, expected_found, "{}", vector["comment"]);
+            }
+        }
+    }
+
+    /// Returns a P2WPKH input spent by `secret_key` and the output it spends.
+    fn p2wpkh_input(secret_key: &SecretKey, vout: u32) -> (TxIn, TxOut) {
+        let secp = Secp256k1::signing_only();
+        let public_key = secret_key.public_key(&secp).serialize();
+        let mut spk = vec![0x00, 0x14];
+        spk.extend_from_slice(hash160::Hash::hash(&public_key).as_byte_array());
+        let txin = TxIn {
+            previous_output: OutPoint { txid: Txid::from_byte_array([1; 32]), vout },
+            script_sig: ScriptSigBuf::new(),
+            sequence: Sequence::MAX,
+            // The signature is a placeholder, it does not affect input eligibility.
+            witness: Witness::from_slice(&[&[0; 71][..], &public_key[..]]),
+        };
+        (txin, TxOut { value: Amount::ZERO, script_pubkey: ScriptPubKeyBuf::from_bytes(spk) })
+    }
+
+    fn test_keys() -> [SecretKey; 4] {
+        [1, 2, 3, 4].map(|i| SecretKey::from_byte_array(&[i; 32]).unwrap())
+    }
+
+    #[test]
+    fn sender_key_errors() {
+        let secp = Secp256k1::new();
+        let [key_0, key_1, scan_secret, spend_secret] = test_keys();
+        let recipient =
+            Receiver::new(&secp, NetworkKind::Main, scan_secret, spend_secret.public_key(&secp))
+                .address();
+        let (txin_0, prevout_0) = p2wpkh_input(&key_0, 0);
+        let (txin_1, prevout_1) = p2wpkh_input(&key_1, 1);
+
+        let mut inputs = vec![
+            SenderInput { txin: &txin_0, prevout: &prevout_0, secret_key: None },
+            SenderInput { txin: &txin_1, prevout: &prevout_1, secret_key: None },
+        ];
+        assert_eq!(create_outputs(&secp, &inputs, &[recipient]), Err(Error::MissingSecretKey(0)));
+
+        inputs[0].secret_key = Some(key_1);
+        assert_eq!(create_outputs(&secp, &inputs, &[recipient]), Err(Error::SecretKeyMismatch(0)));
+
+        inputs[0].secret_key = Some(key_0);
+        inputs[1].secret_key = Some(key_1);
+        let outputs = create_outputs(&secp, &inputs, &[recipient]).unwrap();
+
+        // The receiver finds the output created by the sender.
+        let receiver =
+            Receiver::new(&secp, NetworkKind::Main, scan_secret, spend_secret.public_key(&secp));
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            inputs: vec![txin_0.clone(), txin_1.clone()],
+            outputs: vec![TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptPubKeyBuf::new_p2tr_tweaked(
+                    crate::key::TweakedPublicKey::dangerous_assume_tweaked(outputs[0]),
+                ),
+            }],
+        };
+        let found = receiver.scan(&secp, &tx, &[prevout_0.clone(), prevout_1.clone()]).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].output_key, outputs[0]);
+    }
+
+    #[test]
+    fn scan_prevouts_length() {
+        let secp = Secp256k1::new();
+        let [key_0, key_1, scan_secret, spend_secret] = test_keys();
+        let (txin_0, prevout_0) = p2wpkh_input(&key_0, 0);
+        let (txin_1, _) = p2wpkh_input(&key_1, 1);
+        let receiver =
+            Receiver::new(&secp, NetworkKind::Main, scan_secret, spend_secret.public_key(&secp));
+
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            inputs: vec![txin_0, txin_1],
+            outputs: Vec::new(),
+        };
+        assert_eq!(
+            receiver.scan(&secp, &tx, &[prevout_0]),
+            Err(Error::PrevoutsLength { inputs: 2, prevouts: 1 })
+        );
+    }
+}
@@ -0,0 +1,495 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! BIP-0039 mnemonic codes.
+//!
+//! Conversion between entropy and mnemonic sentences, mnemonic validation and derivation of the
+//! binary seed used by [`Xpriv::new_master`].
+//!
+//! # Examples
+//!
+//! ```
+//! use bitcoin::bip32::Xpriv;
+//! use bitcoin::bip39::Mnemonic;
+//! use bitcoin::Network;
+//!
+//! let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
+//!     .parse::<Mnemonic>()
+//!     .unwrap();
+//! let seed = mnemonic.to_seed("");
+//! let master = Xpriv::new_master(Network::Bitcoin, &seed);
+//! ```
+//!
+//! [`Xpriv::new_master`]: crate::bip32::Xpriv::new_master
+
+mod english;
+
+use core::fmt;
+use core::str::FromStr;
+
+use hashes::{sha256, sha512, Hash as _, HashEngine as _, HmacEngine};
+
+use crate::prelude::ToString;
+
+/// The minimum entropy length in bytes.
+pub const MIN_ENTROPY_LEN: usize = 16;
+
+/// The maximum entropy length in bytes.
+pub const MAX_ENTROPY_LEN: usize = 32;
+
+/// The number of PBKDF2 iterations used to derive the seed.
+const PBKDF2_ROUNDS: u32 = 2048;
+
+/// The number of bits encoded by each word.
+const BITS_PER_WORD: usize = 11;
+
+/// The language of a mnemonic word list.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Language {
+    /// The English word list.
+    English,
+}
+
+impl Language {
+    /// Returns the word list of the language.
+    pub fn word_list(self) -> &'static [&'static str; 2048] {
+        match self {
+            Language::English => &english::WORDS,
+        }
+    }
+
+    /// Returns the index of `word` in the word list, if it is in the list.
+    pub fn find_word(self, word: &str) -> Option<u16> {
+        // The English word list is sorted.
+        self.word_list().binary_search(&word).ok().map(|index| index as u16)
+    }
+}
+
+/// A BIP-0039 mnemonic sentence.
+///
+/// Stores the entropy the sentence encodes, the words and checksum are computed from it.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Mnemonic {
+    language: Language,
+    /// The entropy, followed by unused zero bytes.
+    entropy: [u8; MAX_ENTROPY_LEN],
+    entropy_len: usize,
+}
+
+impl Mnemonic {
+    /// Constructs the English mnemonic encoding `entropy`.
+    ///
+    /// # Errors
+    ///
+    /// If the length of `entropy` is not a multiple of four between 16 and 32 bytes.
+    pub fn from_entropy(entropy: &[u8]) -> Result<Self, EntropyLengthError> {
+        Mnemonic::from_entropy_in(Language::English, entropy)
+    }
+
+    /// Constructs the mnemonic encoding `entropy` in `language`.
+    ///
+    /// # Errors
+    ///
+    /// If the length of `entropy` is not a multiple of four between 16 and 32 bytes.
+    pub fn from_entropy_in(language: Language, entropy: &[u8]) -> Result<Self, EntropyLengthError> {
+        let len = entropy.len();
+        if !(MIN_ENTROPY_LEN..=MAX_ENTROPY_LEN).contains(&len) || len % 4 != 0 {
+            return Err(EntropyLengthError { length: len });
+        }
+
+        let mut buf = [0; MAX_ENTROPY_LEN];
+        buf[..len].copy_from_slice(entropy);
+        Ok(Mnemonic { language, entropy: buf, entropy_len: len })
+    }
+
+    /// Parses a mnemonic sentence in `language`.
+    ///
+    /// Words may be separated by any whitespace. The words must already be in the normalization
+    /// form NFKD, which is always the case for the English word list.
+    ///
+    /// # Errors
+    ///
+    /// If the number of words is not 12, 15, 18, 21 or 24, a word is not in the word list, or the
+    /// checksum is invalid.
+    pub fn parse_in(language: Language, s: &str) -> Result<Self, ParseError> {
+        let word_count = s.split_whitespace().count();
+        if !matches!(word_count, 12 | 15 | 18 | 21 | 24) {
+            return Err(ParseError::WordCount(word_count));
+        }
+
+        // The entropy followed by the checksum, which is at most one byte.
+        let mut bits = [0_u8; MAX_ENTROPY_LEN + 1];
+        for (i, word) in s.split_whitespace().enumerate() {
+            let index = language.find_word(word).ok_or(ParseError::UnknownWord(i))?;
+            for bit in 0..BITS_PER_WORD {
+                if index & (1 << (BITS_PER_WORD - 1 - bit)) != 0 {
+                    let pos = i * BITS_PER_WORD + bit;
+                    bits[pos / 8] |= 1 << (7 - pos % 8);
+                }
+            }
+        }
+
+        let entropy_len = word_count * 4 / 3;
+        let mnemonic = Mnemonic::from_entropy_in(language, &bits[..entropy_len])
+            .expect("word count is checked so the entropy length is valid");
+        let mask = 0xff_u8 << (8 - word_count / 3);
+        if bits[entropy_len] & mask != mnemonic.checksum() & mask {
+            return Err(ParseError::InvalidChecksum);
+        }
+        Ok(mnemonic)
+    }
+
+    /// Returns the language of the mnemonic.
+    pub fn language(&self) -> Language { self.language }
+
+    /// Returns the entropy encoded by the mnemonic.
+    pub fn entropy(&self) -> &[u8] { &self.entropy[..self.entropy_len] }
+
+    /// Returns the number of words in the mnemonic.
+    pub fn word_count(&self) -> usize { self.entropy_len * 3 / 4 }
+
+    /// Returns an iterator over the words of the mnemonic.
+    pub fn words(&self) -> impl Iterator<Item = &'static str> + '_ {
+        let checksum = self.checksum();
+        let bit = move |pos: usize| {
+            let byte = self.entropy().get(pos / 8).copied().unwrap_or(checksum);
+            u16::from((byte >> (7 - pos % 8)) & 1)
+        };
+        let word_list = self.language.word_list();
+        (0..self.word_count()).map(move |i| {
+            let index =
+                (0..BITS_PER_WORD).fold(0, |acc, b| (acc << 1) | bit(i * BITS_PER_WORD + b));
+            word_list[usize::from(index)]
+        })
+    }
+
+    /// Derives the 64 byte seed from the mnemonic and `passphrase`.
+    ///
+    /// The seed is derived using PBKDF2-HMAC-SHA512 with 2048 iterations. The passphrase must
+    /// already be in the normalization form NFKD, which is always the case for ASCII passphrases.
+    pub fn to_seed(&self, passphrase: &str) -> [u8; 64] {
+        let password = self.to_string();
+        let prf = HmacEngine::<sha512::HashEngine>::new(password.as_bytes());
+
+        let mut engine = prf.clone();
+        engine.input(b"mnemonic");
+        engine.input(passphrase.as_bytes());
+        engine.input(&1_u32.to_be_bytes());
+        let mut block = engine.finalize().to_byte_array();
+
+        let mut seed = block;
+        for _ in 1..PBKDF2_ROUNDS {
+            let mut engine = prf.clone();
+            engine.input(&block);
+            block = engine.finalize().to_byte_array();
+            seed.iter_mut().zip(&block).for_each(|(s, b)| *s ^= b);
+        }
+        seed
+    }
+
+    /// Returns the first byte of the SHA256 hash of the entropy, the checksum is its first bits.
+    fn checksum(&self) -> u8 { sha256::Hash::hash(self.entropy()).to_byte_array()[0] }
+}
+
+impl fmt::Debug for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Mnemonic")
+            .field("language", &self.language)
+            .field("entropy", &"[Entropy]")
+            .finish()
+    }
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, word) in self.words().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            f.write_str(word)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Mnemonic {
+    type Err = ParseError;
+
+    /// Parses an English mnemonic sentence.
+    fn from_str(s: &str) -> Result<Self, Self::Err> { Mnemonic::parse_in(Language::English, s) }
+}
+
+/// Invalid entropy length when constructing a mnemonic.
+///
+/// The entropy must be a multiple of four between 16 and 32 bytes long.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct EntropyLengthError {
+    /// The invalid entropy length in bytes.
+    pub length: usize,
+}
+
+impl fmt::Display for EntropyLengthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "entropy length {} is not a multiple of four between {} and {} bytes",
+            self.length, MIN_ENTROPY_LEN, MAX_ENTROPY_LEN
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EntropyLengthError {}
+
+/// Error parsing a mnemonic sentence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// The number of words is not 12, 15, 18, 21 or 24.
+    WordCount(usize),
+    /// The word at this position is not in the word list.
+    UnknownWord(usize),
+    /// The checksum encoded in the last word does not match the entropy.
+    InvalidChecksum,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ParseError::*;
+
+        match *self {
+            WordCount(n) => write!(f, "mnemonic has {} words, expected 12, 15, 18, 21 or 24", n),
+            UnknownWord(i) => write!(f, "mnemonic word at position {} is not in the word list", i),
+            InvalidChecksum => f.write_str("mnemonic checksum does not match"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ParseError::*;
+
+        match *self {
+            WordCount(_) | UnknownWord(_) | InvalidChecksum => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_lit::hex;
+
+    use super::*;
+    use crate::bip32::Xpriv;
+    use crate::Network;
+
+    // Test vectors from https://github.com/trezor/python-mnemonic/blob/master/vectors.json
+    #[test]
+    fn trezor_vectors() {
+        let vectors: &[(&[u8], &str, [u8; 64])] = &[
+            (
+                &hex!("00000000000000000000000000000000"),
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+                hex!("c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"),
+            ),
+            (
+                &hex!("7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f"),
+                "legal winner thank year wave sausage worth useful legal winner thank yellow",
+                hex!("2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607"),
+            ),
+            (
+                &hex!("80808080808080808080808080808080"),
+                "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+                hex!("d71de856f81a8acc65e6fc851a38d4d7ec216fd0796d0a6827a3ad6ed5511a30fa280f12eb2e47ed2ac03b5c462a0358d18d69fe4f985ec81778c1b370b652a8"),
+            ),
+            (
+                &hex!("ffffffffffffffffffffffffffffffff"),
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong",
+                hex!("ac27495480225222079d7be181583751e86f571027b0497b5b5d11218e0a8a13332572917f0f8e5a589620c6f15b11c61dee327651a14c34e18231052e48c069"),
+            ),
+            (
+                &hex!("000000000000000000000000000000000000000000000000"),
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon agent",
+                hex!("035895f2f481b1b0f01fcf8c289c794660b289981a78f8106447707fdd9666ca06da5a9a565181599b79f53b844d8a71dd9f439c52a3d7b3e8a79c906ac845fa"),
+            ),
+            (
+                &hex!("7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f"),
+                "legal winner thank year wave sausage worth useful legal winner thank year wave sausage worth useful legal will",
+                hex!("f2b94508732bcbacbcc020faefecfc89feafa6649a5491b8c952cede496c214a0c7b3c392d168748f2d4a612bada0753b52a1c7ac53c1e93abd5c6320b9e95dd"),
+            ),
+            (
+                &hex!("808080808080808080808080808080808080808080808080"),
+                "letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic avoid letter always",
+                hex!("107d7c02a5aa6f38c58083ff74f04c607c2d2c0ecc55501dadd72d025b751bc27fe913ffb796f841c49b1d33b610cf0e91d3aa239027f5e99fe4ce9e5088cd65"),
+            ),
+            (
+                &hex!("ffffffffffffffffffffffffffffffffffffffffffffffff"),
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo when",
+                hex!("0cd6e5d827bb62eb8fc1e262254223817fd068a74b5b449cc2f667c3f1f985a76379b43348d952e2265b4cd129090758b3e3c2c49103b5051aac2eaeb890a528"),
+            ),
+            (
+                &hex!("0000000000000000000000000000000000000000000000000000000000000000"),
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon art",
+                hex!("bda85446c68413707090a52022edd26a1c9462295029f2e60cd7c4f2bbd3097170af7a4d73245cafa9c3cca8d561a7c3de6f5d4a10be8ed2a5e608d68f92fcc8"),
+            ),
+            (
+                &hex!("7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f"),
+                "legal winner thank year wave sausage worth useful legal winner thank year wave sausage worth useful legal winner thank year wave sausage worth title",
+                hex!("bc09fca1804f7e69da93c2f2028eb238c227f2e9dda30cd63699232578480a4021b146ad717fbb7e451ce9eb835f43620bf5c514db0f8add49f5d121449d3e87"),
+            ),
+            (
+                &hex!("8080808080808080808080808080808080808080808080808080808080808080"),
+                "letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic bless",
+                hex!("c0c519bd0e91a2ed54357d9d1ebef6f5af218a153624cf4f2da911a0ed8f7a09e2ef61af0aca007096df430022f7a2b6fb91661a9589097069720d015e4e982f"),
+            ),
+            (
+                &hex!("ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff"),
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote",
+                hex!("dd48c104698c30cfe2b6142103248622fb7bb0ff692eebb00089b32d22484e1613912f0a5b694407be899ffd31ed3992c456cdf60f5d4564b8ba3f05a69890ad"),
+            ),
+            (
+                &hex!("9e885d952ad362caeb4efe34a8e91bd2"),
+                "ozone drill grab fiber curtain grace pudding thank cruise elder eight picnic",
+                hex!("274ddc525802f7c828d8ef7ddbcdc5304e87ac3535913611fbbfa986d0c9e5476c91689f9c8a54fd55bd38606aa6a8595ad213d4c9c9f9aca3fb217069a41028"),
+            ),
+            (
+                &hex!("6610b25967cdcca9d59875f5cb50b0ea75433311869e930b"),
+                "gravity machine north sort system female filter attitude volume fold club stay feature office ecology stable narrow fog",
+                hex!("628c3827a8823298ee685db84f55caa34b5cc195a778e52d45f59bcf75aba68e4d7590e101dc414bc1bbd5737666fbbef35d1f1903953b66624f910feef245ac"),
+            ),
+            (
+                &hex!("68a79eaca2324873eacc50cb9c6eca8cc68ea5d936f98787c60c7ebc74e6ce7c"),
+                "hamster diagram private dutch cause delay private meat slide toddler razor book happy fancy gospel tennis maple dilemma loan word shrug inflict delay length",
+                hex!("64c87cde7e12ecf6704ab95bb1408bef047c22db4cc7491c4271d170a1b213d20b385bc1588d9c7b38f1b39d415665b8a9030c9ec653d75e65f847d8fc1fc440"),
+            ),
+            (
+                &hex!("c0ba5a8e914111210f2bd131f3d5e08d"),
+                "scheme spot photo card baby mountain device kick cradle pact join borrow",
+                hex!("ea725895aaae8d4c1cf682c1bfd2d358d52ed9f0f0591131b559e2724bb234fca05aa9c02c57407e04ee9dc3b454aa63fbff483a8b11de949624b9f1831a9612"),
+            ),
+            (
+                &hex!("6d9be1ee6ebd27a258115aad99b7317b9c8d28b6d76431c3"),
+                "horn tenant knee talent sponsor spell gate clip pulse soap slush warm silver nephew swap uncle crack brave",
+                hex!("fd579828af3da1d32544ce4db5c73d53fc8acc4ddb1e3b251a31179cdb71e853c56d2fcb11aed39898ce6c34b10b5382772db8796e52837b54468aeb312cfc3d"),
+            ),
+            (
+                &hex!("9f6a2878b2520799a44ef18bc7df394e7061a224d2c33cd015b157d746869863"),
+                "panda eyebrow bullet gorilla call smoke muffin taste mesh discover soft ostrich alcohol speed nation flash devote level hobby quick inner drive ghost inside",
+                hex!("72be8e052fc4919d2adf28d5306b5474b0069df35b02303de8c1729c9538dbb6fc2d731d5f832193cd9fb6aeecbc469594a70e3dd50811b5067f3b88b28c3e8d"),
+            ),
+            (
+                &hex!("23db8160a31d3e0dca3688ed941adbf3"),
+                "cat swing flag economy stadium alone churn speed unique patch report train",
+                hex!("deb5f45449e615feff5640f2e49f933ff51895de3b4381832b3139941c57b59205a42480c52175b6efcffaa58a2503887c1e8b363a707256bdd2b587b46541f5"),
+            ),
+            (
+                &hex!("8197a4a47f0425faeaa69deebc05ca29c0a5b5cc76ceacc0"),
+                "light rule cinnamon wrap drastic word pride squirrel upgrade then income fatal apart sustain crack supply proud access",
+                hex!("4cbdff1ca2db800fd61cae72a57475fdc6bab03e441fd63f96dabd1f183ef5b782925f00105f318309a7e9c3ea6967c7801e46c8a58082674c860a37b93eda02"),
+            ),
+            (
+                &hex!("066dca1a2bb7e8a1db2832148ce9933eea0f3ac9548d793112d9a95c9407efad"),
+                "all hour make first leader extend hole alien behind guard gospel lava path output census museum junior mass reopen famous sing advance salt reform",
+                hex!("26e975ec644423f4a4c4f4215ef09b4bd7ef924e85d1d17c4cf3f136c2863cf6df0a475045652c57eb5fb41513ca2a2d67722b77e954b4b3fc11f7590449191d"),
+            ),
+            (
+                &hex!("f30f8c1da665478f49b001d94c5fc452"),
+                "vessel ladder alter error federal sibling chat ability sun glass valve picture",
+                hex!("2aaa9242daafcee6aa9d7269f17d4efe271e1b9a529178d7dc139cd18747090bf9d60295d0ce74309a78852a9caadf0af48aae1c6253839624076224374bc63f"),
+            ),
+            (
+                &hex!("c10ec20dc3cd9f652c7fac2f1230f7a3c828389a14392f05"),
+                "scissors invite lock maple supreme raw rapid void congress muscle digital elegant little brisk hair mango congress clump",
+                hex!("7b4a10be9d98e6cba265566db7f136718e1398c71cb581e1b2f464cac1ceedf4f3e274dc270003c670ad8d02c4558b2f8e39edea2775c9e232c7cb798b069e88"),
+            ),
+            (
+                &hex!("f585c11aec520db57dd353c69554b21a89b20fb0650966fa0a9d6f74fd989d8f"),
+                "void come effort suffer camp survey warrior heavy shoot primary clutch crush open amazing screen patrol group space point ten exist slush involve unfold",
+                hex!("01f5bced59dec48e362f2c45b5de68b9fd6c92c6634f44d6d40aab69056506f0e35524a518034ddc1192e1dacd32c1ed3eaa3c3b131c88ed8e7e54c49a5d0998"),
+            ),
+        ];
+
+        for &(entropy, sentence, seed) in vectors {
+            let mnemonic = Mnemonic::from_entropy(entropy).unwrap();
+            assert_eq!(mnemonic.to_string(), sentence);
+            assert_eq!(sentence.parse::<Mnemonic>().unwrap(), mnemonic);
+            assert_eq!(mnemonic.entropy(), entropy);
+            assert_eq!(mnemonic.word_count(), sentence.split(' ').count());
+            assert_eq!(mnemonic.to_seed("TREZOR"), seed);
+        }
+    }
+
+    #[test]
+    fn new_master_from_seed() {
+        let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
+            .parse::<Mnemonic>()
+            .unwrap();
+        let master = Xpriv::new_master(Network::Bitcoin, &mnemonic.to_seed("TREZOR"));
+        assert_eq!(
+            master.to_string(),
+            "xprv9s21ZrQH143K3h3fDYiay8mocZ3afhfULfb5GX8kCBdno77K4HiA15Tg23wpbeF1pLfs1c5SPmYHrEpTuuRhxMwvKDwqdKiGJS9XFKzUsAF"
+        );
+    }
+
+    #[test]
+    fn debug_does_not_leak_entropy() {
+        let mnemonic = Mnemonic::from_entropy(&[0x7f; 16]).unwrap();
+        let debug = format!("{:?}", mnemonic);
+        assert_eq!(debug, "Mnemonic { language: English, entropy: \"[Entropy]\" }");
+        assert!(!debug.contains(mnemonic.words().next().unwrap()));
+    }
+
+    #[test]
+    fn parse_whitespace() {
+        let mnemonic =
+            " legal winner thank year wave sausage\tworth useful legal winner thank\n yellow"
+                .parse::<Mnemonic>()
+                .unwrap();
+        assert_eq!(mnemonic.entropy(), [0x7f; 16]);
+    }
+
+    #[test]
+    fn invalid_mnemonics() {
+        let parse = |s: &str| s.parse::<Mnemonic>().unwrap_err();
+
+        assert_eq!(parse("abandon abandon abandon"), ParseError::WordCount(3));
+        assert_eq!(parse(""), ParseError::WordCount(0));
+        assert_eq!(
+            parse("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon"),
+            ParseError::InvalidChecksum
+        );
+        assert_eq!(
+            parse("abandon abandon abandon abandon abandon bitcoin abandon abandon abandon abandon abandon about"),
+            ParseError::UnknownWord(5)
+        );
+        assert_eq!(
+            parse("Abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"),
+            ParseError::UnknownWord(0)
+        );
+    }
+
+    #[test]
+    fn invalid_entropy_length() {
+        for len in [0, 12, 15, 17, 21, 36] {
+            assert_eq!(
+                Mnemonic::from_entropy(&[0; 36][..len]),
+                Err(EntropyLengthError { length: len })
+            );
+        }
+        for len in [16, 20, 24, 28, 32] {
+            assert!(Mnemonic::from_entropy(&[0; 36][..len]).is_ok());
+        }
+    }
+
+    #[test]
+    fn english_word_list() {
+        let words = Language::English.word_list();
+        assert!(words.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(words.iter().all(|word| word.bytes().all(|b| b.is_ascii_lowercase())));
+        assert_eq!(Language::English.find_word("abandon"), Some(0));
+        assert_eq!(Language::English.find_word("zoo"), Some(2047));
+        assert_eq!(Language::English.find_word("bitcoin"), None);
+    }
+}
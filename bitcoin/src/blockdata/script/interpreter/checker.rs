@@ -0,0 +1,290 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Signature and lock time checkers used by the interpreter.
+
+use secp256k1::{Secp256k1, Verification};
+use units::locktime::absolute::LOCK_TIME_THRESHOLD;
+
+use super::error::ErrorKind;
+use super::eval::read_op;
+use super::{ExecData, SigVersion};
+use crate::crypto::sighash::{Annex, Prevouts, SighashCache, TapSighashType, TaprootError};
+use crate::opcodes::all::OP_CODESEPARATOR;
+use crate::prelude::{Borrow, Vec};
+use crate::{Amount, ScriptPubKey, Sequence, Transaction, TxOut, WitnessScript};
+
+/// Bit of the `nSequence` field that disables relative lock time (BIP-0068).
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: i64 = 1 << 31;
+/// Bit of the `nSequence` field that selects a time based relative lock time (BIP-0068).
+const SEQUENCE_LOCKTIME_TYPE_FLAG: i64 = 1 << 22;
+/// Mask of the relative lock time value in the `nSequence` field (BIP-0068).
+const SEQUENCE_LOCKTIME_MASK: i64 = 0x0000_ffff;
+
+/// Checks signatures and lock times on behalf of the interpreter.
+///
+/// The interpreter only deals with script semantics, everything that depends on the spending
+/// transaction is delegated to this trait (Core's `BaseSignatureChecker`).
+pub trait SignatureChecker {
+    /// Returns true if the ECDSA signature `sig` (DER plus sighash byte) is valid for `pubkey`.
+    ///
+    /// `script_code` is the script after the last executed `OP_CODESEPARATOR`, with signatures
+    /// already removed for legacy scripts.
+    fn check_ecdsa_signature(
+        &mut self,
+        sig: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        sig_version: SigVersion,
+    ) -> bool;
+
+    /// Checks the non-empty Schnorr signature `sig` against the 32 byte `pubkey`.
+    ///
+    /// # Errors
+    ///
+    /// The [`ErrorKind`] to fail the script with, normally one of [`ErrorKind::SchnorrSigSize`],
+    /// [`ErrorKind::SchnorrSigHashType`] and [`ErrorKind::SchnorrSig`].
+    fn check_schnorr_signature(
+        &mut self,
+        sig: &[u8],
+        pubkey: &[u8],
+        sig_version: SigVersion,
+        exec_data: &ExecData,
+    ) -> Result<(), ErrorKind>;
+
+    /// Returns true if the transaction satisfies the `OP_CHECKLOCKTIMEVERIFY` argument.
+    fn check_lock_time(&self, lock_time: i64) -> bool;
+
+    /// Returns true if the input satisfies the `OP_CHECKSEQUENCEVERIFY` argument.
+    fn check_sequence(&self, sequence: i64) -> bool;
+}
+
+/// A checker that fails all signature and lock time checks.
+///
+/// Useful to evaluate scripts that don't depend on a transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct NullSignatureChecker;
+
+impl SignatureChecker for NullSignatureChecker {
+    fn check_ecdsa_signature(&mut self, _: &[u8], _: &[u8], _: &[u8], _: SigVersion) -> bool {
+        false
+    }
+
+    fn check_schnorr_signature(
+        &mut self,
+        _: &[u8],
+        _: &[u8],
+        _: SigVersion,
+        _: &ExecData,
+    ) -> Result<(), ErrorKind> {
+        Err(ErrorKind::SchnorrSig)
+    }
+
+    fn check_lock_time(&self, _: i64) -> bool { false }
+
+    fn check_sequence(&self, _: i64) -> bool { false }
+}
+
+/// Checks signatures and lock times against an input of a transaction.
+///
+/// Signature hashes are computed with a [`SighashCache`] so that the cache can be shared between
+/// the inputs of a transaction.
+#[derive(Debug)]
+pub struct TransactionSignatureChecker<'a, C: Verification, T: Borrow<Transaction>> {
+    secp: &'a Secp256k1<C>,
+    cache: &'a mut SighashCache<T>,
+    input_index: usize,
+    amount: Amount,
+    spent_outputs: Option<&'a [TxOut]>,
+}
+
+impl<'a, C: Verification, T: Borrow<Transaction>> TransactionSignatureChecker<'a, C, T> {
+    /// Constructs a new checker for input `input_index` of the cache's transaction.
+    ///
+    /// `amount` is the value of the output being spent, signatures of segwit v0 inputs commit to it.
+    pub fn new(
+        secp: &'a Secp256k1<C>,
+        cache: &'a mut SighashCache<T>,
+        input_index: usize,
+        amount: Amount,
+    ) -> Self {
+        TransactionSignatureChecker { secp, cache, input_index, amount, spent_outputs: None }
+    }
+
+    /// Sets the outputs spent by all the transaction inputs, in order.
+    ///
+    /// Required to check Taproot signatures since those commit to every spent output.
+    pub fn with_spent_outputs(mut self, spent_outputs: &'a [TxOut]) -> Self {
+        self.spent_outputs = Some(spent_outputs);
+        self
+    }
+}
+
+impl<C: Verification, T: Borrow<Transaction>> SignatureChecker
+    for TransactionSignatureChecker<'_, C, T>
+{
+    fn check_ecdsa_signature(
+        &mut self,
+        sig: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        sig_version: SigVersion,
+    ) -> bool {
+        let pubkey = match secp256k1::PublicKey::from_slice(pubkey) {
+            Ok(pubkey) => pubkey,
+            Err(_) => return false,
+        };
+        // The sighash type is the byte appended to the DER signature.
+        let (sighash_type, der) = match sig.split_last() {
+            Some((&sighash_type, der)) => (u32::from(sighash_type), der),
+            None => return false,
+        };
+        let mut signature = match secp256k1::ecdsa::Signature::from_der_lax(der) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        // libsecp256k1 only accepts low S signatures, high S is a policy rule (LOW_S).
+        signature.normalize_s();
+
+        let msg = match sig_version {
+            SigVersion::Base => {
+                let script_code = remove_code_separators(script_code);
+                match self.cache.legacy_signature_hash(
+                    self.input_index,
+                    ScriptPubKey::from_bytes(&script_code),
+                    sighash_type,
+                ) {
+                    Ok(sighash) => secp256k1::Message::from(sighash),
+                    Err(_) => return false,
+                }
+            }
+            SigVersion::WitnessV0 => match self.cache.segwit_v0_signature_hash_consensus(
+                self.input_index,
+                WitnessScript::from_bytes(script_code),
+                self.amount,
+                sighash_type,
+            ) {
+                Ok(sighash) => secp256k1::Message::from(sighash),
+                Err(_) => return false,
+            },
+            SigVersion::Taproot | SigVersion::Tapscript => return false,
+        };
+        self.secp.verify_ecdsa(&msg, &signature, &pubkey).is_ok()
+    }
+
+    fn check_schnorr_signature(
+        &mut self,
+        sig: &[u8],
+        pubkey: &[u8],
+        sig_version: SigVersion,
+        exec_data: &ExecData,
+    ) -> Result<(), ErrorKind> {
+        let (sig, sighash_type) = match sig.len() {
+            64 => (sig, TapSighashType::Default),
+            65 => {
+                // An explicit `SIGHASH_DEFAULT` would make the signature malleable.
+                let (&sighash_type, sig) = sig.split_last().expect("65 bytes");
+                if sighash_type == 0 {
+                    return Err(ErrorKind::SchnorrSigHashType);
+                }
+                let sighash_type = TapSighashType::from_consensus_u8(sighash_type)
+                    .map_err(|_| ErrorKind::SchnorrSigHashType)?;
+                (sig, sighash_type)
+            }
+            _ => return Err(ErrorKind::SchnorrSigSize),
+        };
+        let spent_outputs = self.spent_outputs.ok_or(ErrorKind::InvalidSpentOutputs)?;
+        let annex = match exec_data.annex {
+            Some(annex) => Some(Annex::new(annex).map_err(|_| ErrorKind::SchnorrSig)?),
+            None => None,
+        };
+        let leaf = match sig_version {
+            SigVersion::Taproot => None,
+            SigVersion::Tapscript => {
+                let leaf_hash = exec_data.tapleaf_hash.ok_or(ErrorKind::SchnorrSig)?;
+                Some((leaf_hash, exec_data.code_separator_pos))
+            }
+            SigVersion::Base | SigVersion::WitnessV0 => return Err(ErrorKind::SchnorrSig),
+        };
+
+        let sighash = self
+            .cache
+            .taproot_signature_hash(
+                self.input_index,
+                &Prevouts::All(spent_outputs),
+                annex,
+                leaf,
+                sighash_type,
+            )
+            .map_err(|e| match e {
+                TaprootError::SingleMissingOutput(_) => ErrorKind::SchnorrSigHashType,
+                _ => ErrorKind::InvalidSpentOutputs,
+            })?;
+
+        let pubkey = <[u8; 32]>::try_from(pubkey).map_err(|_| ErrorKind::SchnorrSig)?;
+        let pubkey = secp256k1::XOnlyPublicKey::from_byte_array(&pubkey)
+            .map_err(|_| ErrorKind::SchnorrSig)?;
+        let sig = <[u8; 64]>::try_from(sig).expect("checked length above");
+        let signature = secp256k1::schnorr::Signature::from_byte_array(sig);
+        self.secp
+            .verify_schnorr(&signature, &sighash.to_byte_array(), &pubkey)
+            .map_err(|_| ErrorKind::SchnorrSig)
+    }
+
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        let tx = self.cache.transaction();
+        let tx_lock_time = i64::from(tx.lock_time.to_consensus_u32());
+        let threshold = i64::from(LOCK_TIME_THRESHOLD);
+
+        // Block height and block time lock times can't be compared.
+        if (tx_lock_time < threshold) != (lock_time < threshold) {
+            return false;
+        }
+        if lock_time > tx_lock_time {
+            return false;
+        }
+        // A final input disables the transaction lock time, and with it the check.
+        match tx.inputs.get(self.input_index) {
+            Some(txin) => txin.sequence != Sequence::MAX,
+            None => false,
+        }
+    }
+
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let tx = self.cache.transaction();
+        let tx_sequence = match tx.inputs.get(self.input_index) {
+            Some(txin) => i64::from(txin.sequence.to_consensus_u32()),
+            None => return false,
+        };
+
+        // Relative lock times are only enforced from version 2 (BIP-0068).
+        if tx.version.to_u32() < 2 {
+            return false;
+        }
+        if tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return false;
+        }
+
+        let mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+        let tx_sequence = tx_sequence & mask;
+        let sequence = sequence & mask;
+        // Block based and time based relative lock times can't be compared.
+        if (tx_sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) != (sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) {
+            return false;
+        }
+        sequence <= tx_sequence
+    }
+}
+
+/// Removes all `OP_CODESEPARATOR`s from a legacy scriptCode, signatures don't commit to them.
+fn remove_code_separators(script: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(script.len());
+    let (mut start, mut pc) = (0, 0);
+    while let Some((op, _)) = read_op(script, &mut pc) {
+        if op == OP_CODESEPARATOR {
+            result.extend_from_slice(&script[start..pc - 1]);
+            start = pc;
+        }
+    }
+    result.extend_from_slice(&script[start..]);
+    result
+}
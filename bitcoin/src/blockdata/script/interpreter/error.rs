@@ -0,0 +1,293 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Script interpreter errors.
+
+use core::fmt;
+
+use crate::opcodes::Opcode;
+use crate::prelude::Vec;
+
+/// Error returned when script verification fails.
+///
+/// Besides the [`ErrorKind`] this carries where the failure happened: the script being executed,
+/// the index of the failing opcode within that script and the stack at the time of the failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    kind: ErrorKind,
+    script: Option<ScriptKind>,
+    opcode_index: Option<usize>,
+    opcode: Option<Opcode>,
+    stack: Vec<Vec<u8>>,
+}
+
+impl Error {
+    pub(super) fn new(kind: ErrorKind, script: Option<ScriptKind>, stack: &[Vec<u8>]) -> Self {
+        Error { kind, script, opcode_index: None, opcode: None, stack: stack.to_vec() }
+    }
+
+    pub(super) fn at_opcode(mut self, index: usize, opcode: Option<Opcode>) -> Self {
+        self.opcode_index = Some(index);
+        self.opcode = opcode;
+        self
+    }
+
+    /// Returns the reason verification failed.
+    pub fn kind(&self) -> ErrorKind { self.kind }
+
+    /// Returns the script that was being executed, if the failure happened inside a script.
+    pub fn script(&self) -> Option<ScriptKind> { self.script }
+
+    /// Returns the index of the failing opcode within [`Self::script`].
+    ///
+    /// Pushes count as a single opcode. This is `None` if the failure was not caused by a
+    /// specific opcode (for example the script finished with a false value on the stack).
+    pub fn opcode_index(&self) -> Option<usize> { self.opcode_index }
+
+    /// Returns the failing opcode.
+    ///
+    /// This is `None` if the failure was not caused by a specific opcode or the opcode could not
+    /// be decoded (a push running past the end of the script).
+    pub fn opcode(&self) -> Option<Opcode> { self.opcode }
+
+    /// Returns the stack at the time of the failure, the last element is the top of the stack.
+    pub fn stack(&self) -> &[Vec<u8>] { &self.stack }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.kind, f)?;
+        if let Some(script) = self.script {
+            write!(f, " in {}", script)?;
+        }
+        match (self.opcode_index, self.opcode) {
+            (Some(index), Some(opcode)) => write!(f, " at opcode {} ({})", index, opcode),
+            (Some(index), None) => write!(f, " at opcode {}", index),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { Some(&self.kind) }
+}
+
+/// The script being executed when verification failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptKind {
+    /// The spending input's `scriptSig`.
+    ScriptSig,
+    /// The spent output's `scriptPubKey`.
+    ScriptPubKey,
+    /// The P2SH redeem script.
+    RedeemScript,
+    /// The P2WSH witness script, or the implied script of a P2WPKH spend.
+    WitnessScript,
+    /// The tapscript of a Taproot script path spend.
+    TapScript,
+}
+
+impl fmt::Display for ScriptKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ScriptKind::*;
+
+        match *self {
+            ScriptSig => f.write_str("scriptSig"),
+            ScriptPubKey => f.write_str("scriptPubKey"),
+            RedeemScript => f.write_str("redeem script"),
+            WitnessScript => f.write_str("witness script"),
+            TapScript => f.write_str("tapscript"),
+        }
+    }
+}
+
+/// The reason script verification failed.
+///
+/// The variants mirror Bitcoin Core's `ScriptError` (the Core name is given in the docs of each
+/// variant) so that results can be compared one to one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Script finished with an empty or false top stack element (`EVAL_FALSE`).
+    EvalFalse,
+    /// `OP_RETURN` was executed (`OP_RETURN`).
+    OpReturn,
+    /// Script is larger than [`super::MAX_SCRIPT_SIZE`] (`SCRIPT_SIZE`).
+    ScriptSize,
+    /// Push is larger than [`super::MAX_SCRIPT_ELEMENT_SIZE`] (`PUSH_SIZE`).
+    PushSize,
+    /// Script has more than [`super::MAX_OPS_PER_SCRIPT`] non-push opcodes (`OP_COUNT`).
+    OpCount,
+    /// Stack and altstack hold more than [`super::MAX_STACK_SIZE`] elements (`STACK_SIZE`).
+    StackSize,
+    /// `OP_CHECKMULTISIG` signature count is negative or larger than the key count (`SIG_COUNT`).
+    SigCount,
+    /// `OP_CHECKMULTISIG` key count is negative or too large (`PUBKEY_COUNT`).
+    PubkeyCount,
+    /// `OP_VERIFY` failed (`VERIFY`).
+    Verify,
+    /// `OP_EQUALVERIFY` failed (`EQUALVERIFY`).
+    EqualVerify,
+    /// `OP_CHECKMULTISIGVERIFY` failed (`CHECKMULTISIGVERIFY`).
+    CheckMultisigVerify,
+    /// `OP_CHECKSIGVERIFY` failed (`CHECKSIGVERIFY`).
+    CheckSigVerify,
+    /// `OP_NUMEQUALVERIFY` failed (`NUMEQUALVERIFY`).
+    NumEqualVerify,
+    /// Opcode is undefined or a push runs past the end of the script (`BAD_OPCODE`).
+    BadOpcode,
+    /// Script contains a disabled opcode (`DISABLED_OPCODE`).
+    DisabledOpcode,
+    /// Not enough elements on the stack (`INVALID_STACK_OPERATION`).
+    InvalidStackOperation,
+    /// Not enough elements on the altstack (`INVALID_ALTSTACK_OPERATION`).
+    InvalidAltstackOperation,
+    /// Unbalanced `OP_IF`/`OP_ELSE`/`OP_ENDIF` (`UNBALANCED_CONDITIONAL`).
+    UnbalancedConditional,
+    /// Lock time argument is negative (`NEGATIVE_LOCKTIME`).
+    NegativeLockTime,
+    /// Lock time requirement not satisfied by the transaction (`UNSATISFIED_LOCKTIME`).
+    UnsatisfiedLockTime,
+    /// Undefined ECDSA sighash type (`SIG_HASHTYPE`).
+    SigHashType,
+    /// ECDSA signature is not strict DER (`SIG_DER`).
+    SigDer,
+    /// Push or number is not minimally encoded (`MINIMALDATA`).
+    MinimalData,
+    /// `scriptSig` contains non-push opcodes (`SIG_PUSHONLY`).
+    SigPushOnly,
+    /// ECDSA signature has a high S value (`SIG_HIGH_S`).
+    SigHighS,
+    /// `OP_CHECKMULTISIG` dummy element is not empty (`SIG_NULLDUMMY`).
+    SigNullDummy,
+    /// Public key is neither compressed nor uncompressed (`PUBKEYTYPE`).
+    PubkeyType,
+    /// Stack does not have exactly one element after execution (`CLEANSTACK`).
+    CleanStack,
+    /// `OP_IF`/`OP_NOTIF` argument is not minimal (`MINIMALIF`).
+    MinimalIf,
+    /// Failed signature check with a non-empty signature (`SIG_NULLFAIL`).
+    SigNullFail,
+    /// Upgradable `OP_NOPx` executed (`DISCOURAGE_UPGRADABLE_NOPS`).
+    DiscourageUpgradableNops,
+    /// Unknown witness program version (`DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM`).
+    DiscourageUpgradableWitnessProgram,
+    /// Unknown Taproot leaf version (`DISCOURAGE_UPGRADABLE_TAPROOT_VERSION`).
+    DiscourageUpgradableTaprootVersion,
+    /// Tapscript contains an `OP_SUCCESSx` opcode (`DISCOURAGE_OP_SUCCESS`).
+    DiscourageOpSuccess,
+    /// Tapscript public key of unknown type (`DISCOURAGE_UPGRADABLE_PUBKEYTYPE`).
+    DiscourageUpgradablePubkeyType,
+    /// Witness program has an invalid length (`WITNESS_PROGRAM_WRONG_LENGTH`).
+    WitnessProgramWrongLength,
+    /// Witness program spent with an empty witness (`WITNESS_PROGRAM_WITNESS_EMPTY`).
+    WitnessProgramWitnessEmpty,
+    /// Witness does not match the witness program (`WITNESS_PROGRAM_MISMATCH`).
+    WitnessProgramMismatch,
+    /// Native witness program spent with a non-empty `scriptSig` (`WITNESS_MALLEATED`).
+    WitnessMalleated,
+    /// P2SH wrapped witness program spent with a `scriptSig` that is not a single push of the
+    /// redeem script (`WITNESS_MALLEATED_P2SH`).
+    WitnessMalleatedP2sh,
+    /// Witness provided for a non-witness output (`WITNESS_UNEXPECTED`).
+    WitnessUnexpected,
+    /// Uncompressed public key used in segwit v0 (`WITNESS_PUBKEYTYPE`).
+    WitnessPubkeyType,
+    /// Schnorr signature is not 64 or 65 bytes (`SCHNORR_SIG_SIZE`).
+    SchnorrSigSize,
+    /// Invalid Schnorr sighash type (`SCHNORR_SIG_HASHTYPE`).
+    SchnorrSigHashType,
+    /// Schnorr signature verification failed (`SCHNORR_SIG`).
+    SchnorrSig,
+    /// Taproot control block has an invalid size (`TAPROOT_WRONG_CONTROL_SIZE`).
+    TaprootWrongControlSize,
+    /// Tapscript exceeded its signature validation budget (`TAPSCRIPT_VALIDATION_WEIGHT`).
+    TapscriptValidationWeight,
+    /// `OP_CHECKMULTISIG(VERIFY)` used in tapscript (`TAPSCRIPT_CHECKMULTISIG`).
+    TapscriptCheckMultisig,
+    /// Tapscript `OP_IF`/`OP_NOTIF` argument is not minimal (`TAPSCRIPT_MINIMALIF`).
+    TapscriptMinimalIf,
+    /// `OP_CODESEPARATOR` in a legacy script with [`super::VerifyFlags::CONST_SCRIPTCODE`]
+    /// (`OP_CODESEPARATOR`).
+    OpCodeSeparator,
+    /// Signature found in the scriptCode with [`super::VerifyFlags::CONST_SCRIPTCODE`]
+    /// (`SIG_FINDANDDELETE`).
+    SigFindAndDelete,
+    /// Number operand is longer than allowed (Core reports `UNKNOWN_ERROR`).
+    ScriptNumOverflow,
+    /// Number operand is not minimally encoded (Core reports `UNKNOWN_ERROR`).
+    NonMinimalScriptNum,
+    /// The combination of verification flags is not supported.
+    InvalidFlags,
+    /// The spent outputs given to the signature checker don't match the transaction inputs.
+    InvalidSpentOutputs,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ErrorKind::*;
+
+        let s = match *self {
+            EvalFalse =>
+                "script evaluated without error but finished with a false/empty top stack element",
+            OpReturn => "OP_RETURN was encountered",
+            ScriptSize => "script is too big",
+            PushSize => "push value size limit exceeded",
+            OpCount => "operation limit exceeded",
+            StackSize => "stack size limit exceeded",
+            SigCount => "signature count negative or greater than pubkey count",
+            PubkeyCount => "pubkey count negative or limit exceeded",
+            Verify => "script failed an OP_VERIFY operation",
+            EqualVerify => "script failed an OP_EQUALVERIFY operation",
+            CheckMultisigVerify => "script failed an OP_CHECKMULTISIGVERIFY operation",
+            CheckSigVerify => "script failed an OP_CHECKSIGVERIFY operation",
+            NumEqualVerify => "script failed an OP_NUMEQUALVERIFY operation",
+            BadOpcode => "opcode missing or not understood",
+            DisabledOpcode => "attempted to use a disabled opcode",
+            InvalidStackOperation => "operation not valid with the current stack size",
+            InvalidAltstackOperation => "operation not valid with the current altstack size",
+            UnbalancedConditional => "invalid OP_IF construction",
+            NegativeLockTime => "negative locktime",
+            UnsatisfiedLockTime => "locktime requirement not satisfied",
+            SigHashType => "signature hash type missing or not understood",
+            SigDer => "non-canonical DER signature",
+            MinimalData => "data push larger than necessary",
+            SigPushOnly => "only push operators allowed in signatures",
+            SigHighS => "non-canonical signature: S value is unnecessarily high",
+            SigNullDummy => "dummy CHECKMULTISIG argument must be zero",
+            PubkeyType => "public key is neither compressed or uncompressed",
+            CleanStack => "stack size must be exactly one after execution",
+            MinimalIf => "OP_IF/NOTIF argument must be minimal",
+            SigNullFail => "signature must be zero for failed CHECK(MULTI)SIG operation",
+            DiscourageUpgradableNops => "NOPx reserved for soft-fork upgrades",
+            DiscourageUpgradableWitnessProgram => "witness version reserved for soft-fork upgrades",
+            DiscourageUpgradableTaprootVersion => "Taproot version reserved for soft-fork upgrades",
+            DiscourageOpSuccess => "OP_SUCCESSx reserved for soft-fork upgrades",
+            DiscourageUpgradablePubkeyType => "public key version reserved for soft-fork upgrades",
+            WitnessProgramWrongLength => "witness program has incorrect length",
+            WitnessProgramWitnessEmpty => "witness program was passed an empty witness",
+            WitnessProgramMismatch => "witness program hash mismatch",
+            WitnessMalleated => "witness requires empty scriptSig",
+            WitnessMalleatedP2sh => "witness requires only-redeemscript scriptSig",
+            WitnessUnexpected => "witness provided for non-witness script",
+            WitnessPubkeyType => "using non-compressed keys in segwit",
+            SchnorrSigSize => "invalid Schnorr signature size",
+            SchnorrSigHashType => "invalid Schnorr signature hash type",
+            SchnorrSig => "invalid Schnorr signature",
+            TaprootWrongControlSize => "invalid Taproot control block size",
+            TapscriptValidationWeight => "too much signature validation relative to witness weight",
+            TapscriptCheckMultisig => "OP_CHECKMULTISIG(VERIFY) is not available in tapscript",
+            TapscriptMinimalIf => "OP_IF/NOTIF argument must be minimal in tapscript",
+            OpCodeSeparator => "using OP_CODESEPARATOR in non-witness script",
+            SigFindAndDelete => "signature is found in scriptCode",
+            ScriptNumOverflow => "script number overflow",
+            NonMinimalScriptNum => "non-minimally encoded script number",
+            InvalidFlags => "invalid combination of verification flags",
+            InvalidSpentOutputs => "spent outputs don't match the transaction inputs",
+        };
+        f.write_str(s)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ErrorKind {}
@@ -0,0 +1,1106 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Script evaluation.
+//!
+//! This is a direct port of Bitcoin Core's `EvalScript`. The script is read byte by byte (instead
+//! of using [`crate::script::Instructions`]) because consensus depends on exactly how malformed
+//! pushes are handled.
+
+use core::mem;
+
+use hashes::{hash160, ripemd160, sha1, sha256, sha256d};
+
+use super::error::{Error, ErrorKind, ScriptKind};
+use super::{
+    ExecData, SigVersion, SignatureChecker, VerifyFlags, MAX_OPS_PER_SCRIPT,
+    MAX_PUBKEYS_PER_MULTISIG, MAX_SCRIPT_ELEMENT_SIZE, MAX_SCRIPT_SIZE, MAX_STACK_SIZE,
+    VALIDATION_WEIGHT_PER_SIGOP_PASSED,
+};
+use crate::opcodes::all::*;
+use crate::opcodes::{Class, ClassifyContext, Opcode};
+use crate::prelude::Vec;
+use crate::script::{read_scriptbool, write_scriptint, Instruction, PushBytes};
+
+/// Bit of the `nSequence` field that disables relative lock time (BIP-0068).
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: i64 = 1 << 31;
+
+/// Execution state shared by all scripts run for a single input.
+#[derive(Debug, Clone)]
+pub(super) struct ExecState<'a> {
+    /// The data signature checks commit to.
+    pub(super) data: ExecData<'a>,
+    /// Remaining tapscript signature validation budget.
+    pub(super) validation_weight_left: i64,
+}
+
+impl Default for ExecState<'_> {
+    fn default() -> Self {
+        ExecState {
+            data: ExecData { annex: None, tapleaf_hash: None, code_separator_pos: u32::MAX },
+            validation_weight_left: 0,
+        }
+    }
+}
+
+/// Reads the opcode at `*pc` and its push data, advancing `*pc` past both.
+///
+/// Returns `None` if `*pc` is at the end of the script or the push data runs past the end.
+pub(super) fn read_op<'s>(script: &'s [u8], pc: &mut usize) -> Option<(Opcode, &'s [u8])> {
+    let opcode = *script.get(*pc)?;
+    *pc += 1;
+    let len = match opcode {
+        0x00..=0x4b => usize::from(opcode),
+        0x4c => {
+            let len = *script.get(*pc)?;
+            *pc += 1;
+            usize::from(len)
+        }
+        0x4d => {
+            let bytes = script.get(*pc..*pc + 2)?;
+            *pc += 2;
+            usize::from(u16::from_le_bytes([bytes[0], bytes[1]]))
+        }
+        0x4e => {
+            let bytes = script.get(*pc..*pc + 4)?;
+            *pc += 4;
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+        }
+        _ => return Some((Opcode::from(opcode), &[])),
+    };
+    let data = script.get(*pc..pc.checked_add(len)?)?;
+    *pc += len;
+    Some((Opcode::from(opcode), data))
+}
+
+/// Returns true if `script` only contains pushes (including `OP_RESERVED`), like Core's
+/// `CScript::IsPushOnly`.
+pub(super) fn is_push_only(script: &[u8]) -> bool {
+    let mut pc = 0;
+    while pc < script.len() {
+        match read_op(script, &mut pc) {
+            Some((op, _)) if op.to_u8() <= OP_PUSHNUM_16.to_u8() => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Returns true if `script` contains an `OP_SUCCESSx` opcode, if a push before it is malformed
+/// returns [`ErrorKind::BadOpcode`].
+pub(super) fn contains_op_success(script: &[u8]) -> Result<bool, ErrorKind> {
+    let mut pc = 0;
+    while pc < script.len() {
+        let (op, _) = read_op(script, &mut pc).ok_or(ErrorKind::BadOpcode)?;
+        if op.classify(ClassifyContext::TapScript) == Class::SuccessOp {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Encodes `data` as a single push, the way Core's `CScript() << data` does.
+pub(super) fn encode_push(data: &[u8]) -> Vec<u8> {
+    let mut script = Vec::with_capacity(data.len() + 5);
+    if data.len() < usize::from(OP_PUSHDATA1.to_u8()) {
+        script.push(data.len() as u8);
+    } else if data.len() <= 0xff {
+        script.push(OP_PUSHDATA1.to_u8());
+        script.push(data.len() as u8);
+    } else if data.len() <= 0xffff {
+        script.push(OP_PUSHDATA2.to_u8());
+        script.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    } else {
+        script.push(OP_PUSHDATA4.to_u8());
+        script.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    }
+    script.extend_from_slice(data);
+    script
+}
+
+/// Removes all pushes of `sig` from `script` and returns how many were removed.
+///
+/// Only matches that start at an opcode boundary are removed (Core's `FindAndDelete`).
+fn find_and_delete(script: &mut Vec<u8>, sig: &[u8]) -> usize {
+    let pattern = encode_push(sig);
+    let mut found = 0;
+    let mut result = Vec::with_capacity(script.len());
+    let (mut pc, mut pc2) = (0, 0);
+    loop {
+        result.extend_from_slice(&script[pc2..pc]);
+        while script.len() - pc >= pattern.len() && script[pc..pc + pattern.len()] == pattern[..] {
+            pc += pattern.len();
+            found += 1;
+        }
+        pc2 = pc;
+        if read_op(script, &mut pc).is_none() {
+            break;
+        }
+    }
+    if found > 0 {
+        result.extend_from_slice(&script[pc2..]);
+        *script = result;
+    }
+    found
+}
+
+/// Returns true if the push of `data` using `opcode` is the smallest possible.
+fn is_minimal_push(data: &[u8], opcode: Opcode) -> bool {
+    let opcode = opcode.to_u8();
+    match data.len() {
+        0 => opcode == OP_PUSHBYTES_0.to_u8(),
+        1 if (1..=16).contains(&data[0]) || data[0] == 0x81 => false,
+        len @ 1..=75 => usize::from(opcode) == len,
+        76..=255 => opcode == OP_PUSHDATA1.to_u8(),
+        256..=65535 => opcode == OP_PUSHDATA2.to_u8(),
+        _ => true,
+    }
+}
+
+/// Decodes a script number of at most `max_size` bytes (Core's `CScriptNum`).
+fn read_num(bytes: &[u8], require_minimal: bool, max_size: usize) -> Result<i64, ErrorKind> {
+    if bytes.len() > max_size {
+        return Err(ErrorKind::ScriptNumOverflow);
+    }
+    match bytes.split_last() {
+        None => Ok(0),
+        Some((&last, rest)) => {
+            // The most significant byte may only be zero (apart from the sign bit) if the next
+            // byte has its high bit set, otherwise the number could be shorter.
+            if require_minimal
+                && last & 0x7f == 0
+                && rest.last().map_or(true, |&byte| byte & 0x80 == 0)
+            {
+                return Err(ErrorKind::NonMinimalScriptNum);
+            }
+            Ok(super::super::scriptint_parse(bytes))
+        }
+    }
+}
+
+/// Encodes a script number.
+fn num_to_vec(n: i64) -> Vec<u8> {
+    let mut buf = [0u8; 8];
+    let len = write_scriptint(&mut buf, n);
+    buf[..len].to_vec()
+}
+
+fn bool_to_vec(b: bool) -> Vec<u8> {
+    if b {
+        vec![1]
+    } else {
+        vec![]
+    }
+}
+
+/// Returns the `i`th element from the top of the stack, `1` being the top.
+///
+/// Callers must check that the stack holds at least `i` elements.
+fn top(stack: &[Vec<u8>], i: usize) -> &[u8] { &stack[stack.len() - i] }
+
+/// Returns true if `sig` is a strict DER encoded signature followed by a sighash byte (BIP-0066).
+fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    // Format: 0x30 [total-length] 0x02 [R-length] [R] 0x02 [S-length] [S] [sighash]
+    if sig.len() < 9 || sig.len() > 73 {
+        return false;
+    }
+    if sig[0] != 0x30 || usize::from(sig[1]) != sig.len() - 3 {
+        return false;
+    }
+    let len_r = usize::from(sig[3]);
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = usize::from(sig[5 + len_r]);
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+
+    // R must be a positive integer without unnecessary leading zeros.
+    if sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 {
+        return false;
+    }
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+
+    // S must be a positive integer without unnecessary leading zeros.
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    if len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0 {
+        return false;
+    }
+    true
+}
+
+/// Returns true if the DER signature (without sighash byte) has a low S value (BIP-0146).
+fn is_low_s(der: &[u8]) -> bool {
+    match secp256k1::ecdsa::Signature::from_der_lax(der) {
+        Ok(sig) => {
+            let mut normalized = sig;
+            normalized.normalize_s();
+            normalized == sig
+        }
+        Err(_) => false,
+    }
+}
+
+fn check_signature_encoding(sig: &[u8], flags: VerifyFlags) -> Result<(), ErrorKind> {
+    // Empty signature. Not strictly DER encoded, but allowed to provide a compact way to provide
+    // an invalid signature for use with CHECK(MULTI)SIG.
+    if sig.is_empty() {
+        return Ok(());
+    }
+    let der_flags = VerifyFlags::DERSIG | VerifyFlags::LOW_S | VerifyFlags::STRICTENC;
+    if flags.intersects(der_flags) && !is_valid_signature_encoding(sig) {
+        return Err(ErrorKind::SigDer);
+    }
+    if flags.contains(VerifyFlags::LOW_S) && !is_low_s(&sig[..sig.len() - 1]) {
+        return Err(ErrorKind::SigHighS);
+    }
+    if flags.contains(VerifyFlags::STRICTENC) {
+        let hash_type = sig[sig.len() - 1] & !0x80;
+        if !(1..=3).contains(&hash_type) {
+            return Err(ErrorKind::SigHashType);
+        }
+    }
+    Ok(())
+}
+
+fn check_pubkey_encoding(
+    pubkey: &[u8],
+    flags: VerifyFlags,
+    sig_version: SigVersion,
+) -> Result<(), ErrorKind> {
+    let compressed = pubkey.len() == 33 && (pubkey[0] == 0x02 || pubkey[0] == 0x03);
+    let uncompressed = pubkey.len() == 65 && pubkey[0] == 0x04;
+    if flags.contains(VerifyFlags::STRICTENC) && !compressed && !uncompressed {
+        return Err(ErrorKind::PubkeyType);
+    }
+    if flags.contains(VerifyFlags::WITNESS_PUBKEYTYPE)
+        && sig_version == SigVersion::WitnessV0
+        && !compressed
+    {
+        return Err(ErrorKind::WitnessPubkeyType);
+    }
+    Ok(())
+}
+
+/// Evaluates a signature check, returns whether the signature is valid.
+#[allow(clippy::too_many_arguments)]
+fn eval_checksig<C: SignatureChecker + ?Sized>(
+    sig: &[u8],
+    pubkey: &[u8],
+    script_code: &[u8],
+    flags: VerifyFlags,
+    checker: &mut C,
+    sig_version: SigVersion,
+    exec: &mut ExecState,
+) -> Result<bool, ErrorKind> {
+    match sig_version {
+        SigVersion::Base | SigVersion::WitnessV0 => {
+            // Drop the signature in pre-segwit scripts but not segwit scripts.
+            let deleted;
+            let script_code = if sig_version == SigVersion::Base {
+                let mut code = script_code.to_vec();
+                if find_and_delete(&mut code, sig) > 0
+                    && flags.contains(VerifyFlags::CONST_SCRIPTCODE)
+                {
+                    return Err(ErrorKind::SigFindAndDelete);
+                }
+                deleted = code;
+                &deleted[..]
+            } else {
+                script_code
+            };
+            check_signature_encoding(sig, flags)?;
+            check_pubkey_encoding(pubkey, flags, sig_version)?;
+            let success = checker.check_ecdsa_signature(sig, pubkey, script_code, sig_version);
+            if !success && flags.contains(VerifyFlags::NULLFAIL) && !sig.is_empty() {
+                return Err(ErrorKind::SigNullFail);
+            }
+            Ok(success)
+        }
+        SigVersion::Tapscript => {
+            // An empty signature is a valid way to fail a check, anything else must be valid.
+            let success = !sig.is_empty();
+            if success {
+                exec.validation_weight_left -= VALIDATION_WEIGHT_PER_SIGOP_PASSED;
+                if exec.validation_weight_left < 0 {
+                    return Err(ErrorKind::TapscriptValidationWeight);
+                }
+            }
+            if pubkey.is_empty() {
+                return Err(ErrorKind::PubkeyType);
+            } else if pubkey.len() == 32 {
+                if success {
+                    checker.check_schnorr_signature(sig, pubkey, sig_version, &exec.data)?;
+                }
+            } else if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_PUBKEYTYPE) {
+                // Unknown public key types are successes for forward compatibility.
+                return Err(ErrorKind::DiscourageUpgradablePubkeyType);
+            }
+            Ok(success)
+        }
+        // Key path spends never execute a script.
+        SigVersion::Taproot => unreachable!("no script is executed in a Taproot key path spend"),
+    }
+}
+
+/// Tracks the `OP_IF` nesting, Core's `ConditionStack`.
+///
+/// Only the size and the position of the first false value are stored since that is all that is
+/// needed to evaluate the stack in constant time.
+#[derive(Debug, Clone)]
+struct ConditionStack {
+    size: u32,
+    first_false_pos: u32,
+}
+
+impl ConditionStack {
+    const NO_FALSE: u32 = u32::MAX;
+
+    fn new() -> Self { ConditionStack { size: 0, first_false_pos: Self::NO_FALSE } }
+
+    fn is_empty(&self) -> bool { self.size == 0 }
+
+    fn all_true(&self) -> bool { self.first_false_pos == Self::NO_FALSE }
+
+    /// Returns the values, conditions nested in a false branch are all reported as false.
+    fn to_vec(&self) -> Vec<bool> { (0..self.size).map(|i| i < self.first_false_pos).collect() }
+
+    fn push(&mut self, value: bool) {
+        if self.first_false_pos == Self::NO_FALSE && !value {
+            self.first_false_pos = self.size;
+        }
+        self.size += 1;
+    }
+
+    // Callers check the stack is not empty.
+    fn pop(&mut self) {
+        self.size -= 1;
+        if self.first_false_pos == self.size {
+            self.first_false_pos = Self::NO_FALSE;
+        }
+    }
+
+    // Callers check the stack is not empty.
+    fn toggle_top(&mut self) {
+        if self.first_false_pos == Self::NO_FALSE {
+            self.first_false_pos = self.size - 1;
+        } else if self.first_false_pos == self.size - 1 {
+            self.first_false_pos = Self::NO_FALSE;
+        }
+        // Otherwise there is a false value below the top, toggling the top changes nothing.
+    }
+}
+
+/// Evaluates `script` on `stack`.
+///
+/// On failure the returned error records `kind`, the position of the failing opcode and a copy
+/// of the stack at that point.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn eval_script<C: SignatureChecker + ?Sized>(
+    stack: &mut Vec<Vec<u8>>,
+    script: &[u8],
+    flags: VerifyFlags,
+    checker: &mut C,
+    sig_version: SigVersion,
+    exec: &mut ExecState,
+    kind: ScriptKind,
+) -> Result<(), Error> {
+    let mut machine = Machine::new(mem::take(stack), script, flags, sig_version, kind);
+    while !machine.is_done() {
+        machine.step(checker, exec)?;
+    }
+    machine.finish()?;
+    *stack = machine.stack;
+    Ok(())
+}
+
+/// A script being evaluated one opcode at a time, the state of Core's `EvalScript` loop.
+#[derive(Debug, Clone)]
+pub(super) struct Machine<'s> {
+    script: &'s [u8],
+    flags: VerifyFlags,
+    sig_version: SigVersion,
+    kind: ScriptKind,
+    pub(super) stack: Vec<Vec<u8>>,
+    pub(super) altstack: Vec<Vec<u8>>,
+    conditions: ConditionStack,
+    op_count: usize,
+    /// Start of the scriptCode, the script after the last executed `OP_CODESEPARATOR`.
+    code_start: usize,
+    /// Byte position of the next opcode.
+    pub(super) pc: usize,
+    /// Index of the next opcode.
+    pub(super) opcode_pos: usize,
+}
+
+impl<'s> Machine<'s> {
+    pub(super) fn new(
+        stack: Vec<Vec<u8>>,
+        script: &'s [u8],
+        flags: VerifyFlags,
+        sig_version: SigVersion,
+        kind: ScriptKind,
+    ) -> Self {
+        Machine {
+            script,
+            flags,
+            sig_version,
+            kind,
+            stack,
+            altstack: Vec::new(),
+            conditions: ConditionStack::new(),
+            op_count: 0,
+            code_start: 0,
+            pc: 0,
+            opcode_pos: 0,
+        }
+    }
+
+    /// Returns true if all opcodes have been executed.
+    pub(super) fn is_done(&self) -> bool { self.pc >= self.script.len() }
+
+    /// Returns the `OP_IF` nesting, innermost last.
+    pub(super) fn conditions(&self) -> Vec<bool> { self.conditions.to_vec() }
+
+    /// Executes the next opcode.
+    ///
+    /// Returns the opcode's instruction and whether it was in an executed branch.
+    pub(super) fn step<C: SignatureChecker + ?Sized>(
+        &mut self,
+        checker: &mut C,
+        exec: &mut ExecState,
+    ) -> Result<(Instruction<'s>, bool), Error> {
+        let (script, flags, sig_version, kind) =
+            (self.script, self.flags, self.sig_version, self.kind);
+        let stack = &mut self.stack;
+        let altstack = &mut self.altstack;
+        let conditions = &mut self.conditions;
+        let (mut pc, opcode_pos) = (self.pc, self.opcode_pos);
+        let (mut op_count, mut code_start) = (self.op_count, self.code_start);
+
+        let pre_tapscript = matches!(sig_version, SigVersion::Base | SigVersion::WitnessV0);
+        if pc == 0 {
+            if pre_tapscript && script.len() > MAX_SCRIPT_SIZE {
+                return Err(Error::new(ErrorKind::ScriptSize, Some(kind), stack));
+            }
+            exec.data.code_separator_pos = u32::MAX;
+        }
+
+        let require_minimal = flags.contains(VerifyFlags::MINIMALDATA);
+        let executing = conditions.all_true();
+
+        let (op, push) = match read_op(script, &mut pc) {
+            Some(op) => op,
+            None => {
+                let err = Error::new(ErrorKind::BadOpcode, Some(kind), stack);
+                return Err(err.at_opcode(opcode_pos, None));
+            }
+        };
+
+        macro_rules! fail {
+            ($kind:expr) => {
+                return Err(Error::new($kind, Some(kind), stack).at_opcode(opcode_pos, Some(op)))
+            };
+        }
+        macro_rules! check {
+            ($res:expr) => {
+                match $res {
+                    Ok(v) => v,
+                    Err(e) => fail!(e),
+                }
+            };
+        }
+        macro_rules! require {
+            ($n:expr) => {
+                if stack.len() < $n {
+                    fail!(ErrorKind::InvalidStackOperation)
+                }
+            };
+        }
+
+        if push.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            fail!(ErrorKind::PushSize);
+        }
+        if pre_tapscript && op.to_u8() > OP_PUSHNUM_16.to_u8() {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                fail!(ErrorKind::OpCount);
+            }
+        }
+        // Disabled opcodes fail even in an unexecuted branch (CVE-2010-5137).
+        if matches!(
+            op,
+            OP_CAT
+                | OP_SUBSTR
+                | OP_LEFT
+                | OP_RIGHT
+                | OP_INVERT
+                | OP_AND
+                | OP_OR
+                | OP_XOR
+                | OP_2MUL
+                | OP_2DIV
+                | OP_MUL
+                | OP_DIV
+                | OP_MOD
+                | OP_LSHIFT
+                | OP_RSHIFT
+        ) {
+            fail!(ErrorKind::DisabledOpcode);
+        }
+        if op == OP_CODESEPARATOR
+            && sig_version == SigVersion::Base
+            && flags.contains(VerifyFlags::CONST_SCRIPTCODE)
+        {
+            fail!(ErrorKind::OpCodeSeparator);
+        }
+
+        if executing && op.to_u8() <= OP_PUSHDATA4.to_u8() {
+            if require_minimal && !is_minimal_push(push, op) {
+                fail!(ErrorKind::MinimalData);
+            }
+            stack.push(push.to_vec());
+        } else if executing || (OP_IF.to_u8() <= op.to_u8() && op.to_u8() <= OP_ENDIF.to_u8()) {
+            match op {
+                OP_PUSHNUM_NEG1 | OP_PUSHNUM_1 | OP_PUSHNUM_2 | OP_PUSHNUM_3 | OP_PUSHNUM_4
+                | OP_PUSHNUM_5 | OP_PUSHNUM_6 | OP_PUSHNUM_7 | OP_PUSHNUM_8 | OP_PUSHNUM_9
+                | OP_PUSHNUM_10 | OP_PUSHNUM_11 | OP_PUSHNUM_12 | OP_PUSHNUM_13 | OP_PUSHNUM_14
+                | OP_PUSHNUM_15 | OP_PUSHNUM_16 => {
+                    let n = i64::from(op.to_u8()) - i64::from(OP_PUSHNUM_1.to_u8()) + 1;
+                    stack.push(num_to_vec(n));
+                }
+
+                OP_NOP => {}
+                OP_CLTV =>
+                    if flags.contains(VerifyFlags::CHECKLOCKTIMEVERIFY) {
+                        require!(1);
+                        // Lock times are up to 5 bytes long to avoid the year 2038 problem.
+                        let lock_time = check!(read_num(top(stack, 1), require_minimal, 5));
+                        if lock_time < 0 {
+                            fail!(ErrorKind::NegativeLockTime);
+                        }
+                        if !checker.check_lock_time(lock_time) {
+                            fail!(ErrorKind::UnsatisfiedLockTime);
+                        }
+                    },
+                OP_CSV =>
+                    if flags.contains(VerifyFlags::CHECKSEQUENCEVERIFY) {
+                        require!(1);
+                        let sequence = check!(read_num(top(stack, 1), require_minimal, 5));
+                        if sequence < 0 {
+                            fail!(ErrorKind::NegativeLockTime);
+                        }
+                        // With the disable flag set the opcode behaves as a NOP.
+                        if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+                            && !checker.check_sequence(sequence)
+                        {
+                            fail!(ErrorKind::UnsatisfiedLockTime);
+                        }
+                    },
+                OP_NOP1 | OP_NOP4 | OP_NOP5 | OP_NOP6 | OP_NOP7 | OP_NOP8 | OP_NOP9 | OP_NOP10 =>
+                    if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS) {
+                        fail!(ErrorKind::DiscourageUpgradableNops);
+                    },
+
+                OP_IF | OP_NOTIF => {
+                    let mut value = false;
+                    if executing {
+                        if stack.is_empty() {
+                            fail!(ErrorKind::UnbalancedConditional);
+                        }
+                        let arg = top(stack, 1);
+                        let minimal = arg.is_empty() || arg == [1];
+                        if sig_version == SigVersion::Tapscript && !minimal {
+                            fail!(ErrorKind::TapscriptMinimalIf);
+                        }
+                        if sig_version == SigVersion::WitnessV0
+                            && flags.contains(VerifyFlags::MINIMALIF)
+                            && !minimal
+                        {
+                            fail!(ErrorKind::MinimalIf);
+                        }
+                        value = read_scriptbool(arg);
+                        if op == OP_NOTIF {
+                            value = !value;
+                        }
+                        stack.pop();
+                    }
+                    conditions.push(value);
+                }
+                OP_ELSE => {
+                    if conditions.is_empty() {
+                        fail!(ErrorKind::UnbalancedConditional);
+                    }
+                    conditions.toggle_top();
+                }
+                OP_ENDIF => {
+                    if conditions.is_empty() {
+                        fail!(ErrorKind::UnbalancedConditional);
+                    }
+                    conditions.pop();
+                }
+                OP_VERIFY => {
+                    require!(1);
+                    if !read_scriptbool(top(stack, 1)) {
+                        fail!(ErrorKind::Verify);
+                    }
+                    stack.pop();
+                }
+                OP_RETURN => fail!(ErrorKind::OpReturn),
+
+                OP_TOALTSTACK => {
+                    require!(1);
+                    altstack.extend(stack.pop());
+                }
+                OP_FROMALTSTACK => match altstack.pop() {
+                    Some(elem) => stack.push(elem),
+                    None => fail!(ErrorKind::InvalidAltstackOperation),
+                },
+                OP_2DROP => {
+                    require!(2);
+                    stack.truncate(stack.len() - 2);
+                }
+                OP_2DUP => {
+                    require!(2);
+                    let len = stack.len();
+                    stack.extend_from_within(len - 2..);
+                }
+                OP_3DUP => {
+                    require!(3);
+                    let len = stack.len();
+                    stack.extend_from_within(len - 3..);
+                }
+                OP_2OVER => {
+                    require!(4);
+                    let len = stack.len();
+                    stack.extend_from_within(len - 4..len - 2);
+                }
+                OP_2ROT => {
+                    require!(6);
+                    let len = stack.len();
+                    stack[len - 6..].rotate_left(2);
+                }
+                OP_2SWAP => {
+                    require!(4);
+                    let len = stack.len();
+                    stack[len - 4..].rotate_left(2);
+                }
+                OP_IFDUP => {
+                    require!(1);
+                    if read_scriptbool(top(stack, 1)) {
+                        let elem = top(stack, 1).to_vec();
+                        stack.push(elem);
+                    }
+                }
+                OP_DEPTH => stack.push(num_to_vec(stack.len() as i64)),
+                OP_DROP => {
+                    require!(1);
+                    stack.pop();
+                }
+                OP_DUP => {
+                    require!(1);
+                    let elem = top(stack, 1).to_vec();
+                    stack.push(elem);
+                }
+                OP_NIP => {
+                    require!(2);
+                    let len = stack.len();
+                    stack.remove(len - 2);
+                }
+                OP_OVER => {
+                    require!(2);
+                    let elem = top(stack, 2).to_vec();
+                    stack.push(elem);
+                }
+                OP_PICK | OP_ROLL => {
+                    require!(2);
+                    let n = check!(read_num(top(stack, 1), require_minimal, 4));
+                    stack.pop();
+                    if n < 0 || n >= stack.len() as i64 {
+                        fail!(ErrorKind::InvalidStackOperation);
+                    }
+                    let index = stack.len() - 1 - n as usize;
+                    let elem =
+                        if op == OP_ROLL { stack.remove(index) } else { stack[index].clone() };
+                    stack.push(elem);
+                }
+                OP_ROT => {
+                    require!(3);
+                    let len = stack.len();
+                    stack[len - 3..].rotate_left(1);
+                }
+                OP_SWAP => {
+                    require!(2);
+                    let len = stack.len();
+                    stack.swap(len - 2, len - 1);
+                }
+                OP_TUCK => {
+                    require!(2);
+                    let len = stack.len();
+                    let elem = top(stack, 1).to_vec();
+                    stack.insert(len - 2, elem);
+                }
+                OP_SIZE => {
+                    require!(1);
+                    let size = top(stack, 1).len() as i64;
+                    stack.push(num_to_vec(size));
+                }
+
+                OP_EQUAL | OP_EQUALVERIFY => {
+                    require!(2);
+                    let equal = top(stack, 2) == top(stack, 1);
+                    stack.truncate(stack.len() - 2);
+                    if op == OP_EQUALVERIFY {
+                        if !equal {
+                            stack.push(bool_to_vec(equal));
+                            fail!(ErrorKind::EqualVerify);
+                        }
+                    } else {
+                        stack.push(bool_to_vec(equal));
+                    }
+                }
+
+                OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                    require!(1);
+                    let n = check!(read_num(top(stack, 1), require_minimal, 4));
+                    let n = match op {
+                        OP_1ADD => n + 1,
+                        OP_1SUB => n - 1,
+                        OP_NEGATE => -n,
+                        OP_ABS => n.abs(),
+                        OP_NOT => i64::from(n == 0),
+                        _ => i64::from(n != 0),
+                    };
+                    stack.pop();
+                    stack.push(num_to_vec(n));
+                }
+                OP_ADD
+                | OP_SUB
+                | OP_BOOLAND
+                | OP_BOOLOR
+                | OP_NUMEQUAL
+                | OP_NUMEQUALVERIFY
+                | OP_NUMNOTEQUAL
+                | OP_LESSTHAN
+                | OP_GREATERTHAN
+                | OP_LESSTHANOREQUAL
+                | OP_GREATERTHANOREQUAL
+                | OP_MIN
+                | OP_MAX => {
+                    require!(2);
+                    let a = check!(read_num(top(stack, 2), require_minimal, 4));
+                    let b = check!(read_num(top(stack, 1), require_minimal, 4));
+                    let n = match op {
+                        OP_ADD => a + b,
+                        OP_SUB => a - b,
+                        OP_BOOLAND => i64::from(a != 0 && b != 0),
+                        OP_BOOLOR => i64::from(a != 0 || b != 0),
+                        OP_NUMEQUAL | OP_NUMEQUALVERIFY => i64::from(a == b),
+                        OP_NUMNOTEQUAL => i64::from(a != b),
+                        OP_LESSTHAN => i64::from(a < b),
+                        OP_GREATERTHAN => i64::from(a > b),
+                        OP_LESSTHANOREQUAL => i64::from(a <= b),
+                        OP_GREATERTHANOREQUAL => i64::from(a >= b),
+                        OP_MIN => a.min(b),
+                        _ => a.max(b),
+                    };
+                    stack.truncate(stack.len() - 2);
+                    stack.push(num_to_vec(n));
+                    if op == OP_NUMEQUALVERIFY {
+                        if n == 0 {
+                            fail!(ErrorKind::NumEqualVerify);
+                        }
+                        stack.pop();
+                    }
+                }
+                OP_WITHIN => {
+                    require!(3);
+                    let x = check!(read_num(top(stack, 3), require_minimal, 4));
+                    let min = check!(read_num(top(stack, 2), require_minimal, 4));
+                    let max = check!(read_num(top(stack, 1), require_minimal, 4));
+                    stack.truncate(stack.len() - 3);
+                    stack.push(bool_to_vec(min <= x && x < max));
+                }
+
+                OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                    require!(1);
+                    let data = top(stack, 1);
+                    let hash = match op {
+                        OP_RIPEMD160 => ripemd160::Hash::hash(data).to_byte_array().to_vec(),
+                        OP_SHA1 => sha1::Hash::hash(data).to_byte_array().to_vec(),
+                        OP_SHA256 => sha256::Hash::hash(data).to_byte_array().to_vec(),
+                        OP_HASH160 => hash160::Hash::hash(data).to_byte_array().to_vec(),
+                        _ => sha256d::Hash::hash(data).to_byte_array().to_vec(),
+                    };
+                    stack.pop();
+                    stack.push(hash);
+                }
+                OP_CODESEPARATOR => {
+                    // Signatures only commit to the script after the last executed separator.
+                    code_start = pc;
+                    exec.data.code_separator_pos = opcode_pos as u32;
+                }
+                OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                    require!(2);
+                    let success = check!(eval_checksig(
+                        top(stack, 2),
+                        top(stack, 1),
+                        &script[code_start..],
+                        flags,
+                        checker,
+                        sig_version,
+                        exec,
+                    ));
+                    stack.truncate(stack.len() - 2);
+                    stack.push(bool_to_vec(success));
+                    if op == OP_CHECKSIGVERIFY {
+                        if !success {
+                            fail!(ErrorKind::CheckSigVerify);
+                        }
+                        stack.pop();
+                    }
+                }
+                OP_CHECKSIGADD => {
+                    if pre_tapscript {
+                        fail!(ErrorKind::BadOpcode);
+                    }
+                    require!(3);
+                    let n = check!(read_num(top(stack, 2), require_minimal, 4));
+                    let success = check!(eval_checksig(
+                        top(stack, 3),
+                        top(stack, 1),
+                        &script[code_start..],
+                        flags,
+                        checker,
+                        sig_version,
+                        exec,
+                    ));
+                    stack.truncate(stack.len() - 3);
+                    stack.push(num_to_vec(n + i64::from(success)));
+                }
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    if sig_version == SigVersion::Tapscript {
+                        fail!(ErrorKind::TapscriptCheckMultisig);
+                    }
+
+                    // Positions are counted from the top of the stack, the top being 1.
+                    let mut i = 1;
+                    require!(i);
+                    let mut keys_count = check!(read_num(top(stack, i), require_minimal, 4));
+                    if keys_count < 0 || keys_count > MAX_PUBKEYS_PER_MULTISIG as i64 {
+                        fail!(ErrorKind::PubkeyCount);
+                    }
+                    op_count += keys_count as usize;
+                    if op_count > MAX_OPS_PER_SCRIPT {
+                        fail!(ErrorKind::OpCount);
+                    }
+                    i += 1;
+                    let mut key_pos = i;
+                    // Position of the last non-signature item, used by the NULLFAIL cleanup.
+                    let mut key_pos2 = keys_count as usize + 2;
+                    i += keys_count as usize;
+                    require!(i);
+
+                    let mut sigs_count = check!(read_num(top(stack, i), require_minimal, 4));
+                    if sigs_count < 0 || sigs_count > keys_count {
+                        fail!(ErrorKind::SigCount);
+                    }
+                    i += 1;
+                    let mut sig_pos = i;
+                    i += sigs_count as usize;
+                    // Also covers the dummy element consumed due to an off-by-one bug.
+                    require!(i);
+
+                    let mut script_code = script[code_start..].to_vec();
+                    if sig_version == SigVersion::Base {
+                        for k in 0..sigs_count as usize {
+                            let sig = top(stack, sig_pos + k);
+                            if find_and_delete(&mut script_code, sig) > 0
+                                && flags.contains(VerifyFlags::CONST_SCRIPTCODE)
+                            {
+                                fail!(ErrorKind::SigFindAndDelete);
+                            }
+                        }
+                    }
+
+                    let mut success = true;
+                    while success && sigs_count > 0 {
+                        let sig = top(stack, sig_pos);
+                        let pubkey = top(stack, key_pos);
+                        // The order of the encoding checks is observable with CHECKMULTISIG NOT
+                        // when STRICTENC is set.
+                        check!(check_signature_encoding(sig, flags));
+                        check!(check_pubkey_encoding(pubkey, flags, sig_version));
+                        if checker.check_ecdsa_signature(sig, pubkey, &script_code, sig_version) {
+                            sig_pos += 1;
+                            sigs_count -= 1;
+                        }
+                        key_pos += 1;
+                        keys_count -= 1;
+                        // More signatures left than keys means too many signatures have failed.
+                        if sigs_count > keys_count {
+                            success = false;
+                        }
+                    }
+
+                    while i > 1 {
+                        i -= 1;
+                        // A failed operation requires all signatures to be empty.
+                        if !success
+                            && flags.contains(VerifyFlags::NULLFAIL)
+                            && key_pos2 == 0
+                            && !top(stack, 1).is_empty()
+                        {
+                            fail!(ErrorKind::SigNullFail);
+                        }
+                        key_pos2 = key_pos2.saturating_sub(1);
+                        stack.pop();
+                    }
+                    // The extra element consumed by the off-by-one bug must be empty with NULLDUMMY.
+                    require!(1);
+                    if flags.contains(VerifyFlags::NULLDUMMY) && !top(stack, 1).is_empty() {
+                        fail!(ErrorKind::SigNullDummy);
+                    }
+                    stack.pop();
+                    stack.push(bool_to_vec(success));
+                    if op == OP_CHECKMULTISIGVERIFY {
+                        if !success {
+                            fail!(ErrorKind::CheckMultisigVerify);
+                        }
+                        stack.pop();
+                    }
+                }
+
+                _ => fail!(ErrorKind::BadOpcode),
+            }
+        }
+
+        if stack.len() + altstack.len() > MAX_STACK_SIZE {
+            fail!(ErrorKind::StackSize);
+        }
+
+        self.pc = pc;
+        self.opcode_pos += 1;
+        self.op_count = op_count;
+        self.code_start = code_start;
+        Ok((instruction(op, push), executing))
+    }
+
+    /// Checks that all conditionals were closed once the script is done.
+    pub(super) fn finish(&self) -> Result<(), Error> {
+        if !self.conditions.is_empty() {
+            return Err(Error::new(ErrorKind::UnbalancedConditional, Some(self.kind), &self.stack));
+        }
+        Ok(())
+    }
+}
+
+/// Converts an opcode and its push data into an [`Instruction`].
+fn instruction<'s>(op: Opcode, push: &'s [u8]) -> Instruction<'s> {
+    if op.to_u8() <= OP_PUSHDATA4.to_u8() {
+        let push = <&PushBytes>::try_from(push).expect("pushes are at most 4 GiB");
+        Instruction::PushBytes(push)
+    } else {
+        Instruction::Op(op)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_op_truncated_pushes() {
+        let mut pc = 0;
+        assert_eq!(
+            read_op(&[0x02, 0xaa, 0xbb], &mut pc),
+            Some((OP_PUSHBYTES_2, &[0xaa, 0xbb][..]))
+        );
+        assert_eq!(pc, 3);
+        assert_eq!(read_op(&[0x02, 0xaa], &mut 0), None);
+        assert_eq!(read_op(&[0x4c], &mut 0), None);
+        assert_eq!(read_op(&[0x4d, 0x01], &mut 0), None);
+        assert_eq!(read_op(&[0x4e, 0x01, 0x00, 0x00, 0x00], &mut 0), None);
+        assert_eq!(read_op(&[0x4d, 0x00, 0x00], &mut 0), Some((OP_PUSHDATA2, &[][..])));
+    }
+
+    #[test]
+    fn find_and_delete_vectors() {
+        // Vectors from Core's `script_FindAndDelete` unit test.
+        fn check(script: &[u8], sig: &[u8], expected: &[u8], count: usize) {
+            let mut script = script.to_vec();
+            assert_eq!(find_and_delete(&mut script, sig), count);
+            assert_eq!(script, expected);
+        }
+        check(&[0x02, 0x03, 0x02, 0x03], &[0x03], &[0x02, 0x03, 0x02, 0x03], 0);
+        check(&[0x01, 0x02, 0x01, 0x02], &[0x02], &[], 2);
+        check(&[0x01, 0x02, 0x01, 0x02, 0x01], &[0x02], &[0x01], 2);
+        // Matches must start at an opcode boundary.
+        check(&[0x02, 0x01, 0x02, 0x01, 0x02], &[0x02], &[0x02, 0x01, 0x02], 1);
+        check(&[0x00, 0x01, 0x02, 0x01], &[0x02], &[0x00, 0x01], 1);
+        // Pushes that contain the pattern are kept.
+        check(&[0x03, 0x02, 0x01, 0x02, 0x01, 0x02], &[0x02], &[0x03, 0x02, 0x01, 0x02], 1);
+    }
+
+    #[test]
+    fn minimal_push() {
+        assert!(is_minimal_push(&[], OP_PUSHBYTES_0));
+        assert!(!is_minimal_push(&[], OP_PUSHDATA1));
+        assert!(!is_minimal_push(&[0x05], OP_PUSHBYTES_1));
+        assert!(!is_minimal_push(&[0x81], OP_PUSHBYTES_1));
+        assert!(is_minimal_push(&[0x11], OP_PUSHBYTES_1));
+        assert!(!is_minimal_push(&[0x11; 75], OP_PUSHDATA1));
+        assert!(is_minimal_push(&[0x11; 76], OP_PUSHDATA1));
+        assert!(!is_minimal_push(&[0x11; 256], OP_PUSHDATA1));
+        assert!(is_minimal_push(&[0x11; 256], OP_PUSHDATA2));
+    }
+
+    #[test]
+    fn script_num() {
+        assert_eq!(read_num(&[], true, 4), Ok(0));
+        assert_eq!(read_num(&[0x81], true, 4), Ok(-1));
+        assert_eq!(read_num(&[0xff, 0x00], true, 4), Ok(255));
+        assert_eq!(read_num(&[0x00], true, 4), Err(ErrorKind::NonMinimalScriptNum));
+        assert_eq!(read_num(&[0x80], true, 4), Err(ErrorKind::NonMinimalScriptNum));
+        assert_eq!(read_num(&[0x01, 0x00], true, 4), Err(ErrorKind::NonMinimalScriptNum));
+        assert_eq!(read_num(&[0x01, 0x00], false, 4), Ok(1));
+        assert_eq!(read_num(&[0x01; 5], false, 4), Err(ErrorKind::ScriptNumOverflow));
+        assert_eq!(read_num(&[0xff, 0xff, 0xff, 0xff, 0x00], true, 5), Ok(0xffff_ffff));
+    }
+
+    #[test]
+    fn strict_der() {
+        let mut sig = vec![0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01];
+        assert!(is_valid_signature_encoding(&sig));
+        // Negative R.
+        sig[4] = 0x81;
+        assert!(!is_valid_signature_encoding(&sig));
+        sig[4] = 0x01;
+        // Wrong total length.
+        sig[1] = 0x07;
+        assert!(!is_valid_signature_encoding(&sig));
+    }
+
+    #[test]
+    fn condition_stack() {
+        let mut conditions = ConditionStack::new();
+        assert!(conditions.is_empty() && conditions.all_true());
+        conditions.push(true);
+        conditions.push(false);
+        conditions.push(true);
+        assert!(!conditions.all_true());
+        assert_eq!(conditions.to_vec(), [true, false, false]);
+        conditions.toggle_top();
+        assert!(!conditions.all_true());
+        conditions.pop();
+        conditions.toggle_top();
+        assert!(conditions.all_true());
+        conditions.pop();
+        conditions.pop();
+        assert!(conditions.is_empty());
+    }
+}
@@ -0,0 +1,959 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Bitcoin script interpreter.
+//!
+//! A native implementation of Bitcoin Core's script verification (`VerifyScript`) covering legacy,
+//! P2SH, segwit v0 and Taproot (BIP-0341, BIP-0342) spends. Which rules are enforced is controlled
+//! with [`VerifyFlags`], the flag values match Core's `SCRIPT_VERIFY_*` constants.
+//!
+//! Everything that depends on the spending transaction (signatures and lock times) is delegated to
+//! a [`SignatureChecker`], [`TransactionSignatureChecker`] implements it using a
+//! [`SighashCache`](crate::sighash::SighashCache).
+//!
+//! Failures are reported with an [`Error`] giving the reason, the script and opcode that failed
+//! and the stack at the time of the failure. To see how a script got there, [`Execution`] runs it
+//! one opcode at a time.
+
+mod checker;
+mod error;
+mod eval;
+mod trace;
+
+use core::ops::{BitOr, BitOrAssign};
+
+use hashes::{sha256, sha256t};
+use secp256k1::{Parity, Secp256k1, Verification};
+
+use self::eval::{contains_op_success, encode_push, eval_script, is_push_only, ExecState};
+use crate::consensus::Encodable as _;
+use crate::crypto::sighash::SighashCache;
+use crate::key::XOnlyPublicKey;
+use crate::opcodes::all::*;
+use crate::prelude::Vec;
+use crate::script::ScriptPubKeyExt as _;
+use crate::taproot::{
+    TapLeafHash, TapLeafTag, TapNodeHash, TapTweakHash, TAPROOT_ANNEX_PREFIX,
+    TAPROOT_CONTROL_BASE_SIZE, TAPROOT_CONTROL_MAX_SIZE, TAPROOT_CONTROL_NODE_SIZE,
+    TAPROOT_LEAF_MASK, TAPROOT_LEAF_TAPSCRIPT,
+};
+use crate::{ScriptPubKey, ScriptSig, TapScript, Transaction, TxOut, Witness};
+
+#[rustfmt::skip]                // Keep public re-exports separate.
+#[doc(inline)]
+pub use self::{
+    checker::{NullSignatureChecker, SignatureChecker, TransactionSignatureChecker},
+    error::{Error, ErrorKind, ScriptKind},
+    trace::{ExecutableTag, Execution, Step},
+};
+
+/// Maximum size of a legacy or segwit v0 script in bytes.
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+
+/// Maximum size of a stack element in bytes.
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+
+/// Maximum number of non-push opcodes in a legacy or segwit v0 script.
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+
+/// Maximum number of public keys in an `OP_CHECKMULTISIG`.
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+
+/// Maximum number of elements on the stack and altstack combined.
+pub const MAX_STACK_SIZE: usize = 1000;
+
+/// Validation weight consumed by each successful tapscript signature check (BIP-0342).
+pub const VALIDATION_WEIGHT_PER_SIGOP_PASSED: i64 = 50;
+
+/// Validation weight budget of a tapscript on top of the witness size (BIP-0342).
+pub const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+
+/// Script verification flags, Bitcoin Core's `SCRIPT_VERIFY_*` constants.
+///
+/// Flags are combined with `|`, for example `VerifyFlags::P2SH | VerifyFlags::WITNESS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct VerifyFlags(u32);
+
+impl VerifyFlags {
+    /// No flags, only the rules of the original script system are enforced.
+    pub const NONE: Self = VerifyFlags(0);
+    /// Evaluate P2SH subscripts (BIP-0016).
+    pub const P2SH: Self = VerifyFlags(1 << 0);
+    /// Require strict encoding of signatures and public keys.
+    pub const STRICTENC: Self = VerifyFlags(1 << 1);
+    /// Require strict DER encoding of signatures (BIP-0066).
+    pub const DERSIG: Self = VerifyFlags(1 << 2);
+    /// Require low S values in signatures (BIP-0146).
+    pub const LOW_S: Self = VerifyFlags(1 << 3);
+    /// Require the `OP_CHECKMULTISIG` dummy element to be empty (BIP-0147).
+    pub const NULLDUMMY: Self = VerifyFlags(1 << 4);
+    /// Require the `scriptSig` to only contain pushes.
+    pub const SIGPUSHONLY: Self = VerifyFlags(1 << 5);
+    /// Require minimal pushes and number encodings.
+    pub const MINIMALDATA: Self = VerifyFlags(1 << 6);
+    /// Fail on upgradable `OP_NOPx` opcodes.
+    pub const DISCOURAGE_UPGRADABLE_NOPS: Self = VerifyFlags(1 << 7);
+    /// Require exactly one stack element after execution.
+    pub const CLEANSTACK: Self = VerifyFlags(1 << 8);
+    /// Enable `OP_CHECKLOCKTIMEVERIFY` (BIP-0065).
+    pub const CHECKLOCKTIMEVERIFY: Self = VerifyFlags(1 << 9);
+    /// Enable `OP_CHECKSEQUENCEVERIFY` (BIP-0112).
+    pub const CHECKSEQUENCEVERIFY: Self = VerifyFlags(1 << 10);
+    /// Evaluate witness programs (BIP-0141).
+    pub const WITNESS: Self = VerifyFlags(1 << 11);
+    /// Fail on witness programs of unknown versions.
+    pub const DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM: Self = VerifyFlags(1 << 12);
+    /// Require the `OP_IF`/`OP_NOTIF` argument to be empty or `0x01` in segwit v0.
+    pub const MINIMALIF: Self = VerifyFlags(1 << 13);
+    /// Require failed signature checks to use an empty signature.
+    pub const NULLFAIL: Self = VerifyFlags(1 << 14);
+    /// Require compressed public keys in segwit v0.
+    pub const WITNESS_PUBKEYTYPE: Self = VerifyFlags(1 << 15);
+    /// Fail on `OP_CODESEPARATOR` and signatures in the scriptCode of legacy scripts.
+    pub const CONST_SCRIPTCODE: Self = VerifyFlags(1 << 16);
+    /// Evaluate Taproot outputs (BIP-0341, BIP-0342).
+    pub const TAPROOT: Self = VerifyFlags(1 << 17);
+    /// Fail on Taproot leaf versions other than tapscript.
+    pub const DISCOURAGE_UPGRADABLE_TAPROOT_VERSION: Self = VerifyFlags(1 << 18);
+    /// Fail on tapscripts containing `OP_SUCCESSx` opcodes.
+    pub const DISCOURAGE_OP_SUCCESS: Self = VerifyFlags(1 << 19);
+    /// Fail on tapscript public keys of unknown types.
+    pub const DISCOURAGE_UPGRADABLE_PUBKEYTYPE: Self = VerifyFlags(1 << 20);
+
+    /// The flags enforced by consensus since Taproot activation.
+    pub const CONSENSUS: Self = VerifyFlags(
+        Self::P2SH.0
+            | Self::DERSIG.0
+            | Self::NULLDUMMY.0
+            | Self::CHECKLOCKTIMEVERIFY.0
+            | Self::CHECKSEQUENCEVERIFY.0
+            | Self::WITNESS.0
+            | Self::TAPROOT.0,
+    );
+
+    /// The flags enforced by Bitcoin Core's mempool policy (`STANDARD_SCRIPT_VERIFY_FLAGS`).
+    pub const STANDARD: Self = VerifyFlags(
+        Self::CONSENSUS.0
+            | Self::STRICTENC.0
+            | Self::MINIMALDATA.0
+            | Self::DISCOURAGE_UPGRADABLE_NOPS.0
+            | Self::CLEANSTACK.0
+            | Self::MINIMALIF.0
+            | Self::NULLFAIL.0
+            | Self::LOW_S.0
+            | Self::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM.0
+            | Self::WITNESS_PUBKEYTYPE.0
+            | Self::CONST_SCRIPTCODE.0
+            | Self::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION.0
+            | Self::DISCOURAGE_OP_SUCCESS.0
+            | Self::DISCOURAGE_UPGRADABLE_PUBKEYTYPE.0,
+    );
+
+    /// All flags known to this library.
+    const ALL: Self = VerifyFlags((1 << 21) - 1);
+
+    /// Constructs flags from Core's bit representation, unknown bits are kept.
+    pub const fn from_u32(bits: u32) -> Self { VerifyFlags(bits) }
+
+    /// Returns Core's bit representation of the flags.
+    pub const fn to_u32(self) -> u32 { self.0 }
+
+    /// Returns true if all flags in `other` are set.
+    pub const fn contains(self, other: Self) -> bool { self.0 & other.0 == other.0 }
+
+    /// Returns true if any flag in `other` is set.
+    pub const fn intersects(self, other: Self) -> bool { self.0 & other.0 != 0 }
+
+    /// Checks that the flags are known and that their combination is supported.
+    ///
+    /// `CLEANSTACK` requires `P2SH` and `WITNESS`, `WITNESS` requires `P2SH`.
+    fn validate(self) -> Result<(), Error> {
+        let valid = self.0 & !Self::ALL.0 == 0
+            && (!self.contains(Self::WITNESS) || self.contains(Self::P2SH))
+            && (!self.contains(Self::CLEANSTACK) || self.contains(Self::P2SH | Self::WITNESS));
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::InvalidFlags, None, &[]))
+        }
+    }
+}
+
+impl BitOr for VerifyFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self { VerifyFlags(self.0 | rhs.0) }
+}
+
+impl BitOrAssign for VerifyFlags {
+    fn bitor_assign(&mut self, rhs: Self) { self.0 |= rhs.0 }
+}
+
+/// The signature scheme a script is executed under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SigVersion {
+    /// Legacy and P2SH scripts.
+    Base,
+    /// Segwit v0 scripts (BIP-0143).
+    WitnessV0,
+    /// Taproot key path spends (BIP-0341).
+    Taproot,
+    /// Taproot script path spends with leaf version `0xc0` (BIP-0342).
+    Tapscript,
+}
+
+/// Data of a Taproot spend that signatures commit to, passed to the [`SignatureChecker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ExecData<'a> {
+    /// The annex (including the `0x50` prefix), if the witness has one.
+    pub annex: Option<&'a [u8]>,
+    /// The leaf hash of the executing tapscript, `None` for key path spends.
+    pub tapleaf_hash: Option<TapLeafHash>,
+    /// The opcode position of the last executed `OP_CODESEPARATOR`, `0xffffffff` if none.
+    pub code_separator_pos: u32,
+}
+
+impl<'a> ExecData<'a> {
+    /// Constructs the data of a tapscript spend of the leaf `tapleaf_hash`.
+    pub fn new(tapleaf_hash: TapLeafHash, annex: Option<&'a [u8]>) -> Self {
+        ExecData { annex, tapleaf_hash: Some(tapleaf_hash), code_separator_pos: u32::MAX }
+    }
+}
+
+/// Verifies that `script_sig` and `witness` satisfy `script_pubkey`.
+///
+/// This is Bitcoin Core's `VerifyScript`, `secp` is used to verify Taproot commitments.
+///
+/// # Errors
+///
+/// If verification fails, or [`ErrorKind::InvalidFlags`] if `flags` is not a supported
+/// combination (see [`VerifyFlags`]).
+pub fn verify_script<C: Verification, S: SignatureChecker + ?Sized>(
+    secp: &Secp256k1<C>,
+    script_sig: &ScriptSig,
+    script_pubkey: &ScriptPubKey,
+    witness: &Witness,
+    flags: VerifyFlags,
+    checker: &mut S,
+) -> Result<(), Error> {
+    flags.validate()?;
+
+    if flags.contains(VerifyFlags::SIGPUSHONLY) && !is_push_only(script_sig.as_bytes()) {
+        return Err(Error::new(ErrorKind::SigPushOnly, Some(ScriptKind::ScriptSig), &[]));
+    }
+
+    let mut exec = ExecState::default();
+    let mut stack = Vec::new();
+    eval_script(
+        &mut stack,
+        script_sig.as_bytes(),
+        flags,
+        checker,
+        SigVersion::Base,
+        &mut exec,
+        ScriptKind::ScriptSig,
+    )?;
+    let stack_copy = if flags.contains(VerifyFlags::P2SH) { stack.clone() } else { Vec::new() };
+    eval_script(
+        &mut stack,
+        script_pubkey.as_bytes(),
+        flags,
+        checker,
+        SigVersion::Base,
+        &mut exec,
+        ScriptKind::ScriptPubKey,
+    )?;
+    check_eval_true(&stack, ScriptKind::ScriptPubKey)?;
+
+    let mut had_witness = false;
+    if flags.contains(VerifyFlags::WITNESS) {
+        if let Some((version, program)) = witness_program(script_pubkey.as_bytes()) {
+            had_witness = true;
+            // Native witness programs must not be malleable through the scriptSig.
+            if !script_sig.is_empty() {
+                return Err(Error::new(ErrorKind::WitnessMalleated, None, &[]));
+            }
+            verify_witness_program(secp, witness, version, program, flags, checker, false)?;
+            // Leave a single true value for the CLEANSTACK check.
+            stack.truncate(1);
+        }
+    }
+
+    if flags.contains(VerifyFlags::P2SH) && script_pubkey.is_p2sh() {
+        if !is_push_only(script_sig.as_bytes()) {
+            return Err(Error::new(ErrorKind::SigPushOnly, Some(ScriptKind::ScriptSig), &[]));
+        }
+        // The scriptPubKey can only succeed if the scriptSig pushed the redeem script.
+        stack = stack_copy;
+        let redeem_script = match stack.pop() {
+            Some(redeem_script) => redeem_script,
+            None => return Err(Error::new(ErrorKind::EvalFalse, None, &[])),
+        };
+        eval_script(
+            &mut stack,
+            &redeem_script,
+            flags,
+            checker,
+            SigVersion::Base,
+            &mut exec,
+            ScriptKind::RedeemScript,
+        )?;
+        check_eval_true(&stack, ScriptKind::RedeemScript)?;
+
+        if flags.contains(VerifyFlags::WITNESS) {
+            if let Some((version, program)) = witness_program(&redeem_script) {
+                had_witness = true;
+                // The scriptSig must be exactly a single push of the redeem script.
+                if script_sig.as_bytes() != encode_push(&redeem_script) {
+                    return Err(Error::new(ErrorKind::WitnessMalleatedP2sh, None, &[]));
+                }
+                verify_witness_program(secp, witness, version, program, flags, checker, true)?;
+                stack.truncate(1);
+            }
+        }
+    }
+
+    if flags.contains(VerifyFlags::CLEANSTACK) && stack.len() != 1 {
+        return Err(Error::new(ErrorKind::CleanStack, None, &stack));
+    }
+    if flags.contains(VerifyFlags::WITNESS) && !had_witness && !witness.is_empty() {
+        return Err(Error::new(ErrorKind::WitnessUnexpected, None, &[]));
+    }
+    Ok(())
+}
+
+/// Verifies input `input_index` of `tx`.
+///
+/// `spent_outputs` are the outputs spent by all inputs of `tx`, in order.
+///
+/// # Errors
+///
+/// If verification fails, or [`ErrorKind::InvalidSpentOutputs`] if `spent_outputs` does not have
+/// an entry per input or `input_index` is out of range.
+pub fn verify_input<C: Verification>(
+    secp: &Secp256k1<C>,
+    tx: &Transaction,
+    input_index: usize,
+    spent_outputs: &[TxOut],
+    flags: VerifyFlags,
+) -> Result<(), Error> {
+    let (txin, spent) = match (tx.inputs.get(input_index), spent_outputs.get(input_index)) {
+        (Some(txin), Some(spent)) if spent_outputs.len() == tx.inputs.len() => (txin, spent),
+        _ => return Err(Error::new(ErrorKind::InvalidSpentOutputs, None, &[])),
+    };
+    let mut cache = SighashCache::new(tx);
+    let mut checker = TransactionSignatureChecker::new(secp, &mut cache, input_index, spent.value)
+        .with_spent_outputs(spent_outputs);
+    verify_script(secp, &txin.script_sig, &spent.script_pubkey, &txin.witness, flags, &mut checker)
+}
+
+/// Returns an error if the stack is empty or its top element is false.
+fn check_eval_true(stack: &[Vec<u8>], script: ScriptKind) -> Result<(), Error> {
+    match stack.last() {
+        Some(top) if crate::script::read_scriptbool(top) => Ok(()),
+        _ => Err(Error::new(ErrorKind::EvalFalse, Some(script), stack)),
+    }
+}
+
+/// Returns the version and program if `script` is a witness program.
+fn witness_program(script: &[u8]) -> Option<(u8, &[u8])> {
+    if script.len() < 4 || script.len() > 42 {
+        return None;
+    }
+    let version = match script[0] {
+        0x00 => 0,
+        op @ 0x51..=0x60 => op - 0x50,
+        _ => return None,
+    };
+    if usize::from(script[1]) + 2 != script.len() {
+        return None;
+    }
+    Some((version, &script[2..]))
+}
+
+fn verify_witness_program<C: Verification, S: SignatureChecker + ?Sized>(
+    secp: &Secp256k1<C>,
+    witness: &Witness,
+    version: u8,
+    program: &[u8],
+    flags: VerifyFlags,
+    checker: &mut S,
+    is_p2sh: bool,
+) -> Result<(), Error> {
+    let mut stack = witness.to_vec();
+    match version {
+        0 if program.len() == 32 => {
+            let script = match stack.pop() {
+                Some(script) => script,
+                None => return Err(Error::new(ErrorKind::WitnessProgramWitnessEmpty, None, &[])),
+            };
+            if sha256::Hash::hash(&script).as_byte_array()[..] != *program {
+                return Err(Error::new(ErrorKind::WitnessProgramMismatch, None, &stack));
+            }
+            execute_witness_script(
+                stack,
+                &script,
+                flags,
+                SigVersion::WitnessV0,
+                checker,
+                &mut ExecState::default(),
+            )
+        }
+        0 if program.len() == 20 => {
+            if stack.len() != 2 {
+                return Err(Error::new(ErrorKind::WitnessProgramMismatch, None, &stack));
+            }
+            let mut script = Vec::with_capacity(25);
+            script.extend_from_slice(&[OP_DUP.to_u8(), OP_HASH160.to_u8(), 20]);
+            script.extend_from_slice(program);
+            script.extend_from_slice(&[OP_EQUALVERIFY.to_u8(), OP_CHECKSIG.to_u8()]);
+            execute_witness_script(
+                stack,
+                &script,
+                flags,
+                SigVersion::WitnessV0,
+                checker,
+                &mut ExecState::default(),
+            )
+        }
+        0 => Err(Error::new(ErrorKind::WitnessProgramWrongLength, None, &[])),
+        1 if program.len() == 32 && !is_p2sh => {
+            if !flags.contains(VerifyFlags::TAPROOT) {
+                return Ok(());
+            }
+            if stack.is_empty() {
+                return Err(Error::new(ErrorKind::WitnessProgramWitnessEmpty, None, &[]));
+            }
+            let annex = match stack.last() {
+                Some(last) if stack.len() >= 2 && last.first() == Some(&TAPROOT_ANNEX_PREFIX) =>
+                    stack.pop(),
+                _ => None,
+            };
+            let mut exec = ExecState::default();
+            exec.data.annex = annex.as_deref();
+
+            if stack.len() == 1 {
+                // Key path spend.
+                return checker
+                    .check_schnorr_signature(&stack[0], program, SigVersion::Taproot, &exec.data)
+                    .map_err(|kind| Error::new(kind, None, &stack));
+            }
+
+            // Script path spend, the stack has at least two elements.
+            let control = stack.pop().expect("at least two elements");
+            let script = stack.pop().expect("at least two elements");
+            if control.len() < TAPROOT_CONTROL_BASE_SIZE
+                || control.len() > TAPROOT_CONTROL_MAX_SIZE
+                || (control.len() - TAPROOT_CONTROL_BASE_SIZE) % TAPROOT_CONTROL_NODE_SIZE != 0
+            {
+                return Err(Error::new(ErrorKind::TaprootWrongControlSize, None, &stack));
+            }
+            let leaf_version = control[0] & TAPROOT_LEAF_MASK;
+            let tapleaf_hash = compute_tapleaf_hash(leaf_version, &script);
+            if !verify_taproot_commitment(secp, &control, program, tapleaf_hash) {
+                return Err(Error::new(ErrorKind::WitnessProgramMismatch, None, &stack));
+            }
+            exec.data.tapleaf_hash = Some(tapleaf_hash);
+
+            if leaf_version == TAPROOT_LEAF_TAPSCRIPT {
+                exec.validation_weight_left = witness.size() as i64 + VALIDATION_WEIGHT_OFFSET;
+                return execute_witness_script(
+                    stack,
+                    &script,
+                    flags,
+                    SigVersion::Tapscript,
+                    checker,
+                    &mut exec,
+                );
+            }
+            if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION) {
+                return Err(Error::new(ErrorKind::DiscourageUpgradableTaprootVersion, None, &[]));
+            }
+            Ok(())
+        }
+        // Other versions and sizes are unencumbered for future soft forks.
+        _ =>
+            if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM) {
+                Err(Error::new(ErrorKind::DiscourageUpgradableWitnessProgram, None, &[]))
+            } else {
+                Ok(())
+            },
+    }
+}
+
+/// Executes a witness script or tapscript on the remaining witness stack.
+fn execute_witness_script<S: SignatureChecker + ?Sized>(
+    mut stack: Vec<Vec<u8>>,
+    script: &[u8],
+    flags: VerifyFlags,
+    sig_version: SigVersion,
+    checker: &mut S,
+    exec: &mut ExecState,
+) -> Result<(), Error> {
+    let kind = match sig_version {
+        SigVersion::Tapscript => ScriptKind::TapScript,
+        _ => ScriptKind::WitnessScript,
+    };
+
+    if sig_version == SigVersion::Tapscript {
+        // `OP_SUCCESSx` makes the script succeed before anything else is checked.
+        match contains_op_success(script) {
+            Ok(true) if flags.contains(VerifyFlags::DISCOURAGE_OP_SUCCESS) =>
+                return Err(Error::new(ErrorKind::DiscourageOpSuccess, Some(kind), &stack)),
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(e) => return Err(Error::new(e, Some(kind), &stack)),
+        }
+        if stack.len() > MAX_STACK_SIZE {
+            return Err(Error::new(ErrorKind::StackSize, Some(kind), &stack));
+        }
+    }
+    if stack.iter().any(|elem| elem.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+        return Err(Error::new(ErrorKind::PushSize, Some(kind), &stack));
+    }
+
+    eval_script(&mut stack, script, flags, checker, sig_version, exec, kind)?;
+
+    // Witness scripts implicitly require a clean stack.
+    if stack.len() != 1 {
+        return Err(Error::new(ErrorKind::CleanStack, Some(kind), &stack));
+    }
+    check_eval_true(&stack, kind)
+}
+
+/// Computes the leaf hash for any leaf version, including ones [`crate::taproot::LeafVersion`]
+/// can't represent.
+fn compute_tapleaf_hash(leaf_version: u8, script: &[u8]) -> TapLeafHash {
+    let mut eng = sha256t::Hash::<TapLeafTag>::engine();
+    leaf_version.consensus_encode(&mut eng).expect("engines don't error");
+    TapScript::from_bytes(script).consensus_encode(&mut eng).expect("engines don't error");
+    let inner = sha256t::Hash::<TapLeafTag>::from_engine(eng);
+    TapLeafHash::from_byte_array(inner.to_byte_array())
+}
+
+/// Returns true if `control` proves that the leaf is committed to by the output key `program`.
+fn verify_taproot_commitment<C: Verification>(
+    secp: &Secp256k1<C>,
+    control: &[u8],
+    program: &[u8],
+    tapleaf_hash: TapLeafHash,
+) -> bool {
+    let internal_key = match <&[u8; 32]>::try_from(&control[1..TAPROOT_CONTROL_BASE_SIZE])
+        .ok()
+        .and_then(|key| XOnlyPublicKey::from_byte_array(key).ok())
+    {
+        Some(key) => key,
+        None => return false,
+    };
+    let output_key = match <&[u8; 32]>::try_from(program)
+        .ok()
+        .and_then(|key| XOnlyPublicKey::from_byte_array(key).ok())
+    {
+        Some(key) => key,
+        None => return false,
+    };
+
+    let mut node = TapNodeHash::from(tapleaf_hash);
+    for hash in control[TAPROOT_CONTROL_BASE_SIZE..].chunks_exact(TAPROOT_CONTROL_NODE_SIZE) {
+        let hash = <[u8; 32]>::try_from(hash).expect("chunks are 32 bytes");
+        node = TapNodeHash::from_node_hashes(node, TapNodeHash::from_byte_array(hash));
+    }
+    let tweak = TapTweakHash::from_key_and_merkle_root(internal_key, Some(node)).to_scalar();
+    let parity = if control[0] & 1 == 1 { Parity::Odd } else { Parity::Even };
+    internal_key.tweak_add_check(secp, &output_key, parity, tweak)
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{Keypair, Message, SecretKey};
+
+    use super::*;
+    use crate::locktime::absolute;
+    use crate::script::{ScriptPubKeyBuf, ScriptSigBuf};
+    use crate::sighash::{EcdsaSighashType, Prevouts, TapSighashType};
+    use crate::taproot::LeafVersion;
+    use crate::transaction::{self, TxIn};
+    use crate::{Amount, OutPoint, PublicKey, Sequence};
+
+    const VALUE: Amount = Amount::from_sat_u32(100_000);
+
+    fn keypair(n: u8) -> Keypair {
+        let secp = Secp256k1::new();
+        Keypair::from_secret_key(&secp, &SecretKey::from_byte_array(&[n; 32]).unwrap())
+    }
+
+    fn pubkey(n: u8) -> PublicKey { PublicKey::new(keypair(n).public_key()) }
+
+    fn push(data: &[u8]) -> Vec<u8> { encode_push(data) }
+
+    fn spending_tx(script_sig: Vec<u8>, witness: &[Vec<u8>]) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            inputs: vec![TxIn {
+                previous_output: OutPoint::COINBASE_PREVOUT,
+                script_sig: ScriptSigBuf::from_bytes(script_sig),
+                sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+                witness: Witness::from_slice(witness),
+            }],
+            outputs: vec![TxOut { value: VALUE, script_pubkey: ScriptPubKeyBuf::new() }],
+        }
+    }
+
+    fn verify(tx: &Transaction, script_pubkey: &[u8], flags: VerifyFlags) -> Result<(), Error> {
+        let spent = [TxOut {
+            value: VALUE,
+            script_pubkey: ScriptPubKeyBuf::from_bytes(script_pubkey.to_vec()),
+        }];
+        verify_input(&Secp256k1::verification_only(), tx, 0, &spent, flags)
+    }
+
+    /// Evaluates `script_sig` and `script_pubkey` without a transaction.
+    fn eval(script_sig: &[u8], script_pubkey: &[u8], flags: VerifyFlags) -> Result<(), Error> {
+        verify_script(
+            &Secp256k1::verification_only(),
+            ScriptSig::from_bytes(script_sig),
+            ScriptPubKey::from_bytes(script_pubkey),
+            &Witness::new(),
+            flags,
+            &mut NullSignatureChecker,
+        )
+    }
+
+    /// Signs input 0 of `tx` with an ECDSA signature over the legacy sighash.
+    fn sign_legacy(tx: &Transaction, script_code: &[u8], n: u8) -> Vec<u8> {
+        let cache = SighashCache::new(tx);
+        let sighash =
+            cache.legacy_signature_hash(0, ScriptPubKey::from_bytes(script_code), 0x01).unwrap();
+        let secp = Secp256k1::new();
+        let sig = secp.sign_ecdsa(&Message::from(sighash), &keypair(n).secret_key());
+        let mut sig = sig.serialize_der().to_vec();
+        sig.push(0x01);
+        sig
+    }
+
+    fn p2pkh_script(n: u8) -> Vec<u8> {
+        let mut script = vec![OP_DUP.to_u8(), OP_HASH160.to_u8()];
+        script.extend(push(pubkey(n).pubkey_hash().as_byte_array()));
+        script.extend([OP_EQUALVERIFY.to_u8(), OP_CHECKSIG.to_u8()]);
+        script
+    }
+
+    #[test]
+    fn arithmetic_and_flow_control() {
+        let flags = VerifyFlags::NONE;
+        // 2 3 ADD 5 EQUAL
+        assert!(eval(&[0x52, 0x53], &[0x93, 0x55, 0x87], flags).is_ok());
+        // 1 IF 2 ELSE 0 ENDIF
+        assert!(eval(&[], &[0x51, 0x63, 0x52, 0x67, 0x00, 0x68], flags).is_ok());
+        // 0 IF 0 ELSE 2 ENDIF
+        assert!(eval(&[], &[0x00, 0x63, 0x00, 0x67, 0x52, 0x68], flags).is_ok());
+        // -1 ABS 1 NUMEQUAL
+        assert!(eval(&[0x4f], &[0x90, 0x51, 0x9c], flags).is_ok());
+        // 3 4 5 ROT: 4 5 3 -> 3 EQUALVERIFY 5 EQUALVERIFY 4 EQUAL
+        assert!(
+            eval(&[0x53, 0x54, 0x55], &[0x7b, 0x53, 0x88, 0x55, 0x88, 0x54, 0x87], flags).is_ok()
+        );
+        // 'abc' SIZE 3 EQUALVERIFY SHA256 <hash> EQUAL
+        let mut script = vec![0x82, 0x53, 0x88, 0xa8];
+        script.extend(push(sha256::Hash::hash(b"abc").as_byte_array()));
+        script.push(0x87);
+        assert!(eval(&push(b"abc"), &script, flags).is_ok());
+    }
+
+    #[test]
+    fn error_location() {
+        // 1 2 IF RETURN ENDIF
+        let err = eval(&[], &[0x51, 0x52, 0x63, 0x6a, 0x68], VerifyFlags::NONE).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OpReturn);
+        assert_eq!(err.script(), Some(ScriptKind::ScriptPubKey));
+        assert_eq!(err.opcode_index(), Some(3));
+        assert_eq!(err.opcode(), Some(OP_RETURN));
+        assert_eq!(err.stack(), &[vec![1]]);
+
+        let err = eval(&[0x00], &[], VerifyFlags::NONE).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::EvalFalse);
+        assert_eq!(err.opcode_index(), None);
+
+        // Truncated push.
+        let err = eval(&[0x51, 0x02, 0x00], &[], VerifyFlags::NONE).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BadOpcode);
+        assert_eq!(err.script(), Some(ScriptKind::ScriptSig));
+        assert_eq!(err.opcode_index(), Some(1));
+        assert_eq!(err.opcode(), None);
+    }
+
+    #[test]
+    fn unexecuted_branches() {
+        let flags = VerifyFlags::NONE;
+        // Disabled opcodes fail even when not executed: 1 0 IF CAT ENDIF
+        let err = eval(&[], &[0x51, 0x00, 0x63, 0x7e, 0x68], flags).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DisabledOpcode);
+        // So does OP_VERIF: 1 0 IF VERIF ENDIF
+        let err = eval(&[], &[0x51, 0x00, 0x63, 0x65, 0x68], flags).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BadOpcode);
+        // Unknown opcodes don't: 1 0 IF 0xba ENDIF
+        assert!(eval(&[], &[0x51, 0x00, 0x63, 0xba, 0x68], flags).is_ok());
+        // Unbalanced: 1 IF
+        let err = eval(&[], &[0x51, 0x63], flags).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnbalancedConditional);
+        // Scripts are executed separately: IF in scriptSig, ENDIF in scriptPubKey.
+        let err = eval(&[0x51, 0x63], &[0x68, 0x51], flags).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnbalancedConditional);
+    }
+
+    #[test]
+    fn minimal_data() {
+        // 0x01 0x05 pushes 5 non-minimally.
+        assert!(eval(&[0x01, 0x05], &[0x55, 0x87], VerifyFlags::NONE).is_ok());
+        let err = eval(&[0x01, 0x05], &[0x55, 0x87], VerifyFlags::MINIMALDATA).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MinimalData);
+        // Numbers with a trailing zero byte.
+        assert!(eval(&[0x02, 0x05, 0x00], &[0x55, 0x9c], VerifyFlags::NONE).is_ok());
+        let err = eval(&[0x02, 0x05, 0x00], &[0x55, 0x9c], VerifyFlags::MINIMALDATA).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NonMinimalScriptNum);
+        // Numbers are limited to 4 bytes.
+        let err = eval(&[0x05, 1, 0, 0, 0, 0], &[0x8b], VerifyFlags::NONE).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ScriptNumOverflow);
+    }
+
+    #[test]
+    fn invalid_flags() {
+        let err = eval(&[0x51], &[], VerifyFlags::WITNESS).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidFlags);
+        let err = eval(&[0x51], &[], VerifyFlags::CLEANSTACK | VerifyFlags::P2SH).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidFlags);
+        let err = eval(&[0x51], &[], VerifyFlags::from_u32(1 << 31)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidFlags);
+        assert!(eval(&[0x51], &[], VerifyFlags::STANDARD).is_ok());
+    }
+
+    #[test]
+    fn p2pkh() {
+        let script_pubkey = p2pkh_script(1);
+        let unsigned = spending_tx(vec![], &[]);
+        let sig = sign_legacy(&unsigned, &script_pubkey, 1);
+
+        let mut script_sig = push(&sig);
+        script_sig.extend(push(&pubkey(1).to_vec()));
+        let tx = spending_tx(script_sig, &[]);
+        assert!(verify(&tx, &script_pubkey, VerifyFlags::STANDARD).is_ok());
+
+        // Signature by the wrong key.
+        let sig = sign_legacy(&unsigned, &script_pubkey, 2);
+        let mut script_sig = push(&sig);
+        script_sig.extend(push(&pubkey(1).to_vec()));
+        let tx = spending_tx(script_sig, &[]);
+        let err = verify(&tx, &script_pubkey, VerifyFlags::CONSENSUS).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::EvalFalse);
+        let err = verify(&tx, &script_pubkey, VerifyFlags::STANDARD).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::SigNullFail);
+        assert_eq!(err.opcode(), Some(OP_CHECKSIG));
+    }
+
+    #[test]
+    fn p2sh_multisig() {
+        // 1 <pk1> <pk2> 2 CHECKMULTISIG
+        let mut redeem_script = vec![0x51];
+        redeem_script.extend(push(&pubkey(1).to_vec()));
+        redeem_script.extend(push(&pubkey(2).to_vec()));
+        redeem_script.extend([0x52, OP_CHECKMULTISIG.to_u8()]);
+        let mut script_pubkey = vec![OP_HASH160.to_u8()];
+        script_pubkey.extend(push(hashes::hash160::Hash::hash(&redeem_script).as_byte_array()));
+        script_pubkey.push(OP_EQUAL.to_u8());
+
+        let sig = sign_legacy(&spending_tx(vec![], &[]), &redeem_script, 2);
+        let script_sig = |dummy: &[u8]| {
+            let mut script_sig = dummy.to_vec();
+            script_sig.extend(push(&sig));
+            script_sig.extend(push(&redeem_script));
+            spending_tx(script_sig, &[])
+        };
+
+        assert!(verify(&script_sig(&[0x00]), &script_pubkey, VerifyFlags::STANDARD).is_ok());
+        // Without P2SH only the hash of the redeem script is checked.
+        assert!(verify(&script_sig(&[0x51]), &script_pubkey, VerifyFlags::NONE).is_ok());
+        let err = verify(&script_sig(&[0x51]), &script_pubkey, VerifyFlags::CONSENSUS).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::SigNullDummy);
+        assert_eq!(err.script(), Some(ScriptKind::RedeemScript));
+        assert_eq!(err.opcode_index(), Some(4));
+    }
+
+    #[test]
+    fn p2wpkh() {
+        let pk = pubkey(1);
+        let mut script_pubkey = vec![0x00];
+        script_pubkey.extend(push(pk.wpubkey_hash().unwrap().as_byte_array()));
+
+        let unsigned = spending_tx(vec![], &[]);
+        let mut cache = SighashCache::new(&unsigned);
+        let sighash = cache
+            .p2wpkh_signature_hash(
+                0,
+                ScriptPubKey::from_bytes(&script_pubkey),
+                VALUE,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        let secp = Secp256k1::new();
+        let sig = secp.sign_ecdsa(&Message::from(sighash), &keypair(1).secret_key());
+        let mut sig = sig.serialize_der().to_vec();
+        sig.push(0x01);
+
+        let tx = spending_tx(vec![], &[sig.clone(), pk.to_vec()]);
+        assert!(verify(&tx, &script_pubkey, VerifyFlags::STANDARD).is_ok());
+
+        // Without WITNESS the output is anyone can spend.
+        assert!(verify(&tx, &script_pubkey, VerifyFlags::P2SH).is_ok());
+
+        let tx = spending_tx(vec![0x51], &[sig.clone(), pk.to_vec()]);
+        let err = verify(&tx, &script_pubkey, VerifyFlags::CONSENSUS).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WitnessMalleated);
+
+        let tx = spending_tx(vec![], &[sig]);
+        let err = verify(&tx, &script_pubkey, VerifyFlags::CONSENSUS).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WitnessProgramMismatch);
+    }
+
+    #[test]
+    fn p2wsh_cleanstack() {
+        // Witness script: DROP 1
+        let witness_script = vec![0x75, 0x51];
+        let mut script_pubkey = vec![0x00];
+        script_pubkey.extend(push(sha256::Hash::hash(&witness_script).as_byte_array()));
+
+        let tx = spending_tx(vec![], &[vec![0x05], witness_script.clone()]);
+        assert!(verify(&tx, &script_pubkey, VerifyFlags::CONSENSUS).is_ok());
+
+        // The witness script must leave exactly one element, even without CLEANSTACK.
+        let tx = spending_tx(vec![], &[vec![0x05], vec![0x05], witness_script.clone()]);
+        let err = verify(&tx, &script_pubkey, VerifyFlags::CONSENSUS).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::CleanStack);
+        assert_eq!(err.script(), Some(ScriptKind::WitnessScript));
+
+        let tx = spending_tx(vec![], &[vec![0x05], vec![0x51]]);
+        let err = verify(&tx, &script_pubkey, VerifyFlags::CONSENSUS).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WitnessProgramMismatch);
+    }
+
+    #[test]
+    fn taproot_key_and_script_path() {
+        let secp = Secp256k1::new();
+        let internal = keypair(1);
+        let (internal_key, _) = internal.x_only_public_key();
+        let (leaf_key, _) = keypair(2).x_only_public_key();
+
+        let mut leaf_script = push(&leaf_key.serialize());
+        leaf_script.push(OP_CHECKSIG.to_u8());
+        let leaf_hash =
+            TapLeafHash::from_script(TapScript::from_bytes(&leaf_script), LeafVersion::TapScript);
+        let tweak = TapTweakHash::from_key_and_merkle_root(internal_key, Some(leaf_hash.into()))
+            .to_scalar();
+        let tweaked = internal.add_xonly_tweak(&secp, &tweak).unwrap();
+        let (output_key, parity) = tweaked.x_only_public_key();
+
+        let mut script_pubkey = vec![0x51];
+        script_pubkey.extend(push(&output_key.serialize()));
+        let spent = [TxOut {
+            value: VALUE,
+            script_pubkey: ScriptPubKeyBuf::from_bytes(script_pubkey.clone()),
+        }];
+        let unsigned = spending_tx(vec![], &[]);
+
+        // Key path.
+        let sighash = SighashCache::new(&unsigned)
+            .taproot_key_spend_signature_hash(0, &Prevouts::All(&spent), TapSighashType::Default)
+            .unwrap();
+        let sig = secp.sign_schnorr_no_aux_rand(&sighash.to_byte_array(), &tweaked);
+        let tx = spending_tx(vec![], &[sig.to_byte_array().to_vec()]);
+        assert!(verify(&tx, &script_pubkey, VerifyFlags::STANDARD).is_ok());
+
+        // An explicit SIGHASH_DEFAULT byte is not allowed.
+        let mut sig_default = sig.to_byte_array().to_vec();
+        sig_default.push(0x00);
+        let tx = spending_tx(vec![], &[sig_default]);
+        let err = verify(&tx, &script_pubkey, VerifyFlags::CONSENSUS).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::SchnorrSigHashType);
+
+        // Without TAPROOT the output is anyone can spend.
+        let tx = spending_tx(vec![], &[vec![0x01; 64]]);
+        assert!(verify(&tx, &script_pubkey, VerifyFlags::P2SH | VerifyFlags::WITNESS).is_ok());
+        let err = verify(&tx, &script_pubkey, VerifyFlags::CONSENSUS).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::SchnorrSig);
+
+        // Script path.
+        let sighash = SighashCache::new(&unsigned)
+            .taproot_script_spend_signature_hash(
+                0,
+                &Prevouts::All(&spent),
+                leaf_hash,
+                TapSighashType::Default,
+            )
+            .unwrap();
+        let sig = secp.sign_schnorr_no_aux_rand(&sighash.to_byte_array(), &keypair(2));
+        let mut control = vec![TAPROOT_LEAF_TAPSCRIPT | parity.to_u8()];
+        control.extend(internal_key.serialize());
+        let tx =
+            spending_tx(vec![], &[sig.to_byte_array().to_vec(), leaf_script.clone(), control.clone()]);
+        assert!(verify(&tx, &script_pubkey, VerifyFlags::STANDARD).is_ok());
+
+        // An empty signature fails the check without an error.
+        let tx = spending_tx(vec![], &[vec![], leaf_script.clone(), control.clone()]);
+        let err = verify(&tx, &script_pubkey, VerifyFlags::STANDARD).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::EvalFalse);
+        assert_eq!(err.script(), Some(ScriptKind::TapScript));
+
+        // Wrong parity in the control block.
+        control[0] ^= 1;
+        let tx = spending_tx(vec![], &[sig.to_byte_array().to_vec(), leaf_script.clone(), control]);
+        let err = verify(&tx, &script_pubkey, VerifyFlags::STANDARD).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WitnessProgramMismatch);
+    }
+
+    #[test]
+    fn tapscript_op_success() {
+        let secp = Secp256k1::new();
+        let internal = keypair(1);
+        let (internal_key, _) = internal.x_only_public_key();
+        // 0x50 is OP_SUCCESS80 in tapscript, the script would otherwise fail.
+        let leaf_script = vec![0x6a, 0x50];
+        let leaf_hash =
+            TapLeafHash::from_script(TapScript::from_bytes(&leaf_script), LeafVersion::TapScript);
+        let tweak = TapTweakHash::from_key_and_merkle_root(internal_key, Some(leaf_hash.into()))
+            .to_scalar();
+        let (output_key, parity) =
+            internal.add_xonly_tweak(&secp, &tweak).unwrap().x_only_public_key();
+
+        let mut script_pubkey = vec![0x51];
+        script_pubkey.extend(push(&output_key.serialize()));
+        let mut control = vec![TAPROOT_LEAF_TAPSCRIPT | parity.to_u8()];
+        control.extend(internal_key.serialize());
+
+        let tx = spending_tx(vec![], &[leaf_script, control]);
+        assert!(verify(&tx, &script_pubkey, VerifyFlags::CONSENSUS).is_ok());
+        let err = verify(&tx, &script_pubkey, VerifyFlags::STANDARD).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DiscourageOpSuccess);
+    }
+
+    #[test]
+    fn check_lock_time_verify() {
+        // 100 CHECKLOCKTIMEVERIFY
+        let script_pubkey = [0x01, 100, OP_CLTV.to_u8()];
+        let mut tx = spending_tx(vec![], &[]);
+        let flags = VerifyFlags::CONSENSUS;
+
+        let err = verify(&tx, &script_pubkey, flags).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnsatisfiedLockTime);
+
+        tx.lock_time = absolute::LockTime::from_consensus(100);
+        assert!(verify(&tx, &script_pubkey, flags).is_ok());
+
+        // A final input disables the lock time.
+        tx.inputs[0].sequence = Sequence::MAX;
+        let err = verify(&tx, &script_pubkey, flags).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnsatisfiedLockTime);
+
+        // Treated as a NOP without the flag.
+        assert!(verify(&tx, &script_pubkey, VerifyFlags::P2SH).is_ok());
+    }
+}
@@ -0,0 +1,299 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Step-by-step script execution.
+//!
+//! [`Execution`] runs a single script one opcode at a time, yielding a [`Step`] with a snapshot
+//! of the interpreter state after each opcode. This is intended for debugging tools and tests,
+//! use [`verify_script`](super::verify_script) to verify spends.
+
+use super::error::{Error, ScriptKind};
+use super::eval::{ExecState, Machine};
+use super::{ExecData, SigVersion, SignatureChecker, VerifyFlags};
+use crate::prelude::Vec;
+use crate::script::{
+    Instruction, RedeemScriptTag, Script, ScriptPubKeyTag, TapScriptTag, WitnessScriptTag,
+};
+
+/// Script types that can be executed step by step.
+///
+/// This trait is sealed, it is implemented for the tags of [`ScriptPubKey`](crate::ScriptPubKey),
+/// [`RedeemScript`](crate::RedeemScript), [`WitnessScript`](crate::WitnessScript) and
+/// [`TapScript`](crate::TapScript).
+pub trait ExecutableTag: sealed::Sealed {
+    /// The signature scheme scripts of this type are executed under.
+    const SIG_VERSION: SigVersion;
+
+    /// The kind reported in errors.
+    #[doc(hidden)]
+    const KIND: ScriptKind;
+}
+
+impl ExecutableTag for ScriptPubKeyTag {
+    const SIG_VERSION: SigVersion = SigVersion::Base;
+    const KIND: ScriptKind = ScriptKind::ScriptPubKey;
+}
+
+impl ExecutableTag for RedeemScriptTag {
+    const SIG_VERSION: SigVersion = SigVersion::Base;
+    const KIND: ScriptKind = ScriptKind::RedeemScript;
+}
+
+impl ExecutableTag for WitnessScriptTag {
+    const SIG_VERSION: SigVersion = SigVersion::WitnessV0;
+    const KIND: ScriptKind = ScriptKind::WitnessScript;
+}
+
+impl ExecutableTag for TapScriptTag {
+    const SIG_VERSION: SigVersion = SigVersion::Tapscript;
+    const KIND: ScriptKind = ScriptKind::TapScript;
+}
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for crate::script::ScriptPubKeyTag {}
+    impl Sealed for crate::script::RedeemScriptTag {}
+    impl Sealed for crate::script::WitnessScriptTag {}
+    impl Sealed for crate::script::TapScriptTag {}
+}
+
+/// The interpreter state after executing one opcode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step<'a> {
+    /// Index of the opcode in the script.
+    pub index: usize,
+    /// Byte position of the opcode in the script.
+    pub position: usize,
+    /// The executed instruction.
+    pub instruction: Instruction<'a>,
+    /// False if the opcode is in a branch that is not executed.
+    pub executed: bool,
+    /// The main stack, top element last.
+    pub stack: Vec<Vec<u8>>,
+    /// The alt stack, top element last.
+    pub altstack: Vec<Vec<u8>>,
+    /// The `OP_IF` condition stack, innermost last.
+    ///
+    /// Conditions nested in a branch that is not executed are all reported as false.
+    pub conditions: Vec<bool>,
+}
+
+/// A script being executed one opcode at a time.
+///
+/// The iterator yields a [`Step`] after each opcode. If an opcode fails, or the script ends with
+/// an unbalanced conditional, the error is yielded instead and the iterator ends.
+///
+/// Scripts are executed on their own, witness and P2SH rules that depend on how the script is
+/// spent (e.g. the clean stack requirement of witness scripts) are not checked.
+///
+/// # Examples
+///
+/// ```
+/// use bitcoin::script::interpreter::{Execution, NullSignatureChecker, VerifyFlags};
+/// use bitcoin::opcodes::all::*;
+/// use bitcoin::script::{Builder, ScriptPubKeyBuf};
+///
+/// // 2 ADD 5 EQUAL
+/// let script: ScriptPubKeyBuf = Builder::new()
+///     .push_int(2).unwrap()
+///     .push_opcode(OP_ADD)
+///     .push_int(5).unwrap()
+///     .push_opcode(OP_EQUAL)
+///     .into_script();
+/// let mut checker = NullSignatureChecker;
+/// let exec = Execution::new(&script, VerifyFlags::STANDARD, &mut checker)
+///     .with_stack(vec![vec![3]]);
+///
+/// let steps = exec.collect::<Result<Vec<_>, _>>().unwrap();
+/// assert_eq!(steps.len(), 4);
+/// assert_eq!(steps[1].stack, [vec![5]]);
+/// assert_eq!(steps[3].stack, [vec![1]]);
+/// ```
+pub struct Execution<'a, C: SignatureChecker + ?Sized> {
+    machine: Machine<'a>,
+    checker: &'a mut C,
+    exec: ExecState<'a>,
+    done: bool,
+}
+
+impl<'a, C: SignatureChecker + ?Sized> Execution<'a, C> {
+    /// Starts executing `script` on an empty stack.
+    ///
+    /// Tapscripts have an unlimited signature validation budget, use
+    /// [`with_validation_weight`](Self::with_validation_weight) to enforce the BIP-0342 limit.
+    pub fn new<T: ExecutableTag>(
+        script: &'a Script<T>,
+        flags: VerifyFlags,
+        checker: &'a mut C,
+    ) -> Self {
+        let machine = Machine::new(Vec::new(), script.as_bytes(), flags, T::SIG_VERSION, T::KIND);
+        let exec = ExecState { validation_weight_left: i64::MAX, ..Default::default() };
+        Execution { machine, checker, exec, done: false }
+    }
+
+    /// Sets the initial stack, top element last.
+    ///
+    /// For a spend this is the stack left by the `scriptSig` or the witness stack.
+    pub fn with_stack(mut self, stack: Vec<Vec<u8>>) -> Self {
+        self.machine.stack = stack;
+        self
+    }
+
+    /// Sets the Taproot data passed to the signature checker.
+    pub fn with_exec_data(mut self, exec_data: ExecData<'a>) -> Self {
+        self.exec.data = exec_data;
+        self
+    }
+
+    /// Sets the tapscript signature validation budget.
+    ///
+    /// BIP-0342 limits the budget to the size of the witness plus 50.
+    pub fn with_validation_weight(mut self, validation_weight: i64) -> Self {
+        self.exec.validation_weight_left = validation_weight;
+        self
+    }
+
+    /// Returns the current main stack, top element last.
+    pub fn stack(&self) -> &[Vec<u8>] { &self.machine.stack }
+
+    /// Returns the current alt stack, top element last.
+    pub fn altstack(&self) -> &[Vec<u8>] { &self.machine.altstack }
+
+    /// Returns the current `OP_IF` condition stack, innermost last.
+    pub fn conditions(&self) -> Vec<bool> { self.machine.conditions() }
+
+    /// Returns the byte position of the next opcode.
+    pub fn position(&self) -> usize { self.machine.pc }
+
+    /// Returns true if the script ran to completion or failed.
+    pub fn is_done(&self) -> bool { self.done }
+}
+
+impl<'a, C: SignatureChecker + ?Sized> Iterator for Execution<'a, C> {
+    type Item = Result<Step<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if self.machine.is_done() {
+            self.done = true;
+            return self.machine.finish().err().map(Err);
+        }
+
+        let (index, position) = (self.machine.opcode_pos, self.machine.pc);
+        match self.machine.step(&mut *self.checker, &mut self.exec) {
+            Ok((instruction, executed)) => Some(Ok(Step {
+                index,
+                position,
+                instruction,
+                executed,
+                stack: self.machine.stack.clone(),
+                altstack: self.machine.altstack.clone(),
+                conditions: self.machine.conditions(),
+            })),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes::all::*;
+    use crate::script::interpreter::{ErrorKind, NullSignatureChecker};
+    use crate::script::{Builder, ScriptPubKeyBuf, TapScriptBuf, WitnessScriptBuf};
+
+    #[test]
+    fn branches() {
+        // 1 IF 2 ELSE 3 ENDIF
+        let script: WitnessScriptBuf = Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_IF)
+            .push_opcode(OP_PUSHNUM_2)
+            .push_opcode(OP_ELSE)
+            .push_opcode(OP_PUSHNUM_3)
+            .push_opcode(OP_ENDIF)
+            .into_script();
+        let mut checker = NullSignatureChecker;
+        let steps = Execution::new(&script, VerifyFlags::STANDARD, &mut checker)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(steps.len(), 6);
+        assert_eq!(steps[1].instruction, Instruction::Op(OP_IF));
+        assert_eq!(steps[1].conditions, [true]);
+        assert!(steps[1].stack.is_empty());
+        assert_eq!(steps[2].stack, [vec![2]]);
+        assert_eq!(steps[3].conditions, [false]);
+        assert!(!steps[4].executed);
+        assert_eq!(steps[4].stack, [vec![2]]);
+        assert!(steps[5].conditions.is_empty());
+        assert_eq!(steps.iter().map(|s| s.index).collect::<Vec<_>>(), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn altstack_and_positions() {
+        // <aabb> TOALTSTACK 1 FROMALTSTACK
+        let script: ScriptPubKeyBuf = Builder::new()
+            .push_slice([0xaa, 0xbb])
+            .push_opcode(OP_TOALTSTACK)
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_FROMALTSTACK)
+            .into_script();
+        let mut checker = NullSignatureChecker;
+        let mut exec = Execution::new(&script, VerifyFlags::NONE, &mut checker);
+
+        let step = exec.next().unwrap().unwrap();
+        assert_eq!(step.position, 0);
+        assert_eq!(step.stack, [vec![0xaa, 0xbb]]);
+        let step = exec.next().unwrap().unwrap();
+        assert_eq!(step.position, 3);
+        assert!(step.stack.is_empty());
+        assert_eq!(step.altstack, [vec![0xaa, 0xbb]]);
+        assert_eq!(exec.position(), 4);
+        exec.next().unwrap().unwrap();
+        let step = exec.next().unwrap().unwrap();
+        assert_eq!(step.stack, [vec![1], vec![0xaa, 0xbb]]);
+        assert!(step.altstack.is_empty());
+        assert!(exec.next().is_none());
+        assert!(exec.is_done());
+    }
+
+    #[test]
+    fn failure_ends_execution() {
+        // 1 VERIFY 0 VERIFY 1
+        let script: TapScriptBuf = Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_VERIFY)
+            .push_opcode(OP_PUSHBYTES_0)
+            .push_opcode(OP_VERIFY)
+            .push_opcode(OP_PUSHNUM_1)
+            .into_script();
+        let mut checker = NullSignatureChecker;
+        let mut exec = Execution::new(&script, VerifyFlags::STANDARD, &mut checker);
+
+        assert!(exec.by_ref().take(3).all(|step| step.is_ok()));
+        let err = exec.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Verify);
+        assert_eq!(err.opcode_index(), Some(3));
+        assert_eq!(err.script(), Some(ScriptKind::TapScript));
+        assert!(exec.next().is_none());
+    }
+
+    #[test]
+    fn unbalanced_conditional_at_end() {
+        let script: ScriptPubKeyBuf =
+            Builder::new().push_opcode(OP_PUSHNUM_1).push_opcode(OP_IF).into_script();
+        let mut checker = NullSignatureChecker;
+        let mut exec = Execution::new(&script, VerifyFlags::NONE, &mut checker);
+
+        assert!(exec.next().unwrap().is_ok());
+        assert!(exec.next().unwrap().is_ok());
+        let err = exec.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnbalancedConditional);
+        assert!(exec.next().is_none());
+    }
+}
@@ -52,6 +52,7 @@
 mod push_bytes;
 #[cfg(test)]
 mod tests;
+pub mod interpreter;
 pub mod witness_program;
 pub mod witness_version;
 
@@ -80,10 +81,11 @@
 };
 #[doc(inline)]
 pub use primitives::script::{
-    RedeemScript, RedeemScriptBuf, RedeemScriptSizeError, RedeemScriptTag, Script, ScriptBuf,
-    ScriptHash, ScriptHashableTag, ScriptPubKey, ScriptPubKeyBuf, ScriptPubKeyTag, ScriptSig,
-    ScriptSigBuf, ScriptSigTag, Tag, TapScript, TapScriptBuf, WScriptHash, WitnessScript,
-    WitnessScriptBuf, WitnessScriptSizeError, WitnessScriptTag,
+    ParseAsmError, ParseAsmErrorKind, RedeemScript, RedeemScriptBuf, RedeemScriptSizeError,
+    RedeemScriptTag, Script, ScriptBuf, ScriptHash, ScriptHashableTag, ScriptPubKey,
+    ScriptPubKeyBuf, ScriptPubKeyTag, ScriptSig, ScriptSigBuf, ScriptSigTag, Tag, TapScript,
+    TapScriptBuf, TapScriptTag, WScriptHash, WitnessScript, WitnessScriptBuf,
+    WitnessScriptSizeError, WitnessScriptTag,
 };
 
 pub(crate) use self::borrowed::ScriptExtPriv;
@@ -0,0 +1,195 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Branch and Bound coin selection.
+//!
+//! A depth first search over the inclusion or omission of each UTXO, as described in Murch's
+//! [An Evaluation of Coin Selection Strategies](https://murch.one/wp-content/uploads/2016/11/erhardt2016coinselection.pdf).
+
+use super::{utxo_pool, Algorithm, Candidate, Selection, SelectionError, SelectionParams};
+use crate::prelude::Vec;
+
+/// The maximum number of search steps taken by [`branch_and_bound`].
+pub const BNB_TOTAL_TRIES: usize = 100_000;
+
+/// Selects coins using Branch and Bound.
+///
+/// Searches for an input set whose effective value is at least the target but exceeds it by no
+/// more than `change_cost`, so that the transaction does not need a change output. Of the input
+/// sets found within [`BNB_TOTAL_TRIES`] steps the one with the lowest [`waste`](super::waste) is
+/// returned.
+///
+/// # Errors
+///
+/// * [`SelectionError::InsufficientFunds`] if the candidates cannot fund the target.
+/// * [`SelectionError::MaxWeightExceeded`] if every changeless input set exceeds `max_weight`.
+/// * [`SelectionError::NoSolution`] if no changeless input set was found.
+pub fn branch_and_bound(
+    candidates: &[Candidate],
+    params: &SelectionParams,
+) -> Result<Selection, SelectionError> {
+    let target = params.target.to_sat();
+    let mut pool = utxo_pool(candidates, params, target)?;
+    pool.sort_by_key(|utxo| core::cmp::Reverse(utxo.effective_value));
+
+    let upper_bound = target + params.change_cost.to_sat();
+    let max_weight = params.max_weight.to_wu();
+    let is_fee_rate_high = params.fee_rate > params.long_term_fee_rate;
+
+    // Effective value of the UTXOs not yet included or omitted.
+    let mut available = pool.iter().map(|utxo| utxo.effective_value).sum::<u64>();
+    let mut value = 0;
+    let mut waste = 0;
+    let mut weight = 0;
+    let mut selection: Vec<usize> = Vec::new();
+
+    let mut best = Vec::new();
+    let mut best_waste = i64::MAX;
+    let mut max_weight_exceeded = false;
+
+    let mut index = 0;
+    for _ in 0..BNB_TOTAL_TRIES {
+        let mut backtrack = false;
+        if value + available < target
+            || value > upper_bound
+            || (is_fee_rate_high && waste > best_waste)
+        {
+            // Cannot reach the target, overshot it, or adding more inputs only adds waste.
+            backtrack = true;
+        } else if weight > max_weight {
+            max_weight_exceeded = true;
+            backtrack = true;
+        } else if value >= target {
+            // Found a solution, the excess would go to fees so it counts towards waste.
+            let solution_waste = waste + (value - target) as i64;
+            if solution_waste <= best_waste {
+                best.clone_from(&selection);
+                best_waste = solution_waste;
+            }
+            backtrack = true;
+        }
+
+        if backtrack {
+            let last = match selection.last() {
+                Some(&last) => last,
+                None => break, // The whole tree has been searched.
+            };
+
+            // Add the omitted UTXOs back before walking back to the last inclusion.
+            index -= 1;
+            while index > last {
+                available += pool[index].effective_value;
+                index -= 1;
+            }
+
+            // The last UTXO was included in the previous branch, try omitting it.
+            let utxo = &pool[index];
+            value -= utxo.effective_value;
+            waste -= utxo.waste();
+            weight -= utxo.weight;
+            selection.pop();
+        } else {
+            let utxo = &pool[index];
+            available -= utxo.effective_value;
+
+            // Omitting an equivalent UTXO to the one just omitted searches the same branch again.
+            let is_clone_of_omitted = selection.last().map_or(false, |&last| last + 1 != index)
+                && utxo.effective_value == pool[index - 1].effective_value
+                && utxo.fee == pool[index - 1].fee;
+            if !is_clone_of_omitted {
+                selection.push(index);
+                value += utxo.effective_value;
+                waste += utxo.waste();
+                weight += utxo.weight;
+            }
+        }
+        index += 1;
+    }
+
+    if best.is_empty() {
+        return Err(if max_weight_exceeded {
+            SelectionError::MaxWeightExceeded
+        } else {
+            SelectionError::NoSolution
+        });
+    }
+
+    let indices = best.iter().map(|&i| pool[i].index).collect();
+    Ok(Selection::new(Algorithm::BranchAndBound, indices, candidates, params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coin_selection::tests::{candidate, params};
+    use crate::{Amount, FeeRate};
+
+    fn selected_values(selection: &Selection, values: &[u64]) -> Vec<u64> {
+        selection.indices.iter().map(|&i| values[i]).collect()
+    }
+
+    #[test]
+    fn exact_match() {
+        let values = [1_000, 2_000, 3_000, 4_000];
+        let candidates = values.map(|v| candidate(v, FeeRate::ZERO));
+
+        let selection = branch_and_bound(&candidates, &params(5_000, FeeRate::ZERO)).unwrap();
+        assert_eq!(selection.algorithm, Algorithm::BranchAndBound);
+        assert_eq!(selection.excess, Amount::ZERO);
+        assert_eq!(selected_values(&selection, &values).iter().sum::<u64>(), 5_000);
+    }
+
+    #[test]
+    fn within_cost_of_change() {
+        let values = [1_000, 2_000, 5_000];
+        let candidates = values.map(|v| candidate(v, FeeRate::ZERO));
+
+        let selection = branch_and_bound(&candidates, &params(2_500, FeeRate::ZERO)).unwrap();
+        assert_eq!(selected_values(&selection, &values), [1_000, 2_000]);
+        assert_eq!(selection.excess, Amount::from_sat_u32(500));
+    }
+
+    #[test]
+    fn no_changeless_solution() {
+        let candidates = [candidate(1_000, FeeRate::ZERO), candidate(4_000, FeeRate::ZERO)];
+
+        let err = branch_and_bound(&candidates, &params(2_500, FeeRate::ZERO)).unwrap_err();
+        assert_eq!(err, SelectionError::NoSolution);
+    }
+
+    #[test]
+    fn insufficient_funds() {
+        let candidates = [candidate(1_000, FeeRate::ZERO), candidate(2_000, FeeRate::ZERO)];
+
+        let err = branch_and_bound(&candidates, &params(5_000, FeeRate::ZERO)).unwrap_err();
+        assert!(matches!(err, SelectionError::InsufficientFunds { .. }));
+    }
+
+    #[test]
+    fn prefers_fewer_inputs_at_high_fee_rate() {
+        let fee_rate = FeeRate::from_sat_per_vb(20);
+        let values = [2_500, 2_500, 5_000];
+        let candidates = values.map(|v| candidate(v, fee_rate));
+
+        let selection = branch_and_bound(&candidates, &params(5_000, fee_rate)).unwrap();
+        assert_eq!(selection.indices, [2]);
+    }
+
+    #[test]
+    fn prefers_more_inputs_at_low_fee_rate() {
+        let values = [2_500, 2_500, 5_000];
+        let candidates = values.map(|v| candidate(v, FeeRate::ZERO));
+
+        let selection = branch_and_bound(&candidates, &params(5_000, FeeRate::ZERO)).unwrap();
+        assert_eq!(selection.indices, [0, 1]);
+    }
+
+    #[test]
+    fn max_weight_exceeded() {
+        let candidates = [candidate(2_500, FeeRate::ZERO), candidate(2_500, FeeRate::ZERO)];
+        let mut params = params(5_000, FeeRate::ZERO);
+        params.max_weight = candidates[0].weight_prediction.total_weight();
+
+        let err = branch_and_bound(&candidates, &params).unwrap_err();
+        assert_eq!(err, SelectionError::MaxWeightExceeded);
+    }
+}
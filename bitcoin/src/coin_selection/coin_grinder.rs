@@ -0,0 +1,227 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! CoinGrinder coin selection.
+//!
+//! A depth first search for the lightest input set that funds the target and a change output.
+//! Useful at high fee rates where the weight of the inputs dominates the cost of a transaction.
+
+use super::{utxo_pool, Algorithm, Candidate, Selection, SelectionError, SelectionParams};
+use crate::prelude::Vec;
+
+/// The maximum number of search steps taken by [`coin_grinder`].
+pub const COIN_GRINDER_TOTAL_TRIES: usize = 100_000;
+
+/// Selects coins using CoinGrinder.
+///
+/// Searches for the input set with the lowest weight whose effective value covers the target plus
+/// a change output of at least `min_change` after paying `change_cost`. Ties in weight are broken
+/// in favour of the input set with less excess. If the search is not finished within
+/// [`COIN_GRINDER_TOTAL_TRIES`] steps the best input set found so far is returned.
+///
+/// # Errors
+///
+/// * [`SelectionError::InsufficientFunds`] if the candidates cannot fund the target and change.
+/// * [`SelectionError::MaxWeightExceeded`] if every input set found exceeds `max_weight`.
+/// * [`SelectionError::NoSolution`] if no input set was found within the search limit.
+pub fn coin_grinder(
+    candidates: &[Candidate],
+    params: &SelectionParams,
+) -> Result<Selection, SelectionError> {
+    let target = params.target_with_change();
+    let mut pool = utxo_pool(candidates, params, target)?;
+    // Sort by descending effective value and then by ascending weight, so that of UTXOs with the
+    // same effective value the lightest one is tried first.
+    pool.sort_by(|a, b| {
+        b.effective_value.cmp(&a.effective_value).then_with(|| a.weight.cmp(&b.weight))
+    });
+
+    // The total effective value and the minimum weight of the UTXOs after each index.
+    let len = pool.len();
+    let mut lookahead = vec![0; len];
+    let mut min_tail_weight = vec![u64::MAX; len];
+    for i in (0..len - 1).rev() {
+        lookahead[i] = lookahead[i + 1] + pool[i + 1].effective_value;
+        min_tail_weight[i] = min_tail_weight[i + 1].min(pool[i + 1].weight);
+    }
+
+    let max_weight = params.max_weight.to_wu();
+    let mut value = 0;
+    let mut weight = 0;
+    let mut selection: Vec<usize> = Vec::new();
+
+    let mut best = Vec::new();
+    let mut best_weight = max_weight;
+    let mut best_value = u64::MAX;
+    let mut max_weight_exceeded = false;
+
+    let mut next = 0;
+    let mut tries = 0;
+    'search: loop {
+        let mut should_shift = false;
+        let mut should_cut = false;
+
+        let tail = next;
+        let utxo = &pool[tail];
+        selection.push(tail);
+        value += utxo.effective_value;
+        weight += utxo.weight;
+        next += 1;
+        tries += 1;
+
+        // The tail is the lightest of the remaining UTXOs, any sibling would be at least as heavy.
+        let tail_is_lightest = utxo.weight <= min_tail_weight[tail];
+        if value + lookahead[tail] < target {
+            // The remaining UTXOs cannot reach the target, nor can those of any sibling.
+            should_cut = true;
+        } else if weight > best_weight {
+            if weight > max_weight {
+                max_weight_exceeded = true;
+            }
+            if tail_is_lightest {
+                should_cut = true;
+            } else {
+                should_shift = true;
+            }
+        } else if value >= target {
+            should_shift = true;
+            if weight < best_weight || value < best_value {
+                best.clone_from(&selection);
+                best_weight = weight;
+                best_value = value;
+            }
+        } else if !best.is_empty() {
+            // Estimate the weight of reaching the target using UTXOs worth at most the tail.
+            let missing = (target - value + utxo.effective_value - 1) / utxo.effective_value;
+            let min_weight = weight.saturating_add(min_tail_weight[tail].saturating_mul(missing));
+            if min_weight > best_weight {
+                if tail_is_lightest {
+                    should_cut = true;
+                } else {
+                    should_shift = true;
+                }
+            }
+        }
+
+        if tries >= COIN_GRINDER_TOTAL_TRIES {
+            break;
+        }
+
+        if next == len {
+            // Nothing left to add, the last UTXO can only be omitted.
+            should_cut = true;
+        }
+
+        if should_cut {
+            // Omit the tail and then shift its predecessor.
+            deselect_last(&mut selection, &pool, &mut value, &mut weight);
+            should_shift = true;
+        }
+
+        while should_shift {
+            // Replace the tail with the next UTXO.
+            let last = match selection.last() {
+                Some(&last) => last,
+                None => break 'search, // The whole tree has been searched.
+            };
+            next = last + 1;
+            deselect_last(&mut selection, &pool, &mut value, &mut weight);
+            should_shift = false;
+
+            // Omitting a UTXO and then selecting an identical one searches the same branch again.
+            while pool[next - 1].effective_value == pool[next].effective_value {
+                if next >= len - 1 {
+                    should_shift = true;
+                    break;
+                }
+                next += 1;
+            }
+        }
+    }
+
+    if best.is_empty() {
+        return Err(if max_weight_exceeded {
+            SelectionError::MaxWeightExceeded
+        } else {
+            SelectionError::NoSolution
+        });
+    }
+
+    let indices = best.iter().map(|&i| pool[i].index).collect();
+    Ok(Selection::new(Algorithm::CoinGrinder, indices, candidates, params))
+}
+
+/// Removes the last UTXO from `selection`, updating the running totals.
+fn deselect_last(
+    selection: &mut Vec<usize>,
+    pool: &[super::Utxo],
+    value: &mut u64,
+    weight: &mut u64,
+) {
+    let last = selection.pop().expect("only called on a non-empty selection");
+    *value -= pool[last].effective_value;
+    *weight -= pool[last].weight;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coin_selection::tests::{candidate, params};
+    use crate::transaction::InputWeightPrediction;
+    use crate::{Amount, FeeRate};
+
+    #[test]
+    fn prefers_lighter_inputs() {
+        let heavy = InputWeightPrediction::P2PKH_COMPRESSED_MAX;
+        let light = InputWeightPrediction::P2TR_KEY_DEFAULT_SIGHASH;
+        let candidates = [
+            Candidate::new(Amount::from_sat_u32(70_000), heavy),
+            Candidate::new(Amount::from_sat_u32(40_000), light),
+            Candidate::new(Amount::from_sat_u32(40_000), light),
+        ];
+        let mut params = params(60_000, FeeRate::ZERO);
+        params.min_change = Amount::from_sat_u32(1_000);
+
+        let selection = coin_grinder(&candidates, &params).unwrap();
+        assert_eq!(selection.algorithm, Algorithm::CoinGrinder);
+        assert_eq!(selection.indices, [1, 2]);
+        assert_eq!(selection.weight, light.total_weight() * 2);
+    }
+
+    #[test]
+    fn funds_change() {
+        let fee_rate = FeeRate::from_sat_per_vb(10);
+        let candidates = [candidate(30_000, fee_rate), candidate(45_000, fee_rate)];
+
+        let params = params(20_000, fee_rate);
+        let selection = coin_grinder(&candidates, &params).unwrap();
+        assert_eq!(selection.indices, [0, 1]);
+        assert!(selection.excess >= (params.min_change + params.change_cost).unwrap());
+    }
+
+    #[test]
+    fn prefers_less_excess_at_equal_weight() {
+        let values = [120_000, 80_000, 65_000];
+        let candidates = values.map(|v| candidate(v, FeeRate::ZERO));
+
+        let selection = coin_grinder(&candidates, &params(10_000, FeeRate::ZERO)).unwrap();
+        assert_eq!(selection.indices, [2]);
+    }
+
+    #[test]
+    fn insufficient_funds_for_change() {
+        let candidates = [candidate(30_000, FeeRate::ZERO), candidate(40_000, FeeRate::ZERO)];
+
+        let err = coin_grinder(&candidates, &params(60_000, FeeRate::ZERO)).unwrap_err();
+        assert!(matches!(err, SelectionError::InsufficientFunds { .. }));
+    }
+
+    #[test]
+    fn max_weight_exceeded() {
+        let candidates = [candidate(40_000, FeeRate::ZERO), candidate(40_000, FeeRate::ZERO)];
+        let mut params = params(20_000, FeeRate::ZERO);
+        params.max_weight = candidates[0].weight_prediction.total_weight();
+
+        let err = coin_grinder(&candidates, &params).unwrap_err();
+        assert_eq!(err, SelectionError::MaxWeightExceeded);
+    }
+}
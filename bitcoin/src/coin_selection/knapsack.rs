@@ -0,0 +1,211 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Knapsack coin selection.
+//!
+//! The original Bitcoin Core wallet algorithm. Looks for a single UTXO that matches the target or a
+//! random subset of the smaller UTXOs that gets close to it.
+
+use secp256k1::rand::seq::SliceRandom;
+use secp256k1::rand::Rng;
+
+use super::{utxo_pool, Algorithm, Candidate, Selection, SelectionError, SelectionParams, Utxo};
+use crate::prelude::Vec;
+
+/// The number of random subsets tried by [`knapsack`] for each target.
+pub const KNAPSACK_ITERATIONS: usize = 1_000;
+
+/// Selects coins using the knapsack solver.
+///
+/// UTXOs with an effective value below the target plus a change output of at least `min_change`
+/// after paying `change_cost` are combined into random subsets, searching for the subset closest
+/// to the target, first exactly and then with change. The smallest UTXO larger than that is used
+/// instead if it is closer or no suitable subset is found.
+///
+/// # Errors
+///
+/// * [`SelectionError::InsufficientFunds`] if the candidates cannot fund the target.
+/// * [`SelectionError::MaxWeightExceeded`] if the selection found exceeds `max_weight`.
+pub fn knapsack<R: Rng + ?Sized>(
+    candidates: &[Candidate],
+    params: &SelectionParams,
+    rng: &mut R,
+) -> Result<Selection, SelectionError> {
+    let target = params.target.to_sat();
+    let target_with_change = params.target_with_change();
+    let max_weight = params.max_weight.to_wu();
+    let mut pool = utxo_pool(candidates, params, target)?;
+    pool.shuffle(rng);
+
+    let mut applicable = Vec::new();
+    let mut total_lower = 0;
+    let mut lowest_larger: Option<Utxo> = None;
+    for utxo in pool {
+        if utxo.effective_value == target {
+            return finish(&[utxo], candidates, params);
+        } else if utxo.effective_value < target_with_change {
+            total_lower += utxo.effective_value;
+            applicable.push(utxo);
+        } else if lowest_larger.map_or(true, |larger| utxo.effective_value < larger.effective_value)
+        {
+            lowest_larger = Some(utxo);
+        }
+    }
+
+    if total_lower == target {
+        return finish(&applicable, candidates, params);
+    }
+
+    if total_lower < target {
+        return match lowest_larger {
+            Some(larger) => finish(&[larger], candidates, params),
+            // Unreachable since `utxo_pool` checked the total, kept for robustness.
+            None => Err(SelectionError::NoSolution),
+        };
+    }
+
+    applicable.sort_by_key(|utxo| core::cmp::Reverse(utxo.effective_value));
+    let (mut best, mut best_value) =
+        approximate_best_subset(rng, &applicable, total_lower, target, max_weight);
+    if best_value != target && total_lower >= target_with_change {
+        (best, best_value) =
+            approximate_best_subset(rng, &applicable, total_lower, target_with_change, max_weight);
+    }
+
+    // Use the larger UTXO if no subset reached the target with change, or it is closer.
+    if let Some(larger) = lowest_larger {
+        if (best_value != target && best_value < target_with_change)
+            || larger.effective_value <= best_value
+        {
+            return finish(&[larger], candidates, params);
+        }
+    }
+
+    let selected = applicable
+        .iter()
+        .zip(best)
+        .filter_map(|(utxo, included)| if included { Some(*utxo) } else { None })
+        .collect::<Vec<_>>();
+    finish(&selected, candidates, params)
+}
+
+/// Finds the random subset of `utxos` with the lowest total effective value of at least `target`.
+///
+/// `utxos` must be sorted by descending effective value, `total` is their total effective value.
+/// Returns which UTXOs are included in the best subset and its total effective value.
+fn approximate_best_subset<R: Rng + ?Sized>(
+    rng: &mut R,
+    utxos: &[Utxo],
+    total: u64,
+    target: u64,
+    max_weight: u64,
+) -> (Vec<bool>, u64) {
+    let mut best = vec![true; utxos.len()];
+    let mut best_value = total;
+
+    for _ in 0..KNAPSACK_ITERATIONS {
+        if best_value == target {
+            break;
+        }
+
+        let mut included = vec![false; utxos.len()];
+        let mut value = 0;
+        let mut weight = 0;
+        let mut reached_target = false;
+        // The first pass includes UTXOs at random, the second pass includes the ones left out.
+        for pass in 0..2 {
+            if reached_target {
+                break;
+            }
+            for (i, utxo) in utxos.iter().enumerate() {
+                let include = if pass == 0 { rng.gen::<bool>() } else { !included[i] };
+                if !include {
+                    continue;
+                }
+
+                value += utxo.effective_value;
+                weight += utxo.weight;
+                included[i] = true;
+                if value >= target {
+                    reached_target = true;
+                    if value < best_value && weight <= max_weight {
+                        best.clone_from(&included);
+                        best_value = value;
+                    }
+                    // Try to get closer to the target by leaving this UTXO out.
+                    value -= utxo.effective_value;
+                    weight -= utxo.weight;
+                    included[i] = false;
+                }
+            }
+        }
+    }
+
+    (best, best_value)
+}
+
+/// Builds the selection for `utxos`, checking the maximum weight.
+fn finish(
+    utxos: &[Utxo],
+    candidates: &[Candidate],
+    params: &SelectionParams,
+) -> Result<Selection, SelectionError> {
+    if utxos.iter().map(|utxo| utxo.weight).sum::<u64>() > params.max_weight.to_wu() {
+        return Err(SelectionError::MaxWeightExceeded);
+    }
+
+    let indices = utxos.iter().map(|utxo| utxo.index).collect();
+    Ok(Selection::new(Algorithm::Knapsack, indices, candidates, params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coin_selection::tests::{candidate, params, rng};
+    use crate::{Amount, FeeRate};
+
+    #[test]
+    fn exact_single_match() {
+        let candidates = [1_000, 2_000, 5_000].map(|v| candidate(v, FeeRate::ZERO));
+
+        let selection = knapsack(&candidates, &params(2_000, FeeRate::ZERO), &mut rng()).unwrap();
+        assert_eq!(selection.algorithm, Algorithm::Knapsack);
+        assert_eq!(selection.indices, [1]);
+    }
+
+    #[test]
+    fn all_smaller_match() {
+        let candidates = [1_000, 2_000, 100_000].map(|v| candidate(v, FeeRate::ZERO));
+
+        let selection = knapsack(&candidates, &params(3_000, FeeRate::ZERO), &mut rng()).unwrap();
+        assert_eq!(selection.indices, [0, 1]);
+        assert_eq!(selection.excess, Amount::ZERO);
+    }
+
+    #[test]
+    fn lowest_larger() {
+        let candidates = [1_000, 200_000, 100_000].map(|v| candidate(v, FeeRate::ZERO));
+
+        let selection = knapsack(&candidates, &params(3_000, FeeRate::ZERO), &mut rng()).unwrap();
+        assert_eq!(selection.indices, [2]);
+    }
+
+    #[test]
+    fn subset_reaches_target() {
+        let fee_rate = FeeRate::from_sat_per_vb(2);
+        let candidates =
+            [5_000, 6_000, 7_000, 8_000, 9_000, 10_000].map(|v| candidate(v, fee_rate));
+
+        let params = params(20_000, fee_rate);
+        let selection = knapsack(&candidates, &params, &mut rng()).unwrap();
+        assert!(selection.effective_value >= params.target);
+        assert!(selection.indices.len() >= 2);
+    }
+
+    #[test]
+    fn insufficient_funds() {
+        let candidates = [1_000, 2_000].map(|v| candidate(v, FeeRate::ZERO));
+
+        let err = knapsack(&candidates, &params(5_000, FeeRate::ZERO), &mut rng()).unwrap_err();
+        assert!(matches!(err, SelectionError::InsufficientFunds { .. }));
+    }
+}
@@ -0,0 +1,471 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Coin selection.
+//!
+//! Algorithms for choosing which UTXOs to spend in order to fund a transaction. These follow the
+//! algorithms used by the Bitcoin Core wallet:
+//!
+//! * [`branch_and_bound`] searches for an input set that makes a change output unnecessary.
+//! * [`coin_grinder`] searches for the lightest input set that also funds a change output.
+//! * [`knapsack`] approximates the input set closest to the target by random sampling.
+//! * [`single_random_draw`] adds randomly chosen inputs until the target is reached.
+//!
+//! All algorithms work on the effective value of each candidate, that is its value less the fee
+//! required to spend it. [`select_coins`] runs every applicable algorithm and picks the result
+//! with the lowest [`waste`].
+
+mod bnb;
+mod coin_grinder;
+mod knapsack;
+mod srd;
+
+use core::fmt;
+
+use secp256k1::rand::Rng;
+
+use crate::policy::MAX_STANDARD_TX_WEIGHT;
+use crate::prelude::Vec;
+use crate::transaction::InputWeightPrediction;
+use crate::{Amount, FeeRate, SignedAmount, Weight};
+
+#[rustfmt::skip]                // Keep public re-exports separate.
+#[doc(inline)]
+pub use self::{
+    bnb::{branch_and_bound, BNB_TOTAL_TRIES},
+    coin_grinder::{coin_grinder, COIN_GRINDER_TOTAL_TRIES},
+    knapsack::{knapsack, KNAPSACK_ITERATIONS},
+    srd::single_random_draw,
+};
+
+/// The lower bound for the value of a change output created by coin selection.
+pub const CHANGE_LOWER: Amount = Amount::from_sat_u32(50_000);
+
+/// A UTXO that may be spent to fund a transaction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// The value of the output being spent.
+    pub value: Amount,
+    /// The predicted weight of the input spending this output.
+    pub weight_prediction: InputWeightPrediction,
+}
+
+impl Candidate {
+    /// Constructs a new candidate UTXO.
+    pub const fn new(value: Amount, weight_prediction: InputWeightPrediction) -> Self {
+        Candidate { value, weight_prediction }
+    }
+}
+
+/// Parameters shared by all coin selection algorithms.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SelectionParams {
+    /// The amount the selected inputs must fund.
+    ///
+    /// This is the sum of the payment outputs plus the fee for every part of the transaction
+    /// other than its inputs. The fee for the inputs is accounted for by coin selection.
+    pub target: Amount,
+    /// The fee rate the transaction will be broadcast at.
+    pub fee_rate: FeeRate,
+    /// The fee rate the wallet expects to pay for spending UTXOs in the long run.
+    ///
+    /// Spending more inputs than necessary is wasteful when `fee_rate` is above this value and
+    /// worthwhile consolidation when it is below.
+    pub long_term_fee_rate: FeeRate,
+    /// The cost of change: the fee for adding a change output now plus the fee for spending it
+    /// later.
+    pub change_cost: Amount,
+    /// The smallest change output the algorithms that create change aim for.
+    ///
+    /// Defaults to [`CHANGE_LOWER`].
+    pub min_change: Amount,
+    /// The maximum total weight of the selected inputs.
+    ///
+    /// Defaults to [`MAX_STANDARD_TX_WEIGHT`].
+    pub max_weight: Weight,
+}
+
+impl SelectionParams {
+    /// Constructs new selection parameters with the default minimum change and maximum weight.
+    pub const fn new(
+        target: Amount,
+        fee_rate: FeeRate,
+        long_term_fee_rate: FeeRate,
+        change_cost: Amount,
+    ) -> Self {
+        SelectionParams {
+            target,
+            fee_rate,
+            long_term_fee_rate,
+            change_cost,
+            min_change: CHANGE_LOWER,
+            max_weight: Weight::from_wu(MAX_STANDARD_TX_WEIGHT as u64),
+        }
+    }
+
+    /// The target for algorithms that always create change: `target` plus a change output of at
+    /// least `min_change` after paying `change_cost`.
+    fn target_with_change(&self) -> u64 {
+        self.target.to_sat() + self.min_change.to_sat() + self.change_cost.to_sat()
+    }
+}
+
+/// The coin selection algorithm that produced a [`Selection`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    /// Branch and Bound, see [`branch_and_bound`].
+    BranchAndBound,
+    /// CoinGrinder, see [`coin_grinder`].
+    CoinGrinder,
+    /// Knapsack solver, see [`knapsack`].
+    Knapsack,
+    /// Single Random Draw, see [`single_random_draw`].
+    SingleRandomDraw,
+}
+
+/// The result of a successful coin selection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    /// The algorithm that produced this selection.
+    pub algorithm: Algorithm,
+    /// Indices of the selected candidates, in ascending order.
+    pub indices: Vec<usize>,
+    /// The total effective value of the selected candidates.
+    pub effective_value: Amount,
+    /// The amount by which `effective_value` exceeds the target.
+    ///
+    /// If this is more than the change cost a change output should be added, otherwise the excess
+    /// is left to the fee.
+    pub excess: Amount,
+    /// The total predicted weight of the selected inputs.
+    pub weight: Weight,
+    /// The waste metric of this selection, see [`waste`].
+    pub waste: SignedAmount,
+}
+
+impl Selection {
+    fn new(
+        algorithm: Algorithm,
+        mut indices: Vec<usize>,
+        candidates: &[Candidate],
+        params: &SelectionParams,
+    ) -> Self {
+        indices.sort_unstable();
+
+        let selected = indices.iter().map(|&i| candidates[i]).collect::<Vec<_>>();
+        let mut effective_value = 0;
+        let mut weight = Weight::ZERO;
+        for candidate in &selected {
+            let input_weight = candidate.weight_prediction.total_weight();
+            effective_value +=
+                candidate.value.to_sat() - params.fee_rate.to_fee(input_weight).to_sat();
+            weight += input_weight;
+        }
+
+        Selection {
+            algorithm,
+            indices,
+            effective_value: to_amount(effective_value),
+            excess: to_amount(effective_value.saturating_sub(params.target.to_sat())),
+            weight,
+            waste: waste(&selected, params),
+        }
+    }
+}
+
+/// Returns the waste metric of spending `selected`.
+///
+/// Waste is the cost of spending the inputs at `fee_rate` rather than at `long_term_fee_rate`,
+/// plus either the cost of change if the selection produces change, or the excess that goes to
+/// fees if it does not. A selection is considered to produce change if its excess is more than
+/// `change_cost`. Lower is better.
+///
+/// The result saturates at [`SignedAmount::MIN`] and [`SignedAmount::MAX`].
+pub fn waste(selected: &[Candidate], params: &SelectionParams) -> SignedAmount {
+    let mut waste = 0_i64;
+    let mut effective_value = 0_i64;
+    for candidate in selected {
+        let weight = candidate.weight_prediction.total_weight();
+        let fee = params.fee_rate.to_fee(weight).to_sat() as i64;
+        let long_term_fee = params.long_term_fee_rate.to_fee(weight).to_sat() as i64;
+
+        waste += fee - long_term_fee;
+        effective_value += candidate.value.to_sat() as i64 - fee;
+    }
+
+    let excess = effective_value - params.target.to_sat() as i64;
+    let change_cost = params.change_cost.to_sat() as i64;
+    waste += if excess > change_cost { change_cost } else { excess };
+
+    match SignedAmount::from_sat(waste) {
+        Ok(waste) => waste,
+        Err(_) if waste < 0 => SignedAmount::MIN,
+        Err(_) => SignedAmount::MAX,
+    }
+}
+
+/// Runs every applicable coin selection algorithm and returns the selection with the lowest waste.
+///
+/// CoinGrinder is only run if `fee_rate` is more than three times `long_term_fee_rate`, when
+/// minimizing weight is worth the extra search. Ties in waste are broken in favour of the selection
+/// spending more inputs.
+///
+/// # Errors
+///
+/// If none of the algorithms finds a selection. [`SelectionError::MaxWeightExceeded`] is returned
+/// if any algorithm was only prevented from succeeding by `max_weight`.
+pub fn select_coins<R: Rng + ?Sized>(
+    candidates: &[Candidate],
+    params: &SelectionParams,
+    rng: &mut R,
+) -> Result<Selection, SelectionError> {
+    let mut results = Vec::with_capacity(4);
+    results.push(branch_and_bound(candidates, params));
+    if params.long_term_fee_rate.checked_mul(3).map_or(false, |rate| params.fee_rate > rate) {
+        results.push(coin_grinder(candidates, params));
+    }
+    results.push(knapsack(candidates, params, rng));
+    results.push(single_random_draw(candidates, params, rng));
+
+    let mut best: Option<Selection> = None;
+    let mut error = None;
+    for result in results {
+        match result {
+            Ok(selection) => {
+                let better = best.as_ref().map_or(true, |best| {
+                    (selection.waste, core::cmp::Reverse(selection.indices.len()))
+                        < (best.waste, core::cmp::Reverse(best.indices.len()))
+                });
+                if better {
+                    best = Some(selection);
+                }
+            }
+            Err(SelectionError::MaxWeightExceeded) =>
+                error = Some(SelectionError::MaxWeightExceeded),
+            // Keep the first other error, it is reported against the plain target.
+            Err(e) =>
+                if error.is_none() {
+                    error = Some(e);
+                },
+        }
+    }
+
+    best.ok_or_else(|| error.expect("every algorithm either succeeds or errors"))
+}
+
+/// A candidate prepared for selection.
+#[derive(Debug, Copy, Clone)]
+struct Utxo {
+    /// Index of the candidate this was built from.
+    index: usize,
+    /// Value less the fee to spend at the current fee rate, always positive.
+    effective_value: u64,
+    /// Fee to spend at the current fee rate.
+    fee: u64,
+    /// Fee to spend at the long-term fee rate.
+    long_term_fee: u64,
+    /// Predicted input weight in weight units.
+    weight: u64,
+}
+
+impl Utxo {
+    /// The waste of spending this UTXO now rather than at the long-term fee rate.
+    fn waste(&self) -> i64 { self.fee as i64 - self.long_term_fee as i64 }
+}
+
+/// Prepares the candidates with a positive effective value for selection.
+///
+/// # Errors
+///
+/// If the total effective value of the candidates is less than `target`.
+fn utxo_pool(
+    candidates: &[Candidate],
+    params: &SelectionParams,
+    target: u64,
+) -> Result<Vec<Utxo>, SelectionError> {
+    let mut available = 0;
+    let mut pool = Vec::with_capacity(candidates.len());
+    for (index, candidate) in candidates.iter().enumerate() {
+        let weight = candidate.weight_prediction.total_weight();
+        let fee = params.fee_rate.to_fee(weight).to_sat();
+        let value = candidate.value.to_sat();
+        if value <= fee {
+            continue;
+        }
+
+        let long_term_fee = params.long_term_fee_rate.to_fee(weight).to_sat();
+        available += value - fee;
+        pool.push(Utxo {
+            index,
+            effective_value: value - fee,
+            fee,
+            long_term_fee,
+            weight: weight.to_wu(),
+        });
+    }
+
+    if pool.is_empty() || available < target {
+        return Err(SelectionError::InsufficientFunds {
+            available: to_amount(available),
+            target: to_amount(target),
+        });
+    }
+    Ok(pool)
+}
+
+/// Converts a sum of candidate values to an amount.
+///
+/// Real UTXOs can never be worth more than the total supply so saturating is lossless in practice.
+fn to_amount(sat: u64) -> Amount { Amount::from_sat(sat).unwrap_or(Amount::MAX) }
+
+/// Error returned when coin selection fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SelectionError {
+    /// The candidates do not have enough effective value to fund the target.
+    InsufficientFunds {
+        /// The total effective value of the candidates.
+        available: Amount,
+        /// The value the algorithm needed to select.
+        target: Amount,
+    },
+    /// The algorithm could not find a selection within its search limits.
+    NoSolution,
+    /// Every selection found exceeded the maximum weight.
+    MaxWeightExceeded,
+}
+
+impl fmt::Display for SelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use SelectionError::*;
+
+        match *self {
+            InsufficientFunds { available, target } => write!(
+                f,
+                "insufficient funds: {} available but {} is required",
+                available.display_dynamic(),
+                target.display_dynamic()
+            ),
+            NoSolution => f.write_str("no coin selection solution found"),
+            MaxWeightExceeded => f.write_str("coin selection exceeds the maximum weight"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SelectionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use SelectionError::*;
+
+        match *self {
+            InsufficientFunds { .. } | NoSolution | MaxWeightExceeded => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::rand::rngs::mock::StepRng;
+
+    use super::*;
+
+    const P2WPKH: InputWeightPrediction = InputWeightPrediction::P2WPKH_MAX;
+
+    /// Returns a candidate with the given effective value at `fee_rate`.
+    pub(super) fn candidate(effective_value: u64, fee_rate: FeeRate) -> Candidate {
+        let fee = fee_rate.to_fee(P2WPKH.total_weight());
+        Candidate::new((Amount::from_sat(effective_value).unwrap() + fee).unwrap(), P2WPKH)
+    }
+
+    pub(super) fn params(target: u64, fee_rate: FeeRate) -> SelectionParams {
+        SelectionParams::new(
+            Amount::from_sat(target).unwrap(),
+            fee_rate,
+            FeeRate::from_sat_per_vb(1),
+            Amount::from_sat_u32(1_000),
+        )
+    }
+
+    pub(super) fn rng() -> StepRng { StepRng::new(0x2545_f491_4f6c_dd1d, 0x9e37_79b9_7f4a_7c15) }
+
+    #[test]
+    fn waste_without_change() {
+        let fee_rate = FeeRate::from_sat_per_vb(10);
+        let selected = [candidate(3_000, fee_rate), candidate(2_500, fee_rate)];
+        let params = params(5_000, fee_rate);
+
+        let weight = P2WPKH.total_weight();
+        let per_input = fee_rate.to_fee(weight) - params.long_term_fee_rate.to_fee(weight);
+        let expected = (per_input.unwrap() * 2).unwrap() + Amount::from_sat_u32(500);
+        assert_eq!(waste(&selected, &params), expected.unwrap().to_signed());
+    }
+
+    #[test]
+    fn waste_with_change() {
+        let fee_rate = FeeRate::from_sat_per_vb(10);
+        let selected = [candidate(10_000, fee_rate)];
+        let params = params(5_000, fee_rate);
+
+        let weight = P2WPKH.total_weight();
+        let per_input = fee_rate.to_fee(weight) - params.long_term_fee_rate.to_fee(weight);
+        let expected = per_input.unwrap() + params.change_cost;
+        assert_eq!(waste(&selected, &params), expected.unwrap().to_signed());
+    }
+
+    #[test]
+    fn waste_negative_below_long_term_fee_rate() {
+        let selected = [candidate(5_000, FeeRate::ZERO)];
+        let params = params(5_000, FeeRate::ZERO);
+        assert!(waste(&selected, &params).is_negative());
+    }
+
+    #[test]
+    fn select_coins_prefers_changeless() {
+        let fee_rate = FeeRate::from_sat_per_vb(5);
+        let candidates = [
+            candidate(100_000, fee_rate),
+            candidate(30_000, fee_rate),
+            candidate(20_000, fee_rate),
+            candidate(250_000, fee_rate),
+        ];
+        let params = params(50_000, fee_rate);
+
+        let selection = select_coins(&candidates, &params, &mut rng()).unwrap();
+        assert_eq!(selection.algorithm, Algorithm::BranchAndBound);
+        assert_eq!(selection.indices, [1, 2]);
+        assert_eq!(selection.excess, Amount::ZERO);
+    }
+
+    #[test]
+    fn select_coins_with_change() {
+        let fee_rate = FeeRate::from_sat_per_vb(5);
+        let candidates = [candidate(100_000, fee_rate), candidate(250_000, fee_rate)];
+        let params = params(40_000, fee_rate);
+
+        let selection = select_coins(&candidates, &params, &mut rng()).unwrap();
+        assert!(selection.excess >= (params.min_change + params.change_cost).unwrap());
+        let selected = selection.indices.iter().map(|&i| candidates[i]).collect::<Vec<_>>();
+        assert_eq!(selection.waste, waste(&selected, &params));
+    }
+
+    #[test]
+    fn select_coins_insufficient_funds() {
+        let fee_rate = FeeRate::from_sat_per_vb(5);
+        let candidates = [candidate(10_000, fee_rate), candidate(20_000, fee_rate)];
+        let params = params(50_000, fee_rate);
+
+        let err = select_coins(&candidates, &params, &mut rng()).unwrap_err();
+        assert!(matches!(err, SelectionError::InsufficientFunds { .. }));
+    }
+
+    #[test]
+    fn uneconomical_candidates_are_ignored() {
+        let fee_rate = FeeRate::from_sat_per_vb(100);
+        let fee = fee_rate.to_fee(P2WPKH.total_weight());
+        let candidates = [Candidate::new(fee, P2WPKH), candidate(60_000, fee_rate)];
+        let params = params(60_000, fee_rate);
+
+        let selection = branch_and_bound(&candidates, &params).unwrap();
+        assert_eq!(selection.indices, [1]);
+    }
+}
@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Single Random Draw coin selection.
+
+use core::cmp::Reverse;
+
+use secp256k1::rand::seq::SliceRandom;
+use secp256k1::rand::Rng;
+
+use super::{utxo_pool, Algorithm, Candidate, Selection, SelectionError, SelectionParams};
+use crate::prelude::BinaryHeap;
+
+/// Selects coins using Single Random Draw.
+///
+/// Adds UTXOs in random order until their effective value covers the target plus a change output
+/// of at least `min_change` after paying `change_cost`. If the inputs exceed `max_weight` the
+/// UTXOs with the lowest effective value are dropped again.
+///
+/// # Errors
+///
+/// * [`SelectionError::InsufficientFunds`] if the candidates cannot fund the target and change.
+/// * [`SelectionError::MaxWeightExceeded`] if the target cannot be reached within `max_weight`.
+pub fn single_random_draw<R: Rng + ?Sized>(
+    candidates: &[Candidate],
+    params: &SelectionParams,
+    rng: &mut R,
+) -> Result<Selection, SelectionError> {
+    let target = params.target_with_change();
+    let max_weight = params.max_weight.to_wu();
+    let mut pool = utxo_pool(candidates, params, target)?;
+    pool.shuffle(rng);
+
+    // Min-heap of the selected UTXOs by effective value.
+    let mut selected = BinaryHeap::new();
+    let mut value = 0;
+    let mut weight = 0;
+    for (position, utxo) in pool.iter().enumerate() {
+        selected.push(Reverse((utxo.effective_value, position)));
+        value += utxo.effective_value;
+        weight += utxo.weight;
+
+        while weight > max_weight {
+            let Reverse((_, position)) =
+                selected.pop().expect("weight is non-zero so a UTXO is selected");
+            value -= pool[position].effective_value;
+            weight -= pool[position].weight;
+        }
+
+        if value >= target {
+            let indices =
+                selected.into_iter().map(|Reverse((_, position))| pool[position].index).collect();
+            return Ok(Selection::new(Algorithm::SingleRandomDraw, indices, candidates, params));
+        }
+    }
+
+    // The pool has enough value so the target can only have been missed due to weight.
+    Err(SelectionError::MaxWeightExceeded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coin_selection::tests::{candidate, params, rng};
+    use crate::FeeRate;
+
+    #[test]
+    fn funds_target_and_change() {
+        let fee_rate = FeeRate::from_sat_per_vb(3);
+        let candidates =
+            [10_000, 20_000, 30_000, 40_000, 50_000, 60_000].map(|v| candidate(v, fee_rate));
+
+        let params = params(50_000, fee_rate);
+        let selection = single_random_draw(&candidates, &params, &mut rng()).unwrap();
+        assert_eq!(selection.algorithm, Algorithm::SingleRandomDraw);
+        let min_excess = (params.min_change + params.change_cost).unwrap();
+        assert!(selection.excess >= min_excess);
+    }
+
+    #[test]
+    fn drops_smallest_when_over_weight() {
+        let candidates = [10_000, 30_000, 35_000].map(|v| candidate(v, FeeRate::ZERO));
+        let mut params = params(50_000, FeeRate::ZERO);
+        params.min_change = crate::Amount::ZERO;
+        params.max_weight = candidates[0].weight_prediction.total_weight() * 2;
+
+        let selection = single_random_draw(&candidates, &params, &mut rng()).unwrap();
+        assert_eq!(selection.indices, [1, 2]);
+    }
+
+    #[test]
+    fn max_weight_exceeded() {
+        let candidates = [40_000, 40_000].map(|v| candidate(v, FeeRate::ZERO));
+        let mut params = params(20_000, FeeRate::ZERO);
+        params.max_weight = candidates[0].weight_prediction.total_weight();
+
+        let err = single_random_draw(&candidates, &params, &mut rng()).unwrap_err();
+        assert_eq!(err, SelectionError::MaxWeightExceeded);
+    }
+}
@@ -823,10 +823,32 @@ pub fn segwit_v0_encode_signing_data_to<W: Write + ?Sized>(
         script_code: &WitnessScript,
         value: Amount,
         sighash_type: EcdsaSighashType,
+    ) -> Result<(), SigningDataError<transaction::InputsIndexError>> {
+        self.segwit_v0_encode_signing_data_consensus_to(
+            writer,
+            input_index,
+            script_code,
+            value,
+            sighash_type.to_u32(),
+        )
+    }
+
+    /// Encodes the BIP-0143 signing data for a raw `u32` sighash flag.
+    ///
+    /// The flag is interpreted as in [`EcdsaSighashType::from_consensus`] but committed to as is,
+    /// which is what consensus requires for signatures with non-standard sighash flags.
+    fn segwit_v0_encode_signing_data_consensus_to<W: Write + ?Sized>(
+        &mut self,
+        writer: &mut W,
+        input_index: usize,
+        script_code: &WitnessScript,
+        value: Amount,
+        sighash_type: u32,
     ) -> Result<(), SigningDataError<transaction::InputsIndexError>> {
         let zero_hash = [0; 32];
 
-        let (sighash, anyone_can_pay) = sighash_type.split_anyonecanpay_flag();
+        let (sighash, anyone_can_pay) =
+            EcdsaSighashType::from_consensus(sighash_type).split_anyonecanpay_flag();
 
         self.tx.borrow().version.consensus_encode(writer)?;
 
@@ -867,7 +889,7 @@ pub fn segwit_v0_encode_signing_data_to<W: Write + ?Sized>(
         }
 
         self.tx.borrow().lock_time.consensus_encode(writer)?;
-        sighash_type.to_u32().consensus_encode(writer)?;
+        sighash_type.consensus_encode(writer)?;
         Ok(())
     }
 
@@ -919,6 +941,28 @@ pub fn p2wsh_signature_hash(
         Ok(SegwitV0Sighash::from_engine(enc))
     }
 
+    /// Computes the BIP-0143 sighash for a raw `u32` sighash flag.
+    ///
+    /// Used when verifying signatures, see [`Self::segwit_v0_encode_signing_data_consensus_to`].
+    pub(crate) fn segwit_v0_signature_hash_consensus(
+        &mut self,
+        input_index: usize,
+        script_code: &WitnessScript,
+        value: Amount,
+        sighash_type: u32,
+    ) -> Result<SegwitV0Sighash, transaction::InputsIndexError> {
+        let mut enc = SegwitV0Sighash::engine();
+        self.segwit_v0_encode_signing_data_consensus_to(
+            &mut enc,
+            input_index,
+            script_code,
+            value,
+            sighash_type,
+        )
+        .map_err(SigningDataError::unwrap_sighash)?;
+        Ok(SegwitV0Sighash::from_engine(enc))
+    }
+
     /// Encodes the legacy signing data from which a signature hash for a given input index with a
     /// given sighash flag can be computed.
     ///
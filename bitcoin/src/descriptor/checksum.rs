@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Descriptor checksum.
+//!
+//! Implementation of the descriptor checksum defined in BIP-0380, an eight character BCH code
+//! appended to a descriptor after a `#`.
+
+use core::fmt;
+
+/// The characters that may appear in a descriptor, in the order used by the checksum.
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+
+/// The characters used to encode the checksum.
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// The length of a descriptor checksum.
+pub const CHECKSUM_LENGTH: usize = 8;
+
+fn poly_mod(mut c: u64, value: u64) -> u64 {
+    let c0 = c >> 35;
+    c = ((c & 0x7_ffff_ffff) << 5) ^ value;
+    if c0 & 1 != 0 {
+        c ^= 0xf5_dee5_1989;
+    }
+    if c0 & 2 != 0 {
+        c ^= 0xa9_fdca_3312;
+    }
+    if c0 & 4 != 0 {
+        c ^= 0x1b_ab10_e32d;
+    }
+    if c0 & 8 != 0 {
+        c ^= 0x37_06b1_677a;
+    }
+    if c0 & 16 != 0 {
+        c ^= 0x64_4d62_6ffd;
+    }
+    c
+}
+
+/// Computes the checksum of `descriptor`, which must not include a checksum.
+///
+/// # Errors
+///
+/// If `descriptor` contains a character not allowed in descriptors.
+pub fn checksum(descriptor: &str) -> Result<[char; CHECKSUM_LENGTH], ChecksumError> {
+    let mut c = 1;
+    let mut class = 0;
+    let mut class_count = 0;
+    for ch in descriptor.chars() {
+        let pos = INPUT_CHARSET.find(ch).ok_or(ChecksumError::InvalidCharacter(ch))? as u64;
+        c = poly_mod(c, pos & 31);
+        class = class * 3 + (pos >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = poly_mod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = poly_mod(c, class);
+    }
+    for _ in 0..CHECKSUM_LENGTH {
+        c = poly_mod(c, 0);
+    }
+    c ^= 1;
+
+    let mut ret = ['\0'; CHECKSUM_LENGTH];
+    for (j, ch) in ret.iter_mut().enumerate() {
+        *ch = CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize].into();
+    }
+    Ok(ret)
+}
+
+/// Splits a checksum off `s` and verifies it, returning the descriptor without the checksum.
+///
+/// The checksum is optional, a descriptor without a `#` is returned unchanged.
+///
+/// # Errors
+///
+/// If the descriptor contains a character not allowed in descriptors, or the checksum is present
+/// but malformed or does not match.
+pub fn verify_checksum(s: &str) -> Result<&str, ChecksumError> {
+    let (descriptor, expected) = match s.rfind('#') {
+        Some(pos) => (&s[..pos], Some(&s[pos + 1..])),
+        None => (s, None),
+    };
+
+    let computed = checksum(descriptor)?;
+    if let Some(expected) = expected {
+        if expected.chars().count() != CHECKSUM_LENGTH {
+            return Err(ChecksumError::InvalidLength(expected.chars().count()));
+        }
+        if !expected.chars().eq(computed.iter().copied()) {
+            return Err(ChecksumError::Mismatch(computed));
+        }
+    }
+    Ok(descriptor)
+}
+
+/// Error verifying a descriptor checksum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ChecksumError {
+    /// The descriptor contains a character not allowed in descriptors.
+    InvalidCharacter(char),
+    /// The checksum is not eight characters long.
+    InvalidLength(usize),
+    /// The checksum does not match the descriptor, contains the computed checksum.
+    Mismatch([char; CHECKSUM_LENGTH]),
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ChecksumError::*;
+
+        match *self {
+            InvalidCharacter(ch) => write!(f, "invalid character in descriptor: {:?}", ch),
+            InvalidLength(len) =>
+                write!(f, "checksum has length {}, expected {}", len, CHECKSUM_LENGTH),
+            Mismatch(ref computed) => {
+                f.write_str("checksum does not match, expected ")?;
+                computed.iter().try_for_each(|ch| write!(f, "{}", ch))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ChecksumError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ChecksumError::*;
+
+        match *self {
+            InvalidCharacter(_) | InvalidLength(_) | Mismatch(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test vectors from BIP-0380.
+    #[test]
+    fn bip380_vectors() {
+        assert_eq!(verify_checksum("raw(deadbeef)#89f8spxm"), Ok("raw(deadbeef)"));
+        assert_eq!(verify_checksum("raw(deadbeef)"), Ok("raw(deadbeef)"));
+
+        assert_eq!(verify_checksum("raw(deadbeef)#"), Err(ChecksumError::InvalidLength(0)));
+        assert_eq!(
+            verify_checksum("raw(deadbeef)#89f8spxmx"),
+            Err(ChecksumError::InvalidLength(9))
+        );
+        assert_eq!(verify_checksum("raw(deadbeef)#89f8spx"), Err(ChecksumError::InvalidLength(7)));
+        assert!(matches!(
+            verify_checksum("raw(deedbeef)#89f8spxm"),
+            Err(ChecksumError::Mismatch(_))
+        ));
+        assert!(verify_checksum("raw(deadbeef)##9f8spxm").is_err());
+        assert_eq!(verify_checksum("raw(Ü)#00000000"), Err(ChecksumError::InvalidCharacter('Ü')));
+    }
+
+    #[test]
+    fn checksum_chars() {
+        let computed = checksum("raw(deadbeef)").unwrap();
+        assert_eq!(computed.iter().collect::<crate::prelude::String>(), "89f8spxm");
+    }
+}
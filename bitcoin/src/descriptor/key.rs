@@ -0,0 +1,418 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Descriptor key expressions.
+//!
+//! Implementation of the `KEY` expressions defined in BIP-0380, including key origins, extended
+//! public keys with derivation paths and wildcards, and the multipath `<a;b>` syntax of BIP-0389.
+
+use core::fmt;
+use core::str::FromStr;
+
+use internals::write_err;
+use secp256k1::{Secp256k1, Verification};
+
+use super::DeriveError;
+use crate::bip32::{self, ChildNumber, DerivationPath, Fingerprint, KeySource, Xpriv, Xpub};
+use crate::key::{
+    ParsePublicKeyError, ParseXOnlyPublicKeyError, PrivateKey, PublicKey, XOnlyPublicKey,
+};
+use crate::prelude::Vec;
+
+/// A public key in a descriptor.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DescriptorPublicKey {
+    origin: Option<KeySource>,
+    key: KeyKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum KeyKind {
+    /// A hex encoded public key.
+    Single(SinglePubKey),
+    /// An extended public key with a derivation path.
+    Extended {
+        xpub: Xpub,
+        /// The derivation steps, excluding any multipath step.
+        path: DerivationPath,
+        /// The position in `path` and alternatives of the multipath step, if any.
+        multipath: Option<(usize, Vec<ChildNumber>)>,
+        /// Whether the path ends in an unhardened wildcard `/*`.
+        wildcard: bool,
+    },
+}
+
+/// A single public key, as it appears in a descriptor or once derived.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(super) enum SinglePubKey {
+    /// A compressed or uncompressed public key.
+    Full(PublicKey),
+    /// An x-only public key, only valid in taproot descriptors.
+    XOnly(XOnlyPublicKey),
+}
+
+impl SinglePubKey {
+    /// Returns the full public key.
+    ///
+    /// # Panics
+    ///
+    /// If the key is x-only, which the descriptor parser only accepts in taproot contexts.
+    pub(super) fn to_full(self) -> PublicKey {
+        match self {
+            SinglePubKey::Full(pk) => pk,
+            SinglePubKey::XOnly(_) => panic!("x-only keys are only parsed in taproot contexts"),
+        }
+    }
+
+    /// Returns the x-only public key.
+    pub(super) fn to_x_only(self) -> XOnlyPublicKey {
+        match self {
+            SinglePubKey::Full(pk) => XOnlyPublicKey::from(pk.inner),
+            SinglePubKey::XOnly(pk) => pk,
+        }
+    }
+}
+
+impl DescriptorPublicKey {
+    /// Returns the origin of the key, the master key fingerprint and derivation path to the key.
+    pub fn origin(&self) -> Option<&KeySource> { self.origin.as_ref() }
+
+    /// Returns true if the key is an extended key whose derivation path ends in a wildcard.
+    pub fn has_wildcard(&self) -> bool {
+        matches!(self.key, KeyKind::Extended { wildcard: true, .. })
+    }
+
+    /// Returns true if the key is an extended key with a multipath derivation step.
+    pub fn is_multipath(&self) -> bool { self.multipath_len().is_some() }
+
+    /// Returns the number of alternatives of the multipath step, if any.
+    pub(super) fn multipath_len(&self) -> Option<usize> {
+        match self.key {
+            KeyKind::Extended { multipath: Some((_, ref alternatives)), .. } =>
+                Some(alternatives.len()),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the key is an uncompressed hex encoded public key.
+    pub(super) fn is_uncompressed(&self) -> bool {
+        matches!(self.key, KeyKind::Single(SinglePubKey::Full(pk)) if !pk.compressed)
+    }
+
+    /// Returns true if the key is an x-only hex encoded public key.
+    pub(super) fn is_x_only(&self) -> bool {
+        matches!(self.key, KeyKind::Single(SinglePubKey::XOnly(_)))
+    }
+
+    /// Returns the key with the multipath step replaced by its alternative at `index`.
+    ///
+    /// Keys without a multipath step are returned unchanged.
+    pub(super) fn at_multipath_index(&self, index: usize) -> DescriptorPublicKey {
+        match self.key {
+            KeyKind::Extended {
+                xpub,
+                ref path,
+                multipath: Some((pos, ref alternatives)),
+                wildcard,
+            } => {
+                let mut steps = path.as_ref().to_vec();
+                steps.insert(pos, alternatives[index]);
+                DescriptorPublicKey {
+                    origin: self.origin.clone(),
+                    key: KeyKind::Extended { xpub, path: steps.into(), multipath: None, wildcard },
+                }
+            }
+            _ => self.clone(),
+        }
+    }
+
+    /// Derives the public key at `index`, which is only used if the key has a wildcard.
+    pub(super) fn derive<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<SinglePubKey, DeriveError> {
+        match self.key {
+            KeyKind::Single(pk) => Ok(pk),
+            KeyKind::Extended { multipath: Some(_), .. } => Err(DeriveError::Multipath),
+            KeyKind::Extended { xpub, ref path, multipath: None, wildcard } => {
+                let mut xpub = xpub.derive_xpub(secp, path).map_err(DeriveError::Derivation)?;
+                if wildcard {
+                    let child = ChildNumber::from_normal_idx(index)
+                        .map_err(DeriveError::IndexOutOfRange)?;
+                    xpub = xpub.ckd_pub(secp, child).map_err(DeriveError::Derivation)?;
+                }
+                Ok(SinglePubKey::Full(PublicKey::new(xpub.public_key)))
+            }
+        }
+    }
+}
+
+impl fmt::Display for DescriptorPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((fingerprint, ref path)) = self.origin {
+            write!(f, "[{}", fingerprint)?;
+            for child in path {
+                write!(f, "/{:#}", child)?;
+            }
+            f.write_str("]")?;
+        }
+
+        match self.key {
+            KeyKind::Single(SinglePubKey::Full(pk)) => write!(f, "{}", pk),
+            KeyKind::Single(SinglePubKey::XOnly(pk)) => write!(f, "{}", pk),
+            KeyKind::Extended { xpub, ref path, ref multipath, wildcard } => {
+                write!(f, "{}", xpub)?;
+                let steps = path.as_ref();
+                for i in 0..=steps.len() {
+                    if let Some((pos, ref alternatives)) = *multipath {
+                        if pos == i {
+                            f.write_str("/<")?;
+                            for (j, child) in alternatives.iter().enumerate() {
+                                if j > 0 {
+                                    f.write_str(";")?;
+                                }
+                                write!(f, "{}", child)?;
+                            }
+                            f.write_str(">")?;
+                        }
+                    }
+                    if let Some(child) = steps.get(i) {
+                        write!(f, "/{}", child)?;
+                    }
+                }
+                if wildcard {
+                    f.write_str("/*")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for DescriptorPublicKey {
+    type Err = KeyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (origin, key) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let end = rest.find(']').ok_or(KeyParseError::MalformedOrigin)?;
+                (Some(parse_origin(&rest[..end])?), &rest[end + 1..])
+            }
+            None => (None, s),
+        };
+
+        let mut steps = key.split('/');
+        let encoded = steps.next().expect("split always yields at least one item");
+        let is_hex = encoded.chars().all(|c| c.is_ascii_hexdigit());
+        if is_hex && key.len() == 64 {
+            let pk = XOnlyPublicKey::from_str(key).map_err(KeyParseError::XOnlyPublicKey)?;
+            return Ok(DescriptorPublicKey {
+                origin,
+                key: KeyKind::Single(SinglePubKey::XOnly(pk)),
+            });
+        }
+        if is_hex && !key.contains('/') {
+            let pk = PublicKey::from_str(key).map_err(KeyParseError::PublicKey)?;
+            return Ok(DescriptorPublicKey {
+                origin,
+                key: KeyKind::Single(SinglePubKey::Full(pk)),
+            });
+        }
+
+        let xpub = match Xpub::from_str(encoded) {
+            Ok(xpub) => xpub,
+            Err(_) if Xpriv::from_str(encoded).is_ok() || PrivateKey::from_wif(encoded).is_ok() =>
+                return Err(KeyParseError::PrivateKey),
+            Err(e) => return Err(KeyParseError::Xpub(e)),
+        };
+
+        let mut path = Vec::new();
+        let mut multipath = None;
+        let mut wildcard = false;
+        while let Some(step) = steps.next() {
+            if step == "*" {
+                if steps.next().is_some() {
+                    return Err(KeyParseError::WildcardNotLast);
+                }
+                wildcard = true;
+            } else if step == "*'" || step == "*h" {
+                return Err(KeyParseError::HardenedDerivation);
+            } else if let Some(inner) = step.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+                if multipath.is_some() {
+                    return Err(KeyParseError::MultipleMultipath);
+                }
+                let alternatives =
+                    inner.split(';').map(parse_unhardened).collect::<Result<Vec<_>, _>>()?;
+                let has_duplicates = alternatives
+                    .iter()
+                    .enumerate()
+                    .any(|(i, child)| alternatives[..i].contains(child));
+                if alternatives.len() < 2 || has_duplicates {
+                    return Err(KeyParseError::MalformedMultipath);
+                }
+                multipath = Some((path.len(), alternatives));
+            } else {
+                path.push(parse_unhardened(step)?);
+            }
+        }
+
+        let key = KeyKind::Extended { xpub, path: path.into(), multipath, wildcard };
+        Ok(DescriptorPublicKey { origin, key })
+    }
+}
+
+/// Parses the contents of a key origin, `fingerprint/path` without the brackets.
+fn parse_origin(s: &str) -> Result<KeySource, KeyParseError> {
+    let mut parts = s.split('/');
+    let fingerprint = parts.next().expect("split always yields at least one item");
+    if fingerprint.len() != 8 {
+        return Err(KeyParseError::MalformedOrigin);
+    }
+    let fingerprint =
+        Fingerprint::from_str(fingerprint).map_err(|_| KeyParseError::MalformedOrigin)?;
+    let path = parts
+        .map(ChildNumber::from_str)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(KeyParseError::ChildNumber)?;
+    Ok((fingerprint, path.into()))
+}
+
+/// Parses a derivation step after an extended public key, which must not be hardened.
+fn parse_unhardened(s: &str) -> Result<ChildNumber, KeyParseError> {
+    let child = ChildNumber::from_str(s).map_err(KeyParseError::ChildNumber)?;
+    if child.is_hardened() {
+        return Err(KeyParseError::HardenedDerivation);
+    }
+    Ok(child)
+}
+
+/// Error parsing a descriptor key expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum KeyParseError {
+    /// The key origin is not of the form `[fingerprint/path]`.
+    MalformedOrigin,
+    /// Invalid derivation step.
+    ChildNumber(bip32::ParseChildNumberError),
+    /// Invalid hex encoded public key.
+    PublicKey(ParsePublicKeyError),
+    /// Invalid hex encoded x-only public key.
+    XOnlyPublicKey(ParseXOnlyPublicKeyError),
+    /// Invalid extended public key.
+    Xpub(bip32::ParseError),
+    /// Private keys are not supported.
+    PrivateKey,
+    /// Hardened derivation from an extended public key is not possible.
+    HardenedDerivation,
+    /// A wildcard is only allowed as the last derivation step.
+    WildcardNotLast,
+    /// A multipath step must have at least two distinct alternatives.
+    MalformedMultipath,
+    /// Only one multipath step is allowed per key.
+    MultipleMultipath,
+}
+
+impl fmt::Display for KeyParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use KeyParseError::*;
+
+        match *self {
+            MalformedOrigin => f.write_str("key origin is not of the form [fingerprint/path]"),
+            ChildNumber(ref e) => write_err!(f, "invalid derivation step"; e),
+            PublicKey(ref e) => write_err!(f, "invalid public key"; e),
+            XOnlyPublicKey(ref e) => write_err!(f, "invalid x-only public key"; e),
+            Xpub(ref e) => write_err!(f, "invalid extended public key"; e),
+            PrivateKey => f.write_str("private keys are not supported in descriptors"),
+            HardenedDerivation =>
+                f.write_str("hardened derivation from an extended public key is not possible"),
+            WildcardNotLast => f.write_str("wildcard is not the last derivation step"),
+            MalformedMultipath =>
+                f.write_str("multipath step must have at least two distinct unhardened steps"),
+            MultipleMultipath => f.write_str("key has more than one multipath step"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for KeyParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use KeyParseError::*;
+
+        match *self {
+            ChildNumber(ref e) => Some(e),
+            PublicKey(ref e) => Some(e),
+            XOnlyPublicKey(ref e) => Some(e),
+            Xpub(ref e) => Some(e),
+            MalformedOrigin | PrivateKey | HardenedDerivation | WildcardNotLast
+            | MalformedMultipath | MultipleMultipath => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XPUB: &str = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+
+    #[test]
+    fn single_keys() {
+        let compressed = "0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c";
+        let key = compressed.parse::<DescriptorPublicKey>().unwrap();
+        assert_eq!(key.to_string(), compressed);
+        assert!(!key.is_uncompressed());
+
+        let x_only = "30d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c";
+        let key = x_only.parse::<DescriptorPublicKey>().unwrap();
+        assert!(key.is_x_only());
+        assert_eq!(key.to_string(), x_only);
+
+        let with_origin = "[d34db33f/44h/0h/0h]0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c";
+        let key = with_origin.parse::<DescriptorPublicKey>().unwrap();
+        assert_eq!(key.origin().unwrap().0, "d34db33f".parse::<Fingerprint>().unwrap());
+        assert_eq!(key.to_string(), with_origin);
+    }
+
+    #[test]
+    fn extended_keys() {
+        let s = format!("[73c5da0a/86h/0h/0h]{}/0/*", XPUB);
+        let key = s.parse::<DescriptorPublicKey>().unwrap();
+        assert!(key.has_wildcard());
+        assert!(!key.is_multipath());
+        assert_eq!(key.to_string(), s);
+
+        // Apostrophes are accepted but displayed as `h`.
+        let key = format!("[73c5da0a/86'/0'/0']{}/1", XPUB).parse::<DescriptorPublicKey>().unwrap();
+        assert_eq!(key.to_string(), format!("[73c5da0a/86h/0h/0h]{}/1", XPUB));
+    }
+
+    #[test]
+    fn multipath_keys() {
+        let s = format!("{}/<0;1>/*", XPUB);
+        let key = s.parse::<DescriptorPublicKey>().unwrap();
+        assert_eq!(key.multipath_len(), Some(2));
+        assert_eq!(key.to_string(), s);
+        assert_eq!(key.at_multipath_index(1).to_string(), format!("{}/1/*", XPUB));
+
+        let s = format!("{}/7/<0;1;2>", XPUB);
+        assert_eq!(s.parse::<DescriptorPublicKey>().unwrap().to_string(), s);
+    }
+
+    #[test]
+    fn invalid_keys() {
+        let parse = |s: &str| s.parse::<DescriptorPublicKey>().unwrap_err();
+
+        assert_eq!(parse(&format!("{}/1h/*", XPUB)), KeyParseError::HardenedDerivation);
+        assert_eq!(parse(&format!("{}/*h", XPUB)), KeyParseError::HardenedDerivation);
+        assert_eq!(parse(&format!("{}/*/0", XPUB)), KeyParseError::WildcardNotLast);
+        assert_eq!(parse(&format!("{}/<0;0>", XPUB)), KeyParseError::MalformedMultipath);
+        assert_eq!(parse(&format!("{}/<0>", XPUB)), KeyParseError::MalformedMultipath);
+        assert_eq!(parse(&format!("{}/<0;1>/<2;3>", XPUB)), KeyParseError::MultipleMultipath);
+        assert_eq!(parse(&format!("[d34db33f{}", XPUB)), KeyParseError::MalformedOrigin);
+        assert_eq!(parse(&format!("[d34d/0]{}", XPUB)), KeyParseError::MalformedOrigin);
+        assert_eq!(
+            parse("L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1"),
+            KeyParseError::PrivateKey
+        );
+    }
+}
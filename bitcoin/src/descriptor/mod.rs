@@ -0,0 +1,1018 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Output script descriptors.
+//!
+//! Implementation of the output script descriptors defined in BIP-0380 through BIP-0386, without
+//! miniscript. Supports `pk`, `pkh`, `wpkh`, `sh`, `wsh`, `multi`, `sortedmulti`, `tr` with script
+//! trees of `pk`, `multi_a` and `sortedmulti_a` leaves, `addr` and `raw`.
+//!
+//! # Examples
+//!
+//! ```
+//! use bitcoin::descriptor::Descriptor;
+//! use bitcoin::secp256k1::Secp256k1;
+//! use bitcoin::Network;
+//!
+//! let secp = Secp256k1::verification_only();
+//! let descriptor = "tr([73c5da0a/86h/0h/0h]xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/*)"
+//!     .parse::<Descriptor>()
+//!     .unwrap();
+//! let address = descriptor.address_at(&secp, 0, Network::Bitcoin).unwrap();
+//! assert_eq!(address.to_string(), "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr");
+//! ```
+
+mod checksum;
+mod key;
+
+use core::fmt;
+use core::str::FromStr;
+
+use internals::write_err;
+use secp256k1::{Secp256k1, Verification};
+
+use self::key::SinglePubKey;
+use crate::address::{self, Address, NetworkUnchecked};
+use crate::bip32;
+use crate::network::Network;
+use crate::opcodes::all::*;
+use crate::prelude::{Box, DisplayHex, ToString, Vec};
+use crate::script::{
+    Builder, RedeemScriptBuf, ScriptBuf, ScriptBufExt as _, ScriptExt as _, ScriptPubKeyBuf,
+    ScriptPubKeyBufExt as _, TapScriptBuf, WitnessScriptBuf, WitnessScriptExt as _,
+};
+use crate::taproot::{TaprootBuilder, TAPROOT_CONTROL_MAX_NODE_COUNT};
+
+#[rustfmt::skip]                // Keep public re-exports separate.
+#[doc(inline)]
+pub use self::{
+    checksum::{checksum, verify_checksum, ChecksumError, CHECKSUM_LENGTH},
+    key::{DescriptorPublicKey, KeyParseError},
+};
+
+/// The maximum number of keys in a bare `multi` descriptor.
+const MAX_BARE_MULTI_KEYS: usize = 3;
+
+/// The maximum number of keys in a `multi` descriptor inside `sh`.
+const MAX_P2SH_MULTI_KEYS: usize = 15;
+
+/// The maximum number of keys in a `multi` descriptor inside `wsh`.
+const MAX_P2WSH_MULTI_KEYS: usize = 20;
+
+/// The maximum number of keys in a `multi_a` descriptor.
+const MAX_MULTI_A_KEYS: usize = 999;
+
+/// An output script descriptor.
+///
+/// Parsing accepts descriptors with or without a checksum, [`fmt::Display`] always appends one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Descriptor {
+    node: Node,
+}
+
+impl Descriptor {
+    /// Returns true if any key in the descriptor ends in a wildcard.
+    ///
+    /// Descriptors without a wildcard describe a single output script for every index.
+    pub fn has_wildcard(&self) -> bool { self.keys().iter().any(|key| key.has_wildcard()) }
+
+    /// Returns true if any key in the descriptor has a multipath derivation step.
+    pub fn is_multipath(&self) -> bool { self.keys().iter().any(|key| key.is_multipath()) }
+
+    /// Splits a multipath descriptor into one descriptor per multipath alternative.
+    ///
+    /// For example `wpkh(xpub/<0;1>/*)` becomes `wpkh(xpub/0/*)` and `wpkh(xpub/1/*)`, the
+    /// receive and change descriptors. A descriptor without multipath steps is returned as is.
+    pub fn into_single_descriptors(self) -> Vec<Descriptor> {
+        let len = self.keys().iter().find_map(|key| key.multipath_len());
+        match len {
+            Some(len) => (0..len)
+                .map(|i| {
+                    let node =
+                        self.node.map_keys(&|key: &DescriptorPublicKey| key.at_multipath_index(i));
+                    Descriptor { node }
+                })
+                .collect(),
+            None => vec![self],
+        }
+    }
+
+    /// Derives the output script at `index`.
+    ///
+    /// The index is only used by keys ending in a wildcard.
+    ///
+    /// # Errors
+    ///
+    /// * [`DeriveError::Multipath`] if the descriptor is multipath, see
+    ///   [`Descriptor::into_single_descriptors`].
+    /// * [`DeriveError::IndexOutOfRange`] if `index` is hardened and the descriptor has a wildcard.
+    /// * [`DeriveError::ScriptSize`] if a redeem or witness script is too large.
+    pub fn script_pubkey_at<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<ScriptPubKeyBuf, DeriveError> {
+        self.node.script(secp, index)
+    }
+
+    /// Derives the address at `index` for `network`.
+    ///
+    /// # Errors
+    ///
+    /// As for [`Descriptor::script_pubkey_at`], and additionally:
+    ///
+    /// * [`DeriveError::NoAddress`] if the output script has no address form, e.g. `pk` or `multi`.
+    /// * [`DeriveError::NetworkMismatch`] if an `addr` descriptor is not valid for `network`.
+    pub fn address_at<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+        network: Network,
+    ) -> Result<Address, DeriveError> {
+        if let Node::Addr(ref address) = self.node {
+            if !address.is_valid_for_network(network) {
+                return Err(DeriveError::NetworkMismatch);
+            }
+            return Ok(*address.assume_checked_ref());
+        }
+
+        let script_pubkey = self.script_pubkey_at(secp, index)?;
+        Address::from_script(&script_pubkey, network).map_err(|_| DeriveError::NoAddress)
+    }
+
+    /// Returns all keys in the descriptor.
+    fn keys(&self) -> Vec<&DescriptorPublicKey> {
+        let mut keys = Vec::new();
+        self.node.keys(&mut keys);
+        keys
+    }
+}
+
+impl fmt::Display for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let descriptor = self.node.to_string();
+        let checksum = checksum(&descriptor).map_err(|_| fmt::Error)?;
+        write!(f, "{}#", descriptor)?;
+        checksum.iter().try_for_each(|ch| write!(f, "{}", ch))
+    }
+}
+
+impl FromStr for Descriptor {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = verify_checksum(s).map_err(ParseError::Checksum)?;
+        let descriptor = Descriptor { node: Node::parse(s, Context::Top)? };
+
+        let mut lengths = descriptor.keys().into_iter().filter_map(|key| key.multipath_len());
+        if let Some(first) = lengths.next() {
+            if lengths.any(|len| len != first) {
+                return Err(ParseError::MultipathMismatch);
+            }
+        }
+        Ok(descriptor)
+    }
+}
+
+/// The script context a descriptor expression appears in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Context {
+    /// The top level, the expression describes the output script.
+    Top,
+    /// Inside `sh`, the expression describes the redeem script.
+    P2sh,
+    /// Inside `wsh`, the expression describes the witness script.
+    P2wsh,
+    /// Inside a `tr` script tree, the expression describes a leaf script.
+    Tap,
+}
+
+/// A descriptor expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Pk(DescriptorPublicKey),
+    Pkh(DescriptorPublicKey),
+    Wpkh(DescriptorPublicKey),
+    Sh(Box<Node>),
+    Wsh(Box<Node>),
+    Multi { sorted: bool, threshold: usize, keys: Vec<DescriptorPublicKey> },
+    MultiA { sorted: bool, threshold: usize, keys: Vec<DescriptorPublicKey> },
+    Tr { internal_key: DescriptorPublicKey, tree: Option<TapTree> },
+    Addr(Address<NetworkUnchecked>),
+    Raw(ScriptPubKeyBuf),
+}
+
+/// A `tr` script tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TapTree {
+    Leaf(Box<Node>),
+    Branch(Box<TapTree>, Box<TapTree>),
+}
+
+impl Node {
+    /// Parses the expression `s` appearing in `context`.
+    fn parse(s: &str, context: Context) -> Result<Node, ParseError> {
+        use Context::*;
+
+        let open = s.find('(').ok_or(ParseError::Syntax)?;
+        let (name, args) = (&s[..open], s[open + 1..].strip_suffix(')').ok_or(ParseError::Syntax)?);
+
+        let node = match name {
+            "pk" => Node::Pk(parse_key(single_arg(args)?, context)?),
+            "pkh" if context != Tap => Node::Pkh(parse_key(single_arg(args)?, context)?),
+            "wpkh" if matches!(context, Top | P2sh) => {
+                let key = parse_key(single_arg(args)?, context)?;
+                if key.is_uncompressed() {
+                    return Err(ParseError::UncompressedKey);
+                }
+                Node::Wpkh(key)
+            }
+            "sh" if context == Top => Node::Sh(Box::new(Node::parse(single_arg(args)?, P2sh)?)),
+            "wsh" if matches!(context, Top | P2sh) =>
+                Node::Wsh(Box::new(Node::parse(single_arg(args)?, P2wsh)?)),
+            "multi" | "sortedmulti" if context != Tap => {
+                let max_keys = match context {
+                    Top => MAX_BARE_MULTI_KEYS,
+                    P2sh => MAX_P2SH_MULTI_KEYS,
+                    P2wsh | Tap => MAX_P2WSH_MULTI_KEYS,
+                };
+                let (threshold, keys) = parse_multi(args, context, max_keys)?;
+                Node::Multi { sorted: name == "sortedmulti", threshold, keys }
+            }
+            "multi_a" | "sortedmulti_a" if context == Tap => {
+                let (threshold, keys) = parse_multi(args, context, MAX_MULTI_A_KEYS)?;
+                Node::MultiA { sorted: name == "sortedmulti_a", threshold, keys }
+            }
+            "tr" if context == Top => {
+                let args = split_args(args)?;
+                let internal_key = parse_key(args[0], Tap)?;
+                let tree = match args.len() {
+                    1 => None,
+                    2 => Some(TapTree::parse(args[1], 0)?),
+                    _ => return Err(ParseError::Syntax),
+                };
+                Node::Tr { internal_key, tree }
+            }
+            "addr" if context == Top =>
+                Node::Addr(single_arg(args)?.parse().map_err(ParseError::Address)?),
+            "raw" if context == Top => Node::Raw(
+                ScriptPubKeyBuf::from_hex_no_length_prefix(single_arg(args)?)
+                    .map_err(ParseError::Hex)?,
+            ),
+            "pkh" | "wpkh" | "sh" | "wsh" | "multi" | "sortedmulti" | "multi_a"
+            | "sortedmulti_a" | "tr" | "addr" | "raw" => return Err(ParseError::InvalidContext),
+            _ => return Err(ParseError::UnknownFunction),
+        };
+        Ok(node)
+    }
+
+    /// Appends all keys in the expression to `keys`.
+    fn keys<'a>(&'a self, keys: &mut Vec<&'a DescriptorPublicKey>) {
+        match *self {
+            Node::Pk(ref key) | Node::Pkh(ref key) | Node::Wpkh(ref key) => keys.push(key),
+            Node::Sh(ref inner) | Node::Wsh(ref inner) => inner.keys(keys),
+            Node::Multi { keys: ref multi_keys, .. }
+            | Node::MultiA { keys: ref multi_keys, .. } => keys.extend(multi_keys),
+            Node::Tr { ref internal_key, ref tree } => {
+                keys.push(internal_key);
+                if let Some(ref tree) = *tree {
+                    tree.keys(keys);
+                }
+            }
+            Node::Addr(_) | Node::Raw(_) => {}
+        }
+    }
+
+    /// Returns the expression with every key replaced by `f(key)`.
+    fn map_keys<F>(&self, f: &F) -> Node
+    where
+        F: Fn(&DescriptorPublicKey) -> DescriptorPublicKey,
+    {
+        match *self {
+            Node::Pk(ref key) => Node::Pk(f(key)),
+            Node::Pkh(ref key) => Node::Pkh(f(key)),
+            Node::Wpkh(ref key) => Node::Wpkh(f(key)),
+            Node::Sh(ref inner) => Node::Sh(Box::new(inner.map_keys(f))),
+            Node::Wsh(ref inner) => Node::Wsh(Box::new(inner.map_keys(f))),
+            Node::Multi { sorted, threshold, ref keys } =>
+                Node::Multi { sorted, threshold, keys: keys.iter().map(f).collect() },
+            Node::MultiA { sorted, threshold, ref keys } =>
+                Node::MultiA { sorted, threshold, keys: keys.iter().map(f).collect() },
+            Node::Tr { ref internal_key, ref tree } => Node::Tr {
+                internal_key: f(internal_key),
+                tree: tree.as_ref().map(|tree| tree.map_keys(f)),
+            },
+            Node::Addr(address) => Node::Addr(address),
+            Node::Raw(ref script) => Node::Raw(script.clone()),
+        }
+    }
+
+    /// Derives the script described by the expression at `index`.
+    fn script<T, C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<ScriptBuf<T>, DeriveError> {
+        let script = match *self {
+            Node::Pk(ref key) => Builder::new()
+                .push_key(key.derive(secp, index)?.to_full())
+                .push_opcode(OP_CHECKSIG)
+                .into_script(),
+            Node::Pkh(ref key) => Builder::new()
+                .push_opcode(OP_DUP)
+                .push_opcode(OP_HASH160)
+                .push_slice(key.derive(secp, index)?.to_full().pubkey_hash())
+                .push_opcode(OP_EQUALVERIFY)
+                .push_opcode(OP_CHECKSIG)
+                .into_script(),
+            Node::Wpkh(ref key) => {
+                let hash = key
+                    .derive(secp, index)?
+                    .to_full()
+                    .wpubkey_hash()
+                    .expect("uncompressed keys are rejected when parsing wpkh");
+                ScriptBuf::from_bytes(ScriptPubKeyBuf::new_p2wpkh(hash).into_bytes())
+            }
+            Node::Sh(ref inner) => {
+                let redeem_script: RedeemScriptBuf = inner.script(secp, index)?;
+                let hash = redeem_script.script_hash().map_err(|_| DeriveError::ScriptSize)?;
+                ScriptBuf::from_bytes(ScriptPubKeyBuf::new_p2sh(hash).into_bytes())
+            }
+            Node::Wsh(ref inner) => {
+                let witness_script: WitnessScriptBuf = inner.script(secp, index)?;
+                let hash = witness_script.wscript_hash().map_err(|_| DeriveError::ScriptSize)?;
+                ScriptBuf::from_bytes(ScriptPubKeyBuf::new_p2wsh(hash).into_bytes())
+            }
+            Node::Multi { sorted, threshold, ref keys } => {
+                let keys = derive_keys(keys, sorted, secp, index)?;
+                let mut builder = Builder::new().push_int_unchecked(threshold as i64);
+                for key in &keys {
+                    builder = builder.push_key(key.to_full());
+                }
+                builder
+                    .push_int_unchecked(keys.len() as i64)
+                    .push_opcode(OP_CHECKMULTISIG)
+                    .into_script()
+            }
+            Node::MultiA { sorted, threshold, ref keys } => {
+                let keys = derive_keys(keys, sorted, secp, index)?;
+                let mut builder = Builder::new();
+                for (i, key) in keys.iter().enumerate() {
+                    let opcode = if i == 0 { OP_CHECKSIG } else { OP_CHECKSIGADD };
+                    builder = builder.push_x_only_key(key.to_x_only()).push_opcode(opcode);
+                }
+                builder.push_int_unchecked(threshold as i64).push_opcode(OP_NUMEQUAL).into_script()
+            }
+            Node::Tr { ref internal_key, ref tree } => {
+                let internal_key = internal_key.derive(secp, index)?.to_x_only();
+                let mut builder = TaprootBuilder::new();
+                if let Some(ref tree) = *tree {
+                    builder = tree.add_leaves(builder, 0, secp, index)?;
+                }
+                let spend_info = builder
+                    .finalize(secp, internal_key)
+                    .expect("leaves of a complete tree are added in DFS order");
+                ScriptBuf::from_bytes(
+                    ScriptPubKeyBuf::new_p2tr_tweaked(spend_info.output_key()).into_bytes(),
+                )
+            }
+            Node::Addr(ref address) =>
+                ScriptBuf::from_bytes(address.assume_checked_ref().script_pubkey().into_bytes()),
+            Node::Raw(ref script) => ScriptBuf::from_bytes(script.as_bytes().to_vec()),
+        };
+        Ok(script)
+    }
+
+    /// Derives the leaf script described by the expression at `index`.
+    fn tap_script<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<TapScriptBuf, DeriveError> {
+        match *self {
+            Node::Pk(ref key) => Ok(Builder::new()
+                .push_x_only_key(key.derive(secp, index)?.to_x_only())
+                .push_opcode(OP_CHECKSIG)
+                .into_script()),
+            _ => self.script(secp, index),
+        }
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Node::Pk(ref key) => write!(f, "pk({})", key),
+            Node::Pkh(ref key) => write!(f, "pkh({})", key),
+            Node::Wpkh(ref key) => write!(f, "wpkh({})", key),
+            Node::Sh(ref inner) => write!(f, "sh({})", inner),
+            Node::Wsh(ref inner) => write!(f, "wsh({})", inner),
+            Node::Multi { sorted, threshold, ref keys } => {
+                let name = if sorted { "sortedmulti" } else { "multi" };
+                fmt_multi(f, name, threshold, keys)
+            }
+            Node::MultiA { sorted, threshold, ref keys } => {
+                let name = if sorted { "sortedmulti_a" } else { "multi_a" };
+                fmt_multi(f, name, threshold, keys)
+            }
+            Node::Tr { ref internal_key, ref tree } => {
+                write!(f, "tr({}", internal_key)?;
+                if let Some(ref tree) = *tree {
+                    write!(f, ",{}", tree)?;
+                }
+                f.write_str(")")
+            }
+            Node::Addr(ref address) => write!(f, "addr({})", address.assume_checked_ref()),
+            Node::Raw(ref script) => write!(f, "raw({})", script.as_bytes().as_hex()),
+        }
+    }
+}
+
+impl TapTree {
+    /// Parses the script tree `s` whose root is at `depth`.
+    fn parse(s: &str, depth: usize) -> Result<TapTree, ParseError> {
+        if depth > TAPROOT_CONTROL_MAX_NODE_COUNT {
+            return Err(ParseError::TaprootDepth);
+        }
+
+        match s.strip_prefix('{') {
+            Some(rest) => {
+                let branches = split_args(rest.strip_suffix('}').ok_or(ParseError::Syntax)?)?;
+                if branches.len() != 2 {
+                    return Err(ParseError::Syntax);
+                }
+                let left = TapTree::parse(branches[0], depth + 1)?;
+                let right = TapTree::parse(branches[1], depth + 1)?;
+                Ok(TapTree::Branch(Box::new(left), Box::new(right)))
+            }
+            None => Ok(TapTree::Leaf(Box::new(Node::parse(s, Context::Tap)?))),
+        }
+    }
+
+    /// Appends all keys in the tree to `keys`.
+    fn keys<'a>(&'a self, keys: &mut Vec<&'a DescriptorPublicKey>) {
+        match *self {
+            TapTree::Leaf(ref leaf) => leaf.keys(keys),
+            TapTree::Branch(ref left, ref right) => {
+                left.keys(keys);
+                right.keys(keys);
+            }
+        }
+    }
+
+    /// Returns the tree with every key replaced by `f(key)`.
+    fn map_keys<F>(&self, f: &F) -> TapTree
+    where
+        F: Fn(&DescriptorPublicKey) -> DescriptorPublicKey,
+    {
+        match *self {
+            TapTree::Leaf(ref leaf) => TapTree::Leaf(Box::new(leaf.map_keys(f))),
+            TapTree::Branch(ref left, ref right) =>
+                TapTree::Branch(Box::new(left.map_keys(f)), Box::new(right.map_keys(f))),
+        }
+    }
+
+    /// Adds the leaves of the tree, whose root is at `depth`, to `builder` in DFS order.
+    fn add_leaves<C: Verification>(
+        &self,
+        builder: TaprootBuilder,
+        depth: u8,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<TaprootBuilder, DeriveError> {
+        match *self {
+            TapTree::Leaf(ref leaf) => Ok(builder
+                .add_leaf(depth, leaf.tap_script(secp, index)?)
+                .expect("depth is checked when parsing and leaves are added in DFS order")),
+            TapTree::Branch(ref left, ref right) => {
+                let builder = left.add_leaves(builder, depth + 1, secp, index)?;
+                right.add_leaves(builder, depth + 1, secp, index)
+            }
+        }
+    }
+}
+
+impl fmt::Display for TapTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TapTree::Leaf(ref leaf) => write!(f, "{}", leaf),
+            TapTree::Branch(ref left, ref right) => write!(f, "{{{},{}}}", left, right),
+        }
+    }
+}
+
+/// Splits `s` at the commas that are not nested inside parentheses or braces.
+fn split_args(s: &str) -> Result<Vec<&str>, ParseError> {
+    let mut args = Vec::new();
+    let mut depth = 0_usize;
+    let mut start = 0;
+    for (i, ch) in s.char_indices() {
+        match ch {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth = depth.checked_sub(1).ok_or(ParseError::Syntax)?,
+            ',' if depth == 0 => {
+                args.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(ParseError::Syntax);
+    }
+    args.push(&s[start..]);
+    Ok(args)
+}
+
+/// Returns the only argument in `s`.
+fn single_arg(s: &str) -> Result<&str, ParseError> {
+    match split_args(s)?[..] {
+        [arg] => Ok(arg),
+        _ => Err(ParseError::Syntax),
+    }
+}
+
+/// Parses a key expression, checking it is valid in `context`.
+fn parse_key(s: &str, context: Context) -> Result<DescriptorPublicKey, ParseError> {
+    let key = s.parse::<DescriptorPublicKey>().map_err(ParseError::Key)?;
+    if key.is_x_only() && context != Context::Tap {
+        return Err(ParseError::XOnlyKey);
+    }
+    if key.is_uncompressed() && matches!(context, Context::P2wsh | Context::Tap) {
+        return Err(ParseError::UncompressedKey);
+    }
+    Ok(key)
+}
+
+/// Parses the arguments of a `multi` style expression, the threshold followed by the keys.
+fn parse_multi(
+    s: &str,
+    context: Context,
+    max_keys: usize,
+) -> Result<(usize, Vec<DescriptorPublicKey>), ParseError> {
+    let args = split_args(s)?;
+    let threshold = args[0].parse::<usize>().map_err(|_| ParseError::Threshold)?;
+    let keys =
+        args[1..].iter().map(|key| parse_key(key, context)).collect::<Result<Vec<_>, _>>()?;
+
+    if keys.len() > max_keys {
+        return Err(ParseError::TooManyKeys(keys.len()));
+    }
+    if threshold == 0 || threshold > keys.len() {
+        return Err(ParseError::Threshold);
+    }
+    Ok((threshold, keys))
+}
+
+/// Derives `keys` at `index`, sorting them by their serialization if `sorted` is true.
+fn derive_keys<C: Verification>(
+    keys: &[DescriptorPublicKey],
+    sorted: bool,
+    secp: &Secp256k1<C>,
+    index: u32,
+) -> Result<Vec<SinglePubKey>, DeriveError> {
+    let mut keys = keys.iter().map(|key| key.derive(secp, index)).collect::<Result<Vec<_>, _>>()?;
+    if sorted {
+        keys.sort_by_cached_key(|key| match *key {
+            SinglePubKey::Full(pk) => pk.to_vec(),
+            SinglePubKey::XOnly(pk) => pk.serialize().to_vec(),
+        });
+    }
+    Ok(keys)
+}
+
+/// Writes a `multi` style expression.
+fn fmt_multi(
+    f: &mut fmt::Formatter,
+    name: &str,
+    threshold: usize,
+    keys: &[DescriptorPublicKey],
+) -> fmt::Result {
+    write!(f, "{}({}", name, threshold)?;
+    for key in keys {
+        write!(f, ",{}", key)?;
+    }
+    f.write_str(")")
+}
+
+/// Error parsing a descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// Invalid checksum.
+    Checksum(ChecksumError),
+    /// The descriptor is not a well formed expression.
+    Syntax,
+    /// Unknown descriptor function.
+    UnknownFunction,
+    /// The descriptor function is not allowed in this position, e.g. `wpkh` inside `wsh`.
+    InvalidContext,
+    /// Invalid key expression.
+    Key(KeyParseError),
+    /// An uncompressed key in a segwit context.
+    UncompressedKey,
+    /// An x-only key outside of a taproot context.
+    XOnlyKey,
+    /// The threshold of a `multi` style expression is not between one and the number of keys.
+    Threshold,
+    /// Too many keys in a `multi` style expression for its context.
+    TooManyKeys(usize),
+    /// Invalid address in an `addr` expression.
+    Address(address::ParseError),
+    /// Invalid hex in a `raw` expression.
+    Hex(hex::HexToBytesError),
+    /// The script tree of a `tr` expression is deeper than 128 levels.
+    TaprootDepth,
+    /// The multipath steps of the keys have a different number of alternatives.
+    MultipathMismatch,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ParseError::*;
+
+        match *self {
+            Checksum(ref e) => write_err!(f, "invalid descriptor checksum"; e),
+            Syntax => f.write_str("malformed descriptor expression"),
+            UnknownFunction => f.write_str("unknown descriptor function"),
+            InvalidContext => f.write_str("descriptor function is not allowed in this position"),
+            Key(ref e) => write_err!(f, "invalid descriptor key"; e),
+            UncompressedKey => f.write_str("uncompressed keys are not allowed in segwit contexts"),
+            XOnlyKey => f.write_str("x-only keys are only allowed in taproot contexts"),
+            Threshold => f.write_str("threshold is not between one and the number of keys"),
+            TooManyKeys(n) => write!(f, "{} keys is too many in this position", n),
+            Address(ref e) => write_err!(f, "invalid address"; e),
+            Hex(ref e) => write_err!(f, "invalid raw script hex"; e),
+            TaprootDepth => write!(
+                f,
+                "taproot script tree is deeper than {} levels",
+                TAPROOT_CONTROL_MAX_NODE_COUNT
+            ),
+            MultipathMismatch =>
+                f.write_str("multipath steps have a different number of alternatives"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ParseError::*;
+
+        match *self {
+            Checksum(ref e) => Some(e),
+            Key(ref e) => Some(e),
+            Address(ref e) => Some(e),
+            Hex(ref e) => Some(e),
+            Syntax | UnknownFunction | InvalidContext | UncompressedKey | XOnlyKey | Threshold
+            | TooManyKeys(_) | TaprootDepth | MultipathMismatch => None,
+        }
+    }
+}
+
+/// Error deriving an output script or address from a descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DeriveError {
+    /// The index is hardened but must be unhardened for wildcard derivation.
+    IndexOutOfRange(bip32::IndexOutOfRangeError),
+    /// Error deriving a child key.
+    Derivation(bip32::DerivationError),
+    /// The descriptor is multipath and must be split into single descriptors first.
+    Multipath,
+    /// A redeem or witness script exceeds its maximum size.
+    ScriptSize,
+    /// The output script has no address form.
+    NoAddress,
+    /// The address of an `addr` descriptor is not valid for the network.
+    NetworkMismatch,
+}
+
+impl fmt::Display for DeriveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use DeriveError::*;
+
+        match *self {
+            IndexOutOfRange(ref e) => write_err!(f, "invalid wildcard index"; e),
+            Derivation(ref e) => write_err!(f, "key derivation failed"; e),
+            Multipath => f.write_str("cannot derive from a multipath descriptor"),
+            ScriptSize => f.write_str("redeem or witness script exceeds the maximum size"),
+            NoAddress => f.write_str("output script has no address form"),
+            NetworkMismatch => f.write_str("address is not valid for the network"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DeriveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use DeriveError::*;
+
+        match *self {
+            IndexOutOfRange(ref e) => Some(e),
+            Derivation(ref e) => Some(e),
+            Multipath | ScriptSize | NoAddress | NetworkMismatch => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bip32::{DerivationPath, Xpub};
+    use crate::key::CompressedPublicKey;
+    use crate::prelude::String;
+
+    // Keys derived from the "abandon abandon ... about" BIP-0039 test mnemonic.
+    const BIP44_KEY: &str = "03aaeb52dd7494c361049de67cc680e83ebcbbbdbeb13637d92cd845f70308af5e";
+    const BIP49_KEY: &str = "039b3b694b8fc5b5e07fb069c783cac754f5d38c3e08bed1960e31fdb1dda35c24";
+    const BIP84_KEY: &str = "0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c";
+    const BIP86_XPUB: &str = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+    const UNCOMPRESSED_KEY: &str = "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+
+    fn parse(s: &str) -> Descriptor { s.parse().unwrap() }
+
+    fn address(descriptor: &str, index: u32) -> String {
+        let secp = Secp256k1::verification_only();
+        parse(descriptor).address_at(&secp, index, Network::Bitcoin).unwrap().to_string()
+    }
+
+    #[test]
+    fn single_key_addresses() {
+        assert_eq!(
+            address(&format!("pkh({})", BIP44_KEY), 0),
+            "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA"
+        );
+        assert_eq!(
+            address(&format!("sh(wpkh({}))", BIP49_KEY), 0),
+            "37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf"
+        );
+        assert_eq!(
+            address(&format!("wpkh({})", BIP84_KEY), 0),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+    }
+
+    #[test]
+    fn taproot_xpub_addresses() {
+        let descriptor = format!("tr([73c5da0a/86h/0h/0h]{}/0/*)", BIP86_XPUB);
+        assert_eq!(
+            address(&descriptor, 0),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+    }
+
+    #[test]
+    fn wildcard_derivation() {
+        let secp = Secp256k1::verification_only();
+        let descriptor = parse(&format!("wpkh({}/0/*)", BIP86_XPUB));
+        assert!(descriptor.has_wildcard());
+
+        let xpub = BIP86_XPUB.parse::<Xpub>().unwrap();
+        let path = "0/7".parse::<DerivationPath>().unwrap();
+        let key = CompressedPublicKey(xpub.derive_xpub(&secp, &path).unwrap().public_key);
+        assert_eq!(
+            descriptor.address_at(&secp, 7, Network::Bitcoin).unwrap(),
+            Address::p2wpkh(key, Network::Bitcoin)
+        );
+
+        assert!(matches!(
+            descriptor.script_pubkey_at(&secp, 1 << 31),
+            Err(DeriveError::IndexOutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn multipath_descriptors() {
+        let secp = Secp256k1::verification_only();
+        let descriptor = parse(&format!("wpkh({}/<0;1>/*)", BIP86_XPUB));
+        assert!(descriptor.is_multipath());
+        assert_eq!(descriptor.script_pubkey_at(&secp, 0), Err(DeriveError::Multipath));
+
+        let single = descriptor.into_single_descriptors();
+        assert_eq!(single.len(), 2);
+        assert_eq!(single[0], parse(&format!("wpkh({}/0/*)", BIP86_XPUB)));
+        assert_eq!(single[1], parse(&format!("wpkh({}/1/*)", BIP86_XPUB)));
+
+        let mismatch = format!("wsh(multi(1,{0}/<0;1>/*,{0}/<0;1;2>/*))", BIP86_XPUB);
+        assert_eq!(mismatch.parse::<Descriptor>(), Err(ParseError::MultipathMismatch));
+    }
+
+    #[test]
+    fn sortedmulti_sorts_keys() {
+        let secp = Secp256k1::verification_only();
+        let sorted =
+            parse(&format!("wsh(sortedmulti(2,{},{},{}))", BIP44_KEY, BIP84_KEY, BIP49_KEY));
+        let multi = parse(&format!("wsh(multi(2,{},{},{}))", BIP84_KEY, BIP49_KEY, BIP44_KEY));
+        assert_eq!(sorted.script_pubkey_at(&secp, 0), multi.script_pubkey_at(&secp, 0));
+    }
+
+    #[test]
+    fn taproot_script_tree() {
+        let secp = Secp256k1::verification_only();
+        let x_only = &BIP84_KEY[2..];
+        let descriptor = parse(&format!(
+            "tr({},{{pk({}),{{multi_a(1,{},{}),sortedmulti_a(2,{},{})}}}})",
+            BIP44_KEY, x_only, BIP49_KEY, BIP44_KEY, BIP44_KEY, BIP49_KEY
+        ));
+
+        let key = |s: &str| s.parse::<crate::key::PublicKey>().unwrap();
+        let x_only_key = |s: &str| crate::key::XOnlyPublicKey::from(key(s).inner);
+        let pk_leaf = Builder::new()
+            .push_x_only_key(x_only.parse().unwrap())
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let multi_a_leaf = |a: &str, b: &str, threshold| {
+            Builder::new()
+                .push_x_only_key(x_only_key(a))
+                .push_opcode(OP_CHECKSIG)
+                .push_x_only_key(x_only_key(b))
+                .push_opcode(OP_CHECKSIGADD)
+                .push_int_unchecked(threshold)
+                .push_opcode(OP_NUMEQUAL)
+                .into_script()
+        };
+        // The keys of `sortedmulti_a` are sorted by their x-only serialization.
+        let (first, second) = if BIP44_KEY[2..] < BIP49_KEY[2..] {
+            (BIP44_KEY, BIP49_KEY)
+        } else {
+            (BIP49_KEY, BIP44_KEY)
+        };
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(1, pk_leaf)
+            .unwrap()
+            .add_leaf(2, multi_a_leaf(BIP49_KEY, BIP44_KEY, 1))
+            .unwrap()
+            .add_leaf(2, multi_a_leaf(first, second, 2))
+            .unwrap()
+            .finalize(&secp, x_only_key(BIP44_KEY))
+            .unwrap();
+
+        assert_eq!(
+            descriptor.script_pubkey_at(&secp, 0).unwrap(),
+            ScriptPubKeyBuf::new_p2tr_tweaked(spend_info.output_key())
+        );
+    }
+
+    #[test]
+    fn addr_and_raw() {
+        let secp = Secp256k1::verification_only();
+        let descriptor = parse("addr(bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu)");
+        assert_eq!(
+            descriptor,
+            parse(&format!("addr({})", address(&format!("wpkh({})", BIP84_KEY), 0)))
+        );
+        assert_eq!(
+            descriptor.address_at(&secp, 0, Network::Signet),
+            Err(DeriveError::NetworkMismatch)
+        );
+
+        let raw = parse("raw(deadbeef)#89f8spxm");
+        assert_eq!(raw.to_string(), "raw(deadbeef)#89f8spxm");
+        assert_eq!(
+            raw.script_pubkey_at(&secp, 0).unwrap(),
+            ScriptPubKeyBuf::from_bytes(vec![0xde, 0xad, 0xbe, 0xef])
+        );
+        assert_eq!(raw.address_at(&secp, 0, Network::Bitcoin), Err(DeriveError::NoAddress));
+    }
+
+    #[test]
+    fn display_roundtrip() {
+        let descriptors = [
+            format!("pk({})", UNCOMPRESSED_KEY),
+            format!("sh(multi(1,{},{}))", BIP44_KEY, UNCOMPRESSED_KEY),
+            format!(
+                "sh(wsh(sortedmulti(1,[73c5da0a/48h/0h/0h/2h]{}/<0;1>/*,{})))",
+                BIP86_XPUB, BIP84_KEY
+            ),
+            format!("tr({},{{pk({}),pk({})}})", BIP44_KEY, BIP49_KEY, &BIP84_KEY[2..]),
+        ];
+        for s in &descriptors {
+            let descriptor = parse(s);
+            let displayed = descriptor.to_string();
+            assert_eq!(displayed.split('#').next(), Some(s.as_str()));
+            assert_eq!(parse(&displayed), descriptor);
+        }
+
+        // Apostrophes are accepted for hardened steps but displayed as `h`.
+        let descriptor = parse(&format!("pkh([73c5da0a/44'/0'/0']{}/0/*)", BIP86_XPUB));
+        assert!(descriptor.to_string().starts_with("pkh([73c5da0a/44h/0h/0h]"));
+    }
+
+    fn script_pubkeys(descriptor: &str, count: u32) -> Vec<String> {
+        let secp = Secp256k1::verification_only();
+        let descriptor = parse(descriptor);
+        (0..count).map(|i| descriptor.script_pubkey_at(&secp, i).unwrap().to_hex_string()).collect()
+    }
+
+    // Test vectors from BIP-0380 that don't use private keys.
+    #[test]
+    fn bip380_key_vectors() {
+        assert_eq!(
+            script_pubkeys(
+                "pk(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+                1
+            ),
+            ["210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac"]
+        );
+        assert_eq!(
+            script_pubkeys("pk(04a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd5b8dec5235a0fa8722476c7709c02559e3aa73aa03918ba2d492eea75abea235)", 1),
+            ["4104a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd5b8dec5235a0fa8722476c7709c02559e3aa73aa03918ba2d492eea75abea235ac"]
+        );
+        assert_eq!(
+            script_pubkeys("pk(xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8)", 1),
+            ["210339a36013301597daef41fbe593a02cc513d0b55527ec2df1050e2e8ff49c85c2ac"]
+        );
+        assert_eq!(
+            script_pubkeys("pk([deadbeef/0h/0h/0h]0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)", 1),
+            ["210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac"]
+        );
+
+        let xpub = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+        let invalid = [
+            // Children indicator in key origin.
+            format!("pk([deadbeef/1/2h/3/4h]/{})", xpub),
+            // Fingerprint too short.
+            format!("pk([deadbef/0h/0h/0h]{})", xpub),
+            // Fingerprint too long.
+            format!("pk([deadbeeef/0h/0h/0h]{})", xpub),
+            // Key path value exceeds 2^31 - 1.
+            format!("pk({}/2147483648)", xpub),
+            // Multiple wildcards.
+            format!("pk({}/1/*/*)", xpub),
+            // Wildcard not at the end.
+            format!("pk({}/1/*/2)", xpub),
+            // Hardened derivation from an xpub.
+            format!("pk({}/1h/2)", xpub),
+            // Key origin without key.
+            "pk([deadbeef/0h/0h/0h])".into(),
+            // Multiple key origins.
+            format!("pk([deadbeef/0h][deadbeef/0h]{})", xpub),
+            // Uncompressed key in segwit.
+            "wsh(pk(04a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd5b8dec5235a0fa8722476c7709c02559e3aa73aa03918ba2d492eea75abea235))".into(),
+        ];
+        for s in &invalid {
+            assert!(s.parse::<Descriptor>().is_err(), "{}", s);
+        }
+    }
+
+    // Test vectors from BIP-0386 that don't use private keys.
+    #[test]
+    fn bip386_vectors() {
+        assert_eq!(
+            script_pubkeys(
+                "tr(a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd)",
+                1
+            ),
+            ["512077aab6e066f8a7419c5ab714c12c67d25007ed55a43cadcacb4d7a970a093f11"]
+        );
+        assert_eq!(
+            script_pubkeys("tr(a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd,pk(669b8afcec803a0d323e9a17f3ea8e68e8abe5a278020a929adbec52421adbd0))", 1),
+            ["512017cf18db381d836d8923b1bdb246cfcd818da1a9f0e6e7907f187f0b2f937754"]
+        );
+
+        let invalid = [
+            // Uncompressed internal key.
+            "tr(04a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd5b8dec5235a0fa8722476c7709c02559e3aa73aa03918ba2d492eea75abea235)",
+            // Uncompressed key in a leaf.
+            "tr(a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd,pk(04a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd5b8dec5235a0fa8722476c7709c02559e3aa73aa03918ba2d492eea75abea235))",
+            // A branch with a single child.
+            "tr(a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd,{pk(669b8afcec803a0d323e9a17f3ea8e68e8abe5a278020a929adbec52421adbd0)})",
+            // `tr` is only allowed at the top level.
+            "sh(tr(a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd))",
+            "wsh(tr(a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd))",
+        ];
+        for s in &invalid {
+            assert!(s.parse::<Descriptor>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn invalid_descriptors() {
+        let err = |s: String| s.parse::<Descriptor>().unwrap_err();
+
+        assert!(matches!(err("raw(deadbeef)#89f8spxx".into()), ParseError::Checksum(_)));
+        assert_eq!(err(format!("foo({})", BIP44_KEY)), ParseError::UnknownFunction);
+        assert_eq!(err(format!("wsh(wpkh({}))", BIP44_KEY)), ParseError::InvalidContext);
+        assert_eq!(err(format!("sh(sh(pk({})))", BIP44_KEY)), ParseError::InvalidContext);
+        assert_eq!(err(format!("wsh(tr({}))", BIP44_KEY)), ParseError::InvalidContext);
+        assert_eq!(
+            err(format!("tr({},multi(1,{}))", BIP44_KEY, BIP49_KEY)),
+            ParseError::InvalidContext
+        );
+        assert_eq!(err(format!("wpkh({})", UNCOMPRESSED_KEY)), ParseError::UncompressedKey);
+        assert_eq!(err(format!("wsh(pk({}))", UNCOMPRESSED_KEY)), ParseError::UncompressedKey);
+        assert_eq!(err(format!("pk({})", &BIP44_KEY[2..])), ParseError::XOnlyKey);
+        assert_eq!(err(format!("multi(0,{})", BIP44_KEY)), ParseError::Threshold);
+        assert_eq!(err(format!("multi(2,{})", BIP44_KEY)), ParseError::Threshold);
+        assert_eq!(err(format!("multi(1,{0},{0},{0},{0})", BIP44_KEY)), ParseError::TooManyKeys(4));
+        assert_eq!(err(format!("pkh({}", BIP44_KEY)), ParseError::Syntax);
+        assert_eq!(err(format!("pkh({0},{0})", BIP44_KEY)), ParseError::Syntax);
+        assert!(matches!(err(format!("pkh({}/1h/*)", BIP86_XPUB)), ParseError::Key(_)));
+
+        let mut deep = format!("pk({})", BIP44_KEY);
+        for _ in 0..=TAPROOT_CONTROL_MAX_NODE_COUNT {
+            deep = format!("{{{},pk({})}}", deep, BIP44_KEY);
+        }
+        assert_eq!(err(format!("tr({},{})", BIP44_KEY, deep)), ParseError::TaprootDepth);
+    }
+}
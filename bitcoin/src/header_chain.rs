@@ -0,0 +1,933 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Block header chain validation.
+//!
+//! [`HeaderChain`] keeps a tree of block headers rooted at the genesis block of a network and
+//! tracks the chain with the most cumulative work. Headers are checked against the contextual
+//! rules Bitcoin Core applies to headers before downloading blocks: linkage to a known header,
+//! the required difficulty (including the testnet minimum difficulty and BIP-0094 rules), the
+//! proof-of-work, the median-time-past, the future time limit and the minimum header version.
+//!
+//! To bound the memory an attacker can make the chain use, headers forking from the active chain
+//! more than [`HeaderChain::max_fork_depth`] blocks below the tip are rejected, and side branches
+//! are pruned once they fall that far behind the tip.
+//!
+//! Only headers are validated, a header chain does not check that the blocks themselves are valid.
+//!
+//! # Examples
+//!
+//! ```
+//! use bitcoin::header_chain::HeaderChain;
+//! use bitcoin::network::Params;
+//!
+//! let chain = HeaderChain::new(&Params::MAINNET);
+//! assert_eq!(chain.height().to_u32(), 0);
+//! assert_eq!(chain.locator(), [chain.tip().block_hash]);
+//! ```
+
+use core::fmt;
+
+use crate::block::{BlockHash, Header, HeaderExt as _, ValidationError};
+use crate::constants::genesis_block;
+use crate::network::Params;
+use crate::pow::{CompactTarget, Target, Work};
+use crate::prelude::{BTreeMap, BTreeSet, Vec};
+use crate::{BlockHeight, BlockMtp, BlockTime};
+
+/// The number of previous blocks used to calculate the median-time-past.
+const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Maximum number of seconds a header's time may be ahead of the current time.
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// Maximum number of seconds the first block of a difficulty period may be behind its parent on
+/// networks enforcing BIP-0094.
+pub const MAX_TIMEWARP: u32 = 600;
+
+/// Default maximum number of blocks below the tip a header may fork from the active chain.
+///
+/// This is the same number of blocks Bitcoin Core allows below its tip in the anti-DoS work
+/// threshold for headers sync.
+pub const DEFAULT_MAX_FORK_DEPTH: u32 = 144;
+
+/// A header in a [`HeaderChain`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainEntry {
+    /// The block header.
+    pub header: Header,
+    /// The hash of `header`.
+    pub block_hash: BlockHash,
+    /// The height of the block.
+    pub height: BlockHeight,
+    /// The total work of the chain up to and including this block.
+    pub chain_work: Work,
+}
+
+/// The result of successfully accepting a header into a [`HeaderChain`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Accepted {
+    /// The header was already known.
+    Duplicate,
+    /// The header extends the active chain and is the new tip.
+    Extended,
+    /// The header is valid but is not part of the most-work chain.
+    SideBranch,
+    /// The header completes a branch with more work than the active chain, which it replaced.
+    Reorg {
+        /// Height of the last block common to the old and the new active chain.
+        fork_height: BlockHeight,
+        /// Hashes of the blocks removed from the active chain, lowest first.
+        disconnected: Vec<BlockHash>,
+        /// Hashes of the blocks added to the active chain, lowest first.
+        connected: Vec<BlockHash>,
+    },
+}
+
+/// A tree of validated block headers with the most-work chain as the active chain.
+#[derive(Debug, Clone)]
+pub struct HeaderChain {
+    params: Params,
+    entries: BTreeMap<BlockHash, ChainEntry>,
+    /// Block hashes of the active chain indexed by height.
+    active: Vec<BlockHash>,
+    /// Heights and hashes of the headers not in the active chain.
+    side: BTreeSet<(BlockHeight, BlockHash)>,
+    max_fork_depth: u32,
+}
+
+impl HeaderChain {
+    /// Constructs a new header chain containing only the genesis block of `params`.
+    pub fn new(params: impl AsRef<Params>) -> Self {
+        let params = params.as_ref().clone();
+        let header = *genesis_block(&params).header();
+        let block_hash = header.block_hash();
+        let genesis =
+            ChainEntry { header, block_hash, height: BlockHeight::ZERO, chain_work: header.work() };
+        let mut entries = BTreeMap::new();
+        entries.insert(block_hash, genesis);
+        HeaderChain {
+            params,
+            entries,
+            active: vec![block_hash],
+            side: BTreeSet::new(),
+            max_fork_depth: DEFAULT_MAX_FORK_DEPTH,
+        }
+    }
+
+    /// Sets the maximum number of blocks below the tip a header may fork from the active chain.
+    ///
+    /// Defaults to [`DEFAULT_MAX_FORK_DEPTH`].
+    pub fn with_max_fork_depth(mut self, max_fork_depth: u32) -> Self {
+        self.max_fork_depth = max_fork_depth;
+        self.prune();
+        self
+    }
+
+    /// Returns the consensus parameters headers are validated against.
+    pub fn params(&self) -> &Params { &self.params }
+
+    /// Returns the maximum number of blocks below the tip a header may fork from the active chain.
+    pub fn max_fork_depth(&self) -> u32 { self.max_fork_depth }
+
+    /// Returns the tip of the active chain.
+    pub fn tip(&self) -> &ChainEntry {
+        let hash = self.active.last().expect("active chain contains genesis");
+        &self.entries[hash]
+    }
+
+    /// Returns the height of the active chain.
+    pub fn height(&self) -> BlockHeight { self.tip().height }
+
+    /// Returns the total number of headers, including headers not in the active chain that have
+    /// not been pruned.
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    /// Returns false, a header chain always contains the genesis block.
+    pub fn is_empty(&self) -> bool { false }
+
+    /// Returns the header with the given hash, whether or not it is in the active chain.
+    pub fn get(&self, block_hash: &BlockHash) -> Option<&ChainEntry> {
+        self.entries.get(block_hash)
+    }
+
+    /// Returns the header at `height` in the active chain.
+    pub fn get_by_height(&self, height: BlockHeight) -> Option<&ChainEntry> {
+        let hash = self.active.get(usize::try_from(height.to_u32()).ok()?)?;
+        Some(&self.entries[hash])
+    }
+
+    /// Returns true if the block with the given hash is in the active chain.
+    pub fn is_active(&self, block_hash: &BlockHash) -> bool {
+        self.entries.get(block_hash).map_or(false, |entry| self.is_active_entry(entry))
+    }
+
+    /// Returns the median-time-past of the block with the given hash.
+    ///
+    /// This is the median time of the block and its 10 ancestors, or of all blocks down to the
+    /// genesis block if there are fewer.
+    pub fn median_time_past(&self, block_hash: &BlockHash) -> Option<BlockMtp> {
+        let mut entry = self.entries.get(block_hash)?;
+        let mut times = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        loop {
+            times.push(entry.header.time.to_u32());
+            if times.len() == MEDIAN_TIME_SPAN {
+                break;
+            }
+            match self.entries.get(&entry.header.prev_blockhash) {
+                Some(prev) => entry = prev,
+                None => break,
+            }
+        }
+        times.sort_unstable();
+        Some(BlockMtp::from_u32(times[times.len() / 2]))
+    }
+
+    /// Returns the target required for a block with the given `time` built on `prev_blockhash`.
+    ///
+    /// Returns `None` if `prev_blockhash` is not in the chain or some of the ancestors needed to
+    /// calculate the target have been pruned.
+    pub fn next_work_required(
+        &self,
+        prev_blockhash: &BlockHash,
+        time: BlockTime,
+    ) -> Option<CompactTarget> {
+        // Follows `GetNextWorkRequired` in Bitcoin Core's `pow.cpp`.
+        let params = &self.params;
+        let prev = self.entries.get(prev_blockhash)?;
+        let interval = self.interval();
+        let pow_limit = params.max_attainable_target.to_compact_lossy();
+
+        if (prev.height.to_u32() + 1) % interval != 0 {
+            if !params.allow_min_difficulty_blocks {
+                return Some(prev.header.bits);
+            }
+            // If the new block's timestamp is more than twice the target spacing after the
+            // previous block then allow mining of a minimum difficulty block.
+            if i64::from(time.to_u32())
+                > i64::from(prev.header.time.to_u32()) + 2 * params.pow_target_spacing as i64
+            {
+                return Some(pow_limit);
+            }
+            // Otherwise return the target of the last block that is not a minimum difficulty block.
+            let mut entry = prev;
+            while entry.height.to_u32() % interval != 0 && entry.header.bits == pow_limit {
+                match self.entries.get(&entry.header.prev_blockhash) {
+                    Some(ancestor) => entry = ancestor,
+                    None => break,
+                }
+            }
+            return Some(entry.header.bits);
+        }
+        if params.no_pow_retargeting {
+            return Some(prev.header.bits);
+        }
+
+        let first_height = BlockHeight::from_u32(prev.height.to_u32() + 1 - interval);
+        let first = self.ancestor(prev, first_height)?;
+        let timespan = i64::from(prev.header.time.to_u32()) - i64::from(first.header.time.to_u32());
+        // BIP-0094 uses the first block of the period to avoid inheriting a minimum difficulty
+        // target from the last block.
+        let bits = if params.enforce_bip94 { first.header.bits } else { prev.header.bits };
+        let min_timespan = i64::from(params.pow_target_timespan / 4);
+        let max_timespan = i64::from(params.pow_target_timespan) * 4;
+        let timespan = timespan.clamp(min_timespan, max_timespan) as u32;
+        let target = Target::from(bits).retarget_saturating(
+            timespan,
+            params.pow_target_timespan,
+            params.max_attainable_target,
+        );
+        Some(target.to_compact_lossy())
+    }
+
+    /// Validates `header` and adds it to the chain.
+    ///
+    /// `now` is the current time, headers more than [`MAX_FUTURE_BLOCK_TIME`] seconds ahead of it
+    /// are rejected. If the header completes a chain with strictly more work than the active
+    /// chain, the active chain is switched to it.
+    ///
+    /// Headers whose branch forks from the active chain more than [`Self::max_fork_depth`]
+    /// blocks below the tip are rejected.
+    pub fn accept(&mut self, header: Header, now: BlockTime) -> Result<Accepted, AcceptError> {
+        let block_hash = header.block_hash();
+        if self.entries.contains_key(&block_hash) {
+            return Ok(Accepted::Duplicate);
+        }
+        let prev = self
+            .entries
+            .get(&header.prev_blockhash)
+            .ok_or(AcceptError::UnknownPrevious(header.prev_blockhash))?;
+        let height = BlockHeight::from_u32(prev.height.to_u32() + 1);
+        // Checked first, the checks below walk the ancestors of the header.
+        if !self.forks_within_max_depth(prev) {
+            return Err(AcceptError::ForkTooDeep(block_hash));
+        }
+
+        let required = self
+            .next_work_required(&header.prev_blockhash, header.time)
+            .expect("previous header is known");
+        match header.validate_pow(Target::from_compact(required)) {
+            Ok(_) => {}
+            Err(ValidationError::BadTarget) =>
+                return Err(AcceptError::BadTarget { required, actual: header.bits }),
+            Err(_) => return Err(AcceptError::BadProofOfWork(block_hash)),
+        }
+
+        let median_time_past =
+            self.median_time_past(&header.prev_blockhash).expect("previous header is known");
+        if header.time.to_u32() <= median_time_past.to_u32() {
+            return Err(AcceptError::TimeTooOld { time: header.time, median_time_past });
+        }
+        if u64::from(header.time.to_u32())
+            > u64::from(now.to_u32()) + u64::from(MAX_FUTURE_BLOCK_TIME)
+        {
+            return Err(AcceptError::TimeTooNew { time: header.time, now });
+        }
+        if self.params.enforce_bip94
+            && height.to_u32() % self.interval() == 0
+            && i64::from(header.time.to_u32())
+                < i64::from(prev.header.time.to_u32()) - i64::from(MAX_TIMEWARP)
+        {
+            return Err(AcceptError::TimeWarp { time: header.time, prev_time: prev.header.time });
+        }
+
+        let version = header.version.to_consensus();
+        let params = &self.params;
+        if (version < 2 && height >= params.bip34_height)
+            || (version < 3 && height >= params.bip66_height)
+            || (version < 4 && height >= params.bip65_height)
+        {
+            return Err(AcceptError::ObsoleteVersion { version, height });
+        }
+
+        let entry =
+            ChainEntry { header, block_hash, height, chain_work: prev.chain_work + header.work() };
+        let extends_tip = header.prev_blockhash == self.tip().block_hash;
+        let more_work = entry.chain_work > self.tip().chain_work;
+        self.entries.insert(block_hash, entry);
+
+        if !more_work {
+            self.side.insert((height, block_hash));
+            return Ok(Accepted::SideBranch);
+        }
+        if extends_tip {
+            self.active.push(block_hash);
+            self.prune();
+            return Ok(Accepted::Extended);
+        }
+
+        let mut connected = Vec::new();
+        let mut entry = &self.entries[&block_hash];
+        while !self.is_active_entry(entry) {
+            connected.push(entry.block_hash);
+            entry = &self.entries[&entry.header.prev_blockhash];
+        }
+        connected.reverse();
+        let fork_height = entry.height;
+        let disconnected = self.active.split_off(fork_height.to_u32() as usize + 1);
+        self.active.extend_from_slice(&connected);
+        for hash in &connected {
+            self.side.remove(&(self.entries[hash].height, *hash));
+        }
+        for hash in &disconnected {
+            self.side.insert((self.entries[hash].height, *hash));
+        }
+        self.prune();
+        Ok(Accepted::Reorg { fork_height, disconnected, connected })
+    }
+
+    /// Returns a block locator for the active chain, suitable for `getheaders` messages.
+    ///
+    /// The locator contains the hashes of the last 10 blocks followed by hashes exponentially
+    /// further apart, ending with the genesis block.
+    pub fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = Vec::new();
+        let mut height = self.active.len() - 1;
+        let mut step = 1;
+        loop {
+            locator.push(self.active[height]);
+            if height == 0 {
+                break;
+            }
+            height = height.saturating_sub(step);
+            if locator.len() > 10 {
+                step *= 2;
+            }
+        }
+        locator
+    }
+
+    /// Returns the number of blocks between difficulty adjustments.
+    fn interval(&self) -> u32 {
+        u32::try_from(self.params.difficulty_adjustment_interval())
+            .expect("difficulty adjustment interval fits in a u32")
+    }
+
+    fn is_active_entry(&self, entry: &ChainEntry) -> bool {
+        self.active.get(entry.height.to_u32() as usize) == Some(&entry.block_hash)
+    }
+
+    /// Returns the lowest height a header may fork from the active chain at.
+    fn min_fork_height(&self) -> BlockHeight {
+        BlockHeight::from_u32(self.height().to_u32().saturating_sub(self.max_fork_depth))
+    }
+
+    /// Returns true if a header built on `prev` forks from the active chain at or above
+    /// [`Self::min_fork_height`].
+    fn forks_within_max_depth<'a>(&'a self, mut prev: &'a ChainEntry) -> bool {
+        let min_height = self.min_fork_height();
+        while !self.is_active_entry(prev) {
+            // The parent is below the minimum height, or has been pruned.
+            if prev.height <= min_height {
+                return false;
+            }
+            match self.entries.get(&prev.header.prev_blockhash) {
+                Some(parent) => prev = parent,
+                None => return false,
+            }
+        }
+        prev.height >= min_height
+    }
+
+    /// Removes the headers not in the active chain that are below [`Self::min_fork_height`].
+    ///
+    /// Their descendants above it can no longer be extended, see
+    /// [`Self::forks_within_max_depth`], and are removed once they fall below it too.
+    fn prune(&mut self) {
+        let min_height = self.min_fork_height();
+        while let Some(&(height, hash)) = self.side.iter().next() {
+            if height >= min_height {
+                break;
+            }
+            self.side.remove(&(height, hash));
+            self.entries.remove(&hash);
+        }
+    }
+
+    /// Returns the ancestor of `entry` at `height`, or `None` if `height` is above `entry` or an
+    /// ancestor has been pruned.
+    fn ancestor<'a>(
+        &'a self,
+        mut entry: &'a ChainEntry,
+        height: BlockHeight,
+    ) -> Option<&'a ChainEntry> {
+        if height > entry.height {
+            return None;
+        }
+        while entry.height != height {
+            if self.is_active_entry(entry) {
+                return self.get_by_height(height);
+            }
+            entry = self.entries.get(&entry.header.prev_blockhash)?;
+        }
+        Some(entry)
+    }
+}
+
+/// An error accepting a header into a [`HeaderChain`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AcceptError {
+    /// The header's parent is not in the chain.
+    UnknownPrevious(BlockHash),
+    /// The header's target is not the one required at its position in the chain.
+    BadTarget {
+        /// The required target.
+        required: CompactTarget,
+        /// The target in the header.
+        actual: CompactTarget,
+    },
+    /// The header's hash does not meet its target.
+    BadProofOfWork(BlockHash),
+    /// The header's time is not after the median-time-past of its parent.
+    TimeTooOld {
+        /// The time in the header.
+        time: BlockTime,
+        /// The median-time-past of the parent.
+        median_time_past: BlockMtp,
+    },
+    /// The header's time is too far in the future.
+    TimeTooNew {
+        /// The time in the header.
+        time: BlockTime,
+        /// The current time.
+        now: BlockTime,
+    },
+    /// The header starts a difficulty period too far before its parent (BIP-0094).
+    TimeWarp {
+        /// The time in the header.
+        time: BlockTime,
+        /// The time in the parent header.
+        prev_time: BlockTime,
+    },
+    /// The header's version is below the minimum required at its height.
+    ObsoleteVersion {
+        /// The version in the header.
+        version: i32,
+        /// The height of the header.
+        height: BlockHeight,
+    },
+    /// The header's branch forks from the active chain too far below the tip.
+    ForkTooDeep(BlockHash),
+}
+
+impl fmt::Display for AcceptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use AcceptError::*;
+
+        match *self {
+            UnknownPrevious(ref hash) => write!(f, "previous block {} is unknown", hash),
+            BadTarget { required, actual } => write!(
+                f,
+                "incorrect target {:#010x}, required {:#010x}",
+                actual.to_consensus(),
+                required.to_consensus()
+            ),
+            BadProofOfWork(ref hash) => write!(f, "block hash {} does not meet its target", hash),
+            TimeTooOld { time, median_time_past } => write!(
+                f,
+                "block time {} is not after the median time past {}",
+                time.to_u32(),
+                median_time_past.to_u32()
+            ),
+            TimeTooNew { time, now } => write!(
+                f,
+                "block time {} is too far after the current time {}",
+                time.to_u32(),
+                now.to_u32()
+            ),
+            TimeWarp { time, prev_time } => write!(
+                f,
+                "block time {} is too far before the previous block time {}",
+                time.to_u32(),
+                prev_time.to_u32()
+            ),
+            ObsoleteVersion { version, height } =>
+                write!(f, "block version {} is obsolete at height {}", version, height),
+            ForkTooDeep(ref hash) =>
+                write!(f, "block {} forks from the active chain too far below the tip", hash),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AcceptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use AcceptError::*;
+
+        match *self {
+            UnknownPrevious(_)
+            | BadTarget { .. }
+            | BadProofOfWork(_)
+            | TimeTooOld { .. }
+            | TimeTooNew { .. }
+            | TimeWarp { .. }
+            | ObsoleteVersion { .. }
+            | ForkTooDeep(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Version;
+    use crate::TxMerkleNode;
+
+    const NOW: BlockTime = BlockTime::from_u32(u32::MAX - MAX_FUTURE_BLOCK_TIME);
+
+    /// Regtest parameters with retargeting every 10 blocks.
+    fn retarget_params() -> Params {
+        let mut params = Params::REGTEST;
+        params.pow_target_timespan = 10 * 10 * 60;
+        params.no_pow_retargeting = false;
+        params.allow_min_difficulty_blocks = false;
+        params
+    }
+
+    /// Mines a header on `prev` with the given time, target and branch tag.
+    fn mine(prev: &BlockHash, time: u32, bits: CompactTarget, tag: u8) -> Header {
+        let mut header = Header {
+            version: Version::from_consensus(4),
+            prev_blockhash: *prev,
+            merkle_root: TxMerkleNode::from_byte_array([tag; 32]),
+            time: BlockTime::from_u32(time),
+            bits,
+            nonce: 0,
+        };
+        while header.validate_pow(header.target()).is_err() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    /// Mines and accepts a header on the tip with the required target.
+    fn extend(chain: &mut HeaderChain, time: u32, tag: u8) -> Header {
+        let prev = chain.tip().block_hash;
+        let bits = chain.next_work_required(&prev, BlockTime::from_u32(time)).unwrap();
+        let header = mine(&prev, time, bits, tag);
+        assert_eq!(chain.accept(header, NOW), Ok(Accepted::Extended));
+        header
+    }
+
+    fn tip_time(chain: &HeaderChain) -> u32 { chain.tip().header.time.to_u32() }
+
+    #[test]
+    fn genesis() {
+        let chain = HeaderChain::new(&Params::MAINNET);
+        let genesis = genesis_block(&Params::MAINNET);
+        assert_eq!(chain.tip().block_hash, genesis.block_hash());
+        assert_eq!(chain.height(), BlockHeight::ZERO);
+        assert_eq!(chain.tip().chain_work, genesis.header().work());
+        assert_eq!(chain.len(), 1);
+    }
+
+    #[test]
+    fn extend_active_chain() {
+        let mut chain = HeaderChain::new(&Params::REGTEST);
+        let genesis_work = chain.tip().chain_work;
+        let mut hashes = vec![chain.tip().block_hash];
+        for _ in 0..5 {
+            let time = tip_time(&chain) + 600;
+            hashes.push(extend(&mut chain, time, 0).block_hash());
+        }
+
+        assert_eq!(chain.height(), BlockHeight::from_u32(5));
+        let parent_work = chain.get_by_height(BlockHeight::from_u32(4)).unwrap().chain_work;
+        assert_eq!(chain.tip().chain_work, parent_work + chain.tip().header.work());
+        assert!(chain.tip().chain_work > genesis_work);
+        for (height, hash) in hashes.iter().enumerate() {
+            let entry = chain.get_by_height(BlockHeight::from_u32(height as u32)).unwrap();
+            assert_eq!(entry.block_hash, *hash);
+            assert!(chain.is_active(hash));
+        }
+        let header = chain.tip().header;
+        assert_eq!(chain.accept(header, NOW), Ok(Accepted::Duplicate));
+    }
+
+    #[test]
+    fn unknown_previous() {
+        let mut chain = HeaderChain::new(&Params::REGTEST);
+        let prev = BlockHash::from_byte_array([1; 32]);
+        let header = mine(&prev, tip_time(&chain) + 600, chain.tip().header.bits, 0);
+        assert_eq!(chain.accept(header, NOW), Err(AcceptError::UnknownPrevious(prev)));
+    }
+
+    #[test]
+    fn bad_target_and_proof_of_work() {
+        let mut chain = HeaderChain::new(&Params::REGTEST);
+        let genesis = chain.tip().block_hash;
+        let time = tip_time(&chain) + 600;
+
+        let bits = CompactTarget::from_consensus(0x1f7fffff);
+        let header = mine(&genesis, time, bits, 0);
+        let required = chain.tip().header.bits;
+        assert_eq!(
+            chain.accept(header, NOW),
+            Err(AcceptError::BadTarget { required, actual: bits })
+        );
+
+        let mut header = mine(&genesis, time, required, 0);
+        while header.validate_pow(header.target()).is_ok() {
+            header.nonce += 1;
+        }
+        assert_eq!(
+            chain.accept(header, NOW),
+            Err(AcceptError::BadProofOfWork(header.block_hash()))
+        );
+        assert_eq!(chain.len(), 1);
+    }
+
+    #[test]
+    fn time_limits() {
+        let mut chain = HeaderChain::new(&Params::REGTEST);
+        let start = tip_time(&chain);
+        for i in 1..=11 {
+            extend(&mut chain, start + i * 600, 0);
+        }
+        let tip = chain.tip().block_hash;
+        let bits = chain.tip().header.bits;
+        // Median of the times of blocks 1 to 11.
+        let median_time_past = chain.median_time_past(&tip).unwrap();
+        assert_eq!(median_time_past.to_u32(), start + 6 * 600);
+
+        let header = mine(&tip, start + 6 * 600, bits, 0);
+        assert_eq!(
+            chain.accept(header, NOW),
+            Err(AcceptError::TimeTooOld { time: header.time, median_time_past })
+        );
+        // Times before the tip but after the median are fine.
+        let header = mine(&tip, start + 6 * 600 + 1, bits, 0);
+        assert_eq!(chain.accept(header, NOW), Ok(Accepted::Extended));
+
+        let tip = chain.tip().block_hash;
+        let now = BlockTime::from_u32(start + 20 * 600);
+        let header = mine(&tip, now.to_u32() + MAX_FUTURE_BLOCK_TIME + 1, bits, 0);
+        assert_eq!(
+            chain.accept(header, now),
+            Err(AcceptError::TimeTooNew { time: header.time, now })
+        );
+        let header = mine(&tip, now.to_u32() + MAX_FUTURE_BLOCK_TIME, bits, 0);
+        assert_eq!(chain.accept(header, now), Ok(Accepted::Extended));
+    }
+
+    #[test]
+    fn obsolete_version() {
+        let mut params = Params::REGTEST;
+        params.bip34_height = BlockHeight::from_u32(2);
+        let mut chain = HeaderChain::new(&params);
+        let bits = chain.tip().header.bits;
+        let start = tip_time(&chain);
+        let mine_v1 = |prev: &BlockHash, time: u32| {
+            let mut header = mine(prev, time, bits, 0);
+            header.version = Version::ONE;
+            while header.validate_pow(header.target()).is_err() {
+                header.nonce += 1;
+            }
+            header
+        };
+
+        let header = mine_v1(&chain.tip().block_hash, start + 600);
+        assert_eq!(chain.accept(header, NOW), Ok(Accepted::Extended));
+        let header = mine_v1(&chain.tip().block_hash, start + 1200);
+        assert_eq!(
+            chain.accept(header, NOW),
+            Err(AcceptError::ObsoleteVersion { version: 1, height: BlockHeight::from_u32(2) })
+        );
+    }
+
+    #[test]
+    fn retarget() {
+        let params = retarget_params();
+        let mut chain = HeaderChain::new(&params);
+        let start = tip_time(&chain);
+        let pow_limit = chain.tip().header.bits;
+
+        // Blocks slower than the target spacing keep the difficulty at the limit.
+        for i in 1..10 {
+            extend(&mut chain, start + i * 700, 0);
+        }
+        let header = extend(&mut chain, start + 10 * 700, 0);
+        assert_eq!(header.bits, pow_limit);
+
+        // Blocks one second apart increase the difficulty at the next retarget.
+        let period_start = tip_time(&chain);
+        for i in 1..10 {
+            let header = extend(&mut chain, period_start + i, 0);
+            assert_eq!(header.bits, pow_limit);
+        }
+        let time = BlockTime::from_u32(period_start + 10);
+        let bits = chain.next_work_required(&chain.tip().block_hash, time).unwrap();
+        // The target can decrease by at most a factor of four, `0x207fffff / 4`.
+        assert_eq!(bits, CompactTarget::from_consensus(0x201fffff));
+
+        let header = mine(&chain.tip().block_hash, time.to_u32(), pow_limit, 0);
+        assert!(matches!(chain.accept(header, NOW), Err(AcceptError::BadTarget { .. })));
+        let header = extend(&mut chain, time.to_u32(), 0);
+        assert_eq!(header.bits, bits);
+        assert!(header.work() > genesis_block(&params).header().work());
+    }
+
+    #[test]
+    fn no_pow_retargeting() {
+        let mut params = retarget_params();
+        params.no_pow_retargeting = true;
+        let mut chain = HeaderChain::new(&params);
+        let start = tip_time(&chain);
+        let pow_limit = chain.tip().header.bits;
+
+        // Blocks one second apart would otherwise increase the difficulty at the retarget.
+        for i in 1..10 {
+            extend(&mut chain, start + i, 0);
+        }
+        let time = BlockTime::from_u32(start + 10);
+        assert_eq!(chain.next_work_required(&chain.tip().block_hash, time), Some(pow_limit));
+        let header = extend(&mut chain, time.to_u32(), 0);
+        assert_eq!(header.bits, pow_limit);
+    }
+
+    #[test]
+    fn min_difficulty_blocks() {
+        let mut params = retarget_params();
+        params.allow_min_difficulty_blocks = true;
+        let mut chain = HeaderChain::new(&params);
+        let pow_limit = chain.tip().header.bits;
+        for _ in 0..10 {
+            let time = tip_time(&chain) + 1;
+            extend(&mut chain, time, 0);
+        }
+        let bits = chain.tip().header.bits;
+        assert_ne!(bits, pow_limit);
+
+        // A block more than 20 minutes after its parent may be mined at minimum difficulty.
+        let time = tip_time(&chain) + 1201;
+        let header = extend(&mut chain, time, 0);
+        assert_eq!(header.bits, pow_limit);
+        // The next block returns to the last target that was not the minimum.
+        let time = tip_time(&chain) + 1;
+        let header = extend(&mut chain, time, 0);
+        assert_eq!(header.bits, bits);
+        // A block exactly 20 minutes after its parent does not qualify.
+        let time = tip_time(&chain) + 1200;
+        let header = mine(&chain.tip().block_hash, time, pow_limit, 0);
+        assert_eq!(
+            chain.accept(header, NOW),
+            Err(AcceptError::BadTarget { required: bits, actual: pow_limit })
+        );
+    }
+
+    #[test]
+    fn bip94() {
+        let mut params = retarget_params();
+        params.allow_min_difficulty_blocks = true;
+        params.enforce_bip94 = true;
+        let mut chain = HeaderChain::new(&params);
+        for _ in 0..10 {
+            let time = tip_time(&chain) + 1;
+            extend(&mut chain, time, 0);
+        }
+        let first_bits = chain.tip().header.bits;
+        let first_time = tip_time(&chain);
+        for _ in 1..9 {
+            let time = tip_time(&chain) + 600;
+            extend(&mut chain, time, 0);
+        }
+        // The last block of the period is a minimum difficulty block.
+        let time = tip_time(&chain) + 1201;
+        let last = extend(&mut chain, time, 0);
+        assert_eq!(last.bits, params.max_attainable_target.to_compact_lossy());
+
+        // The retarget is based on the first block of the period.
+        let tip = chain.tip().block_hash;
+        let time = tip_time(&chain);
+        let bits = chain.next_work_required(&tip, BlockTime::from_u32(time + 1)).unwrap();
+        assert_eq!(first_bits, CompactTarget::from_consensus(0x201fffff));
+        assert_eq!(time - first_time, 6001);
+        // `0x1fffff * 6001 / 6000`
+        assert_eq!(bits, CompactTarget::from_consensus(0x2020015c));
+
+        // The first block of a period may not be more than 10 minutes before its parent.
+        let header = mine(&tip, time - MAX_TIMEWARP - 1, bits, 0);
+        assert_eq!(
+            chain.accept(header, NOW),
+            Err(AcceptError::TimeWarp { time: header.time, prev_time: last.time })
+        );
+        let header = mine(&tip, time - MAX_TIMEWARP, bits, 0);
+        assert_eq!(chain.accept(header, NOW), Ok(Accepted::Extended));
+    }
+
+    #[test]
+    fn reorg_to_most_work() {
+        let mut chain = HeaderChain::new(&Params::REGTEST);
+        let bits = chain.tip().header.bits;
+        let start = tip_time(&chain);
+        let genesis = chain.tip().block_hash;
+        let a1 = extend(&mut chain, start + 600, 0).block_hash();
+        let a2 = extend(&mut chain, start + 1200, 0).block_hash();
+        let a3 = extend(&mut chain, start + 1800, 0).block_hash();
+
+        // A competing branch from block 1 with equal work does not replace the active chain.
+        let b2 = mine(&a1, start + 1201, bits, 1);
+        let b3 = mine(&b2.block_hash(), start + 1801, bits, 1);
+        assert_eq!(chain.accept(b2, NOW), Ok(Accepted::SideBranch));
+        assert_eq!(chain.accept(b3, NOW), Ok(Accepted::SideBranch));
+        assert_eq!(chain.tip().block_hash, a3);
+        assert!(!chain.is_active(&b2.block_hash()));
+        assert_eq!(chain.get(&b3.block_hash()).unwrap().height, BlockHeight::from_u32(3));
+
+        // Once it has more work it does.
+        let b4 = mine(&b3.block_hash(), start + 2401, bits, 1);
+        assert_eq!(
+            chain.accept(b4, NOW),
+            Ok(Accepted::Reorg {
+                fork_height: BlockHeight::from_u32(1),
+                disconnected: vec![a2, a3],
+                connected: vec![b2.block_hash(), b3.block_hash(), b4.block_hash()],
+            })
+        );
+        assert_eq!(chain.height(), BlockHeight::from_u32(4));
+        assert_eq!(chain.tip().block_hash, b4.block_hash());
+        assert!(chain.is_active(&b2.block_hash()));
+        assert!(!chain.is_active(&a2));
+        assert!(chain.is_active(&genesis));
+        assert_eq!(chain.len(), 7);
+
+        // The old branch can be extended again.
+        let a4 = mine(&a3, start + 2400, bits, 0);
+        assert_eq!(chain.accept(a4, NOW), Ok(Accepted::SideBranch));
+        let a5 = mine(&a4.block_hash(), start + 3000, bits, 0);
+        assert!(matches!(chain.accept(a5, NOW), Ok(Accepted::Reorg { .. })));
+        assert_eq!(chain.tip().block_hash, a5.block_hash());
+    }
+
+    #[test]
+    fn fork_depth() {
+        let mut chain = HeaderChain::new(&Params::REGTEST).with_max_fork_depth(2);
+        assert_eq!(chain.max_fork_depth(), 2);
+        let bits = chain.tip().header.bits;
+        let start = tip_time(&chain);
+        let a = (1..=5).map(|i| extend(&mut chain, start + i * 600, 0)).collect::<Vec<_>>();
+        let hash = |height: usize| a[height - 1].block_hash();
+
+        // Forks may start at most two blocks below the tip at height 5.
+        let b3 = mine(&hash(2), start + 1801, bits, 1);
+        assert_eq!(chain.accept(b3, NOW), Err(AcceptError::ForkTooDeep(b3.block_hash())));
+        let b4 = mine(&hash(3), start + 2401, bits, 1);
+        assert_eq!(chain.accept(b4, NOW), Ok(Accepted::SideBranch));
+        let b5 = mine(&b4.block_hash(), start + 3001, bits, 1);
+        assert_eq!(chain.accept(b5, NOW), Ok(Accepted::SideBranch));
+        assert_eq!(chain.len(), 8);
+
+        // Once the tip moves on the branch can no longer be extended.
+        extend(&mut chain, start + 3600, 0);
+        let b6 = mine(&b5.block_hash(), start + 3601, bits, 1);
+        assert_eq!(chain.accept(b6, NOW), Err(AcceptError::ForkTooDeep(b6.block_hash())));
+        assert_eq!(chain.len(), 9);
+        // And it is pruned as it falls behind.
+        extend(&mut chain, start + 4200, 0);
+        assert!(chain.get(&b4.block_hash()).is_none());
+        assert!(chain.get(&b5.block_hash()).is_some());
+        assert_eq!(chain.len(), 9);
+        extend(&mut chain, start + 4800, 0);
+        assert!(chain.get(&b5.block_hash()).is_none());
+        assert_eq!(chain.len(), 9);
+        assert_eq!(chain.accept(b6, NOW), Err(AcceptError::UnknownPrevious(b5.block_hash())));
+    }
+
+    #[test]
+    fn prune_disconnected() {
+        let mut chain = HeaderChain::new(&Params::REGTEST).with_max_fork_depth(1);
+        let bits = chain.tip().header.bits;
+        let start = tip_time(&chain);
+        let a1 = extend(&mut chain, start + 600, 0).block_hash();
+        let a2 = extend(&mut chain, start + 1200, 0).block_hash();
+
+        // A reorg turns the old tip into a side branch.
+        let b2 = mine(&a1, start + 1201, bits, 1);
+        assert_eq!(chain.accept(b2, NOW), Ok(Accepted::SideBranch));
+        let b3 = mine(&b2.block_hash(), start + 1801, bits, 1);
+        assert!(matches!(chain.accept(b3, NOW), Ok(Accepted::Reorg { .. })));
+        assert!(chain.get(&a2).is_some());
+
+        // Which is pruned once it is more than one block below the tip.
+        extend(&mut chain, start + 2400, 1);
+        assert!(chain.get(&a2).is_none());
+        assert!(chain.is_active(&b2.block_hash()));
+        assert_eq!(chain.len(), 5);
+    }
+
+    #[test]
+    fn locator() {
+        let mut chain = HeaderChain::new(&Params::REGTEST);
+        let start = tip_time(&chain);
+        for i in 1..=30 {
+            extend(&mut chain, start + i * 600, 0);
+        }
+        let heights = chain
+            .locator()
+            .iter()
+            .map(|hash| chain.get(hash).unwrap().height.to_u32())
+            .collect::<Vec<_>>();
+        assert_eq!(heights, [30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 17, 13, 5, 0]);
+    }
+}
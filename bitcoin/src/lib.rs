@@ -125,14 +125,21 @@ pub mod ext {
 pub mod address;
 pub mod bip152;
 pub mod bip158;
+pub mod bip21;
 pub mod bip32;
+pub mod bip322;
+pub mod bip352;
+pub mod bip39;
 pub mod blockdata;
+pub mod coin_selection;
 pub mod consensus;
 #[cfg(feature = "bitcoinconsensus")]
 pub mod consensus_validation;
 // Private until we either make this a crate or flatten it - still to be decided.
 pub(crate) mod crypto;
+pub mod descriptor;
 pub mod hash_types;
+pub mod header_chain;
 pub mod merkle_tree;
 pub mod network;
 pub mod policy;
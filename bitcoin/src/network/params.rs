@@ -109,6 +109,8 @@ pub struct Params {
     pub allow_min_difficulty_blocks: bool,
     /// Determines whether retargeting is disabled for this network or not.
     pub no_pow_retargeting: bool,
+    /// Determines whether the BIP-0094 difficulty adjustment and timewarp rules are enforced.
+    pub enforce_bip94: bool,
 }
 
 /// The mainnet parameters.
@@ -151,6 +153,7 @@ impl Params {
         pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
         allow_min_difficulty_blocks: false,
         no_pow_retargeting: false,
+        enforce_bip94: false,
     };
 
     /// The testnet3 parameters.
@@ -169,6 +172,7 @@ impl Params {
         pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
         allow_min_difficulty_blocks: true,
         no_pow_retargeting: false,
+        enforce_bip94: false,
     };
 
     /// The testnet3 parameters.
@@ -186,6 +190,7 @@ impl Params {
         pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
         allow_min_difficulty_blocks: true,
         no_pow_retargeting: false,
+        enforce_bip94: false,
     };
 
     /// The testnet4 parameters.
@@ -203,6 +208,7 @@ impl Params {
         pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
         allow_min_difficulty_blocks: true,
         no_pow_retargeting: false,
+        enforce_bip94: true,
     };
 
     /// The signet parameters.
@@ -220,6 +226,7 @@ impl Params {
         pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
         allow_min_difficulty_blocks: false,
         no_pow_retargeting: false,
+        enforce_bip94: false,
     };
 
     /// The regtest parameters.
@@ -237,6 +244,7 @@ impl Params {
         pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
         allow_min_difficulty_blocks: true,
         no_pow_retargeting: true,
+        enforce_bip94: false,
     };
 
     /// Constructs parameters set for the given network.
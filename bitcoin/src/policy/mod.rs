@@ -11,10 +11,25 @@
 //!
 //! These values were taken from bitcoind v0.21.1 (194b9b8792d9b0798fdb570b79fa51f1d1f5ebaf).
 
+pub mod package;
+pub mod rbf;
+pub mod standard;
+
 use core::cmp;
 
 use super::constants::{MAX_BLOCK_SIGOPS_COST, WITNESS_SCALE_FACTOR};
 
+#[rustfmt::skip]                // Keep public re-exports separate.
+#[doc(inline)]
+pub use self::{
+    package::{check_package, PackageError, PackageTx},
+    rbf::{Conflict, ReplacementError, ReplacementPolicy},
+    standard::{
+        check_standard, check_standard_inputs, check_standard_tx, is_standard_script_pubkey,
+        NonStandardError,
+    },
+};
+
 /// Maximum weight of a transaction for it to be relayed by most nodes on the network
 pub const MAX_STANDARD_TX_WEIGHT: u32 = 400_000;
 
@@ -24,6 +39,24 @@
 /// Maximum number of sigops in a standard tx.
 pub const MAX_STANDARD_TX_SIGOPS_COST: u32 = MAX_BLOCK_SIGOPS_COST as u32 / 5;
 
+/// Maximum size of a standard scriptSig, large enough for a 15-of-15 multisig P2SH spend.
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+
+/// Maximum number of sigops in a standard P2SH redeem script.
+pub const MAX_P2SH_SIGOPS: usize = 15;
+
+/// Maximum size of a standard P2WSH witness script.
+pub const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+
+/// Maximum number of stack items in a standard P2WSH witness, not counting the witness script.
+pub const MAX_STANDARD_P2WSH_STACK_ITEMS: usize = 100;
+
+/// Maximum size of a stack item in a standard P2WSH witness, not counting the witness script.
+pub const MAX_STANDARD_P2WSH_STACK_ITEM_SIZE: usize = 80;
+
+/// Maximum size of a stack item in a standard tapscript witness.
+pub const MAX_STANDARD_TAPSCRIPT_STACK_ITEM_SIZE: usize = 80;
+
 /// The minimum incremental *feerate* (despite the name), in sats per virtual kilobyte for RBF.
 pub const DEFAULT_INCREMENTAL_RELAY_FEE: u32 = 1_000;
 
@@ -0,0 +1,544 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Package relay and TRUC policy.
+//!
+//! Implements the rules Bitcoin Core applies to packages of a child transaction and its parents,
+//! as relayed between peers (one parent and one child) and accepted by `submitpackage`. This
+//! includes the rules for topologically restricted until confirmation (TRUC, [BIP-0431])
+//! transactions and for ephemeral dust, such as the zero value pay-to-anchor outputs used with
+//! CPFP fee bumping.
+//!
+//! [BIP-0431]: <https://github.com/bitcoin/bips/blob/master/bip-0431.mediawiki>
+
+use core::fmt;
+
+use internals::write_err;
+
+use super::standard::check_standard_tx_with_dust;
+use super::{NonStandardError, DEFAULT_MIN_RELAY_TX_FEE};
+use crate::prelude::{BTreeSet, Vec};
+use crate::script::ScriptPubKeyExt as _;
+use crate::transaction::{self, OutPoint, Transaction, TransactionExt as _, Txid};
+use crate::{Amount, FeeRate, Weight};
+
+/// Maximum number of transactions in a package.
+pub const MAX_PACKAGE_COUNT: usize = 25;
+
+/// Maximum total weight of the transactions in a package.
+pub const MAX_PACKAGE_WEIGHT: Weight = Weight::from_wu(404_000);
+
+/// Maximum virtual size of a TRUC transaction.
+pub const TRUC_MAX_VSIZE: usize = 10_000;
+
+/// Maximum virtual size of a TRUC transaction with an unconfirmed parent.
+pub const TRUC_CHILD_MAX_VSIZE: usize = 1_000;
+
+/// Maximum number of dust outputs in a transaction, which must pay no fee.
+pub const MAX_DUST_OUTPUTS_PER_TX: usize = 1;
+
+/// A transaction in a package and the fee it pays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackageTx<'a> {
+    /// The transaction.
+    pub tx: &'a Transaction,
+    /// The fee paid by `tx`.
+    pub fee: Amount,
+}
+
+impl<'a> PackageTx<'a> {
+    /// Constructs a new package transaction.
+    pub fn new(tx: &'a Transaction, fee: Amount) -> Self { PackageTx { tx, fee } }
+}
+
+/// Returns true if `tx` opts in to the TRUC rules by using transaction version 3.
+pub fn is_truc(tx: &Transaction) -> bool { tx.version == transaction::Version::THREE }
+
+/// Checks a package of a child transaction and its parents.
+///
+/// The child must be last and every other transaction must be a parent of the child. Parents must
+/// come in an order in which they can be submitted, and must not spend each other. All inputs
+/// spending transactions not in the package are assumed to be confirmed.
+///
+/// Each transaction is checked with [`check_standard_tx`], except that a transaction paying no fee
+/// may have a single dust output if a transaction in the package spends it.
+///
+/// [`check_standard_tx`]: super::check_standard_tx
+pub fn check_package(package: &[PackageTx<'_>]) -> Result<(), PackageError> {
+    if package.is_empty() {
+        return Err(PackageError::Empty);
+    }
+    let txids = package.iter().map(|p| p.tx.compute_txid()).collect::<Vec<_>>();
+    check_well_formed(package, &txids)?;
+    check_child_with_parents(package, &txids)?;
+
+    for (index, p) in package.iter().enumerate() {
+        let max_dust = if p.fee == Amount::ZERO { MAX_DUST_OUTPUTS_PER_TX } else { 0 };
+        check_standard_tx_with_dust(p.tx, max_dust)
+            .map_err(|error| PackageError::NonStandard { index, error })?;
+    }
+
+    check_truc(package, &txids)?;
+    check_ephemeral_spends(package, &txids)?;
+
+    let vsize = package.iter().map(|p| p.tx.vsize() as u64).sum::<u64>();
+    let fee = package.iter().map(|p| p.fee.to_sat()).fold(0, u64::saturating_add);
+    let fee = Amount::from_sat(fee).unwrap_or(Amount::MAX);
+    let minimum_fee = FeeRate::from_sat_per_kvb(DEFAULT_MIN_RELAY_TX_FEE)
+        .to_fee(Weight::from_vb_unchecked(vsize));
+    if fee < minimum_fee {
+        return Err(PackageError::FeeTooLow { fee, minimum_fee });
+    }
+
+    Ok(())
+}
+
+/// Checks the package size and that its transactions are sorted and do not conflict.
+fn check_well_formed(package: &[PackageTx<'_>], txids: &[Txid]) -> Result<(), PackageError> {
+    if package.len() > MAX_PACKAGE_COUNT {
+        return Err(PackageError::TooManyTransactions(package.len()));
+    }
+    let weight = package.iter().map(|p| p.tx.weight()).fold(Weight::ZERO, |a, b| a + b);
+    if weight > MAX_PACKAGE_WEIGHT {
+        return Err(PackageError::TooLarge(weight));
+    }
+
+    let mut seen = BTreeSet::new();
+    let mut spent = BTreeSet::new();
+    for (index, (p, txid)) in package.iter().zip(txids).enumerate() {
+        if !seen.insert(txid) {
+            return Err(PackageError::Duplicate(*txid));
+        }
+        for txin in &p.tx.inputs {
+            if txids[index + 1..].contains(&txin.previous_output.txid) {
+                return Err(PackageError::NotSorted { index });
+            }
+            if !spent.insert(txin.previous_output) {
+                return Err(PackageError::Conflict { index, outpoint: txin.previous_output });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the last transaction spends all others, which do not spend each other.
+fn check_child_with_parents(package: &[PackageTx<'_>], txids: &[Txid]) -> Result<(), PackageError> {
+    let (child, parents) = package.split_last().expect("package is not empty");
+    let parent_txids = &txids[..parents.len()];
+    for (index, (p, txid)) in parents.iter().zip(parent_txids).enumerate() {
+        let spent_by_child = child.tx.inputs.iter().any(|txin| txin.previous_output.txid == *txid);
+        let spends_parent =
+            p.tx.inputs.iter().any(|txin| parent_txids.contains(&txin.previous_output.txid));
+        if !spent_by_child || spends_parent {
+            return Err(PackageError::NotChildWithParents { index });
+        }
+    }
+    Ok(())
+}
+
+/// Checks the TRUC rules, a TRUC transaction may only have one unconfirmed parent which must also
+/// be a TRUC transaction, and a non-TRUC transaction may not spend an unconfirmed TRUC transaction.
+fn check_truc(package: &[PackageTx<'_>], txids: &[Txid]) -> Result<(), PackageError> {
+    for (index, p) in package.iter().enumerate() {
+        let parents = package[..index]
+            .iter()
+            .zip(txids)
+            .filter(|(_, txid)| p.tx.inputs.iter().any(|txin| txin.previous_output.txid == **txid))
+            .map(|(parent, _)| parent)
+            .collect::<Vec<_>>();
+
+        if !is_truc(p.tx) {
+            if parents.iter().any(|parent| is_truc(parent.tx)) {
+                return Err(PackageError::NonTrucSpendsTruc { index });
+            }
+            continue;
+        }
+
+        if parents.iter().any(|parent| !is_truc(parent.tx)) {
+            return Err(PackageError::TrucSpendsNonTruc { index });
+        }
+        if parents.len() > 1 {
+            return Err(PackageError::TrucTooManyAncestors { index, ancestors: parents.len() });
+        }
+        let vsize = p.tx.vsize();
+        let max = if parents.is_empty() { TRUC_MAX_VSIZE } else { TRUC_CHILD_MAX_VSIZE };
+        if vsize > max {
+            return Err(PackageError::TrucTooLarge { index, vsize, max });
+        }
+    }
+    Ok(())
+}
+
+/// Checks that every dust output is spent within the package.
+fn check_ephemeral_spends(package: &[PackageTx<'_>], txids: &[Txid]) -> Result<(), PackageError> {
+    let spent = package
+        .iter()
+        .flat_map(|p| p.tx.inputs.iter().map(|txin| txin.previous_output))
+        .collect::<BTreeSet<_>>();
+    for (index, (p, txid)) in package.iter().zip(txids).enumerate() {
+        for (vout, txout) in p.tx.outputs.iter().enumerate() {
+            let script_pubkey = &txout.script_pubkey;
+            if script_pubkey.is_op_return() || txout.value >= script_pubkey.minimal_non_dust() {
+                continue;
+            }
+            let outpoint = OutPoint { txid: *txid, vout: vout as u32 };
+            if !spent.contains(&outpoint) {
+                return Err(PackageError::MissingEphemeralSpend { index, output: vout });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// An error checking a package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PackageError {
+    /// The package has no transactions.
+    Empty,
+    /// The package has more than [`MAX_PACKAGE_COUNT`] transactions.
+    TooManyTransactions(usize),
+    /// The package weight is above [`MAX_PACKAGE_WEIGHT`].
+    TooLarge(Weight),
+    /// The package contains a transaction more than once.
+    Duplicate(Txid),
+    /// A transaction spends a transaction that comes after it.
+    NotSorted {
+        /// The index of the transaction.
+        index: usize,
+    },
+    /// Two transactions spend the same output.
+    Conflict {
+        /// The index of the second transaction spending the output.
+        index: usize,
+        /// The output spent twice.
+        outpoint: OutPoint,
+    },
+    /// A transaction is not a parent of the last transaction, or spends another parent.
+    NotChildWithParents {
+        /// The index of the transaction.
+        index: usize,
+    },
+    /// A transaction is not standard.
+    NonStandard {
+        /// The index of the transaction.
+        index: usize,
+        /// The standardness rule the transaction fails.
+        error: NonStandardError,
+    },
+    /// A TRUC transaction spends an unconfirmed non-TRUC transaction.
+    TrucSpendsNonTruc {
+        /// The index of the transaction.
+        index: usize,
+    },
+    /// A non-TRUC transaction spends an unconfirmed TRUC transaction.
+    NonTrucSpendsTruc {
+        /// The index of the transaction.
+        index: usize,
+    },
+    /// A TRUC transaction has more than one unconfirmed parent.
+    TrucTooManyAncestors {
+        /// The index of the transaction.
+        index: usize,
+        /// The number of unconfirmed parents.
+        ancestors: usize,
+    },
+    /// A TRUC transaction is above its maximum virtual size.
+    TrucTooLarge {
+        /// The index of the transaction.
+        index: usize,
+        /// The virtual size of the transaction.
+        vsize: usize,
+        /// The maximum virtual size.
+        max: usize,
+    },
+    /// A dust output is not spent by a transaction in the package.
+    MissingEphemeralSpend {
+        /// The index of the transaction with the dust output.
+        index: usize,
+        /// The index of the dust output.
+        output: usize,
+    },
+    /// The package fee does not pay the minimum relay feerate for the package size.
+    FeeTooLow {
+        /// The total fee of the package.
+        fee: Amount,
+        /// The smallest total fee that would pass.
+        minimum_fee: Amount,
+    },
+}
+
+impl fmt::Display for PackageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use PackageError::*;
+
+        match *self {
+            Empty => f.write_str("package is empty"),
+            TooManyTransactions(count) => write!(
+                f,
+                "package has {} transactions, the maximum is {}",
+                count, MAX_PACKAGE_COUNT
+            ),
+            TooLarge(weight) => write!(
+                f,
+                "package weight {} exceeds the maximum {}",
+                weight.to_wu(),
+                MAX_PACKAGE_WEIGHT.to_wu()
+            ),
+            Duplicate(ref txid) => write!(f, "transaction {} is in the package twice", txid),
+            NotSorted { index } =>
+                write!(f, "transaction {} spends a transaction that comes after it", index),
+            Conflict { index, ref outpoint } =>
+                write!(f, "transaction {} spends {} which is already spent", index, outpoint),
+            NotChildWithParents { index } =>
+                write!(f, "transaction {} is not a parent of the last transaction", index),
+            NonStandard { index, ref error } =>
+                write_err!(f, "transaction {} is not standard", index; error),
+            TrucSpendsNonTruc { index } =>
+                write!(f, "TRUC transaction {} spends a non-TRUC transaction", index),
+            NonTrucSpendsTruc { index } =>
+                write!(f, "non-TRUC transaction {} spends a TRUC transaction", index),
+            TrucTooManyAncestors { index, ancestors } => write!(
+                f,
+                "TRUC transaction {} has {} unconfirmed parents, the maximum is 1",
+                index, ancestors
+            ),
+            TrucTooLarge { index, vsize, max } =>
+                write!(f, "TRUC transaction {} is {} vbytes, the maximum is {}", index, vsize, max),
+            MissingEphemeralSpend { index, output } =>
+                write!(f, "dust output {} of transaction {} is not spent", output, index),
+            FeeTooLow { fee, minimum_fee } =>
+                write!(f, "package fee {} is below the minimum {}", fee, minimum_fee),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PackageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use PackageError::*;
+
+        match *self {
+            NonStandard { ref error, .. } => Some(error),
+            Empty
+            | TooManyTransactions(_)
+            | TooLarge(_)
+            | Duplicate(_)
+            | NotSorted { .. }
+            | Conflict { .. }
+            | NotChildWithParents { .. }
+            | TrucSpendsNonTruc { .. }
+            | NonTrucSpendsTruc { .. }
+            | TrucTooManyAncestors { .. }
+            | TrucTooLarge { .. }
+            | MissingEphemeralSpend { .. }
+            | FeeTooLow { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::locktime::absolute;
+    use crate::script::{ScriptPubKeyBuf, ScriptPubKeyBufExt as _, ScriptSigBuf};
+    use crate::transaction::{TxIn, TxOut};
+    use crate::{Sequence, Witness};
+
+    fn p2wpkh() -> ScriptPubKeyBuf {
+        ScriptPubKeyBuf::from_bytes([[0x00, 0x14].as_slice(), &[0xab; 20]].concat())
+    }
+
+    fn tx(version: transaction::Version, inputs: &[OutPoint], outputs: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version,
+            lock_time: absolute::LockTime::ZERO,
+            inputs: inputs
+                .iter()
+                .map(|previous_output| TxIn {
+                    previous_output: *previous_output,
+                    script_sig: ScriptSigBuf::new(),
+                    sequence: Sequence::ENABLE_LOCKTIME_AND_RBF,
+                    witness: Witness::from_slice(&[[0x30; 72].as_slice(), &[0x02; 33]]),
+                })
+                .collect(),
+            outputs,
+        }
+    }
+
+    fn confirmed(n: u8) -> OutPoint { OutPoint { txid: Txid::from_byte_array([n; 32]), vout: 0 } }
+
+    fn output(value: u32) -> TxOut {
+        TxOut { value: Amount::from_sat_u32(value), script_pubkey: p2wpkh() }
+    }
+
+    fn anchor() -> TxOut {
+        TxOut { value: Amount::ZERO, script_pubkey: ScriptPubKeyBuf::new_p2a() }
+    }
+
+    /// A zero fee TRUC parent with an anchor and the TRUC child spending the anchor.
+    fn anchor_package() -> (Transaction, Transaction) {
+        let v3 = transaction::Version::THREE;
+        let parent = tx(v3, &[confirmed(1)], vec![output(50_000), anchor()]);
+        let anchor = OutPoint { txid: parent.compute_txid(), vout: 1 };
+        let child = tx(v3, &[anchor, confirmed(2)], vec![output(10_000)]);
+        (parent, child)
+    }
+
+    #[test]
+    fn anchor_cpfp() {
+        let (parent, child) = anchor_package();
+        let package = [
+            PackageTx::new(&parent, Amount::ZERO),
+            PackageTx::new(&child, Amount::from_sat_u32(1_000)),
+        ];
+        assert_eq!(check_package(&package), Ok(()));
+        assert_eq!(
+            check_package(&package[..1]),
+            Err(PackageError::MissingEphemeralSpend { index: 0, output: 1 })
+        );
+    }
+
+    #[test]
+    fn dust_with_fee() {
+        let (parent, child) = anchor_package();
+        let package = [
+            PackageTx::new(&parent, Amount::ONE_SAT),
+            PackageTx::new(&child, Amount::from_sat_u32(1_000)),
+        ];
+        assert!(matches!(
+            check_package(&package),
+            Err(PackageError::NonStandard {
+                index: 0,
+                error: NonStandardError::Dust { output: 1, .. }
+            })
+        ));
+    }
+
+    #[test]
+    fn missing_ephemeral_spend() {
+        let (parent, _) = anchor_package();
+        let v3 = transaction::Version::THREE;
+        let child =
+            tx(v3, &[OutPoint { txid: parent.compute_txid(), vout: 0 }], vec![output(10_000)]);
+        let package = [
+            PackageTx::new(&parent, Amount::ZERO),
+            PackageTx::new(&child, Amount::from_sat_u32(1_000)),
+        ];
+        assert_eq!(
+            check_package(&package),
+            Err(PackageError::MissingEphemeralSpend { index: 0, output: 1 })
+        );
+    }
+
+    #[test]
+    fn package_feerate() {
+        let (parent, child) = anchor_package();
+        let vsize = (parent.vsize() + child.vsize()) as u64;
+        let minimum_fee = Amount::from_sat(vsize).unwrap();
+        let package = [PackageTx::new(&parent, Amount::ZERO), PackageTx::new(&child, minimum_fee)];
+        assert_eq!(check_package(&package), Ok(()));
+
+        let fee = (minimum_fee - Amount::ONE_SAT).unwrap();
+        let package = [PackageTx::new(&parent, Amount::ZERO), PackageTx::new(&child, fee)];
+        assert_eq!(check_package(&package), Err(PackageError::FeeTooLow { fee, minimum_fee }));
+    }
+
+    #[test]
+    fn truc_topology() {
+        let v2 = transaction::Version::TWO;
+        let v3 = transaction::Version::THREE;
+        let fee = Amount::from_sat_u32(10_000);
+
+        let parent = tx(v2, &[confirmed(1)], vec![output(50_000)]);
+        let spend = OutPoint { txid: parent.compute_txid(), vout: 0 };
+        let child = tx(v3, &[spend], vec![output(40_000)]);
+        let package = [PackageTx::new(&parent, fee), PackageTx::new(&child, fee)];
+        assert_eq!(check_package(&package), Err(PackageError::TrucSpendsNonTruc { index: 1 }));
+
+        let parent = tx(v3, &[confirmed(1)], vec![output(50_000)]);
+        let spend = OutPoint { txid: parent.compute_txid(), vout: 0 };
+        let child = tx(v2, &[spend], vec![output(40_000)]);
+        let package = [PackageTx::new(&parent, fee), PackageTx::new(&child, fee)];
+        assert_eq!(check_package(&package), Err(PackageError::NonTrucSpendsTruc { index: 1 }));
+
+        let other = tx(v3, &[confirmed(2)], vec![output(50_000)]);
+        let spends = [spend, OutPoint { txid: other.compute_txid(), vout: 0 }];
+        let child = tx(v3, &spends, vec![output(40_000)]);
+        let package = [
+            PackageTx::new(&parent, fee),
+            PackageTx::new(&other, fee),
+            PackageTx::new(&child, fee),
+        ];
+        assert_eq!(
+            check_package(&package),
+            Err(PackageError::TrucTooManyAncestors { index: 2, ancestors: 2 })
+        );
+    }
+
+    #[test]
+    fn truc_child_size() {
+        let v3 = transaction::Version::THREE;
+        let parent = tx(v3, &[confirmed(1)], vec![output(50_000)]);
+        let mut inputs = vec![OutPoint { txid: parent.compute_txid(), vout: 0 }];
+        inputs.extend((2..20).map(confirmed));
+        let child = tx(v3, &inputs, vec![output(40_000)]);
+        let vsize = child.vsize();
+        assert!(vsize > TRUC_CHILD_MAX_VSIZE);
+
+        let fee = Amount::from_sat_u32(10_000);
+        let package = [PackageTx::new(&parent, fee), PackageTx::new(&child, fee)];
+        assert_eq!(
+            check_package(&package),
+            Err(PackageError::TrucTooLarge { index: 1, vsize, max: TRUC_CHILD_MAX_VSIZE })
+        );
+        // Without an unconfirmed parent the limit is higher.
+        assert_eq!(check_package(&package[1..]), Ok(()));
+    }
+
+    #[test]
+    fn topology() {
+        let v2 = transaction::Version::TWO;
+        let fee = Amount::from_sat_u32(10_000);
+        let parent = tx(v2, &[confirmed(1)], vec![output(50_000), output(50_000)]);
+        let spend = |vout| OutPoint { txid: parent.compute_txid(), vout };
+        let child = tx(v2, &[spend(0)], vec![output(40_000)]);
+
+        assert_eq!(check_package(&[]), Err(PackageError::Empty));
+
+        let package = [PackageTx::new(&child, fee), PackageTx::new(&parent, fee)];
+        assert_eq!(check_package(&package), Err(PackageError::NotSorted { index: 0 }));
+
+        let package = [PackageTx::new(&parent, fee), PackageTx::new(&parent, fee)];
+        assert_eq!(check_package(&package), Err(PackageError::Duplicate(parent.compute_txid())));
+
+        let conflict = tx(v2, &[confirmed(1)], vec![output(40_000)]);
+        let package = [PackageTx::new(&parent, fee), PackageTx::new(&conflict, fee)];
+        assert_eq!(
+            check_package(&package),
+            Err(PackageError::Conflict { index: 1, outpoint: confirmed(1) })
+        );
+
+        // A grandparent is not allowed.
+        let grandchild = tx(
+            v2,
+            &[OutPoint { txid: child.compute_txid(), vout: 0 }, spend(1)],
+            vec![output(30_000)],
+        );
+        let package = [
+            PackageTx::new(&parent, fee),
+            PackageTx::new(&child, fee),
+            PackageTx::new(&grandchild, fee),
+        ];
+        assert_eq!(check_package(&package), Err(PackageError::NotChildWithParents { index: 1 }));
+
+        let unrelated = tx(v2, &[confirmed(3)], vec![output(40_000)]);
+        let package = [PackageTx::new(&unrelated, fee), PackageTx::new(&child, fee)];
+        assert_eq!(check_package(&package), Err(PackageError::NotChildWithParents { index: 0 }));
+
+        let package = vec![PackageTx::new(&child, fee); MAX_PACKAGE_COUNT + 1];
+        assert_eq!(
+            check_package(&package),
+            Err(PackageError::TooManyTransactions(MAX_PACKAGE_COUNT + 1))
+        );
+    }
+}
@@ -0,0 +1,459 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Replace-by-fee.
+//!
+//! Implements the rules Bitcoin Core applies when a transaction conflicts with transactions in its
+//! mempool, as described in [BIP-0125] and Bitcoin Core's `doc/policy/mempool-replacements.md`.
+//!
+//! [BIP-0125]: <https://github.com/bitcoin/bips/blob/master/bip-0125.mediawiki>
+
+use core::fmt;
+
+use super::DEFAULT_INCREMENTAL_RELAY_FEE;
+use crate::prelude::{BTreeSet, Vec};
+use crate::transaction::{OutPoint, Transaction, TransactionExt as _, Txid};
+use crate::{Amount, FeeRate, Weight};
+
+/// Maximum number of transactions a replacement may evict from the mempool, including descendants.
+pub const MAX_REPLACEMENT_CANDIDATES: usize = 100;
+
+/// A mempool transaction that conflicts with a replacement.
+///
+/// A conflict is a transaction spending at least one of the outputs the replacement spends. It is
+/// evicted from the mempool together with all of its descendants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conflict<'a> {
+    /// The conflicting transaction.
+    pub tx: &'a Transaction,
+    /// The fee paid by `tx`.
+    pub fee: Amount,
+    /// The number of mempool descendants of `tx`.
+    pub descendant_count: usize,
+    /// The total fee paid by the descendants of `tx`.
+    pub descendant_fees: Amount,
+}
+
+impl<'a> Conflict<'a> {
+    /// Constructs a new conflict with no descendants.
+    pub fn new(tx: &'a Transaction, fee: Amount) -> Self {
+        Conflict { tx, fee, descendant_count: 0, descendant_fees: Amount::ZERO }
+    }
+
+    /// Sets the number of descendants of the conflict and the total fee they pay.
+    ///
+    /// A descendant of several conflicts must only be counted for one of them.
+    #[must_use]
+    pub fn with_descendants(mut self, count: usize, fees: Amount) -> Self {
+        self.descendant_count = count;
+        self.descendant_fees = fees;
+        self
+    }
+}
+
+/// The replacement rules of a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ReplacementPolicy {
+    /// Whether transactions may be replaced without signalling replaceability (`-mempoolfullrbf`).
+    pub full_rbf: bool,
+    /// The minimum feerate a replacement must pay for its own size on top of the replaced fees.
+    pub incremental_relay_fee: FeeRate,
+    /// The maximum number of transactions a replacement may evict.
+    pub max_replacement_candidates: usize,
+}
+
+impl Default for ReplacementPolicy {
+    /// The defaults of Bitcoin Core 28.0, which enabled full RBF.
+    fn default() -> Self {
+        ReplacementPolicy {
+            full_rbf: true,
+            incremental_relay_fee: FeeRate::from_sat_per_kvb(DEFAULT_INCREMENTAL_RELAY_FEE),
+            max_replacement_candidates: MAX_REPLACEMENT_CANDIDATES,
+        }
+    }
+}
+
+impl ReplacementPolicy {
+    /// The replacement rules before full RBF, conflicts must signal replaceability.
+    pub fn opt_in() -> Self { ReplacementPolicy { full_rbf: false, ..Default::default() } }
+
+    /// Checks whether `replacement`, paying `fee`, may replace the `conflicts`.
+    ///
+    /// `is_unconfirmed` is called for the inputs of the replacement and must return true if the
+    /// output spent is created by a transaction that is still in the mempool.
+    pub fn check<F>(
+        &self,
+        replacement: &Transaction,
+        fee: Amount,
+        conflicts: &[Conflict<'_>],
+        mut is_unconfirmed: F,
+    ) -> Result<(), ReplacementError>
+    where
+        F: FnMut(&OutPoint) -> bool,
+    {
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+
+        // Rule 1: the conflicts signal replaceability, or full RBF is enabled.
+        if !self.full_rbf {
+            if let Some(conflict) = conflicts.iter().find(|c| !c.tx.is_explicitly_rbf()) {
+                return Err(ReplacementError::NotReplaceable(conflict.tx.compute_txid()));
+            }
+        }
+
+        // Rule 5: the number of evicted transactions is limited.
+        let evicted = conflicts.iter().map(|c| 1 + c.descendant_count).sum::<usize>();
+        if evicted > self.max_replacement_candidates {
+            return Err(ReplacementError::TooManyReplacements {
+                evicted,
+                max: self.max_replacement_candidates,
+            });
+        }
+
+        // Rule 2: no unconfirmed inputs other than those already spent by the conflicts.
+        let conflict_txids = conflicts.iter().map(|c| c.tx.compute_txid()).collect::<Vec<_>>();
+        let conflict_parents = conflicts
+            .iter()
+            .flat_map(|c| c.tx.inputs.iter().map(|txin| txin.previous_output.txid))
+            .collect::<BTreeSet<_>>();
+        for (input, txin) in replacement.inputs.iter().enumerate() {
+            let outpoint = &txin.previous_output;
+            if conflict_txids.contains(&outpoint.txid) {
+                return Err(ReplacementError::SpendsConflict { input });
+            }
+            if !conflict_parents.contains(&outpoint.txid) && is_unconfirmed(outpoint) {
+                return Err(ReplacementError::NewUnconfirmedInput { input });
+            }
+        }
+
+        let minimum_fee = self.minimum_fee(replacement, conflicts);
+        let vsize = replacement.vsize() as u64;
+
+        // Rule 6: the feerate is higher than the feerate of every conflict.
+        let feerate = sat_per_kvb(fee, vsize);
+        for (conflict, txid) in conflicts.iter().zip(conflict_txids) {
+            if feerate <= sat_per_kvb(conflict.fee, conflict.tx.vsize() as u64) {
+                return Err(ReplacementError::InsufficientFeeRate { txid, fee, minimum_fee });
+            }
+        }
+
+        // Rule 3: the fee is at least the fee of the evicted transactions.
+        let evicted_fees = evicted_fees(conflicts);
+        if fee.to_sat() < evicted_fees {
+            return Err(ReplacementError::InsufficientFee { fee, minimum_fee });
+        }
+
+        // Rule 4: the additional fee pays for the replacement at the incremental relay feerate.
+        if fee.to_sat() - evicted_fees < self.incremental_fee(vsize).to_sat() {
+            return Err(ReplacementError::InsufficientIncrementalFee { fee, minimum_fee });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the smallest fee with which `replacement` satisfies the fee rules.
+    ///
+    /// The fee of the replacement does not change its size, so this can be computed with a
+    /// placeholder fee output and used to set the final one.
+    pub fn minimum_fee(&self, replacement: &Transaction, conflicts: &[Conflict<'_>]) -> Amount {
+        let vsize = replacement.vsize() as u64;
+        let mut minimum =
+            evicted_fees(conflicts).saturating_add(self.incremental_fee(vsize).to_sat());
+        for conflict in conflicts {
+            // The smallest fee giving a higher feerate than the conflict.
+            let feerate = sat_per_kvb(conflict.fee, conflict.tx.vsize() as u64) + 1;
+            let fee = (feerate * u128::from(vsize) + 999) / 1000;
+            minimum = minimum.max(u64::try_from(fee).unwrap_or(u64::MAX));
+        }
+        Amount::from_sat(minimum).unwrap_or(Amount::MAX)
+    }
+
+    fn incremental_fee(&self, vsize: u64) -> Amount {
+        self.incremental_relay_fee.to_fee(Weight::from_vb_unchecked(vsize))
+    }
+}
+
+/// Returns the feerate of a transaction in satoshis per kilo-virtualbyte, rounded down like
+/// Bitcoin Core's `CFeeRate`.
+fn sat_per_kvb(fee: Amount, vsize: u64) -> u128 {
+    u128::from(fee.to_sat()) * 1000 / u128::from(vsize)
+}
+
+/// Returns the total fee of the conflicts and their descendants in satoshis.
+fn evicted_fees(conflicts: &[Conflict<'_>]) -> u64 {
+    conflicts
+        .iter()
+        .map(|c| c.fee.to_sat().saturating_add(c.descendant_fees.to_sat()))
+        .fold(0, u64::saturating_add)
+}
+
+/// The replacement rule a transaction fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ReplacementError {
+    /// A conflict does not signal replaceability and full RBF is disabled.
+    NotReplaceable(Txid),
+    /// The replacement would evict too many transactions.
+    TooManyReplacements {
+        /// The number of conflicts and descendants.
+        evicted: usize,
+        /// The maximum number of evicted transactions.
+        max: usize,
+    },
+    /// An input spends an output of one of the conflicts.
+    SpendsConflict {
+        /// The index of the input.
+        input: usize,
+    },
+    /// An input spends an unconfirmed output that none of the conflicts spends.
+    NewUnconfirmedInput {
+        /// The index of the input.
+        input: usize,
+    },
+    /// The feerate is not higher than the feerate of a conflict.
+    InsufficientFeeRate {
+        /// The conflict paying at least the feerate of the replacement.
+        txid: Txid,
+        /// The fee of the replacement.
+        fee: Amount,
+        /// The smallest fee that satisfies all fee rules.
+        minimum_fee: Amount,
+    },
+    /// The fee is less than the fee of the evicted transactions.
+    InsufficientFee {
+        /// The fee of the replacement.
+        fee: Amount,
+        /// The smallest fee that satisfies all fee rules.
+        minimum_fee: Amount,
+    },
+    /// The additional fee does not pay for the replacement at the incremental relay feerate.
+    InsufficientIncrementalFee {
+        /// The fee of the replacement.
+        fee: Amount,
+        /// The smallest fee that satisfies all fee rules.
+        minimum_fee: Amount,
+    },
+}
+
+impl ReplacementError {
+    /// Returns the smallest fee that satisfies all fee rules if a fee rule failed.
+    pub fn minimum_fee(&self) -> Option<Amount> {
+        use ReplacementError::*;
+
+        match *self {
+            InsufficientFeeRate { minimum_fee, .. }
+            | InsufficientFee { minimum_fee, .. }
+            | InsufficientIncrementalFee { minimum_fee, .. } => Some(minimum_fee),
+            NotReplaceable(_)
+            | TooManyReplacements { .. }
+            | SpendsConflict { .. }
+            | NewUnconfirmedInput { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for ReplacementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ReplacementError::*;
+
+        match *self {
+            NotReplaceable(ref txid) =>
+                write!(f, "conflicting transaction {} is not replaceable", txid),
+            TooManyReplacements { evicted, max } =>
+                write!(f, "replacement evicts {} transactions, the maximum is {}", evicted, max),
+            SpendsConflict { input } =>
+                write!(f, "input {} spends an output of a replaced transaction", input),
+            NewUnconfirmedInput { input } =>
+                write!(f, "input {} spends a new unconfirmed output", input),
+            InsufficientFeeRate { ref txid, fee, minimum_fee } => write!(
+                f,
+                "feerate is not higher than replaced transaction {} (fee {}, minimum {})",
+                txid, fee, minimum_fee
+            ),
+            InsufficientFee { fee, minimum_fee } =>
+                write!(f, "fee {} is less than the replaced fees (minimum {})", fee, minimum_fee),
+            InsufficientIncrementalFee { fee, minimum_fee } => write!(
+                f,
+                "fee {} does not pay the incremental relay fee (minimum {})",
+                fee, minimum_fee
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ReplacementError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::locktime::absolute;
+    use crate::script::{ScriptPubKeyBuf, ScriptSigBuf};
+    use crate::transaction::{self, TxIn, TxOut};
+    use crate::{Sequence, Witness};
+
+    fn outpoint(n: u8) -> OutPoint { OutPoint { txid: Txid::from_byte_array([n; 32]), vout: 0 } }
+
+    fn tx(inputs: &[OutPoint], sequence: Sequence, value: u32) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            inputs: inputs
+                .iter()
+                .map(|previous_output| TxIn {
+                    previous_output: *previous_output,
+                    script_sig: ScriptSigBuf::new(),
+                    sequence,
+                    witness: Witness::from_slice(&[[0x30; 72].as_slice(), &[0x02; 33]]),
+                })
+                .collect(),
+            outputs: vec![TxOut {
+                value: Amount::from_sat_u32(value),
+                script_pubkey: ScriptPubKeyBuf::from_bytes(vec![0x00; 22]),
+            }],
+        }
+    }
+
+    fn confirmed(_: &OutPoint) -> bool { false }
+
+    #[test]
+    fn signalling() {
+        let original = tx(&[outpoint(1)], Sequence::MAX, 1_000);
+        let replacement = tx(&[outpoint(1)], Sequence::MAX, 900);
+        let conflicts = [Conflict::new(&original, Amount::from_sat_u32(1_000))];
+        let fee = Amount::from_sat_u32(2_000);
+
+        assert_eq!(
+            ReplacementPolicy::opt_in().check(&replacement, fee, &conflicts, confirmed),
+            Err(ReplacementError::NotReplaceable(original.compute_txid()))
+        );
+        assert_eq!(
+            ReplacementPolicy::default().check(&replacement, fee, &conflicts, confirmed),
+            Ok(())
+        );
+
+        let original = tx(&[outpoint(1)], Sequence::ENABLE_LOCKTIME_AND_RBF, 1_000);
+        let conflicts = [Conflict::new(&original, Amount::from_sat_u32(1_000))];
+        assert_eq!(
+            ReplacementPolicy::opt_in().check(&replacement, fee, &conflicts, confirmed),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn fee_rules() {
+        let policy = ReplacementPolicy::default();
+        let original = tx(&[outpoint(1)], Sequence::MAX, 1_000);
+        let replacement = tx(&[outpoint(1)], Sequence::MAX, 900);
+        let vsize = replacement.vsize() as u64;
+        assert_eq!(vsize, original.vsize() as u64);
+        let conflicts = [Conflict::new(&original, Amount::from_sat_u32(1_000))
+            .with_descendants(2, Amount::from_sat_u32(500))];
+
+        // 1500 sats of replaced fees plus 1 sat/vB for the replacement.
+        let minimum_fee = Amount::from_sat(1_500 + vsize).unwrap();
+        assert_eq!(policy.minimum_fee(&replacement, &conflicts), minimum_fee);
+        assert_eq!(policy.check(&replacement, minimum_fee, &conflicts, confirmed), Ok(()));
+
+        let fee = Amount::from_sat(1_499 + vsize).unwrap();
+        let err = policy.check(&replacement, fee, &conflicts, confirmed).unwrap_err();
+        assert_eq!(err, ReplacementError::InsufficientIncrementalFee { fee, minimum_fee });
+        assert_eq!(err.minimum_fee(), Some(minimum_fee));
+
+        let fee = Amount::from_sat_u32(1_499);
+        assert_eq!(
+            policy.check(&replacement, fee, &conflicts, confirmed),
+            Err(ReplacementError::InsufficientFee { fee, minimum_fee })
+        );
+
+        let fee = Amount::from_sat_u32(1_000);
+        assert_eq!(
+            policy.check(&replacement, fee, &conflicts, confirmed),
+            Err(ReplacementError::InsufficientFeeRate {
+                txid: original.compute_txid(),
+                fee,
+                minimum_fee
+            })
+        );
+    }
+
+    #[test]
+    fn feerate_of_smaller_conflict() {
+        // A small, high feerate conflict sets the minimum for a large replacement.
+        let policy = ReplacementPolicy::default();
+        let original = tx(&[outpoint(1)], Sequence::MAX, 1_000);
+        let replacement = tx(&[outpoint(1), outpoint(2), outpoint(3)], Sequence::MAX, 900);
+        let conflicts = [Conflict::new(&original, Amount::from_sat_u32(10_000))];
+
+        let vsize = u128::from(replacement.vsize() as u64);
+        let original_feerate = 10_000 * 1000 / u128::from(original.vsize() as u64);
+        let minimum_fee = ((original_feerate + 1) * vsize + 999) / 1000;
+        let minimum_fee = Amount::from_sat(minimum_fee as u64).unwrap();
+        assert!(minimum_fee > Amount::from_sat(10_000 + vsize as u64).unwrap());
+        assert_eq!(policy.minimum_fee(&replacement, &conflicts), minimum_fee);
+        assert_eq!(policy.check(&replacement, minimum_fee, &conflicts, confirmed), Ok(()));
+
+        let fee = (minimum_fee - Amount::ONE_SAT).unwrap();
+        assert!(matches!(
+            policy.check(&replacement, fee, &conflicts, confirmed),
+            Err(ReplacementError::InsufficientFeeRate { .. })
+        ));
+    }
+
+    #[test]
+    fn too_many_replacements() {
+        let policy = ReplacementPolicy::default();
+        let original = tx(&[outpoint(1)], Sequence::MAX, 1_000);
+        let replacement = tx(&[outpoint(1)], Sequence::MAX, 900);
+        let fee = Amount::from_sat_u32(100_000);
+
+        let conflicts = [Conflict::new(&original, Amount::from_sat_u32(1_000))
+            .with_descendants(99, Amount::from_sat_u32(1_000))];
+        assert_eq!(policy.check(&replacement, fee, &conflicts, confirmed), Ok(()));
+
+        let conflicts = [Conflict::new(&original, Amount::from_sat_u32(1_000))
+            .with_descendants(100, Amount::from_sat_u32(1_000))];
+        assert_eq!(
+            policy.check(&replacement, fee, &conflicts, confirmed),
+            Err(ReplacementError::TooManyReplacements { evicted: 101, max: 100 })
+        );
+    }
+
+    #[test]
+    fn unconfirmed_inputs() {
+        let policy = ReplacementPolicy::default();
+        let original = tx(&[outpoint(1), outpoint(2)], Sequence::MAX, 1_000);
+        let conflicts = [Conflict::new(&original, Amount::from_sat_u32(1_000))];
+        let fee = Amount::from_sat_u32(100_000);
+        let unconfirmed = |outpoint: &OutPoint| outpoint.txid != Txid::from_byte_array([1; 32]);
+
+        // Unconfirmed inputs already spent by the original are allowed.
+        let replacement = tx(&[outpoint(1), outpoint(2)], Sequence::MAX, 900);
+        assert_eq!(policy.check(&replacement, fee, &conflicts, unconfirmed), Ok(()));
+
+        let replacement = tx(&[outpoint(1), outpoint(3)], Sequence::MAX, 900);
+        assert_eq!(
+            policy.check(&replacement, fee, &conflicts, unconfirmed),
+            Err(ReplacementError::NewUnconfirmedInput { input: 1 })
+        );
+
+        let child_of_original = OutPoint { txid: original.compute_txid(), vout: 0 };
+        let replacement = tx(&[outpoint(1), child_of_original], Sequence::MAX, 900);
+        assert_eq!(
+            policy.check(&replacement, fee, &conflicts, unconfirmed),
+            Err(ReplacementError::SpendsConflict { input: 1 })
+        );
+    }
+
+    #[test]
+    fn no_conflicts() {
+        let replacement = tx(&[outpoint(1)], Sequence::MAX, 900);
+        assert_eq!(
+            ReplacementPolicy::opt_in().check(&replacement, Amount::ZERO, &[], |_| true),
+            Ok(())
+        );
+    }
+}
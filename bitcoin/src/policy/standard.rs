@@ -0,0 +1,856 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Transaction standardness.
+//!
+//! Implements the checks Bitcoin Core applies before accepting a transaction into its mempool
+//! that are not consensus rules, `IsStandardTx`, `AreInputsStandard` and `IsWitnessStandard`.
+//!
+//! The checks match Bitcoin Core's default configuration, a node started with non-default
+//! options (e.g. `-datacarrier` or `-dustrelayfee`) may accept more or fewer transactions.
+
+use core::fmt;
+
+use super::{
+    MAX_OP_RETURN_RELAY, MAX_P2SH_SIGOPS, MAX_STANDARD_P2WSH_SCRIPT_SIZE,
+    MAX_STANDARD_P2WSH_STACK_ITEMS, MAX_STANDARD_P2WSH_STACK_ITEM_SIZE,
+    MAX_STANDARD_SCRIPTSIG_SIZE, MAX_STANDARD_TAPSCRIPT_STACK_ITEM_SIZE,
+    MAX_STANDARD_TX_SIGOPS_COST, MAX_STANDARD_TX_WEIGHT, MIN_STANDARD_TX_NONWITNESS_SIZE,
+};
+use crate::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHBYTES_1};
+use crate::prelude::Vec;
+use crate::script::{
+    Instruction, ScriptExt as _, ScriptPubKey, ScriptPubKeyExt as _, ScriptSigExt as _,
+};
+use crate::taproot::{TAPROOT_ANNEX_PREFIX, TAPROOT_LEAF_MASK, TAPROOT_LEAF_TAPSCRIPT};
+use crate::transaction::{self, OutPoint, Transaction, TransactionExt as _, TxOut};
+use crate::witness::WitnessExt as _;
+use crate::{Amount, Weight, WitnessVersion};
+
+/// Checks the parts of Bitcoin Core's standardness rules that only depend on the transaction.
+///
+/// This checks the version, the size, the scriptSigs and the outputs. Use
+/// [`check_standard_inputs`] to check the outputs being spent.
+///
+/// Every output below [`minimal_non_dust`] is rejected, even though Bitcoin Core accepts a single
+/// dust output in a transaction that pays no fee. Use [`check_package`] to check such transactions
+/// together with the child spending the dust.
+///
+/// [`check_package`]: super::check_package
+///
+/// [`minimal_non_dust`]: crate::script::ScriptPubKeyExt::minimal_non_dust
+pub fn check_standard_tx(tx: &Transaction) -> Result<(), NonStandardError> {
+    check_standard_tx_with_dust(tx, 0)
+}
+
+/// Checks `tx` like [`check_standard_tx`] but allows up to `max_dust` dust outputs.
+pub(super) fn check_standard_tx_with_dust(
+    tx: &Transaction,
+    max_dust: usize,
+) -> Result<(), NonStandardError> {
+    if !tx.version.is_standard() {
+        return Err(NonStandardError::Version(tx.version));
+    }
+
+    let weight = tx.weight();
+    if weight > Weight::from_wu(MAX_STANDARD_TX_WEIGHT.into()) {
+        return Err(NonStandardError::TxSize(weight));
+    }
+    for (input, txin) in tx.inputs.iter().enumerate() {
+        let size = txin.script_sig.len();
+        if size > MAX_STANDARD_SCRIPTSIG_SIZE {
+            return Err(NonStandardError::ScriptSigSize { input, size });
+        }
+        if !txin.script_sig.is_push_only() {
+            return Err(NonStandardError::ScriptSigNotPushOnly { input });
+        }
+    }
+
+    let mut data_outputs = 0;
+    for (output, txout) in tx.outputs.iter().enumerate() {
+        if !is_standard_script_pubkey(&txout.script_pubkey) {
+            return Err(NonStandardError::ScriptPubKey { output });
+        }
+        if txout.script_pubkey.is_op_return() {
+            data_outputs += 1;
+        }
+    }
+    // Like Bitcoin Core, dust is only checked once every output script is known to be standard.
+    let mut dust = tx.outputs.iter().enumerate().filter(|(_, txout)| {
+        !txout.script_pubkey.is_op_return() && txout.value < txout.script_pubkey.minimal_non_dust()
+    });
+    if let Some((output, txout)) = dust.nth(max_dust) {
+        let minimum = txout.script_pubkey.minimal_non_dust();
+        return Err(NonStandardError::Dust { output, value: txout.value, minimum });
+    }
+    if data_outputs > 1 {
+        return Err(NonStandardError::MultiOpReturn);
+    }
+
+    // Bitcoin Core checks this after `IsStandardTx`, so the other rules take precedence.
+    let base_size = tx.base_size();
+    if base_size < MIN_STANDARD_TX_NONWITNESS_SIZE as usize {
+        return Err(NonStandardError::TxSizeSmall(base_size));
+    }
+
+    Ok(())
+}
+
+/// Checks the parts of Bitcoin Core's standardness rules that depend on the outputs being spent.
+///
+/// This checks the type of each spent output, the P2SH redeem scripts, the witnesses and the
+/// total sigop cost of the transaction.
+///
+/// The `spent` parameter is a closure/function that looks up the output being spent by each
+/// input.
+pub fn check_standard_inputs<S>(tx: &Transaction, mut spent: S) -> Result<(), NonStandardError>
+where
+    S: FnMut(&OutPoint) -> Option<TxOut>,
+{
+    let mut prevouts = Vec::with_capacity(tx.inputs.len());
+    for (input, txin) in tx.inputs.iter().enumerate() {
+        let prevout =
+            spent(&txin.previous_output).ok_or(NonStandardError::MissingPrevout { input })?;
+        check_input(input, txin, &prevout.script_pubkey)?;
+        prevouts.push(prevout);
+    }
+
+    let sigop_cost = tx.total_sigop_cost(|outpoint| {
+        tx.inputs
+            .iter()
+            .position(|txin| txin.previous_output == *outpoint)
+            .map(|input| prevouts[input].clone())
+    });
+    if sigop_cost > MAX_STANDARD_TX_SIGOPS_COST as usize {
+        return Err(NonStandardError::SigopCost(sigop_cost));
+    }
+
+    Ok(())
+}
+
+/// Checks that `tx` passes [`check_standard_tx`] and [`check_standard_inputs`].
+pub fn check_standard<S>(tx: &Transaction, spent: S) -> Result<(), NonStandardError>
+where
+    S: FnMut(&OutPoint) -> Option<TxOut>,
+{
+    check_standard_tx(tx)?;
+    check_standard_inputs(tx, spent)
+}
+
+/// Returns true if `script_pubkey` is of a type Bitcoin Core relays.
+///
+/// These are P2PK, P2PKH, P2SH, bare multisig with up to three keys, `OP_RETURN` outputs of at
+/// most 83 bytes, P2WPKH, P2WSH and outputs with a witness version above 0.
+pub fn is_standard_script_pubkey(script_pubkey: &ScriptPubKey) -> bool {
+    match script_pubkey.witness_version() {
+        Some(WitnessVersion::V0) => script_pubkey.is_p2wpkh() || script_pubkey.is_p2wsh(),
+        Some(_) => true,
+        None =>
+            script_pubkey.is_p2pk()
+                || script_pubkey.is_p2pkh()
+                || script_pubkey.is_p2sh()
+                || is_standard_multisig(script_pubkey)
+                || is_standard_op_return(script_pubkey),
+    }
+}
+
+/// Returns true if `script_pubkey` is an output type Bitcoin Core relays spends of.
+///
+/// Unlike [`is_standard_script_pubkey`] this accepts bare multisig with up to 20 keys and only the
+/// witness versions with standard spends, following the checks of `AreInputsStandard`.
+fn is_standard_prevout(script_pubkey: &ScriptPubKey) -> bool {
+    match script_pubkey.witness_version() {
+        Some(WitnessVersion::V0) => script_pubkey.is_p2wpkh() || script_pubkey.is_p2wsh(),
+        // Bitcoin Core does not relay spends of unknown witness versions.
+        Some(_) => script_pubkey.is_p2tr() || is_p2a(script_pubkey),
+        None =>
+            script_pubkey.is_p2pk()
+                || script_pubkey.is_p2pkh()
+                || script_pubkey.is_p2sh()
+                || multisig_keys(script_pubkey).is_some()
+                || is_standard_op_return(script_pubkey),
+    }
+}
+
+/// Returns true if `script_pubkey` is a bare multisig with between one and three keys.
+fn is_standard_multisig(script_pubkey: &ScriptPubKey) -> bool {
+    multisig_keys(script_pubkey).map_or(false, |keys| keys <= 3)
+}
+
+/// Returns the number of keys if `script_pubkey` is a bare multisig.
+///
+/// Follows `MatchMultisig` in Bitcoin Core's `solver.cpp`, which allows up to 20 keys.
+fn multisig_keys(script_pubkey: &ScriptPubKey) -> Option<u8> {
+    let bytes = script_pubkey.as_bytes();
+    let mut instructions = script_pubkey.instruction_indices();
+    let (index, instruction) = instructions.next()?.ok()?;
+    let required = multisig_number(bytes[index], instruction).filter(|n| (1..=20).contains(n))?;
+
+    let mut keys = 0;
+    let total = loop {
+        let (index, instruction) = instructions.next()?.ok()?;
+        match instruction {
+            Instruction::PushBytes(key) if is_valid_key_size(key.as_bytes()) => {
+                keys += 1;
+                if keys > 20 {
+                    return None;
+                }
+            }
+            _ => break multisig_number(bytes[index], instruction)?,
+        }
+    };
+
+    let matches = total == keys
+        && (required..=20).contains(&total)
+        && matches!(instructions.next(), Some(Ok((_, Instruction::Op(op)))) if op == OP_CHECKMULTISIG)
+        && instructions.next().is_none();
+    if matches {
+        Some(keys)
+    } else {
+        None
+    }
+}
+
+/// Returns the number pushed by a multisig key count `instruction` starting with `opcode`.
+///
+/// Numbers up to 16 must be pushed with `OP_PUSHNUM_N`, larger ones with `OP_PUSHBYTES_1`.
+fn multisig_number(opcode: u8, instruction: Instruction) -> Option<u8> {
+    match instruction {
+        Instruction::Op(op) => op.decode_pushnum(),
+        Instruction::PushBytes(data) => match data.as_bytes() {
+            [n] if opcode == OP_PUSHBYTES_1.to_u8() && (17..0x80).contains(n) => Some(*n),
+            _ => None,
+        },
+    }
+}
+
+/// Returns true if `key` has the size its prefix byte requires, like `CPubKey::ValidSize`.
+fn is_valid_key_size(key: &[u8]) -> bool {
+    match key.first() {
+        Some(0x02 | 0x03) => key.len() == 33,
+        Some(0x04 | 0x06 | 0x07) => key.len() == 65,
+        _ => false,
+    }
+}
+
+/// Returns true if `script_pubkey` is an `OP_RETURN` followed by pushes within the relay limit.
+fn is_standard_op_return(script_pubkey: &ScriptPubKey) -> bool {
+    script_pubkey.is_op_return()
+        && script_pubkey.len() <= MAX_OP_RETURN_RELAY
+        && ScriptPubKey::from_bytes(&script_pubkey.as_bytes()[1..]).is_push_only()
+}
+
+/// Checks a single input against the output it spends.
+fn check_input(
+    input: usize,
+    txin: &transaction::TxIn,
+    script_pubkey: &ScriptPubKey,
+) -> Result<(), NonStandardError> {
+    if !is_standard_prevout(script_pubkey) {
+        return Err(NonStandardError::NonStandardInput { input });
+    }
+
+    let mut program = script_pubkey;
+    let mut p2sh = false;
+    if script_pubkey.is_p2sh() {
+        let redeem_script =
+            txin.script_sig.redeem_script().ok_or(NonStandardError::NonStandardInput { input })?;
+        let sigops = redeem_script.count_sigops();
+        if sigops > MAX_P2SH_SIGOPS {
+            return Err(NonStandardError::P2shSigops { input, sigops });
+        }
+        program = ScriptPubKey::from_bytes(redeem_script.as_bytes());
+        p2sh = true;
+    }
+
+    let witness = &txin.witness;
+    if witness.is_empty() {
+        return Ok(());
+    }
+    if !program.is_witness_program() {
+        return Err(NonStandardError::UnexpectedWitness { input });
+    }
+
+    if program.is_p2wsh() {
+        let size = witness.last().map_or(0, <[u8]>::len);
+        if size > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+            return Err(NonStandardError::WitnessScriptSize { input, size });
+        }
+        let items = witness.len() - 1;
+        if items > MAX_STANDARD_P2WSH_STACK_ITEMS {
+            return Err(NonStandardError::WitnessStackItems { input, items });
+        }
+        if let Some(size) = witness
+            .iter()
+            .take(items)
+            .map(<[u8]>::len)
+            .find(|&size| size > MAX_STANDARD_P2WSH_STACK_ITEM_SIZE)
+        {
+            return Err(NonStandardError::WitnessStackItemSize { input, size });
+        }
+    } else if program.is_p2tr() && !p2sh {
+        // Follows Bitcoin Core in only looking at the first byte of the control block, the rest is
+        // checked by consensus.
+        let items = witness.len();
+        if items >= 2 && witness[items - 1].first() == Some(&TAPROOT_ANNEX_PREFIX) {
+            return Err(NonStandardError::TaprootAnnex { input });
+        }
+        if items >= 2 {
+            let control_block = &witness[items - 1];
+            match control_block.first() {
+                None => return Err(NonStandardError::EmptyControlBlock { input }),
+                Some(&leaf) if leaf & TAPROOT_LEAF_MASK == TAPROOT_LEAF_TAPSCRIPT => {
+                    if let Some(size) = witness
+                        .iter()
+                        .take(items - 2)
+                        .map(<[u8]>::len)
+                        .find(|&size| size > MAX_STANDARD_TAPSCRIPT_STACK_ITEM_SIZE)
+                    {
+                        return Err(NonStandardError::WitnessStackItemSize { input, size });
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns true if `script_pubkey` is a pay-to-anchor output.
+fn is_p2a(script_pubkey: &ScriptPubKey) -> bool {
+    script_pubkey.as_bytes() == [0x51, 0x02, 0x4e, 0x73]
+}
+
+/// The reason a transaction is not standard.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NonStandardError {
+    /// The transaction version is not standard.
+    Version(transaction::Version),
+    /// The transaction weight is above [`MAX_STANDARD_TX_WEIGHT`].
+    TxSize(Weight),
+    /// The transaction size without witness data is below [`MIN_STANDARD_TX_NONWITNESS_SIZE`].
+    TxSizeSmall(usize),
+    /// A scriptSig is larger than [`MAX_STANDARD_SCRIPTSIG_SIZE`].
+    ScriptSigSize {
+        /// The index of the input.
+        input: usize,
+        /// The size of the scriptSig.
+        size: usize,
+    },
+    /// A scriptSig contains opcodes other than pushes.
+    ScriptSigNotPushOnly {
+        /// The index of the input.
+        input: usize,
+    },
+    /// An output script is of a non-standard type.
+    ScriptPubKey {
+        /// The index of the output.
+        output: usize,
+    },
+    /// An output is below the dust limit.
+    Dust {
+        /// The index of the output.
+        output: usize,
+        /// The value of the output.
+        value: Amount,
+        /// The smallest value that is not dust.
+        minimum: Amount,
+    },
+    /// The transaction has more than one `OP_RETURN` output.
+    MultiOpReturn,
+    /// The output spent by an input was not provided.
+    MissingPrevout {
+        /// The index of the input.
+        input: usize,
+    },
+    /// An input spends a non-standard output, or a P2SH output without a redeem script.
+    NonStandardInput {
+        /// The index of the input.
+        input: usize,
+    },
+    /// A P2SH redeem script has more than [`MAX_P2SH_SIGOPS`] sigops.
+    P2shSigops {
+        /// The index of the input.
+        input: usize,
+        /// The number of sigops in the redeem script.
+        sigops: usize,
+    },
+    /// An input that does not spend a witness program has a witness.
+    UnexpectedWitness {
+        /// The index of the input.
+        input: usize,
+    },
+    /// A P2WSH witness script is larger than [`MAX_STANDARD_P2WSH_SCRIPT_SIZE`].
+    WitnessScriptSize {
+        /// The index of the input.
+        input: usize,
+        /// The size of the witness script.
+        size: usize,
+    },
+    /// A P2WSH witness has more than [`MAX_STANDARD_P2WSH_STACK_ITEMS`] items.
+    WitnessStackItems {
+        /// The index of the input.
+        input: usize,
+        /// The number of stack items, not counting the witness script.
+        items: usize,
+    },
+    /// A P2WSH or tapscript witness stack item is too large.
+    WitnessStackItemSize {
+        /// The index of the input.
+        input: usize,
+        /// The size of the stack item.
+        size: usize,
+    },
+    /// A Taproot witness has an annex.
+    TaprootAnnex {
+        /// The index of the input.
+        input: usize,
+    },
+    /// A Taproot script path witness has an empty control block.
+    EmptyControlBlock {
+        /// The index of the input.
+        input: usize,
+    },
+    /// The sigop cost of the transaction is above [`MAX_STANDARD_TX_SIGOPS_COST`].
+    SigopCost(usize),
+}
+
+impl NonStandardError {
+    /// Returns the reject reason Bitcoin Core reports for this error.
+    pub fn reject_reason(&self) -> &'static str {
+        use NonStandardError::*;
+
+        match *self {
+            Version(_) => "version",
+            TxSize(_) => "tx-size",
+            TxSizeSmall(_) => "tx-size-small",
+            ScriptSigSize { .. } => "scriptsig-size",
+            ScriptSigNotPushOnly { .. } => "scriptsig-not-pushonly",
+            ScriptPubKey { .. } => "scriptpubkey",
+            Dust { .. } => "dust",
+            MultiOpReturn => "multi-op-return",
+            MissingPrevout { .. } => "bad-txns-inputs-missingorspent",
+            NonStandardInput { .. } | P2shSigops { .. } => "bad-txns-nonstandard-inputs",
+            UnexpectedWitness { .. }
+            | WitnessScriptSize { .. }
+            | WitnessStackItems { .. }
+            | WitnessStackItemSize { .. }
+            | TaprootAnnex { .. }
+            | EmptyControlBlock { .. } => "bad-witness-nonstandard",
+            SigopCost(_) => "bad-txns-too-many-sigops",
+        }
+    }
+}
+
+impl fmt::Display for NonStandardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use NonStandardError::*;
+
+        match *self {
+            Version(version) => write!(f, "transaction version {} is not standard", version),
+            TxSize(weight) => write!(
+                f,
+                "transaction weight {} exceeds the maximum {}",
+                weight.to_wu(),
+                MAX_STANDARD_TX_WEIGHT
+            ),
+            TxSizeSmall(size) => write!(
+                f,
+                "transaction size without witness {} is below the minimum {}",
+                size, MIN_STANDARD_TX_NONWITNESS_SIZE
+            ),
+            ScriptSigSize { input, size } => write!(
+                f,
+                "scriptSig of input {} is {} bytes, the maximum is {}",
+                input, size, MAX_STANDARD_SCRIPTSIG_SIZE
+            ),
+            ScriptSigNotPushOnly { input } =>
+                write!(f, "scriptSig of input {} is not push only", input),
+            ScriptPubKey { output } => write!(f, "output {} has a non-standard script", output),
+            Dust { output, value, minimum } =>
+                write!(f, "output {} value {} is below the dust limit {}", output, value, minimum),
+            MultiOpReturn => f.write_str("transaction has more than one OP_RETURN output"),
+            MissingPrevout { input } => write!(f, "output spent by input {} is missing", input),
+            NonStandardInput { input } => write!(f, "input {} spends a non-standard output", input),
+            P2shSigops { input, sigops } => write!(
+                f,
+                "redeem script of input {} has {} sigops, the maximum is {}",
+                input, sigops, MAX_P2SH_SIGOPS
+            ),
+            UnexpectedWitness { input } =>
+                write!(f, "input {} has a witness but does not spend a witness program", input),
+            WitnessScriptSize { input, size } => write!(
+                f,
+                "witness script of input {} is {} bytes, the maximum is {}",
+                input, size, MAX_STANDARD_P2WSH_SCRIPT_SIZE
+            ),
+            WitnessStackItems { input, items } => write!(
+                f,
+                "witness of input {} has {} stack items, the maximum is {}",
+                input, items, MAX_STANDARD_P2WSH_STACK_ITEMS
+            ),
+            WitnessStackItemSize { input, size } =>
+                write!(f, "witness of input {} has a {} byte stack item", input, size),
+            TaprootAnnex { input } => write!(f, "witness of input {} has an annex", input),
+            EmptyControlBlock { input } =>
+                write!(f, "witness of input {} has an empty control block", input),
+            SigopCost(cost) => write!(
+                f,
+                "transaction sigop cost {} exceeds the maximum {}",
+                cost, MAX_STANDARD_TX_SIGOPS_COST
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for NonStandardError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::PubkeyHash;
+    use crate::locktime::absolute;
+    use crate::opcodes::all::*;
+    use crate::opcodes::OP_TRUE;
+    use crate::script::{
+        Builder, PushBytes, PushBytesBuf, RedeemScriptBuf, ScriptPubKeyBuf,
+        ScriptPubKeyBufExt as _, ScriptSigBuf, TapScriptBuf, WitnessScriptBuf,
+        WitnessScriptExt as _,
+    };
+    use crate::{Sequence, TxIn, Txid, Witness};
+
+    fn p2wpkh() -> ScriptPubKeyBuf {
+        ScriptPubKeyBuf::from_bytes([[0x00, 0x14].as_slice(), &[0xab; 20]].concat())
+    }
+
+    fn prevout(script_pubkey: ScriptPubKeyBuf) -> TxOut {
+        TxOut { value: Amount::from_sat_u32(100_000), script_pubkey }
+    }
+
+    fn input(vout: u32) -> TxIn {
+        TxIn {
+            previous_output: OutPoint { txid: Txid::from_byte_array([1; 32]), vout },
+            script_sig: ScriptSigBuf::new(),
+            sequence: Sequence::ENABLE_LOCKTIME_AND_RBF,
+            witness: Witness::from_slice(&[[0x30; 72].as_slice(), &[0x02; 33]]),
+        }
+    }
+
+    /// A standard one-input, one-output P2WPKH transaction.
+    fn tx() -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            inputs: vec![input(0)],
+            outputs: vec![TxOut { value: Amount::from_sat_u32(50_000), script_pubkey: p2wpkh() }],
+        }
+    }
+
+    /// A 1-of-`n` bare multisig script.
+    fn multisig(n: usize) -> ScriptPubKeyBuf {
+        let mut builder = Builder::new().push_int_unchecked(1);
+        for _ in 0..n {
+            builder = builder.push_slice([0x02; 33]);
+        }
+        builder.push_int_unchecked(n as i64).push_opcode(OP_CHECKMULTISIG).into_script()
+    }
+
+    fn op_return(data: &[u8]) -> ScriptPubKeyBuf {
+        let data = <&PushBytes>::try_from(data).unwrap();
+        ScriptPubKeyBuf::new_op_return(data)
+    }
+
+    #[test]
+    fn standard_transaction() {
+        let tx = tx();
+        assert_eq!(check_standard(&tx, |_| Some(prevout(p2wpkh()))), Ok(()));
+    }
+
+    #[test]
+    fn version() {
+        let mut tx = tx();
+        tx.version = transaction::Version::maybe_non_standard(4);
+        let err = check_standard_tx(&tx).unwrap_err();
+        assert_eq!(err, NonStandardError::Version(tx.version));
+        assert_eq!(err.reject_reason(), "version");
+
+        tx.version = transaction::Version::THREE;
+        assert_eq!(check_standard_tx(&tx), Ok(()));
+    }
+
+    #[test]
+    fn size() {
+        let mut tx = tx();
+        tx.outputs[0].script_pubkey = op_return(&[]);
+        tx.outputs[0].value = Amount::ZERO;
+        assert!(matches!(check_standard_tx(&tx), Err(NonStandardError::TxSizeSmall(_))));
+
+        let mut tx = self::tx();
+        let output = tx.outputs[0].clone();
+        tx.outputs = vec![output; 10_000];
+        let err = check_standard_tx(&tx).unwrap_err();
+        assert_eq!(err, NonStandardError::TxSize(tx.weight()));
+        assert_eq!(err.reject_reason(), "tx-size");
+    }
+
+    #[test]
+    fn script_sig() {
+        let mut tx = tx();
+        tx.inputs[0].script_sig =
+            Builder::new().push_opcode(OP_PUSHNUM_1).push_opcode(OP_DROP).into_script();
+        assert_eq!(
+            check_standard_tx(&tx),
+            Err(NonStandardError::ScriptSigNotPushOnly { input: 0 })
+        );
+
+        let push = PushBytesBuf::try_from(vec![0u8; 520]).unwrap();
+        let mut builder = Builder::new();
+        for _ in 0..4 {
+            builder = builder.push_slice(&push);
+        }
+        tx.inputs[0].script_sig = builder.into_script();
+        assert_eq!(
+            check_standard_tx(&tx),
+            Err(NonStandardError::ScriptSigSize { input: 0, size: 4 * 523 })
+        );
+    }
+
+    #[test]
+    fn output_types() {
+        assert!(is_standard_script_pubkey(&multisig(3)));
+        assert!(!is_standard_script_pubkey(&multisig(4)));
+
+        assert!(is_standard_script_pubkey(&op_return(&[0; 80])));
+        assert!(!is_standard_script_pubkey(&op_return(&[0; 81])));
+        let not_push_only = Builder::new().push_opcode(OP_RETURN).push_opcode(OP_ADD).into_script();
+        assert!(!is_standard_script_pubkey(&not_push_only));
+
+        // Unknown witness versions are standard to create, but not to spend.
+        let v2 = ScriptPubKeyBuf::from_bytes([[0x52, 0x20].as_slice(), &[0xab; 32]].concat());
+        assert!(is_standard_script_pubkey(&v2));
+        let v0 = ScriptPubKeyBuf::from_bytes([[0x00, 0x18].as_slice(), &[0xab; 24]].concat());
+        assert!(!is_standard_script_pubkey(&v0));
+
+        let mut tx = tx();
+        tx.outputs[0].script_pubkey = Builder::new().push_opcode(OP_TRUE).into_script();
+        assert_eq!(check_standard_tx(&tx), Err(NonStandardError::ScriptPubKey { output: 0 }));
+
+        tx.outputs[0].script_pubkey = v2.clone();
+        assert_eq!(check_standard_tx(&tx), Ok(()));
+        assert_eq!(
+            check_standard_inputs(&tx, |_| Some(prevout(v2.clone()))),
+            Err(NonStandardError::NonStandardInput { input: 0 })
+        );
+    }
+
+    #[test]
+    fn op_returns_and_dust() {
+        let mut tx = tx();
+        tx.outputs.push(TxOut { value: Amount::ZERO, script_pubkey: op_return(b"hello") });
+        assert_eq!(check_standard_tx(&tx), Ok(()));
+        tx.outputs.push(TxOut { value: Amount::ZERO, script_pubkey: op_return(b"world") });
+        assert_eq!(check_standard_tx(&tx), Err(NonStandardError::MultiOpReturn));
+
+        let mut tx = self::tx();
+        let minimum = p2wpkh().minimal_non_dust();
+        assert_eq!(minimum, Amount::from_sat_u32(294));
+        tx.outputs[0].value = minimum;
+        assert_eq!(check_standard_tx(&tx), Ok(()));
+        tx.outputs[0].value = Amount::from_sat_u32(293);
+        assert_eq!(
+            check_standard_tx(&tx),
+            Err(NonStandardError::Dust { output: 0, value: tx.outputs[0].value, minimum })
+        );
+
+        // Output scripts are checked before dust, dust before the number of `OP_RETURN` outputs.
+        let op_returns = vec![TxOut { value: Amount::ZERO, script_pubkey: op_return(b"data") }; 2];
+        tx.outputs.extend(op_returns);
+        assert!(matches!(check_standard_tx(&tx), Err(NonStandardError::Dust { output: 0, .. })));
+        let non_standard = Builder::new().push_opcode(OP_TRUE).into_script();
+        tx.outputs.push(TxOut { value: Amount::ONE_BTC, script_pubkey: non_standard });
+        assert_eq!(check_standard_tx(&tx), Err(NonStandardError::ScriptPubKey { output: 3 }));
+    }
+
+    #[test]
+    fn multisig_prevouts() {
+        let mut tx = tx();
+        tx.inputs[0].witness = Witness::new();
+        let spends = |script_pubkey: ScriptPubKeyBuf| {
+            check_standard_inputs(&tx, |_| Some(prevout(script_pubkey.clone())))
+        };
+
+        // Bare multisig with more than three keys is not standard to create, but is to spend.
+        for n in [1, 3, 4, 16, 17, 20] {
+            assert_eq!(is_standard_script_pubkey(&multisig(n)), n <= 3);
+            assert_eq!(spends(multisig(n)), Ok(()), "{} keys", n);
+        }
+        assert_eq!(spends(multisig(21)), Err(NonStandardError::NonStandardInput { input: 0 }));
+
+        let mut many_keys = Builder::new().push_int_unchecked(1);
+        for _ in 0..300 {
+            many_keys = many_keys.push_slice([0x02; 33]);
+        }
+        let many_keys = many_keys.push_opcode(OP_CHECKMULTISIG).into_script();
+        assert_eq!(spends(many_keys), Err(NonStandardError::NonStandardInput { input: 0 }));
+
+        // Key counts above 16 must be pushed minimally.
+        let mut script_pubkey = multisig(17).into_bytes();
+        let len = script_pubkey.len();
+        script_pubkey.splice(len - 3..len - 1, [OP_PUSHDATA1.to_u8(), 0x01, 0x11]);
+        let non_minimal = ScriptPubKeyBuf::from_bytes(script_pubkey);
+        assert_eq!(spends(non_minimal), Err(NonStandardError::NonStandardInput { input: 0 }));
+
+        // Keys must have the size their prefix requires.
+        let wrong_prefix = Builder::new()
+            .push_int_unchecked(1)
+            .push_slice([0x04; 33])
+            .push_int_unchecked(1)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        assert!(!is_standard_script_pubkey(&wrong_prefix));
+        assert_eq!(spends(wrong_prefix), Err(NonStandardError::NonStandardInput { input: 0 }));
+    }
+
+    #[test]
+    fn missing_prevout() {
+        let tx = tx();
+        assert_eq!(
+            check_standard_inputs(&tx, |_| None),
+            Err(NonStandardError::MissingPrevout { input: 0 })
+        );
+    }
+
+    #[test]
+    fn p2sh_sigops() {
+        let mut redeem_script = Builder::new();
+        for _ in 0..16 {
+            redeem_script = redeem_script.push_opcode(OP_CHECKSIG);
+        }
+        let redeem_script: RedeemScriptBuf = redeem_script.into_script();
+        let script_pubkey = ScriptPubKeyBuf::new_p2sh(redeem_script.script_hash().unwrap());
+
+        let mut tx = tx();
+        tx.inputs[0].witness = Witness::new();
+        let push = <&PushBytes>::try_from(redeem_script.as_bytes()).unwrap();
+        tx.inputs[0].script_sig = Builder::new().push_slice(push).into_script();
+        assert_eq!(
+            check_standard_inputs(&tx, |_| Some(prevout(script_pubkey.clone()))),
+            Err(NonStandardError::P2shSigops { input: 0, sigops: 16 })
+        );
+    }
+
+    #[test]
+    fn unexpected_witness() {
+        let script_pubkey = ScriptPubKeyBuf::new_p2pkh(PubkeyHash::from_byte_array([0xab; 20]));
+        let tx = tx();
+        assert_eq!(
+            check_standard_inputs(&tx, |_| Some(prevout(script_pubkey.clone()))),
+            Err(NonStandardError::UnexpectedWitness { input: 0 })
+        );
+    }
+
+    #[test]
+    fn p2wsh_witness() {
+        let witness_script: WitnessScriptBuf =
+            Builder::new().push_opcode(OP_DROP).push_opcode(OP_TRUE).into_script();
+        let script_pubkey = ScriptPubKeyBuf::new_p2wsh(witness_script.wscript_hash().unwrap());
+        let spent = |_: &OutPoint| Some(prevout(script_pubkey.clone()));
+
+        let mut tx = tx();
+        let with_stack = |stack: Vec<Vec<u8>>| {
+            let mut stack = stack;
+            stack.push(witness_script.as_bytes().to_vec());
+            Witness::from_slice(&stack)
+        };
+
+        tx.inputs[0].witness = with_stack(vec![vec![0; 80]]);
+        assert_eq!(check_standard_inputs(&tx, spent), Ok(()));
+
+        tx.inputs[0].witness = with_stack(vec![vec![0; 81]]);
+        assert_eq!(
+            check_standard_inputs(&tx, spent),
+            Err(NonStandardError::WitnessStackItemSize { input: 0, size: 81 })
+        );
+
+        tx.inputs[0].witness = with_stack(vec![vec![]; 101]);
+        assert_eq!(
+            check_standard_inputs(&tx, spent),
+            Err(NonStandardError::WitnessStackItems { input: 0, items: 101 })
+        );
+
+        tx.inputs[0].witness = Witness::from_slice(&[vec![0; 3601]]);
+        let err = check_standard_inputs(&tx, spent).unwrap_err();
+        assert_eq!(err, NonStandardError::WitnessScriptSize { input: 0, size: 3601 });
+        assert_eq!(err.reject_reason(), "bad-witness-nonstandard");
+    }
+
+    #[test]
+    fn taproot_witness() {
+        let script_pubkey =
+            ScriptPubKeyBuf::from_bytes([[0x51, 0x20].as_slice(), &[0xab; 32]].concat());
+        let spent = |_: &OutPoint| Some(prevout(script_pubkey.clone()));
+
+        let mut tx = tx();
+        tx.inputs[0].witness = Witness::from_slice(&[[0x30; 64]]);
+        assert_eq!(check_standard_inputs(&tx, spent), Ok(()));
+
+        tx.inputs[0].witness = Witness::from_slice(&[[0x30; 64].as_slice(), &[0x50, 0x01]]);
+        assert_eq!(
+            check_standard_inputs(&tx, spent),
+            Err(NonStandardError::TaprootAnnex { input: 0 })
+        );
+
+        let tapscript: TapScriptBuf =
+            Builder::new().push_opcode(OP_DROP).push_opcode(OP_TRUE).into_script();
+        let control_block = [[0xc0].as_slice(), &[0x02; 32]].concat();
+        let witness = |item: Vec<u8>| {
+            Witness::from_slice(&[item, tapscript.as_bytes().to_vec(), control_block.clone()])
+        };
+        tx.inputs[0].witness = witness(vec![0; 80]);
+        assert_eq!(check_standard_inputs(&tx, spent), Ok(()));
+        tx.inputs[0].witness = witness(vec![0; 81]);
+        assert_eq!(
+            check_standard_inputs(&tx, spent),
+            Err(NonStandardError::WitnessStackItemSize { input: 0, size: 81 })
+        );
+
+        let empty_control_block = [vec![0; 80], tapscript.as_bytes().to_vec(), vec![]];
+        tx.inputs[0].witness = Witness::from_slice(&empty_control_block);
+        let err = check_standard_inputs(&tx, spent).unwrap_err();
+        assert_eq!(err, NonStandardError::EmptyControlBlock { input: 0 });
+        assert_eq!(err.reject_reason(), "bad-witness-nonstandard");
+
+        // The annex is detected even if the control block is invalid.
+        let mut with_annex = empty_control_block.to_vec();
+        with_annex.push(vec![0x50]);
+        tx.inputs[0].witness = Witness::from_slice(&with_annex);
+        assert_eq!(
+            check_standard_inputs(&tx, spent),
+            Err(NonStandardError::TaprootAnnex { input: 0 })
+        );
+    }
+
+    #[test]
+    fn sigop_cost() {
+        // Bare multisig outputs cost 80 sigops each, the P2WPKH input costs one.
+        let key = [0x02; 33];
+        let multisig = Builder::new()
+            .push_int_unchecked(1)
+            .push_slice(key)
+            .push_int_unchecked(1)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let mut tx = tx();
+        tx.outputs =
+            vec![TxOut { value: Amount::from_sat_u32(1_000), script_pubkey: multisig }; 201];
+        assert_eq!(check_standard_tx(&tx), Ok(()));
+        assert_eq!(
+            check_standard_inputs(&tx, |_| Some(prevout(p2wpkh()))),
+            Err(NonStandardError::SigopCost(201 * 80 + 1))
+        );
+    }
+}
@@ -328,6 +328,30 @@ pub fn max_transition_threshold(&self, params: impl AsRef<Params>) -> Self {
     /// The return value should be checked against [`Params::max_attainable_target`] or use one of
     /// the `Target::MAX_ATTAINABLE_FOO` constants.
     pub fn max_transition_threshold_unchecked(&self) -> Self { Self(self.0 << 2) }
+
+    /// Computes `self * actual_timespan / target_timespan` saturating at `max`.
+    ///
+    /// Unlike [`CompactTarget::from_next_work_required`] this does not overflow for targets close
+    /// to 2^256, such as the regtest maximum.
+    pub(crate) fn retarget_saturating(
+        &self,
+        actual_timespan: u32,
+        target_timespan: u32,
+        max: Target,
+    ) -> Self {
+        let target_timespan = U256::from(target_timespan);
+        let (quotient, remainder) = self.0.div_rem(target_timespan);
+        let (high, overflow) = quotient.mul_u64(actual_timespan.into());
+        if overflow {
+            return max;
+        }
+        // The remainder is less than `target_timespan` so this can't overflow.
+        let low = remainder.mul_u64(actual_timespan.into()).0 / target_timespan;
+        match high.overflowing_add(low) {
+            (retarget, false) => cmp::min(Self(retarget), max),
+            (_, true) => max,
+        }
+    }
 }
 do_impl!(Target);
 impl_to_hex_from_lower_hex!(Target, |_| 64);
@@ -1752,6 +1776,25 @@ fn compact_target_from_downwards_difficulty_adjustment() {
         assert_eq!(adjustment, adjustment_bits);
     }
 
+    #[test]
+    fn target_retarget_saturating() {
+        // Signet blocks 2016 and 4032, as in the `from_next_work_required` tests above.
+        let params = Params::new(crate::Network::Signet);
+        let max = params.max_attainable_target;
+        let target = Target::from(CompactTarget::from_consensus(503543726));
+        let adjusted = target.retarget_saturating(1599332177 - 1598918400, 1_209_600, max);
+        assert_eq!(adjusted.to_compact_lossy(), CompactTarget::from_consensus(503394215));
+        let target = Target::from(CompactTarget::from_consensus(503394215));
+        let adjusted = target.retarget_saturating(1600591200 - 1599332844, 1_209_600, max);
+        assert_eq!(adjusted.to_compact_lossy(), CompactTarget::from_consensus(503397348));
+
+        // Multiplying the regtest maximum by four would overflow 256 bits.
+        let max = Target::MAX_ATTAINABLE_REGTEST;
+        assert_eq!(max.retarget_saturating(4, 1, max), max);
+        assert_eq!(max.retarget_saturating(1, 4, max), Target(max.0 >> 2));
+        assert_eq!(Target::MAX.retarget_saturating(u32::MAX, 1, Target::MAX), Target::MAX);
+    }
+
     #[test]
     fn compact_target_from_upwards_difficulty_adjustment_using_headers() {
         use crate::block::Version;
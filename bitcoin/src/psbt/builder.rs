@@ -0,0 +1,653 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Transaction builder.
+//!
+//! Builds an unsigned transaction paying to a set of recipients, computes the fee from a fee rate
+//! using [`predict_weight`] and adds change. The result is returned as a [`Psbt`] with the UTXO
+//! information filled in, ready to be passed to signers.
+//!
+//! The builder spends every input it is given, use [`coin_selection`](crate::coin_selection) to
+//! choose them.
+
+use core::fmt;
+
+use super::Psbt;
+use crate::address::Address;
+use crate::locktime::absolute;
+use crate::policy::{MAX_OP_RETURN_RELAY, MAX_STANDARD_TX_WEIGHT};
+use crate::prelude::Vec;
+use crate::script::{
+    PushBytes, RedeemScriptBuf, ScriptExt as _, ScriptPubKeyBuf, ScriptPubKeyBufExt as _,
+    ScriptPubKeyExt as _, ScriptSigBuf,
+};
+use crate::transaction::{
+    self, predict_weight, InputWeightPrediction, OutPoint, Transaction, TxIn, TxOut,
+};
+use crate::{Amount, FeeRate, Sequence, Weight, Witness};
+
+/// An output that can be spent by a transaction built with [`TxBuilder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpendableInput {
+    /// The output being spent.
+    pub outpoint: OutPoint,
+    /// The value and script of the output being spent.
+    pub txout: TxOut,
+    /// The predicted weight of the input once it is satisfied.
+    pub weight_prediction: InputWeightPrediction,
+    /// The transaction that created the output being spent.
+    ///
+    /// Required unless `txout` is a segwit output or a P2SH output wrapping a segwit program.
+    pub previous_tx: Option<Transaction>,
+    /// The redeem script of the P2SH output being spent.
+    pub redeem_script: Option<RedeemScriptBuf>,
+}
+
+impl SpendableInput {
+    /// Constructs a new spendable input without its previous transaction.
+    pub fn new(outpoint: OutPoint, txout: TxOut, weight_prediction: InputWeightPrediction) -> Self {
+        SpendableInput {
+            outpoint,
+            txout,
+            weight_prediction,
+            previous_tx: None,
+            redeem_script: None,
+        }
+    }
+
+    /// Sets the transaction that created the output being spent.
+    #[must_use]
+    pub fn with_previous_tx(mut self, previous_tx: Transaction) -> Self {
+        self.previous_tx = Some(previous_tx);
+        self
+    }
+
+    /// Sets the redeem script of the P2SH output being spent.
+    #[must_use]
+    pub fn with_redeem_script(mut self, redeem_script: RedeemScriptBuf) -> Self {
+        self.redeem_script = Some(redeem_script);
+        self
+    }
+
+    /// Returns true if the output being spent is segwit, natively or wrapped in P2SH.
+    fn is_segwit(&self) -> bool {
+        self.txout.script_pubkey.is_witness_program()
+            || self
+                .redeem_script
+                .as_ref()
+                .map_or(false, |script| script.witness_version().is_some())
+    }
+}
+
+/// Builds an unsigned transaction and returns it as a [`Psbt`].
+///
+/// Defaults to a version 2 transaction without lock time that does not signal replaceability,
+/// paying [`FeeRate::BROADCAST_MIN`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxBuilder {
+    version: transaction::Version,
+    lock_time: absolute::LockTime,
+    sequence: Sequence,
+    fee_rate: FeeRate,
+    inputs: Vec<SpendableInput>,
+    outputs: Vec<TxOut>,
+    op_return: Option<ScriptPubKeyBuf>,
+    change_script: Option<ScriptPubKeyBuf>,
+}
+
+impl TxBuilder {
+    /// Constructs a new, empty transaction builder.
+    pub fn new() -> Self {
+        TxBuilder {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+            fee_rate: FeeRate::BROADCAST_MIN,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            op_return: None,
+            change_script: None,
+        }
+    }
+
+    /// Adds an output paying `amount` to `address`.
+    pub fn add_recipient(self, address: &Address, amount: Amount) -> Self {
+        self.add_recipient_script(address.script_pubkey(), amount)
+    }
+
+    /// Adds an output paying `amount` to `script_pubkey`.
+    pub fn add_recipient_script(mut self, script_pubkey: ScriptPubKeyBuf, amount: Amount) -> Self {
+        self.outputs.push(TxOut { value: amount, script_pubkey });
+        self
+    }
+
+    /// Adds a zero value `OP_RETURN` output carrying `data`, replacing any previously set.
+    ///
+    /// The output is placed after the recipients.
+    pub fn op_return<T: AsRef<PushBytes>>(mut self, data: T) -> Self {
+        self.op_return = Some(ScriptPubKeyBuf::new_op_return(data));
+        self
+    }
+
+    /// Adds an input to spend.
+    pub fn add_input(mut self, input: SpendableInput) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    /// Adds all of `inputs` to spend.
+    pub fn add_inputs<I: IntoIterator<Item = SpendableInput>>(mut self, inputs: I) -> Self {
+        self.inputs.extend(inputs);
+        self
+    }
+
+    /// Sets the fee rate the transaction pays.
+    pub fn fee_rate(mut self, fee_rate: FeeRate) -> Self {
+        self.fee_rate = fee_rate;
+        self
+    }
+
+    /// Sets the script any change is paid to.
+    ///
+    /// Change is added as the last output, unless it would be dust in which case it is left to the
+    /// fee. Without a change script the whole excess is left to the fee.
+    pub fn change_script(mut self, change_script: ScriptPubKeyBuf) -> Self {
+        self.change_script = Some(change_script);
+        self
+    }
+
+    /// Sets the transaction version.
+    pub fn version(mut self, version: transaction::Version) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Sets the absolute lock time of the transaction.
+    pub fn lock_time(mut self, lock_time: absolute::LockTime) -> Self {
+        self.lock_time = lock_time;
+        self
+    }
+
+    /// Sets the sequence number used for every input.
+    pub fn sequence(mut self, sequence: Sequence) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Signals replaceability (BIP-0125) by setting the sequence number of every input to
+    /// [`Sequence::ENABLE_LOCKTIME_AND_RBF`].
+    pub fn enable_rbf(self) -> Self { self.sequence(Sequence::ENABLE_LOCKTIME_AND_RBF) }
+
+    /// Builds the transaction and returns it as an unsigned PSBT.
+    ///
+    /// The fee is the fee rate applied to the predicted weight of the signed transaction. Segwit
+    /// inputs, including P2SH-wrapped ones, get their `witness_utxo` set, inputs with a previous
+    /// transaction get their `non_witness_utxo` set and P2SH inputs get their `redeem_script` set.
+    ///
+    /// # Errors
+    ///
+    /// If the transaction has no inputs or outputs, a recipient output is dust, the inputs do not
+    /// cover the outputs and fee, or the transaction would be heavier than
+    /// [`MAX_STANDARD_TX_WEIGHT`]. See [`BuildError`] for the full list.
+    pub fn build(self) -> Result<Psbt, BuildError> {
+        if self.inputs.is_empty() {
+            return Err(BuildError::NoInputs);
+        }
+        if self.lock_time != absolute::LockTime::ZERO && !self.sequence.enables_absolute_lock_time()
+        {
+            return Err(BuildError::LockTimeDisabled);
+        }
+        for (index, input) in self.inputs.iter().enumerate() {
+            check_redeem_script(index, input)?;
+            check_previous_tx(index, input)?;
+        }
+
+        let mut outputs = self.outputs;
+        for (index, output) in outputs.iter().enumerate() {
+            let minimum = output.script_pubkey.minimal_non_dust();
+            if output.value < minimum {
+                return Err(BuildError::Dust { output: index, value: output.value, minimum });
+            }
+        }
+        if let Some(script_pubkey) = self.op_return {
+            if script_pubkey.len() > MAX_OP_RETURN_RELAY {
+                return Err(BuildError::OpReturnSize(script_pubkey.len()));
+            }
+            outputs.push(TxOut { value: Amount::ZERO, script_pubkey });
+        }
+
+        let available = sum(self.inputs.iter().map(|input| input.txout.value))?;
+        let spent = sum(outputs.iter().map(|output| output.value))?;
+
+        let predictions = self.inputs.iter().map(|input| input.weight_prediction);
+        let script_lens =
+            outputs.iter().map(|output| output.script_pubkey.len()).collect::<Vec<_>>();
+        let mut weight = predict_weight(predictions.clone(), script_lens.iter().copied());
+        let required = sum([spent, self.fee_rate.to_fee(weight)])?;
+        if available < required {
+            return Err(BuildError::InsufficientFunds { available, required });
+        }
+
+        if let Some(script_pubkey) = self.change_script {
+            let change_lens = script_lens.iter().copied().chain(Some(script_pubkey.len()));
+            let weight_with_change = predict_weight(predictions, change_lens);
+            let required = sum([spent, self.fee_rate.to_fee(weight_with_change)])?;
+            if let Some(change) = available.checked_sub(required) {
+                if change >= script_pubkey.minimal_non_dust() {
+                    outputs.push(TxOut { value: change, script_pubkey });
+                    weight = weight_with_change;
+                }
+            }
+        }
+
+        if outputs.is_empty() {
+            return Err(BuildError::NoOutputs);
+        }
+
+        let max = Weight::from_wu(u64::from(MAX_STANDARD_TX_WEIGHT));
+        if weight > max {
+            return Err(BuildError::TooLarge(weight));
+        }
+
+        let tx = Transaction {
+            version: self.version,
+            lock_time: self.lock_time,
+            inputs: self
+                .inputs
+                .iter()
+                .map(|input| TxIn {
+                    previous_output: input.outpoint,
+                    script_sig: ScriptSigBuf::new(),
+                    sequence: self.sequence,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            outputs,
+        };
+
+        let mut psbt = Psbt::from_unsigned_tx(tx).expect("script sigs and witnesses are empty");
+        for (input, psbt_input) in self.inputs.into_iter().zip(psbt.inputs.iter_mut()) {
+            if input.is_segwit() {
+                psbt_input.witness_utxo = Some(input.txout);
+            }
+            psbt_input.non_witness_utxo = input.previous_tx;
+            psbt_input.redeem_script = input.redeem_script;
+        }
+        Ok(psbt)
+    }
+}
+
+impl Default for TxBuilder {
+    fn default() -> Self { Self::new() }
+}
+
+/// Checks that `input` has a previous transaction if it needs one, and that it matches.
+fn check_previous_tx(index: usize, input: &SpendableInput) -> Result<(), BuildError> {
+    match input.previous_tx {
+        Some(ref tx) => {
+            let vout = input.outpoint.vout as usize;
+            if tx.compute_txid() != input.outpoint.txid
+                || tx.outputs.get(vout) != Some(&input.txout)
+            {
+                return Err(BuildError::PreviousTxMismatch { input: index });
+            }
+        }
+        None =>
+            if !input.is_segwit() {
+                return Err(BuildError::MissingPreviousTx { input: index });
+            },
+    }
+    Ok(())
+}
+
+/// Checks that the redeem script of `input`, if any, is the one committed to by its P2SH output.
+fn check_redeem_script(index: usize, input: &SpendableInput) -> Result<(), BuildError> {
+    if let Some(ref redeem_script) = input.redeem_script {
+        if redeem_script.to_p2sh().ok().as_ref() != Some(&input.txout.script_pubkey) {
+            return Err(BuildError::RedeemScriptMismatch { input: index });
+        }
+    }
+    Ok(())
+}
+
+/// Sums `amounts`, erroring if the total is out of range.
+fn sum<I: IntoIterator<Item = Amount>>(amounts: I) -> Result<Amount, BuildError> {
+    amounts
+        .into_iter()
+        .try_fold(Amount::ZERO, Amount::checked_add)
+        .ok_or(BuildError::ValueOutOfRange)
+}
+
+/// Error returned by [`TxBuilder::build`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// No inputs were added.
+    NoInputs,
+    /// No outputs were added and there is no change left to pay to the change script.
+    NoOutputs,
+    /// A lock time was set but the sequence number disables it.
+    LockTimeDisabled,
+    /// A non-segwit input is missing its previous transaction.
+    MissingPreviousTx {
+        /// The index of the input.
+        input: usize,
+    },
+    /// The previous transaction of an input does not contain the output being spent.
+    PreviousTxMismatch {
+        /// The index of the input.
+        input: usize,
+    },
+    /// The redeem script of an input does not hash to the P2SH output being spent.
+    RedeemScriptMismatch {
+        /// The index of the input.
+        input: usize,
+    },
+    /// A recipient output is below the dust threshold.
+    Dust {
+        /// The index of the output.
+        output: usize,
+        /// The value of the output.
+        value: Amount,
+        /// The minimum non-dust value for the output's script.
+        minimum: Amount,
+    },
+    /// The `OP_RETURN` output script is larger than standardness rules allow.
+    OpReturnSize(usize),
+    /// The total value of the inputs or outputs is out of range.
+    ValueOutOfRange,
+    /// The inputs do not cover the outputs and fee.
+    InsufficientFunds {
+        /// The total value of the inputs.
+        available: Amount,
+        /// The total value of the outputs plus the fee.
+        required: Amount,
+    },
+    /// The transaction would exceed [`MAX_STANDARD_TX_WEIGHT`].
+    TooLarge(Weight),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use BuildError::*;
+
+        match *self {
+            NoInputs => f.write_str("transaction has no inputs"),
+            NoOutputs => f.write_str("transaction has no outputs"),
+            LockTimeDisabled => f.write_str("lock time is set but disabled by the sequence number"),
+            MissingPreviousTx { input } =>
+                write!(f, "input {} is not segwit and has no previous transaction", input),
+            PreviousTxMismatch { input } => write!(
+                f,
+                "previous transaction of input {} does not contain the spent output",
+                input
+            ),
+            RedeemScriptMismatch { input } =>
+                write!(f, "redeem script of input {} does not match the spent output", input),
+            Dust { output, value, minimum } => write!(
+                f,
+                "output {} value {} is below the dust threshold {}",
+                output,
+                value.display_dynamic(),
+                minimum.display_dynamic()
+            ),
+            OpReturnSize(size) => write!(
+                f,
+                "OP_RETURN script is {} bytes, exceeding the maximum {}",
+                size, MAX_OP_RETURN_RELAY
+            ),
+            ValueOutOfRange => f.write_str("total value is out of range"),
+            InsufficientFunds { available, required } => write!(
+                f,
+                "insufficient funds: {} available but {} is required",
+                available.display_dynamic(),
+                required.display_dynamic()
+            ),
+            TooLarge(weight) => write!(
+                f,
+                "transaction weight {} exceeds the maximum {}",
+                weight, MAX_STANDARD_TX_WEIGHT
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use BuildError::*;
+
+        match *self {
+            NoInputs
+            | NoOutputs
+            | LockTimeDisabled
+            | MissingPreviousTx { .. }
+            | PreviousTxMismatch { .. }
+            | RedeemScriptMismatch { .. }
+            | Dust { .. }
+            | OpReturnSize(_)
+            | ValueOutOfRange
+            | InsufficientFunds { .. }
+            | TooLarge(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::KnownHrp;
+    use crate::key::{PubkeyHash, WPubkeyHash};
+    use crate::script::ScriptBufExt as _;
+    use crate::transaction::TransactionExt as _;
+    use crate::{CompressedPublicKey, Txid};
+
+    fn p2wpkh() -> ScriptPubKeyBuf {
+        ScriptPubKeyBuf::from_bytes([[0x00, 0x14].as_slice(), &[0xab; 20]].concat())
+    }
+
+    fn change() -> ScriptPubKeyBuf {
+        ScriptPubKeyBuf::from_bytes([[0x00, 0x14].as_slice(), &[0xcd; 20]].concat())
+    }
+
+    fn segwit_input(n: u8, value: u64) -> SpendableInput {
+        let outpoint = OutPoint { txid: Txid::from_byte_array([n; 32]), vout: 0 };
+        let txout = TxOut { value: Amount::from_sat(value).unwrap(), script_pubkey: p2wpkh() };
+        SpendableInput::new(outpoint, txout, InputWeightPrediction::P2WPKH_MAX)
+    }
+
+    fn recipient() -> Address {
+        let pk = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+            .parse::<CompressedPublicKey>()
+            .unwrap();
+        Address::p2wpkh(pk, KnownHrp::Mainnet)
+    }
+
+    fn expected_fee(psbt: &Psbt, fee_rate: FeeRate) -> Amount {
+        let lens = psbt.unsigned_tx.outputs.iter().map(|output| output.script_pubkey.len());
+        let predictions = psbt.inputs.iter().map(|_| InputWeightPrediction::P2WPKH_MAX);
+        fee_rate.to_fee(predict_weight(predictions, lens))
+    }
+
+    #[test]
+    fn build_with_change() {
+        let fee_rate = FeeRate::from_sat_per_vb(5);
+        let psbt = TxBuilder::new()
+            .add_input(segwit_input(1, 100_000))
+            .add_input(segwit_input(2, 50_000))
+            .add_recipient(&recipient(), Amount::from_sat_u32(120_000))
+            .fee_rate(fee_rate)
+            .change_script(change())
+            .build()
+            .unwrap();
+
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.inputs.len(), 2);
+        assert_eq!(tx.outputs.len(), 2);
+        assert_eq!(tx.outputs[0].script_pubkey, recipient().script_pubkey());
+        assert_eq!(tx.outputs[1].script_pubkey, change());
+        assert!(tx.inputs.iter().all(|input| input.sequence == Sequence::ENABLE_LOCKTIME_NO_RBF));
+        assert!(psbt.inputs.iter().all(|input| input.witness_utxo.is_some()));
+        assert_eq!(psbt.fee().unwrap(), expected_fee(&psbt, fee_rate));
+    }
+
+    #[test]
+    fn dust_change_goes_to_fee() {
+        let fee_rate = FeeRate::from_sat_per_vb(1);
+        let psbt = TxBuilder::new()
+            .add_input(segwit_input(1, 100_000))
+            .add_recipient_script(p2wpkh(), Amount::from_sat_u32(99_800))
+            .fee_rate(fee_rate)
+            .change_script(change())
+            .build()
+            .unwrap();
+
+        assert_eq!(psbt.unsigned_tx.outputs.len(), 1);
+        assert_eq!(psbt.fee().unwrap(), Amount::from_sat_u32(200));
+    }
+
+    #[test]
+    fn op_return_and_rbf() {
+        let psbt = TxBuilder::new()
+            .add_input(segwit_input(1, 100_000))
+            .op_return(b"hello")
+            .change_script(change())
+            .enable_rbf()
+            .lock_time(absolute::LockTime::from_height(800_000).unwrap())
+            .build()
+            .unwrap();
+
+        let tx = &psbt.unsigned_tx;
+        assert!(tx.outputs[0].script_pubkey.is_op_return());
+        assert_eq!(tx.outputs[0].value, Amount::ZERO);
+        assert_eq!(tx.outputs[1].script_pubkey, change());
+        assert!(tx.is_explicitly_rbf());
+        assert_eq!(tx.lock_time, absolute::LockTime::from_height(800_000).unwrap());
+    }
+
+    #[test]
+    fn legacy_input_needs_previous_tx() {
+        let script_pubkey = ScriptPubKeyBuf::new_p2pkh(PubkeyHash::from_byte_array([0x11; 20]));
+        let previous_tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            inputs: vec![],
+            outputs: vec![TxOut { value: Amount::from_sat_u32(100_000), script_pubkey }],
+        };
+        let outpoint = OutPoint { txid: previous_tx.compute_txid(), vout: 0 };
+        let input = SpendableInput::new(
+            outpoint,
+            previous_tx.outputs[0].clone(),
+            InputWeightPrediction::P2PKH_COMPRESSED_MAX,
+        );
+
+        let builder = TxBuilder::new().add_recipient_script(p2wpkh(), Amount::from_sat_u32(50_000));
+        let err = builder.clone().add_input(input.clone()).build().unwrap_err();
+        assert_eq!(err, BuildError::MissingPreviousTx { input: 0 });
+
+        let psbt = builder.add_input(input.with_previous_tx(previous_tx.clone())).build().unwrap();
+        assert_eq!(psbt.inputs[0].non_witness_utxo, Some(previous_tx));
+        assert_eq!(psbt.inputs[0].witness_utxo, None);
+    }
+
+    #[test]
+    fn nested_segwit_input() {
+        let redeem_script = RedeemScriptBuf::new_p2wpkh(WPubkeyHash::from_byte_array([0x22; 20]));
+        let script_pubkey = redeem_script.to_p2sh().unwrap();
+        let outpoint = OutPoint { txid: Txid::from_byte_array([1; 32]), vout: 0 };
+        let txout = TxOut { value: Amount::from_sat_u32(100_000), script_pubkey };
+        let input =
+            SpendableInput::new(outpoint, txout.clone(), InputWeightPrediction::NESTED_P2WPKH_MAX);
+
+        let builder = TxBuilder::new().add_recipient_script(p2wpkh(), Amount::from_sat_u32(50_000));
+        // Without the redeem script the output can not be told apart from a legacy P2SH output.
+        let err = builder.clone().add_input(input.clone()).build().unwrap_err();
+        assert_eq!(err, BuildError::MissingPreviousTx { input: 0 });
+
+        let psbt = builder
+            .clone()
+            .add_input(input.clone().with_redeem_script(redeem_script.clone()))
+            .build()
+            .unwrap();
+        assert_eq!(psbt.inputs[0].witness_utxo, Some(txout));
+        assert_eq!(psbt.inputs[0].redeem_script, Some(redeem_script));
+        assert_eq!(psbt.inputs[0].non_witness_utxo, None);
+
+        let other = RedeemScriptBuf::new_p2wpkh(WPubkeyHash::from_byte_array([0x33; 20]));
+        let err = builder.add_input(input.with_redeem_script(other)).build().unwrap_err();
+        assert_eq!(err, BuildError::RedeemScriptMismatch { input: 0 });
+    }
+
+    #[test]
+    fn previous_tx_mismatch() {
+        let previous_tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            inputs: vec![],
+            outputs: vec![],
+        };
+        let err = TxBuilder::new()
+            .add_input(segwit_input(1, 100_000).with_previous_tx(previous_tx))
+            .add_recipient_script(p2wpkh(), Amount::from_sat_u32(50_000))
+            .build()
+            .unwrap_err();
+        assert_eq!(err, BuildError::PreviousTxMismatch { input: 0 });
+    }
+
+    #[test]
+    fn refuses_dust() {
+        let err = TxBuilder::new()
+            .add_input(segwit_input(1, 100_000))
+            .add_recipient_script(p2wpkh(), Amount::from_sat_u32(100))
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuildError::Dust { output: 0, .. }));
+    }
+
+    #[test]
+    fn insufficient_funds() {
+        let err = TxBuilder::new()
+            .add_input(segwit_input(1, 50_000))
+            .add_recipient_script(p2wpkh(), Amount::from_sat_u32(50_000))
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuildError::InsufficientFunds { .. }));
+    }
+
+    #[test]
+    fn refuses_too_large() {
+        let inputs = (0..=u8::MAX).flat_map(|n| {
+            (0..10).map(move |vout| {
+                let mut input = segwit_input(n, 10_000);
+                input.outpoint.vout = vout;
+                input
+            })
+        });
+        let err = TxBuilder::new()
+            .add_inputs(inputs)
+            .add_recipient_script(p2wpkh(), Amount::from_sat_u32(50_000))
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuildError::TooLarge(_)));
+    }
+
+    #[test]
+    fn lock_time_disabled() {
+        let err = TxBuilder::new()
+            .add_input(segwit_input(1, 100_000))
+            .add_recipient_script(p2wpkh(), Amount::from_sat_u32(50_000))
+            .lock_time(absolute::LockTime::from_height(800_000).unwrap())
+            .sequence(Sequence::MAX)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, BuildError::LockTimeDisabled);
+    }
+
+    #[test]
+    fn no_inputs_or_outputs() {
+        let err = TxBuilder::new().add_recipient(&recipient(), Amount::ONE_BTC).build();
+        assert_eq!(err.unwrap_err(), BuildError::NoInputs);
+
+        let err = TxBuilder::new().add_input(segwit_input(1, 100_000)).build();
+        assert_eq!(err.unwrap_err(), BuildError::NoOutputs);
+    }
+}
@@ -7,6 +7,7 @@
 
 use crate::bip32::Xpub;
 use crate::consensus::encode;
+use crate::locktime::absolute;
 use crate::prelude::Box;
 use crate::psbt::raw;
 use crate::{ecdsa, key, taproot, OutPoint, Transaction, Txid};
@@ -100,6 +101,12 @@ pub enum Error {
     InvalidEcdsaSignature(ecdsa::DecodeError),
     /// Parsing error indicating invalid Taproot signatures
     InvalidTaprootSignature(taproot::SigFromSliceError),
+    /// Parsing error indicating an invalid MuSig2 participant/aggregate key pair
+    InvalidMusig2Key,
+    /// Parsing error indicating an invalid MuSig2 public nonce
+    InvalidMusig2PubNonce,
+    /// Parsing error indicating an invalid MuSig2 partial signature
+    InvalidMusig2PartialSig,
     /// Parsing error indicating invalid control block
     InvalidControlBlock,
     /// Parsing error indicating invalid leaf version
@@ -112,6 +119,10 @@ pub enum Error {
     XPubKey(&'static str),
     /// Error related to PSBT version
     Version(&'static str),
+    /// Parsing error indicating an invalid required lock time.
+    InvalidLockTime(absolute::ConversionError),
+    /// The inputs of a PSBTv2 have required lock times that can't be satisfied together.
+    IncompatibleLockTimes,
     /// PSBT data is not consumed entirely
     PartialDataConsumption,
     /// I/O error.
@@ -176,12 +187,18 @@ fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             InvalidXOnlyPublicKey => f.write_str("invalid xonly public key"),
             InvalidEcdsaSignature(ref e) => write_err!(f, "invalid ECDSA signature"; e),
             InvalidTaprootSignature(ref e) => write_err!(f, "invalid Taproot signature"; e),
+            InvalidMusig2Key => f.write_str("invalid MuSig2 participant and aggregate key"),
+            InvalidMusig2PubNonce => f.write_str("invalid MuSig2 public nonce"),
+            InvalidMusig2PartialSig => f.write_str("invalid MuSig2 partial signature"),
             InvalidControlBlock => f.write_str("invalid control block"),
             InvalidLeafVersion => f.write_str("invalid leaf version"),
             Taproot(s) => write!(f, "Taproot error -  {}", s),
             TapTree(ref e) => write_err!(f, "Taproot tree error"; e),
             XPubKey(s) => write!(f, "xpub key error -  {}", s),
             Version(s) => write!(f, "version error {}", s),
+            InvalidLockTime(ref e) => write_err!(f, "invalid required lock time"; e),
+            IncompatibleLockTimes =>
+                f.write_str("inputs require both a height based and a time based lock time"),
             PartialDataConsumption =>
                 f.write_str("data not consumed entirely when explicitly deserializing"),
             Io(ref e) => write_err!(f, "I/O error"; e),
@@ -199,6 +216,7 @@ fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
             ConsensusEncoding(ref e) => Some(e),
             ConsensusDeserialize(ref e) => Some(e),
             ConsensusParse(ref e) => Some(e),
+            InvalidLockTime(ref e) => Some(e),
             Io(ref e) => Some(e),
             InvalidMagic
             | MissingUtxo
@@ -223,12 +241,16 @@ fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
             | InvalidXOnlyPublicKey
             | InvalidEcdsaSignature(_)
             | InvalidTaprootSignature(_)
+            | InvalidMusig2Key
+            | InvalidMusig2PubNonce
+            | InvalidMusig2PartialSig
             | InvalidControlBlock
             | InvalidLeafVersion
             | Taproot(_)
             | TapTree(_)
             | XPubKey(_)
             | Version(_)
+            | IncompatibleLockTimes
             | PartialDataConsumption => None,
         }
     }
@@ -250,6 +272,10 @@ impl From<encode::ParseError> for Error {
     fn from(e: encode::ParseError) -> Self { Error::ConsensusParse(e) }
 }
 
+impl From<absolute::ConversionError> for Error {
+    fn from(e: absolute::ConversionError) -> Self { Error::InvalidLockTime(e) }
+}
+
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self { Error::Io(e) }
 }
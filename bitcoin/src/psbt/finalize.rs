@@ -0,0 +1,780 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! The PSBT Input Finalizer role.
+//!
+//! Implements the Input Finalizer role from BIP-0174 for the standard script templates. The
+//! finalizer constructs `final_script_sig` and `final_script_witness` from the signatures added by
+//! the signers and clears all other data that BIP-0174 requires to be removed from the input.
+
+use core::convert::Infallible;
+use core::{fmt, mem};
+
+use internals::write_err;
+use secp256k1::{Secp256k1, Verification};
+
+use super::{IndexOutOfBoundsError, Input, Psbt};
+use crate::crypto::key::{PublicKey, XOnlyPublicKey};
+use crate::crypto::{ecdsa, taproot};
+use crate::opcodes::all::*;
+use crate::opcodes::OP_0;
+use crate::prelude::{BTreeMap, Vec};
+use crate::script::{
+    Builder, Instruction, PushBytes, RedeemScript, ScriptExt as _, ScriptPubKey,
+    ScriptPubKeyExt as _, ScriptSigBuf, TapScript, WitnessScript, WitnessScriptExt as _,
+};
+use crate::taproot::{LeafVersion, TapLeafHash};
+use crate::transaction::TxOut;
+use crate::witness::{Witness, WitnessExt as _};
+
+/// Map of input index -> the error encountered while attempting to finalize that input.
+pub type FinalizeErrors = BTreeMap<usize, FinalizeError>;
+
+impl Psbt {
+    /// Finalizes all inputs of this PSBT (the Input Finalizer role described in BIP-0174).
+    ///
+    /// Inputs that already have a `final_script_sig` or `final_script_witness` are left untouched.
+    /// See [`Input::finalize`] for the supported script templates.
+    ///
+    /// # Errors
+    ///
+    /// Returns a map of input index -> error for each input that could not be finalized. Inputs
+    /// that were finalized successfully remain finalized even when an error is returned.
+    pub fn finalize<C: Verification>(&mut self, secp: &Secp256k1<C>) -> Result<(), FinalizeErrors> {
+        let mut errors = BTreeMap::new();
+
+        for i in 0..self.inputs.len() {
+            if let Err(e) = self.finalize_input(i, secp) {
+                errors.insert(i, e);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Finalizes the input at `input_index`.
+    ///
+    /// See [`Input::finalize`] for the supported script templates.
+    pub fn finalize_input<C: Verification>(
+        &mut self,
+        input_index: usize,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), FinalizeError> {
+        self.check_index_is_within_bounds(input_index)?;
+
+        let input = &self.inputs[input_index];
+        let utxo = match (&input.witness_utxo, &input.non_witness_utxo) {
+            (Some(witness_utxo), _) => witness_utxo.clone(),
+            (None, Some(non_witness_utxo)) => {
+                let vout = self.unsigned_tx.inputs[input_index].previous_output.vout as usize;
+                non_witness_utxo.outputs.get(vout).ok_or(FinalizeError::MissingSpendUtxo)?.clone()
+            }
+            (None, None) => return Err(FinalizeError::MissingSpendUtxo),
+        };
+
+        self.inputs[input_index].finalize(&utxo, secp)
+    }
+}
+
+impl Input {
+    /// Finalizes this input given the output it spends.
+    ///
+    /// On success `final_script_sig` and/or `final_script_witness` are set and, as required by
+    /// BIP-0174, all other fields except the UTXOs, proprietary and unknown key-value pairs are
+    /// cleared. If the input is already finalized this is a no-op.
+    ///
+    /// The following output types are supported:
+    ///
+    /// - P2PK and P2PKH.
+    /// - P2WPKH, bare and nested in P2SH.
+    /// - Multisig (`m <pubkey>... n OP_CHECKMULTISIG`) in P2SH, P2WSH and P2SH-P2WSH.
+    /// - Taproot key path spends using `tap_key_sig`.
+    /// - Taproot script path spends of a single leaf from `tap_scripts` that is either
+    ///   `<xonly> OP_CHECKSIG` or `<xonly> OP_CHECKSIG (<xonly> OP_CHECKSIGADD)* <k> OP_NUMEQUAL`.
+    ///   If multiple leaves are satisfiable the one with the smallest witness is used. Leaves
+    ///   whose control block does not commit to the spent output key are ignored.
+    ///
+    /// # Errors
+    ///
+    /// If the script is not supported or if the input is missing data required to satisfy it. The
+    /// input is left unmodified in this case.
+    pub fn finalize<C: Verification>(
+        &mut self,
+        spent_utxo: &TxOut,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), FinalizeError> {
+        if self.final_script_sig.is_some() || self.final_script_witness.is_some() {
+            return Ok(());
+        }
+
+        let (script_sig, witness) = self.satisfy(spent_utxo, secp)?;
+
+        *self = Input {
+            non_witness_utxo: self.non_witness_utxo.take(),
+            witness_utxo: self.witness_utxo.take(),
+            final_script_sig: script_sig,
+            final_script_witness: witness,
+            proprietary: mem::take(&mut self.proprietary),
+            unknown: mem::take(&mut self.unknown),
+            ..Default::default()
+        };
+
+        Ok(())
+    }
+
+    /// Constructs the final scriptSig and witness required to spend `spent_utxo`.
+    fn satisfy<C: Verification>(
+        &self,
+        spent_utxo: &TxOut,
+        secp: &Secp256k1<C>,
+    ) -> Result<(Option<ScriptSigBuf>, Option<Witness>), FinalizeError> {
+        let spk = &spent_utxo.script_pubkey;
+
+        if spk.is_p2pk() {
+            let pk = spk.p2pk_public_key().ok_or(FinalizeError::UnsupportedScript)?;
+            let sig = self.partial_sigs.get(&pk).ok_or(FinalizeError::MissingEcdsaSignature)?;
+            let script_sig = Builder::new().push_slice(sig.serialize()).into_script();
+            Ok((Some(script_sig), None))
+        } else if spk.is_p2pkh() {
+            let (pk, sig) = self.signature_for_pubkey_hash(&spk.as_bytes()[3..23], false)?;
+            let script_sig = Builder::new().push_slice(sig.serialize()).push_key(pk).into_script();
+            Ok((Some(script_sig), None))
+        } else if spk.is_p2wpkh() {
+            let (pk, sig) = self.signature_for_pubkey_hash(&spk.as_bytes()[2..22], true)?;
+            Ok((None, Some(Witness::p2wpkh(sig, pk.inner))))
+        } else if spk.is_p2wsh() {
+            let witness_script = self.checked_witness_script(spk)?;
+            Ok((None, Some(self.multisig_witness(witness_script)?)))
+        } else if spk.is_p2sh() {
+            let redeem_script =
+                self.redeem_script.as_ref().ok_or(FinalizeError::MissingRedeemScript)?;
+            if redeem_script.to_p2sh().ok().as_ref() != Some(spk) {
+                return Err(FinalizeError::RedeemScriptMismatch);
+            }
+            // `to_p2sh` checked that the redeem script is at most 520 bytes long.
+            let redeem_push = <&PushBytes>::try_from(redeem_script.as_bytes())
+                .expect("redeem script size checked above");
+
+            if redeem_script.is_p2wpkh() {
+                let (pk, sig) =
+                    self.signature_for_pubkey_hash(&redeem_script.as_bytes()[2..22], true)?;
+                let script_sig = Builder::new().push_slice(redeem_push).into_script();
+                Ok((Some(script_sig), Some(Witness::p2wpkh(sig, pk.inner))))
+            } else if redeem_script.is_p2wsh() {
+                let witness_script = self
+                    .checked_witness_script(ScriptPubKey::from_bytes(redeem_script.as_bytes()))?;
+                let witness = self.multisig_witness(witness_script)?;
+                let script_sig = Builder::new().push_slice(redeem_push).into_script();
+                Ok((Some(script_sig), Some(witness)))
+            } else {
+                let script_sig = self.multisig_script_sig(redeem_script, redeem_push)?;
+                Ok((Some(script_sig), None))
+            }
+        } else if spk.is_p2tr() {
+            Ok((None, Some(self.taproot_witness(spk, secp)?)))
+        } else {
+            Err(FinalizeError::UnsupportedScript)
+        }
+    }
+
+    /// Returns the witness script after checking it commits to the witness program `spk`.
+    fn checked_witness_script(&self, spk: &ScriptPubKey) -> Result<&WitnessScript, FinalizeError> {
+        let witness_script =
+            self.witness_script.as_ref().ok_or(FinalizeError::MissingWitnessScript)?;
+        if witness_script.to_p2wsh().ok().as_deref() != Some(spk) {
+            return Err(FinalizeError::WitnessScriptMismatch);
+        }
+        Ok(witness_script)
+    }
+
+    /// Finds a partial signature made with a key that hashes to `hash`.
+    ///
+    /// For SegWit (`segwit` is true) the key must be compressed.
+    fn signature_for_pubkey_hash(
+        &self,
+        hash: &[u8],
+        segwit: bool,
+    ) -> Result<(PublicKey, ecdsa::Signature), FinalizeError> {
+        self.partial_sigs
+            .iter()
+            .find(|(pk, _)| {
+                if segwit {
+                    pk.wpubkey_hash().map(|h| h.as_byte_array()[..] == *hash).unwrap_or(false)
+                } else {
+                    pk.pubkey_hash().as_byte_array()[..] == *hash
+                }
+            })
+            .map(|(pk, sig)| (*pk, *sig))
+            .ok_or(FinalizeError::MissingEcdsaSignature)
+    }
+
+    /// Returns the signatures satisfying the multisig `script` in the order the keys appear.
+    fn multisig_signatures(&self, script: &[u8]) -> Result<Vec<ecdsa::Signature>, FinalizeError> {
+        let (required, keys) = multisig_keys(script).ok_or(FinalizeError::UnsupportedScript)?;
+        let sigs = keys.iter().filter_map(|pk| self.partial_sigs.get(pk)).collect::<Vec<_>>();
+
+        if sigs.len() < required {
+            return Err(FinalizeError::InsufficientSignatures { required, available: sigs.len() });
+        }
+        Ok(sigs.into_iter().take(required).copied().collect())
+    }
+
+    /// Constructs the scriptSig for a legacy P2SH multisig spend.
+    fn multisig_script_sig(
+        &self,
+        redeem_script: &RedeemScript,
+        redeem_push: &PushBytes,
+    ) -> Result<ScriptSigBuf, FinalizeError> {
+        let sigs = self.multisig_signatures(redeem_script.as_bytes())?;
+
+        // OP_CHECKMULTISIG bug pops one extra value off the stack.
+        let mut builder = Builder::new().push_opcode(OP_0);
+        for sig in sigs {
+            builder = builder.push_slice(sig.serialize());
+        }
+        Ok(builder.push_slice(redeem_push).into_script())
+    }
+
+    /// Constructs the witness for a P2WSH multisig spend.
+    fn multisig_witness(&self, witness_script: &WitnessScript) -> Result<Witness, FinalizeError> {
+        let sigs = self.multisig_signatures(witness_script.as_bytes())?;
+
+        let mut witness = Witness::new();
+        // OP_CHECKMULTISIG bug pops one extra value off the stack.
+        witness.push([]);
+        for sig in sigs {
+            witness.push_ecdsa_signature(sig);
+        }
+        witness.push(witness_script.as_bytes());
+        Ok(witness)
+    }
+
+    /// Constructs the witness for a Taproot key path or single leaf script path spend of `spk`.
+    fn taproot_witness<C: Verification>(
+        &self,
+        spk: &ScriptPubKey,
+        secp: &Secp256k1<C>,
+    ) -> Result<Witness, FinalizeError> {
+        if let Some(ref sig) = self.tap_key_sig {
+            return Ok(Witness::p2tr_key_spend(sig));
+        }
+
+        let program = <&[u8; 32]>::try_from(&spk.as_bytes()[2..34]).expect("checked P2TR");
+        let output_key = XOnlyPublicKey::from_byte_array(program)
+            .map_err(|_| FinalizeError::UnsupportedScript)?;
+
+        let mut best: Option<(usize, Witness)> = None;
+        let mut uncommitted = false;
+        for (control_block, (script, leaf_version)) in &self.tap_scripts {
+            if *leaf_version != LeafVersion::TapScript {
+                continue;
+            }
+            let (required, keys) = match tap_script_keys(script) {
+                Some(v) => v,
+                None => continue,
+            };
+            let leaf_hash = TapLeafHash::from_script(script, *leaf_version);
+
+            let sigs = keys
+                .iter()
+                .map(|pk| self.tap_script_sigs.get(&(*pk, leaf_hash)))
+                .collect::<Vec<Option<&taproot::Signature>>>();
+            if sigs.iter().filter(|sig| sig.is_some()).count() < required {
+                continue;
+            }
+            if !control_block.verify_taproot_commitment(secp, output_key, script) {
+                uncommitted = true;
+                continue;
+            }
+
+            // Keys are checked in script order so the signature for the first key must be on top
+            // of the stack (last in the witness). Provide exactly `required` signatures and empty
+            // vectors for the rest, as required by `OP_CHECKSIGADD`.
+            let mut remaining = required;
+            let mut items = Vec::with_capacity(keys.len());
+            for sig in sigs {
+                match sig {
+                    Some(sig) if remaining > 0 => {
+                        remaining -= 1;
+                        items.push(sig.to_vec());
+                    }
+                    _ => items.push(Vec::new()),
+                }
+            }
+
+            let mut witness = Witness::new();
+            for item in items.iter().rev() {
+                witness.push(item);
+            }
+            witness.push_p2tr_script_spend(script, control_block, None);
+
+            let size = witness.size();
+            if best.as_ref().map(|(best_size, _)| size < *best_size).unwrap_or(true) {
+                best = Some((size, witness));
+            }
+        }
+
+        match best {
+            Some((_, witness)) => Ok(witness),
+            None if uncommitted => Err(FinalizeError::ControlBlockMismatch),
+            None => Err(FinalizeError::MissingTaprootSignature),
+        }
+    }
+}
+
+/// Parses a `m <pubkey>... n OP_CHECKMULTISIG` script.
+///
+/// Returns the number of required signatures and the public keys in script order.
+fn multisig_keys(script: &[u8]) -> Option<(usize, Vec<PublicKey>)> {
+    let script = ScriptPubKey::from_bytes(script);
+    if !script.is_multisig() {
+        return None;
+    }
+
+    let mut instructions = script.instructions();
+    let required = instructions.next()?.ok()?.opcode()?.decode_pushnum()?;
+
+    let mut keys = Vec::new();
+    for instruction in instructions {
+        match instruction.ok()? {
+            Instruction::PushBytes(bytes) =>
+                keys.push(PublicKey::from_slice(bytes.as_bytes()).ok()?),
+            Instruction::Op(_) => break,
+        }
+    }
+    Some((usize::from(required), keys))
+}
+
+/// Parses a `<xonly> OP_CHECKSIG` or `<xonly> OP_CHECKSIG (<xonly> OP_CHECKSIGADD)* <k> OP_NUMEQUAL`
+/// tapscript.
+///
+/// Returns the number of required signatures and the keys in script order.
+fn tap_script_keys(script: &TapScript) -> Option<(usize, Vec<XOnlyPublicKey>)> {
+    let mut instructions = script.instructions().peekable();
+    let mut keys = Vec::new();
+
+    loop {
+        let key = match instructions.peek() {
+            Some(Ok(Instruction::PushBytes(bytes))) if bytes.len() == 32 => {
+                let bytes = <&[u8; 32]>::try_from(bytes.as_bytes()).expect("checked length");
+                XOnlyPublicKey::from_byte_array(bytes).ok()?
+            }
+            _ => break,
+        };
+        instructions.next();
+
+        let expected = if keys.is_empty() { OP_CHECKSIG } else { OP_CHECKSIGADD };
+        if instructions.next()?.ok()?.opcode()? != expected {
+            return None;
+        }
+        keys.push(key);
+    }
+
+    if keys.is_empty() {
+        return None;
+    }
+
+    match instructions.next() {
+        None if keys.len() == 1 => Some((1, keys)),
+        None => None,
+        Some(instruction) => {
+            let required = instruction.ok()?.read_int()?;
+            if instructions.next()?.ok()?.opcode()? != OP_NUMEQUAL || instructions.next().is_some()
+            {
+                return None;
+            }
+            let required = usize::try_from(required).ok()?;
+            if required == 0 || required > keys.len() {
+                return None;
+            }
+            Some((required, keys))
+        }
+    }
+}
+
+/// Error encountered while finalizing a PSBT input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FinalizeError {
+    /// Input index out of bounds.
+    IndexOutOfBounds(IndexOutOfBoundsError),
+    /// Missing spending utxo.
+    MissingSpendUtxo,
+    /// Missing redeem script.
+    MissingRedeemScript,
+    /// The redeem script does not match the spent P2SH script pubkey.
+    RedeemScriptMismatch,
+    /// Missing witness script.
+    MissingWitnessScript,
+    /// The witness script does not match the spent P2WSH witness program.
+    WitnessScriptMismatch,
+    /// No partial signature for the key required by the spent script.
+    MissingEcdsaSignature,
+    /// Not enough partial signatures to satisfy a multisig script.
+    InsufficientSignatures {
+        /// The number of signatures required by the script.
+        required: usize,
+        /// The number of usable signatures present in the input.
+        available: usize,
+    },
+    /// No key path signature and no satisfiable script path for a Taproot input.
+    MissingTaprootSignature,
+    /// The only satisfiable script path leaves have a control block that does not commit to the
+    /// spent output key.
+    ControlBlockMismatch,
+    /// The script is not one of the templates supported by the finalizer.
+    UnsupportedScript,
+}
+
+impl From<Infallible> for FinalizeError {
+    fn from(never: Infallible) -> Self { match never {} }
+}
+
+impl fmt::Display for FinalizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use FinalizeError::*;
+
+        match *self {
+            IndexOutOfBounds(ref e) => write_err!(f, "index out of bounds"; e),
+            MissingSpendUtxo => write!(f, "missing spend utxo in PSBT"),
+            MissingRedeemScript => write!(f, "missing redeem script"),
+            RedeemScriptMismatch => {
+                write!(f, "redeem script does not match the spent script pubkey")
+            }
+            MissingWitnessScript => write!(f, "missing witness script"),
+            WitnessScriptMismatch => {
+                write!(f, "witness script does not match the spent witness program")
+            }
+            MissingEcdsaSignature => write!(f, "missing partial signature for the required key"),
+            InsufficientSignatures { required, available } => write!(
+                f,
+                "multisig requires {} signatures but only {} are available",
+                required, available
+            ),
+            MissingTaprootSignature => {
+                write!(f, "no key path signature or satisfiable script path for Taproot input")
+            }
+            ControlBlockMismatch => {
+                write!(f, "control block does not commit to the spent output key")
+            }
+            UnsupportedScript => write!(f, "script is not supported by the finalizer"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FinalizeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use FinalizeError::*;
+
+        match *self {
+            IndexOutOfBounds(ref e) => Some(e),
+            MissingSpendUtxo
+            | MissingRedeemScript
+            | RedeemScriptMismatch
+            | MissingWitnessScript
+            | WitnessScriptMismatch
+            | MissingEcdsaSignature
+            | InsufficientSignatures { .. }
+            | MissingTaprootSignature
+            | ControlBlockMismatch
+            | UnsupportedScript => None,
+        }
+    }
+}
+
+impl From<IndexOutOfBoundsError> for FinalizeError {
+    fn from(e: IndexOutOfBoundsError) -> Self { FinalizeError::IndexOutOfBounds(e) }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_lit::hex;
+
+    use super::*;
+    use crate::locktime::absolute;
+    use crate::script::{
+        RedeemScriptBuf, ScriptBufExt as _, ScriptPubKeyBuf, ScriptPubKeyBufExt as _, TapScriptBuf,
+        WitnessScriptBuf,
+    };
+    use crate::sighash::{EcdsaSighashType, TapSighashType};
+    use crate::taproot::{ControlBlock, TaprootBuilder};
+    use crate::transaction::{self, Transaction, TxIn};
+    use crate::Amount;
+
+    // The very first signature in block 734,958.
+    fn der_sig() -> [u8; 70] {
+        hex!("304402207c800d698f4b0298c5aac830b822f011bb02df41eb114ade9a6702f364d5e39c0220366900d2a60cab903e77ef7dd415d46509b1f78ac78906e3296f495aa1b1b541")
+    }
+
+    // The public keys for secret keys 1, 2 and 3.
+    const PUBKEYS: [&str; 3] = [
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5",
+        "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+    ];
+
+    fn pubkey(i: usize) -> PublicKey { PUBKEYS[i].parse().unwrap() }
+
+    fn xonly(i: usize) -> XOnlyPublicKey { pubkey(i).inner.into() }
+
+    /// Returns an ECDSA signature distinguishable by its sighash type.
+    fn ecdsa_sig(sighash_type: EcdsaSighashType) -> ecdsa::Signature {
+        let signature = secp256k1::ecdsa::Signature::from_der(&der_sig()).unwrap();
+        ecdsa::Signature { signature, sighash_type }
+    }
+
+    fn schnorr_sig(byte: u8) -> taproot::Signature {
+        let signature = secp256k1::schnorr::Signature::from_byte_array([byte; 64]);
+        taproot::Signature { signature, sighash_type: TapSighashType::Default }
+    }
+
+    fn utxo(script_pubkey: ScriptPubKeyBuf) -> TxOut {
+        TxOut { value: Amount::from_sat_u32(10_000), script_pubkey }
+    }
+
+    fn p2tr_utxo() -> TxOut {
+        let mut spk = vec![0x51, 0x20];
+        spk.extend_from_slice(&xonly(0).serialize());
+        utxo(ScriptPubKeyBuf::from_bytes(spk))
+    }
+
+    fn multisig_2_of_3() -> WitnessScriptBuf {
+        Builder::new()
+            .push_opcode(OP_PUSHNUM_2)
+            .push_key(pubkey(0))
+            .push_key(pubkey(1))
+            .push_key(pubkey(2))
+            .push_opcode(OP_PUSHNUM_3)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    #[test]
+    fn finalize_p2wpkh() {
+        let secp = Secp256k1::verification_only();
+        let pk = pubkey(0);
+        let sig = ecdsa_sig(EcdsaSighashType::All);
+        let utxo = utxo(ScriptPubKeyBuf::new_p2wpkh(pk.wpubkey_hash().unwrap()));
+
+        let mut input = Input { witness_utxo: Some(utxo.clone()), ..Default::default() };
+        input.partial_sigs.insert(pk, sig);
+        input.sighash_type = Some(EcdsaSighashType::All.into());
+
+        input.finalize(&utxo, &secp).unwrap();
+
+        let want = Input {
+            witness_utxo: Some(utxo),
+            final_script_witness: Some(Witness::p2wpkh(sig, pk.inner)),
+            ..Default::default()
+        };
+        assert_eq!(input, want);
+    }
+
+    #[test]
+    fn finalize_p2sh_p2wpkh() {
+        let secp = Secp256k1::verification_only();
+        let pk = pubkey(0);
+        let sig = ecdsa_sig(EcdsaSighashType::All);
+        let redeem_script = RedeemScriptBuf::from_bytes(
+            ScriptPubKeyBuf::new_p2wpkh(pk.wpubkey_hash().unwrap()).into_bytes(),
+        );
+        let utxo = utxo(redeem_script.to_p2sh().unwrap());
+
+        let mut input = Input { redeem_script: Some(redeem_script.clone()), ..Default::default() };
+        input.partial_sigs.insert(pk, sig);
+
+        input.finalize(&utxo, &secp).unwrap();
+
+        let push = <&PushBytes>::try_from(redeem_script.as_bytes()).unwrap();
+        let script_sig = Builder::new().push_slice(push).into_script();
+        assert_eq!(input.final_script_sig, Some(script_sig));
+        assert_eq!(input.final_script_witness, Some(Witness::p2wpkh(sig, pk.inner)));
+        assert!(input.redeem_script.is_none());
+        assert!(input.partial_sigs.is_empty());
+    }
+
+    #[test]
+    fn finalize_p2wsh_multisig_orders_signatures_by_script() {
+        let secp = Secp256k1::verification_only();
+        let witness_script = multisig_2_of_3();
+        let utxo = utxo(witness_script.to_p2wsh().unwrap());
+        let sig_0 = ecdsa_sig(EcdsaSighashType::All);
+        let sig_2 = ecdsa_sig(EcdsaSighashType::Single);
+
+        let mut input =
+            Input { witness_script: Some(witness_script.clone()), ..Default::default() };
+        input.partial_sigs.insert(pubkey(2), sig_2);
+        input.partial_sigs.insert(pubkey(0), sig_0);
+
+        input.finalize(&utxo, &secp).unwrap();
+
+        let mut want = Witness::new();
+        want.push([]);
+        want.push(sig_0.serialize());
+        want.push(sig_2.serialize());
+        want.push(witness_script.as_bytes());
+        assert_eq!(input.final_script_witness, Some(want));
+        assert!(input.witness_script.is_none());
+    }
+
+    #[test]
+    fn finalize_p2wsh_insufficient_signatures() {
+        let secp = Secp256k1::verification_only();
+        let witness_script = multisig_2_of_3();
+        let utxo = utxo(witness_script.to_p2wsh().unwrap());
+
+        let mut input = Input { witness_script: Some(witness_script), ..Default::default() };
+        input.partial_sigs.insert(pubkey(1), ecdsa_sig(EcdsaSighashType::All));
+        let before = input.clone();
+
+        let err = input.finalize(&utxo, &secp).unwrap_err();
+        assert_eq!(err, FinalizeError::InsufficientSignatures { required: 2, available: 1 });
+        assert_eq!(input, before);
+    }
+
+    #[test]
+    fn finalize_p2wsh_witness_script_mismatch() {
+        let secp = Secp256k1::verification_only();
+        let utxo = utxo(multisig_2_of_3().to_p2wsh().unwrap());
+        let other = WitnessScriptBuf::from_bytes(vec![OP_PUSHNUM_1.to_u8()]);
+
+        let mut input = Input { witness_script: Some(other), ..Default::default() };
+        assert_eq!(input.finalize(&utxo, &secp), Err(FinalizeError::WitnessScriptMismatch));
+    }
+
+    #[test]
+    fn finalize_p2tr_key_path() {
+        let secp = Secp256k1::verification_only();
+        let sig = schnorr_sig(1);
+        let mut input = Input { tap_key_sig: Some(sig), ..Default::default() };
+        input.tap_internal_key = Some(xonly(0));
+
+        input.finalize(&p2tr_utxo(), &secp).unwrap();
+
+        assert_eq!(input.final_script_witness, Some(Witness::p2tr_key_spend(&sig)));
+        assert!(input.tap_key_sig.is_none());
+        assert!(input.tap_internal_key.is_none());
+    }
+
+    fn multi_a_2_of_3() -> TapScriptBuf {
+        Builder::new()
+            .push_x_only_key(xonly(0))
+            .push_opcode(OP_CHECKSIG)
+            .push_x_only_key(xonly(1))
+            .push_opcode(OP_CHECKSIGADD)
+            .push_x_only_key(xonly(2))
+            .push_opcode(OP_CHECKSIGADD)
+            .push_int(2)
+            .unwrap()
+            .push_opcode(OP_NUMEQUAL)
+            .into_script()
+    }
+
+    #[test]
+    fn finalize_p2tr_multi_a_script_path() {
+        let secp = Secp256k1::verification_only();
+        let script = multi_a_2_of_3();
+        let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(0, script.clone())
+            .unwrap()
+            .finalize(&secp, xonly(0))
+            .unwrap();
+        let control_block =
+            spend_info.control_block(&(script.clone(), LeafVersion::TapScript)).unwrap();
+        let utxo = utxo(ScriptPubKeyBuf::new_p2tr_tweaked(spend_info.output_key()));
+
+        let mut input = Input::default();
+        input.tap_scripts.insert(control_block.clone(), (script.clone(), LeafVersion::TapScript));
+        // Three signatures available, only two are required.
+        for i in 0..3 {
+            input.tap_script_sigs.insert((xonly(i), leaf_hash), schnorr_sig(i as u8 + 1));
+        }
+
+        input.finalize(&utxo, &secp).unwrap();
+
+        let mut want = Witness::new();
+        want.push([]);
+        want.push(schnorr_sig(2).to_vec());
+        want.push(schnorr_sig(1).to_vec());
+        want.push_p2tr_script_spend(&script, &control_block, None);
+        assert_eq!(input.final_script_witness, Some(want));
+        assert!(input.tap_scripts.is_empty());
+        assert!(input.tap_script_sigs.is_empty());
+    }
+
+    #[test]
+    fn finalize_p2tr_control_block_mismatch() {
+        let secp = Secp256k1::verification_only();
+        let script = multi_a_2_of_3();
+        let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+
+        // Commits to the leaf but not to the output key of `p2tr_utxo`.
+        let mut control_block_bytes = vec![0xc0];
+        control_block_bytes.extend_from_slice(&xonly(0).serialize());
+        let control_block = ControlBlock::decode(&control_block_bytes).unwrap();
+
+        let mut input = Input::default();
+        input.tap_scripts.insert(control_block, (script, LeafVersion::TapScript));
+        for i in 0..2 {
+            input.tap_script_sigs.insert((xonly(i), leaf_hash), schnorr_sig(i as u8 + 1));
+        }
+        let before = input.clone();
+
+        assert_eq!(input.finalize(&p2tr_utxo(), &secp), Err(FinalizeError::ControlBlockMismatch));
+        assert_eq!(input, before);
+    }
+
+    #[test]
+    fn finalize_p2tr_missing_signature() {
+        let secp = Secp256k1::verification_only();
+        let mut input = Input::default();
+        assert_eq!(
+            input.finalize(&p2tr_utxo(), &secp),
+            Err(FinalizeError::MissingTaprootSignature)
+        );
+    }
+
+    #[test]
+    fn finalize_already_final_input_is_noop() {
+        let secp = Secp256k1::verification_only();
+        let mut input = Input {
+            final_script_witness: Some(Witness::from_slice(&[[1u8]])),
+            tap_key_sig: Some(schnorr_sig(1)),
+            ..Default::default()
+        };
+        let before = input.clone();
+
+        input.finalize(&p2tr_utxo(), &secp).unwrap();
+        assert_eq!(input, before);
+    }
+
+    #[test]
+    fn psbt_finalize_collects_errors() {
+        let secp = Secp256k1::verification_only();
+        let pk = pubkey(0);
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            inputs: vec![TxIn::EMPTY_COINBASE, TxIn::EMPTY_COINBASE],
+            outputs: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+
+        let utxo = utxo(ScriptPubKeyBuf::new_p2wpkh(pk.wpubkey_hash().unwrap()));
+        psbt.inputs[0].witness_utxo = Some(utxo);
+        psbt.inputs[0].partial_sigs.insert(pk, ecdsa_sig(EcdsaSighashType::All));
+
+        let errors = psbt.finalize(&secp).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[&1], FinalizeError::MissingSpendUtxo);
+        assert!(psbt.inputs[0].final_script_witness.is_some());
+
+        assert!(matches!(psbt.finalize_input(2, &secp), Err(FinalizeError::IndexOutOfBounds(_))));
+    }
+}
@@ -4,17 +4,29 @@
 use io::{BufRead, Cursor, Read};
 
 use crate::bip32::{ChildNumber, DerivationPath, Fingerprint, Xpub};
-use crate::consensus::encode::MAX_VEC_SIZE;
+use crate::consensus::encode::{ReadExt as _, WriteExt as _, MAX_VEC_SIZE};
 use crate::consensus::{encode, Decodable};
+use crate::locktime::absolute;
 use crate::prelude::{btree_map, BTreeMap, Vec};
 use crate::psbt::map::Map;
-use crate::psbt::{raw, Error, Psbt};
-use crate::transaction::Transaction;
+use crate::psbt::serialize::{Deserialize, Serialize};
+use crate::psbt::{raw, Error, Psbt, TxModifiable};
+use crate::transaction::{self, Transaction};
 
 /// Type: Unsigned Transaction PSBT_GLOBAL_UNSIGNED_TX = 0x00
 const PSBT_GLOBAL_UNSIGNED_TX: u64 = 0x00;
 /// Type: Extended Public Key PSBT_GLOBAL_XPUB = 0x01
 const PSBT_GLOBAL_XPUB: u64 = 0x01;
+/// Type: Transaction Version PSBT_GLOBAL_TX_VERSION = 0x02
+const PSBT_GLOBAL_TX_VERSION: u64 = 0x02;
+/// Type: Fallback Locktime PSBT_GLOBAL_FALLBACK_LOCKTIME = 0x03
+const PSBT_GLOBAL_FALLBACK_LOCKTIME: u64 = 0x03;
+/// Type: Input Count PSBT_GLOBAL_INPUT_COUNT = 0x04
+const PSBT_GLOBAL_INPUT_COUNT: u64 = 0x04;
+/// Type: Output Count PSBT_GLOBAL_OUTPUT_COUNT = 0x05
+const PSBT_GLOBAL_OUTPUT_COUNT: u64 = 0x05;
+/// Type: Transaction Modifiable Flags PSBT_GLOBAL_TX_MODIFIABLE = 0x06
+const PSBT_GLOBAL_TX_MODIFIABLE: u64 = 0x06;
 /// Type: Version Number PSBT_GLOBAL_VERSION = 0xFB
 const PSBT_GLOBAL_VERSION: u64 = 0xFB;
 /// Type: Proprietary Use Type PSBT_GLOBAL_PROPRIETARY = 0xFC
@@ -24,19 +36,22 @@ impl Map for Psbt {
     fn get_pairs(&self) -> Vec<raw::Pair> {
         let mut rv: Vec<raw::Pair> = Default::default();
 
-        rv.push(raw::Pair {
-            key: raw::Key { type_value: PSBT_GLOBAL_UNSIGNED_TX, key_data: vec![] },
-            value: {
-                // Manually serialized to ensure 0-input txs are serialized
-                // without witnesses.
-                let mut ret = Vec::new();
-                ret.extend(encode::serialize(&self.unsigned_tx.version));
-                ret.extend(encode::serialize(&self.unsigned_tx.inputs));
-                ret.extend(encode::serialize(&self.unsigned_tx.outputs));
-                ret.extend(encode::serialize(&self.unsigned_tx.lock_time));
-                ret
-            },
-        });
+        // PSBTv2 describes the unsigned transaction using separate global, input and output fields.
+        if self.version < 2 {
+            rv.push(raw::Pair {
+                key: raw::Key { type_value: PSBT_GLOBAL_UNSIGNED_TX, key_data: vec![] },
+                value: {
+                    // Manually serialized to ensure 0-input txs are serialized
+                    // without witnesses.
+                    let mut ret = Vec::new();
+                    ret.extend(encode::serialize(&self.unsigned_tx.version));
+                    ret.extend(encode::serialize(&self.unsigned_tx.inputs));
+                    ret.extend(encode::serialize(&self.unsigned_tx.outputs));
+                    ret.extend(encode::serialize(&self.unsigned_tx.lock_time));
+                    ret
+                },
+            });
+        }
 
         for (xpub, (fingerprint, derivation)) in &self.xpub {
             rv.push(raw::Pair {
@@ -50,6 +65,31 @@ fn get_pairs(&self) -> Vec<raw::Pair> {
             });
         }
 
+        if self.version >= 2 {
+            let tx = &self.unsigned_tx;
+            rv.push(raw::Pair {
+                key: raw::Key { type_value: PSBT_GLOBAL_TX_VERSION, key_data: vec![] },
+                value: Serialize::serialize(&tx.version),
+            });
+
+            impl_psbt_get_pair! {
+                rv.push(self.fallback_lock_time, PSBT_GLOBAL_FALLBACK_LOCKTIME)
+            }
+
+            for (type_value, count) in [
+                (PSBT_GLOBAL_INPUT_COUNT, tx.inputs.len()),
+                (PSBT_GLOBAL_OUTPUT_COUNT, tx.outputs.len()),
+            ] {
+                let mut value = Vec::new();
+                value.emit_compact_size(count).expect("in-memory writers don't error");
+                rv.push(raw::Pair { key: raw::Key { type_value, key_data: vec![] }, value });
+            }
+
+            impl_psbt_get_pair! {
+                rv.push(self.tx_modifiable, PSBT_GLOBAL_TX_MODIFIABLE)
+            }
+        }
+
         // Serializing version only for non-default value; otherwise test vectors fail
         if self.version > 0 {
             rv.push(raw::Pair {
@@ -71,10 +111,21 @@ fn get_pairs(&self) -> Vec<raw::Pair> {
 }
 
 impl Psbt {
-    pub(crate) fn decode_global<R: BufRead + ?Sized>(r: &mut R) -> Result<Self, Error> {
+    /// Decodes the global map, returning the PSBT along with the number of inputs and outputs.
+    ///
+    /// For version 2 PSBTs the inputs and outputs of the unsigned transaction are empty, they are
+    /// filled in as the input and output maps are decoded.
+    pub(crate) fn decode_global<R: BufRead + ?Sized>(
+        r: &mut R,
+    ) -> Result<(Self, usize, usize), Error> {
         let mut r = r.take(MAX_VEC_SIZE.to_u64());
         let mut tx: Option<Transaction> = None;
         let mut version: Option<u32> = None;
+        let mut tx_version: Option<transaction::Version> = None;
+        let mut fallback_lock_time: Option<absolute::LockTime> = None;
+        let mut input_count: Option<usize> = None;
+        let mut output_count: Option<usize> = None;
+        let mut tx_modifiable: Option<TxModifiable> = None;
         let mut unknowns: BTreeMap<raw::Key, Vec<u8>> = Default::default();
         let mut xpub_map: BTreeMap<Xpub, (Fingerprint, DerivationPath)> = Default::default();
         let mut proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>> = Default::default();
@@ -161,11 +212,11 @@ pub(crate) fn decode_global<R: BufRead + ?Sized>(r: &mut R) -> Result<Self, Erro
                                         ));
                                     }
                                     version = Some(Decodable::consensus_decode(&mut decoder)?);
-                                    // We only understand version 0 PSBTs. According to BIP-0174 we
-                                    // should throw an error if we see anything other than version 0.
-                                    if version != Some(0) {
+                                    // We understand version 0 (BIP-0174) and version 2 (BIP-0370)
+                                    // PSBTs, version 1 was never defined.
+                                    if version != Some(0) && version != Some(2) {
                                         return Err(Error::Version(
-                                            "PSBT versions greater than 0 are not supported",
+                                            "only PSBT versions 0 and 2 are supported",
                                         ));
                                     }
                                 } else {
@@ -175,6 +226,22 @@ pub(crate) fn decode_global<R: BufRead + ?Sized>(r: &mut R) -> Result<Self, Erro
                                 return Err(Error::InvalidKey(pair.key));
                             }
                         }
+                        // Keys of the version 2 types with key data are unknown keys.
+                        PSBT_GLOBAL_TX_VERSION if pair.key.key_data.is_empty() => {
+                            decode_unkeyed(&mut tx_version, pair)?;
+                        }
+                        PSBT_GLOBAL_FALLBACK_LOCKTIME if pair.key.key_data.is_empty() => {
+                            decode_unkeyed(&mut fallback_lock_time, pair)?;
+                        }
+                        PSBT_GLOBAL_INPUT_COUNT if pair.key.key_data.is_empty() => {
+                            decode_count(&mut input_count, pair)?;
+                        }
+                        PSBT_GLOBAL_OUTPUT_COUNT if pair.key.key_data.is_empty() => {
+                            decode_count(&mut output_count, pair)?;
+                        }
+                        PSBT_GLOBAL_TX_MODIFIABLE if pair.key.key_data.is_empty() => {
+                            decode_unkeyed(&mut tx_modifiable, pair)?;
+                        }
                         PSBT_GLOBAL_PROPRIETARY => match proprietary
                             .entry(raw::ProprietaryKey::try_from(pair.key.clone())?)
                         {
@@ -198,18 +265,86 @@ pub(crate) fn decode_global<R: BufRead + ?Sized>(r: &mut R) -> Result<Self, Erro
             }
         }
 
-        if let Some(tx) = tx {
-            Ok(Psbt {
-                unsigned_tx: tx,
-                version: version.unwrap_or(0),
-                xpub: xpub_map,
-                proprietary,
-                unknown: unknowns,
+        let version = version.unwrap_or(0);
+        let unsigned_tx = if version >= 2 {
+            if tx.is_some() {
+                return Err(Error::Version("PSBT version 2 must not have an unsigned transaction"));
+            }
+            let version = tx_version
+                .ok_or(Error::Version("PSBT version 2 must have a transaction version"))?;
+            if version < transaction::Version::TWO {
+                return Err(Error::Version(
+                    "PSBT version 2 transaction version must be at least 2",
+                ));
+            }
+            // The lock time is computed once the input maps have been decoded.
+            Transaction {
+                version,
+                lock_time: absolute::LockTime::ZERO,
                 inputs: vec![],
                 outputs: vec![],
-            })
+            }
         } else {
-            Err(Error::MustHaveUnsignedTx)
-        }
+            if tx_version.is_some()
+                || fallback_lock_time.is_some()
+                || input_count.is_some()
+                || output_count.is_some()
+                || tx_modifiable.is_some()
+            {
+                return Err(Error::Version("PSBT version 0 must not have PSBT version 2 fields"));
+            }
+            tx.ok_or(Error::MustHaveUnsignedTx)?
+        };
+
+        let (inputs_len, outputs_len) = if version >= 2 {
+            (
+                input_count.ok_or(Error::Version("PSBT version 2 must have an input count"))?,
+                output_count.ok_or(Error::Version("PSBT version 2 must have an output count"))?,
+            )
+        } else {
+            (unsigned_tx.inputs.len(), unsigned_tx.outputs.len())
+        };
+
+        let psbt = Psbt {
+            unsigned_tx,
+            version,
+            fallback_lock_time,
+            tx_modifiable,
+            xpub: xpub_map,
+            proprietary,
+            unknown: unknowns,
+            inputs: vec![],
+            outputs: vec![],
+        };
+        Ok((psbt, inputs_len, outputs_len))
+    }
+}
+
+/// Decodes the value of a global field that has an empty key into `field`.
+fn decode_unkeyed<T: Deserialize>(field: &mut Option<T>, pair: raw::Pair) -> Result<(), Error> {
+    if field.is_some() {
+        return Err(Error::DuplicateKey(pair.key));
+    }
+    *field = Some(T::deserialize(&pair.value)?);
+    Ok(())
+}
+
+/// Decodes a compact size encoded input or output count into `count`.
+fn decode_count(count: &mut Option<usize>, pair: raw::Pair) -> Result<(), Error> {
+    if count.is_some() {
+        return Err(Error::DuplicateKey(pair.key));
+    }
+
+    let mut decoder = Cursor::new(pair.value.as_slice());
+    let n = decoder.read_compact_size()?;
+    if decoder.position() != pair.value.len().to_u64() {
+        return Err(Error::PartialDataConsumption);
     }
+    // Each map takes at least one byte so this bounds the count by the size of the PSBT.
+    let n = usize::try_from(n)
+        .ok()
+        .filter(|n| *n <= MAX_VEC_SIZE)
+        .ok_or(Error::Version("PSBT version 2 input or output count is too large"))?;
+    *count = Some(n);
+    Ok(())
 }
@@ -8,18 +8,20 @@
 use crate::bip32::KeySource;
 use crate::crypto::key::{PublicKey, XOnlyPublicKey};
 use crate::crypto::{ecdsa, taproot};
+use crate::locktime::absolute;
 use crate::prelude::{btree_map, BTreeMap, Borrow, Box, ToOwned, Vec};
 use crate::psbt::map::Map;
-use crate::psbt::serialize::Deserialize;
-use crate::psbt::{error, raw, Error};
+use crate::psbt::serialize::{Deserialize, Serialize};
+use crate::psbt::{error, raw, Error, Musig2Key, Musig2PartialSig, Musig2PubNonce};
 use crate::script::{RedeemScriptBuf, ScriptSigBuf, TapScriptBuf, WitnessScriptBuf};
 use crate::sighash::{
     EcdsaSighashType, InvalidSighashTypeError, NonStandardSighashTypeError, SighashTypeParseError,
     TapSighashType,
 };
 use crate::taproot::{ControlBlock, LeafVersion, TapLeafHash, TapNodeHash};
-use crate::transaction::{Transaction, TxOut};
+use crate::transaction::{OutPoint, Transaction, TxIn, TxOut};
 use crate::witness::Witness;
+use crate::{Sequence, Txid};
 
 /// Type: Non-Witness UTXO PSBT_IN_NON_WITNESS_UTXO = 0x00
 const PSBT_IN_NON_WITNESS_UTXO: u64 = 0x00;
@@ -47,6 +49,16 @@
 const PSBT_IN_HASH160: u64 = 0x0c;
 /// Type: HASH256 preimage PSBT_IN_HASH256 = 0x0d
 const PSBT_IN_HASH256: u64 = 0x0d;
+/// Type: Previous TXID PSBT_IN_PREVIOUS_TXID = 0x0e
+const PSBT_IN_PREVIOUS_TXID: u64 = 0x0e;
+/// Type: Spent Output Index PSBT_IN_OUTPUT_INDEX = 0x0f
+const PSBT_IN_OUTPUT_INDEX: u64 = 0x0f;
+/// Type: Sequence Number PSBT_IN_SEQUENCE = 0x10
+const PSBT_IN_SEQUENCE: u64 = 0x10;
+/// Type: Required Time-based Locktime PSBT_IN_REQUIRED_TIME_LOCKTIME = 0x11
+const PSBT_IN_REQUIRED_TIME_LOCKTIME: u64 = 0x11;
+/// Type: Required Height-based Locktime PSBT_IN_REQUIRED_HEIGHT_LOCKTIME = 0x12
+const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u64 = 0x12;
 /// Type: Taproot Signature in Key Spend PSBT_IN_TAP_KEY_SIG = 0x13
 const PSBT_IN_TAP_KEY_SIG: u64 = 0x13;
 /// Type: Taproot Signature in Script Spend PSBT_IN_TAP_SCRIPT_SIG = 0x14
@@ -61,6 +73,10 @@
 const PSBT_IN_TAP_MERKLE_ROOT: u64 = 0x18;
 /// Type: MuSig2 Public Keys Participating in Aggregate Input PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS = 0x1a
 const PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS: u64 = 0x1a;
+/// Type: MuSig2 Public Nonce PSBT_IN_MUSIG2_PUB_NONCE = 0x1b
+const PSBT_IN_MUSIG2_PUB_NONCE: u64 = 0x1b;
+/// Type: MuSig2 Participant Partial Signature PSBT_IN_MUSIG2_PARTIAL_SIG = 0x1c
+const PSBT_IN_MUSIG2_PARTIAL_SIG: u64 = 0x1c;
 /// Type: Proprietary Use Type PSBT_IN_PROPRIETARY = 0xFC
 const PSBT_IN_PROPRIETARY: u64 = 0xFC;
 
@@ -103,6 +119,10 @@ pub struct Input {
     pub hash160_preimages: BTreeMap<hash160::Hash, Vec<u8>>,
     /// HASH256 hash to preimage map.
     pub hash256_preimages: BTreeMap<sha256d::Hash, Vec<u8>>,
+    /// The minimum time based lock time this input requires (PSBT version 2 only).
+    pub required_time_lock_time: Option<absolute::MedianTimePast>,
+    /// The minimum height based lock time this input requires (PSBT version 2 only).
+    pub required_height_lock_time: Option<absolute::Height>,
     /// Serialized Taproot signature with sighash type for key spend.
     pub tap_key_sig: Option<taproot::Signature>,
     /// Map of `<xonlypubkey>|<leafhash>` with signature.
@@ -117,6 +137,10 @@ pub struct Input {
     pub tap_merkle_root: Option<TapNodeHash>,
     /// Mapping from MuSig2 aggregate keys to the participant keys from which they were aggregated.
     pub musig2_participant_pubkeys: BTreeMap<secp256k1::PublicKey, Vec<secp256k1::PublicKey>>,
+    /// Map of `<participant pubkey>|<aggregate pubkey>|<leafhash>` with MuSig2 public nonce.
+    pub musig2_pub_nonces: BTreeMap<Musig2Key, Musig2PubNonce>,
+    /// Map of `<participant pubkey>|<aggregate pubkey>|<leafhash>` with MuSig2 partial signature.
+    pub musig2_partial_sigs: BTreeMap<Musig2Key, Musig2PartialSig>,
     /// Proprietary key-value pairs for this input.
     pub proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>>,
     /// Unknown key-value pairs for this input.
@@ -326,6 +350,15 @@ pub(super) fn insert_pair(&mut self, pair: raw::Pair) -> Result<(), Error> {
                     &mut self.hash256_preimages <= raw_key|raw_value|sha256d|error::PsbtHash::Hash256
                 }
             }
+            PSBT_IN_PREVIOUS_TXID
+            | PSBT_IN_OUTPUT_INDEX
+            | PSBT_IN_SEQUENCE
+            | PSBT_IN_REQUIRED_TIME_LOCKTIME
+            | PSBT_IN_REQUIRED_HEIGHT_LOCKTIME
+                if raw_key.key_data.is_empty() =>
+                return Err(Error::Version(
+                    "previous txid, output index, sequence and required lock times are only allowed in PSBT version 2",
+                )),
             PSBT_IN_TAP_KEY_SIG => {
                 impl_psbt_insert_pair! {
                     self.tap_key_sig <= <raw_key: _>|<raw_value: taproot::Signature>
@@ -361,6 +394,16 @@ pub(super) fn insert_pair(&mut self, pair: raw::Pair) -> Result<(), Error> {
                     self.musig2_participant_pubkeys <= <raw_key: secp256k1::PublicKey>|< raw_value: Vec<secp256k1::PublicKey> >
                 }
             }
+            PSBT_IN_MUSIG2_PUB_NONCE => {
+                impl_psbt_insert_pair! {
+                    self.musig2_pub_nonces <= <raw_key: Musig2Key>|<raw_value: Musig2PubNonce>
+                }
+            }
+            PSBT_IN_MUSIG2_PARTIAL_SIG => {
+                impl_psbt_insert_pair! {
+                    self.musig2_partial_sigs <= <raw_key: Musig2Key>|<raw_value: Musig2PartialSig>
+                }
+            }
             PSBT_IN_PROPRIETARY => {
                 let key = raw::ProprietaryKey::try_from(raw_key.clone())?;
                 match self.proprietary.entry(key) {
@@ -400,6 +443,8 @@ pub fn combine(&mut self, other: Self) {
         self.tap_scripts.extend(other.tap_scripts);
         self.tap_key_origins.extend(other.tap_key_origins);
         self.musig2_participant_pubkeys.extend(other.musig2_participant_pubkeys);
+        self.musig2_pub_nonces.extend(other.musig2_pub_nonces);
+        self.musig2_partial_sigs.extend(other.musig2_partial_sigs);
         self.proprietary.extend(other.proprietary);
         self.unknown.extend(other.unknown);
 
@@ -410,7 +455,101 @@ pub fn combine(&mut self, other: Self) {
         combine!(tap_key_sig, self, other);
         combine!(tap_internal_key, self, other);
         combine!(tap_merkle_root, self, other);
+        combine!(required_time_lock_time, self, other);
+        combine!(required_height_lock_time, self, other);
     }
+
+    /// Decodes a PSBT version 2 input map.
+    ///
+    /// Returns the input along with the unsigned transaction input it describes.
+    pub(crate) fn decode_v2<R: io::BufRead + ?Sized>(r: &mut R) -> Result<(Self, TxIn), Error> {
+        let mut rv = Input::default();
+        let mut txin = TxInFields::default();
+
+        loop {
+            match raw::Pair::decode(r) {
+                Ok(pair) => {
+                    let raw::Pair { key: raw_key, value: raw_value } = pair;
+                    match raw_key.type_value {
+                        // Keys of these types with key data are unknown keys.
+                        PSBT_IN_PREVIOUS_TXID if raw_key.key_data.is_empty() => {
+                            impl_psbt_insert_pair! {
+                                txin.previous_txid <= <raw_key: _>|<raw_value: Txid>
+                            }
+                        }
+                        PSBT_IN_OUTPUT_INDEX if raw_key.key_data.is_empty() => {
+                            impl_psbt_insert_pair! {
+                                txin.output_index <= <raw_key: _>|<raw_value: u32>
+                            }
+                        }
+                        PSBT_IN_SEQUENCE if raw_key.key_data.is_empty() => {
+                            impl_psbt_insert_pair! {
+                                txin.sequence <= <raw_key: _>|<raw_value: Sequence>
+                            }
+                        }
+                        PSBT_IN_REQUIRED_TIME_LOCKTIME if raw_key.key_data.is_empty() => {
+                            impl_psbt_insert_pair! {
+                                rv.required_time_lock_time <= <raw_key: _>|<raw_value: absolute::MedianTimePast>
+                            }
+                        }
+                        PSBT_IN_REQUIRED_HEIGHT_LOCKTIME if raw_key.key_data.is_empty() => {
+                            impl_psbt_insert_pair! {
+                                rv.required_height_lock_time <= <raw_key: _>|<raw_value: absolute::Height>
+                            }
+                        }
+                        _ => rv.insert_pair(raw::Pair { key: raw_key, value: raw_value })?,
+                    }
+                }
+                Err(Error::NoMorePairs) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let txid = txin
+            .previous_txid
+            .ok_or(Error::Version("PSBT version 2 input must have a previous txid"))?;
+        let vout = txin
+            .output_index
+            .ok_or(Error::Version("PSBT version 2 input must have an output index"))?;
+        let txin = TxIn {
+            previous_output: OutPoint { txid, vout },
+            sequence: txin.sequence.unwrap_or(Sequence::MAX),
+            ..TxIn::EMPTY_COINBASE
+        };
+        Ok((rv, txin))
+    }
+
+    /// Serializes this input as a PSBT version 2 input map for the unsigned transaction input `txin`.
+    pub(crate) fn serialize_map_v2(&self, txin: &TxIn) -> Vec<u8> {
+        let mut pairs = self.get_pairs();
+
+        let mut push = |type_value, value| {
+            pairs.push(raw::Pair { key: raw::Key { type_value, key_data: vec![] }, value })
+        };
+        push(PSBT_IN_PREVIOUS_TXID, txin.previous_output.txid.serialize());
+        push(PSBT_IN_OUTPUT_INDEX, txin.previous_output.vout.serialize());
+        // The sequence number defaults to 0xffffffff when omitted.
+        if txin.sequence != Sequence::MAX {
+            push(PSBT_IN_SEQUENCE, txin.sequence.serialize());
+        }
+        // Stable sort keeps keys of the same type in their original order.
+        pairs.sort_by_key(|pair| pair.key.type_value);
+
+        let mut buf = Vec::new();
+        for pair in pairs {
+            buf.extend(&pair.serialize());
+        }
+        buf.push(0x00_u8);
+        buf
+    }
+}
+
+/// The fields of a PSBT version 2 input map that describe the unsigned transaction input.
+#[derive(Default)]
+struct TxInFields {
+    previous_txid: Option<Txid>,
+    output_index: Option<u32>,
+    sequence: Option<Sequence>,
 }
 
 impl Map for Input {
@@ -469,6 +608,14 @@ fn get_pairs(&self) -> Vec<raw::Pair> {
             rv.push_map(self.hash256_preimages, PSBT_IN_HASH256)
         }
 
+        impl_psbt_get_pair! {
+            rv.push(self.required_time_lock_time, PSBT_IN_REQUIRED_TIME_LOCKTIME)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.required_height_lock_time, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME)
+        }
+
         impl_psbt_get_pair! {
             rv.push(self.tap_key_sig, PSBT_IN_TAP_KEY_SIG)
         }
@@ -497,6 +644,14 @@ fn get_pairs(&self) -> Vec<raw::Pair> {
             rv.push_map(self.musig2_participant_pubkeys, PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS)
         }
 
+        impl_psbt_get_pair! {
+            rv.push_map(self.musig2_pub_nonces, PSBT_IN_MUSIG2_PUB_NONCE)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push_map(self.musig2_partial_sigs, PSBT_IN_MUSIG2_PARTIAL_SIG)
+        }
+
         for (key, value) in self.proprietary.iter() {
             rv.push(raw::Pair { key: key.to_key(), value: value.clone() });
         }
@@ -4,9 +4,12 @@
 use crate::crypto::key::XOnlyPublicKey;
 use crate::prelude::{btree_map, BTreeMap, Vec};
 use crate::psbt::map::Map;
+use crate::psbt::serialize::Serialize;
 use crate::psbt::{raw, Error};
-use crate::script::{RedeemScriptBuf, WitnessScriptBuf};
+use crate::script::{RedeemScriptBuf, ScriptPubKeyBuf, WitnessScriptBuf};
 use crate::taproot::{TapLeafHash, TapTree};
+use crate::transaction::TxOut;
+use crate::Amount;
 
 /// Type: Redeem ScriptBuf PSBT_OUT_REDEEM_SCRIPT = 0x00
 const PSBT_OUT_REDEEM_SCRIPT: u64 = 0x00;
@@ -14,6 +17,10 @@
 const PSBT_OUT_WITNESS_SCRIPT: u64 = 0x01;
 /// Type: BIP-0032 Derivation Path PSBT_OUT_BIP32_DERIVATION = 0x02
 const PSBT_OUT_BIP32_DERIVATION: u64 = 0x02;
+/// Type: Output Amount PSBT_OUT_AMOUNT = 0x03
+const PSBT_OUT_AMOUNT: u64 = 0x03;
+/// Type: Output Script PSBT_OUT_SCRIPT = 0x04
+const PSBT_OUT_SCRIPT: u64 = 0x04;
 /// Type: Taproot Internal Key PSBT_OUT_TAP_INTERNAL_KEY = 0x05
 const PSBT_OUT_TAP_INTERNAL_KEY: u64 = 0x05;
 /// Type: Taproot Tree PSBT_OUT_TAP_TREE = 0x06
@@ -70,6 +77,10 @@ pub(super) fn insert_pair(&mut self, pair: raw::Pair) -> Result<(), Error> {
                     self.bip32_derivation <= <raw_key: secp256k1::PublicKey>|<raw_value: KeySource>
                 }
             }
+            PSBT_OUT_AMOUNT | PSBT_OUT_SCRIPT if raw_key.key_data.is_empty() =>
+                return Err(Error::Version(
+                    "output amount and script are only allowed in PSBT version 2",
+                )),
             PSBT_OUT_PROPRIETARY => {
                 let key = raw::ProprietaryKey::try_from(raw_key.clone())?;
                 match self.proprietary.entry(key) {
@@ -125,6 +136,77 @@ pub fn combine(&mut self, other: Self) {
     }
 }
 
+impl Output {
+    /// Decodes a PSBT version 2 output map.
+    ///
+    /// Returns the output along with the unsigned transaction output it describes.
+    pub(crate) fn decode_v2<R: io::BufRead + ?Sized>(r: &mut R) -> Result<(Self, TxOut), Error> {
+        let mut rv = Output::default();
+        let mut txout = TxOutFields::default();
+
+        loop {
+            match raw::Pair::decode(r) {
+                Ok(pair) => {
+                    let raw::Pair { key: raw_key, value: raw_value } = pair;
+                    match raw_key.type_value {
+                        // Keys of these types with key data are unknown keys.
+                        PSBT_OUT_AMOUNT if raw_key.key_data.is_empty() => {
+                            impl_psbt_insert_pair! {
+                                txout.amount <= <raw_key: _>|<raw_value: Amount>
+                            }
+                        }
+                        PSBT_OUT_SCRIPT if raw_key.key_data.is_empty() => {
+                            impl_psbt_insert_pair! {
+                                txout.script <= <raw_key: _>|<raw_value: ScriptPubKeyBuf>
+                            }
+                        }
+                        _ => rv.insert_pair(raw::Pair { key: raw_key, value: raw_value })?,
+                    }
+                }
+                Err(Error::NoMorePairs) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let value =
+            txout.amount.ok_or(Error::Version("PSBT version 2 output must have an amount"))?;
+        let script_pubkey =
+            txout.script.ok_or(Error::Version("PSBT version 2 output must have a script"))?;
+        Ok((rv, TxOut { value, script_pubkey }))
+    }
+
+    /// Serializes this output as a PSBT version 2 output map for the unsigned transaction output
+    /// `txout`.
+    pub(crate) fn serialize_map_v2(&self, txout: &TxOut) -> Vec<u8> {
+        let mut pairs = self.get_pairs();
+
+        pairs.push(raw::Pair {
+            key: raw::Key { type_value: PSBT_OUT_AMOUNT, key_data: vec![] },
+            value: txout.value.serialize(),
+        });
+        pairs.push(raw::Pair {
+            key: raw::Key { type_value: PSBT_OUT_SCRIPT, key_data: vec![] },
+            value: txout.script_pubkey.serialize(),
+        });
+        // Stable sort keeps keys of the same type in their original order.
+        pairs.sort_by_key(|pair| pair.key.type_value);
+
+        let mut buf = Vec::new();
+        for pair in pairs {
+            buf.extend(&pair.serialize());
+        }
+        buf.push(0x00_u8);
+        buf
+    }
+}
+
+/// The fields of a PSBT version 2 output map that describe the unsigned transaction output.
+#[derive(Default)]
+struct TxOutFields {
+    amount: Option<Amount>,
+    script: Option<ScriptPubKeyBuf>,
+}
+
 impl Map for Output {
     fn get_pairs(&self) -> Vec<raw::Pair> {
         let mut rv: Vec<raw::Pair> = Default::default();
@@ -8,10 +8,14 @@
 
 #[macro_use]
 mod macros;
+mod builder;
 mod error;
+mod finalize;
 mod map;
+mod musig2;
 pub mod raw;
 pub mod serialize;
+mod v2;
 
 use core::convert::Infallible;
 use core::{cmp, fmt};
@@ -25,6 +29,7 @@
 use crate::crypto::key::{PrivateKey, PublicKey};
 use crate::crypto::{ecdsa, taproot};
 use crate::key::{TapTweak, XOnlyPublicKey};
+use crate::locktime::absolute;
 use crate::prelude::{btree_map, BTreeMap, BTreeSet, Borrow, Box, Vec};
 use crate::script::{ScriptExt as _, ScriptPubKeyExt as _};
 use crate::sighash::{self, EcdsaSighashType, Prevouts, SighashCache};
@@ -35,7 +40,11 @@
 #[doc(inline)]
 pub use self::{
     map::{Input, Output, PsbtSighashType},
+    builder::{BuildError, SpendableInput, TxBuilder},
     error::Error,
+    finalize::{FinalizeError, FinalizeErrors},
+    musig2::{Musig2Key, Musig2PartialSig, Musig2PubNonce},
+    v2::{ModifyError, TxModifiable},
 };
 
 /// A Partially Signed Transaction.
@@ -45,6 +54,10 @@ pub struct Psbt {
     pub unsigned_tx: Transaction,
     /// The version number of this PSBT. If omitted, the version number is 0.
     pub version: u32,
+    /// The lock time to use if no input requires one (PSBT version 2 only).
+    pub fallback_lock_time: Option<absolute::LockTime>,
+    /// Whether inputs and outputs may be added to the transaction (PSBT version 2 only).
+    pub tx_modifiable: Option<TxModifiable>,
     /// A global map from extended public keys to the used key fingerprint and
     /// derivation path as defined by BIP 32.
     pub xpub: BTreeMap<Xpub, KeySource>,
@@ -114,6 +127,8 @@ pub fn from_unsigned_tx(tx: Transaction) -> Result<Self, Error> {
             unsigned_tx: tx,
             xpub: Default::default(),
             version: 0,
+            fallback_lock_time: None,
+            tx_modifiable: None,
             proprietary: Default::default(),
             unknown: Default::default(),
         };
@@ -229,6 +244,8 @@ pub fn combine(&mut self, other: Self) -> Result<(), Error> {
 
         // Keeping the highest version
         self.version = cmp::max(self.version, other.version);
+        combine!(fallback_lock_time, self, other);
+        combine!(tx_modifiable, self, other);
 
         // Merging xpubs
         for (xpub, (fingerprint1, derivation1)) in other.xpub {
@@ -1386,6 +1403,8 @@ fn psbt_with_values(input: u64, output: u64) -> Psbt {
             },
             xpub: Default::default(),
             version: 0,
+            fallback_lock_time: None,
+            tx_modifiable: None,
             proprietary: BTreeMap::new(),
             unknown: BTreeMap::new(),
 
@@ -1414,6 +1433,8 @@ fn trivial_psbt() {
             },
             xpub: Default::default(),
             version: 0,
+            fallback_lock_time: None,
+            tx_modifiable: None,
             proprietary: BTreeMap::new(),
             unknown: BTreeMap::new(),
 
@@ -1571,6 +1592,8 @@ fn serialize_then_deserialize_global() {
             },
             xpub: Default::default(),
             version: 0,
+            fallback_lock_time: None,
+            tx_modifiable: None,
             proprietary: Default::default(),
             unknown: Default::default(),
             inputs: vec![Input::default()],
@@ -1661,6 +1684,8 @@ fn serde_psbt() {
 
         let psbt = Psbt {
             version: 0,
+            fallback_lock_time: None,
+            tx_modifiable: None,
             xpub: {
                 let xpub: Xpub =
                     "xpub661MyMwAqRbcGoRVtwfvzZsq2VBJR1LAHfQstHUoxqDorV89vRoMxUZ27kLrraAj6MPi\
@@ -1817,6 +1842,8 @@ fn valid_vector_1() {
                 },
                 xpub: Default::default(),
                 version: 0,
+                fallback_lock_time: None,
+                tx_modifiable: None,
                 proprietary: BTreeMap::new(),
                 unknown: BTreeMap::new(),
 
@@ -2177,6 +2204,8 @@ fn serialize_and_deserialize_preimage_psbt() {
                 ],
             },
             version: 0,
+            fallback_lock_time: None,
+            tx_modifiable: None,
             xpub: Default::default(),
             proprietary: Default::default(),
             unknown: BTreeMap::new(),
@@ -2266,8 +2295,6 @@ fn serialize_and_deserialize_proprietary() {
     // Deserialize MuSig2 PSBT participant keys according to BIP-0373
     #[test]
     fn serialize_and_deserialize_musig2_participants() {
-        // XXX: Does not cover PSBT_IN_MUSIG2_PUB_NONCE, PSBT_IN_MUSIG2_PARTIAL_SIG (yet)
-
         let expected_in_agg_pk = secp256k1::PublicKey::from_str(
             "021401301810a46a4e3f39e4603ec228ed301d9f2079767fda758dee7224b32e00",
         )
@@ -2324,6 +2351,108 @@ fn serialize_and_deserialize_musig2_participants() {
             .expect_err("Deserializing PSBT with truncated musig participants should error");
     }
 
+    // Deserialize MuSig2 PSBT public nonces and partial signatures according to BIP-0373
+    #[test]
+    fn serialize_and_deserialize_musig2_nonces_and_partial_sigs() {
+        let agg_pk = secp256k1::PublicKey::from_str(
+            "021401301810a46a4e3f39e4603ec228ed301d9f2079767fda758dee7224b32e00",
+        )
+        .unwrap();
+        let pk1 = secp256k1::PublicKey::from_str(
+            "02bebd7a1cef20283444b96e9ce78137e951ce48705390933896311a9abc75736a",
+        )
+        .unwrap();
+        let pk2 = secp256k1::PublicKey::from_str(
+            "0355212dff7b3d7e8126687a62fd0435a3fb4de56d9af9ae23a1c9ca05b349c8e2",
+        )
+        .unwrap();
+        let leaf_hash = TapLeafHash::from_byte_array([0xab; 32]);
+
+        // The participant pubkeys PSBT above with a key path public nonce for `pk1` and a script
+        // path partial signature for `pk2` added to the input.
+        const PSBT_HEX: &str = "70736274ff01005e02000000017b42be5ea467afe0d0571dc4a91bef97ff9605a590c0b8d5892323946414d1810000000000ffffffff01f0b9f50500000000225120bc7e18f55e2c7a28d78cadac1bc72c248372375d269bafe6b315bc40505d07e5000000000001012b00e1f50500000000225120de564ebf8ff7bd9bb41bd88264c04b1713ebb9dc8df36319091d2eabb16cda6221161401301810a46a4e3f39e4603ec228ed301d9f2079767fda758dee7224b32e000500eb4cbe62211655212dff7b3d7e8126687a62fd0435a3fb4de56d9af9ae23a1c9ca05b349c8e20500755abbf92116bebd7a1cef20283444b96e9ce78137e951ce48705390933896311a9abc75736a05002a33dfd90117201401301810a46a4e3f39e4603ec228ed301d9f2079767fda758dee7224b32e00221a021401301810a46a4e3f39e4603ec228ed301d9f2079767fda758dee7224b32e004202bebd7a1cef20283444b96e9ce78137e951ce48705390933896311a9abc75736a0355212dff7b3d7e8126687a62fd0435a3fb4de56d9af9ae23a1c9ca05b349c8e2431b02bebd7a1cef20283444b96e9ce78137e951ce48705390933896311a9abc75736a021401301810a46a4e3f39e4603ec228ed301d9f2079767fda758dee7224b32e00420355212dff7b3d7e8126687a62fd0435a3fb4de56d9af9ae23a1c9ca05b349c8e202bebd7a1cef20283444b96e9ce78137e951ce48705390933896311a9abc75736a631c0355212dff7b3d7e8126687a62fd0435a3fb4de56d9af9ae23a1c9ca05b349c8e2021401301810a46a4e3f39e4603ec228ed301d9f2079767fda758dee7224b32e00abababababababababababababababababababababababababababababababab207f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f0001052064934a64831bd917a2667b886671650846f021e1c025e4b2bb65e49ab3e7cba5210764934a64831bd917a2667b886671650846f021e1c025e4b2bb65e49ab3e7cba50500fa4c6afa22080364934a64831bd917a2667b886671650846f021e1c025e4b2bb65e49ab3e7cba54202841d69a8b80ae23a8090e6f3765540ea5efd8c287b1307c983a6e2a3a171b52502bad833849a98cdfb0a0749609ddccab16ad54485ecc67f828df4bdc4f2b90d4c00";
+
+        let psbt = hex_psbt(PSBT_HEX).unwrap();
+        let input = &psbt.inputs[0];
+
+        assert_eq!(input.musig2_pub_nonces.len(), 1);
+        let (key, nonce) = input.musig2_pub_nonces.iter().next().unwrap();
+        assert_eq!(
+            key,
+            &Musig2Key { participant_pubkey: pk1, aggregate_pubkey: agg_pk, leaf_hash: None }
+        );
+        assert_eq!(nonce.as_byte_array()[..33], pk2.serialize());
+        assert_eq!(nonce.as_byte_array()[33..], pk1.serialize());
+
+        assert_eq!(input.musig2_partial_sigs.len(), 1);
+        let (key, sig) = input.musig2_partial_sigs.iter().next().unwrap();
+        assert_eq!(
+            key,
+            &Musig2Key {
+                participant_pubkey: pk2,
+                aggregate_pubkey: agg_pk,
+                leaf_hash: Some(leaf_hash)
+            }
+        );
+        assert_eq!(sig.to_byte_array(), [0x7f; 32]);
+
+        // Check round trip de/serialization
+        assert_eq!(psbt.serialize_hex(), PSBT_HEX);
+
+        // A nonce whose second point is not on the curve (there is no point with x = 0).
+        let nonce_hex = format!("42{}{}", pk2, pk1);
+        let invalid_nonce =
+            PSBT_HEX.replace(&nonce_hex, &format!("42{}02{}", pk2, "00".repeat(32)));
+        hex_psbt(&invalid_nonce).expect_err("Deserializing PSBT with invalid nonce should error");
+
+        // A partial signature that is not less than the curve order.
+        let invalid_sig = PSBT_HEX.replace(&"7f".repeat(32), &"ff".repeat(32));
+        hex_psbt(&invalid_sig)
+            .expect_err("Deserializing PSBT with invalid partial signature should error");
+    }
+
+    #[test]
+    fn combine_musig2_nonces_and_partial_sigs() {
+        let agg_pk = secp256k1::PublicKey::from_str(
+            "021401301810a46a4e3f39e4603ec228ed301d9f2079767fda758dee7224b32e00",
+        )
+        .unwrap();
+        let pk1 = secp256k1::PublicKey::from_str(
+            "02bebd7a1cef20283444b96e9ce78137e951ce48705390933896311a9abc75736a",
+        )
+        .unwrap();
+        let pk2 = secp256k1::PublicKey::from_str(
+            "0355212dff7b3d7e8126687a62fd0435a3fb4de56d9af9ae23a1c9ca05b349c8e2",
+        )
+        .unwrap();
+
+        let mut nonce = [0u8; 66];
+        nonce[..33].copy_from_slice(&pk1.serialize());
+        nonce[33..].copy_from_slice(&pk2.serialize());
+        let nonce = Musig2PubNonce::from_byte_array(nonce).unwrap();
+        let sig = Musig2PartialSig::from_byte_array([0x01; 32]).unwrap();
+
+        let key = |participant_pubkey| Musig2Key {
+            participant_pubkey,
+            aggregate_pubkey: agg_pk,
+            leaf_hash: None,
+        };
+
+        let mut input1 = Input::default();
+        input1.musig2_pub_nonces.insert(key(pk1), nonce);
+        input1.musig2_partial_sigs.insert(key(pk1), sig);
+
+        let mut input2 = Input::default();
+        input2.musig2_pub_nonces.insert(key(pk2), nonce);
+        input2.musig2_partial_sigs.insert(key(pk2), sig);
+
+        input1.combine(input2);
+        assert_eq!(input1.musig2_pub_nonces.len(), 2);
+        assert_eq!(input1.musig2_partial_sigs.len(), 2);
+        assert!(input1.musig2_pub_nonces.contains_key(&key(pk2)));
+        assert!(input1.musig2_partial_sigs.contains_key(&key(pk2)));
+    }
+
     // PSBTs taken from BIP 174 test vectors.
     #[test]
     fn combine_psbts() {
@@ -2476,6 +2605,8 @@ fn fee() {
             },
             xpub: Default::default(),
             version: 0,
+            fallback_lock_time: None,
+            tx_modifiable: None,
             proprietary: BTreeMap::new(),
             unknown: BTreeMap::new(),
 
@@ -0,0 +1,261 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! MuSig2 PSBT fields.
+//!
+//! Types used by the MuSig2 input fields defined in BIP-0373
+//! <https://github.com/bitcoin/bips/blob/master/bip-0373.mediawiki>.
+//!
+//! This module only deals with (de)serialization, the MuSig2 protocol itself (nonce generation,
+//! signing and aggregation) is out of scope.
+
+use core::fmt;
+
+use hex::FromHex as _;
+
+use crate::prelude::{DisplayHex, Vec};
+use crate::psbt::serialize::{Deserialize, Serialize};
+use crate::psbt::Error;
+use crate::taproot::TapLeafHash;
+
+/// Key of the MuSig2 public nonce and partial signature input maps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Musig2Key {
+    /// The public key of the participant providing the nonce or partial signature.
+    pub participant_pubkey: secp256k1::PublicKey,
+    /// The aggregate public key the participant is signing for.
+    pub aggregate_pubkey: secp256k1::PublicKey,
+    /// The hash of the leaf script, `None` when the aggregate key is used in the Taproot key path.
+    pub leaf_hash: Option<TapLeafHash>,
+}
+
+/// A MuSig2 public nonce as produced by the BIP-0327 `NonceGen` algorithm.
+///
+/// The nonce is the serialization of two compressed points (66 bytes), both points are checked to
+/// be valid when decoding.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Musig2PubNonce([u8; 66]);
+
+impl Musig2PubNonce {
+    /// The length of a serialized public nonce.
+    pub const SIZE: usize = 66;
+
+    /// Constructs a public nonce from its 66 byte serialization.
+    ///
+    /// # Errors
+    ///
+    /// If either half of `bytes` is not a valid compressed point.
+    pub fn from_byte_array(bytes: [u8; 66]) -> Result<Self, Error> {
+        let (r1, r2) = bytes.split_at(33);
+        if !is_compressed_point(r1) || !is_compressed_point(r2) {
+            return Err(Error::InvalidMusig2PubNonce);
+        }
+        Ok(Musig2PubNonce(bytes))
+    }
+
+    /// Constructs a public nonce from a slice.
+    ///
+    /// # Errors
+    ///
+    /// If `bytes` is not 66 bytes long or either half is not a valid compressed point.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes = <[u8; 66]>::try_from(bytes).map_err(|_| Error::InvalidMusig2PubNonce)?;
+        Self::from_byte_array(bytes)
+    }
+
+    /// Returns the 66 byte serialization of this public nonce.
+    pub fn to_byte_array(self) -> [u8; 66] { self.0 }
+
+    /// Returns a reference to the 66 byte serialization of this public nonce.
+    pub fn as_byte_array(&self) -> &[u8; 66] { &self.0 }
+}
+
+impl fmt::Debug for Musig2PubNonce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Musig2PubNonce({})", self.0.as_hex())
+    }
+}
+
+impl fmt::Display for Musig2PubNonce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt::Display::fmt(&self.0.as_hex(), f) }
+}
+
+impl core::str::FromStr for Musig2PubNonce {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = <[u8; 66]>::from_hex(s).map_err(|_| Error::InvalidMusig2PubNonce)?;
+        Self::from_byte_array(bytes)
+    }
+}
+
+/// A MuSig2 partial signature as produced by the BIP-0327 `Sign` algorithm.
+///
+/// The partial signature is a 32 byte scalar, it is checked to be less than the curve order when
+/// decoding.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Musig2PartialSig([u8; 32]);
+
+impl Musig2PartialSig {
+    /// The length of a serialized partial signature.
+    pub const SIZE: usize = 32;
+
+    /// Constructs a partial signature from its 32 byte serialization.
+    ///
+    /// # Errors
+    ///
+    /// If `bytes` is not less than the secp256k1 curve order.
+    pub fn from_byte_array(bytes: [u8; 32]) -> Result<Self, Error> {
+        secp256k1::Scalar::from_be_bytes(bytes).map_err(|_| Error::InvalidMusig2PartialSig)?;
+        Ok(Musig2PartialSig(bytes))
+    }
+
+    /// Constructs a partial signature from a slice.
+    ///
+    /// # Errors
+    ///
+    /// If `bytes` is not 32 bytes long or is not less than the secp256k1 curve order.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes = <[u8; 32]>::try_from(bytes).map_err(|_| Error::InvalidMusig2PartialSig)?;
+        Self::from_byte_array(bytes)
+    }
+
+    /// Returns the 32 byte serialization of this partial signature.
+    pub fn to_byte_array(self) -> [u8; 32] { self.0 }
+
+    /// Returns a reference to the 32 byte serialization of this partial signature.
+    pub fn as_byte_array(&self) -> &[u8; 32] { &self.0 }
+}
+
+impl fmt::Debug for Musig2PartialSig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Musig2PartialSig({})", self.0.as_hex())
+    }
+}
+
+impl fmt::Display for Musig2PartialSig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt::Display::fmt(&self.0.as_hex(), f) }
+}
+
+impl core::str::FromStr for Musig2PartialSig {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = <[u8; 32]>::from_hex(s).map_err(|_| Error::InvalidMusig2PartialSig)?;
+        Self::from_byte_array(bytes)
+    }
+}
+
+/// Returns true if `bytes` is a valid compressed secp256k1 point.
+fn is_compressed_point(bytes: &[u8]) -> bool {
+    bytes.len() == 33
+        && (bytes[0] == 0x02 || bytes[0] == 0x03)
+        && secp256k1::PublicKey::from_slice(bytes).is_ok()
+}
+
+impl Serialize for Musig2PubNonce {
+    fn serialize(&self) -> Vec<u8> { self.0.to_vec() }
+}
+
+impl Deserialize for Musig2PubNonce {
+    fn deserialize(bytes: &[u8]) -> Result<Self, Error> { Self::from_slice(bytes) }
+}
+
+impl Serialize for Musig2PartialSig {
+    fn serialize(&self) -> Vec<u8> { self.0.to_vec() }
+}
+
+impl Deserialize for Musig2PartialSig {
+    fn deserialize(bytes: &[u8]) -> Result<Self, Error> { Self::from_slice(bytes) }
+}
+
+impl Serialize for Musig2Key {
+    fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(33 + 33 + 32);
+        buf.extend(self.participant_pubkey.serialize());
+        buf.extend(self.aggregate_pubkey.serialize());
+        if let Some(leaf_hash) = self.leaf_hash {
+            buf.extend(leaf_hash.as_byte_array());
+        }
+        buf
+    }
+}
+
+impl Deserialize for Musig2Key {
+    fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        const PK: usize = secp256k1::constants::PUBLIC_KEY_SIZE;
+
+        if bytes.len() != 2 * PK && bytes.len() != 2 * PK + 32 {
+            return Err(Error::InvalidMusig2Key);
+        }
+        let participant_pubkey = Deserialize::deserialize(&bytes[..PK])?;
+        let aggregate_pubkey = Deserialize::deserialize(&bytes[PK..2 * PK])?;
+        let leaf_hash = if bytes.len() > 2 * PK {
+            Some(Deserialize::deserialize(&bytes[2 * PK..])?)
+        } else {
+            None
+        };
+        Ok(Musig2Key { participant_pubkey, aggregate_pubkey, leaf_hash })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Participant keys taken from the BIP-0373 `PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS` vectors.
+    const PK1: &str = "02bebd7a1cef20283444b96e9ce78137e951ce48705390933896311a9abc75736a";
+    const PK2: &str = "0355212dff7b3d7e8126687a62fd0435a3fb4de56d9af9ae23a1c9ca05b349c8e2";
+
+    #[test]
+    fn pub_nonce_roundtrip() {
+        let s = format!("{}{}", PK1, PK2);
+        let nonce = s.parse::<Musig2PubNonce>().unwrap();
+        assert_eq!(nonce.to_string(), s);
+        assert_eq!(Musig2PubNonce::deserialize(&nonce.serialize()).unwrap(), nonce);
+    }
+
+    #[test]
+    fn pub_nonce_invalid() {
+        // Too short.
+        assert!(PK1.parse::<Musig2PubNonce>().is_err());
+        // Uncompressed prefix on the second point.
+        let s = format!("{}04{}", PK1, &PK2[2..]);
+        assert!(s.parse::<Musig2PubNonce>().is_err());
+        // Second point not on the curve.
+        let s = format!("{}02{}", PK1, "00".repeat(32));
+        assert!(s.parse::<Musig2PubNonce>().is_err());
+    }
+
+    #[test]
+    fn partial_sig_range() {
+        let zero = "00".repeat(32);
+        assert!(zero.parse::<Musig2PartialSig>().is_ok());
+        // n - 1 is the largest valid scalar.
+        let max = "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364140";
+        assert!(max.parse::<Musig2PartialSig>().is_ok());
+        // n is out of range.
+        let n = "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141";
+        assert!(n.parse::<Musig2PartialSig>().is_err());
+        assert!(Musig2PartialSig::from_slice(&[0; 31]).is_err());
+    }
+
+    #[test]
+    fn key_roundtrip() {
+        let pk1 = PK1.parse::<secp256k1::PublicKey>().unwrap();
+        let pk2 = PK2.parse::<secp256k1::PublicKey>().unwrap();
+        let leaf_hash = TapLeafHash::from_byte_array([0xab; 32]);
+
+        let key =
+            |leaf_hash| Musig2Key { participant_pubkey: pk1, aggregate_pubkey: pk2, leaf_hash };
+
+        for key in [key(None), key(Some(leaf_hash))] {
+            let ser = key.serialize();
+            assert_eq!(ser.len(), if key.leaf_hash.is_some() { 98 } else { 66 });
+            assert_eq!(Musig2Key::deserialize(&ser).unwrap(), key);
+        }
+
+        let ser = key(Some(leaf_hash)).serialize();
+        assert!(Musig2Key::deserialize(&ser[..ser.len() - 1]).is_err());
+        assert!(Musig2Key::deserialize(&ser[..65]).is_err());
+    }
+}
@@ -16,14 +16,16 @@
 use crate::crypto::key::{PublicKey, XOnlyPublicKey};
 use crate::crypto::{ecdsa, taproot};
 use crate::io::Write;
+use crate::locktime::absolute;
 use crate::prelude::{DisplayHex, String, Vec};
 use crate::psbt::{Error, Psbt};
 use crate::script::ScriptBuf;
 use crate::taproot::{
     ControlBlock, LeafVersion, TapLeafHash, TapNodeHash, TapTree, TaprootBuilder,
 };
-use crate::transaction::{Transaction, TxOut};
+use crate::transaction::{self, Transaction, TxOut};
 use crate::witness::Witness;
+use crate::{Amount, Sequence, Txid};
 
 /// A trait for serializing a value as raw data for insertion into PSBT
 /// key-value maps.
@@ -64,12 +66,22 @@ fn write_all(w: &mut impl Write, data: &[u8]) -> io::Result<usize> {
 
         written_len += write_all(w, &self.serialize_map())?;
 
-        for i in &self.inputs {
-            written_len += write_all(w, &i.serialize_map())?;
-        }
+        if self.version >= 2 {
+            for (i, txin) in self.inputs.iter().zip(&self.unsigned_tx.inputs) {
+                written_len += write_all(w, &i.serialize_map_v2(txin))?;
+            }
 
-        for i in &self.outputs {
-            written_len += write_all(w, &i.serialize_map())?;
+            for (o, txout) in self.outputs.iter().zip(&self.unsigned_tx.outputs) {
+                written_len += write_all(w, &o.serialize_map_v2(txout))?;
+            }
+        } else {
+            for i in &self.inputs {
+                written_len += write_all(w, &i.serialize_map())?;
+            }
+
+            for i in &self.outputs {
+                written_len += write_all(w, &i.serialize_map())?;
+            }
         }
 
         Ok(written_len)
@@ -95,16 +107,20 @@ pub fn deserialize_from_reader<R: io::BufRead>(r: &mut R) -> Result<Self, Error>
             return Err(Error::InvalidSeparator);
         }
 
-        let mut global = Psbt::decode_global(r)?;
+        let (mut global, inputs_len, outputs_len) = Psbt::decode_global(r)?;
         global.unsigned_tx_checks()?;
 
         let inputs: Vec<Input> = {
-            let inputs_len: usize = (global.unsigned_tx.inputs).len();
-
-            let mut inputs: Vec<Input> = Vec::with_capacity(inputs_len);
+            let mut inputs: Vec<Input> = Vec::new();
 
             for i in 0..inputs_len {
-                let input = Input::decode(r)?;
+                let input = if global.version >= 2 {
+                    let (input, txin) = Input::decode_v2(r)?;
+                    global.unsigned_tx.inputs.push(txin);
+                    input
+                } else {
+                    Input::decode(r)?
+                };
                 if let Some(ref tx) = input.non_witness_utxo {
                     let input_outpoint = global.unsigned_tx.inputs[i].previous_output;
                     let txid = tx.compute_txid();
@@ -123,12 +139,17 @@ pub fn deserialize_from_reader<R: io::BufRead>(r: &mut R) -> Result<Self, Error>
         };
 
         let outputs: Vec<Output> = {
-            let outputs_len: usize = (global.unsigned_tx.outputs).len();
-
-            let mut outputs: Vec<Output> = Vec::with_capacity(outputs_len);
+            let mut outputs: Vec<Output> = Vec::new();
 
             for _ in 0..outputs_len {
-                outputs.push(Output::decode(r)?);
+                let output = if global.version >= 2 {
+                    let (output, txout) = Output::decode_v2(r)?;
+                    global.unsigned_tx.outputs.push(txout);
+                    output
+                } else {
+                    Output::decode(r)?
+                };
+                outputs.push(output);
             }
 
             outputs
@@ -136,12 +157,23 @@ pub fn deserialize_from_reader<R: io::BufRead>(r: &mut R) -> Result<Self, Error>
 
         global.inputs = inputs;
         global.outputs = outputs;
+
+        if global.version >= 2 {
+            global.unsigned_tx.lock_time =
+                global.compute_lock_time().ok_or(Error::IncompatibleLockTimes)?;
+        }
         Ok(global)
     }
 }
 impl_psbt_de_serialize!(Transaction);
 impl_psbt_de_serialize!(TxOut);
 impl_psbt_de_serialize!(Witness);
+impl_psbt_de_serialize!(Txid);
+impl_psbt_de_serialize!(u32);
+impl_psbt_de_serialize!(Sequence);
+impl_psbt_de_serialize!(Amount);
+impl_psbt_de_serialize!(absolute::LockTime);
+impl_psbt_de_serialize!(transaction::Version);
 impl_psbt_hash_de_serialize!(ripemd160::Hash);
 impl_psbt_hash_de_serialize!(sha256::Hash);
 impl_psbt_hash_de_serialize!(TapLeafHash);
@@ -286,6 +318,28 @@ fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
     }
 }
 
+impl Serialize for absolute::Height {
+    fn serialize(&self) -> Vec<u8> { serialize(&self.to_u32()) }
+}
+
+impl Deserialize for absolute::Height {
+    fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        let raw: u32 = encode::deserialize(bytes)?;
+        Ok(absolute::Height::from_u32(raw)?)
+    }
+}
+
+impl Serialize for absolute::MedianTimePast {
+    fn serialize(&self) -> Vec<u8> { serialize(&self.to_u32()) }
+}
+
+impl Deserialize for absolute::MedianTimePast {
+    fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        let raw: u32 = encode::deserialize(bytes)?;
+        Ok(absolute::MedianTimePast::from_u32(raw)?)
+    }
+}
+
 // Taproot related ser/deser
 impl Serialize for XOnlyPublicKey {
     fn serialize(&self) -> Vec<u8> { XOnlyPublicKey::serialize(self).to_vec() }
@@ -0,0 +1,453 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! PSBT version 2.
+//!
+//! Implementation of BIP-0370 PSBT Version 2 as defined at
+//! <https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki>.
+//!
+//! We keep the unsigned transaction in [`Psbt::unsigned_tx`] for both versions. For version 2 the
+//! per-input previous txid, output index and sequence number as well as the per-output amount and
+//! script are read from, and written to, the inputs and outputs of the unsigned transaction. The
+//! lock time of the unsigned transaction is determined from the global fallback lock time and the
+//! per-input required lock times.
+
+use core::convert::Infallible;
+use core::fmt;
+
+use super::serialize::{Deserialize, Serialize};
+use super::{Error, Input, Output, Psbt};
+use crate::locktime::absolute;
+use crate::prelude::Vec;
+use crate::transaction::{TxIn, TxOut};
+
+/// The transaction modifiable flags of a version 2 PSBT (`PSBT_GLOBAL_TX_MODIFIABLE`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct TxModifiable(u8);
+
+impl TxModifiable {
+    /// No inputs or outputs may be added.
+    pub const NONE: Self = TxModifiable(0);
+    /// Inputs may be added.
+    pub const INPUTS: Self = TxModifiable(0x01);
+    /// Outputs may be added.
+    pub const OUTPUTS: Self = TxModifiable(0x02);
+    /// The PSBT has a signature using `SIGHASH_SINGLE` so inputs and outputs must be added in pairs.
+    pub const SIGHASH_SINGLE: Self = TxModifiable(0x04);
+
+    /// Constructs a new `TxModifiable` from the raw flags byte.
+    pub const fn from_u8(flags: u8) -> Self { TxModifiable(flags) }
+
+    /// Returns the raw flags byte.
+    pub const fn to_u8(self) -> u8 { self.0 }
+
+    /// Returns true if all the flags set in `other` are also set in `self`.
+    pub const fn contains(self, other: Self) -> bool { self.0 & other.0 == other.0 }
+
+    /// Returns true if inputs may be added to the PSBT.
+    pub const fn inputs_modifiable(self) -> bool { self.contains(Self::INPUTS) }
+
+    /// Returns true if outputs may be added to the PSBT.
+    pub const fn outputs_modifiable(self) -> bool { self.contains(Self::OUTPUTS) }
+
+    /// Returns true if the PSBT has a signature using `SIGHASH_SINGLE`.
+    pub const fn has_sighash_single(self) -> bool { self.contains(Self::SIGHASH_SINGLE) }
+}
+
+impl core::ops::BitOr for TxModifiable {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self { TxModifiable(self.0 | rhs.0) }
+}
+
+impl Serialize for TxModifiable {
+    fn serialize(&self) -> Vec<u8> { vec![self.0] }
+}
+
+impl Deserialize for TxModifiable {
+    fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        match bytes {
+            [flags] => Ok(TxModifiable(*flags)),
+            _ => Err(Error::Version("transaction modifiable flags must be a single byte")),
+        }
+    }
+}
+
+impl Psbt {
+    /// Converts this PSBT to version 2 (BIP-0370).
+    ///
+    /// The lock time of the unsigned transaction becomes the fallback lock time and inputs and
+    /// outputs are not modifiable. Converting a version 2 PSBT is a no-op.
+    ///
+    /// The transaction version is kept, BIP-0370 requires it to be at least 2 so a PSBT spending a
+    /// version 1 transaction fails to deserialize after conversion.
+    pub fn into_v2(mut self) -> Self {
+        if self.version >= 2 {
+            return self;
+        }
+
+        self.version = 2;
+        if self.unsigned_tx.lock_time != absolute::LockTime::ZERO {
+            self.fallback_lock_time = Some(self.unsigned_tx.lock_time);
+        }
+        self
+    }
+
+    /// Converts this PSBT to version 0 (BIP-0174).
+    ///
+    /// The unsigned transaction, including its lock time, is kept. The fallback lock time, the
+    /// transaction modifiable flags and the required lock time of each input are dropped since
+    /// they can't be represented in a version 0 PSBT. Converting a version 0 PSBT is a no-op.
+    pub fn into_v0(mut self) -> Self {
+        if self.version < 2 {
+            return self;
+        }
+
+        self.version = 0;
+        self.fallback_lock_time = None;
+        self.tx_modifiable = None;
+        for input in &mut self.inputs {
+            input.required_time_lock_time = None;
+            input.required_height_lock_time = None;
+        }
+        self
+    }
+
+    /// Adds an input to a version 2 PSBT.
+    ///
+    /// The lock time of the unsigned transaction is recomputed to take into account the required
+    /// lock times of `input`.
+    ///
+    /// # Errors
+    ///
+    /// If the PSBT is not version 2, if inputs are not modifiable, if the required lock time of
+    /// `input` is incompatible with the other inputs or if `txin` has a scriptSig or witness.
+    pub fn add_input(&mut self, txin: TxIn, input: Input) -> Result<(), ModifyError> {
+        if self.version < 2 {
+            return Err(ModifyError::NotVersion2);
+        }
+        if !self.tx_modifiable.unwrap_or_default().inputs_modifiable() {
+            return Err(ModifyError::InputsNotModifiable);
+        }
+        if !txin.script_sig.is_empty() || !txin.witness.is_empty() {
+            return Err(ModifyError::SignedInput);
+        }
+
+        self.unsigned_tx.inputs.push(txin);
+        self.inputs.push(input);
+
+        match self.compute_lock_time() {
+            Some(lock_time) => {
+                self.unsigned_tx.lock_time = lock_time;
+                Ok(())
+            }
+            None => {
+                self.unsigned_tx.inputs.pop();
+                self.inputs.pop();
+                Err(ModifyError::IncompatibleLockTime)
+            }
+        }
+    }
+
+    /// Adds an output to a version 2 PSBT.
+    ///
+    /// # Errors
+    ///
+    /// If the PSBT is not version 2 or if outputs are not modifiable.
+    pub fn add_output(&mut self, txout: TxOut, output: Output) -> Result<(), ModifyError> {
+        if self.version < 2 {
+            return Err(ModifyError::NotVersion2);
+        }
+        if !self.tx_modifiable.unwrap_or_default().outputs_modifiable() {
+            return Err(ModifyError::OutputsNotModifiable);
+        }
+
+        self.unsigned_tx.outputs.push(txout);
+        self.outputs.push(output);
+        Ok(())
+    }
+
+    /// Determines the lock time of a version 2 PSBT as described in BIP-0370.
+    ///
+    /// If no input requires a lock time the fallback lock time (or zero) is used. Otherwise the
+    /// lock time type supported by all inputs that require one is used, preferring height based
+    /// lock times, and the lock time is the maximum required value of that type.
+    ///
+    /// Returns `None` if some inputs only support height based lock times and some only support
+    /// time based lock times.
+    pub(crate) fn compute_lock_time(&self) -> Option<absolute::LockTime> {
+        let mut height_supported = true;
+        let mut time_supported = true;
+        let mut max_height: Option<absolute::Height> = None;
+        let mut max_time: Option<absolute::MedianTimePast> = None;
+
+        for input in &self.inputs {
+            match (input.required_height_lock_time, input.required_time_lock_time) {
+                (None, None) => continue,
+                (Some(_), None) => time_supported = false,
+                (None, Some(_)) => height_supported = false,
+                (Some(_), Some(_)) => {}
+            }
+            max_height = max_height.max(input.required_height_lock_time);
+            max_time = max_time.max(input.required_time_lock_time);
+        }
+
+        if max_height.is_none() && max_time.is_none() {
+            return Some(self.fallback_lock_time.unwrap_or(absolute::LockTime::ZERO));
+        }
+
+        if height_supported {
+            max_height.map(absolute::LockTime::from)
+        } else if time_supported {
+            max_time.map(absolute::LockTime::from)
+        } else {
+            None
+        }
+    }
+}
+
+/// Error while adding an input or output to a version 2 PSBT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ModifyError {
+    /// Inputs and outputs can only be added to a version 2 PSBT.
+    NotVersion2,
+    /// The transaction modifiable flags do not allow adding inputs.
+    InputsNotModifiable,
+    /// The transaction modifiable flags do not allow adding outputs.
+    OutputsNotModifiable,
+    /// The transaction input to add has a scriptSig or witness.
+    SignedInput,
+    /// The required lock time of the input is incompatible with the existing inputs.
+    IncompatibleLockTime,
+}
+
+impl From<Infallible> for ModifyError {
+    fn from(never: Infallible) -> Self { match never {} }
+}
+
+impl fmt::Display for ModifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ModifyError::*;
+
+        match *self {
+            NotVersion2 => f.write_str("inputs and outputs can only be added to a version 2 PSBT"),
+            InputsNotModifiable => f.write_str("the PSBT inputs are not modifiable"),
+            OutputsNotModifiable => f.write_str("the PSBT outputs are not modifiable"),
+            SignedInput => f.write_str("the transaction input has a scriptSig or witness"),
+            IncompatibleLockTime =>
+                f.write_str("the required lock time is incompatible with the existing inputs"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ModifyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ModifyError::*;
+
+        match *self {
+            NotVersion2 | InputsNotModifiable | OutputsNotModifiable | SignedInput
+            | IncompatibleLockTime => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::psbt::raw;
+    use crate::script::{ScriptBufExt as _, ScriptPubKeyBuf, ScriptSigBuf};
+    use crate::transaction::{self, OutPoint, Transaction};
+    use crate::{Amount, Sequence, Witness};
+
+    fn txin(vout: u32) -> TxIn {
+        TxIn {
+            previous_output: OutPoint {
+                txid: "f61b1742ca13176464adb3cb66050c00787bb3a4eead37e985f2df1e37718126"
+                    .parse()
+                    .unwrap(),
+                vout,
+            },
+            script_sig: ScriptSigBuf::new(),
+            sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+            witness: Witness::default(),
+        }
+    }
+
+    fn txout() -> TxOut {
+        TxOut {
+            value: Amount::from_sat_u32(99_999_699),
+            script_pubkey: ScriptPubKeyBuf::from_hex_no_length_prefix(
+                "76a914d0c59903c5bac2868760e90fd521a4665aa7652088ac",
+            )
+            .unwrap(),
+        }
+    }
+
+    fn psbt_v0(lock_time: absolute::LockTime) -> Psbt {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time,
+            inputs: vec![txin(0), txin(1)],
+            outputs: vec![txout()],
+        };
+        Psbt::from_unsigned_tx(tx).unwrap()
+    }
+
+    fn height(n: u32) -> absolute::Height { absolute::Height::from_u32(n).unwrap() }
+
+    fn time(n: u32) -> absolute::MedianTimePast { absolute::MedianTimePast::from_u32(n).unwrap() }
+
+    #[test]
+    fn v2_serialization_roundtrip() {
+        let mut psbt = psbt_v0(absolute::LockTime::from_consensus(1257139)).into_v2();
+        psbt.tx_modifiable = Some(TxModifiable::INPUTS | TxModifiable::OUTPUTS);
+        psbt.inputs[1].required_height_lock_time = Some(height(1257140));
+        psbt.unsigned_tx.lock_time = psbt.compute_lock_time().unwrap();
+
+        let serialized = psbt.serialize();
+        let decoded = Psbt::deserialize(&serialized).unwrap();
+        assert_eq!(decoded, psbt);
+        assert_eq!(decoded.unsigned_tx.lock_time, absolute::LockTime::from_consensus(1257140));
+        assert_eq!(decoded.fallback_lock_time, Some(absolute::LockTime::from_consensus(1257139)));
+    }
+
+    #[test]
+    fn v2_does_not_serialize_unsigned_tx() {
+        let psbt = psbt_v0(absolute::LockTime::ZERO);
+        let v0 = psbt.serialize();
+        let v2 = psbt.into_v2().serialize();
+
+        // Global map key type 0x00 (unsigned tx) follows directly after magic and separator.
+        assert_eq!(&v0[5..7], &[0x01, 0x00]);
+        assert_ne!(&v2[5..7], &[0x01, 0x00]);
+    }
+
+    #[test]
+    fn v0_v2_conversion_roundtrip() {
+        for lock_time in [absolute::LockTime::ZERO, absolute::LockTime::from_consensus(1257139)] {
+            let psbt = psbt_v0(lock_time);
+            let v2 = psbt.clone().into_v2();
+            assert_eq!(v2.version, 2);
+            assert_eq!(v2.unsigned_tx, psbt.unsigned_tx);
+            assert_eq!(v2.compute_lock_time(), Some(lock_time));
+            assert_eq!(v2.into_v0(), psbt);
+        }
+    }
+
+    #[test]
+    fn v0_rejects_v2_fields() {
+        let mut psbt = psbt_v0(absolute::LockTime::ZERO).into_v2();
+        psbt.tx_modifiable = Some(TxModifiable::INPUTS);
+        // Serialize as v2 then overwrite the version field value with 0.
+        let mut bytes = psbt.serialize();
+        let pos = bytes.windows(6).position(|w| w == [0x01, 0xfb, 0x04, 0x02, 0x00, 0x00]).unwrap();
+        bytes[pos + 3] = 0x00;
+
+        assert!(Psbt::deserialize(&bytes).is_err());
+    }
+
+    #[test]
+    fn v2_rejects_tx_version_below_two() {
+        let mut psbt = psbt_v0(absolute::LockTime::ZERO).into_v2();
+        assert!(Psbt::deserialize(&psbt.serialize()).is_ok());
+
+        psbt.unsigned_tx.version = transaction::Version::ONE;
+        assert!(matches!(Psbt::deserialize(&psbt.serialize()), Err(Error::Version(_))));
+    }
+
+    #[test]
+    fn v0_rejects_required_lock_times() {
+        let mut psbt = psbt_v0(absolute::LockTime::ZERO);
+        psbt.inputs[0].required_height_lock_time = Some(height(100));
+
+        assert!(matches!(Psbt::deserialize(&psbt.serialize()), Err(Error::Version(_))));
+    }
+
+    #[test]
+    fn v2_keys_with_key_data_are_unknown() {
+        let key = |type_value| raw::Key { type_value, key_data: vec![0x01] };
+        for version in [0, 2] {
+            let mut psbt = psbt_v0(absolute::LockTime::ZERO);
+            if version == 2 {
+                psbt = psbt.into_v2();
+            }
+            for type_value in 0x02..=0x06 {
+                psbt.unknown.insert(key(type_value), vec![0x00]);
+            }
+            for type_value in 0x0e..=0x12 {
+                psbt.inputs[0].unknown.insert(key(type_value), vec![0x00]);
+            }
+            for type_value in 0x03..=0x04 {
+                psbt.outputs[0].unknown.insert(key(type_value), vec![0x00]);
+            }
+
+            let decoded = Psbt::deserialize(&psbt.serialize()).unwrap();
+            assert_eq!(decoded, psbt);
+        }
+    }
+
+    #[test]
+    fn lock_time_from_required_lock_times() {
+        let mut psbt = psbt_v0(absolute::LockTime::ZERO).into_v2();
+        psbt.fallback_lock_time = Some(absolute::LockTime::from_consensus(10));
+        assert_eq!(psbt.compute_lock_time(), Some(absolute::LockTime::from_consensus(10)));
+
+        // Height is preferred when an input supports both.
+        psbt.inputs[0].required_height_lock_time = Some(height(100));
+        psbt.inputs[0].required_time_lock_time = Some(time(500_000_100));
+        assert_eq!(psbt.compute_lock_time(), Some(absolute::LockTime::from(height(100))));
+
+        // Time is used when another input only supports time.
+        psbt.inputs[1].required_time_lock_time = Some(time(500_000_200));
+        assert_eq!(psbt.compute_lock_time(), Some(absolute::LockTime::from(time(500_000_200))));
+
+        // An input that only supports height makes the lock times incompatible.
+        psbt.inputs[0].required_time_lock_time = None;
+        assert_eq!(psbt.compute_lock_time(), None);
+    }
+
+    #[test]
+    fn incompatible_lock_times_fail_to_deserialize() {
+        let mut psbt = psbt_v0(absolute::LockTime::ZERO).into_v2();
+        psbt.inputs[0].required_height_lock_time = Some(height(100));
+        psbt.inputs[1].required_time_lock_time = Some(time(500_000_200));
+
+        assert!(matches!(Psbt::deserialize(&psbt.serialize()), Err(Error::IncompatibleLockTimes)));
+    }
+
+    #[test]
+    fn add_input_and_output() {
+        let mut psbt = psbt_v0(absolute::LockTime::ZERO);
+        assert_eq!(psbt.add_input(txin(2), Input::default()), Err(ModifyError::NotVersion2));
+
+        let mut psbt = psbt.into_v2();
+        assert_eq!(
+            psbt.add_input(txin(2), Input::default()),
+            Err(ModifyError::InputsNotModifiable)
+        );
+        assert_eq!(
+            psbt.add_output(txout(), Output::default()),
+            Err(ModifyError::OutputsNotModifiable)
+        );
+
+        psbt.tx_modifiable = Some(TxModifiable::INPUTS | TxModifiable::OUTPUTS);
+        let input = Input { required_height_lock_time: Some(height(100)), ..Default::default() };
+        psbt.add_input(txin(2), input).unwrap();
+        psbt.add_output(txout(), Output::default()).unwrap();
+        assert_eq!(psbt.inputs.len(), 3);
+        assert_eq!(psbt.unsigned_tx.inputs.len(), 3);
+        assert_eq!(psbt.outputs.len(), 2);
+        assert_eq!(psbt.unsigned_tx.outputs.len(), 2);
+        assert_eq!(psbt.unsigned_tx.lock_time, absolute::LockTime::from(height(100)));
+
+        let input =
+            Input { required_time_lock_time: Some(time(500_000_200)), ..Default::default() };
+        assert_eq!(psbt.add_input(txin(3), input), Err(ModifyError::IncompatibleLockTime));
+        assert_eq!(psbt.inputs.len(), 3);
+        assert_eq!(psbt.unsigned_tx.inputs.len(), 3);
+
+        let decoded = Psbt::deserialize(&psbt.serialize()).unwrap();
+        assert_eq!(decoded, psbt);
+    }
+}
@@ -7,12 +7,11 @@
 use bitcoin::bip32::{Fingerprint, IntoDerivationPath, KeySource, Xpriv, Xpub};
 use bitcoin::consensus::encode::{deserialize, serialize_hex};
 use bitcoin::hex::FromHex;
-use bitcoin::opcodes::OP_0;
 use bitcoin::psbt::{Psbt, PsbtSighashType};
-use bitcoin::script::{PushBytes, ScriptBuf, ScriptBufExt as _};
+use bitcoin::script::{ScriptBuf, ScriptBufExt as _};
 use bitcoin::secp256k1::Secp256k1;
 use bitcoin::{
-    absolute, script, transaction, NetworkKind, OutPoint, PrivateKey, PublicKey, ScriptPubKeyBuf,
+    absolute, transaction, NetworkKind, OutPoint, PrivateKey, PublicKey, ScriptPubKeyBuf,
     ScriptSigBuf, Sequence, Transaction, TxIn, TxOut, Witness,
 };
 
@@ -366,11 +365,11 @@ fn combine(mut this: Psbt, that: Psbt) -> Psbt {
 /// Does the finalize step according to the BIP, returns the combined PSBT. Verifies against BIP 174
 /// test vector.
 #[track_caller]
-fn finalize(psbt: Psbt) -> Psbt {
+fn finalize(mut psbt: Psbt) -> Psbt {
     let expected_psbt_hex = include_str!("data/finalize_psbt_hex");
     let expected_psbt: Psbt = hex_psbt(expected_psbt_hex);
 
-    let psbt = finalize_psbt(psbt);
+    psbt.finalize(&Secp256k1::verification_only()).expect("failed to finalize PSBT");
 
     assert_eq!(psbt, expected_psbt);
     psbt
@@ -415,59 +414,3 @@ fn sign(mut psbt: Psbt, keys: BTreeMap<bitcoin::PublicKey, PrivateKey>) -> Psbt
     psbt.sign(&keys, &secp).unwrap();
     psbt
 }
-
-/// Finalizes a PSBT accord to the Input Finalizer role described in BIP 174.
-/// This is just a test. For a production-ready PSBT Finalizer, use [rust-miniscript](https://docs.rs/miniscript/latest/miniscript/psbt/trait.PsbtExt.html#tymethod.finalize)
-fn finalize_psbt(mut psbt: Psbt) -> Psbt {
-    // Input 0: legacy UTXO
-
-    let sigs: Vec<_> = psbt.inputs[0].partial_sigs.values().collect();
-    let script_sig = script::Builder::new()
-        .push_opcode(OP_0) // OP_CHECKMULTISIG bug pops +1 value when evaluating so push OP_0.
-        .push_slice(sigs[0].serialize())
-        .push_slice(sigs[1].serialize())
-        .push_slice(
-            <&PushBytes>::try_from(psbt.inputs[0].redeem_script.as_ref().unwrap().as_bytes())
-                .unwrap(),
-        )
-        .into_script();
-
-    psbt.inputs[0].final_script_sig = Some(script_sig);
-
-    psbt.inputs[0].partial_sigs = BTreeMap::new();
-    psbt.inputs[0].sighash_type = None;
-    psbt.inputs[0].redeem_script = None;
-    psbt.inputs[0].bip32_derivation = BTreeMap::new();
-
-    // Input 1: SegWit UTXO
-
-    let script_sig = script::Builder::new()
-        .push_slice(
-            <&PushBytes>::try_from(psbt.inputs[1].redeem_script.as_ref().unwrap().as_bytes())
-                .unwrap(),
-        )
-        .into_script();
-
-    psbt.inputs[1].final_script_sig = Some(script_sig);
-
-    let script_witness = {
-        let sigs: Vec<_> = psbt.inputs[1].partial_sigs.values().collect();
-        let mut script_witness = Witness::new();
-        script_witness.push([]); // Push 0x00 to the stack.
-        script_witness.push(sigs[1].serialize());
-        script_witness.push(sigs[0].serialize());
-        script_witness.push(psbt.inputs[1].witness_script.clone().unwrap().as_bytes());
-
-        script_witness
-    };
-
-    psbt.inputs[1].final_script_witness = Some(script_witness);
-
-    psbt.inputs[1].partial_sigs = BTreeMap::new();
-    psbt.inputs[1].sighash_type = None;
-    psbt.inputs[1].redeem_script = None;
-    psbt.inputs[1].witness_script = None;
-    psbt.inputs[1].bip32_derivation = BTreeMap::new();
-
-    psbt
-}
@@ -0,0 +1,48 @@
+//! Tests PSBT vectors for the cases listed in BIP 370 and BIP 373
+//! defined at <https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki#test-vectors>
+//! and <https://github.com/bitcoin/bips/blob/master/bip-0373.mediawiki#test-vectors>
+//!
+//! The data files hold one base64 encoded PSBT per line, preceded by a `# Case: ...` line
+//! describing it.
+
+#![cfg(feature = "base64")]
+
+use std::str::FromStr;
+
+use bitcoin::psbt::Psbt;
+
+/// Returns the base64 encoded PSBTs in `data`, skipping empty lines and `#` comments.
+fn vectors(data: &str) -> impl Iterator<Item = &str> {
+    data.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+#[test]
+fn bip370_valid_vectors() {
+    for vector in vectors(include_str!("data/bip370_valid.txt")) {
+        let psbt = Psbt::from_str(vector).unwrap_or_else(|e| panic!("{}: {}", vector, e));
+        assert_eq!(psbt.version, 2, "{}", vector);
+        assert_eq!(Psbt::from_str(&psbt.to_string()).unwrap(), psbt, "{}", vector);
+    }
+}
+
+#[test]
+fn bip370_invalid_vectors() {
+    for vector in vectors(include_str!("data/bip370_invalid.txt")) {
+        assert!(Psbt::from_str(vector).is_err(), "{}", vector);
+    }
+}
+
+#[test]
+fn bip373_valid_vectors() {
+    for vector in vectors(include_str!("data/bip373_valid.txt")) {
+        let psbt = Psbt::from_str(vector).unwrap_or_else(|e| panic!("{}: {}", vector, e));
+        assert_eq!(psbt.to_string(), vector);
+    }
+}
+
+#[test]
+fn bip373_invalid_vectors() {
+    for vector in vectors(include_str!("data/bip373_invalid.txt")) {
+        assert!(Psbt::from_str(vector).is_err(), "{}", vector);
+    }
+}
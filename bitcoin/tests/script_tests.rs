@@ -0,0 +1,309 @@
+//! Runs script tests in the format of Bitcoin Core's `script_tests.json` against the script
+//! interpreter.
+//!
+//! The vectors are in `tests/data/script_tests.json` (see `tests/data/README.md`). Each entry is
+//! either a single-string comment or
+//!
+//! `[[wit..., amount]?, scriptSig, scriptPubKey, flags, expected_error, comment?]`
+//!
+//! where the scripts use Core's test notation (see [`parse_script`]).
+
+use std::collections::HashMap;
+
+use bitcoin::hex::FromHex;
+use bitcoin::opcodes::Opcode;
+use bitcoin::script::interpreter::{verify_input, ErrorKind, VerifyFlags};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{
+    absolute, transaction, Amount, OutPoint, ScriptPubKeyBuf, ScriptSigBuf, Sequence, Transaction,
+    TxIn, TxOut, Witness,
+};
+use serde_json::Value;
+
+#[test]
+fn core_script_tests() { run_script_tests(include_str!("data/script_tests.json")); }
+
+/// Checks the harness itself on a few entries in Core's format.
+#[test]
+fn harness() {
+    run_script_tests(
+        r#"[
+        ["A comment"],
+        ["", "DEPTH 0 EQUAL", "P2SH,STRICTENC", "OK"],
+        ["1 2", "2 EQUALVERIFY 1 EQUAL", "P2SH,STRICTENC", "OK", "whitespace between symbols"],
+        ["'abc'", "SIZE 3 EQUALVERIFY 'abc' EQUAL", "", "OK"],
+        ["'ab'", "SIZE 3 EQUALVERIFY", "", "EQUALVERIFY"],
+        ["-1 1000 0x02 0xe803", "EQUALVERIFY -1 EQUAL", "NONE", "OK"],
+        ["0x01 0x05", "5 EQUAL", "MINIMALDATA", "MINIMALDATA"],
+        ["0x02 0x0500", "5 NUMEQUAL", "MINIMALDATA", "UNKNOWN_ERROR"],
+        ["1", "NOP2 CHECKLOCKTIMEVERIFY DROP 1", "", "OK"],
+        ["0", "IF CAT ENDIF 1", "", "DISABLED_OPCODE"],
+        [["51", 0.00000001], "", "0x00 0x20 0x4ae81572f06e1b88fd5ced7a1a000945432e83e1551e6f721ee9c00b8cc33260", "P2SH,WITNESS", "OK"],
+        [["52", 0.00000001], "", "0x00 0x20 0x4ae81572f06e1b88fd5ced7a1a000945432e83e1551e6f721ee9c00b8cc33260", "P2SH,WITNESS", "WITNESS_PROGRAM_MISMATCH"]
+    ]"#,
+    );
+}
+
+/// Runs every test in `json`, panicking with a list of failures.
+fn run_script_tests(json: &str) {
+    let secp = Secp256k1::verification_only();
+    let opcodes = opcode_names();
+    let tests: Vec<Value> = serde_json::from_str(json).expect("valid JSON");
+
+    let mut failures = Vec::new();
+    for test in &tests {
+        let test = test.as_array().expect("tests are arrays");
+        if test.len() == 1 {
+            continue; // Comment.
+        }
+
+        let (witness, amount, fields) = match test[0] {
+            Value::Array(ref witness) => {
+                let (amount, items) = witness.split_last().expect("witness has an amount");
+                let items = items
+                    .iter()
+                    .map(|item| Vec::from_hex(item.as_str().unwrap()).unwrap())
+                    .collect::<Vec<_>>();
+                let amount = Amount::from_btc(amount.as_f64().unwrap()).unwrap();
+                (Witness::from_slice(&items), amount, &test[1..])
+            }
+            _ => (Witness::new(), Amount::ZERO, &test[..]),
+        };
+        let field = |i: usize| fields[i].as_str().expect("fields are strings");
+
+        let script_sig = ScriptSigBuf::from_bytes(parse_script(field(0), &opcodes));
+        let script_pubkey = ScriptPubKeyBuf::from_bytes(parse_script(field(1), &opcodes));
+        let mut flags = parse_flags(field(2));
+        let expected = field(3);
+        // Core's harness implies the flags CLEANSTACK depends on.
+        if flags.contains(VerifyFlags::CLEANSTACK) {
+            flags = flags | VerifyFlags::P2SH | VerifyFlags::WITNESS;
+        }
+
+        let credit = crediting_tx(script_pubkey, amount);
+        let spend = spending_tx(&credit, script_sig, witness);
+        let result = match verify_input(&secp, &spend, 0, &credit.outputs, flags) {
+            Ok(()) => "OK",
+            Err(e) => core_error_name(e.kind()),
+        };
+        if result != expected {
+            failures.push(format!("{}: got {}", Value::Array(test.to_vec()), result));
+        }
+    }
+
+    assert!(failures.is_empty(), "{} failures:\n{}", failures.len(), failures.join("\n"));
+}
+
+/// Builds the transaction whose only output is spent by the test (`BuildCreditingTransaction`).
+fn crediting_tx(script_pubkey: ScriptPubKeyBuf, value: Amount) -> Transaction {
+    Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        inputs: vec![TxIn {
+            previous_output: OutPoint::COINBASE_PREVOUT,
+            script_sig: ScriptSigBuf::from_bytes(vec![0x00, 0x00]),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        outputs: vec![TxOut { value, script_pubkey }],
+    }
+}
+
+/// Builds the transaction spending `credit` (`BuildSpendingTransaction`).
+fn spending_tx(credit: &Transaction, script_sig: ScriptSigBuf, witness: Witness) -> Transaction {
+    Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        inputs: vec![TxIn {
+            previous_output: OutPoint { txid: credit.compute_txid(), vout: 0 },
+            script_sig,
+            sequence: Sequence::MAX,
+            witness,
+        }],
+        outputs: vec![TxOut {
+            value: credit.outputs[0].value,
+            script_pubkey: ScriptPubKeyBuf::new(),
+        }],
+    }
+}
+
+/// Returns the opcodes recognized by name, with and without the `OP_` prefix (`ParseScript`).
+fn opcode_names() -> HashMap<String, u8> {
+    let mut names = HashMap::new();
+    for op in (0x61..=0xff).chain(Some(0x50)) {
+        let name = Opcode::from(op).to_string();
+        names.insert(name[3..].to_owned(), op);
+        names.insert(name, op);
+    }
+    // Core names these after their soft forks, the tests also use the historical names.
+    let aliases = [
+        ("CHECKLOCKTIMEVERIFY", 0xb1),
+        ("CHECKSEQUENCEVERIFY", 0xb2),
+        ("NOP2", 0xb1),
+        ("NOP3", 0xb2),
+    ];
+    for &(name, op) in &aliases {
+        names.insert(name.to_owned(), op);
+        names.insert(format!("OP_{}", name), op);
+    }
+    names
+}
+
+/// Parses a script in Core's test notation.
+///
+/// Tokens are separated by whitespace and are either decimal numbers (pushed as script numbers),
+/// `0x` prefixed hex (inserted verbatim), `'quoted strings'` (pushed) or opcode names.
+fn parse_script(s: &str, opcodes: &HashMap<String, u8>) -> Vec<u8> {
+    let mut script = Vec::new();
+    for token in s.split_whitespace() {
+        let digits = token.strip_prefix('-').unwrap_or(token);
+        if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+            let n = token.parse::<i64>().expect("number in range");
+            match n {
+                -1 => script.push(0x4f),
+                0 => script.push(0x00),
+                1..=16 => script.push(0x50 + n as u8),
+                _ => push(&mut script, &script_num(n)),
+            }
+        } else if let Some(hex) = token.strip_prefix("0x") {
+            script.extend(Vec::from_hex(hex).expect("valid hex"));
+        } else if token.len() >= 2 && token.starts_with('\'') && token.ends_with('\'') {
+            push(&mut script, &token.as_bytes()[1..token.len() - 1]);
+        } else {
+            let op = opcodes.get(token).unwrap_or_else(|| panic!("unknown opcode {}", token));
+            script.push(*op);
+        }
+    }
+    script
+}
+
+/// Appends a push of `data` using the smallest push opcode.
+fn push(script: &mut Vec<u8>, data: &[u8]) {
+    match data.len() {
+        n if n < 0x4c => script.push(n as u8),
+        n if n <= 0xff => script.extend([0x4c, n as u8]),
+        n if n <= 0xffff => {
+            script.push(0x4d);
+            script.extend((n as u16).to_le_bytes());
+        }
+        n => {
+            script.push(0x4e);
+            script.extend((n as u32).to_le_bytes());
+        }
+    }
+    script.extend_from_slice(data);
+}
+
+/// Encodes `n` as a minimal script number.
+fn script_num(n: i64) -> Vec<u8> {
+    let mut abs = n.unsigned_abs();
+    let mut bytes = Vec::new();
+    while abs > 0 {
+        bytes.push(abs as u8);
+        abs >>= 8;
+    }
+    if bytes.last().map_or(false, |b| b & 0x80 != 0) {
+        bytes.push(if n < 0 { 0x80 } else { 0x00 });
+    } else if n < 0 {
+        *bytes.last_mut().expect("n is non-zero") |= 0x80;
+    }
+    bytes
+}
+
+fn parse_flags(s: &str) -> VerifyFlags {
+    s.split(',').filter(|name| !name.is_empty() && *name != "NONE").fold(
+        VerifyFlags::NONE,
+        |flags, name| {
+            flags
+                | match name {
+                    "P2SH" => VerifyFlags::P2SH,
+                    "STRICTENC" => VerifyFlags::STRICTENC,
+                    "DERSIG" => VerifyFlags::DERSIG,
+                    "LOW_S" => VerifyFlags::LOW_S,
+                    "NULLDUMMY" => VerifyFlags::NULLDUMMY,
+                    "SIGPUSHONLY" => VerifyFlags::SIGPUSHONLY,
+                    "MINIMALDATA" => VerifyFlags::MINIMALDATA,
+                    "DISCOURAGE_UPGRADABLE_NOPS" => VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS,
+                    "CLEANSTACK" => VerifyFlags::CLEANSTACK,
+                    "CHECKLOCKTIMEVERIFY" => VerifyFlags::CHECKLOCKTIMEVERIFY,
+                    "CHECKSEQUENCEVERIFY" => VerifyFlags::CHECKSEQUENCEVERIFY,
+                    "WITNESS" => VerifyFlags::WITNESS,
+                    "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM" =>
+                        VerifyFlags::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM,
+                    "MINIMALIF" => VerifyFlags::MINIMALIF,
+                    "NULLFAIL" => VerifyFlags::NULLFAIL,
+                    "WITNESS_PUBKEYTYPE" => VerifyFlags::WITNESS_PUBKEYTYPE,
+                    "CONST_SCRIPTCODE" => VerifyFlags::CONST_SCRIPTCODE,
+                    "TAPROOT" => VerifyFlags::TAPROOT,
+                    "DISCOURAGE_UPGRADABLE_TAPROOT_VERSION" =>
+                        VerifyFlags::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION,
+                    "DISCOURAGE_OP_SUCCESS" => VerifyFlags::DISCOURAGE_OP_SUCCESS,
+                    "DISCOURAGE_UPGRADABLE_PUBKEYTYPE" =>
+                        VerifyFlags::DISCOURAGE_UPGRADABLE_PUBKEYTYPE,
+                    _ => panic!("unknown flag {}", name),
+                }
+        },
+    )
+}
+
+/// Returns the name Core's `script_tests.json` uses for `kind`.
+fn core_error_name(kind: ErrorKind) -> &'static str {
+    use ErrorKind::*;
+
+    match kind {
+        EvalFalse => "EVAL_FALSE",
+        OpReturn => "OP_RETURN",
+        ScriptSize => "SCRIPT_SIZE",
+        PushSize => "PUSH_SIZE",
+        OpCount => "OP_COUNT",
+        StackSize => "STACK_SIZE",
+        SigCount => "SIG_COUNT",
+        PubkeyCount => "PUBKEY_COUNT",
+        Verify => "VERIFY",
+        EqualVerify => "EQUALVERIFY",
+        CheckMultisigVerify => "CHECKMULTISIGVERIFY",
+        CheckSigVerify => "CHECKSIGVERIFY",
+        NumEqualVerify => "NUMEQUALVERIFY",
+        BadOpcode => "BAD_OPCODE",
+        DisabledOpcode => "DISABLED_OPCODE",
+        InvalidStackOperation => "INVALID_STACK_OPERATION",
+        InvalidAltstackOperation => "INVALID_ALTSTACK_OPERATION",
+        UnbalancedConditional => "UNBALANCED_CONDITIONAL",
+        NegativeLockTime => "NEGATIVE_LOCKTIME",
+        UnsatisfiedLockTime => "UNSATISFIED_LOCKTIME",
+        SigHashType => "SIG_HASHTYPE",
+        SigDer => "SIG_DER",
+        MinimalData => "MINIMALDATA",
+        SigPushOnly => "SIG_PUSHONLY",
+        SigHighS => "SIG_HIGH_S",
+        SigNullDummy => "SIG_NULLDUMMY",
+        PubkeyType => "PUBKEYTYPE",
+        CleanStack => "CLEANSTACK",
+        MinimalIf => "MINIMALIF",
+        SigNullFail => "NULLFAIL",
+        DiscourageUpgradableNops => "DISCOURAGE_UPGRADABLE_NOPS",
+        DiscourageUpgradableWitnessProgram => "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM",
+        DiscourageUpgradableTaprootVersion => "DISCOURAGE_UPGRADABLE_TAPROOT_VERSION",
+        DiscourageOpSuccess => "DISCOURAGE_OP_SUCCESS",
+        DiscourageUpgradablePubkeyType => "DISCOURAGE_UPGRADABLE_PUBKEYTYPE",
+        WitnessProgramWrongLength => "WITNESS_PROGRAM_WRONG_LENGTH",
+        WitnessProgramWitnessEmpty => "WITNESS_PROGRAM_WITNESS_EMPTY",
+        WitnessProgramMismatch => "WITNESS_PROGRAM_MISMATCH",
+        WitnessMalleated => "WITNESS_MALLEATED",
+        WitnessMalleatedP2sh => "WITNESS_MALLEATED_P2SH",
+        WitnessUnexpected => "WITNESS_UNEXPECTED",
+        WitnessPubkeyType => "WITNESS_PUBKEYTYPE",
+        SchnorrSigSize => "SCHNORR_SIG_SIZE",
+        SchnorrSigHashType => "SCHNORR_SIG_HASHTYPE",
+        SchnorrSig => "SCHNORR_SIG",
+        TaprootWrongControlSize => "TAPROOT_WRONG_CONTROL_SIZE",
+        TapscriptValidationWeight => "TAPSCRIPT_VALIDATION_WEIGHT",
+        TapscriptCheckMultisig => "TAPSCRIPT_CHECKMULTISIG",
+        TapscriptMinimalIf => "TAPSCRIPT_MINIMALIF",
+        OpCodeSeparator => "OP_CODESEPARATOR",
+        SigFindAndDelete => "SIG_FINDANDDELETE",
+        // Core reports script number errors as exceptions.
+        ScriptNumOverflow | NonMinimalScriptNum => "UNKNOWN_ERROR",
+        _ => "UNKNOWN_ERROR",
+    }
+}
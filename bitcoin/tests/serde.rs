@@ -247,6 +247,8 @@ fn serde_regression_psbt() {
 
     let psbt = Psbt {
         version: 0,
+        fallback_lock_time: None,
+        tx_modifiable: None,
         xpub: {
             let s = include_str!("data/serde/extended_pub_key");
             let xpub = s.trim().parse::<Xpub>().unwrap();
@@ -1,13 +1,11 @@
-use std::io::{BufReader, Write};
+use std::io::{Read, Write};
 use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpStream};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use std::{env, process};
 
-use bitcoin::consensus::{encode, Decodable};
+use bitcoin_p2p_messages::connection::{Connection, Direction, Event};
 use bitcoin_p2p_messages::message_network::{ClientSoftwareVersion, UserAgent, UserAgentVersion};
-use bitcoin_p2p_messages::{
-    self, address, message, message_network, Magic, ProtocolVersion, ServiceFlags,
-};
+use bitcoin_p2p_messages::{self, address, message_network, Magic, ProtocolVersion, ServiceFlags};
 
 const SOFTWARE_VERSION: ClientSoftwareVersion =
     ClientSoftwareVersion::SemVer { major: 0, minor: 1, revision: 0 };
@@ -32,37 +30,37 @@ fn main() {
 
     let version_message = build_version_message(address);
 
-    let first_message = message::RawNetworkMessage::new(Magic::BITCOIN, version_message);
-
     if let Ok(mut stream) = TcpStream::connect(address) {
-        // Send the message
-        let _ = stream.write_all(encode::serialize(&first_message).as_slice());
-        println!("Sent version message");
-
-        // Setup StreamReader
-        let read_stream = stream.try_clone().unwrap();
-        let mut stream_reader = BufReader::new(read_stream);
+        // The connection sends the version message, answers the peer's and completes the handshake.
+        let mut connection =
+            Connection::new(Direction::Outbound, Magic::BITCOIN, version_message, Instant::now());
+        let mut buf = [0; 1024];
         loop {
-            // Loop and retrieve new messages
-            let reply = message::RawNetworkMessage::consensus_decode(&mut stream_reader).unwrap();
-            match reply.payload() {
-                message::NetworkMessage::Version(_) => {
-                    println!("Received version message: {:?}", reply.payload());
-
-                    let second_message = message::RawNetworkMessage::new(
-                        Magic::BITCOIN,
-                        message::NetworkMessage::Verack,
-                    );
-
-                    let _ = stream.write_all(encode::serialize(&second_message).as_slice());
-                    println!("Sent verack message");
+            if let Err(e) = stream.write_all(&connection.take_send_buffer()) {
+                eprintln!("error writing to the peer: {e}");
+                break;
+            }
+            let n = match stream.read(&mut buf) {
+                Ok(0) => {
+                    eprintln!("connection closed by the peer");
+                    break;
+                }
+                Ok(n) => n,
+                Err(e) => {
+                    eprintln!("error reading from the peer: {e}");
+                    break;
                 }
-                message::NetworkMessage::Verack => {
-                    println!("Received verack message: {:?}", reply.payload());
+            };
+            connection.receive(&buf[..n]);
+            match connection.poll() {
+                Ok(Some(Event::Established)) => {
+                    println!("Handshake complete: {:?}", connection.peer());
+                    let _ = stream.write_all(&connection.take_send_buffer());
                     break;
                 }
-                _ => {
-                    println!("Received unknown message: {:?}", reply.payload());
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("protocol error: {e}");
                     break;
                 }
             }
@@ -73,7 +71,7 @@ fn main() {
     }
 }
 
-fn build_version_message(address: SocketAddr) -> message::NetworkMessage {
+fn build_version_message(address: SocketAddr) -> message_network::VersionMessage {
     // Building version message, see https://en.bitcoin.it/wiki/Protocol_documentation#version
     let my_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0);
 
@@ -103,7 +101,7 @@ fn build_version_message(address: SocketAddr) -> message::NetworkMessage {
     let user_agent = UserAgent::new(SOFTWARE_NAME, USER_AGENT_VERSION);
 
     // Construct the message
-    message::NetworkMessage::Version(message_network::VersionMessage::new(
+    message_network::VersionMessage::new(
         protocol_version,
         services,
         timestamp as i64,
@@ -112,5 +110,5 @@ fn build_version_message(address: SocketAddr) -> message::NetworkMessage {
         nonce,
         user_agent,
         start_height,
-    ))
+    )
 }
@@ -0,0 +1,858 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Peer address manager.
+//!
+//! [`AddressManager`] stores peer addresses along the lines of Bitcoin Core's addrman. Addresses
+//! learned from other peers go into the "new" table and are moved into the "tried" table once a
+//! connection to them succeeded. Both tables are split into buckets chosen by hashing the network
+//! group of the address, and for the new table the network group of the peer that announced it,
+//! with a secret key. A peer can therefore only ever fill a small number of buckets no matter how
+//! many addresses it sends, which makes it hard to eclipse a node by flooding it with addresses.
+//!
+//! Network groups are the /16 for IPv4, the /32 for IPv6, and the network itself for Tor, I2P and
+//! CJDNS addresses.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use std::collections::{BTreeMap, HashMap};
+
+use bitcoin::consensus::encode::{self, Decodable, Encodable, ReadExt, WriteExt};
+use hashes::{sha256d, HashEngine as _};
+use io::{BufRead, Write};
+
+use crate::address::{AddrV2, AddrV2Message};
+
+/// Number of buckets of the new table.
+pub const NEW_BUCKET_COUNT: usize = 1024;
+
+/// Number of buckets of the tried table.
+pub const TRIED_BUCKET_COUNT: usize = 256;
+
+/// Number of addresses in a bucket.
+pub const BUCKET_SIZE: usize = 64;
+
+/// Number of tried buckets the addresses of one network group are spread over.
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+
+/// Number of new buckets the addresses announced by one network group are spread over.
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 64;
+
+/// Maximum number of new buckets an address can be in.
+const NEW_BUCKETS_PER_ADDRESS: u32 = 8;
+
+/// Addresses not seen for this many seconds are considered terrible.
+const HORIZON: u32 = 30 * 24 * 60 * 60;
+
+/// Number of failed attempts after which a never connected address is considered terrible.
+const RETRIES: u32 = 3;
+
+/// Number of failed attempts after which an address is considered terrible if it has not been
+/// connected to for [`MIN_FAIL`] seconds.
+const MAX_FAILURES: u32 = 10;
+
+/// See [`MAX_FAILURES`].
+const MIN_FAIL: u32 = 7 * 24 * 60 * 60;
+
+/// Addresses tried in the last this many seconds are unlikely to be selected.
+const RECENT_TRY: u32 = 10 * 60;
+
+/// Version of the serialization format.
+const FORMAT_VERSION: u8 = 1;
+
+/// An address and port, identifying a peer.
+type PeerKey = (AddrV2, u16);
+
+/// One of the two tables.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Table {
+    New,
+    Tried,
+}
+
+/// An address along with its bookkeeping.
+#[derive(Debug, Clone)]
+struct Entry {
+    addr: AddrV2Message,
+    /// The address of the peer which first announced this address.
+    source: AddrV2,
+    last_try: u32,
+    /// The last time a failed attempt was counted.
+    last_count_attempt: u32,
+    last_success: u32,
+    /// Number of failed attempts since the last success.
+    attempts: u32,
+    /// Number of new buckets this entry is in.
+    ref_count: u32,
+    in_tried: bool,
+}
+
+impl Entry {
+    fn new(addr: AddrV2Message, source: AddrV2) -> Self {
+        Entry {
+            addr,
+            source,
+            last_try: 0,
+            last_count_attempt: 0,
+            last_success: 0,
+            attempts: 0,
+            ref_count: 0,
+            in_tried: false,
+        }
+    }
+
+    /// Returns true if the address is not worth keeping or relaying.
+    fn is_terrible(&self, now: u32) -> bool {
+        // Never remove things tried in the last minute.
+        if self.last_try != 0 && now.saturating_sub(self.last_try) <= 60 {
+            return false;
+        }
+        // Came in a flying DeLorean.
+        if self.addr.time > now.saturating_add(10 * 60) {
+            return true;
+        }
+        // Not seen in recent history.
+        if self.addr.time == 0 || now.saturating_sub(self.addr.time) > HORIZON {
+            return true;
+        }
+        // Tried N times and never a success.
+        if self.last_success == 0 && self.attempts >= RETRIES {
+            return true;
+        }
+        // N successive failures in the last week.
+        now.saturating_sub(self.last_success) > MIN_FAIL && self.attempts >= MAX_FAILURES
+    }
+
+    /// Returns the relative chance of this address being selected.
+    fn chance(&self, now: u32) -> f64 {
+        let mut chance = 1.0;
+        // Deprioritize very recent attempts.
+        if now.saturating_sub(self.last_try) < RECENT_TRY {
+            chance *= 0.01;
+        }
+        // Deprioritize 66% after each failed attempt, but at most 1/28th.
+        chance * 0.66f64.powi(self.attempts.min(8) as i32)
+    }
+}
+
+/// A store of peer addresses split into new and tried tables.
+#[derive(Debug, Clone)]
+pub struct AddressManager {
+    /// Secret key used to select buckets.
+    key: [u8; 32],
+    entries: BTreeMap<u64, Entry>,
+    ids: HashMap<PeerKey, u64>,
+    next_id: u64,
+    new_table: Vec<Option<u64>>,
+    tried_table: Vec<Option<u64>>,
+    new_count: usize,
+    tried_count: usize,
+    /// The last time an address was marked good.
+    last_good: u32,
+    /// Counter for generating random numbers from the key.
+    rng_counter: u64,
+}
+
+impl AddressManager {
+    /// Constructs a new empty [`AddressManager`].
+    ///
+    /// `key` must be random and kept secret, the placement of addresses in buckets can be
+    /// predicted with it. It is stored when serializing the address manager.
+    pub fn new(key: [u8; 32]) -> Self {
+        AddressManager {
+            key,
+            entries: BTreeMap::new(),
+            ids: HashMap::new(),
+            next_id: 0,
+            new_table: vec![None; NEW_BUCKET_COUNT * BUCKET_SIZE],
+            tried_table: vec![None; TRIED_BUCKET_COUNT * BUCKET_SIZE],
+            new_count: 0,
+            tried_count: 0,
+            last_good: 1,
+            rng_counter: 0,
+        }
+    }
+
+    /// Returns the number of addresses.
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    /// Returns true if there are no addresses.
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    /// Returns the number of addresses in the new table.
+    pub fn new_count(&self) -> usize { self.new_count }
+
+    /// Returns the number of addresses in the tried table.
+    pub fn tried_count(&self) -> usize { self.tried_count }
+
+    /// Returns the stored address for `addr` and `port`.
+    pub fn get(&self, addr: &AddrV2, port: u16) -> Option<&AddrV2Message> {
+        self.id(addr, port).map(|id| &self.entries[&id].addr)
+    }
+
+    /// Adds addresses announced by the peer at `source` to the new table.
+    ///
+    /// `time_penalty` is subtracted from the announced times unless a peer announces itself.
+    /// Addresses on unknown networks are ignored. Returns the number of addresses not known
+    /// before.
+    pub fn add<I>(&mut self, addrs: I, source: &AddrV2, time_penalty: u32, now: u32) -> usize
+    where
+        I: IntoIterator<Item = AddrV2Message>,
+    {
+        let mut added = 0;
+        for addr in addrs {
+            if self.add_one(addr, source, time_penalty, now) {
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Marks a successful connection to the address, moving it to the tried table.
+    ///
+    /// Returns false if the address is unknown.
+    pub fn good(&mut self, addr: &AddrV2, port: u16, now: u32) -> bool {
+        let id = match self.id(addr, port) {
+            Some(id) => id,
+            None => return false,
+        };
+        self.last_good = now;
+
+        let entry = self.entry_mut(id);
+        entry.last_success = now;
+        entry.last_try = now;
+        entry.attempts = 0;
+        if !entry.in_tried {
+            self.make_tried(id);
+        }
+        true
+    }
+
+    /// Marks an attempt to connect to the address.
+    ///
+    /// If `count_failure` is true the attempt counts as failed, at most once per successful
+    /// connection to any address so a node without network access does not penalize everyone.
+    pub fn attempt(&mut self, addr: &AddrV2, port: u16, count_failure: bool, now: u32) {
+        let id = match self.id(addr, port) {
+            Some(id) => id,
+            None => return,
+        };
+        let last_good = self.last_good;
+
+        let entry = self.entry_mut(id);
+        entry.last_try = now;
+        if count_failure && entry.last_count_attempt < last_good {
+            entry.last_count_attempt = now;
+            entry.attempts += 1;
+        }
+    }
+
+    /// Updates the time the address was last seen when we are connected to it.
+    pub fn connected(&mut self, addr: &AddrV2, port: u16, now: u32) {
+        const UPDATE_INTERVAL: u32 = 20 * 60;
+
+        if let Some(id) = self.id(addr, port) {
+            let entry = self.entry_mut(id);
+            if now.saturating_sub(entry.addr.time) > UPDATE_INTERVAL {
+                entry.addr.time = now;
+            }
+        }
+    }
+
+    /// Selects an address to connect to.
+    ///
+    /// Both tables are picked from with equal probability, unless `new_only` is true. Addresses
+    /// which were recently tried or failed repeatedly are less likely to be selected.
+    pub fn select(&mut self, new_only: bool, now: u32) -> Option<AddrV2Message> {
+        if self.new_count == 0 && (new_only || self.tried_count == 0) {
+            return None;
+        }
+
+        let table = if new_only || self.tried_count == 0 {
+            Table::New
+        } else if self.new_count == 0 || self.random(2) == 0 {
+            Table::Tried
+        } else {
+            Table::New
+        };
+        let bucket_count = match table {
+            Table::New => NEW_BUCKET_COUNT,
+            Table::Tried => TRIED_BUCKET_COUNT,
+        };
+
+        let mut chance_factor = 1.0;
+        loop {
+            let bucket = self.random(bucket_count as u64) as usize;
+            let start = self.random(BUCKET_SIZE as u64) as usize;
+            let id = (0..BUCKET_SIZE).find_map(|i| {
+                let slot = bucket * BUCKET_SIZE + (start + i) % BUCKET_SIZE;
+                match table {
+                    Table::New => self.new_table[slot],
+                    Table::Tried => self.tried_table[slot],
+                }
+            });
+            let id = match id {
+                Some(id) => id,
+                None => continue,
+            };
+
+            let chance = chance_factor * self.entries[&id].chance(now);
+            if (self.random(1 << 30) as f64) < chance * f64::from(1u32 << 30) {
+                return Some(self.entries[&id].addr.clone());
+            }
+            chance_factor *= 1.2;
+        }
+    }
+
+    /// Returns up to `max` randomly chosen addresses, e.g. to answer a `getaddr` message.
+    ///
+    /// Terrible addresses are left out.
+    pub fn addresses(&mut self, max: usize, now: u32) -> Vec<AddrV2Message> {
+        let mut ids = self.entries.keys().copied().collect::<Vec<_>>();
+        let mut addrs = Vec::new();
+        for i in 0..ids.len() {
+            if addrs.len() >= max {
+                break;
+            }
+            let j = i + self.random((ids.len() - i) as u64) as usize;
+            ids.swap(i, j);
+
+            let entry = &self.entries[&ids[i]];
+            if !entry.is_terrible(now) {
+                addrs.push(entry.addr.clone());
+            }
+        }
+        addrs
+    }
+
+    fn add_one(
+        &mut self,
+        mut addr: AddrV2Message,
+        source: &AddrV2,
+        time_penalty: u32,
+        now: u32,
+    ) -> bool {
+        if !is_routable(&addr.addr) {
+            return false;
+        }
+        // Addresses with an unreasonable time are treated as five days old.
+        if addr.time <= 100_000_000 || addr.time > now.saturating_add(10 * 60) {
+            addr.time = now.saturating_sub(5 * 24 * 60 * 60);
+        }
+        let time_penalty = if addr.addr == *source { 0 } else { time_penalty };
+
+        let (id, is_new) = match self.id(&addr.addr, addr.port) {
+            Some(id) => {
+                let entry = self.entry_mut(id);
+                // Periodically update the time seen, more often for peers that are online.
+                let online = now.saturating_sub(addr.time) < 24 * 60 * 60;
+                let update_interval = if online { 60 * 60 } else { 24 * 60 * 60 };
+                if entry.addr.time
+                    < addr.time.saturating_sub(update_interval).saturating_sub(time_penalty)
+                {
+                    entry.addr.time = addr.time.saturating_sub(time_penalty);
+                }
+                entry.addr.services |= addr.services;
+
+                // Do not update if there is no new information.
+                if addr.time <= entry.addr.time
+                    || entry.in_tried
+                    || entry.ref_count >= NEW_BUCKETS_PER_ADDRESS
+                {
+                    return false;
+                }
+                // Each additional reference is half as likely to be added.
+                let factor = 1 << entry.ref_count;
+                if self.random(factor) != 0 {
+                    return false;
+                }
+                (id, false)
+            }
+            None => {
+                addr.time = addr.time.saturating_sub(time_penalty);
+                let id = self.next_id;
+                self.next_id += 1;
+                self.ids.insert((addr.addr.clone(), addr.port), id);
+                self.entries.insert(id, Entry::new(addr, source.clone()));
+                self.new_count += 1;
+                (id, true)
+            }
+        };
+
+        let entry = &self.entries[&id];
+        let bucket = self.new_bucket(&entry.addr, source);
+        let slot = bucket * BUCKET_SIZE + self.position(Table::New, bucket, &entry.addr);
+        match self.new_table[slot] {
+            Some(existing) if existing == id => return is_new,
+            Some(existing) => {
+                // Only replace an existing entry if it is terrible, or if it is in other buckets
+                // while this one is not.
+                let other = &self.entries[&existing];
+                let replace =
+                    other.is_terrible(now) || (other.ref_count > 1 && entry.ref_count == 0);
+                if !replace {
+                    if entry.ref_count == 0 {
+                        self.delete(id);
+                    }
+                    return false;
+                }
+                self.clear_new(slot);
+            }
+            None => {}
+        }
+        self.new_table[slot] = Some(id);
+        self.entry_mut(id).ref_count += 1;
+        is_new
+    }
+
+    /// Moves an entry from the new table to the tried table.
+    fn make_tried(&mut self, id: u64) {
+        let addr = self.entries[&id].addr.clone();
+        for bucket in 0..NEW_BUCKET_COUNT {
+            let slot = bucket * BUCKET_SIZE + self.position(Table::New, bucket, &addr);
+            if self.new_table[slot] == Some(id) {
+                self.new_table[slot] = None;
+                self.entry_mut(id).ref_count -= 1;
+            }
+        }
+        self.new_count -= 1;
+
+        let bucket = self.tried_bucket(&addr);
+        let slot = bucket * BUCKET_SIZE + self.position(Table::Tried, bucket, &addr);
+        if let Some(evicted) = self.tried_table[slot].take() {
+            // Move the evicted entry back into the new table.
+            self.tried_count -= 1;
+            self.entry_mut(evicted).in_tried = false;
+            self.insert_new(evicted);
+        }
+        self.tried_table[slot] = Some(id);
+        self.tried_count += 1;
+        self.entry_mut(id).in_tried = true;
+    }
+
+    /// Inserts an entry which is in neither table into the new bucket of its source, replacing
+    /// the entry in its slot.
+    fn insert_new(&mut self, id: u64) {
+        let entry = &self.entries[&id];
+        let bucket = self.new_bucket(&entry.addr, &entry.source);
+        let slot = bucket * BUCKET_SIZE + self.position(Table::New, bucket, &entry.addr);
+        self.clear_new(slot);
+        self.new_table[slot] = Some(id);
+        self.entry_mut(id).ref_count = 1;
+        self.new_count += 1;
+    }
+
+    /// Removes the entry in a new table slot, deleting it if it is in no other bucket.
+    fn clear_new(&mut self, slot: usize) {
+        if let Some(id) = self.new_table[slot].take() {
+            let entry = self.entry_mut(id);
+            entry.ref_count -= 1;
+            if entry.ref_count == 0 {
+                self.delete(id);
+            }
+        }
+    }
+
+    /// Deletes an entry of the new table which is in no bucket.
+    fn delete(&mut self, id: u64) {
+        let entry = self.entries.remove(&id).expect("entry exists");
+        debug_assert!(!entry.in_tried && entry.ref_count == 0);
+        self.ids.remove(&(entry.addr.addr, entry.addr.port));
+        self.new_count -= 1;
+    }
+
+    fn id(&self, addr: &AddrV2, port: u16) -> Option<u64> {
+        self.ids.get(&(addr.clone(), port)).copied()
+    }
+
+    fn entry_mut(&mut self, id: u64) -> &mut Entry {
+        self.entries.get_mut(&id).expect("ids and tables only hold existing entries")
+    }
+
+    fn tried_bucket(&self, addr: &AddrV2Message) -> usize {
+        let hash1 = self.hash(&[&peer_key_bytes(addr)]) % TRIED_BUCKETS_PER_GROUP;
+        let hash2 = self.hash(&[&network_group(&addr.addr), &hash1.to_le_bytes()]);
+        (hash2 % TRIED_BUCKET_COUNT as u64) as usize
+    }
+
+    fn new_bucket(&self, addr: &AddrV2Message, source: &AddrV2) -> usize {
+        let source_group = network_group(source);
+        let hash1 =
+            self.hash(&[&network_group(&addr.addr), &source_group]) % NEW_BUCKETS_PER_SOURCE_GROUP;
+        let hash2 = self.hash(&[&source_group, &hash1.to_le_bytes()]);
+        (hash2 % NEW_BUCKET_COUNT as u64) as usize
+    }
+
+    fn position(&self, table: Table, bucket: usize, addr: &AddrV2Message) -> usize {
+        let tag: &[u8] = match table {
+            Table::New => b"N",
+            Table::Tried => b"K",
+        };
+        let bucket = (bucket as u64).to_le_bytes();
+        (self.hash(&[tag, &bucket, &peer_key_bytes(addr)]) % BUCKET_SIZE as u64) as usize
+    }
+
+    /// Hashes the length prefixed `parts` keyed with the secret key.
+    fn hash(&self, parts: &[&[u8]]) -> u64 {
+        let mut engine = sha256d::Hash::engine();
+        engine.input(&self.key);
+        for part in parts {
+            engine.input(&(part.len() as u32).to_le_bytes());
+            engine.input(part);
+        }
+        let hash = sha256d::Hash::from_engine(engine).to_byte_array();
+        u64::from_le_bytes(hash[..8].try_into().expect("slice is 8 bytes"))
+    }
+
+    /// Returns a number in `0..range` which can not be predicted without the secret key.
+    fn random(&mut self, range: u64) -> u64 {
+        self.rng_counter += 1;
+        self.hash(&[b"R", &self.rng_counter.to_le_bytes()]) % range
+    }
+}
+
+/// Serializes the address manager in a versioned format which is stable across releases.
+///
+/// Bucket positions are not stored, they are recomputed from the key when decoding.
+impl Encodable for AddressManager {
+    fn consensus_encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        len += FORMAT_VERSION.consensus_encode(w)?;
+        len += self.key.consensus_encode(w)?;
+        len += w.emit_compact_size(self.entries.len())?;
+        for entry in self.entries.values() {
+            len += entry.addr.consensus_encode(w)?;
+            len += entry.source.consensus_encode(w)?;
+            len += entry.last_try.consensus_encode(w)?;
+            len += entry.last_success.consensus_encode(w)?;
+            len += entry.attempts.consensus_encode(w)?;
+            len += entry.in_tried.consensus_encode(w)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for AddressManager {
+    fn consensus_decode<R: BufRead + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        if u8::consensus_decode(r)? != FORMAT_VERSION {
+            return Err(crate::consensus::parse_failed_error("unknown address manager version"));
+        }
+        let mut manager = AddressManager::new(Decodable::consensus_decode(r)?);
+
+        let count = r.read_compact_size()?;
+        if count > ((NEW_BUCKET_COUNT + TRIED_BUCKET_COUNT) * BUCKET_SIZE) as u64 {
+            return Err(crate::consensus::parse_failed_error("too many addresses"));
+        }
+        let mut tried = Vec::new();
+        let mut new = Vec::new();
+        for _ in 0..count {
+            let mut entry =
+                Entry::new(Decodable::consensus_decode(r)?, Decodable::consensus_decode(r)?);
+            entry.last_try = Decodable::consensus_decode(r)?;
+            entry.last_success = Decodable::consensus_decode(r)?;
+            entry.attempts = Decodable::consensus_decode(r)?;
+            let in_tried = Decodable::consensus_decode(r)?;
+
+            let key = (entry.addr.addr.clone(), entry.addr.port);
+            if manager.ids.contains_key(&key) {
+                return Err(crate::consensus::parse_failed_error("duplicate address"));
+            }
+            let id = manager.next_id;
+            manager.next_id += 1;
+            manager.ids.insert(key, id);
+            manager.entries.insert(id, entry);
+            if in_tried {
+                tried.push(id);
+            } else {
+                new.push(id);
+            }
+        }
+
+        // Tried entries colliding with an earlier one are moved to the new table.
+        for id in tried {
+            let addr = &manager.entries[&id].addr;
+            let bucket = manager.tried_bucket(addr);
+            let slot = bucket * BUCKET_SIZE + manager.position(Table::Tried, bucket, addr);
+            if manager.tried_table[slot].is_none() {
+                manager.tried_table[slot] = Some(id);
+                manager.tried_count += 1;
+                manager.entry_mut(id).in_tried = true;
+            } else {
+                manager.insert_new(id);
+            }
+        }
+        // New entries colliding with an earlier one are dropped.
+        for id in new {
+            let entry = &manager.entries[&id];
+            let bucket = manager.new_bucket(&entry.addr, &entry.source);
+            let slot = bucket * BUCKET_SIZE + manager.position(Table::New, bucket, &entry.addr);
+            if manager.new_table[slot].is_none() {
+                manager.new_table[slot] = Some(id);
+                manager.entry_mut(id).ref_count = 1;
+                manager.new_count += 1;
+            } else {
+                let entry = manager.entries.remove(&id).expect("entry exists");
+                manager.ids.remove(&(entry.addr.addr, entry.addr.port));
+            }
+        }
+        Ok(manager)
+    }
+}
+
+/// Returns whether an address is publicly routable.
+///
+/// Mirrors Bitcoin Core's `CNetAddr::IsRoutable`, rejecting unspecified, local, private and
+/// reserved ranges as well as addresses on unknown networks.
+fn is_routable(addr: &AddrV2) -> bool {
+    match *addr {
+        AddrV2::Ipv4(ref ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_broadcast()
+                || a == 0 // RFC 1122 "this network"
+                || ip.is_loopback()
+                || ip.is_private() // RFC 1918
+                || (a == 198 && (b & 0xfe) == 18) // RFC 2544
+                || ip.is_link_local() // RFC 3927
+                || (a == 100 && (b & 0xc0) == 64) // RFC 6598
+                || (a == 192 && b == 0 && c == 2) // RFC 5737
+                || (a == 198 && b == 51 && c == 100)
+                || (a == 203 && b == 0 && c == 113))
+        }
+        AddrV2::Ipv6(ref ip) => {
+            let [s0, s1, s2, s3, s4, s5, _, _] = ip.segments();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || (s0 == 0 && s1 == 0 && s2 == 0 && s3 == 0 && s4 == 0 && s5 == 0xffff) // IPv4-mapped
+                || (s0 == 0x2001 && s1 == 0x0db8) // RFC 3849
+                || (s0 == 0xfe80 && s1 == 0 && s2 == 0 && s3 == 0) // RFC 4862
+                || (s0 & 0xfe00) == 0xfc00 // RFC 4193
+                || (s0 == 0x2001 && (s1 & 0xfff0) == 0x0010) // RFC 4843
+                || (s0 == 0x2001 && (s1 & 0xfff0) == 0x0020)) // RFC 7343
+        }
+        AddrV2::Cjdns(ref ip) => ip.octets()[0] == 0xfc,
+        AddrV2::TorV3(_) | AddrV2::I2p(_) => true,
+        AddrV2::Unknown(..) => false,
+    }
+}
+
+/// Returns the network group of an address.
+fn network_group(addr: &AddrV2) -> Vec<u8> {
+    match *addr {
+        AddrV2::Ipv4(ref ip) => {
+            let octets = ip.octets();
+            vec![1, octets[0], octets[1]]
+        }
+        AddrV2::Ipv6(ref ip) => {
+            let octets = ip.octets();
+            vec![2, octets[0], octets[1], octets[2], octets[3]]
+        }
+        AddrV2::TorV3(_) => vec![4],
+        AddrV2::I2p(_) => vec![5],
+        AddrV2::Cjdns(_) => vec![6],
+        AddrV2::Unknown(network, _) => vec![network],
+    }
+}
+
+/// Returns the serialized address and port of a peer.
+fn peer_key_bytes(addr: &AddrV2Message) -> Vec<u8> {
+    let mut bytes = encode::serialize(&addr.addr);
+    bytes.extend_from_slice(&addr.port.to_be_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use bitcoin::consensus::encode::{deserialize, serialize};
+
+    use super::*;
+    use crate::ServiceFlags;
+
+    const NOW: u32 = 1_700_000_000;
+    const KEY: [u8; 32] = [0x42; 32];
+
+    fn ipv4(n: u32) -> AddrV2 { AddrV2::Ipv4(Ipv4Addr::from(n)) }
+
+    fn msg(addr: AddrV2) -> AddrV2Message {
+        AddrV2Message { time: NOW - 60, services: ServiceFlags::NETWORK, addr, port: 8333 }
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(network_group(&ipv4(0x0102_0304)), [1, 1, 2]);
+        assert_eq!(network_group(&ipv4(0x0102_ffff)), network_group(&ipv4(0x0102_0000)));
+        let ipv6 = AddrV2::Ipv6(Ipv6Addr::new(0x2001, 0x0db8, 0x1234, 0, 0, 0, 0, 1));
+        assert_eq!(network_group(&ipv6), [2, 0x20, 0x01, 0x0d, 0xb8]);
+        assert_eq!(network_group(&AddrV2::TorV3([1; 32])), network_group(&AddrV2::TorV3([2; 32])));
+        assert_ne!(network_group(&AddrV2::TorV3([1; 32])), network_group(&AddrV2::I2p([1; 32])));
+    }
+
+    #[test]
+    fn add_and_get() {
+        let mut manager = AddressManager::new(KEY);
+        let source = ipv4(0x0808_0808);
+        assert!(manager.is_empty());
+
+        assert_eq!(manager.add([msg(ipv4(0x0102_0304))], &source, 0, NOW), 1);
+        assert_eq!(manager.add([msg(ipv4(0x0102_0304))], &source, 0, NOW), 0);
+        assert_eq!(manager.add([msg(AddrV2::Unknown(42, vec![1, 2]))], &source, 0, NOW), 0);
+        assert_eq!(manager.len(), 1);
+        assert_eq!(manager.new_count(), 1);
+        assert_eq!(manager.get(&ipv4(0x0102_0304), 8333).unwrap().time, NOW - 60);
+        assert!(manager.get(&ipv4(0x0102_0304), 8334).is_none());
+
+        // The time penalty does not apply to peers announcing themselves.
+        let addr = msg(ipv4(0x0203_0405));
+        assert_eq!(manager.add([addr.clone()], &addr.addr, 3600, NOW), 1);
+        assert_eq!(manager.get(&addr.addr, 8333).unwrap().time, NOW - 60);
+        let addr = msg(ipv4(0x0304_0506));
+        assert_eq!(manager.add([addr.clone()], &source, 3600, NOW), 1);
+        assert_eq!(manager.get(&addr.addr, 8333).unwrap().time, NOW - 60 - 3600);
+    }
+
+    #[test]
+    fn rejects_non_routable() {
+        let mut manager = AddressManager::new(KEY);
+        let source = ipv4(0x0808_0808);
+        let non_routable = [
+            AddrV2::Ipv4(Ipv4Addr::UNSPECIFIED),
+            AddrV2::Ipv4(Ipv4Addr::BROADCAST),
+            AddrV2::Ipv4(Ipv4Addr::LOCALHOST),
+            AddrV2::Ipv4(Ipv4Addr::new(0, 1, 2, 3)),
+            AddrV2::Ipv4(Ipv4Addr::new(10, 0, 0, 1)),
+            AddrV2::Ipv4(Ipv4Addr::new(172, 31, 255, 255)),
+            AddrV2::Ipv4(Ipv4Addr::new(192, 168, 1, 1)),
+            AddrV2::Ipv4(Ipv4Addr::new(198, 19, 0, 1)),
+            AddrV2::Ipv4(Ipv4Addr::new(169, 254, 0, 1)),
+            AddrV2::Ipv4(Ipv4Addr::new(100, 127, 0, 1)),
+            AddrV2::Ipv4(Ipv4Addr::new(203, 0, 113, 1)),
+            AddrV2::Ipv6(Ipv6Addr::UNSPECIFIED),
+            AddrV2::Ipv6(Ipv6Addr::LOCALHOST),
+            AddrV2::Ipv6(Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0x0102, 0x0304)),
+            AddrV2::Ipv6(Ipv6Addr::new(0x2001, 0x0db8, 0, 0, 0, 0, 0, 1)),
+            AddrV2::Ipv6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)),
+            AddrV2::Ipv6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1)),
+            AddrV2::Ipv6(Ipv6Addr::new(0x2001, 0x0010, 0, 0, 0, 0, 0, 1)),
+            AddrV2::Ipv6(Ipv6Addr::new(0x2001, 0x0020, 0, 0, 0, 0, 0, 1)),
+            AddrV2::Cjdns(Ipv6Addr::new(0x2001, 0x4860, 0, 0, 0, 0, 0, 1)),
+            AddrV2::Unknown(42, vec![1, 2, 3]),
+        ];
+        for addr in non_routable {
+            assert!(!is_routable(&addr), "{:?}", addr);
+            assert_eq!(manager.add([msg(addr)], &source, 0, NOW), 0);
+        }
+        assert!(manager.is_empty());
+
+        let routable = [
+            ipv4(0x0102_0304),
+            AddrV2::Ipv4(Ipv4Addr::new(172, 32, 0, 1)),
+            AddrV2::Ipv6(Ipv6Addr::new(0x2001, 0x4860, 0, 0, 0, 0, 0, 1)),
+            AddrV2::Cjdns(Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 1)),
+            AddrV2::TorV3([1; 32]),
+            AddrV2::I2p([1; 32]),
+        ];
+        for addr in routable {
+            assert_eq!(manager.add([msg(addr)], &source, 0, NOW), 1);
+        }
+    }
+
+    #[test]
+    fn source_group_limits_buckets() {
+        let mut manager = AddressManager::new(KEY);
+        let addrs = (0..20_000).map(|i| msg(ipv4(0x2000_0000 | (i << 14) | 1)));
+        // All sources are in the same /16.
+        for (i, addr) in addrs.enumerate() {
+            manager.add([addr], &ipv4(0x0909_0000 | i as u32 & 0xffff), 0, NOW);
+        }
+
+        let buckets = manager
+            .new_table
+            .iter()
+            .enumerate()
+            .filter(|(_, id)| id.is_some())
+            .map(|(slot, _)| slot / BUCKET_SIZE)
+            .collect::<BTreeSet<_>>();
+        assert!(buckets.len() <= NEW_BUCKETS_PER_SOURCE_GROUP as usize);
+        assert!(manager.len() <= buckets.len() * BUCKET_SIZE);
+        assert_eq!(manager.len(), manager.new_count());
+        assert_eq!(manager.len(), manager.new_table.iter().filter(|id| id.is_some()).count());
+    }
+
+    #[test]
+    fn good_moves_to_tried() {
+        let mut manager = AddressManager::new(KEY);
+        let addr = ipv4(0x0102_0304);
+        manager.add([msg(addr.clone())], &ipv4(0x0808_0808), 0, NOW);
+        assert_eq!(manager.select(false, NOW), Some(msg(addr.clone())));
+
+        assert!(!manager.good(&ipv4(0x0506_0708), 8333, NOW));
+        assert!(manager.good(&addr, 8333, NOW));
+        assert_eq!(manager.new_count(), 0);
+        assert_eq!(manager.tried_count(), 1);
+        assert!(manager.new_table.iter().all(Option::is_none));
+        assert_eq!(manager.select(true, NOW), None);
+        assert_eq!(manager.select(false, NOW), Some(msg(addr.clone())));
+
+        // Tried addresses are not added to the new table again.
+        assert_eq!(manager.add([msg(addr)], &ipv4(0x0909_0909), 0, NOW), 0);
+        assert_eq!(manager.new_count(), 0);
+    }
+
+    #[test]
+    fn attempts() {
+        let mut manager = AddressManager::new(KEY);
+        let source = ipv4(0x0808_0808);
+        let good = ipv4(0x0102_0304);
+        let bad = ipv4(0x0203_0405);
+        manager.add([msg(good.clone()), msg(bad.clone())], &source, 0, NOW);
+        manager.good(&good, 8333, NOW);
+
+        // Failures are only counted once per good connection.
+        for i in 1..=3 {
+            manager.attempt(&bad, 8333, true, NOW + 2 * i);
+            manager.attempt(&bad, 8333, true, NOW + 2 * i);
+            manager.good(&good, 8333, NOW + 2 * i + 1);
+        }
+        let entry = &manager.entries[&manager.id(&bad, 8333).unwrap()];
+        assert_eq!(entry.attempts, 3);
+        assert_eq!(entry.last_try, NOW + 6);
+        // Recently tried addresses are not terrible.
+        assert!(!entry.is_terrible(NOW + 6));
+        assert!(entry.is_terrible(NOW + 3600));
+        assert!(entry.chance(NOW + 3600) < 0.3);
+
+        let addrs = manager.addresses(10, NOW + 3600);
+        assert_eq!(addrs.len(), 1);
+        assert_eq!(addrs[0].addr, good);
+
+        manager.connected(&good, 8333, NOW + 3600);
+        assert_eq!(manager.get(&good, 8333).unwrap().time, NOW + 3600);
+    }
+
+    #[test]
+    fn serialize_roundtrip() {
+        let mut manager = AddressManager::new(KEY);
+        for i in 0..100u32 {
+            let source = ipv4(0x0a00_0000 | (i % 7) << 16);
+            manager.add([msg(ipv4(0x0b00_0000 | i << 12))], &source, 0, NOW);
+        }
+        manager.add([msg(AddrV2::TorV3([7; 32]))], &ipv4(0x0808_0808), 0, NOW);
+        for i in 0..10u32 {
+            manager.good(&ipv4(0x0b00_0000 | i << 12), 8333, NOW);
+        }
+        manager.attempt(&ipv4(0x0b00_0000 | 50 << 12), 8333, true, NOW);
+
+        let bytes = serialize(&manager);
+        let decoded: AddressManager = deserialize(&bytes).unwrap();
+        assert_eq!(decoded.len(), manager.len());
+        assert_eq!(decoded.new_count(), manager.new_count());
+        assert_eq!(decoded.tried_count(), manager.tried_count());
+        assert_eq!(serialize(&decoded), bytes);
+
+        let mut bytes = bytes;
+        bytes[0] = FORMAT_VERSION + 1;
+        assert!(deserialize::<AddressManager>(&bytes).is_err());
+    }
+}
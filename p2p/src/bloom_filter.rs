@@ -0,0 +1,476 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! BIP-0037 Connection Bloom filters.
+//!
+//! A [`BloomFilter`] is loaded into a peer with a `filterload` message. The peer then only relays
+//! transactions, and the transactions of `merkleblock`s, which match the filter. Depending on the
+//! filter's [`BloomFlags`] the peer adds the outpoints of matching outputs to the filter so that
+//! transactions spending them match as well.
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::fmt;
+
+use bitcoin::block::{Block, Checked};
+use bitcoin::merkle_tree::MerkleBlock;
+use bitcoin::script::interpreter::MAX_SCRIPT_ELEMENT_SIZE;
+use bitcoin::script::{Instruction, Instructions, ScriptExt as _, ScriptPubKeyExt as _};
+use bitcoin::{OutPoint, Transaction, Txid};
+
+use crate::message_bloom::{BloomFlags, FilterAdd, FilterLoad};
+
+/// Maximum size of a bloom filter in bytes.
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+
+/// Maximum number of hash functions of a bloom filter.
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+/// Multiplier of the hash function number in the Murmur3 seed.
+const SEED_MULTIPLIER: u32 = 0xFBA4_C795;
+
+/// A BIP-0037 bloom filter.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BloomFilter {
+    data: Vec<u8>,
+    hash_funcs: u32,
+    tweak: u32,
+    flags: BloomFlags,
+}
+
+impl BloomFilter {
+    /// Constructs a new empty [`BloomFilter`] sized to hold `elements` elements with a false
+    /// positive rate of `fp_rate`.
+    ///
+    /// `fp_rate` is a probability between 0 and 1, e.g. `0.0001` for one in ten thousand. The
+    /// filter size and number of hash functions are capped at [`MAX_BLOOM_FILTER_SIZE`] and
+    /// [`MAX_HASH_FUNCS`]. `tweak` should be random so that filters of different clients can not
+    /// be linked.
+    #[cfg(feature = "std")]
+    pub fn new(elements: u32, fp_rate: f64, tweak: u32, flags: BloomFlags) -> Self {
+        use core::f64::consts::LN_2;
+
+        let elements = elements.max(1);
+        // Casting saturates, so an `fp_rate` outside of (0, 1) results in an empty or full size filter.
+        let bits = (-1.0 / (LN_2 * LN_2) * f64::from(elements) * fp_rate.ln()) as usize;
+        let len = bits.min(MAX_BLOOM_FILTER_SIZE * 8) / 8;
+        let hash_funcs = ((len * 8 / elements as usize) as f64 * LN_2) as u32;
+
+        Self { data: alloc::vec![0; len], hash_funcs: hash_funcs.min(MAX_HASH_FUNCS), tweak, flags }
+    }
+
+    /// Returns the filter bit field.
+    pub fn as_bytes(&self) -> &[u8] { &self.data }
+
+    /// Returns the number of hash functions.
+    pub fn hash_funcs(&self) -> u32 { self.hash_funcs }
+
+    /// Returns the random value added to the hash function seeds.
+    pub fn tweak(&self) -> u32 { self.tweak }
+
+    /// Returns the flags controlling how matched outpoints are added to the filter.
+    pub fn flags(&self) -> BloomFlags { self.flags }
+
+    /// Inserts `data` into the filter.
+    pub fn insert(&mut self, data: &[u8]) {
+        // An empty filter matches everything, avoid dividing by zero.
+        if self.data.is_empty() {
+            return;
+        }
+        for n in 0..self.hash_funcs {
+            let bit = self.bit_index(n, data);
+            self.data[bit >> 3] |= 1 << (bit & 7);
+        }
+    }
+
+    /// Returns true if `data` matches the filter.
+    ///
+    /// Elements that were never inserted match with the filter's false positive rate.
+    pub fn contains(&self, data: &[u8]) -> bool {
+        if self.data.is_empty() {
+            return true;
+        }
+        (0..self.hash_funcs).all(|n| {
+            let bit = self.bit_index(n, data);
+            self.data[bit >> 3] & (1 << (bit & 7)) != 0
+        })
+    }
+
+    /// Inserts an outpoint into the filter.
+    pub fn insert_outpoint(&mut self, outpoint: &OutPoint) {
+        self.insert(&serialize_outpoint(outpoint))
+    }
+
+    /// Returns true if `outpoint` matches the filter.
+    pub fn contains_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contains(&serialize_outpoint(outpoint))
+    }
+
+    /// Inserts the data of a `filteradd` message into the filter.
+    ///
+    /// # Errors
+    ///
+    /// If the data is larger than the maximum script element size.
+    pub fn add(&mut self, msg: &FilterAdd) -> Result<(), Error> {
+        if msg.data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(Error::ElementTooLarge(msg.data.len()));
+        }
+        self.insert(&msg.data);
+        Ok(())
+    }
+
+    /// Returns true if `tx` matches the filter, updating the filter according to its flags.
+    ///
+    /// A transaction matches if its txid, one of the data pushes in its output scripts, one of
+    /// the outpoints it spends, or one of the data pushes in its input scripts matches. When an
+    /// output matches, its outpoint is inserted into the filter for [`BloomFlags::All`], and for
+    /// [`BloomFlags::PubkeyOnly`] if the output is P2PK or bare multisig.
+    pub fn match_transaction(&mut self, tx: &Transaction) -> bool {
+        let txid = tx.compute_txid();
+        let mut found = self.contains(txid.as_byte_array());
+
+        for (vout, output) in tx.outputs.iter().enumerate() {
+            if !self.contains_push(output.script_pubkey.instructions()) {
+                continue;
+            }
+            found = true;
+
+            let update = match self.flags {
+                BloomFlags::None => false,
+                BloomFlags::All => true,
+                BloomFlags::PubkeyOnly =>
+                    output.script_pubkey.is_p2pk() || output.script_pubkey.is_multisig(),
+            };
+            if update {
+                let vout = u32::try_from(vout).expect("transactions have less than 2^32 outputs");
+                self.insert_outpoint(&OutPoint { txid, vout });
+            }
+        }
+        if found {
+            return true;
+        }
+
+        tx.inputs.iter().any(|input| {
+            self.contains_outpoint(&input.previous_output)
+                || self.contains_push(input.script_sig.instructions())
+        })
+    }
+
+    /// Constructs a [`MerkleBlock`] proving the transactions of `block` that match the filter.
+    ///
+    /// Transactions are matched in block order with [`BloomFilter::match_transaction`], so a
+    /// transaction spending an output added to the filter earlier in the block matches too.
+    pub fn merkle_block(&mut self, block: &Block<Checked>) -> MerkleBlock {
+        let matches = block
+            .transactions()
+            .iter()
+            .filter(|tx| self.match_transaction(tx))
+            .map(Transaction::compute_txid)
+            .collect::<BTreeSet<Txid>>();
+
+        MerkleBlock::from_block_with_predicate(block, |txid| matches.contains(txid))
+    }
+
+    /// Returns true if a data push of a script matches the filter.
+    ///
+    /// Like Bitcoin Core, parsing stops at the first invalid instruction and empty pushes are
+    /// ignored.
+    fn contains_push(&self, instructions: Instructions<'_>) -> bool {
+        instructions.map_while(Result::ok).any(|instruction| match instruction {
+            Instruction::PushBytes(push) => !push.is_empty() && self.contains(push.as_bytes()),
+            Instruction::Op(_) => false,
+        })
+    }
+
+    /// Returns the index of the bit set by hash function `n` for `data`.
+    fn bit_index(&self, n: u32, data: &[u8]) -> usize {
+        let seed = n.wrapping_mul(SEED_MULTIPLIER).wrapping_add(self.tweak);
+        murmur3(seed, data) as usize % (self.data.len() * 8)
+    }
+}
+
+impl From<BloomFilter> for FilterLoad {
+    fn from(filter: BloomFilter) -> Self {
+        FilterLoad {
+            filter: filter.data,
+            hash_funcs: filter.hash_funcs,
+            tweak: filter.tweak,
+            flags: filter.flags,
+        }
+    }
+}
+
+impl TryFrom<FilterLoad> for BloomFilter {
+    type Error = Error;
+
+    fn try_from(msg: FilterLoad) -> Result<Self, Self::Error> {
+        if msg.filter.len() > MAX_BLOOM_FILTER_SIZE {
+            return Err(Error::FilterTooLarge(msg.filter.len()));
+        }
+        if msg.hash_funcs > MAX_HASH_FUNCS {
+            return Err(Error::TooManyHashFuncs(msg.hash_funcs));
+        }
+        Ok(BloomFilter {
+            data: msg.filter,
+            hash_funcs: msg.hash_funcs,
+            tweak: msg.tweak,
+            flags: msg.flags,
+        })
+    }
+}
+
+/// Serializes an outpoint the way it is inserted into the filter.
+fn serialize_outpoint(outpoint: &OutPoint) -> [u8; 36] {
+    let mut buf = [0; 36];
+    buf[..32].copy_from_slice(outpoint.txid.as_byte_array());
+    buf[32..].copy_from_slice(&outpoint.vout.to_le_bytes());
+    buf
+}
+
+/// The 32-bit Murmur3 hash of `data`.
+fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let mut h = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        h ^= mix(k);
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let k = tail.iter().rev().fold(0, |k, &b| (k << 8) | u32::from(b));
+        h ^= mix(k);
+    }
+
+    h ^= data.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    h
+}
+
+/// An error loading a bloom filter from a peer's message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The filter is larger than [`MAX_BLOOM_FILTER_SIZE`].
+    FilterTooLarge(usize),
+    /// The filter uses more than [`MAX_HASH_FUNCS`] hash functions.
+    TooManyHashFuncs(u32),
+    /// The `filteradd` data is larger than the maximum script element size.
+    ElementTooLarge(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Error::*;
+
+        match *self {
+            FilterTooLarge(len) => write!(f, "bloom filter is too large: {} bytes", len),
+            TooManyHashFuncs(n) => write!(f, "bloom filter uses too many hash functions: {}", n),
+            ElementTooLarge(len) => write!(f, "bloom filter element is too large: {} bytes", len),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use Error::*;
+
+        match *self {
+            FilterTooLarge(_) | TooManyHashFuncs(_) | ElementTooLarge(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use bitcoin::consensus::encode::serialize;
+    use bitcoin::opcodes::all::*;
+    use bitcoin::script::{ScriptPubKey, ScriptPubKeyBuf, ScriptSig, ScriptSigBuf};
+    use bitcoin::transaction::Version;
+    use bitcoin::{absolute, Amount, PublicKey, Sequence, TxIn, TxOut, Witness};
+    use hex_lit::hex;
+
+    use super::*;
+
+    #[test]
+    fn murmur3_vectors() {
+        // From Bitcoin Core's `hash_tests.cpp`.
+        let vectors: &[(u32, u32, &[u8])] = &[
+            (0x0000_0000, 0x0000_0000, &[]),
+            (0x6a39_6f08, 0xFBA4_C795, &[]),
+            (0x81f1_6f39, 0xffff_ffff, &[]),
+            (0x514e_28b7, 0x0000_0000, &hex!("00")),
+            (0xea3f_0b17, 0xFBA4_C795, &hex!("00")),
+            (0xfd6c_f10d, 0x0000_0000, &hex!("ff")),
+            (0x16c6_b7ab, 0x0000_0000, &hex!("0011")),
+            (0x8eb5_1c3d, 0x0000_0000, &hex!("001122")),
+            (0xb447_1bf8, 0x0000_0000, &hex!("00112233")),
+            (0xe230_1fa8, 0x0000_0000, &hex!("0011223344")),
+            (0xfc2e_4a15, 0x0000_0000, &hex!("001122334455")),
+            (0xb074_502c, 0x0000_0000, &hex!("00112233445566")),
+            (0x8034_d2a0, 0x0000_0000, &hex!("0011223344556677")),
+            (0xb469_8def, 0x0000_0000, &hex!("001122334455667788")),
+        ];
+        for (expected, seed, data) in vectors {
+            assert_eq!(murmur3(*seed, data), *expected);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn create_insert_serialize() {
+        // From Bitcoin Core's `bloom_tests.cpp`.
+        for (tweak, expected) in [
+            (0, hex!("03614e9b050000000000000001")),
+            (2147483649, hex!("03ce4299050000000100008001")),
+        ] {
+            let mut filter = BloomFilter::new(3, 0.01, tweak, BloomFlags::All);
+
+            filter.insert(&hex!("99108ad8ed9bb6274d3980bab5a85c048f0950c8"));
+            assert!(filter.contains(&hex!("99108ad8ed9bb6274d3980bab5a85c048f0950c8")));
+            // One bit different in first byte.
+            assert!(!filter.contains(&hex!("19108ad8ed9bb6274d3980bab5a85c048f0950c8")));
+
+            filter.insert(&hex!("b5a2c786d9ef4658287ced5914b37a1b4aa32eee"));
+            assert!(filter.contains(&hex!("b5a2c786d9ef4658287ced5914b37a1b4aa32eee")));
+            filter.insert(&hex!("b9300670b4c5366e95b2699e8b18bc75e5f729c5"));
+            assert!(filter.contains(&hex!("b9300670b4c5366e95b2699e8b18bc75e5f729c5")));
+
+            let msg = FilterLoad::from(filter.clone());
+            assert_eq!(serialize(&msg), expected);
+            assert_eq!(BloomFilter::try_from(msg).unwrap(), filter);
+        }
+    }
+
+    #[test]
+    fn filter_load_limits() {
+        let msg = |len, hash_funcs| FilterLoad {
+            filter: vec![0; len],
+            hash_funcs,
+            tweak: 0,
+            flags: BloomFlags::None,
+        };
+        assert!(BloomFilter::try_from(msg(MAX_BLOOM_FILTER_SIZE, MAX_HASH_FUNCS)).is_ok());
+        assert_eq!(
+            BloomFilter::try_from(msg(MAX_BLOOM_FILTER_SIZE + 1, 1)),
+            Err(Error::FilterTooLarge(MAX_BLOOM_FILTER_SIZE + 1))
+        );
+        assert_eq!(
+            BloomFilter::try_from(msg(1, MAX_HASH_FUNCS + 1)),
+            Err(Error::TooManyHashFuncs(MAX_HASH_FUNCS + 1))
+        );
+
+        let mut filter = BloomFilter::try_from(msg(0, 1)).unwrap();
+        // An empty filter matches everything.
+        assert!(filter.contains(&[1, 2, 3]));
+        assert!(filter.add(&FilterAdd { data: vec![0; MAX_SCRIPT_ELEMENT_SIZE] }).is_ok());
+        assert_eq!(
+            filter.add(&FilterAdd { data: vec![0; MAX_SCRIPT_ELEMENT_SIZE + 1] }),
+            Err(Error::ElementTooLarge(MAX_SCRIPT_ELEMENT_SIZE + 1))
+        );
+    }
+
+    fn filter(flags: BloomFlags) -> BloomFilter {
+        BloomFilter::try_from(FilterLoad { filter: vec![0; 100], hash_funcs: 10, tweak: 7, flags })
+            .unwrap()
+    }
+
+    fn tx(
+        script_sig: ScriptSigBuf,
+        previous_output: OutPoint,
+        outputs: &[ScriptPubKeyBuf],
+    ) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            inputs: vec![TxIn {
+                previous_output,
+                script_sig,
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            outputs: outputs
+                .iter()
+                .map(|script_pubkey| TxOut {
+                    value: Amount::ONE_BTC,
+                    script_pubkey: script_pubkey.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn match_transaction() {
+        let key = "0411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3"
+            .parse::<PublicKey>()
+            .unwrap();
+        let key_hash = [0x11; 20];
+        let p2pk = ScriptPubKey::builder().push_key(key).push_opcode(OP_CHECKSIG).into_script();
+        let p2pkh = ScriptPubKey::builder()
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_HASH160)
+            .push_slice(key_hash)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let funding = tx(ScriptSigBuf::new(), OutPoint::COINBASE_PREVOUT, &[p2pkh, p2pk]);
+        let txid = funding.compute_txid();
+        let spend_p2pkh = tx(ScriptSigBuf::new(), OutPoint { txid, vout: 0 }, &[]);
+        let spend_p2pk = tx(ScriptSigBuf::new(), OutPoint { txid, vout: 1 }, &[]);
+        let unrelated = tx(ScriptSigBuf::new(), OutPoint { txid, vout: 2 }, &[]);
+
+        // Matching by txid does not update the filter.
+        let mut f = filter(BloomFlags::All);
+        f.insert(txid.as_byte_array());
+        assert!(f.match_transaction(&funding));
+        assert!(!f.match_transaction(&spend_p2pkh));
+
+        // All matching outpoints are added.
+        let mut f = filter(BloomFlags::All);
+        f.insert(&key_hash);
+        f.insert(&key.to_vec());
+        assert!(f.match_transaction(&funding));
+        assert!(f.match_transaction(&spend_p2pkh));
+        assert!(f.match_transaction(&spend_p2pk));
+        assert!(!f.match_transaction(&unrelated));
+
+        // Only P2PK and bare multisig outpoints are added.
+        let mut f = filter(BloomFlags::PubkeyOnly);
+        f.insert(&key_hash);
+        f.insert(&key.to_vec());
+        assert!(f.match_transaction(&funding));
+        assert!(!f.match_transaction(&spend_p2pkh));
+        assert!(f.match_transaction(&spend_p2pk));
+
+        // No outpoints are added.
+        let mut f = filter(BloomFlags::None);
+        f.insert(&key_hash);
+        assert!(f.match_transaction(&funding));
+        assert!(!f.match_transaction(&spend_p2pkh));
+
+        // Outpoints and input script pushes match.
+        let mut f = filter(BloomFlags::None);
+        f.insert_outpoint(&OutPoint { txid, vout: 2 });
+        assert!(f.contains_outpoint(&OutPoint { txid, vout: 2 }));
+        assert!(f.match_transaction(&unrelated));
+
+        let script_sig = ScriptSig::builder().push_slice([0x22; 71]).push_key(key).into_script();
+        let spend = tx(script_sig, OutPoint { txid, vout: 3 }, &[]);
+        let mut f = filter(BloomFlags::None);
+        assert!(!f.match_transaction(&spend));
+        f.insert(&key.to_vec());
+        assert!(f.match_transaction(&spend));
+    }
+}
@@ -0,0 +1,729 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Peer connection state machine.
+//!
+//! [`Connection`] implements the version handshake, feature negotiation and ping/pong liveness
+//! of a connection to a peer without doing any I/O: bytes read from the socket are passed to
+//! [`Connection::receive`], the messages for the application are returned by
+//! [`Connection::poll`] and the bytes to write are taken with
+//! [`Connection::take_send_buffer`]. Timers are driven by calling [`Connection::tick`].
+
+use alloc::vec::Vec;
+use core::fmt;
+use std::time::{Duration, Instant};
+
+use bitcoin::consensus::encode;
+use internals::write_err;
+
+use crate::decoder::{DecodeError, V1Decoder};
+use crate::message::{CommandString, NetworkMessage, RawNetworkMessage};
+use crate::message_compact_blocks::SendCmpct;
+use crate::message_network::VersionMessage;
+use crate::{Magic, ProtocolVersion, ServiceFlags};
+
+/// Time allowed for the version handshake.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time between pings.
+pub const PING_INTERVAL: Duration = Duration::from_secs(2 * 60);
+
+/// Time allowed for a peer to answer a ping.
+pub const PING_TIMEOUT: Duration = Duration::from_secs(20 * 60);
+
+/// Who opened the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// We connected to the peer, we send our `version` first.
+    Outbound,
+    /// The peer connected to us, we wait for its `version`.
+    Inbound,
+}
+
+/// An event produced by processing received bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The handshake completed, see [`Connection::peer`] for the negotiated features.
+    Established,
+    /// A message for the application was received.
+    ///
+    /// Handshake and ping/pong messages are handled by the connection and never returned.
+    Message(NetworkMessage),
+}
+
+/// What we know about the peer after the handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PeerInfo {
+    /// The peer's `version` message.
+    pub version: VersionMessage,
+    /// The protocol version used on the connection, the lower of ours and the peer's.
+    pub protocol_version: ProtocolVersion,
+    /// The peer announces transactions by wtxid (BIP-0339).
+    pub wtxid_relay: bool,
+    /// The peer accepts `addrv2` messages (BIP-0155).
+    pub addr_v2: bool,
+    /// The last `sendcmpct` message received from the peer (BIP-0152).
+    pub send_cmpct: Option<SendCmpct>,
+}
+
+/// An outstanding ping.
+#[derive(Debug, Clone, Copy)]
+struct Ping {
+    nonce: u64,
+    sent: Instant,
+}
+
+/// A connection to a peer using the v1 transport.
+///
+/// Messages received before the handshake completes, other than the handshake messages, are
+/// ignored like Bitcoin Core does. Errors are fatal, the connection must be closed after any
+/// method returns an error.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Instant;
+///
+/// use bitcoin_p2p_messages::address::Address;
+/// use bitcoin_p2p_messages::connection::{Connection, Direction, Event};
+/// use bitcoin_p2p_messages::message::NetworkMessage;
+/// use bitcoin_p2p_messages::message_network::{UserAgent, VersionMessage};
+/// use bitcoin_p2p_messages::{Magic, ProtocolVersion, ServiceFlags};
+///
+/// let addr = Address { services: ServiceFlags::NONE, address: [0; 8], port: 0 };
+/// let version = |nonce| {
+///     let user_agent = UserAgent::from_nonstandard("/example:0.1/");
+///     let (version, services) = (ProtocolVersion::WTXID_RELAY_VERSION, ServiceFlags::NONE);
+///     VersionMessage::new(version, services, 0, addr.clone(), addr.clone(), nonce, user_agent, 0)
+/// };
+///
+/// let now = Instant::now();
+/// let mut client = Connection::new(Direction::Outbound, Magic::REGTEST, version(1), now);
+/// let mut server = Connection::new(Direction::Inbound, Magic::REGTEST, version(2), now);
+///
+/// // Shuttle the bytes between the peers, this would normally go through a socket.
+/// server.receive(&client.take_send_buffer());
+/// assert_eq!(server.poll().unwrap(), None);
+/// client.receive(&server.take_send_buffer());
+/// assert_eq!(client.poll().unwrap(), Some(Event::Established));
+/// server.receive(&client.take_send_buffer());
+/// assert_eq!(server.poll().unwrap(), Some(Event::Established));
+///
+/// assert!(client.peer().unwrap().wtxid_relay);
+/// client.send(NetworkMessage::GetAddr).unwrap();
+/// server.receive(&client.take_send_buffer());
+/// assert_eq!(server.poll().unwrap(), Some(Event::Message(NetworkMessage::GetAddr)));
+/// ```
+#[derive(Debug)]
+pub struct Connection {
+    direction: Direction,
+    magic: Magic,
+    our_version: VersionMessage,
+    required_services: ServiceFlags,
+    min_protocol_version: ProtocolVersion,
+    send_cmpct: Option<SendCmpct>,
+    /// Set once the peer's `version` is received.
+    peer: Option<PeerInfo>,
+    verack_received: bool,
+    started: Instant,
+    last_ping: Option<Instant>,
+    ping: Option<Ping>,
+    pings_sent: u64,
+    decoder: V1Decoder,
+    send_buffer: Vec<u8>,
+}
+
+impl Connection {
+    /// Constructs a new connection and, for outbound connections, queues our `version`.
+    ///
+    /// `now` starts the handshake timer.
+    pub fn new(direction: Direction, magic: Magic, version: VersionMessage, now: Instant) -> Self {
+        let mut connection = Connection {
+            direction,
+            magic,
+            our_version: version,
+            required_services: ServiceFlags::NONE,
+            min_protocol_version: ProtocolVersion::MIN_PEER_PROTO_VERSION,
+            send_cmpct: None,
+            peer: None,
+            verack_received: false,
+            started: now,
+            last_ping: None,
+            ping: None,
+            pings_sent: 0,
+            decoder: V1Decoder::new(magic),
+            send_buffer: Vec::new(),
+        };
+        if direction == Direction::Outbound {
+            connection.queue(NetworkMessage::Version(connection.our_version.clone()));
+        }
+        connection
+    }
+
+    /// Sets the services an outbound peer must offer, peers missing any of them are rejected.
+    #[must_use]
+    pub fn with_required_services(mut self, services: ServiceFlags) -> Self {
+        self.required_services = services;
+        self
+    }
+
+    /// Sets the lowest protocol version accepted from the peer.
+    #[must_use]
+    pub fn with_min_protocol_version(mut self, version: ProtocolVersion) -> Self {
+        self.min_protocol_version = version;
+        self
+    }
+
+    /// Sets the `sendcmpct` message sent once the handshake completes.
+    ///
+    /// The message is only sent if the peer supports compact blocks.
+    #[must_use]
+    pub fn with_send_cmpct(mut self, send_cmpct: SendCmpct) -> Self {
+        self.send_cmpct = Some(send_cmpct);
+        self
+    }
+
+    /// Returns who opened the connection.
+    pub fn direction(&self) -> Direction { self.direction }
+
+    /// Returns true if both sides have sent their `verack`.
+    pub fn is_established(&self) -> bool { self.verack_received && self.peer.is_some() }
+
+    /// Returns the negotiated features, available once the handshake completes.
+    pub fn peer(&self) -> Option<&PeerInfo> {
+        if self.is_established() {
+            self.peer.as_ref()
+        } else {
+            None
+        }
+    }
+
+    /// Buffers bytes received from the peer, call [`poll`](Self::poll) to process them.
+    pub fn receive(&mut self, bytes: &[u8]) { self.decoder.push(bytes); }
+
+    /// Processes the received bytes, returning `None` if more bytes are needed.
+    ///
+    /// This should be called until it returns `None` since the received bytes can contain
+    /// several messages.
+    ///
+    /// # Errors
+    ///
+    /// If a message can't be decoded or the peer violates the protocol.
+    pub fn poll(&mut self) -> Result<Option<Event>, Error> {
+        while let Some(message) = self.decode_message()? {
+            if let Some(event) = self.handle_message(message)? {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Queues a message for the peer.
+    ///
+    /// # Errors
+    ///
+    /// If the handshake hasn't completed yet.
+    pub fn send(&mut self, message: NetworkMessage) -> Result<(), Error> {
+        if !self.is_established() {
+            return Err(Error::NotEstablished);
+        }
+        self.queue(message);
+        Ok(())
+    }
+
+    /// Drives the timers: fails a slow handshake, sends pings and fails unanswered pings.
+    ///
+    /// This should be called periodically, e.g. every few seconds or after each read timeout.
+    ///
+    /// # Errors
+    ///
+    /// If the handshake or a ping timed out.
+    pub fn tick(&mut self, now: Instant) -> Result<(), Error> {
+        if !self.is_established() {
+            if now.saturating_duration_since(self.started) > HANDSHAKE_TIMEOUT {
+                return Err(Error::HandshakeTimeout);
+            }
+            return Ok(());
+        }
+        // Peers before BIP-0031 don't answer pings.
+        if self.protocol_version() <= ProtocolVersion::BIP0031_VERSION {
+            return Ok(());
+        }
+
+        match self.ping {
+            Some(ping) if now.saturating_duration_since(ping.sent) > PING_TIMEOUT =>
+                Err(Error::PingTimeout),
+            Some(_) => Ok(()),
+            None => {
+                let due = match self.last_ping {
+                    Some(last) => now.saturating_duration_since(last) >= PING_INTERVAL,
+                    None => true,
+                };
+                if due {
+                    // Nonces only need to differ between pings, no randomness is required.
+                    let nonce = self.our_version.nonce.wrapping_add(self.pings_sent);
+                    self.pings_sent += 1;
+                    self.ping = Some(Ping { nonce, sent: now });
+                    self.last_ping = Some(now);
+                    self.queue(NetworkMessage::Ping(nonce));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the bytes to send to the peer, leaving the send buffer empty.
+    pub fn take_send_buffer(&mut self) -> Vec<u8> { core::mem::take(&mut self.send_buffer) }
+
+    /// The protocol version used on the connection.
+    fn protocol_version(&self) -> ProtocolVersion {
+        match self.peer {
+            Some(ref peer) => peer.protocol_version,
+            None => self.our_version.version,
+        }
+    }
+
+    fn queue(&mut self, message: NetworkMessage) {
+        let raw = RawNetworkMessage::new(self.magic, message);
+        self.send_buffer.extend_from_slice(&encode::serialize(&raw));
+    }
+
+    /// Takes the next complete message out of the received bytes.
+    fn decode_message(&mut self) -> Result<Option<NetworkMessage>, Error> {
+        let raw = self.decoder.next_message().map_err(Error::Decode)?;
+        Ok(raw.map(RawNetworkMessage::into_payload))
+    }
+
+    fn handle_message(&mut self, message: NetworkMessage) -> Result<Option<Event>, Error> {
+        match message {
+            NetworkMessage::Version(_) if self.peer.is_some() => Err(unexpected(&message)),
+            NetworkMessage::Version(version) => {
+                self.handle_version(version)?;
+                Ok(None)
+            }
+            // Everything else requires the peer's `version` first.
+            _ if self.peer.is_none() => Ok(None),
+            NetworkMessage::Verack => {
+                if self.verack_received {
+                    return Err(unexpected(&message));
+                }
+                self.verack_received = true;
+                if let Some(send_cmpct) = self.send_cmpct {
+                    if self.protocol_version() >= ProtocolVersion::SHORT_IDS_BLOCKS_VERSION {
+                        self.queue(NetworkMessage::SendCmpct(send_cmpct));
+                    }
+                }
+                Ok(Some(Event::Established))
+            }
+            // Feature negotiation must happen between `version` and `verack`.
+            NetworkMessage::WtxidRelay | NetworkMessage::SendAddrV2 if self.verack_received =>
+                Err(unexpected(&message)),
+            NetworkMessage::WtxidRelay => {
+                let peer = self.peer.as_mut().expect("checked above");
+                peer.wtxid_relay = peer.protocol_version >= ProtocolVersion::WTXID_RELAY_VERSION;
+                Ok(None)
+            }
+            NetworkMessage::SendAddrV2 => {
+                self.peer.as_mut().expect("checked above").addr_v2 = true;
+                Ok(None)
+            }
+            _ if !self.verack_received => Ok(None),
+            NetworkMessage::SendCmpct(send_cmpct) => {
+                self.peer.as_mut().expect("checked above").send_cmpct = Some(send_cmpct);
+                Ok(None)
+            }
+            NetworkMessage::Ping(nonce) => {
+                if self.protocol_version() > ProtocolVersion::BIP0031_VERSION {
+                    self.queue(NetworkMessage::Pong(nonce));
+                }
+                Ok(None)
+            }
+            NetworkMessage::Pong(nonce) => {
+                // Pongs that don't match the outstanding ping are ignored.
+                if self.ping.map(|ping| ping.nonce) == Some(nonce) {
+                    self.ping = None;
+                }
+                Ok(None)
+            }
+            message => Ok(Some(Event::Message(message))),
+        }
+    }
+
+    /// Checks the peer's `version` and answers with ours, the feature messages and `verack`.
+    fn handle_version(&mut self, version: VersionMessage) -> Result<(), Error> {
+        if version.version < self.min_protocol_version {
+            return Err(Error::ObsoleteVersion(version.version));
+        }
+        if self.direction == Direction::Outbound && !version.services.has(self.required_services) {
+            return Err(Error::MissingServices {
+                required: self.required_services,
+                offered: version.services,
+            });
+        }
+        if self.direction == Direction::Inbound
+            && version.nonce != 0
+            && version.nonce == self.our_version.nonce
+        {
+            return Err(Error::SelfConnection);
+        }
+
+        let protocol_version = version.version.min(self.our_version.version);
+        self.peer = Some(PeerInfo {
+            version,
+            protocol_version,
+            wtxid_relay: false,
+            addr_v2: false,
+            send_cmpct: None,
+        });
+
+        if self.direction == Direction::Inbound {
+            self.queue(NetworkMessage::Version(self.our_version.clone()));
+        }
+        if protocol_version >= ProtocolVersion::WTXID_RELAY_VERSION {
+            self.queue(NetworkMessage::WtxidRelay);
+        }
+        self.queue(NetworkMessage::SendAddrV2);
+        self.queue(NetworkMessage::Verack);
+        Ok(())
+    }
+}
+
+/// Returns the error for a handshake message received out of order.
+fn unexpected(message: &NetworkMessage) -> Error { Error::UnexpectedMessage(message.command()) }
+
+/// Errors of a peer connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The received bytes aren't a valid message.
+    Decode(DecodeError),
+    /// The peer's protocol version is below the configured minimum.
+    ObsoleteVersion(ProtocolVersion),
+    /// The peer doesn't offer all the required services.
+    MissingServices {
+        /// The services we require.
+        required: ServiceFlags,
+        /// The services the peer offers.
+        offered: ServiceFlags,
+    },
+    /// The peer's `version` nonce is ours, we connected to ourselves.
+    SelfConnection,
+    /// A handshake message was received out of order or twice.
+    UnexpectedMessage(CommandString),
+    /// The handshake didn't complete within [`HANDSHAKE_TIMEOUT`].
+    HandshakeTimeout,
+    /// The peer didn't answer a ping within [`PING_TIMEOUT`].
+    PingTimeout,
+    /// Messages can't be sent before the handshake completes.
+    NotEstablished,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Error::*;
+
+        match *self {
+            Decode(ref e) => write_err!(f, "invalid message"; e),
+            ObsoleteVersion(version) =>
+                write!(f, "obsolete protocol version {}", u32::from(version)),
+            MissingServices { required, offered } =>
+                write!(f, "the peer offers services {} but {} are required", offered, required),
+            SelfConnection => write!(f, "connected to ourselves"),
+            UnexpectedMessage(ref command) => write!(f, "unexpected {} message", command),
+            HandshakeTimeout => write!(f, "the handshake timed out"),
+            PingTimeout => write!(f, "the peer didn't answer a ping"),
+            NotEstablished => write!(f, "the handshake hasn't completed"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use Error::*;
+
+        match *self {
+            Decode(ref e) => Some(e),
+            ObsoleteVersion(_)
+            | MissingServices { .. }
+            | SelfConnection
+            | UnexpectedMessage(_)
+            | HandshakeTimeout
+            | PingTimeout
+            | NotEstablished => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::Address;
+    use crate::message_network::UserAgent;
+
+    fn version(version: ProtocolVersion, services: ServiceFlags, nonce: u64) -> VersionMessage {
+        let addr = Address { services: ServiceFlags::NONE, address: [0; 8], port: 0 };
+        let user_agent = UserAgent::from_nonstandard("/test:0.1/");
+        VersionMessage::new(version, services, 0, addr.clone(), addr, nonce, user_agent, 0)
+    }
+
+    fn pair(now: Instant) -> (Connection, Connection) {
+        let client = Connection::new(
+            Direction::Outbound,
+            Magic::REGTEST,
+            version(ProtocolVersion::WTXID_RELAY_VERSION, ServiceFlags::NONE, 1),
+            now,
+        );
+        let server = Connection::new(
+            Direction::Inbound,
+            Magic::REGTEST,
+            version(ProtocolVersion::WTXID_RELAY_VERSION, ServiceFlags::NETWORK, 2),
+            now,
+        );
+        (client, server)
+    }
+
+    /// Moves the queued bytes of `from` to `to` and returns all the events.
+    fn deliver(from: &mut Connection, to: &mut Connection) -> Result<Vec<Event>, Error> {
+        to.receive(&from.take_send_buffer());
+        let mut events = Vec::new();
+        while let Some(event) = to.poll()? {
+            events.push(event);
+        }
+        Ok(events)
+    }
+
+    fn handshake(client: &mut Connection, server: &mut Connection) {
+        assert_eq!(deliver(client, server).unwrap(), []);
+        assert_eq!(deliver(server, client).unwrap(), [Event::Established]);
+        assert_eq!(deliver(client, server).unwrap(), [Event::Established]);
+    }
+
+    fn messages(bytes: &[u8]) -> Vec<NetworkMessage> {
+        let mut messages = Vec::new();
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let (raw, consumed) = encode::deserialize_partial::<RawNetworkMessage>(bytes).unwrap();
+            messages.push(raw.into_payload());
+            bytes = &bytes[consumed..];
+        }
+        messages
+    }
+
+    #[test]
+    fn handshake_messages() {
+        let now = Instant::now();
+        let (mut client, mut server) = pair(now);
+        let sent = client.take_send_buffer();
+        assert!(matches!(messages(&sent)[..], [NetworkMessage::Version(_)]));
+
+        server.receive(&sent);
+        assert_eq!(server.poll().unwrap(), None);
+        let reply = messages(&server.take_send_buffer());
+        assert!(matches!(reply[0], NetworkMessage::Version(_)));
+        assert_eq!(
+            reply[1..],
+            [NetworkMessage::WtxidRelay, NetworkMessage::SendAddrV2, NetworkMessage::Verack]
+        );
+        assert!(!server.is_established());
+        assert_eq!(server.send(NetworkMessage::GetAddr), Err(Error::NotEstablished));
+    }
+
+    #[test]
+    fn negotiation() {
+        let now = Instant::now();
+        let (client, mut server) = pair(now);
+        let mut client = client.with_send_cmpct(SendCmpct { send_compact: false, version: 2 });
+        handshake(&mut client, &mut server);
+
+        let peer = client.peer().unwrap();
+        assert_eq!(peer.version.services, ServiceFlags::NETWORK);
+        assert_eq!(peer.protocol_version, ProtocolVersion::WTXID_RELAY_VERSION);
+        assert!(peer.wtxid_relay && peer.addr_v2);
+        assert_eq!(
+            server.peer().unwrap().send_cmpct,
+            Some(SendCmpct { send_compact: false, version: 2 })
+        );
+        assert_eq!(client.peer().unwrap().send_cmpct, None);
+    }
+
+    #[test]
+    fn old_peer() {
+        let now = Instant::now();
+        let mut client = Connection::new(
+            Direction::Outbound,
+            Magic::REGTEST,
+            version(ProtocolVersion::WTXID_RELAY_VERSION, ServiceFlags::NONE, 1),
+            now,
+        )
+        .with_send_cmpct(SendCmpct { send_compact: true, version: 1 });
+        let mut server = Connection::new(
+            Direction::Inbound,
+            Magic::REGTEST,
+            version(ProtocolVersion::BIP0031_VERSION, ServiceFlags::NONE, 2),
+            now,
+        );
+        handshake(&mut client, &mut server);
+
+        let peer = client.peer().unwrap();
+        assert_eq!(peer.protocol_version, ProtocolVersion::BIP0031_VERSION);
+        assert!(!peer.wtxid_relay);
+        // No compact blocks and no pings for old peers.
+        client.tick(now).unwrap();
+        assert!(client.take_send_buffer().is_empty());
+    }
+
+    #[test]
+    fn rejected_peers() {
+        let now = Instant::now();
+        let (client, mut server) = pair(now);
+        let mut client = client.with_required_services(ServiceFlags::WITNESS);
+        assert_eq!(deliver(&mut client, &mut server).unwrap(), []);
+        assert_eq!(
+            deliver(&mut server, &mut client),
+            Err(Error::MissingServices {
+                required: ServiceFlags::WITNESS,
+                offered: ServiceFlags::NETWORK
+            })
+        );
+
+        let (mut client, server) = pair(now);
+        let mut server = server.with_min_protocol_version(ProtocolVersion::from_nonstandard(70017));
+        assert_eq!(
+            deliver(&mut client, &mut server),
+            Err(Error::ObsoleteVersion(ProtocolVersion::WTXID_RELAY_VERSION))
+        );
+
+        let mut client = Connection::new(
+            Direction::Outbound,
+            Magic::REGTEST,
+            version(ProtocolVersion::WTXID_RELAY_VERSION, ServiceFlags::NONE, 2),
+            now,
+        );
+        let (_, mut server) = pair(now);
+        assert_eq!(deliver(&mut client, &mut server), Err(Error::SelfConnection));
+    }
+
+    #[test]
+    fn protocol_violations() {
+        let now = Instant::now();
+        let (mut client, mut server) = pair(now);
+        handshake(&mut client, &mut server);
+        client.queue(NetworkMessage::WtxidRelay);
+        assert_eq!(
+            deliver(&mut client, &mut server),
+            Err(Error::UnexpectedMessage(NetworkMessage::WtxidRelay.command()))
+        );
+
+        let (mut client, mut server) = pair(now);
+        handshake(&mut client, &mut server);
+        client.queue(NetworkMessage::Version(client.our_version.clone()));
+        assert_eq!(
+            deliver(&mut client, &mut server),
+            Err(Error::UnexpectedMessage(CommandString::try_from_static("version").unwrap()))
+        );
+
+        let (mut client, mut server) = pair(now);
+        handshake(&mut client, &mut server);
+        client.queue(NetworkMessage::Verack);
+        assert_eq!(
+            deliver(&mut client, &mut server),
+            Err(Error::UnexpectedMessage(NetworkMessage::Verack.command()))
+        );
+    }
+
+    #[test]
+    fn messages_before_handshake_are_ignored() {
+        let now = Instant::now();
+        let (mut client, mut server) = pair(now);
+        let version = client.take_send_buffer();
+        client.queue(NetworkMessage::GetAddr);
+        client.send_buffer.extend_from_slice(&version);
+        client.queue(NetworkMessage::Ping(5));
+        assert_eq!(deliver(&mut client, &mut server).unwrap(), []);
+        assert!(!messages(&server.take_send_buffer()).contains(&NetworkMessage::Pong(5)));
+    }
+
+    #[test]
+    fn framing() {
+        let now = Instant::now();
+        let (mut client, mut server) = pair(now);
+        handshake(&mut client, &mut server);
+        client.send(NetworkMessage::GetAddr).unwrap();
+        client.send(NetworkMessage::SendHeaders).unwrap();
+
+        let mut events = Vec::new();
+        for byte in client.take_send_buffer() {
+            server.receive(&[byte]);
+            while let Some(event) = server.poll().unwrap() {
+                events.push(event);
+            }
+        }
+        assert_eq!(
+            events,
+            [Event::Message(NetworkMessage::GetAddr), Event::Message(NetworkMessage::SendHeaders)]
+        );
+
+        let (mut client, mut server) = pair(now);
+        let mut bytes = client.take_send_buffer();
+        bytes[..4].copy_from_slice(&Magic::BITCOIN.to_bytes());
+        server.receive(&bytes);
+        assert_eq!(server.poll(), Err(Error::Decode(DecodeError::UnexpectedBytes(bytes.len()))));
+
+        let (mut client, mut server) = pair(now);
+        let mut bytes = client.take_send_buffer();
+        bytes[16..20].copy_from_slice(&4_000_000u32.to_le_bytes());
+        server.receive(&bytes);
+        assert!(matches!(
+            server.poll(),
+            Err(Error::Decode(DecodeError::PayloadTooLarge { length: 4_000_000, .. }))
+        ));
+
+        let (mut client, mut server) = pair(now);
+        let mut bytes = client.take_send_buffer();
+        bytes[20] ^= 1;
+        server.receive(&bytes);
+        assert!(matches!(server.poll(), Err(Error::Decode(DecodeError::InvalidChecksum { .. }))));
+    }
+
+    #[test]
+    fn ping_pong() {
+        let now = Instant::now();
+        let (mut client, mut server) = pair(now);
+        handshake(&mut client, &mut server);
+
+        client.tick(now).unwrap();
+        let sent = client.take_send_buffer();
+        assert_eq!(messages(&sent), [NetworkMessage::Ping(1)]);
+        server.receive(&sent);
+        assert_eq!(server.poll().unwrap(), None);
+        assert_eq!(deliver(&mut server, &mut client).unwrap(), []);
+        assert!(client.ping.is_none());
+
+        // The next ping is sent after the interval, with a new nonce.
+        client.tick(now + PING_INTERVAL / 2).unwrap();
+        assert!(client.take_send_buffer().is_empty());
+        client.tick(now + PING_INTERVAL).unwrap();
+        assert_eq!(messages(&client.take_send_buffer()), [NetworkMessage::Ping(2)]);
+
+        // A pong with the wrong nonce doesn't count.
+        server.queue(NetworkMessage::Pong(1));
+        assert_eq!(deliver(&mut server, &mut client).unwrap(), []);
+        client.tick(now + PING_INTERVAL + PING_TIMEOUT).unwrap();
+        assert_eq!(
+            client.tick(now + PING_INTERVAL + PING_TIMEOUT + Duration::from_secs(1)),
+            Err(Error::PingTimeout)
+        );
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let now = Instant::now();
+        let (mut client, _) = pair(now);
+        client.tick(now + HANDSHAKE_TIMEOUT).unwrap();
+        assert_eq!(
+            client.tick(now + HANDSHAKE_TIMEOUT + Duration::from_secs(1)),
+            Err(Error::HandshakeTimeout)
+        );
+    }
+}
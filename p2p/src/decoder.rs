@@ -0,0 +1,381 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Incremental decoding of v1 network messages.
+//!
+//! [`V1Decoder`] buffers bytes as they are read from the network, in chunks of any size, and
+//! returns the [`RawNetworkMessage`]s as they complete. Bytes that aren't part of a valid
+//! message are reported and skipped, so the caller can either drop the peer or carry on with
+//! the next message.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use bitcoin::consensus::encode::{self, DeserializeError, ParseError};
+use internals::write_err;
+
+use crate::message::{CommandString, RawNetworkMessage, V1MessageHeader};
+use crate::Magic;
+
+/// Maximum length of a message payload accepted by Bitcoin Core.
+pub const MAX_PROTOCOL_MESSAGE_LENGTH: usize = 4_000_000;
+
+/// Length of the encoded [`V1MessageHeader`].
+pub const HEADER_LEN: usize = 24;
+
+/// Returns the maximum payload length of messages with the `command`.
+///
+/// Messages with a fixed size, or a bounded number of items, are capped at their largest valid
+/// size, all other messages at [`MAX_PROTOCOL_MESSAGE_LENGTH`].
+pub fn max_payload_len(command: &str) -> usize {
+    match command {
+        "verack" | "sendheaders" | "getaddr" | "mempool" | "wtxidrelay" | "sendaddrv2"
+        | "filterclear" => 0,
+        "ping" | "pong" | "feefilter" => 8,
+        "sendcmpct" => 9,
+        // 344 with a user agent of the maximum 256 bytes.
+        "version" => 1024,
+        // At most 1000 addresses.
+        "addr" => 3 + 1000 * 30,
+        // At most 2000 headers, each followed by an empty transaction count.
+        "headers" => 3 + 2000 * 81,
+        // At most 50,000 inventory items.
+        "inv" | "getdata" | "notfound" => 3 + 50_000 * 36,
+        _ => MAX_PROTOCOL_MESSAGE_LENGTH,
+    }
+}
+
+/// An incremental decoder of v1 network messages.
+///
+/// # Examples
+///
+/// ```
+/// use bitcoin::consensus::encode;
+/// use bitcoin_p2p_messages::decoder::V1Decoder;
+/// use bitcoin_p2p_messages::message::{NetworkMessage, RawNetworkMessage};
+/// use bitcoin_p2p_messages::Magic;
+///
+/// let bytes = encode::serialize(&RawNetworkMessage::new(Magic::BITCOIN, NetworkMessage::Ping(42)));
+///
+/// let mut decoder = V1Decoder::new(Magic::BITCOIN);
+/// decoder.push(&bytes[..10]);
+/// assert_eq!(decoder.next_message().unwrap(), None);
+/// decoder.push(&bytes[10..]);
+/// let message = decoder.next_message().unwrap().unwrap();
+/// assert_eq!(message.payload(), &NetworkMessage::Ping(42));
+/// ```
+#[derive(Debug, Clone)]
+pub struct V1Decoder {
+    magic: Magic,
+    buffer: Vec<u8>,
+    /// The header of the message being received.
+    header: Option<V1MessageHeader>,
+}
+
+impl V1Decoder {
+    /// Constructs a new decoder of messages for the network with `magic`.
+    pub fn new(magic: Magic) -> Self { V1Decoder { magic, buffer: Vec::new(), header: None } }
+
+    /// Buffers bytes read from the network.
+    pub fn push(&mut self, bytes: &[u8]) { self.buffer.extend_from_slice(bytes); }
+
+    /// Returns the number of buffered bytes that aren't part of a returned message yet.
+    pub fn buffered_len(&self) -> usize { self.buffer.len() }
+
+    /// Returns the next complete message, `None` if more bytes are needed.
+    ///
+    /// # Errors
+    ///
+    /// If the buffered bytes don't start with a valid message. The offending bytes are skipped
+    /// before returning the error, calling this again continues with the next message.
+    pub fn next_message(&mut self) -> Result<Option<RawNetworkMessage>, DecodeError> {
+        let header = match self.header {
+            Some(ref header) => header,
+            None => {
+                self.find_magic()?;
+                if self.buffer.len() < HEADER_LEN {
+                    return Ok(None);
+                }
+                let header =
+                    match encode::deserialize::<V1MessageHeader>(&self.buffer[..HEADER_LEN]) {
+                        Ok(header) => header,
+                        Err(_) => {
+                            self.skip_magic();
+                            return Err(DecodeError::InvalidCommand);
+                        }
+                    };
+                let max = max_payload_len(header.command.as_ref());
+                if header.length as usize > max {
+                    self.skip_magic();
+                    return Err(DecodeError::PayloadTooLarge {
+                        command: header.command,
+                        length: header.length,
+                        max,
+                    });
+                }
+                &*self.header.insert(header)
+            }
+        };
+
+        let frame_len = HEADER_LEN + header.length as usize;
+        if self.buffer.len() < frame_len {
+            return Ok(None);
+        }
+        let command = header.command.clone();
+        self.header = None;
+        let result = encode::deserialize::<RawNetworkMessage>(&self.buffer[..frame_len]);
+        // Even an invalid message is dropped as a whole, its length was plausible.
+        self.buffer.drain(..frame_len);
+        match result {
+            Ok(message) => Ok(Some(message)),
+            Err(DeserializeError::Parse(ParseError::InvalidChecksum { .. })) =>
+                Err(DecodeError::InvalidChecksum { command }),
+            Err(error) => Err(DecodeError::Payload { command, error }),
+        }
+    }
+
+    /// Skips to the next occurrence of the network magic in the buffer.
+    fn find_magic(&mut self) -> Result<(), DecodeError> {
+        let magic = self.magic.to_bytes();
+        match self.buffer.windows(magic.len()).position(|w| w == magic) {
+            Some(0) => Ok(()),
+            Some(pos) => {
+                self.buffer.drain(..pos);
+                Err(DecodeError::UnexpectedBytes(pos))
+            }
+            None => {
+                // Keep the end of the buffer if it may be the start of the magic.
+                let keep = (1..magic.len())
+                    .rev()
+                    .find(|&len| self.buffer.ends_with(&magic[..len]))
+                    .unwrap_or(0);
+                let skipped = self.buffer.len() - keep;
+                if skipped == 0 {
+                    return Ok(());
+                }
+                self.buffer.drain(..skipped);
+                Err(DecodeError::UnexpectedBytes(skipped))
+            }
+        }
+    }
+
+    /// Drops the magic at the start of the buffer to resynchronize on the next one.
+    fn skip_magic(&mut self) { self.buffer.drain(..4); }
+}
+
+/// Errors decoding v1 network messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DecodeError {
+    /// Bytes that aren't the start of a message were skipped.
+    UnexpectedBytes(usize),
+    /// The command in a message header isn't ASCII.
+    InvalidCommand,
+    /// A message header announces a payload longer than allowed for its command.
+    PayloadTooLarge {
+        /// The command of the message.
+        command: CommandString,
+        /// The announced payload length.
+        length: u32,
+        /// The maximum payload length, see [`max_payload_len`].
+        max: usize,
+    },
+    /// The payload doesn't match the checksum in the message header.
+    InvalidChecksum {
+        /// The command of the message.
+        command: CommandString,
+    },
+    /// The payload couldn't be decoded.
+    Payload {
+        /// The command of the message.
+        command: CommandString,
+        /// The decoding error.
+        error: DeserializeError,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use DecodeError::*;
+
+        match *self {
+            UnexpectedBytes(n) => write!(f, "skipped {} bytes that aren't a message", n),
+            InvalidCommand => write!(f, "message header command isn't ASCII"),
+            PayloadTooLarge { ref command, length, max } => write!(
+                f,
+                "{} message payload of {} bytes exceeds the maximum of {}",
+                command, length, max
+            ),
+            InvalidChecksum { ref command } => write!(f, "invalid {} message checksum", command),
+            Payload { ref command, ref error } =>
+                write_err!(f, "invalid {} message payload", command; error),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use DecodeError::*;
+
+        match *self {
+            Payload { ref error, .. } => Some(error),
+            UnexpectedBytes(_)
+            | InvalidCommand
+            | PayloadTooLarge { .. }
+            | InvalidChecksum { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::NetworkMessage;
+
+    fn encode(message: NetworkMessage) -> Vec<u8> {
+        encode::serialize(&RawNetworkMessage::new(Magic::REGTEST, message))
+    }
+
+    fn payloads(decoder: &mut V1Decoder) -> Vec<Result<NetworkMessage, DecodeError>> {
+        let mut payloads = Vec::new();
+        loop {
+            match decoder.next_message() {
+                Ok(Some(message)) => payloads.push(Ok(message.into_payload())),
+                Ok(None) => return payloads,
+                Err(e) => payloads.push(Err(e)),
+            }
+        }
+    }
+
+    #[test]
+    fn chunked() {
+        let mut bytes = encode(NetworkMessage::Ping(1));
+        bytes.extend(encode(NetworkMessage::Verack));
+        bytes.extend(encode(NetworkMessage::Pong(2)));
+
+        for chunk_size in [1, 3, 24, 31, 100] {
+            let mut decoder = V1Decoder::new(Magic::REGTEST);
+            let mut received = Vec::new();
+            for chunk in bytes.chunks(chunk_size) {
+                decoder.push(chunk);
+                received.extend(payloads(&mut decoder));
+            }
+            assert_eq!(
+                received,
+                [
+                    Ok(NetworkMessage::Ping(1)),
+                    Ok(NetworkMessage::Verack),
+                    Ok(NetworkMessage::Pong(2))
+                ]
+            );
+            assert_eq!(decoder.buffered_len(), 0);
+        }
+    }
+
+    #[test]
+    fn garbage_is_skipped() {
+        let magic = Magic::REGTEST.to_bytes();
+        let mut decoder = V1Decoder::new(Magic::REGTEST);
+        decoder.push(&[1, 2, 3, 4, 5]);
+        assert_eq!(decoder.next_message(), Err(DecodeError::UnexpectedBytes(5)));
+        assert_eq!(decoder.buffered_len(), 0);
+        // A possible start of the magic is kept.
+        decoder.push(&[1, magic[0], magic[1]]);
+        assert_eq!(decoder.next_message(), Err(DecodeError::UnexpectedBytes(1)));
+        assert_eq!(decoder.next_message(), Ok(None));
+        assert_eq!(decoder.buffered_len(), 2);
+
+        let mut decoder = V1Decoder::new(Magic::REGTEST);
+        decoder.push(&[0xaa; 7]);
+        decoder.push(&encode(NetworkMessage::Verack));
+        assert_eq!(
+            payloads(&mut decoder),
+            [Err(DecodeError::UnexpectedBytes(7)), Ok(NetworkMessage::Verack)]
+        );
+
+        // Another network's messages are garbage too.
+        let mut decoder = V1Decoder::new(Magic::BITCOIN);
+        decoder.push(&encode(NetworkMessage::Verack));
+        assert_eq!(payloads(&mut decoder), [Err(DecodeError::UnexpectedBytes(HEADER_LEN))]);
+    }
+
+    #[test]
+    fn oversized_payload() {
+        let mut bytes = encode(NetworkMessage::Ping(1));
+        bytes[16..20].copy_from_slice(&9u32.to_le_bytes());
+        bytes.extend(encode(NetworkMessage::Verack));
+
+        let mut decoder = V1Decoder::new(Magic::REGTEST);
+        decoder.push(&bytes);
+        assert_eq!(
+            payloads(&mut decoder),
+            [
+                Err(DecodeError::PayloadTooLarge {
+                    command: NetworkMessage::Ping(1).command(),
+                    length: 9,
+                    max: 8
+                }),
+                Err(DecodeError::UnexpectedBytes(HEADER_LEN - 4 + 8)),
+                Ok(NetworkMessage::Verack),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_checksum() {
+        let mut bytes = encode(NetworkMessage::Ping(1));
+        bytes[20] ^= 1;
+        bytes.extend(encode(NetworkMessage::Pong(1)));
+
+        let mut decoder = V1Decoder::new(Magic::REGTEST);
+        decoder.push(&bytes);
+        assert_eq!(
+            payloads(&mut decoder),
+            [
+                Err(DecodeError::InvalidChecksum { command: NetworkMessage::Ping(1).command() }),
+                Ok(NetworkMessage::Pong(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_command() {
+        let mut bytes = encode(NetworkMessage::Verack);
+        bytes[4] = 0xff;
+        bytes.extend(encode(NetworkMessage::Verack));
+
+        let mut decoder = V1Decoder::new(Magic::REGTEST);
+        decoder.push(&bytes);
+        assert_eq!(
+            payloads(&mut decoder),
+            [
+                Err(DecodeError::InvalidCommand),
+                Err(DecodeError::UnexpectedBytes(HEADER_LEN - 4)),
+                Ok(NetworkMessage::Verack),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_payload() {
+        // A ping with a truncated nonce, and a matching checksum.
+        let payload = [1, 2, 3];
+        let mut bytes = Magic::REGTEST.to_bytes().to_vec();
+        bytes.extend_from_slice(b"ping\0\0\0\0\0\0\0\0");
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&hashes::sha256d::hash(&payload).to_byte_array()[..4]);
+        bytes.extend_from_slice(&payload);
+
+        let mut decoder = V1Decoder::new(Magic::REGTEST);
+        decoder.push(&bytes);
+        assert!(matches!(decoder.next_message(), Err(DecodeError::Payload { .. })));
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn payload_limits() {
+        assert_eq!(max_payload_len("verack"), 0);
+        assert_eq!(max_payload_len("block"), MAX_PROTOCOL_MESSAGE_LENGTH);
+        assert_eq!(max_payload_len("unknown"), MAX_PROTOCOL_MESSAGE_LENGTH);
+    }
+}
@@ -0,0 +1,579 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! BIP-0157 filter header chain verification.
+//!
+//! [`FilterHeaderChain`] holds the filter headers of the active chain of a [`HeaderChain`]. Each
+//! `cfheaders` response is checked to connect to the previous filter header and to agree with the
+//! headers of a `cfcheckpt` response, and `cfilter`s are checked against their filter header
+//! before being used.
+//!
+//! Filter headers can not be validated without the blocks, so a client asks several peers for
+//! them. If the peers disagree, [`first_checkpoint_divergence`] and [`first_divergence`] find the
+//! first block for which they committed to different filters. The client can then download that
+//! block and the peers' filters for it to work out which peer is lying.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use bitcoin::bip158::{BlockFilter, FilterHeader};
+use bitcoin::block::BlockHash;
+use bitcoin::header_chain::HeaderChain;
+use units::BlockHeight;
+
+use crate::message_filter::{CFCheckpt, CFHeaders, CFilter};
+
+/// Maximum number of filter hashes in a `cfheaders` message.
+pub const MAX_CFHEADERS: usize = 2000;
+
+/// Interval in blocks between the filter headers of a `cfcheckpt` message.
+pub const CHECKPOINT_INTERVAL: u32 = 1000;
+
+/// The filter header preceding the filter header of the genesis block.
+const ZERO_HEADER: FilterHeader = FilterHeader::from_byte_array([0; 32]);
+
+/// A block at which two peers committed to different filters.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// The height of the block.
+    pub height: BlockHeight,
+    /// The hash of the block.
+    pub block_hash: BlockHash,
+}
+
+/// The verified filter headers of one filter type.
+///
+/// Filter headers are stored by height, they are only meaningful for the active chain of the
+/// [`HeaderChain`] they were connected with. On a reorg the headers above the fork must be
+/// dropped with [`FilterHeaderChain::truncate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterHeaderChain {
+    filter_type: u8,
+    /// Filter headers indexed by height, starting at the genesis block.
+    headers: Vec<FilterHeader>,
+    /// Filter headers from a `cfcheckpt`, the one at index `i` is for height `(i + 1) * 1000`.
+    checkpoints: Vec<FilterHeader>,
+}
+
+impl FilterHeaderChain {
+    /// Constructs a new empty filter header chain for filters of `filter_type`.
+    pub fn new(filter_type: u8) -> Self {
+        FilterHeaderChain { filter_type, headers: Vec::new(), checkpoints: Vec::new() }
+    }
+
+    /// Returns the type of the filters.
+    pub fn filter_type(&self) -> u8 { self.filter_type }
+
+    /// Returns the height of the last filter header, `None` if the chain is empty.
+    pub fn height(&self) -> Option<BlockHeight> {
+        self.headers.len().checked_sub(1).map(|height| BlockHeight::from_u32(height as u32))
+    }
+
+    /// Returns the filter header of the block at `height`.
+    pub fn get(&self, height: BlockHeight) -> Option<FilterHeader> {
+        self.headers.get(height.to_u32() as usize).copied()
+    }
+
+    /// Returns the checkpoint filter headers, the first is for height 1000.
+    pub fn checkpoints(&self) -> &[FilterHeader] { &self.checkpoints }
+
+    /// Sets the checkpoints that filter headers connected from now on must match.
+    ///
+    /// The checkpoints must also match the filter headers already connected.
+    pub fn set_checkpoints(
+        &mut self,
+        chain: &HeaderChain,
+        cfcheckpt: &CFCheckpt,
+    ) -> Result<(), Error> {
+        self.check_filter_type(cfcheckpt.filter_type)?;
+        checkpoint_count(chain, cfcheckpt)?;
+
+        for (height, checkpoint) in checkpoint_heights(&cfcheckpt.filter_headers) {
+            match self.headers.get(height as usize) {
+                Some(header) if header != checkpoint =>
+                    return Err(Error::CheckpointMismatch(BlockHeight::from_u32(height))),
+                _ => {}
+            }
+        }
+        self.checkpoints = cfcheckpt.filter_headers.clone();
+        Ok(())
+    }
+
+    /// Checks a `cfheaders` response and appends its filter headers to the chain.
+    ///
+    /// The response may overlap with headers already in the chain as long as it agrees with them,
+    /// but must not start above the next height. Returns the new height of the chain.
+    pub fn connect(
+        &mut self,
+        chain: &HeaderChain,
+        cfheaders: &CFHeaders,
+    ) -> Result<BlockHeight, Error> {
+        self.check_filter_type(cfheaders.filter_type)?;
+        let (start, _) = range(chain, cfheaders)?;
+
+        let previous = match start.checked_sub(1) {
+            None => ZERO_HEADER,
+            Some(height) => match self.headers.get(height as usize) {
+                Some(header) => *header,
+                None => return Err(Error::NotConnected(BlockHeight::from_u32(start))),
+            },
+        };
+        if cfheaders.previous_filter_header != previous {
+            return Err(Error::PreviousHeaderMismatch);
+        }
+
+        let mut new_headers = Vec::new();
+        for (height, header) in (start..).zip(filter_headers(cfheaders)) {
+            if let Some(known) = self.headers.get(height as usize) {
+                if *known != header {
+                    return Err(Error::HeaderMismatch(BlockHeight::from_u32(height)));
+                }
+                continue;
+            }
+            if let Some(checkpoint) = self.checkpoint(height) {
+                if checkpoint != header {
+                    return Err(Error::CheckpointMismatch(BlockHeight::from_u32(height)));
+                }
+            }
+            new_headers.push(header);
+        }
+        self.headers.extend(new_headers);
+        Ok(self.height().expect("connected at least one header"))
+    }
+
+    /// Checks that a downloaded `cfilter` matches its filter header.
+    pub fn check_filter(&self, chain: &HeaderChain, cfilter: &CFilter) -> Result<(), Error> {
+        self.check_filter_type(cfilter.filter_type)?;
+        let height = active_height(chain, &cfilter.block_hash)?;
+
+        let header = self.get(height).ok_or(Error::MissingHeader(height))?;
+        let previous = match height.to_u32().checked_sub(1) {
+            None => ZERO_HEADER,
+            Some(previous) => self.headers[previous as usize],
+        };
+        if BlockFilter::new(&cfilter.filter).filter_header(previous) != header {
+            return Err(Error::FilterMismatch(height));
+        }
+        Ok(())
+    }
+
+    /// Removes the filter headers above `height`, e.g. after the blocks were reorganized out.
+    pub fn truncate(&mut self, height: BlockHeight) {
+        self.headers.truncate((height.to_u32() as usize).saturating_add(1));
+    }
+
+    fn check_filter_type(&self, filter_type: u8) -> Result<(), Error> {
+        if filter_type != self.filter_type {
+            return Err(Error::WrongFilterType(filter_type));
+        }
+        Ok(())
+    }
+
+    /// Returns the checkpoint at `height`, if any.
+    fn checkpoint(&self, height: u32) -> Option<FilterHeader> {
+        if height == 0 || height % CHECKPOINT_INTERVAL != 0 {
+            return None;
+        }
+        self.checkpoints.get((height / CHECKPOINT_INTERVAL - 1) as usize).copied()
+    }
+}
+
+/// Returns the first block for which two `cfheaders` responses commit to different filters.
+///
+/// Both responses must be for the same range. If they already disagree on the previous filter
+/// header the block preceding the range is returned, the client then needs to compare the filter
+/// headers of earlier ranges. Returns `None` if the responses agree.
+pub fn first_divergence(
+    chain: &HeaderChain,
+    a: &CFHeaders,
+    b: &CFHeaders,
+) -> Result<Option<Divergence>, Error> {
+    if a.filter_type != b.filter_type
+        || a.stop_hash != b.stop_hash
+        || a.filter_hashes.len() != b.filter_hashes.len()
+    {
+        return Err(Error::RangeMismatch);
+    }
+    let (start, _) = range(chain, a)?;
+
+    let height = if start > 0 && a.previous_filter_header != b.previous_filter_header {
+        Some(start - 1)
+    } else {
+        a.filter_hashes
+            .iter()
+            .zip(&b.filter_hashes)
+            .position(|(a, b)| a != b)
+            .map(|index| start + index as u32)
+    };
+    Ok(height.map(|height| divergence(chain, height)))
+}
+
+/// Returns the first checkpoint at which two `cfcheckpt` responses disagree.
+///
+/// Both responses must be for the same stop hash. The client then compares the `cfheaders` of the
+/// peers for the 1000 blocks up to the returned block with [`first_divergence`]. Returns `None` if
+/// the responses agree.
+pub fn first_checkpoint_divergence(
+    chain: &HeaderChain,
+    a: &CFCheckpt,
+    b: &CFCheckpt,
+) -> Result<Option<Divergence>, Error> {
+    if a.filter_type != b.filter_type || a.stop_hash != b.stop_hash {
+        return Err(Error::RangeMismatch);
+    }
+    checkpoint_count(chain, a)?;
+    checkpoint_count(chain, b)?;
+
+    let height = checkpoint_heights(&a.filter_headers)
+        .zip(&b.filter_headers)
+        .find(|((_, a), b)| a != b)
+        .map(|((height, _), _)| height);
+    Ok(height.map(|height| divergence(chain, height)))
+}
+
+/// Returns the height of the block with `block_hash` in the active chain.
+fn active_height(chain: &HeaderChain, block_hash: &BlockHash) -> Result<BlockHeight, Error> {
+    match chain.get(block_hash) {
+        Some(entry) if chain.is_active(block_hash) => Ok(entry.height),
+        _ => Err(Error::UnknownBlock(*block_hash)),
+    }
+}
+
+/// Returns the heights of the first and the stop block of a `cfheaders` response.
+fn range(chain: &HeaderChain, cfheaders: &CFHeaders) -> Result<(u32, u32), Error> {
+    let stop = active_height(chain, &cfheaders.stop_hash)?.to_u32();
+    let len = cfheaders.filter_hashes.len();
+    if len == 0 || len > MAX_CFHEADERS || len > stop as usize + 1 {
+        return Err(Error::InvalidLength(len));
+    }
+    Ok((stop + 1 - len as u32, stop))
+}
+
+/// Checks that a `cfcheckpt` response has a header for every checkpoint up to its stop block.
+fn checkpoint_count(chain: &HeaderChain, cfcheckpt: &CFCheckpt) -> Result<(), Error> {
+    let stop = active_height(chain, &cfcheckpt.stop_hash)?.to_u32();
+    let len = cfcheckpt.filter_headers.len();
+    if len != (stop / CHECKPOINT_INTERVAL) as usize {
+        return Err(Error::InvalidLength(len));
+    }
+    Ok(())
+}
+
+/// Returns the filter headers committed to by a `cfheaders` response.
+fn filter_headers(cfheaders: &CFHeaders) -> impl Iterator<Item = FilterHeader> + '_ {
+    cfheaders.filter_hashes.iter().scan(cfheaders.previous_filter_header, |previous, hash| {
+        *previous = hash.filter_header(*previous);
+        Some(*previous)
+    })
+}
+
+/// Pairs checkpoint filter headers with their heights.
+fn checkpoint_heights(
+    checkpoints: &[FilterHeader],
+) -> impl Iterator<Item = (u32, &FilterHeader)> + '_ {
+    (1..).map(|i| i * CHECKPOINT_INTERVAL).zip(checkpoints)
+}
+
+fn divergence(chain: &HeaderChain, height: u32) -> Divergence {
+    let height = BlockHeight::from_u32(height);
+    let block_hash =
+        chain.get_by_height(height).expect("height checked against the chain").block_hash;
+    Divergence { height, block_hash }
+}
+
+/// Error verifying filter headers or filters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The message is for a different filter type.
+    WrongFilterType(u8),
+    /// The block is not in the active chain.
+    UnknownBlock(BlockHash),
+    /// The message has the wrong number of filter hashes or headers for its stop block.
+    InvalidLength(usize),
+    /// The `cfheaders` response starts above the next height of the chain.
+    NotConnected(BlockHeight),
+    /// The previous filter header of a `cfheaders` response does not match the chain.
+    PreviousHeaderMismatch,
+    /// The filter header at this height does not match the one already in the chain.
+    HeaderMismatch(BlockHeight),
+    /// The filter header at this height does not match the checkpoint.
+    CheckpointMismatch(BlockHeight),
+    /// The filter header at this height has not been connected yet.
+    MissingHeader(BlockHeight),
+    /// The filter of the block at this height does not match its filter header.
+    FilterMismatch(BlockHeight),
+    /// The two responses being compared are not for the same range.
+    RangeMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Error::*;
+
+        match *self {
+            WrongFilterType(filter_type) => write!(f, "unexpected filter type {}", filter_type),
+            UnknownBlock(ref hash) => write!(f, "block {} is not in the active chain", hash),
+            InvalidLength(len) => write!(f, "invalid number of filter hashes or headers: {}", len),
+            NotConnected(height) =>
+                write!(f, "filter headers starting at height {} do not connect", height),
+            PreviousHeaderMismatch =>
+                f.write_str("previous filter header does not match the filter header chain"),
+            HeaderMismatch(height) => write!(f, "conflicting filter header at height {}", height),
+            CheckpointMismatch(height) =>
+                write!(f, "filter header at height {} does not match the checkpoint", height),
+            MissingHeader(height) => write!(f, "no filter header at height {}", height),
+            FilterMismatch(height) =>
+                write!(f, "filter at height {} does not match its filter header", height),
+            RangeMismatch => f.write_str("responses are not for the same range"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use Error::*;
+
+        match *self {
+            WrongFilterType(_)
+            | UnknownBlock(_)
+            | InvalidLength(_)
+            | NotConnected(_)
+            | PreviousHeaderMismatch
+            | HeaderMismatch(_)
+            | CheckpointMismatch(_)
+            | MissingHeader(_)
+            | FilterMismatch(_)
+            | RangeMismatch => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::bip158::FilterHash;
+    use bitcoin::block::{Header, HeaderExt as _, Version};
+    use bitcoin::network::Params;
+    use bitcoin::{BlockTime, TxMerkleNode};
+
+    use super::*;
+
+    const FILTER_TYPE: u8 = 0;
+
+    /// Returns a regtest header chain with blocks up to `height`.
+    fn header_chain(height: u32) -> HeaderChain {
+        let mut chain = HeaderChain::new(&Params::REGTEST);
+        for _ in 0..height {
+            let tip = chain.tip().header;
+            let mut header = Header {
+                version: Version::from_consensus(4),
+                prev_blockhash: tip.block_hash(),
+                merkle_root: TxMerkleNode::from_byte_array([0; 32]),
+                time: BlockTime::from_u32(tip.time.to_u32() + 600),
+                bits: tip.bits,
+                nonce: 0,
+            };
+            while header.validate_pow(header.target()).is_err() {
+                header.nonce += 1;
+            }
+            chain.accept(header, BlockTime::from_u32(u32::MAX / 2)).unwrap();
+        }
+        chain
+    }
+
+    /// Returns the filter of the block at `height`, `tag` tells the filters of lying peers apart.
+    fn filter(height: u32, tag: u8) -> Vec<u8> {
+        let mut content = height.to_le_bytes().to_vec();
+        content.push(tag);
+        content
+    }
+
+    /// Returns the filter hashes of the blocks up to `height`, with lies from `lie_height` on.
+    fn filter_hashes(height: u32, lie_height: Option<u32>) -> Vec<FilterHash> {
+        (0..=height)
+            .map(|h| {
+                let tag = if lie_height.map_or(false, |lie| h >= lie) { 1 } else { 0 };
+                BlockFilter::new(&filter(h, tag)).filter_hash()
+            })
+            .collect()
+    }
+
+    /// Returns the `cfheaders` response for blocks `start..=stop` given all filter hashes.
+    fn cfheaders(chain: &HeaderChain, hashes: &[FilterHash], start: u32, stop: u32) -> CFHeaders {
+        let previous_filter_header = hashes[..start as usize]
+            .iter()
+            .fold(ZERO_HEADER, |prev, hash| hash.filter_header(prev));
+        CFHeaders {
+            filter_type: FILTER_TYPE,
+            stop_hash: block_hash(chain, stop),
+            previous_filter_header,
+            filter_hashes: hashes[start as usize..=stop as usize].to_vec(),
+        }
+    }
+
+    /// Returns the `cfcheckpt` response up to `stop` given all filter hashes.
+    fn cfcheckpt(chain: &HeaderChain, hashes: &[FilterHash], stop: u32) -> CFCheckpt {
+        let headers = filter_headers(&cfheaders(chain, hashes, 0, stop)).collect::<Vec<_>>();
+        CFCheckpt {
+            filter_type: FILTER_TYPE,
+            stop_hash: block_hash(chain, stop),
+            filter_headers: (1..=stop / CHECKPOINT_INTERVAL)
+                .map(|i| headers[(i * CHECKPOINT_INTERVAL) as usize])
+                .collect(),
+        }
+    }
+
+    fn cfilter(chain: &HeaderChain, height: u32, tag: u8) -> CFilter {
+        CFilter {
+            filter_type: FILTER_TYPE,
+            block_hash: block_hash(chain, height),
+            filter: filter(height, tag),
+        }
+    }
+
+    fn block_hash(chain: &HeaderChain, height: u32) -> BlockHash {
+        chain.get_by_height(BlockHeight::from_u32(height)).unwrap().block_hash
+    }
+
+    fn height(height: u32) -> BlockHeight { BlockHeight::from_u32(height) }
+
+    #[test]
+    fn connect_and_check_filters() {
+        let chain = header_chain(20);
+        let hashes = filter_hashes(20, None);
+        let mut filter_chain = FilterHeaderChain::new(FILTER_TYPE);
+        assert_eq!(filter_chain.height(), None);
+
+        assert_eq!(filter_chain.connect(&chain, &cfheaders(&chain, &hashes, 0, 9)), Ok(height(9)));
+        // Overlapping responses are fine as long as they agree.
+        assert_eq!(
+            filter_chain.connect(&chain, &cfheaders(&chain, &hashes, 5, 20)),
+            Ok(height(20))
+        );
+
+        for h in [0, 1, 10, 20] {
+            assert_eq!(filter_chain.check_filter(&chain, &cfilter(&chain, h, 0)), Ok(()));
+        }
+        assert_eq!(
+            filter_chain.check_filter(&chain, &cfilter(&chain, 7, 1)),
+            Err(Error::FilterMismatch(height(7)))
+        );
+
+        filter_chain.truncate(height(15));
+        assert_eq!(filter_chain.height(), Some(height(15)));
+        assert_eq!(
+            filter_chain.check_filter(&chain, &cfilter(&chain, 16, 0)),
+            Err(Error::MissingHeader(height(16)))
+        );
+    }
+
+    #[test]
+    fn connect_errors() {
+        let chain = header_chain(20);
+        let hashes = filter_hashes(20, None);
+        let mut filter_chain = FilterHeaderChain::new(FILTER_TYPE);
+
+        assert_eq!(
+            filter_chain.connect(&chain, &cfheaders(&chain, &hashes, 5, 9)),
+            Err(Error::NotConnected(height(5)))
+        );
+
+        let mut wrong_type = cfheaders(&chain, &hashes, 0, 9);
+        wrong_type.filter_type = 1;
+        assert_eq!(filter_chain.connect(&chain, &wrong_type), Err(Error::WrongFilterType(1)));
+
+        let mut unknown_stop = cfheaders(&chain, &hashes, 0, 9);
+        unknown_stop.stop_hash = BlockHash::from_byte_array([1; 32]);
+        assert_eq!(
+            filter_chain.connect(&chain, &unknown_stop),
+            Err(Error::UnknownBlock(unknown_stop.stop_hash))
+        );
+
+        let mut too_long = cfheaders(&chain, &hashes, 0, 9);
+        too_long.filter_hashes.push(hashes[10]);
+        assert_eq!(filter_chain.connect(&chain, &too_long), Err(Error::InvalidLength(11)));
+
+        filter_chain.connect(&chain, &cfheaders(&chain, &hashes, 0, 9)).unwrap();
+
+        let mut wrong_previous = cfheaders(&chain, &hashes, 10, 20);
+        wrong_previous.previous_filter_header = ZERO_HEADER;
+        assert_eq!(
+            filter_chain.connect(&chain, &wrong_previous),
+            Err(Error::PreviousHeaderMismatch)
+        );
+
+        let lies = filter_hashes(20, Some(8));
+        assert_eq!(
+            filter_chain.connect(&chain, &cfheaders(&chain, &lies, 5, 20)),
+            Err(Error::HeaderMismatch(height(8)))
+        );
+        // Failed responses do not change the chain.
+        assert_eq!(filter_chain.height(), Some(height(9)));
+    }
+
+    #[test]
+    fn checkpoints() {
+        let chain = header_chain(2100);
+        let hashes = filter_hashes(2100, None);
+        let lies = filter_hashes(2100, Some(1500));
+        let mut filter_chain = FilterHeaderChain::new(FILTER_TYPE);
+
+        let checkpoint = cfcheckpt(&chain, &hashes, 2100);
+        assert_eq!(checkpoint.filter_headers.len(), 2);
+        filter_chain.set_checkpoints(&chain, &checkpoint).unwrap();
+
+        filter_chain.connect(&chain, &cfheaders(&chain, &hashes, 0, 1499)).unwrap();
+        assert_eq!(
+            filter_chain.connect(&chain, &cfheaders(&chain, &lies, 1500, 2100)),
+            Err(Error::CheckpointMismatch(height(2000)))
+        );
+        assert_eq!(
+            filter_chain.connect(&chain, &cfheaders(&chain, &hashes, 1500, 2100)),
+            Ok(height(2100))
+        );
+
+        // Checkpoints must agree with the headers already connected.
+        assert_eq!(
+            filter_chain.set_checkpoints(&chain, &cfcheckpt(&chain, &lies, 2100)),
+            Err(Error::CheckpointMismatch(height(2000)))
+        );
+
+        let mut short = checkpoint.clone();
+        short.filter_headers.pop();
+        assert_eq!(filter_chain.set_checkpoints(&chain, &short), Err(Error::InvalidLength(1)));
+    }
+
+    #[test]
+    fn divergence() {
+        let chain = header_chain(2100);
+        let hashes = filter_hashes(2100, None);
+        let lies = filter_hashes(2100, Some(1234));
+
+        let honest = cfcheckpt(&chain, &hashes, 2100);
+        let liar = cfcheckpt(&chain, &lies, 2100);
+        assert_eq!(first_checkpoint_divergence(&chain, &honest, &honest), Ok(None));
+        let checkpoint = first_checkpoint_divergence(&chain, &honest, &liar).unwrap().unwrap();
+        assert_eq!(checkpoint.height, height(2000));
+        assert_eq!(checkpoint.block_hash, block_hash(&chain, 2000));
+
+        let stop = checkpoint.height.to_u32();
+        let start = stop - CHECKPOINT_INTERVAL + 1;
+        let honest = cfheaders(&chain, &hashes, start, stop);
+        let liar = cfheaders(&chain, &lies, start, stop);
+        assert_eq!(first_divergence(&chain, &honest, &honest), Ok(None));
+        assert_eq!(
+            first_divergence(&chain, &honest, &liar),
+            Ok(Some(Divergence { height: height(1234), block_hash: block_hash(&chain, 1234) }))
+        );
+
+        // Disagreeing on the previous header points at the block before the range.
+        let honest = cfheaders(&chain, &hashes, 1500, 1600);
+        let liar = cfheaders(&chain, &lies, 1500, 1600);
+        assert_eq!(first_divergence(&chain, &honest, &liar).unwrap().unwrap().height, height(1499));
+
+        assert_eq!(
+            first_divergence(&chain, &honest, &cfheaders(&chain, &hashes, 1500, 1601)),
+            Err(Error::RangeMismatch)
+        );
+    }
+}
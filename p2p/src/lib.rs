@@ -22,6 +22,14 @@
 #[cfg(feature = "std")]
 pub mod address;
 #[cfg(feature = "std")]
+pub mod address_manager;
+pub mod bloom_filter;
+#[cfg(feature = "std")]
+pub mod connection;
+#[cfg(feature = "std")]
+pub mod decoder;
+pub mod filter_header_chain;
+#[cfg(feature = "std")]
 pub mod message;
 pub mod message_blockdata;
 pub mod message_bloom;
@@ -29,6 +37,8 @@
 pub mod message_filter;
 #[cfg(feature = "std")]
 pub mod message_network;
+#[cfg(feature = "std")]
+pub mod v2_transport;
 
 extern crate alloc;
 #[cfg(feature = "std")]
@@ -0,0 +1,974 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! BIP-0324 version 2 encrypted transport.
+//!
+//! [`V2Transport`] implements the v2 handshake and packet encryption as a sans-I/O state
+//! machine: bytes read from the socket are passed to [`V2Transport::receive`], received events
+//! are returned by [`V2Transport::poll`] and the bytes to write to the socket are taken with
+//! [`V2Transport::take_send_buffer`]. Reading and writing the socket is left to the caller.
+//!
+//! Messages are sent in packets using the v2 short message type IDs, see [`V2NetworkMessage`].
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use bitcoin::consensus::encode::{self, DeserializeError};
+use bitcoin::secp256k1::ellswift::{ElligatorSwift, ElligatorSwiftParty};
+use bitcoin::secp256k1::{Secp256k1, SecretKey, Signing, Verification};
+use chacha20_poly1305::chacha20::ChaCha20;
+use chacha20_poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hashes::hkdf::Hkdf;
+use hashes::sha256;
+use internals::write_err;
+
+use crate::message::{NetworkMessage, V2NetworkMessage};
+use crate::Magic;
+
+/// Maximum number of garbage bytes sent before the garbage terminator.
+pub const MAX_GARBAGE_LEN: usize = 4095;
+
+/// Maximum length of the contents of a packet, the largest message plus its message type.
+pub const MAX_CONTENTS_LEN: usize = 1 + 12 + 4_000_000;
+
+/// Number of packets (or encrypted lengths) after which the ciphers are rekeyed.
+const REKEY_INTERVAL: u64 = 224;
+
+/// Length of the ElligatorSwift encoded public keys.
+const KEY_LEN: usize = 64;
+/// Length of the garbage terminators.
+const GARBAGE_TERMINATOR_LEN: usize = 16;
+/// Length of the encrypted packet length.
+const LENGTH_FIELD_LEN: usize = 3;
+/// Length of the packet header.
+const HEADER_LEN: usize = 1;
+/// Length of the Poly1305 authentication tag.
+const TAG_LEN: usize = 16;
+/// Header bit marking a packet that must be ignored by the receiver (a decoy).
+const IGNORE_BIT: u8 = 0x80;
+
+/// The first bytes of a v1 `version` message, after the network magic.
+const V1_VERSION_COMMAND: [u8; 12] = *b"version\0\0\0\0\0";
+
+/// The side of the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// We opened the connection.
+    Initiator,
+    /// The peer opened the connection.
+    Responder,
+}
+
+/// An event produced by processing received bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The handshake completed, the session ID can now be compared out of band.
+    Established,
+    /// A message was received.
+    Message(NetworkMessage),
+}
+
+/// The state of the receiving side of the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for the peer's public key.
+    ReceivingKey,
+    /// Waiting for the end of the peer's garbage.
+    ReceivingGarbage,
+    /// Waiting for the peer's version packet.
+    ReceivingVersion,
+    /// The handshake is complete.
+    Established,
+}
+
+/// A BIP-0324 v2 transport connection.
+///
+/// Errors are fatal, the connection must be closed after any method returns an error.
+///
+/// # Examples
+///
+/// ```
+/// use bitcoin::secp256k1::{Secp256k1, SecretKey};
+/// use bitcoin_p2p_messages::message::NetworkMessage;
+/// use bitcoin_p2p_messages::v2_transport::{Event, Role, V2Transport};
+/// use bitcoin_p2p_messages::Magic;
+///
+/// let secp = Secp256k1::new();
+/// let key = |n| SecretKey::from_byte_array(&[n; 32]).unwrap();
+/// let mut initiator =
+///     V2Transport::new(&secp, Role::Initiator, Magic::BITCOIN, key(1), [0; 32], vec![]).unwrap();
+/// let mut responder =
+///     V2Transport::new(&secp, Role::Responder, Magic::BITCOIN, key(2), [0; 32], vec![]).unwrap();
+///
+/// // Shuttle the bytes between the peers, this would normally go through a socket.
+/// responder.receive(&initiator.take_send_buffer());
+/// assert_eq!(responder.poll().unwrap(), None);
+/// initiator.receive(&responder.take_send_buffer());
+/// assert_eq!(initiator.poll().unwrap(), Some(Event::Established));
+///
+/// initiator.send_message(NetworkMessage::Verack).unwrap();
+/// responder.receive(&initiator.take_send_buffer());
+/// assert_eq!(responder.poll().unwrap(), Some(Event::Established));
+/// assert_eq!(responder.poll().unwrap(), Some(Event::Message(NetworkMessage::Verack)));
+/// assert_eq!(initiator.session_id(), responder.session_id());
+/// ```
+pub struct V2Transport {
+    role: Role,
+    magic: Magic,
+    secret_key: SecretKey,
+    ellswift: ElligatorSwift,
+    state: State,
+    ciphers: Option<Ciphers>,
+    /// Our garbage, authenticated by the first packet we send.
+    send_aad: Option<Vec<u8>>,
+    /// The peer's garbage, authenticated by the first packet we receive.
+    recv_aad: Option<Vec<u8>>,
+    /// Decrypted length of the packet being received.
+    recv_contents_len: Option<usize>,
+    recv_buffer: Vec<u8>,
+    send_buffer: Vec<u8>,
+}
+
+impl V2Transport {
+    /// Constructs a new transport and, for the initiator, queues our public key and garbage.
+    ///
+    /// `aux_rand` should be fresh randomness, it is used to encode the public key and doesn't
+    /// need to be secret. `garbage` should be random bytes of random length.
+    ///
+    /// # Errors
+    ///
+    /// If `garbage` is longer than [`MAX_GARBAGE_LEN`] bytes.
+    pub fn new<C: Signing + Verification>(
+        secp: &Secp256k1<C>,
+        role: Role,
+        magic: Magic,
+        secret_key: SecretKey,
+        aux_rand: [u8; 32],
+        garbage: Vec<u8>,
+    ) -> Result<Self, Error> {
+        if garbage.len() > MAX_GARBAGE_LEN {
+            return Err(Error::GarbageTooLong(garbage.len()));
+        }
+        let ellswift = ElligatorSwift::from_seckey(secp, secret_key, Some(aux_rand));
+
+        let mut transport = V2Transport {
+            role,
+            magic,
+            secret_key,
+            ellswift,
+            state: State::ReceivingKey,
+            ciphers: None,
+            send_aad: None,
+            recv_aad: None,
+            recv_contents_len: None,
+            recv_buffer: Vec::new(),
+            send_buffer: Vec::new(),
+        };
+        // The responder waits to know the peer isn't using v1 before sending anything.
+        if role == Role::Initiator {
+            transport.send_buffer.extend_from_slice(&ellswift.to_array());
+            transport.send_buffer.extend_from_slice(&garbage);
+        }
+        transport.send_aad = Some(garbage);
+        Ok(transport)
+    }
+
+    /// Returns the side of the connection we are on.
+    pub fn role(&self) -> Role { self.role }
+
+    /// Returns true if the peer's version packet has been received.
+    pub fn is_established(&self) -> bool { self.state == State::Established }
+
+    /// Returns the session ID, available once the peer's public key has been received.
+    ///
+    /// Both peers derive the same session ID, comparing it out of band detects a man in the
+    /// middle.
+    pub fn session_id(&self) -> Option<[u8; 32]> {
+        self.ciphers.as_ref().map(|ciphers| ciphers.session_id)
+    }
+
+    /// Buffers bytes received from the peer, call [`poll`](Self::poll) to process them.
+    pub fn receive(&mut self, bytes: &[u8]) { self.recv_buffer.extend_from_slice(bytes); }
+
+    /// Processes the received bytes, returning `None` if more bytes are needed.
+    ///
+    /// This should be called until it returns `None` since a single call to
+    /// [`receive`](Self::receive) can contain several packets. Decoy packets are dropped.
+    ///
+    /// # Errors
+    ///
+    /// If the peer is using the v1 protocol, the peer doesn't follow the protocol or a packet
+    /// fails authentication.
+    pub fn poll(&mut self) -> Result<Option<Event>, Error> {
+        loop {
+            match self.state {
+                State::ReceivingKey => {
+                    if self.role == Role::Responder {
+                        self.check_v1()?;
+                    }
+                    if self.recv_buffer.len() < KEY_LEN {
+                        return Ok(None);
+                    }
+                    let mut their_key = [0; KEY_LEN];
+                    their_key.copy_from_slice(&self.recv_buffer[..KEY_LEN]);
+                    self.recv_buffer.drain(..KEY_LEN);
+                    self.complete_key_exchange(ElligatorSwift::from_array(their_key))?;
+                    self.state = State::ReceivingGarbage;
+                }
+                State::ReceivingGarbage => {
+                    let terminator = self.ciphers.as_ref().expect("keys derived").recv_terminator;
+                    let window =
+                        self.recv_buffer.len().min(MAX_GARBAGE_LEN + GARBAGE_TERMINATOR_LEN);
+                    match self.recv_buffer[..window]
+                        .windows(GARBAGE_TERMINATOR_LEN)
+                        .position(|w| w == terminator)
+                    {
+                        Some(garbage_len) => {
+                            let garbage = self.recv_buffer[..garbage_len].to_vec();
+                            self.recv_buffer.drain(..garbage_len + GARBAGE_TERMINATOR_LEN);
+                            self.recv_aad = Some(garbage);
+                            self.state = State::ReceivingVersion;
+                        }
+                        None if window == MAX_GARBAGE_LEN + GARBAGE_TERMINATOR_LEN =>
+                            return Err(Error::MissingGarbageTerminator),
+                        None => return Ok(None),
+                    }
+                }
+                State::ReceivingVersion | State::Established => {
+                    let (ignore, contents) = match self.decrypt_packet()? {
+                        Some(packet) => packet,
+                        None => return Ok(None),
+                    };
+                    if ignore {
+                        continue;
+                    }
+                    // The version packet contents are reserved for future extensions.
+                    if self.state == State::ReceivingVersion {
+                        self.state = State::Established;
+                        return Ok(Some(Event::Established));
+                    }
+                    let message = encode::deserialize::<V2NetworkMessage>(&contents)
+                        .map_err(Error::Message)?;
+                    return Ok(Some(Event::Message(message.into_payload())));
+                }
+            }
+        }
+    }
+
+    /// Encrypts `message` into a packet and queues it for sending.
+    ///
+    /// Messages can be sent as soon as the peer's public key has been received, the peer
+    /// receives them after our version packet.
+    ///
+    /// # Errors
+    ///
+    /// If the peer's public key hasn't been received yet or the message is too large.
+    pub fn send_message(&mut self, message: NetworkMessage) -> Result<(), Error> {
+        let contents = encode::serialize(&V2NetworkMessage::new(message));
+        self.encrypt_packet(&contents, false)
+    }
+
+    /// Queues a decoy packet with `len` bytes of contents, the peer drops it.
+    ///
+    /// # Errors
+    ///
+    /// If the peer's public key hasn't been received yet or `len` is too large.
+    pub fn send_decoy(&mut self, len: usize) -> Result<(), Error> {
+        self.encrypt_packet(&alloc::vec![0; len], true)
+    }
+
+    /// Returns the bytes to send to the peer, leaving the send buffer empty.
+    pub fn take_send_buffer(&mut self) -> Vec<u8> { core::mem::take(&mut self.send_buffer) }
+
+    /// Fails if the received bytes are the start of a v1 `version` message.
+    fn check_v1(&self) -> Result<(), Error> {
+        let mut prefix = [0; 16];
+        prefix[..4].copy_from_slice(&self.magic.to_bytes());
+        prefix[4..].copy_from_slice(&V1_VERSION_COMMAND);
+        if self.recv_buffer.len() >= prefix.len() && self.recv_buffer[..prefix.len()] == prefix {
+            return Err(Error::V1Peer);
+        }
+        Ok(())
+    }
+
+    /// Derives the session keys and queues our terminator and version packet.
+    fn complete_key_exchange(&mut self, their_key: ElligatorSwift) -> Result<(), Error> {
+        let (initiator_key, responder_key, party) = match self.role {
+            Role::Initiator => (self.ellswift, their_key, ElligatorSwiftParty::A),
+            Role::Responder => (their_key, self.ellswift, ElligatorSwiftParty::B),
+        };
+        let shared_secret = ElligatorSwift::shared_secret(
+            initiator_key,
+            responder_key,
+            self.secret_key,
+            party,
+            None,
+        );
+        self.ciphers = Some(Ciphers::new(shared_secret.as_secret_bytes(), self.magic, self.role));
+
+        if self.role == Role::Responder {
+            self.send_buffer.extend_from_slice(&self.ellswift.to_array());
+            self.send_buffer.extend_from_slice(self.send_aad.as_deref().unwrap_or_default());
+        }
+        let terminator = self.ciphers.as_ref().expect("just set").send_terminator;
+        self.send_buffer.extend_from_slice(&terminator);
+        self.encrypt_packet(&[], false)
+    }
+
+    /// Encrypts a packet into the send buffer.
+    fn encrypt_packet(&mut self, contents: &[u8], ignore: bool) -> Result<(), Error> {
+        if contents.len() > MAX_CONTENTS_LEN {
+            return Err(Error::PacketTooLarge(contents.len()));
+        }
+        let ciphers = self.ciphers.as_mut().ok_or(Error::KeyExchangeIncomplete)?;
+
+        let len = (contents.len() as u32).to_le_bytes();
+        let mut len = [len[0], len[1], len[2]];
+        ciphers.send_length.crypt(&mut len);
+
+        let mut packet = Vec::with_capacity(HEADER_LEN + contents.len());
+        packet.push(if ignore { IGNORE_BIT } else { 0 });
+        packet.extend_from_slice(contents);
+        let aad = self.send_aad.take().unwrap_or_default();
+        let tag = ciphers.send_packet.encrypt(&aad, &mut packet);
+
+        self.send_buffer.reserve(LENGTH_FIELD_LEN + packet.len() + TAG_LEN);
+        self.send_buffer.extend_from_slice(&len);
+        self.send_buffer.extend_from_slice(&packet);
+        self.send_buffer.extend_from_slice(&tag);
+        Ok(())
+    }
+
+    /// Decrypts the next received packet, returning its ignore bit and contents.
+    fn decrypt_packet(&mut self) -> Result<Option<(bool, Vec<u8>)>, Error> {
+        let ciphers = self.ciphers.as_mut().expect("keys derived before receiving packets");
+
+        let contents_len = match self.recv_contents_len {
+            Some(len) => len,
+            None => {
+                if self.recv_buffer.len() < LENGTH_FIELD_LEN {
+                    return Ok(None);
+                }
+                let mut len = [0; LENGTH_FIELD_LEN];
+                len.copy_from_slice(&self.recv_buffer[..LENGTH_FIELD_LEN]);
+                self.recv_buffer.drain(..LENGTH_FIELD_LEN);
+                ciphers.recv_length.crypt(&mut len);
+
+                let len = u32::from_le_bytes([len[0], len[1], len[2], 0]) as usize;
+                if len > MAX_CONTENTS_LEN {
+                    return Err(Error::PacketTooLarge(len));
+                }
+                self.recv_contents_len = Some(len);
+                len
+            }
+        };
+
+        let packet_len = HEADER_LEN + contents_len;
+        if self.recv_buffer.len() < packet_len + TAG_LEN {
+            return Ok(None);
+        }
+        let mut packet = self.recv_buffer[..packet_len].to_vec();
+        let mut tag = [0; TAG_LEN];
+        tag.copy_from_slice(&self.recv_buffer[packet_len..packet_len + TAG_LEN]);
+        self.recv_buffer.drain(..packet_len + TAG_LEN);
+        self.recv_contents_len = None;
+
+        let aad = self.recv_aad.take().unwrap_or_default();
+        ciphers.recv_packet.decrypt(&aad, &mut packet, tag)?;
+
+        let ignore = packet[0] & IGNORE_BIT != 0;
+        packet.remove(0);
+        Ok(Some((ignore, packet)))
+    }
+}
+
+impl fmt::Debug for V2Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Don't leak the secret key or the session keys.
+        f.debug_struct("V2Transport")
+            .field("role", &self.role)
+            .field("magic", &self.magic)
+            .field("state", &self.state)
+            .field("recv_buffer_len", &self.recv_buffer.len())
+            .field("send_buffer_len", &self.send_buffer.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The ciphers and garbage terminators derived from the ECDH shared secret.
+struct Ciphers {
+    send_length: FsChaCha20,
+    send_packet: FsChaCha20Poly1305,
+    recv_length: FsChaCha20,
+    recv_packet: FsChaCha20Poly1305,
+    send_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+    recv_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+    session_id: [u8; 32],
+}
+
+impl Ciphers {
+    /// Derives the session keys with HKDF-SHA256, salted with the network magic.
+    fn new(shared_secret: &[u8; 32], magic: Magic, role: Role) -> Self {
+        let mut salt = b"bitcoin_v2_shared_secret".to_vec();
+        salt.extend_from_slice(&magic.to_bytes());
+        let hkdf = Hkdf::<sha256::HashEngine>::new(&salt, shared_secret);
+        let expand = |info: &[u8]| {
+            let mut okm = [0; 32];
+            hkdf.expand(info, &mut okm).expect("32 bytes is below the HKDF limit");
+            okm
+        };
+
+        let initiator_length = FsChaCha20::new(expand(b"initiator_L"));
+        let initiator_packet = FsChaCha20Poly1305::new(expand(b"initiator_P"));
+        let responder_length = FsChaCha20::new(expand(b"responder_L"));
+        let responder_packet = FsChaCha20Poly1305::new(expand(b"responder_P"));
+        let terminators = expand(b"garbage_terminators");
+        let session_id = expand(b"session_id");
+
+        let mut initiator_terminator = [0; GARBAGE_TERMINATOR_LEN];
+        initiator_terminator.copy_from_slice(&terminators[..GARBAGE_TERMINATOR_LEN]);
+        let mut responder_terminator = [0; GARBAGE_TERMINATOR_LEN];
+        responder_terminator.copy_from_slice(&terminators[GARBAGE_TERMINATOR_LEN..]);
+
+        match role {
+            Role::Initiator => Ciphers {
+                send_length: initiator_length,
+                send_packet: initiator_packet,
+                recv_length: responder_length,
+                recv_packet: responder_packet,
+                send_terminator: initiator_terminator,
+                recv_terminator: responder_terminator,
+                session_id,
+            },
+            Role::Responder => Ciphers {
+                send_length: responder_length,
+                send_packet: responder_packet,
+                recv_length: initiator_length,
+                recv_packet: initiator_packet,
+                send_terminator: responder_terminator,
+                recv_terminator: initiator_terminator,
+                session_id,
+            },
+        }
+    }
+}
+
+/// Forward secure ChaCha20, used to encrypt packet lengths.
+///
+/// A single keystream is used for consecutive chunks, after every [`REKEY_INTERVAL`] chunks
+/// the next 32 bytes of keystream become the new key.
+struct FsChaCha20 {
+    cipher: ChaCha20,
+    chunk_counter: u64,
+}
+
+impl FsChaCha20 {
+    fn new(key: [u8; 32]) -> Self {
+        FsChaCha20 { cipher: ChaCha20::new(Key::new(key), Self::nonce(0), 0), chunk_counter: 0 }
+    }
+
+    /// The nonce is the little endian number of rekeys, prefixed with four zero bytes.
+    fn nonce(rekey_counter: u64) -> Nonce {
+        let mut nonce = [0; 12];
+        nonce[4..].copy_from_slice(&rekey_counter.to_le_bytes());
+        Nonce::new(nonce)
+    }
+
+    /// Encrypts or decrypts `chunk` in place.
+    fn crypt(&mut self, chunk: &mut [u8]) {
+        self.cipher.apply_keystream(chunk);
+        self.chunk_counter += 1;
+        if self.chunk_counter % REKEY_INTERVAL == 0 {
+            let mut key = [0; 32];
+            self.cipher.apply_keystream(&mut key);
+            let nonce = Self::nonce(self.chunk_counter / REKEY_INTERVAL);
+            self.cipher = ChaCha20::new(Key::new(key), nonce, 0);
+        }
+    }
+}
+
+/// Forward secure ChaCha20Poly1305, used to encrypt packets.
+///
+/// Every packet uses a fresh nonce, after every [`REKEY_INTERVAL`] packets the key is replaced
+/// with keystream derived from the current key.
+struct FsChaCha20Poly1305 {
+    key: [u8; 32],
+    packet_counter: u64,
+}
+
+impl FsChaCha20Poly1305 {
+    fn new(key: [u8; 32]) -> Self { FsChaCha20Poly1305 { key, packet_counter: 0 } }
+
+    /// The nonce is the packet index since the last rekey followed by the number of rekeys.
+    fn nonce(&self) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[..4].copy_from_slice(&((self.packet_counter % REKEY_INTERVAL) as u32).to_le_bytes());
+        nonce[4..].copy_from_slice(&(self.packet_counter / REKEY_INTERVAL).to_le_bytes());
+        nonce
+    }
+
+    fn encrypt(&mut self, aad: &[u8], contents: &mut [u8]) -> [u8; TAG_LEN] {
+        let cipher = ChaCha20Poly1305::new(Key::new(self.key), Nonce::new(self.nonce()));
+        let tag = cipher.encrypt(contents, Some(aad));
+        self.next_packet();
+        tag
+    }
+
+    fn decrypt(
+        &mut self,
+        aad: &[u8],
+        contents: &mut [u8],
+        tag: [u8; TAG_LEN],
+    ) -> Result<(), Error> {
+        let cipher = ChaCha20Poly1305::new(Key::new(self.key), Nonce::new(self.nonce()));
+        cipher.decrypt(contents, tag, Some(aad))?;
+        self.next_packet();
+        Ok(())
+    }
+
+    /// Advances the packet counter, rekeying after the last packet of an interval.
+    fn next_packet(&mut self) {
+        if self.packet_counter % REKEY_INTERVAL == REKEY_INTERVAL - 1 {
+            let mut nonce = self.nonce();
+            nonce[..4].copy_from_slice(&[0xff; 4]);
+            let mut key = [0; 32];
+            ChaCha20Poly1305::new(Key::new(self.key), Nonce::new(nonce)).encrypt(&mut key, None);
+            self.key = key;
+        }
+        self.packet_counter += 1;
+    }
+}
+
+/// Errors of the v2 transport.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The garbage is longer than [`MAX_GARBAGE_LEN`] bytes.
+    GarbageTooLong(usize),
+    /// The peer sent a v1 `version` message, the connection may be retried with v1.
+    V1Peer,
+    /// The peer's garbage terminator wasn't found after the maximum amount of garbage.
+    MissingGarbageTerminator,
+    /// The contents of a packet are longer than [`MAX_CONTENTS_LEN`] bytes.
+    PacketTooLarge(usize),
+    /// A packet failed authentication.
+    Decryption(chacha20_poly1305::Error),
+    /// The contents of a packet aren't a valid message.
+    Message(DeserializeError),
+    /// Packets can't be sent before the peer's public key is received.
+    KeyExchangeIncomplete,
+}
+
+impl From<chacha20_poly1305::Error> for Error {
+    fn from(e: chacha20_poly1305::Error) -> Self { Error::Decryption(e) }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Error::*;
+
+        match *self {
+            GarbageTooLong(len) =>
+                write!(f, "garbage of {} bytes exceeds the maximum of {}", len, MAX_GARBAGE_LEN),
+            V1Peer => write!(f, "the peer is using the v1 transport"),
+            MissingGarbageTerminator => write!(f, "the peer's garbage terminator is missing"),
+            PacketTooLarge(len) =>
+                write!(f, "packet of {} bytes exceeds the maximum of {}", len, MAX_CONTENTS_LEN),
+            Decryption(ref e) => write_err!(f, "packet failed authentication"; e),
+            Message(ref e) => write_err!(f, "invalid message in packet"; e),
+            KeyExchangeIncomplete => write!(f, "the peer's public key hasn't been received"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use Error::*;
+
+        match *self {
+            Decryption(ref e) => Some(e),
+            Message(ref e) => Some(e),
+            GarbageTooLong(_)
+            | V1Peer
+            | MissingGarbageTerminator
+            | PacketTooLarge(_)
+            | KeyExchangeIncomplete => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use hex::FromHex as _;
+
+    use super::*;
+    use crate::message::InventoryPayload;
+    use crate::message_blockdata::Inventory;
+
+    fn transport(role: Role, n: u8, garbage: Vec<u8>) -> V2Transport {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_byte_array(&[n; 32]).unwrap();
+        V2Transport::new(&secp, role, Magic::BITCOIN, secret_key, [n; 32], garbage).unwrap()
+    }
+
+    /// Moves the queued bytes of `from` to `to` and returns all the events.
+    fn deliver(from: &mut V2Transport, to: &mut V2Transport) -> Vec<Event> {
+        to.receive(&from.take_send_buffer());
+        let mut events = Vec::new();
+        while let Some(event) = to.poll().unwrap() {
+            events.push(event);
+        }
+        events
+    }
+
+    fn handshake() -> (V2Transport, V2Transport) {
+        let mut initiator = transport(Role::Initiator, 1, vec![0xaa; 10]);
+        let mut responder = transport(Role::Responder, 2, vec![0xbb; 100]);
+        assert!(deliver(&mut initiator, &mut responder).is_empty());
+        assert_eq!(deliver(&mut responder, &mut initiator), [Event::Established]);
+        assert_eq!(deliver(&mut initiator, &mut responder), [Event::Established]);
+        (initiator, responder)
+    }
+
+    #[test]
+    fn bip324_packet_encoding() {
+        let csv = include_str!("../tests/data/packet_encoding_test_vectors.csv");
+        let mut lines = csv.lines();
+        let header = lines.next().expect("header").split(',').collect::<Vec<_>>();
+
+        for (i, line) in lines.enumerate() {
+            let fields = line.split(',').collect::<Vec<_>>();
+            let field =
+                |name: &str| fields[header.iter().position(|column| *column == name).expect(name)];
+            let bytes = |name: &str| Vec::<u8>::from_hex(field(name)).expect(name);
+            let array = |name: &str| <[u8; 32]>::from_hex(field(name)).expect(name);
+
+            let role =
+                if field("in_initiating") == "1" { Role::Initiator } else { Role::Responder };
+            let secret_key = SecretKey::from_byte_array(&array("in_priv_ours")).unwrap();
+            let ours = <[u8; KEY_LEN]>::from_hex(field("in_ellswift_ours")).unwrap();
+            let theirs = <[u8; KEY_LEN]>::from_hex(field("in_ellswift_theirs")).unwrap();
+            let mut transport = V2Transport {
+                role,
+                magic: Magic::BITCOIN,
+                secret_key,
+                ellswift: ElligatorSwift::from_array(ours),
+                state: State::ReceivingKey,
+                ciphers: None,
+                send_aad: None,
+                recv_aad: None,
+                recv_contents_len: None,
+                recv_buffer: Vec::new(),
+                send_buffer: Vec::new(),
+            };
+            transport.complete_key_exchange(ElligatorSwift::from_array(theirs)).unwrap();
+
+            let ciphers = transport.ciphers.as_ref().unwrap();
+            assert_eq!(ciphers.session_id, array("out_session_id"), "vector {}", i);
+            assert_eq!(
+                ciphers.send_terminator[..],
+                bytes("mid_send_garbage_terminator")[..],
+                "vector {}",
+                i
+            );
+            assert_eq!(
+                ciphers.recv_terminator[..],
+                bytes("mid_recv_garbage_terminator")[..],
+                "vector {}",
+                i
+            );
+            let (send_key, recv_key) = match role {
+                Role::Initiator => (array("mid_initiator_p"), array("mid_responder_p")),
+                Role::Responder => (array("mid_responder_p"), array("mid_initiator_p")),
+            };
+            assert_eq!(ciphers.send_packet.key, send_key, "vector {}", i);
+            assert_eq!(ciphers.recv_packet.key, recv_key, "vector {}", i);
+
+            // Restart from fresh ciphers, the version packet queued above counts as a packet.
+            let shared_secret = array("mid_shared_secret");
+            transport.ciphers = Some(Ciphers::new(&shared_secret, Magic::BITCOIN, role));
+
+            let multiply = field("in_multiply").parse::<usize>().unwrap();
+            let contents = bytes("in_contents").repeat(multiply);
+            // Larger packets are valid BIP-0324 but exceed the limit of a network message.
+            if contents.len() > MAX_CONTENTS_LEN {
+                continue;
+            }
+            for _ in 0..field("in_idx").parse::<u32>().unwrap() {
+                transport.encrypt_packet(&[], false).unwrap();
+            }
+            transport.send_buffer.clear();
+            transport.send_aad = Some(bytes("in_aad"));
+            transport.encrypt_packet(&contents, field("in_ignore") == "1").unwrap();
+
+            let ciphertext = bytes("out_ciphertext");
+            if ciphertext.is_empty() {
+                assert!(
+                    transport.send_buffer.ends_with(&bytes("out_ciphertext_endswith")),
+                    "vector {}",
+                    i
+                );
+            } else {
+                assert_eq!(transport.send_buffer, ciphertext, "vector {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn handshake_and_messages() {
+        let (mut initiator, mut responder) = handshake();
+        assert!(initiator.is_established() && responder.is_established());
+        assert!(initiator.session_id().is_some());
+        assert_eq!(initiator.session_id(), responder.session_id());
+
+        initiator.send_message(NetworkMessage::Ping(42)).unwrap();
+        initiator
+            .send_message(NetworkMessage::GetData(InventoryPayload(vec![Inventory::Error(
+                [0; 32],
+            )])))
+            .unwrap();
+        assert_eq!(
+            deliver(&mut initiator, &mut responder),
+            [
+                Event::Message(NetworkMessage::Ping(42)),
+                Event::Message(NetworkMessage::GetData(InventoryPayload(vec![Inventory::Error(
+                    [0; 32]
+                )]))),
+            ]
+        );
+
+        responder.send_message(NetworkMessage::Pong(42)).unwrap();
+        assert_eq!(
+            deliver(&mut responder, &mut initiator),
+            [Event::Message(NetworkMessage::Pong(42))]
+        );
+    }
+
+    #[test]
+    fn byte_by_byte() {
+        let mut initiator = transport(Role::Initiator, 3, vec![1, 2, 3]);
+        let mut responder = transport(Role::Responder, 4, vec![]);
+        let mut events = Vec::new();
+
+        let bytes = initiator.take_send_buffer();
+        for byte in bytes {
+            responder.receive(&[byte]);
+            assert_eq!(responder.poll().unwrap(), None);
+        }
+        initiator.receive(&responder.take_send_buffer());
+        assert_eq!(initiator.poll().unwrap(), Some(Event::Established));
+
+        initiator.send_message(NetworkMessage::Verack).unwrap();
+        for byte in initiator.take_send_buffer() {
+            responder.receive(&[byte]);
+            while let Some(event) = responder.poll().unwrap() {
+                events.push(event);
+            }
+        }
+        assert_eq!(events, [Event::Established, Event::Message(NetworkMessage::Verack)]);
+    }
+
+    #[test]
+    fn decoys_are_dropped() {
+        let (mut initiator, mut responder) = handshake();
+        initiator.send_decoy(0).unwrap();
+        initiator.send_message(NetworkMessage::SendHeaders).unwrap();
+        initiator.send_decoy(1000).unwrap();
+        assert_eq!(
+            deliver(&mut initiator, &mut responder),
+            [Event::Message(NetworkMessage::SendHeaders)]
+        );
+    }
+
+    #[test]
+    fn rekeying() {
+        let (mut initiator, mut responder) = handshake();
+        // Cross the rekey interval of both ciphers a few times.
+        for i in 0..(3 * REKEY_INTERVAL) {
+            initiator.send_message(NetworkMessage::Ping(i)).unwrap();
+            if i % 7 == 0 {
+                initiator.send_decoy(i as usize).unwrap();
+            }
+        }
+        let events = deliver(&mut initiator, &mut responder);
+        assert_eq!(events.len(), 3 * REKEY_INTERVAL as usize);
+        for (i, event) in events.into_iter().enumerate() {
+            assert_eq!(event, Event::Message(NetworkMessage::Ping(i as u64)));
+        }
+    }
+
+    #[test]
+    fn fs_chacha20_poly1305_rekeys() {
+        let mut sender = FsChaCha20Poly1305::new([7; 32]);
+        let mut receiver = FsChaCha20Poly1305::new([7; 32]);
+        let first_key = sender.key;
+        for _ in 0..REKEY_INTERVAL {
+            let mut contents = *b"contents";
+            let tag = sender.encrypt(b"aad", &mut contents);
+            receiver.decrypt(b"aad", &mut contents, tag).unwrap();
+            assert_eq!(&contents, b"contents");
+        }
+        assert_ne!(sender.key, first_key);
+        assert_eq!(sender.key, receiver.key);
+        assert_eq!(sender.nonce(), [0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn fs_chacha20_is_a_continuous_stream() {
+        let mut chunked = FsChaCha20::new([9; 32]);
+        let mut stream = ChaCha20::new(Key::new([9; 32]), FsChaCha20::nonce(0), 0);
+        for _ in 0..(REKEY_INTERVAL - 1) {
+            let mut chunk = [0; 3];
+            let mut expected = [0; 3];
+            chunked.crypt(&mut chunk);
+            stream.apply_keystream(&mut expected);
+            assert_eq!(chunk, expected);
+        }
+        // The last chunk of the interval is followed by the next key.
+        let mut chunk = [0; 3];
+        chunked.crypt(&mut chunk);
+        let mut expected = [0; 3 + 32];
+        stream.apply_keystream(&mut expected);
+        assert_eq!(chunk, expected[..3]);
+
+        let mut key = [0; 32];
+        key.copy_from_slice(&expected[3..]);
+        let mut stream = ChaCha20::new(Key::new(key), FsChaCha20::nonce(1), 0);
+        let mut chunk = [0; 3];
+        let mut expected = [0; 3];
+        chunked.crypt(&mut chunk);
+        stream.apply_keystream(&mut expected);
+        assert_eq!(chunk, expected);
+    }
+
+    #[test]
+    fn tampered_packet() {
+        let (mut initiator, mut responder) = handshake();
+        initiator.send_message(NetworkMessage::Verack).unwrap();
+        let mut bytes = initiator.take_send_buffer();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        responder.receive(&bytes);
+        assert!(matches!(responder.poll(), Err(Error::Decryption(_))));
+    }
+
+    #[test]
+    fn tampered_garbage() {
+        let mut initiator = transport(Role::Initiator, 7, vec![0xaa; 32]);
+        let mut responder = transport(Role::Responder, 8, vec![]);
+        let mut bytes = initiator.take_send_buffer();
+        bytes[KEY_LEN] ^= 1;
+        responder.receive(&bytes);
+        assert_eq!(responder.poll().unwrap(), None);
+        initiator.receive(&responder.take_send_buffer());
+        assert_eq!(initiator.poll().unwrap(), Some(Event::Established));
+        responder.receive(&initiator.take_send_buffer());
+        assert!(matches!(responder.poll(), Err(Error::Decryption(_))));
+    }
+
+    #[test]
+    fn missing_garbage_terminator() {
+        let mut initiator = transport(Role::Initiator, 9, vec![]);
+        let mut responder = transport(Role::Responder, 10, vec![]);
+        responder.receive(&initiator.take_send_buffer()[..KEY_LEN]);
+        assert_eq!(responder.poll().unwrap(), None);
+        responder.receive(&[0; MAX_GARBAGE_LEN + GARBAGE_TERMINATOR_LEN - 1]);
+        assert_eq!(responder.poll().unwrap(), None);
+        responder.receive(&[0]);
+        assert_eq!(responder.poll(), Err(Error::MissingGarbageTerminator));
+    }
+
+    #[test]
+    fn v1_peer() {
+        let mut responder = transport(Role::Responder, 11, vec![]);
+        let mut v1_version = Magic::BITCOIN.to_bytes().to_vec();
+        v1_version.extend_from_slice(&V1_VERSION_COMMAND);
+        responder.receive(&v1_version[..10]);
+        assert_eq!(responder.poll().unwrap(), None);
+        responder.receive(&v1_version[10..]);
+        assert_eq!(responder.poll(), Err(Error::V1Peer));
+        assert!(responder.take_send_buffer().is_empty());
+
+        // Another network's magic isn't a v1 version message.
+        let mut responder = transport(Role::Responder, 11, vec![]);
+        let mut v1_version = Magic::TESTNET3.to_bytes().to_vec();
+        v1_version.extend_from_slice(&V1_VERSION_COMMAND);
+        responder.receive(&v1_version);
+        assert_eq!(responder.poll().unwrap(), None);
+    }
+
+    #[test]
+    fn errors() {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_byte_array(&[1; 32]).unwrap();
+        let garbage = vec![0; MAX_GARBAGE_LEN + 1];
+        assert_eq!(
+            V2Transport::new(&secp, Role::Initiator, Magic::BITCOIN, secret_key, [0; 32], garbage)
+                .unwrap_err(),
+            Error::GarbageTooLong(MAX_GARBAGE_LEN + 1)
+        );
+
+        let mut initiator = transport(Role::Initiator, 1, vec![]);
+        assert_eq!(initiator.send_decoy(1), Err(Error::KeyExchangeIncomplete));
+        let (mut initiator, _) = handshake();
+        assert_eq!(
+            initiator.send_decoy(MAX_CONTENTS_LEN + 1),
+            Err(Error::PacketTooLarge(MAX_CONTENTS_LEN + 1))
+        );
+    }
+
+    #[test]
+    fn loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let peer = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut responder = transport(Role::Responder, 12, vec![0x55; 7]);
+            let mut buf = [0; 1024];
+            let mut ponged = false;
+            while !ponged {
+                let n = stream.read(&mut buf).unwrap();
+                assert_ne!(n, 0, "connection closed early");
+                responder.receive(&buf[..n]);
+                while let Some(event) = responder.poll().unwrap() {
+                    if let Event::Message(NetworkMessage::Ping(nonce)) = event {
+                        responder.send_message(NetworkMessage::Pong(nonce)).unwrap();
+                        ponged = true;
+                    }
+                }
+                stream.write_all(&responder.take_send_buffer()).unwrap();
+            }
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut initiator = transport(Role::Initiator, 13, vec![0x66; 300]);
+        let mut buf = [0; 1024];
+        let mut pinged = false;
+        loop {
+            stream.write_all(&initiator.take_send_buffer()).unwrap();
+            let n = stream.read(&mut buf).unwrap();
+            assert_ne!(n, 0, "connection closed early");
+            initiator.receive(&buf[..n]);
+            while let Some(event) = initiator.poll().unwrap() {
+                match event {
+                    Event::Established => {
+                        initiator.send_message(NetworkMessage::Ping(7)).unwrap();
+                        pinged = true;
+                    }
+                    Event::Message(message) => {
+                        assert!(pinged);
+                        assert_eq!(message, NetworkMessage::Pong(7));
+                        peer.join().unwrap();
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
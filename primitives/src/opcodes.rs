@@ -71,6 +71,18 @@ fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
                 }
             }
         }
+
+        impl Opcode {
+            /// Returns the opcode with the name used by its `Display` implementation.
+            pub(crate) fn from_name(name: &str) -> Option<Opcode> {
+                match name {
+                    $(
+                        stringify!($op) => Some($op),
+                    )+
+                    _ => None,
+                }
+            }
+        }
     }
 }
 
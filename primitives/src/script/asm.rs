@@ -0,0 +1,461 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Parsing scripts from their ASM representation.
+
+use core::convert::Infallible;
+use core::fmt;
+use core::str::FromStr;
+
+use hex::FromHex as _;
+use internals::write_err;
+
+use super::ScriptBuf;
+#[allow(clippy::wildcard_imports)]
+use crate::opcodes::all::*;
+use crate::opcodes::Opcode;
+use crate::prelude::{String, Vec};
+
+/// Largest magnitude of a decimal number, Bitcoin Core's `ParseScript` limit.
+const MAX_DECIMAL: i64 = 0xffff_ffff;
+
+impl<T> ScriptBuf<T> {
+    /// Parses a script from its ASM representation.
+    ///
+    /// The output of the script's `Display` implementation is parsed back into exactly the same
+    /// script, unless the script ends with a truncated push. Such a push is displayed as
+    /// `<push past end>` or `<unexpected end>` without the truncated data, so it can't be parsed
+    /// back and is an error. Other ASM dialects are accepted too, including the ASM of Bitcoin Core's
+    /// `decodescript` and the short-hand used in Core's `script_tests.json`. Tokens are separated
+    /// by whitespace and can be:
+    ///
+    /// * Opcode names with or without the `OP_` prefix, either as displayed by this library or as
+    ///   named by Core (e.g. `OP_1`, `OP_TRUE`, `OP_CHECKLOCKTIMEVERIFY`).
+    /// * `OP_PUSHBYTES_n` or `OP_PUSHDATA1/2/4` followed by the pushed data in hex.
+    /// * Decimal numbers in the range `-0xffffffff..=0xffffffff`, pushed as script numbers
+    ///   (e.g. `1` is `OP_PUSHNUM_1` and `1000` is `OP_PUSHBYTES_2 e803`).
+    /// * Hex prefixed with `0x`, inserted into the script as is.
+    /// * Single-quoted strings without whitespace and bare hex, pushed as data.
+    ///
+    /// Tokens that are both a valid decimal number and valid hex are parsed as numbers.
+    ///
+    /// # Errors
+    ///
+    /// If a token can't be parsed, the error gives its position.
+    pub fn from_asm(s: &str) -> Result<Self, ParseAsmError> {
+        let mut script = Vec::new();
+        let mut tokens = s
+            .split_ascii_whitespace()
+            .enumerate()
+            .map(|(index, word)| Token {
+                index,
+                position: word.as_ptr() as usize - s.as_ptr() as usize,
+                word,
+            })
+            .peekable();
+
+        while let Some(token) = tokens.next() {
+            let word = token.word;
+
+            if is_decimal(word) {
+                let n = word
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|n| (-MAX_DECIMAL..=MAX_DECIMAL).contains(n))
+                    .ok_or_else(|| token.error(ParseAsmErrorKind::NumberOutOfRange))?;
+                push_int(&mut script, n);
+            } else if let Some(op) = opcode_from_name(word) {
+                script.push(op.to_u8());
+                match op {
+                    OP_PUSHBYTES_0 => {}
+                    OP_PUSHDATA1 | OP_PUSHDATA2 | OP_PUSHDATA4 => {
+                        // Empty pushes are displayed without data, the next token is an opcode.
+                        let data = match tokens.next_if(|next| !next.word.starts_with("OP_")) {
+                            Some(next) => next.hex()?,
+                            None => Vec::new(),
+                        };
+                        let (max, len_bytes) = match op {
+                            OP_PUSHDATA1 => (0xff, 1),
+                            OP_PUSHDATA2 => (0xffff, 2),
+                            _ => (0xffff_ffff, 4),
+                        };
+                        if data.len() > max {
+                            let kind = ParseAsmErrorKind::PushTooLong { max, actual: data.len() };
+                            return Err(token.error(kind));
+                        }
+                        script.extend_from_slice(&(data.len() as u32).to_le_bytes()[..len_bytes]);
+                        script.extend_from_slice(&data);
+                    }
+                    _ if op.to_u8() < OP_PUSHDATA1.to_u8() => {
+                        let next = tokens
+                            .next()
+                            .ok_or_else(|| token.error(ParseAsmErrorKind::MissingPushData))?;
+                        let data = next.hex()?;
+                        let expected = usize::from(op.to_u8());
+                        if data.len() != expected {
+                            let kind = ParseAsmErrorKind::PushLengthMismatch {
+                                expected,
+                                actual: data.len(),
+                            };
+                            return Err(next.error(kind));
+                        }
+                        script.extend_from_slice(&data);
+                    }
+                    _ => {}
+                }
+            } else if let Some(hex) = word.strip_prefix("0x").filter(|hex| !hex.is_empty()) {
+                let bytes = Vec::from_hex(hex)
+                    .map_err(|e| token.error(ParseAsmErrorKind::InvalidHex(e)))?;
+                script.extend_from_slice(&bytes);
+            } else if word.len() >= 2 && word.starts_with('\'') && word.ends_with('\'') {
+                push_data(&mut script, &word.as_bytes()[1..word.len() - 1])
+                    .map_err(|kind| token.error(kind))?;
+            } else {
+                let data = Vec::from_hex(word)
+                    .map_err(|_| token.error(ParseAsmErrorKind::UnknownToken))?;
+                push_data(&mut script, &data).map_err(|kind| token.error(kind))?;
+            }
+        }
+        Ok(ScriptBuf::from_bytes(script))
+    }
+}
+
+impl<T> FromStr for ScriptBuf<T> {
+    type Err = ParseAsmError;
+
+    /// Parses a script from ASM, see [`ScriptBuf::from_asm`].
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> { Self::from_asm(s) }
+}
+
+/// A whitespace separated token of the ASM.
+struct Token<'a> {
+    index: usize,
+    position: usize,
+    word: &'a str,
+}
+
+impl Token<'_> {
+    fn error(&self, kind: ParseAsmErrorKind) -> ParseAsmError {
+        ParseAsmError { token: self.index, position: self.position, kind }
+    }
+
+    /// Decodes the token as the hex data of a push opcode.
+    fn hex(&self) -> Result<Vec<u8>, ParseAsmError> {
+        Vec::from_hex(self.word).map_err(|e| self.error(ParseAsmErrorKind::InvalidHex(e)))
+    }
+}
+
+/// Returns true if `word` is a decimal number, optionally negative.
+fn is_decimal(word: &str) -> bool {
+    let digits = word.strip_prefix('-').unwrap_or(word);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Returns the opcode named `word`, see [`ScriptBuf::from_asm`] for the accepted names.
+fn opcode_from_name(word: &str) -> Option<Opcode> {
+    if word.starts_with("OP_") {
+        return Opcode::from_name(word).or_else(|| core_opcode_from_name(word));
+    }
+    // Core's short-hand, names without the `OP_` prefix.
+    let mut name = String::with_capacity(word.len() + 3);
+    name.push_str("OP_");
+    name.push_str(word);
+    Opcode::from_name(&name).or_else(|| core_opcode_from_name(&name))
+}
+
+/// Returns the opcode for Bitcoin Core's names that differ from ours.
+fn core_opcode_from_name(name: &str) -> Option<Opcode> {
+    match name {
+        "OP_0" | "OP_FALSE" => Some(OP_PUSHBYTES_0),
+        "OP_TRUE" => Some(OP_PUSHNUM_1),
+        "OP_1NEGATE" => Some(OP_PUSHNUM_NEG1),
+        "OP_NOP2" | "OP_CHECKLOCKTIMEVERIFY" => Some(OP_CLTV),
+        "OP_NOP3" | "OP_CHECKSEQUENCEVERIFY" => Some(OP_CSV),
+        _ => {
+            let n = name.strip_prefix("OP_")?;
+            if n.starts_with('0') {
+                return None;
+            }
+            match n.parse::<u8>() {
+                Ok(n @ 1..=16) => Some(Opcode::from(OP_PUSHNUM_1.to_u8() + n - 1)),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Pushes `n` the way Core's `CScript::push_int64` does.
+fn push_int(script: &mut Vec<u8>, n: i64) {
+    match n {
+        0 => script.push(OP_PUSHBYTES_0.to_u8()),
+        -1 => script.push(OP_PUSHNUM_NEG1.to_u8()),
+        1..=16 => script.push(OP_PUSHNUM_1.to_u8() + n as u8 - 1),
+        _ => {
+            // Minimal little-endian sign-magnitude encoding, at most 5 bytes in range.
+            let mut buf = [0u8; 8];
+            let mut len = 0;
+            let mut abs = n.unsigned_abs();
+            while abs > 0 {
+                buf[len] = abs as u8;
+                len += 1;
+                abs >>= 8;
+            }
+            if buf[len - 1] & 0x80 != 0 {
+                buf[len] = if n < 0 { 0x80 } else { 0x00 };
+                len += 1;
+            } else if n < 0 {
+                buf[len - 1] |= 0x80;
+            }
+            push_data(script, &buf[..len]).expect("at most 9 bytes");
+        }
+    }
+}
+
+/// Pushes `data` with the smallest push opcode for its length, Core's `CScript::operator<<`.
+fn push_data(script: &mut Vec<u8>, data: &[u8]) -> Result<(), ParseAsmErrorKind> {
+    match data.len() {
+        len if len < usize::from(OP_PUSHDATA1.to_u8()) => script.push(len as u8),
+        len if len <= 0xff => script.extend_from_slice(&[OP_PUSHDATA1.to_u8(), len as u8]),
+        len if len <= 0xffff => {
+            script.push(OP_PUSHDATA2.to_u8());
+            script.extend_from_slice(&(len as u16).to_le_bytes());
+        }
+        len => {
+            let len = u32::try_from(len)
+                .map_err(|_| ParseAsmErrorKind::PushTooLong { max: 0xffff_ffff, actual: len })?;
+            script.push(OP_PUSHDATA4.to_u8());
+            script.extend_from_slice(&len.to_le_bytes());
+        }
+    }
+    script.extend_from_slice(data);
+    Ok(())
+}
+
+/// Error parsing a script from ASM, see [`ScriptBuf::from_asm`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseAsmError {
+    /// Index of the invalid token, counting from zero.
+    token: usize,
+    /// Byte position of the invalid token in the input.
+    position: usize,
+    /// Why the token is invalid.
+    kind: ParseAsmErrorKind,
+}
+
+impl ParseAsmError {
+    /// Returns the index of the invalid token, counting from zero.
+    pub fn token_index(&self) -> usize { self.token }
+
+    /// Returns the byte position of the invalid token in the input.
+    pub fn position(&self) -> usize { self.position }
+
+    /// Returns why the token is invalid.
+    pub fn kind(&self) -> &ParseAsmErrorKind { &self.kind }
+}
+
+impl From<Infallible> for ParseAsmError {
+    #[inline]
+    fn from(never: Infallible) -> Self { match never {} }
+}
+
+impl fmt::Display for ParseAsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_err!(f, "invalid ASM token {} at position {}", self.token, self.position; self.kind)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseAsmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { Some(&self.kind) }
+}
+
+/// The reason a token in the ASM is invalid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseAsmErrorKind {
+    /// The token is not an opcode, number, string or hex.
+    UnknownToken,
+    /// A decimal number is out of the range `-0xffffffff..=0xffffffff`.
+    NumberOutOfRange,
+    /// Push data is not valid hex.
+    InvalidHex(hex::HexToBytesError),
+    /// An `OP_PUSHBYTES_n` opcode is not followed by its data.
+    MissingPushData,
+    /// The data following an `OP_PUSHBYTES_n` opcode is not `n` bytes long.
+    PushLengthMismatch {
+        /// The length required by the opcode.
+        expected: usize,
+        /// The length of the data.
+        actual: usize,
+    },
+    /// The data is too long for the push opcode.
+    PushTooLong {
+        /// The maximum length the opcode can push.
+        max: usize,
+        /// The length of the data.
+        actual: usize,
+    },
+}
+
+impl From<Infallible> for ParseAsmErrorKind {
+    #[inline]
+    fn from(never: Infallible) -> Self { match never {} }
+}
+
+impl fmt::Display for ParseAsmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::UnknownToken => f.write_str("not an opcode, number, string or hex"),
+            Self::NumberOutOfRange => f.write_str("number out of range"),
+            Self::InvalidHex(ref e) => write_err!(f, "invalid push data hex"; e),
+            Self::MissingPushData => f.write_str("push opcode without data"),
+            Self::PushLengthMismatch { expected, actual } =>
+                write!(f, "push data is {} bytes but the opcode pushes {}", actual, expected),
+            Self::PushTooLong { max, actual } =>
+                write!(f, "push data is {} bytes but the opcode pushes at most {}", actual, max),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseAsmErrorKind {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Self::InvalidHex(ref e) => Some(e),
+            Self::UnknownToken
+            | Self::NumberOutOfRange
+            | Self::MissingPushData
+            | Self::PushLengthMismatch { .. }
+            | Self::PushTooLong { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+
+    use super::*;
+    use crate::prelude::ToString;
+    use crate::script::ScriptPubKeyBuf;
+
+    fn parse(s: &str) -> Vec<u8> { ScriptPubKeyBuf::from_asm(s).unwrap().into_bytes() }
+
+    fn parse_err(s: &str) -> ParseAsmError { ScriptPubKeyBuf::from_asm(s).unwrap_err() }
+
+    #[test]
+    fn round_trip_display() {
+        let scripts: &[&[u8]] = &[
+            &[],
+            // P2PKH
+            &[
+                0x76, 0xa9, 0x14, 0x16, 0xe1, 0xae, 0x70, 0xff, 0x0f, 0xa1, 0x02, 0x90, 0x5d, 0x4a,
+                0xf2, 0x97, 0xf6, 0x91, 0x2b, 0xda, 0x6c, 0xce, 0x19, 0x88, 0xac,
+            ],
+            // Empty and non-minimal pushes.
+            &[0x00, 0x4c, 0x00, 0x4d, 0x00, 0x00, 0x4e, 0x00, 0x00, 0x00, 0x00, 0x52],
+            &[0x4c, 0x01, 0xaa, 0x4d, 0x02, 0x00, 0xbb, 0xcc, 0x4e, 0x01, 0x00, 0x00, 0x00, 0xdd],
+            &[0x01, 0x05, 0x4c, 0x00],
+            // Every non-push opcode.
+            &(0x4f..=0xff).collect::<Vec<u8>>(),
+        ];
+        for &bytes in scripts {
+            let script = ScriptPubKeyBuf::from_bytes(bytes.to_vec());
+            let asm = script.to_string();
+            assert_eq!(parse(&asm), bytes, "{}", asm);
+        }
+
+        // Truncated pushes are displayed without their data so they can't be parsed back.
+        let truncated: &[&[u8]] = &[&[0x01], &[0x02, 0x01], &[0x4c], &[0x4c, 0x02, 0x01]];
+        for &bytes in truncated {
+            let asm = ScriptPubKeyBuf::from_bytes(bytes.to_vec()).to_string();
+            assert!(asm.ends_with("<push past end>") || asm.ends_with("<unexpected end>"));
+            assert!(ScriptPubKeyBuf::from_asm(&asm).is_err(), "{}", asm);
+        }
+    }
+
+    #[test]
+    fn push_opcodes() {
+        assert_eq!(parse("OP_PUSHBYTES_2 abcd OP_0"), [0x02, 0xab, 0xcd, 0x00]);
+        assert_eq!(parse("OP_PUSHDATA1 abcd"), [0x4c, 0x02, 0xab, 0xcd]);
+        assert_eq!(parse("OP_PUSHDATA2 ab"), [0x4d, 0x01, 0x00, 0xab]);
+        assert_eq!(parse("OP_PUSHDATA4 ab"), [0x4e, 0x01, 0x00, 0x00, 0x00, 0xab]);
+        assert_eq!(parse("OP_PUSHDATA1 OP_DROP"), [0x4c, 0x00, 0x75]);
+        assert_eq!(parse("OP_PUSHDATA1"), [0x4c, 0x00]);
+
+        let long = "ab".repeat(256);
+        let err = parse_err(&format!("OP_PUSHDATA1 {}", long));
+        assert_eq!(*err.kind(), ParseAsmErrorKind::PushTooLong { max: 0xff, actual: 256 });
+        assert_eq!(parse(&format!("'{}'", "a".repeat(256)))[..3], [0x4d, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse("0 -1 1 16"), [0x00, 0x4f, 0x51, 0x60]);
+        assert_eq!(parse("17 -2"), [0x01, 0x11, 0x01, 0x82]);
+        assert_eq!(parse("128 -128"), [0x02, 0x80, 0x00, 0x02, 0x80, 0x80]);
+        assert_eq!(parse("1000"), [0x02, 0xe8, 0x03]);
+        assert_eq!(parse("4294967295"), [0x05, 0xff, 0xff, 0xff, 0xff, 0x00]);
+        assert_eq!(parse("-4294967295"), [0x05, 0xff, 0xff, 0xff, 0xff, 0x80]);
+
+        let err = parse_err("1 4294967296");
+        assert_eq!(*err.kind(), ParseAsmErrorKind::NumberOutOfRange);
+        assert_eq!(err.token_index(), 1);
+        assert_eq!(err.position(), 2);
+        assert_eq!(parse_err("99999999999999999999").kind(), &ParseAsmErrorKind::NumberOutOfRange);
+    }
+
+    #[test]
+    fn core_short_hand() {
+        // From Bitcoin Core's `script_tests.json`.
+        assert_eq!(
+            parse(
+                "DUP HASH160 0x14 0x89abcdefabbaabbaabbaabbaabbaabbaabbaabba EQUALVERIFY CHECKSIG"
+            )
+            .len(),
+            25
+        );
+        assert_eq!(parse("0x4c 0x01 0x07"), [0x4c, 0x01, 0x07]);
+        assert_eq!(parse("'Az' 'a' ''"), [0x02, b'A', b'z', 0x01, b'a', 0x00]);
+        assert_eq!(
+            parse("NOP2 CHECKLOCKTIMEVERIFY OP_NOP3 1NEGATE TRUE FALSE"),
+            [0xb1, 0xb1, 0xb2, 0x4f, 0x51, 0x00]
+        );
+        assert_eq!(parse("OP_1 OP_16 1ADD"), [0x51, 0x60, 0x8b]);
+        // Core's `decodescript` ASM pushes data as bare hex.
+        assert_eq!(parse("OP_RETURN 0a0b0c0d0e"), [0x6a, 0x05, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e]);
+        // All digit tokens are numbers, even if they could be hex.
+        assert_eq!(parse("0102030405"), [0x04, 0x45, 0xdc, 0x14, 0x06]);
+        // Whitespace of any kind separates tokens.
+        assert_eq!(parse(" DUP\n\tDROP  "), [0x76, 0x75]);
+    }
+
+    #[test]
+    fn errors() {
+        let err = parse_err("OP_DUP OP_FOO");
+        assert_eq!(*err.kind(), ParseAsmErrorKind::UnknownToken);
+        assert_eq!((err.token_index(), err.position()), (1, 7));
+
+        let err = parse_err("OP_DUP OP_PUSHBYTES_2");
+        assert_eq!(*err.kind(), ParseAsmErrorKind::MissingPushData);
+        assert_eq!(err.token_index(), 1);
+
+        let err = parse_err("OP_PUSHBYTES_2   abcdef");
+        assert_eq!(*err.kind(), ParseAsmErrorKind::PushLengthMismatch { expected: 2, actual: 3 });
+        assert_eq!((err.token_index(), err.position()), (1, 17));
+
+        let err = parse_err("OP_PUSHBYTES_1 xx");
+        assert!(matches!(err.kind(), ParseAsmErrorKind::InvalidHex(_)));
+        assert_eq!(parse_err("0xabc").token_index(), 0);
+        assert_eq!(*parse_err("0x").kind(), ParseAsmErrorKind::UnknownToken);
+        assert_eq!(*parse_err("op_dup").kind(), ParseAsmErrorKind::UnknownToken);
+        assert_eq!(*parse_err("OP_01").kind(), ParseAsmErrorKind::UnknownToken);
+        assert_eq!(*parse_err("'").kind(), ParseAsmErrorKind::UnknownToken);
+    }
+
+    #[test]
+    fn from_str() {
+        let script: ScriptPubKeyBuf = "OP_PUSHNUM_1 OP_PUSHNUM_2 OP_ADD".parse().unwrap();
+        assert_eq!(script.as_bytes(), [0x51, 0x52, 0x93]);
+    }
+}
@@ -2,6 +2,8 @@
 
 //! Bitcoin scripts.
 
+#[cfg(feature = "hex")]
+mod asm;
 mod borrowed;
 mod owned;
 mod tag;
@@ -32,6 +34,9 @@
     owned::ScriptBuf,
     tag::{Tag, RedeemScriptTag, ScriptPubKeyTag, ScriptSigTag, TapScriptTag, WitnessScriptTag},
 };
+#[cfg(feature = "hex")]
+#[doc(inline)]
+pub use self::asm::{ParseAsmError, ParseAsmErrorKind};
 
 /// A P2SH redeem script.
 pub type RedeemScriptBuf = ScriptBuf<RedeemScriptTag>;